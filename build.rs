@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Generates the gRPC service and messages from the protobuf definition.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/toydb.proto")?;
+    Ok(())
+}
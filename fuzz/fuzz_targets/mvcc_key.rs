@@ -0,0 +1,9 @@
+//! Decodes MVCC keys from arbitrary bytes, checking that decoding never panics and that valid
+//! keys encode back to the same bytes.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use toydb::storage::kv::mvcc;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = mvcc::fuzz_decode_key(bytes);
+});
@@ -0,0 +1,102 @@
+//! Evaluates arbitrary expression trees against a row of edge-case values, checking that
+//! evaluation never panics, e.g. on integer overflow.
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use toydb::sql::parser::MAX_EXPRESSION_DEPTH;
+use toydb::sql::types::{Expression, Value};
+
+/// An expression, mirroring sql::types::Expression.
+#[derive(Arbitrary, Debug)]
+enum Expr {
+    Constant(Constant),
+    Field(u8),
+
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+
+    Equal(Box<Expr>, Box<Expr>),
+    GreaterThan(Box<Expr>, Box<Expr>),
+    IsNull(Box<Expr>),
+    LessThan(Box<Expr>, Box<Expr>),
+
+    Add(Box<Expr>, Box<Expr>),
+    Assert(Box<Expr>),
+    Divide(Box<Expr>, Box<Expr>),
+    Exponentiate(Box<Expr>, Box<Expr>),
+    Factorial(Box<Expr>),
+    Modulo(Box<Expr>, Box<Expr>),
+    Multiply(Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Subtract(Box<Expr>, Box<Expr>),
+
+    Like(Box<Expr>, Box<Expr>),
+}
+
+/// A constant value, mirroring sql::types::Value.
+#[derive(Arbitrary, Debug)]
+enum Constant {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Expr {
+    /// Builds the expression, replacing subexpressions beyond the maximum depth allowed by the
+    /// parser with NULL.
+    fn build(self, depth: usize) -> Expression {
+        use Expression as E;
+        if depth >= MAX_EXPRESSION_DEPTH {
+            return E::Constant(Value::Null);
+        }
+        let build = |expr: Box<Expr>| Box::new(expr.build(depth + 1));
+        match self {
+            Self::Constant(Constant::Null) => E::Constant(Value::Null),
+            Self::Constant(Constant::Boolean(b)) => E::Constant(Value::Boolean(b)),
+            Self::Constant(Constant::Integer(i)) => E::Constant(Value::Integer(i)),
+            Self::Constant(Constant::Float(f)) => E::Constant(Value::Float(f)),
+            Self::Constant(Constant::String(s)) => E::Constant(Value::String(s)),
+            Self::Field(i) => E::Field(i as usize, None),
+
+            Self::And(lhs, rhs) => E::And(build(lhs), build(rhs)),
+            Self::Not(expr) => E::Not(build(expr)),
+            Self::Or(lhs, rhs) => E::Or(build(lhs), build(rhs)),
+
+            Self::Equal(lhs, rhs) => E::Equal(build(lhs), build(rhs)),
+            Self::GreaterThan(lhs, rhs) => E::GreaterThan(build(lhs), build(rhs)),
+            Self::IsNull(expr) => E::IsNull(build(expr)),
+            Self::LessThan(lhs, rhs) => E::LessThan(build(lhs), build(rhs)),
+
+            Self::Add(lhs, rhs) => E::Add(build(lhs), build(rhs)),
+            Self::Assert(expr) => E::Assert(build(expr)),
+            Self::Divide(lhs, rhs) => E::Divide(build(lhs), build(rhs)),
+            Self::Exponentiate(lhs, rhs) => E::Exponentiate(build(lhs), build(rhs)),
+            Self::Factorial(expr) => E::Factorial(build(expr)),
+            Self::Modulo(lhs, rhs) => E::Modulo(build(lhs), build(rhs)),
+            Self::Multiply(lhs, rhs) => E::Multiply(build(lhs), build(rhs)),
+            Self::Negate(expr) => E::Negate(build(expr)),
+            Self::Subtract(lhs, rhs) => E::Subtract(build(lhs), build(rhs)),
+
+            Self::Like(lhs, rhs) => E::Like(build(lhs), build(rhs)),
+        }
+    }
+}
+
+fuzz_target!(|expr: Expr| {
+    let row = vec![
+        Value::Null,
+        Value::Boolean(true),
+        Value::Integer(0),
+        Value::Integer(-1),
+        Value::Integer(std::i64::MIN),
+        Value::Integer(std::i64::MAX),
+        Value::Float(-0.0),
+        Value::Float(std::f64::NAN),
+        Value::Float(std::f64::INFINITY),
+        Value::String("%_\\".into()),
+    ];
+    let _ = expr.build(0).evaluate(Some(&row));
+});
@@ -0,0 +1,8 @@
+//! Parses arbitrary strings as SQL statements, checking that the parser never panics.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use toydb::sql::parser::Parser;
+
+fuzz_target!(|query: &str| {
+    let _ = Parser::new(query).parse();
+});
@@ -0,0 +1,156 @@
+//! Executes sequences of SQL statements built from arbitrary tokens against an in-memory engine
+//! with a small dataset, checking that parsing, planning, and execution never panic.
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use toydb::sql::engine::{Engine as _, KV};
+use toydb::sql::execution::ResultSet;
+use toydb::storage::kv;
+
+/// Keywords and punctuation.
+const TOKENS: &[&str] = &[
+    "!",
+    "!=",
+    "%",
+    "(",
+    ")",
+    "*",
+    "+",
+    ",",
+    "-",
+    ".",
+    "/",
+    ";",
+    "<",
+    "<=",
+    "=",
+    ">",
+    ">=",
+    "?",
+    "^",
+    "AND",
+    "AS",
+    "ASC",
+    "BEGIN",
+    "BY",
+    "COMMIT",
+    "CREATE",
+    "CROSS",
+    "DEFAULT",
+    "DELETE",
+    "DESC",
+    "DROP",
+    "EXPLAIN",
+    "FALSE",
+    "FROM",
+    "GROUP",
+    "HAVING",
+    "INDEX",
+    "INFINITY",
+    "INNER",
+    "INSERT",
+    "INTEGER",
+    "INTO",
+    "IS",
+    "JOIN",
+    "KEY",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NAN",
+    "NOT",
+    "NULL",
+    "OF",
+    "OFFSET",
+    "ON",
+    "ONLY",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "PRIMARY",
+    "READ",
+    "REFERENCES",
+    "RIGHT",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "STRING",
+    "SYSTEM",
+    "TABLE",
+    "TIME",
+    "TRANSACTION",
+    "TRUE",
+    "UNIQUE",
+    "UPDATE",
+    "VALUES",
+    "WHERE",
+    "WRITE",
+];
+
+/// Identifiers, including the tables and columns of the dataset.
+const IDENTS: &[&str] = &[
+    "genres", "movies", "g", "m", "id", "name", "title", "genre_id", "rating", "count", "sum",
+    "avg", "min", "max",
+];
+
+/// The dataset.
+const SETUP: &[&str] = &[
+    "CREATE TABLE genres (id INTEGER PRIMARY KEY, name STRING NOT NULL)",
+    "CREATE TABLE movies (
+        id INTEGER PRIMARY KEY,
+        title STRING NOT NULL INDEX,
+        genre_id INTEGER REFERENCES genres,
+        rating FLOAT
+    )",
+    "INSERT INTO genres VALUES (1, 'Science Fiction'), (2, 'Action')",
+    "INSERT INTO movies VALUES (1, 'Stalker', 1, 8.2), (2, 'Heat', 2, 8.2), (3, 'Her', NULL, NULL)",
+];
+
+/// Operators which the planner expands exponentially when nested: >= and <= are planned as
+/// an OR of two comparisons, and filters are converted to conjunctive normal form. Inputs with
+/// more of these are skipped, to avoid spurious timeouts.
+const EXPONENTIAL: &[&str] = &["OR", "<=", ">="];
+const MAX_EXPONENTIAL: usize = 8;
+
+/// A query fragment.
+#[derive(Arbitrary, Debug)]
+enum Fragment {
+    Token(u8),
+    Ident(u8),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Fragment {
+    fn to_sql(&self) -> String {
+        match self {
+            Self::Token(i) => TOKENS[*i as usize % TOKENS.len()].to_string(),
+            Self::Ident(i) => IDENTS[*i as usize % IDENTS.len()].to_string(),
+            Self::Integer(i) => i.to_string(),
+            Self::Float(f) => format!("{:?}", f),
+            Self::String(s) => format!("'{}'", s.replace("'", "''")),
+        }
+    }
+}
+
+fuzz_target!(|statements: Vec<Vec<Fragment>>| {
+    let statements: Vec<Vec<String>> =
+        statements.iter().map(|s| s.iter().map(Fragment::to_sql).collect()).collect();
+    if statements.iter().flatten().filter(|s| EXPONENTIAL.contains(&s.as_str())).count()
+        > MAX_EXPONENTIAL
+    {
+        return;
+    }
+
+    let engine = KV::new(kv::MVCC::new(Box::new(kv::Memory::new())));
+    let mut session = engine.session().unwrap();
+    for query in SETUP {
+        session.execute(query).unwrap();
+    }
+    for statement in statements {
+        if let Ok(ResultSet::Query { rows, .. }) = session.execute(&statement.join(" ")) {
+            rows.for_each(drop);
+        }
+    }
+});
@@ -0,0 +1,245 @@
+//! Benchmark workloads, used by the `toydb bench` subcommand to measure throughput and latency.
+//! YCSB-style key/value workloads can run either against a SQL cluster or directly against a
+//! local storage engine, while the TPC-C-lite order processing workload runs against a SQL
+//! cluster.
+
+pub mod tpcc;
+pub mod ycsb;
+
+use crate::error::{Error, Result};
+use crate::sql::execution::ResultSet;
+use crate::sql::types::Row;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// A latency histogram. It keeps every sample, so percentiles are exact.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    samples: Vec<Duration>,
+    sorted: bool,
+}
+
+impl Histogram {
+    /// Creates a new, empty histogram.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a sample.
+    pub fn record(&mut self, sample: Duration) {
+        self.samples.push(sample);
+        self.sorted = false;
+    }
+
+    /// Merges another histogram into this one.
+    pub fn merge(&mut self, other: &Histogram) {
+        self.samples.extend_from_slice(&other.samples);
+        self.sorted = false;
+    }
+
+    /// Returns the number of samples.
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns the mean sample, or zero if empty.
+    pub fn mean(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::default();
+        }
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    /// Returns the sample at the given percentile (0-100) using the nearest-rank method, or zero
+    /// if empty.
+    pub fn percentile(&mut self, percentile: f64) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::default();
+        }
+        if !self.sorted {
+            self.samples.sort();
+            self.sorted = true;
+        }
+        let rank = (percentile / 100.0 * self.samples.len() as f64).ceil() as usize;
+        self.samples[rank.max(1).min(self.samples.len()) - 1]
+    }
+}
+
+/// A benchmark report, with latency histograms and error counts by operation. Errors are
+/// serialization failures and aborts, which are expected under contention and not retried.
+#[derive(Clone, Debug)]
+pub struct Report {
+    name: String,
+    elapsed: Duration,
+    operations: BTreeMap<&'static str, Histogram>,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl Report {
+    /// Creates a new, empty report.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            elapsed: Duration::default(),
+            operations: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        }
+    }
+
+    /// Records the result of an operation started at the given time. Serialization failures and
+    /// aborts are counted as errors, while other errors are returned.
+    pub fn record<T>(
+        &mut self,
+        operation: &'static str,
+        start: Instant,
+        result: Result<T>,
+    ) -> Result<()> {
+        match result {
+            Ok(_) => self.operations.entry(operation).or_default().record(start.elapsed()),
+            Err(Error::Serialization) | Err(Error::Abort) => {
+                *self.errors.entry(operation).or_default() += 1
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    /// Merges another report into this one, e.g. from a different worker.
+    pub fn merge(&mut self, other: &Report) {
+        for (operation, histogram) in &other.operations {
+            self.operations.entry(operation).or_default().merge(histogram);
+        }
+        for (operation, errors) in &other.errors {
+            *self.errors.entry(operation).or_default() += errors;
+        }
+    }
+
+    /// Sets the total elapsed time of the benchmark.
+    pub fn finish(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+    }
+
+    /// Returns the total number of successful operations.
+    pub fn count(&self) -> usize {
+        self.operations.values().map(|h| h.count()).sum()
+    }
+
+    /// Returns the total number of errors.
+    pub fn errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Returns the latency histogram for an operation, if it succeeded at least once.
+    pub fn histogram(&self, operation: &str) -> Option<&Histogram> {
+        self.operations.get(operation)
+    }
+
+    /// Returns the throughput of an operation count, in operations per second.
+    fn rate(&self, count: usize) -> f64 {
+        count as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        writeln!(
+            f,
+            "{}: {} operations in {:.3}s ({:.1}/s), {} errors",
+            self.name,
+            self.count(),
+            self.elapsed.as_secs_f64(),
+            self.rate(self.count()),
+            self.errors()
+        )?;
+        writeln!(
+            f,
+            "{:<14} {:>8} {:>10} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "operation", "count", "ops/s", "errors", "mean", "p50", "p90", "p99", "max"
+        )?;
+        let mut names: Vec<&&str> = self.operations.keys().chain(self.errors.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let mut histogram = self.operations.get(name).cloned().unwrap_or_default();
+            writeln!(
+                f,
+                "{:<14} {:>8} {:>10.1} {:>8} {:>7.3}ms {:>7.3}ms {:>7.3}ms {:>7.3}ms {:>7.3}ms",
+                name,
+                histogram.count(),
+                self.rate(histogram.count()),
+                self.errors.get(name).copied().unwrap_or(0),
+                ms(histogram.mean()),
+                ms(histogram.percentile(50.0)),
+                ms(histogram.percentile(90.0)),
+                ms(histogram.percentile(99.0)),
+                ms(histogram.percentile(100.0)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the rows of a query result.
+fn rows(result: ResultSet) -> Result<Vec<Row>> {
+    match result {
+        ResultSet::Query { rows, .. } => rows.collect(),
+        result => Err(Error::Value(format!("Not a query result: {:?}", result))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.percentile(50.0), Duration::default());
+        assert_eq!(histogram.mean(), Duration::default());
+
+        for ms in (1..=100).rev() {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.mean(), Duration::from_micros(50_500));
+        assert_eq!(histogram.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(histogram.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(histogram.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(histogram.percentile(99.5), Duration::from_millis(100));
+        assert_eq!(histogram.percentile(100.0), Duration::from_millis(100));
+
+        let mut other = Histogram::new();
+        other.record(Duration::from_millis(200));
+        histogram.merge(&other);
+        assert_eq!(histogram.count(), 101);
+        assert_eq!(histogram.percentile(100.0), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn report() -> Result<()> {
+        let start = Instant::now();
+        let mut report = Report::new("test");
+        report.record("read", start, Ok(()))?;
+        report.record("read", start, Err::<(), _>(Error::Serialization))?;
+        report.record("update", start, Err::<(), _>(Error::Abort))?;
+        assert_eq!(
+            report.record("update", start, Err::<(), _>(Error::Value("boom".into()))),
+            Err(Error::Value("boom".into()))
+        );
+
+        let mut other = Report::new("other");
+        other.record("update", start, Ok(()))?;
+        report.merge(&other);
+        report.finish(Duration::from_secs(1));
+
+        assert_eq!(report.count(), 2);
+        assert_eq!(report.errors(), 2);
+        assert_eq!(report.histogram("read").map(|h| h.count()), Some(1));
+        assert_eq!(report.histogram("scan").map(|h| h.count()), None);
+        assert!(report.to_string().starts_with("test: 2 operations in 1.000s (2.0/s), 2 errors\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,602 @@
+//! A TPC-C-lite order processing workload, loosely modeled on the TPC-C benchmark. It uses the
+//! same five transaction types and mix, but with a much smaller dataset (30 customers per
+//! district and 1000 items), uniformly random choices, no initial orders, and no order history.
+//! Composite TPC-C keys are flattened into integer primary keys.
+
+use super::{rows, Report};
+use crate::client::{Client, Pool};
+use crate::error::{Error, Result};
+
+use futures::stream::TryStreamExt as _;
+use rand::Rng as _;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::time::Instant;
+
+const DISTRICTS_PER_WAREHOUSE: i64 = 10;
+const CUSTOMERS_PER_DISTRICT: i64 = 30;
+const ITEMS: i64 = 1000;
+
+/// Order IDs are allocated per district, starting at district ID times this.
+const ORDER_ID_SPACE: i64 = 1_000_000;
+/// Order line IDs are the order ID times this plus the line number.
+const ORDER_LINE_ID_SPACE: i64 = 16;
+
+/// The number of rows inserted per statement during loading.
+const LOAD_BATCH_SIZE: usize = 100;
+/// The maximum number of OR'ed lookups per query, to stay within the expression depth limit.
+const LOOKUP_BATCH_SIZE: usize = 32;
+
+/// The schema.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE warehouse (
+        id INTEGER PRIMARY KEY,
+        name STRING NOT NULL,
+        tax FLOAT NOT NULL,
+        ytd INTEGER NOT NULL
+    )",
+    "CREATE TABLE district (
+        id INTEGER PRIMARY KEY,
+        warehouse_id INTEGER NOT NULL INDEX REFERENCES warehouse,
+        name STRING NOT NULL,
+        tax FLOAT NOT NULL,
+        ytd INTEGER NOT NULL,
+        next_order_id INTEGER NOT NULL
+    )",
+    "CREATE TABLE customer (
+        id INTEGER PRIMARY KEY,
+        district_id INTEGER NOT NULL INDEX REFERENCES district,
+        name STRING NOT NULL,
+        discount FLOAT NOT NULL,
+        balance INTEGER NOT NULL,
+        ytd_payment INTEGER NOT NULL,
+        payment_count INTEGER NOT NULL,
+        delivery_count INTEGER NOT NULL
+    )",
+    "CREATE TABLE item (
+        id INTEGER PRIMARY KEY,
+        name STRING NOT NULL,
+        price INTEGER NOT NULL
+    )",
+    "CREATE TABLE stock (
+        id INTEGER PRIMARY KEY,
+        warehouse_id INTEGER NOT NULL REFERENCES warehouse,
+        item_id INTEGER NOT NULL REFERENCES item,
+        quantity INTEGER NOT NULL,
+        ytd INTEGER NOT NULL,
+        order_count INTEGER NOT NULL
+    )",
+    "CREATE TABLE orders (
+        id INTEGER PRIMARY KEY,
+        district_id INTEGER NOT NULL REFERENCES district,
+        customer_id INTEGER NOT NULL INDEX REFERENCES customer,
+        line_count INTEGER NOT NULL,
+        carrier_id INTEGER
+    )",
+    "CREATE TABLE new_order (
+        order_id INTEGER PRIMARY KEY REFERENCES orders,
+        district_id INTEGER NOT NULL INDEX REFERENCES district
+    )",
+    "CREATE TABLE order_line (
+        id INTEGER PRIMARY KEY,
+        order_id INTEGER NOT NULL INDEX REFERENCES orders,
+        item_id INTEGER NOT NULL REFERENCES item,
+        quantity INTEGER NOT NULL,
+        amount INTEGER NOT NULL
+    )",
+];
+
+/// A TPC-C-lite benchmark. Monetary amounts are integer cents.
+#[derive(Clone, Debug)]
+pub struct Tpcc {
+    warehouses: i64,
+}
+
+impl Tpcc {
+    /// Creates a new TPC-C-lite benchmark with the given number of warehouses.
+    pub fn new(warehouses: u64) -> Self {
+        Self { warehouses: warehouses.max(1) as i64 }
+    }
+
+    /// Creates the schema and loads the initial dataset.
+    pub async fn load(&self, pool: &Pool) -> Result<Report> {
+        let mut report = Report::new("TPC-C load");
+        let start = Instant::now();
+        let client = pool.get().await;
+        for query in SCHEMA {
+            client.execute(query).await?;
+        }
+        let mut rng = rand::thread_rng();
+        let tables: Vec<(&str, Vec<String>)> = vec![
+            (
+                "warehouse",
+                (1..=self.warehouses)
+                    .map(|w| {
+                        format!("({}, 'warehouse{}', {:.4}, 0)", w, w, rng.gen_range(0.0, 0.2))
+                    })
+                    .collect(),
+            ),
+            (
+                "district",
+                self.districts()
+                    .map(|(w, d)| {
+                        format!(
+                            "({}, {}, 'district{}', {:.4}, 0, 1)",
+                            d,
+                            w,
+                            d,
+                            rng.gen_range(0.0, 0.2)
+                        )
+                    })
+                    .collect(),
+            ),
+            (
+                "customer",
+                self.districts()
+                    .flat_map(|(_, d)| Self::customers(d).map(move |c| (d, c)))
+                    .map(|(d, c)| {
+                        format!(
+                            "({}, {}, 'customer{}', {:.4}, 0, 0, 0, 0)",
+                            c,
+                            d,
+                            c,
+                            rng.gen_range(0.0, 0.5)
+                        )
+                    })
+                    .collect(),
+            ),
+            (
+                "item",
+                (1..=ITEMS)
+                    .map(|i| format!("({}, 'item{}', {})", i, i, rng.gen_range(100, 10_000)))
+                    .collect(),
+            ),
+            (
+                "stock",
+                (1..=self.warehouses)
+                    .flat_map(|w| (1..=ITEMS).map(move |i| (w, i)))
+                    .map(|(w, i)| {
+                        format!(
+                            "({}, {}, {}, {}, 0, 0)",
+                            stock_id(w, i),
+                            w,
+                            i,
+                            rng.gen_range(10, 101)
+                        )
+                    })
+                    .collect(),
+            ),
+        ];
+        for (table, values) in tables {
+            for batch in values.chunks(LOAD_BATCH_SIZE) {
+                let start = Instant::now();
+                let query = format!("INSERT INTO {} VALUES {}", table, batch.join(", "));
+                report.record("load", start, client.execute(&query).await)?;
+            }
+        }
+        report.finish(start.elapsed());
+        Ok(report)
+    }
+
+    /// Runs the given number of transactions, using all pool clients concurrently. Serialization
+    /// failures are retried, and transactions that still fail are counted as errors.
+    pub async fn run(&self, pool: &Pool, transactions: u64) -> Result<Report> {
+        let report = RefCell::new(Report::new("TPC-C"));
+        let start = Instant::now();
+        futures::stream::iter(
+            std::iter::repeat_with(|| self.generate()).take(transactions as usize).map(Ok),
+        )
+        .try_for_each_concurrent(pool.size(), |transaction| {
+            let report = &report;
+            async move {
+                let client = pool.get().await;
+                let start = Instant::now();
+                let result = transaction.execute(&client).await;
+                report.borrow_mut().record(transaction.name(), start, result)
+            }
+        })
+        .await?;
+        let mut report = report.into_inner();
+        report.finish(start.elapsed());
+        Ok(report)
+    }
+
+    /// Verifies that consistency invariants hold: warehouse and district year-to-date payments
+    /// match, district order IDs match the orders, and all orders are either new or delivered.
+    pub async fn verify(&self, pool: &Pool) -> Result<()> {
+        let client = pool.get().await;
+        let client: &Client = &client;
+        let value = |query| async move { client.execute(query).await?.into_value()?.integer() };
+        let warehouse_ytd = value("SELECT SUM(ytd) FROM warehouse").await?;
+        let district_ytd = value("SELECT SUM(ytd) FROM district").await?;
+        if warehouse_ytd != district_ytd {
+            return Err(Error::Value(format!(
+                "Warehouse payments {} do not match district payments {}",
+                warehouse_ytd, district_ytd
+            )));
+        }
+        let next_order_ids = value("SELECT SUM(next_order_id - 1) FROM district").await?;
+        let orders = value("SELECT COUNT(*) FROM orders").await?;
+        if next_order_ids != orders {
+            return Err(Error::Value(format!(
+                "District order IDs allocated {} orders, found {}",
+                next_order_ids, orders
+            )));
+        }
+        let new_orders = value("SELECT COUNT(*) FROM new_order").await?;
+        let undelivered = value("SELECT COUNT(*) FROM orders WHERE carrier_id IS NULL").await?;
+        if new_orders != undelivered {
+            return Err(Error::Value(format!(
+                "Found {} new orders but {} undelivered orders",
+                new_orders, undelivered
+            )));
+        }
+        Ok(())
+    }
+
+    /// Generates a random transaction, using the TPC-C transaction mix.
+    fn generate(&self) -> Transaction {
+        let mut rng = rand::thread_rng();
+        let warehouse = rng.gen_range(1, self.warehouses + 1);
+        let district = district_id(warehouse, rng.gen_range(1, DISTRICTS_PER_WAREHOUSE + 1));
+        let customer = customer_id(district, rng.gen_range(1, CUSTOMERS_PER_DISTRICT + 1));
+        match rng.gen_range(0, 100) {
+            0..=44 => {
+                let mut items = BTreeSet::new();
+                let count = rng.gen_range(5, 16);
+                while items.len() < count {
+                    items.insert(rng.gen_range(1, ITEMS + 1));
+                }
+                let lines = items.into_iter().map(|i| (i, rng.gen_range(1, 11))).collect();
+                Transaction::NewOrder { warehouse, district, customer, lines }
+            }
+            45..=87 => {
+                let amount = rng.gen_range(100, 500_001);
+                Transaction::Payment { warehouse, district, customer, amount }
+            }
+            88..=91 => Transaction::OrderStatus { customer },
+            92..=95 => Transaction::Delivery { warehouse, carrier: rng.gen_range(1, 11) },
+            _ => Transaction::StockLevel { warehouse, district, threshold: rng.gen_range(10, 21) },
+        }
+    }
+
+    /// Returns the warehouse and district IDs of all districts.
+    fn districts(&self) -> impl Iterator<Item = (i64, i64)> {
+        (1..=self.warehouses)
+            .flat_map(|w| (1..=DISTRICTS_PER_WAREHOUSE).map(move |d| (w, district_id(w, d))))
+    }
+
+    /// Returns the customer IDs of a district.
+    fn customers(district: i64) -> impl Iterator<Item = i64> {
+        (1..=CUSTOMERS_PER_DISTRICT).map(move |c| customer_id(district, c))
+    }
+}
+
+/// Returns the ID of a warehouse's district, given its number (1-10) in the warehouse.
+fn district_id(warehouse: i64, district: i64) -> i64 {
+    (warehouse - 1) * DISTRICTS_PER_WAREHOUSE + district
+}
+
+/// Returns the ID of a district's customer, given its number in the district.
+fn customer_id(district: i64, customer: i64) -> i64 {
+    (district - 1) * CUSTOMERS_PER_DISTRICT + customer
+}
+
+/// Returns the ID of a warehouse's stock of an item.
+fn stock_id(warehouse: i64, item: i64) -> i64 {
+    (warehouse - 1) * ITEMS + item
+}
+
+/// A TPC-C transaction, with randomized parameters.
+#[derive(Debug)]
+enum Transaction {
+    /// Places an order for a customer, with (item, quantity) lines.
+    NewOrder { warehouse: i64, district: i64, customer: i64, lines: Vec<(i64, i64)> },
+    /// Records a customer payment.
+    Payment { warehouse: i64, district: i64, customer: i64, amount: i64 },
+    /// Fetches the status of a customer's latest order.
+    OrderStatus { customer: i64 },
+    /// Delivers the oldest new order of each district in a warehouse.
+    Delivery { warehouse: i64, carrier: i64 },
+    /// Counts recently ordered items whose stock is below a threshold.
+    StockLevel { warehouse: i64, district: i64, threshold: i64 },
+}
+
+impl Transaction {
+    /// Returns the transaction name, used in reports.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::NewOrder { .. } => "neworder",
+            Self::Payment { .. } => "payment",
+            Self::OrderStatus { .. } => "orderstatus",
+            Self::Delivery { .. } => "delivery",
+            Self::StockLevel { .. } => "stocklevel",
+        }
+    }
+
+    /// Executes the transaction, retrying serialization failures.
+    async fn execute(&self, client: &Client) -> Result<()> {
+        match self {
+            Self::NewOrder { warehouse, district, customer, lines } => {
+                client
+                    .with_txn(|txn| async move {
+                        txn.execute(&format!("SELECT tax FROM warehouse WHERE id = {}", warehouse))
+                            .await?
+                            .into_value()?;
+                        let next_order_id = txn
+                            .execute(&format!(
+                                "SELECT next_order_id FROM district WHERE id = {}",
+                                district
+                            ))
+                            .await?
+                            .into_value()?
+                            .integer()?;
+                        txn.execute(&format!(
+                            "UPDATE district SET next_order_id = next_order_id + 1 WHERE id = {}",
+                            district
+                        ))
+                        .await?;
+                        txn.execute(&format!(
+                            "SELECT discount FROM customer WHERE id = {}",
+                            customer
+                        ))
+                        .await?
+                        .into_value()?;
+
+                        let order = district * ORDER_ID_SPACE + next_order_id;
+                        txn.execute(&format!(
+                            "INSERT INTO orders VALUES ({}, {}, {}, {}, NULL)",
+                            order,
+                            district,
+                            customer,
+                            lines.len()
+                        ))
+                        .await?;
+                        txn.execute(&format!(
+                            "INSERT INTO new_order VALUES ({}, {})",
+                            order, district
+                        ))
+                        .await?;
+
+                        for (number, (item, quantity)) in lines.iter().enumerate() {
+                            let price = txn
+                                .execute(&format!("SELECT price FROM item WHERE id = {}", item))
+                                .await?
+                                .into_value()?
+                                .integer()?;
+                            let stock = stock_id(*warehouse, *item);
+                            let mut stock_quantity = txn
+                                .execute(&format!(
+                                    "SELECT quantity FROM stock WHERE id = {}",
+                                    stock
+                                ))
+                                .await?
+                                .into_value()?
+                                .integer()?;
+                            // Stock is replenished when it runs low, as in TPC-C.
+                            stock_quantity -= quantity;
+                            if stock_quantity < 10 {
+                                stock_quantity += 91;
+                            }
+                            txn.execute(&format!(
+                                "UPDATE stock SET quantity = {}, ytd = ytd + {}, \
+                                 order_count = order_count + 1 WHERE id = {}",
+                                stock_quantity, quantity, stock
+                            ))
+                            .await?;
+                            txn.execute(&format!(
+                                "INSERT INTO order_line VALUES ({}, {}, {}, {}, {})",
+                                order * ORDER_LINE_ID_SPACE + number as i64 + 1,
+                                order,
+                                item,
+                                quantity,
+                                price * quantity
+                            ))
+                            .await?;
+                        }
+                        Ok(())
+                    })
+                    .await
+            }
+
+            Self::Payment { warehouse, district, customer, amount } => {
+                client
+                    .with_txn(|txn| async move {
+                        txn.execute(&format!(
+                            "UPDATE warehouse SET ytd = ytd + {} WHERE id = {}",
+                            amount, warehouse
+                        ))
+                        .await?;
+                        txn.execute(&format!(
+                            "UPDATE district SET ytd = ytd + {} WHERE id = {}",
+                            amount, district
+                        ))
+                        .await?;
+                        txn.execute(&format!(
+                            "UPDATE customer SET balance = balance - {}, \
+                             ytd_payment = ytd_payment + {}, payment_count = payment_count + 1 \
+                             WHERE id = {}",
+                            amount, amount, customer
+                        ))
+                        .await?;
+                        Ok(())
+                    })
+                    .await
+            }
+
+            Self::OrderStatus { customer } => {
+                client
+                    .with_txn(|txn| async move {
+                        txn.execute(&format!(
+                            "SELECT name, balance FROM customer WHERE id = {}",
+                            customer
+                        ))
+                        .await?
+                        .into_row()?;
+                        let orders = rows(
+                            txn.execute(&format!(
+                                "SELECT id, carrier_id FROM orders WHERE customer_id = {} \
+                                 ORDER BY id DESC LIMIT 1",
+                                customer
+                            ))
+                            .await?,
+                        )?;
+                        if let Some(mut order) = orders.into_iter().next() {
+                            rows(
+                                txn.execute(&format!(
+                                    "SELECT item_id, quantity, amount FROM order_line \
+                                     WHERE order_id = {}",
+                                    order.remove(0).integer()?
+                                ))
+                                .await?,
+                            )?;
+                        }
+                        Ok(())
+                    })
+                    .await
+            }
+
+            Self::Delivery { warehouse, carrier } => {
+                client
+                    .with_txn(|txn| async move {
+                        for number in 1..=DISTRICTS_PER_WAREHOUSE {
+                            let district = district_id(*warehouse, number);
+                            let new_orders = rows(
+                                txn.execute(&format!(
+                                    "SELECT order_id FROM new_order WHERE district_id = {} \
+                                     ORDER BY order_id LIMIT 1",
+                                    district
+                                ))
+                                .await?,
+                            )?;
+                            let order = match new_orders.into_iter().next() {
+                                Some(mut row) => row.remove(0).integer()?,
+                                None => continue,
+                            };
+                            txn.execute(&format!(
+                                "DELETE FROM new_order WHERE order_id = {}",
+                                order
+                            ))
+                            .await?;
+                            let customer = txn
+                                .execute(&format!(
+                                    "SELECT customer_id FROM orders WHERE id = {}",
+                                    order
+                                ))
+                                .await?
+                                .into_value()?
+                                .integer()?;
+                            txn.execute(&format!(
+                                "UPDATE orders SET carrier_id = {} WHERE id = {}",
+                                carrier, order
+                            ))
+                            .await?;
+                            let amount = txn
+                                .execute(&format!(
+                                    "SELECT SUM(amount) FROM order_line WHERE order_id = {}",
+                                    order
+                                ))
+                                .await?
+                                .into_value()?
+                                .integer()?;
+                            txn.execute(&format!(
+                                "UPDATE customer SET balance = balance + {}, \
+                                 delivery_count = delivery_count + 1 WHERE id = {}",
+                                amount, customer
+                            ))
+                            .await?;
+                        }
+                        Ok(())
+                    })
+                    .await
+            }
+
+            Self::StockLevel { warehouse, district, threshold } => {
+                client
+                    .with_txn(|txn| async move {
+                        let next_order_id = txn
+                            .execute(&format!(
+                                "SELECT next_order_id FROM district WHERE id = {}",
+                                district
+                            ))
+                            .await?
+                            .into_value()?
+                            .integer()?;
+                        let orders: Vec<String> = ((next_order_id - 20).max(1)..next_order_id)
+                            .map(|o| format!("order_id = {}", district * ORDER_ID_SPACE + o))
+                            .collect();
+                        if orders.is_empty() {
+                            return Ok(());
+                        }
+                        let mut items = BTreeSet::new();
+                        for mut row in rows(
+                            txn.execute(&format!(
+                                "SELECT item_id FROM order_line WHERE {}",
+                                orders.join(" OR ")
+                            ))
+                            .await?,
+                        )? {
+                            items.insert(row.remove(0).integer()?);
+                        }
+                        let stocks: Vec<String> = items
+                            .into_iter()
+                            .map(|i| format!("id = {}", stock_id(*warehouse, i)))
+                            .collect();
+                        for batch in stocks.chunks(LOOKUP_BATCH_SIZE) {
+                            txn.execute(&format!(
+                                "SELECT COUNT(*) FROM stock WHERE ({}) AND quantity < {}",
+                                batch.join(" OR "),
+                                threshold
+                            ))
+                            .await?
+                            .into_value()?;
+                        }
+                        Ok(())
+                    })
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids() {
+        assert_eq!(district_id(1, 1), 1);
+        assert_eq!(district_id(2, 10), 20);
+        assert_eq!(customer_id(1, 1), 1);
+        assert_eq!(customer_id(20, 30), 600);
+        assert_eq!(stock_id(2, 1), 1001);
+
+        let tpcc = Tpcc::new(2);
+        let districts: Vec<_> = tpcc.districts().collect();
+        assert_eq!(districts.len(), 20);
+        assert_eq!(districts[10], (2, 11));
+        let customers: BTreeSet<_> =
+            tpcc.districts().flat_map(|(_, d)| Tpcc::customers(d)).collect();
+        assert_eq!(customers, (1..=600).collect());
+    }
+
+    #[test]
+    fn generate() {
+        let tpcc = Tpcc::new(2);
+        for _ in 0..1000 {
+            match tpcc.generate() {
+                Transaction::NewOrder { warehouse, district, customer, lines } => {
+                    assert!((1..=2).contains(&warehouse));
+                    assert_eq!((district - 1) / DISTRICTS_PER_WAREHOUSE + 1, warehouse);
+                    assert_eq!((customer - 1) / CUSTOMERS_PER_DISTRICT + 1, district);
+                    assert!((5..=15).contains(&lines.len()));
+                    assert!(lines.windows(2).all(|w| w[0].0 < w[1].0));
+                }
+                Transaction::StockLevel { threshold, .. } => {
+                    assert!((10..=20).contains(&threshold))
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,520 @@
+//! YCSB-style key/value workloads, modeled on the core workloads of the Yahoo! Cloud Serving
+//! Benchmark. Records have sequential user keys (e.g. user0000000042) and a single random string
+//! value, and are either stored in a SQL table or directly in an MVCC store.
+
+use super::{rows, Report};
+use crate::client::{Client, Pool};
+use crate::error::{Error, Result};
+use crate::storage::kv::mvcc::{Mode, Transaction, MVCC};
+
+use futures::stream::TryStreamExt as _;
+use rand::distributions::{Alphanumeric, Distribution};
+use rand::Rng as _;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The number of records written per load transaction.
+const LOAD_BATCH_SIZE: u64 = 100;
+
+/// A YCSB core workload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Workload {
+    /// Update heavy: 50% reads, 50% updates.
+    A,
+    /// Read mostly: 95% reads, 5% updates.
+    B,
+    /// Read only: 100% reads.
+    C,
+    /// Read latest: 95% reads of recently inserted records, 5% inserts.
+    D,
+    /// Short ranges: 95% scans, 5% inserts.
+    E,
+    /// Read-modify-write: 50% reads, 50% read-modify-write transactions.
+    F,
+}
+
+impl Workload {
+    /// Parses a workload name (a to f).
+    pub fn parse(workload: &str) -> Result<Self> {
+        match workload.to_lowercase().as_str() {
+            "a" => Ok(Self::A),
+            "b" => Ok(Self::B),
+            "c" => Ok(Self::C),
+            "d" => Ok(Self::D),
+            "e" => Ok(Self::E),
+            "f" => Ok(Self::F),
+            workload => Err(Error::Config(format!("Invalid YCSB workload {}", workload))),
+        }
+    }
+}
+
+impl std::fmt::Display for Workload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// A YCSB benchmark.
+#[derive(Clone, Debug)]
+pub struct Ycsb {
+    workload: Workload,
+    records: u64,
+    value_size: usize,
+    max_scan: u64,
+}
+
+impl Ycsb {
+    /// Creates a new YCSB benchmark for the given workload and number of initial records, with
+    /// 100-byte values and scans of up to 100 records.
+    pub fn new(workload: Workload, records: u64) -> Self {
+        Self { workload, records: records.max(1), value_size: 100, max_scan: 100 }
+    }
+
+    /// Sets the value size in bytes.
+    pub fn with_value_size(mut self, value_size: usize) -> Self {
+        self.value_size = value_size;
+        self
+    }
+
+    /// Sets the maximum number of records returned by a scan.
+    pub fn with_max_scan(mut self, max_scan: u64) -> Self {
+        self.max_scan = max_scan.max(1);
+        self
+    }
+
+    /// Creates the usertable table and loads the initial records into a SQL cluster.
+    pub async fn load_sql(&self, pool: &Pool) -> Result<Report> {
+        pool.get()
+            .await
+            .execute("CREATE TABLE usertable (id STRING PRIMARY KEY, field STRING NOT NULL)")
+            .await?;
+
+        let report = RefCell::new(Report::new("YCSB load"));
+        let start = Instant::now();
+        futures::stream::iter(self.batches().map(Ok))
+            .try_for_each_concurrent(pool.size(), |(from, to)| {
+                let report = &report;
+                async move {
+                    let client = pool.get().await;
+                    let start = Instant::now();
+                    let values = (from..to)
+                        .map(|id| format!("('{}', '{}')", key(id), self.value()))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let result =
+                        client.execute(&format!("INSERT INTO usertable VALUES {}", values)).await;
+                    report.borrow_mut().record("load", start, result)
+                }
+            })
+            .await?;
+        let mut report = report.into_inner();
+        report.finish(start.elapsed());
+        Ok(report)
+    }
+
+    /// Runs the given number of operations against a SQL cluster, using all pool clients
+    /// concurrently. The records must already be loaded.
+    pub async fn run_sql(&self, pool: &Pool, operations: u64) -> Result<Report> {
+        let mut generator = Generator::new(self);
+        let report = RefCell::new(Report::new(&format!("YCSB workload {}", self.workload)));
+        let start = Instant::now();
+        futures::stream::iter(
+            std::iter::repeat_with(|| generator.generate()).take(operations as usize).map(Ok),
+        )
+        .try_for_each_concurrent(pool.size(), |operation| {
+            let report = &report;
+            async move {
+                let client = pool.get().await;
+                let start = Instant::now();
+                let result = self.execute_sql(&client, &operation).await;
+                report.borrow_mut().record(operation.name(), start, result)
+            }
+        })
+        .await?;
+        let mut report = report.into_inner();
+        report.finish(start.elapsed());
+        Ok(report)
+    }
+
+    /// Executes an operation against a SQL cluster.
+    async fn execute_sql(&self, client: &Client, operation: &Operation) -> Result<()> {
+        match operation {
+            Operation::Read(id) => {
+                rows(client.execute(&Self::select_sql(*id)).await?)?;
+            }
+            Operation::Update(id) => {
+                client.execute(&self.update_sql(*id)).await?;
+            }
+            Operation::Insert(id) => {
+                client
+                    .execute(&format!(
+                        "INSERT INTO usertable VALUES ('{}', '{}')",
+                        key(*id),
+                        self.value()
+                    ))
+                    .await?;
+            }
+            Operation::Scan(id, limit) => {
+                rows(
+                    client
+                        .execute(&format!(
+                            "SELECT id, field FROM usertable WHERE id >= '{}' ORDER BY id LIMIT {}",
+                            key(*id),
+                            limit
+                        ))
+                        .await?,
+                )?;
+            }
+            Operation::ReadModifyWrite(id) => {
+                let result: Result<()> = async {
+                    client.execute("BEGIN").await?;
+                    rows(client.execute(&Self::select_sql(*id)).await?)?;
+                    client.execute(&self.update_sql(*id)).await?;
+                    client.execute("COMMIT").await?;
+                    Ok(())
+                }
+                .await;
+                if result.is_err() && client.txn().is_some() {
+                    client.execute("ROLLBACK").await.ok();
+                }
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a query that reads a record.
+    fn select_sql(id: u64) -> String {
+        format!("SELECT field FROM usertable WHERE id = '{}'", key(id))
+    }
+
+    /// Returns a query that updates a record with a new random value.
+    fn update_sql(&self, id: u64) -> String {
+        format!("UPDATE usertable SET field = '{}' WHERE id = '{}'", self.value(), key(id))
+    }
+
+    /// Loads the initial records directly into an MVCC store.
+    pub fn load_kv(&self, mvcc: &MVCC) -> Result<Report> {
+        let mut report = Report::new("YCSB load");
+        let start = Instant::now();
+        for (from, to) in self.batches() {
+            let start = Instant::now();
+            let result = with_txn(mvcc, Mode::ReadWrite, |txn| {
+                for id in from..to {
+                    txn.set(key(id).as_bytes(), self.value().into_bytes())?;
+                }
+                Ok(())
+            });
+            report.record("load", start, result)?;
+        }
+        report.finish(start.elapsed());
+        Ok(report)
+    }
+
+    /// Runs the given number of operations directly against an MVCC store, bypassing SQL and
+    /// Raft, using the given number of concurrent threads. The records must already be loaded.
+    pub fn run_kv(&self, mvcc: &MVCC, concurrency: u64, operations: u64) -> Result<Report> {
+        let concurrency = concurrency.max(1);
+        let generator = Generator::new(self);
+        let start = Instant::now();
+        let workers: Vec<_> = (0..concurrency)
+            .map(|i| {
+                let operations = operations / concurrency + (i < operations % concurrency) as u64;
+                let (ycsb, mvcc, mut generator) = (self.clone(), mvcc.clone(), generator.clone());
+                std::thread::spawn(move || -> Result<Report> {
+                    let mut report = Report::new("");
+                    for _ in 0..operations {
+                        let operation = generator.generate();
+                        let start = Instant::now();
+                        let result = ycsb.execute_kv(&mvcc, &operation);
+                        report.record(operation.name(), start, result)?;
+                    }
+                    Ok(report)
+                })
+            })
+            .collect();
+
+        let mut report = Report::new(&format!("YCSB workload {} (key/value)", self.workload));
+        for worker in workers {
+            let worker = worker.join().map_err(|_| Error::Internal("Worker panicked".into()))?;
+            report.merge(&worker?);
+        }
+        report.finish(start.elapsed());
+        Ok(report)
+    }
+
+    /// Executes an operation against an MVCC store.
+    fn execute_kv(&self, mvcc: &MVCC, operation: &Operation) -> Result<()> {
+        match operation {
+            Operation::Read(id) => {
+                with_txn(mvcc, Mode::ReadOnly, |txn| txn.get(key(*id).as_bytes()))?;
+            }
+            Operation::Update(id) | Operation::Insert(id) => {
+                with_txn(mvcc, Mode::ReadWrite, |txn| {
+                    txn.set(key(*id).as_bytes(), self.value().into_bytes())
+                })?;
+            }
+            Operation::Scan(id, limit) => {
+                with_txn(mvcc, Mode::ReadOnly, |txn| {
+                    txn.scan(key(*id).into_bytes()..)?
+                        .take(*limit as usize)
+                        .collect::<Result<Vec<_>>>()
+                })?;
+            }
+            Operation::ReadModifyWrite(id) => {
+                with_txn(mvcc, Mode::ReadWrite, |txn| {
+                    txn.get(key(*id).as_bytes())?;
+                    txn.set(key(*id).as_bytes(), self.value().into_bytes())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the ID ranges of the load batches.
+    fn batches(&self) -> impl Iterator<Item = (u64, u64)> {
+        let records = self.records;
+        (0..records)
+            .step_by(LOAD_BATCH_SIZE as usize)
+            .map(move |from| (from, (from + LOAD_BATCH_SIZE).min(records)))
+    }
+
+    /// Generates a random value.
+    fn value(&self) -> String {
+        rand::thread_rng().sample_iter(&Alphanumeric).take(self.value_size).collect()
+    }
+}
+
+/// Returns the key of a record ID.
+fn key(id: u64) -> String {
+    format!("user{:010}", id)
+}
+
+/// Runs a closure in an MVCC transaction, committing it on success and rolling it back on error.
+fn with_txn<R, F: FnOnce(&mut Transaction) -> Result<R>>(
+    mvcc: &MVCC,
+    mode: Mode,
+    f: F,
+) -> Result<R> {
+    let mut txn = mvcc.begin_with_mode(mode)?;
+    match f(&mut txn) {
+        Ok(result) => {
+            txn.commit()?;
+            Ok(result)
+        }
+        Err(err) => {
+            txn.rollback()?;
+            Err(err)
+        }
+    }
+}
+
+/// A workload operation, on a record ID.
+#[derive(Debug, PartialEq)]
+enum Operation {
+    Read(u64),
+    Update(u64),
+    Insert(u64),
+    /// Scans up to the given number of records from the record ID.
+    Scan(u64, u64),
+    ReadModifyWrite(u64),
+}
+
+impl Operation {
+    /// Returns the operation name, used in reports.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Read(_) => "read",
+            Self::Update(_) => "update",
+            Self::Insert(_) => "insert",
+            Self::Scan(_, _) => "scan",
+            Self::ReadModifyWrite(_) => "readmodifywrite",
+        }
+    }
+}
+
+/// Generates random operations for a workload. Records are chosen from a scrambled Zipfian
+/// distribution over the initial records, except for workload D which reads the latest inserted
+/// records. Insert IDs are shared between clones, so concurrent workers insert distinct records.
+#[derive(Clone)]
+struct Generator {
+    workload: Workload,
+    max_scan: u64,
+    zipfian: Zipfian,
+    next_insert: Arc<AtomicU64>,
+}
+
+impl Generator {
+    /// Creates a new generator for a benchmark.
+    fn new(ycsb: &Ycsb) -> Self {
+        Self {
+            workload: ycsb.workload,
+            max_scan: ycsb.max_scan,
+            zipfian: Zipfian::new(ycsb.records),
+            next_insert: Arc::new(AtomicU64::new(ycsb.records)),
+        }
+    }
+
+    /// Generates a random operation.
+    fn generate(&mut self) -> Operation {
+        let mut rng = rand::thread_rng();
+        let p: f64 = rng.gen();
+        match self.workload {
+            Workload::A if p < 0.5 => Operation::Read(self.choose()),
+            Workload::A => Operation::Update(self.choose()),
+            Workload::B if p < 0.95 => Operation::Read(self.choose()),
+            Workload::B => Operation::Update(self.choose()),
+            Workload::C => Operation::Read(self.choose()),
+            Workload::D if p < 0.95 => Operation::Read(self.latest()),
+            Workload::D => Operation::Insert(self.insert()),
+            Workload::E if p < 0.95 => {
+                Operation::Scan(self.choose(), rng.gen_range(1, self.max_scan + 1))
+            }
+            Workload::E => Operation::Insert(self.insert()),
+            Workload::F if p < 0.5 => Operation::Read(self.choose()),
+            Workload::F => Operation::ReadModifyWrite(self.choose()),
+        }
+    }
+
+    /// Chooses a record, scrambling the Zipfian rank so that popular records are spread out.
+    fn choose(&self) -> u64 {
+        fnv1a(self.zipfian.sample(&mut rand::thread_rng())) % self.zipfian.n
+    }
+
+    /// Chooses a recently inserted record, skewed towards the latest.
+    fn latest(&self) -> u64 {
+        let latest = self.next_insert.load(Ordering::SeqCst) - 1;
+        latest - self.zipfian.sample(&mut rand::thread_rng())
+    }
+
+    /// Allocates the ID of a new record.
+    fn insert(&self) -> u64 {
+        self.next_insert.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// A Zipfian distribution over the ranks 0..n with YCSB's skew of 0.99, where rank 0 is the most
+/// popular. Uses the algorithm from "Quickly Generating Billion-Record Synthetic Databases" by
+/// Gray et al.
+#[derive(Clone, Debug)]
+struct Zipfian {
+    n: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    const THETA: f64 = 0.99;
+
+    /// Creates a new Zipfian distribution. This takes O(n) time.
+    fn new(n: u64) -> Self {
+        let theta = Self::THETA;
+        let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zetan = zeta(n);
+        let eta = (1.0 - (2.0 / n as f64).powf(1.0 - theta)) / (1.0 - zeta(2) / zetan);
+        Self { n, theta, alpha: 1.0 / (1.0 - theta), zetan, eta }
+    }
+}
+
+impl Distribution<u64> for Zipfian {
+    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> u64 {
+        let u: f64 = rng.gen();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5_f64.powf(self.theta) {
+            return 1.min(self.n - 1);
+        }
+        let rank = (self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        rank.min(self.n - 1)
+    }
+}
+
+/// Hashes a value using 64-bit FNV-1a.
+fn fnv1a(value: u64) -> u64 {
+    value.to_le_bytes().iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::Memory;
+
+    #[test]
+    fn workload_parse() -> Result<()> {
+        assert_eq!(Workload::parse("a")?, Workload::A);
+        assert_eq!(Workload::parse("F")?, Workload::F);
+        assert!(Workload::parse("g").is_err());
+        assert_eq!(Workload::E.to_string(), "e");
+        Ok(())
+    }
+
+    #[test]
+    fn zipfian() {
+        let zipfian = Zipfian::new(1000);
+        let mut rng = rand::thread_rng();
+        let mut counts = vec![0; 1000];
+        for _ in 0..100_000 {
+            counts[zipfian.sample(&mut rng) as usize] += 1;
+        }
+        // The distribution is heavily skewed towards the low ranks.
+        assert!(counts[0] > counts[1]);
+        assert!(counts[1] > counts[10]);
+        assert!(counts[0] > 100_000 / 20);
+        assert!(counts[..100].iter().sum::<u64>() > counts[100..].iter().sum::<u64>());
+
+        // Single-record distributions always pick the record.
+        let single = Zipfian::new(1);
+        assert!((0..100).all(|_| single.sample(&mut rng) == 0));
+    }
+
+    #[test]
+    fn generator() {
+        let ycsb = Ycsb::new(Workload::D, 100);
+        let mut generator = Generator::new(&ycsb);
+        let mut inserts = Vec::new();
+        for _ in 0..1000 {
+            match generator.generate() {
+                Operation::Read(id) => assert!(id < generator.next_insert.load(Ordering::SeqCst)),
+                Operation::Insert(id) => inserts.push(id),
+                operation => panic!("Unexpected operation {:?}", operation),
+            }
+        }
+        assert!(!inserts.is_empty());
+        assert_eq!(inserts, (100..100 + inserts.len() as u64).collect::<Vec<_>>());
+
+        let ycsb = Ycsb::new(Workload::E, 100).with_max_scan(10);
+        let mut generator = Generator::new(&ycsb);
+        for _ in 0..1000 {
+            if let Operation::Scan(id, limit) = generator.generate() {
+                assert!(id < 100);
+                assert!((1..=10).contains(&limit));
+            }
+        }
+    }
+
+    #[test]
+    fn run_kv() -> Result<()> {
+        let mvcc = MVCC::new(Box::new(Memory::new()));
+        let ycsb = Ycsb::new(Workload::E, 250).with_value_size(10);
+        let report = ycsb.load_kv(&mvcc)?;
+        assert_eq!(report.count(), 3);
+
+        let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(txn.scan(..)?.count(), 250);
+        assert_eq!(txn.get(key(249).as_bytes())?.map(|v| v.len()), Some(10));
+        txn.commit()?;
+
+        let report = ycsb.run_kv(&mvcc, 4, 1000)?;
+        assert_eq!(report.count() as u64 + report.errors(), 1000);
+        assert!(report.histogram("scan").is_some());
+        Ok(())
+    }
+}
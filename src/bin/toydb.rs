@@ -2,6 +2,12 @@
  * toydb is the toyDB server. It takes configuration via a configuration file, command-line
  * parameters, and environment variables, then starts up a toyDB TCP server that communicates with
  * SQL clients (port 9605) and Raft peers (port 9705).
+ *
+ * The bench subcommand instead runs a benchmark workload against a running cluster, or for YCSB
+ * workloads optionally against a local storage engine, reporting throughput and latencies.
+ *
+ * The check-index subcommand checks the secondary indexes of the node's local SQL storage for
+ * inconsistent entries, like the CHECK INDEX statement. The node must not be running.
  */
 
 #![warn(clippy::all)]
@@ -9,7 +15,14 @@
 use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version};
 use serde_derive::Deserialize;
 use std::collections::HashMap;
+use toydb::bench::{tpcc::Tpcc, ycsb, Report};
+use toydb::client::Pool;
 use toydb::error::{Error, Result};
+use toydb::raft;
+use toydb::sql::engine::{Engine as _, Mode, Transaction as _};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::parser::format_ident;
+use toydb::sql::schema::Catalog as _;
 use toydb::storage;
 use toydb::Server;
 
@@ -24,8 +37,103 @@ async fn main() -> Result<()> {
                 .takes_value(true)
                 .default_value("/etc/toydb.yaml"),
         )
+        .arg(
+            clap::Arg::with_name("memory")
+                .long("memory")
+                .help("Use in-memory storage only, never writing files (config file is optional)"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("bench")
+                .about("Runs a benchmark workload and reports throughput and latencies")
+                .arg(
+                    clap::Arg::with_name("workload")
+                        .help("Workload to run")
+                        .required(true)
+                        .possible_values(&[
+                            "ycsb-a", "ycsb-b", "ycsb-c", "ycsb-d", "ycsb-e", "ycsb-f", "tpcc",
+                        ]),
+                )
+                .arg(
+                    clap::Arg::with_name("host")
+                        .short("h")
+                        .long("host")
+                        .help("Host to connect to, optionally with port number")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .multiple(true)
+                        .default_value("127.0.0.1:9605"),
+                )
+                .arg(
+                    clap::Arg::with_name("concurrency")
+                        .short("c")
+                        .long("concurrency")
+                        .help("Concurrent clients (or threads, with --engine)")
+                        .takes_value(true)
+                        .default_value("8"),
+                )
+                .arg(
+                    clap::Arg::with_name("operations")
+                        .short("n")
+                        .long("operations")
+                        .help("Number of operations or transactions to run [default: 10000, or 1000 for tpcc]")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("records")
+                        .short("r")
+                        .long("records")
+                        .help("Number of records to load for YCSB workloads")
+                        .takes_value(true)
+                        .default_value("1000"),
+                )
+                .arg(
+                    clap::Arg::with_name("value-size")
+                        .long("value-size")
+                        .help("Record value size in bytes for YCSB workloads")
+                        .takes_value(true)
+                        .default_value("100"),
+                )
+                .arg(
+                    clap::Arg::with_name("warehouses")
+                        .short("w")
+                        .long("warehouses")
+                        .help("Number of warehouses for the tpcc workload")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    clap::Arg::with_name("engine")
+                        .short("e")
+                        .long("engine")
+                        .help("Run YCSB workloads directly against a local key/value storage engine")
+                        .takes_value(true)
+                        .conflicts_with("host"),
+                )
+                .arg(
+                    clap::Arg::with_name("sync")
+                        .long("sync")
+                        .help("Storage engine sync mode, with --engine")
+                        .takes_value(true)
+                        .default_value("always"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("check-index")
+                .about("Checks the secondary indexes of the local SQL storage, while stopped")
+                .arg(
+                    clap::Arg::with_name("table")
+                        .help("Table to check [default: all tables]")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
-    let cfg = Config::new(opts.value_of("config").unwrap())?;
+    if let ("bench", Some(opts)) = opts.subcommand() {
+        return bench(opts).await;
+    }
+    let mut cfg = Config::new(opts.value_of("config").unwrap(), opts.is_present("memory"))?;
+    if let ("check-index", Some(opts)) = opts.subcommand() {
+        return check_index(cfg, opts.value_of("table"));
+    }
 
     let loglevel = cfg.log_level.parse::<simplelog::LevelFilter>()?;
     let mut logconfig = simplelog::ConfigBuilder::new();
@@ -33,54 +141,305 @@ async fn main() -> Result<()> {
         logconfig.add_filter_allow_str("toydb");
     }
     simplelog::SimpleLogger::init(loglevel, logconfig.build())?;
+    // Query trace spans are logged to stdout when they close, with their durations.
+    #[cfg(feature = "trace")]
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::fmt()
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .finish(),
+    )
+    .map_err(|err| toydb::error::Error::Internal(err.to_string()))?;
+
+    if cfg.memory {
+        log::info!("Running with in-memory storage, all data will be lost on exit");
+        cfg.storage_raft = "memory".into();
+        cfg.storage_raft_options.clear();
+        cfg.storage_sql = "memory".into();
+        cfg.storage_sql_options.clear();
+    }
 
-    let path = std::path::Path::new(&cfg.data_dir);
-    let raft_store: Box<dyn storage::log::Store> = match cfg.storage_raft.as_str() {
-        "hybrid" | "" => Box::new(storage::log::Hybrid::new(&path, cfg.sync)?),
-        "memory" => Box::new(storage::log::Memory::new()),
-        name => return Err(Error::Config(format!("Unknown Raft storage engine {}", name))),
+    let registry = storage::Registry::default();
+    let options = storage::Options::new(
+        std::path::PathBuf::from(&cfg.data_dir),
+        storage::SyncMode::parse(&cfg.sync, std::time::Duration::from_millis(cfg.sync_interval))?,
+        storage::Compression::parse(&cfg.compression)?,
+    );
+    let raft_store = registry
+        .open_log(&cfg.storage_raft, &options.clone().with_options(cfg.storage_raft_options))?;
+    let server = if cfg.witness {
+        Server::new_witness(&cfg.id, cfg.peers, raft_store).await?
+    } else {
+        let sql_store =
+            registry.open_kv(&cfg.storage_sql, &options.with_options(cfg.storage_sql_options))?;
+        Server::new(&cfg.id, cfg.peers, raft_store, sql_store).await?
     };
-    let sql_store: Box<dyn storage::kv::Store> = match cfg.storage_sql.as_str() {
-        "memory" | "" => Box::new(storage::kv::Memory::new()),
-        "stdmemory" => Box::new(storage::kv::StdMemory::new()),
-        name => return Err(Error::Config(format!("Unknown SQL storage engine {}", name))),
+
+    let ms = std::time::Duration::from_millis;
+    let mut server = server
+        .with_raft_options(raft::Options {
+            heartbeat_interval: raft::ticks(ms(cfg.raft_heartbeat_interval)),
+            election_timeout_min: raft::ticks(ms(cfg.raft_election_timeout_min)),
+            election_timeout_max: raft::ticks(ms(cfg.raft_election_timeout_max)),
+            max_inflight: cfg.raft_max_inflight,
+            adaptive_timeouts: cfg.raft_adaptive_timeouts,
+            witness: cfg.witness,
+        })?
+        .with_raft_compression(
+            storage::Compression::parse(&cfg.raft_compression)?,
+            cfg.raft_compression_threshold,
+        )
+        .with_audit_log(cfg.audit_log)
+        .with_max_apply_lag(cfg.health_max_apply_lag)
+        .with_limits(toydb::server::Limits {
+            max_connections: cfg.max_connections,
+            max_user_queries: cfg.max_user_queries,
+            max_user_txns: cfg.max_user_txns,
+        })
+        .with_admission(toydb::server::Admission {
+            max_raft_backlog: cfg.admission_max_raft_backlog,
+            max_memory: cfg.admission_max_memory,
+            max_cpu: cfg.admission_max_cpu,
+        })
+        .with_timeouts(toydb::server::Timeouts {
+            idle_in_transaction: ms(cfg.idle_in_transaction_timeout),
+            idle_session: ms(cfg.idle_session_timeout),
+            detached: ms(cfg.detached_transaction_timeout),
+        })
+        .with_gc_interval(ms(cfg.gc_interval))
+        .with_shutdown_grace_period(ms(cfg.shutdown_grace_period));
+    if cfg.read_lease {
+        server = server.with_lease(std::time::Duration::from_millis(cfg.read_lease_max_skew))?;
+    }
+    server = server.listen(&cfg.listen_sql, &cfg.listen_raft).await?;
+    if !cfg.listen_http.is_empty() {
+        server = server.listen_http(&cfg.listen_http).await?;
+    }
+    if !cfg.listen_grpc.is_empty() {
+        #[cfg(feature = "grpc")]
+        {
+            server = server.listen_grpc(&cfg.listen_grpc).await?;
+        }
+        #[cfg(not(feature = "grpc"))]
+        return Err(Error::Config("listen_grpc requires toyDB built with the grpc feature".into()));
+    }
+    server.serve_until(shutdown_signal()).await
+}
+
+/// Runs the bench subcommand.
+async fn bench(opts: &clap::ArgMatches<'_>) -> Result<()> {
+    let workload = opts.value_of("workload").unwrap();
+    let concurrency = opts.value_of("concurrency").unwrap().parse()?;
+    let operations = opts.value_of("operations").map(str::parse).transpose()?;
+    let print = |report: Report| println!("{}", report);
+
+    if workload == "tpcc" {
+        let tpcc = Tpcc::new(opts.value_of("warehouses").unwrap().parse()?);
+        let pool = Pool::new(opts.values_of("host").unwrap().collect(), concurrency).await?;
+        print(tpcc.load(&pool).await?);
+        print(tpcc.run(&pool, operations.unwrap_or(1000)).await?);
+        tpcc.verify(&pool).await?;
+        println!("Verified consistency invariants");
+        return Ok(());
+    }
+
+    let ycsb = ycsb::Ycsb::new(
+        ycsb::Workload::parse(workload.trim_start_matches("ycsb-"))?,
+        opts.value_of("records").unwrap().parse()?,
+    )
+    .with_value_size(opts.value_of("value-size").unwrap().parse()?);
+    let operations = operations.unwrap_or(10000);
+
+    if let Some(engine) = opts.value_of("engine") {
+        // File-based engines write to a fresh temporary directory, removed afterwards.
+        let dir = std::env::temp_dir().join(format!("toydb-bench-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let result = (|| {
+            let options = storage::Options::new(
+                dir.clone(),
+                storage::SyncMode::parse(
+                    opts.value_of("sync").unwrap(),
+                    std::time::Duration::from_secs(1),
+                )?,
+                storage::Compression::None,
+            );
+            let store = storage::Registry::default().open_kv(engine, &options)?;
+            println!("Running against local {} storage engine\n", store);
+            let mvcc = storage::kv::MVCC::new(store);
+            print(ycsb.load_kv(&mvcc)?);
+            print(ycsb.run_kv(&mvcc, concurrency, operations)?);
+            Ok(())
+        })();
+        std::fs::remove_dir_all(&dir)?;
+        return result;
+    }
+
+    let pool = Pool::new(opts.values_of("host").unwrap().collect(), concurrency).await?;
+    print(ycsb.load_sql(&pool).await?);
+    print(ycsb.run_sql(&pool, operations).await?);
+    Ok(())
+}
+
+/// Runs the check-index subcommand, which checks the secondary indexes of the local SQL storage
+/// and errors if any entries are inconsistent. Each table is checked in a separate snapshot.
+fn check_index(cfg: Config, table: Option<&str>) -> Result<()> {
+    if cfg.memory || cfg.storage_sql == "memory" || cfg.storage_sql == "stdmemory" {
+        return Err(Error::Config(format!(
+            "Can't check in-memory SQL storage {}, it is rebuilt from the Raft log",
+            cfg.storage_sql
+        )));
+    }
+    let options = storage::Options::new(
+        std::path::PathBuf::from(&cfg.data_dir),
+        storage::SyncMode::parse(&cfg.sync, std::time::Duration::from_millis(cfg.sync_interval))?,
+        storage::Compression::parse(&cfg.compression)?,
+    );
+    let store = storage::Registry::default()
+        .open_kv(&cfg.storage_sql, &options.with_options(cfg.storage_sql_options))?;
+    let engine = toydb::sql::engine::KV::new(storage::kv::MVCC::new(store));
+
+    let txn = engine.begin(Mode::ReadOnly)?;
+    let tables = match table {
+        Some(table) => vec![txn.must_read_table(table)?.name],
+        None => txn
+            .scan_tables()?
+            .filter(|t| t.columns.iter().any(|c| c.index))
+            .map(|t| t.name)
+            .collect(),
     };
+    txn.rollback()?;
 
-    Server::new(&cfg.id, cfg.peers, raft_store, sql_store)
-        .await?
-        .listen(&cfg.listen_sql, &cfg.listen_raft)
-        .await?
-        .serve()
-        .await
+    let mut session = engine.session()?;
+    let mut problems = 0;
+    for table in tables {
+        let rows = match session.execute(&format!("CHECK INDEX ON {}", format_ident(&table)))? {
+            ResultSet::Query { rows, .. } => rows,
+            result => return Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        };
+        for row in rows {
+            let row = row?;
+            println!("{}.{}: {} entry {} => {}", table, row[0], row[3], row[1], row[2]);
+            problems += 1;
+        }
+        println!("Checked table {}", table);
+    }
+    match problems {
+        0 => Ok(()),
+        n => Err(Error::Value(format!("Found {} inconsistent index entries", n))),
+    }
+}
+
+/// Waits for a SIGTERM or SIGINT (Ctrl-C) signal, which initiates a graceful shutdown. If
+/// signal handlers can't be installed, it never completes.
+async fn shutdown_signal() {
+    let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        Ok(sigterm) => sigterm,
+        Err(err) => {
+            log::error!("Failed to install SIGTERM handler: {}", err);
+            return futures::future::pending().await;
+        }
+    };
+    tokio::select! {
+        _ = sigterm.recv() => log::info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => log::info!("Received SIGINT"),
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct Config {
     id: String,
     peers: HashMap<String, String>,
+    witness: bool,
     listen_sql: String,
     listen_raft: String,
+    listen_http: String,
+    listen_grpc: String,
     log_level: String,
+    memory: bool,
     data_dir: String,
-    sync: bool,
+    sync: String,
+    sync_interval: u64,
+    compression: String,
     storage_raft: String,
+    storage_raft_options: HashMap<String, String>,
     storage_sql: String,
+    storage_sql_options: HashMap<String, String>,
+    raft_heartbeat_interval: u64,
+    raft_election_timeout_min: u64,
+    raft_election_timeout_max: u64,
+    raft_max_inflight: u64,
+    raft_adaptive_timeouts: bool,
+    raft_compression: String,
+    raft_compression_threshold: u64,
+    read_lease: bool,
+    read_lease_max_skew: u64,
+    audit_log: bool,
+    health_max_apply_lag: u64,
+    max_connections: u64,
+    max_user_queries: u64,
+    max_user_txns: u64,
+    admission_max_raft_backlog: u64,
+    admission_max_memory: u64,
+    admission_max_cpu: u64,
+    idle_in_transaction_timeout: u64,
+    idle_session_timeout: u64,
+    detached_transaction_timeout: u64,
+    gc_interval: u64,
+    shutdown_grace_period: u64,
 }
 
 impl Config {
-    fn new(file: &str) -> Result<Self> {
+    /// Loads the configuration from the given file, which is optional in memory mode.
+    fn new(file: &str, memory: bool) -> Result<Self> {
         let mut c = config::Config::new();
         c.set_default("id", "toydb")?;
+        c.set_default("peers", HashMap::<String, config::Value>::new())?;
+        c.set_default("witness", false)?;
         c.set_default("listen_sql", "0.0.0.0:9605")?;
         c.set_default("listen_raft", "0.0.0.0:9705")?;
+        c.set_default("listen_http", "0.0.0.0:9805")?;
+        c.set_default("listen_grpc", "")?;
         c.set_default("log_level", "info")?;
+        c.set_default("memory", false)?;
         c.set_default("data_dir", "/var/lib/toydb")?;
-        c.set_default("sync", true)?;
+        c.set_default("sync", "always")?;
+        c.set_default("sync_interval", 1000)?;
+        c.set_default("compression", "none")?;
         c.set_default("storage_raft", "hybrid")?;
+        c.set_default("storage_raft_options", HashMap::<String, config::Value>::new())?;
         c.set_default("storage_sql", "memory")?;
+        c.set_default("storage_sql_options", HashMap::<String, config::Value>::new())?;
+        c.set_default("raft_heartbeat_interval", 100)?;
+        c.set_default("raft_election_timeout_min", 800)?;
+        c.set_default("raft_election_timeout_max", 1500)?;
+        c.set_default("raft_max_inflight", 0)?;
+        c.set_default("raft_adaptive_timeouts", true)?;
+        c.set_default("raft_compression", "snappy")?;
+        c.set_default("raft_compression_threshold", 4096)?;
+        c.set_default("read_lease", false)?;
+        c.set_default("read_lease_max_skew", 200)?;
+        c.set_default("audit_log", false)?;
+        c.set_default("health_max_apply_lag", toydb::server::DEFAULT_MAX_APPLY_LAG as i64)?;
+        c.set_default("max_connections", 0)?;
+        c.set_default("max_user_queries", 0)?;
+        c.set_default("max_user_txns", 0)?;
+        c.set_default("admission_max_raft_backlog", 0)?;
+        c.set_default("admission_max_memory", 0)?;
+        c.set_default("admission_max_cpu", 0)?;
+        c.set_default("idle_in_transaction_timeout", 0)?;
+        c.set_default("idle_session_timeout", 0)?;
+        c.set_default("detached_transaction_timeout", 3_600_000)?;
+        c.set_default("gc_interval", 60_000)?;
+        c.set_default(
+            "shutdown_grace_period",
+            toydb::server::DEFAULT_SHUTDOWN_GRACE_PERIOD.as_millis() as i64,
+        )?;
 
-        c.merge(config::File::with_name(file))?;
+        c.merge(config::File::with_name(file).required(!memory))?;
         c.merge(config::Environment::with_prefix("TOYDB"))?;
+        if memory {
+            c.set("memory", true)?;
+        }
         Ok(c.try_into()?)
     }
 }
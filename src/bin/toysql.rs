@@ -1,6 +1,6 @@
 /*
  * toysql is a command-line client for toyDB. It connects to a toyDB cluster node and executes SQL
- * queries against it via a REPL interface.
+ * queries against it via a REPL interface, or non-interactively from commands and script files.
  */
 
 #![warn(clippy::all)]
@@ -13,13 +13,52 @@ use toydb::error::{Error, Result};
 use toydb::sql::engine::Mode;
 use toydb::sql::execution::ResultSet;
 use toydb::sql::parser::{Lexer, Token};
+use toydb::sql::types::{Columns, Rows, Value};
+use toydb::trace::TraceContext;
 use toydb::Client;
 
+use std::io::Write as _;
+use std::time::Instant;
+
+/// The maximum number of history entries to keep.
+const MAX_HISTORY: usize = 1000;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = app_from_crate!()
         .arg(clap::Arg::with_name("command"))
+        .arg(
+            clap::Arg::with_name("commands")
+                .short("c")
+                .long("command")
+                .help("Execute the given statements or command, then exit")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("files")
+                .short("f")
+                .long("file")
+                .help("Execute statements and commands from the given file, or - for stdin, then exit")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("on-error-stop")
+                .long("on-error-stop")
+                .help("Stop executing commands and files at the first error"),
+        )
         .arg(clap::Arg::with_name("headers").short("H").long("headers").help("Show column headers"))
+        .arg(
+            clap::Arg::with_name("format")
+                .long("format")
+                .help("Output format for query results")
+                .takes_value(true)
+                .possible_values(&["unaligned", "table", "csv", "json", "vertical"])
+                .default_value("unaligned"),
+        )
         .arg(
             clap::Arg::with_name("host")
                 .short("h")
@@ -40,18 +79,84 @@ async fn main() -> Result<()> {
         )
         .get_matches();
 
+    // Commands and files are executed in the order they were given.
+    let mut scripts = Vec::new();
+    if let Some(command) = opts.value_of("command") {
+        scripts.push((0, Script::Command(command.to_string())));
+    }
+    if let (Some(indices), Some(commands)) =
+        (opts.indices_of("commands"), opts.values_of("commands"))
+    {
+        scripts.extend(indices.zip(commands.map(|c| Script::Command(c.to_string()))));
+    }
+    if let (Some(indices), Some(files)) = (opts.indices_of("files"), opts.values_of("files")) {
+        scripts.extend(indices.zip(files.map(|f| Script::File(f.to_string()))));
+    }
+    scripts.sort_by_key(|(index, _)| *index);
+
     let mut toysql =
-        ToySQL::new(opts.value_of("host").unwrap(), opts.value_of("port").unwrap().parse()?)
-            .await?;
+        match ToySQL::new(opts.value_of("host").unwrap(), opts.value_of("port").unwrap().parse()?)
+            .await
+        {
+            Ok(toysql) => toysql,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(EXIT_CONNECTION);
+            }
+        };
     if opts.is_present("headers") {
         toysql.show_headers = true
     }
+    toysql.format = opts.value_of("format").unwrap().parse()?;
 
-    if let Some(command) = opts.value_of("command") {
-        toysql.execute(&command).await
-    } else {
-        toysql.run().await
+    if scripts.is_empty() {
+        return toysql.run().await;
+    }
+    let on_error_stop = opts.is_present("on-error-stop");
+    let mut failed = false;
+    for (_, script) in scripts {
+        let (source, input) = match script {
+            Script::Command(command) => (None, command),
+            Script::File(path) => {
+                let input = match path.as_str() {
+                    "-" => {
+                        let mut input = String::new();
+                        std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)
+                            .map(|_| input)
+                    }
+                    path => std::fs::read_to_string(path),
+                };
+                match input {
+                    Ok(input) => (Some(path), input),
+                    Err(err) => {
+                        eprintln!("Error: failed to read {}: {}", path, err);
+                        std::process::exit(EXIT_FAILURE);
+                    }
+                }
+            }
+        };
+        if !toysql.execute_script(source.as_deref(), &input, on_error_stop).await {
+            failed = true;
+            if on_error_stop {
+                break;
+            }
+        }
     }
+    std::process::exit(if failed { EXIT_FAILURE } else { 0 })
+}
+
+/// The exit code when a statement or command fails.
+const EXIT_FAILURE: i32 = 1;
+
+/// The exit code when toysql can't connect to the server.
+const EXIT_CONNECTION: i32 = 2;
+
+/// A non-interactive script to execute
+enum Script {
+    /// Statements or a command given on the command line
+    Command(String),
+    /// A script file path, or - for stdin
+    File(String),
 }
 
 /// The ToySQL REPL
@@ -60,6 +165,16 @@ struct ToySQL {
     editor: Editor<InputValidator>,
     history_path: Option<std::path::PathBuf>,
     show_headers: bool,
+    /// Whether to send each query with a new trace context, printing its traceparent.
+    trace: bool,
+    /// The output format for query results.
+    format: Format,
+    /// The file to write results to, if any, otherwise stdout.
+    output: Option<std::fs::File>,
+    /// Whether to display the latency of each statement.
+    timing: bool,
+    /// Whether to display results that don't fit in the terminal via a pager.
+    pager: bool,
 }
 
 impl ToySQL {
@@ -67,10 +182,20 @@ impl ToySQL {
     async fn new(host: &str, port: u16) -> Result<Self> {
         Ok(Self {
             client: Client::new((host, port)).await?,
-            editor: Editor::new(),
+            editor: Editor::with_config(
+                rustyline::Config::builder()
+                    .max_history_size(MAX_HISTORY)
+                    .history_ignore_dups(true)
+                    .build(),
+            ),
             history_path: std::env::var_os("HOME")
                 .map(|home| std::path::Path::new(&home).join(".toysql.history")),
             show_headers: false,
+            trace: false,
+            format: Format::Unaligned,
+            output: None,
+            timing: false,
+            pager: true,
         })
     }
 
@@ -85,17 +210,54 @@ impl ToySQL {
         }
     }
 
+    /// Executes a script of ;-terminated statements and ! commands, printing any errors prefixed by
+    /// the source file and line. Returns false if any failed, stopping at the first error if
+    /// on_error_stop is set or the connection failed.
+    async fn execute_script(
+        &mut self,
+        source: Option<&str>,
+        script: &str,
+        on_error_stop: bool,
+    ) -> bool {
+        let mut ok = true;
+        for (line, input) in split_script(script) {
+            match self.execute(&input).await {
+                Ok(()) => {}
+                Err(error) => {
+                    // Syntax error lines are relative to the statement, so make them relative to
+                    // the script instead.
+                    let (line, error) = match error {
+                        Error::Syntax { message, line: l, column, snippet } => (
+                            line + l - 1,
+                            Error::Syntax { message, line: line + l - 1, column, snippet },
+                        ),
+                        error => (line, error),
+                    };
+                    match source {
+                        Some(source) => eprintln!("{}:{}: Error: {}", source, line, error),
+                        None => eprintln!("Error: {}", error),
+                    }
+                    ok = false;
+                    if on_error_stop || matches!(error, Error::Internal(_)) {
+                        break;
+                    }
+                }
+            }
+        }
+        ok
+    }
+
     /// Handles a REPL command (prefixed by !, e.g. !help)
     async fn execute_command(&mut self, input: &str) -> Result<()> {
         let mut input = input.split_ascii_whitespace();
         let command = input.next().ok_or_else(|| Error::Parse("Expected command.".to_string()))?;
 
+        let args: Vec<&str> = input.collect();
         let getargs = |n| {
-            let args: Vec<&str> = input.collect();
             if args.len() != n {
                 Err(Error::Parse(format!("{}: expected {} args, got {}", command, n, args.len())))
             } else {
-                Ok(args)
+                Ok(args.clone())
             }
         };
 
@@ -111,46 +273,139 @@ impl ToySQL {
                 }
                 v => return Err(Error::Parse(format!("Invalid value {}, expected on or off", v))),
             },
+            "!format" => {
+                self.format = getargs(1)?[0].parse()?;
+                println!("Output format is {}", self.format);
+            }
+            "!output" => match args.as_slice() {
+                [] => {
+                    self.output = None;
+                    println!("Writing results to stdout");
+                }
+                [path] => {
+                    self.output = Some(std::fs::File::create(path)?);
+                    println!("Writing results to {}", path);
+                }
+                _ => {
+                    return Err(Error::Parse(format!(
+                        "{}: expected 0 or 1 args, got {}",
+                        command,
+                        args.len()
+                    )))
+                }
+            },
+            "!pager" => match getargs(1)?[0] {
+                "on" => {
+                    self.pager = true;
+                    println!("Pager enabled");
+                }
+                "off" => {
+                    self.pager = false;
+                    println!("Pager disabled");
+                }
+                v => return Err(Error::Parse(format!("Invalid value {}, expected on or off", v))),
+            },
+            "!timing" => match getargs(1)?[0] {
+                "on" => {
+                    self.timing = true;
+                    println!("Timing enabled");
+                }
+                "off" => {
+                    self.timing = false;
+                    println!("Timing disabled");
+                }
+                v => return Err(Error::Parse(format!("Invalid value {}, expected on or off", v))),
+            },
+            "!decommission" => {
+                getargs(0)?;
+                let id = self.client.decommission().await?;
+                println!("Decommissioned node {}", id)
+            }
+            "!trace" => match getargs(1)?[0] {
+                "on" => {
+                    self.trace = true;
+                    println!("Tracing enabled");
+                }
+                "off" => {
+                    self.trace = false;
+                    println!("Tracing disabled");
+                }
+                v => return Err(Error::Parse(format!("Invalid value {}, expected on or off", v))),
+            },
             "!help" => println!(
                 r#"
 Enter a SQL statement terminated by a semicolon (;) to execute it and display the result.
 The following commands are also available:
 
+    !decommission      Drain the connected node and remove it from the cluster
+    !format <format>   Set the query result format: unaligned, table, csv, json, or vertical
     !headers <on|off>  Enable or disable column headers
     !help              This help message
+    !output [file]     Write results to the given file, or stdout if none
+    !pager <on|off>    Display results that don't fit in the terminal via $PAGER (default less)
     !status            Display server status
     !table [table]     Display table schema, if it exists
     !tables            List tables
+    !timing <on|off>   Display the latency of each statement
+    !trace <on|off>    Trace queries, displaying their traceparent
+
+Multi-line statements can be cancelled with Ctrl-C, and input history is stored in ~/.toysql.history.
 "#
             ),
             "!status" => {
                 let status = self.client.status().await?;
-                let mut node_logs = status
+                let nodes = status
                     .raft
-                    .node_last_index
+                    .nodes
                     .iter()
-                    .map(|(id, index)| format!("{}:{}", id, index))
+                    .map(|(id, node)| {
+                        format!(
+                            "{}: {}, {} replicated, {} applied, seen {}, lag {} entries ({}ms)",
+                            id,
+                            node.role,
+                            node.last_index,
+                            node.apply_index,
+                            match node.last_seen {
+                                Some(ms) => format!("{}ms ago", ms),
+                                None => "never".into(),
+                            },
+                            node.lag_entries,
+                            node.lag_millis,
+                        )
+                    })
                     .collect::<Vec<_>>();
-                node_logs.sort();
                 println!(
                     r#"
-Server:    {server} (leader {leader} in term {term} with {nodes} nodes)
+Server:    {server} ({role}, leader {leader} in term {term} with {node_count} nodes)
+Elections: {elections} started by {server}
 Raft log:  {committed} committed, {applied} applied, {raft_size} MB ({raft_storage} storage)
-Node logs: {logs}
+Nodes:     {nodes}
+Draining:  {draining}
 SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
+SQL data:  {keys} keys, {versions} versions ({dead_versions} dead), {sql_size} MB
 "#,
                     server = status.raft.server,
+                    role = status.raft.role,
+                    elections = status.raft.elections,
                     leader = status.raft.leader,
                     term = status.raft.term,
-                    nodes = status.raft.node_last_index.len(),
+                    node_count = status.raft.nodes.len(),
                     committed = status.raft.commit_index,
                     applied = status.raft.apply_index,
                     raft_storage = status.raft.storage,
                     raft_size = format!("{:.3}", status.raft.storage_size as f64 / 1000.0 / 1000.0),
-                    logs = node_logs.join(" "),
+                    nodes = nodes.join("\n           "),
+                    draining = match status.raft.draining.is_empty() {
+                        true => "none".to_string(),
+                        false => status.raft.draining.iter().cloned().collect::<Vec<_>>().join(" "),
+                    },
                     txns = status.mvcc.txns,
                     txns_active = status.mvcc.txns_active,
-                    sql_storage = status.mvcc.storage
+                    sql_storage = status.mvcc.storage,
+                    keys = status.mvcc.stats.keys,
+                    versions = status.mvcc.stats.versions,
+                    dead_versions = status.mvcc.stats.dead_versions,
+                    sql_size = format!("{:.3}", status.mvcc.stats.bytes as f64 / 1000.0 / 1000.0),
                 )
             }
             "!table" => {
@@ -170,42 +425,143 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
 
     /// Runs a query and displays the results
     async fn execute_query(&mut self, query: &str) -> Result<()> {
-        match self.client.execute(query).await? {
+        let started = Instant::now();
+        let resultset = match self.trace {
+            true => {
+                let trace = TraceContext::new();
+                println!("Trace: {}", trace);
+                self.client.execute_traced(query, trace).await?
+            }
+            false => self.client.execute(query).await?,
+        };
+        let (format, headers) = (self.format, self.show_headers);
+        let height = match (&self.output, self.pager) {
+            (None, true) => self.editor.dimensions().map(|(_, height)| height),
+            _ => None,
+        };
+        // Results are buffered if they may need paging. The latency includes receiving all rows,
+        // but not time spent in the pager.
+        let mut buffer = None;
+        match &mut self.output {
+            Some(file) => Self::write_result(file, resultset, format, headers)?,
+            None if height.is_some() => {
+                let mut output = Vec::new();
+                Self::write_result(&mut output, resultset, format, headers)?;
+                buffer = Some(output);
+            }
+            None => Self::write_result(&mut std::io::stdout(), resultset, format, headers)?,
+        }
+        let elapsed = started.elapsed();
+        if let (Some(output), Some(height)) = (buffer, height) {
+            Self::page(output, height)?;
+        }
+        if self.timing {
+            println!("Time: {:.3}ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        Ok(())
+    }
+
+    /// Writes a result set in the given format
+    fn write_result(
+        out: &mut dyn std::io::Write,
+        resultset: ResultSet,
+        format: Format,
+        headers: bool,
+    ) -> Result<()> {
+        let mut out = std::io::BufWriter::new(out);
+        match resultset {
             ResultSet::Begin { id, mode } => match mode {
-                Mode::ReadWrite => println!("Began transaction {}", id),
-                Mode::ReadOnly => println!("Began read-only transaction {}", id),
-                Mode::Snapshot { version, .. } => println!(
+                Mode::ReadWrite => writeln!(out, "Began transaction {}", id)?,
+                Mode::ReadOnly => writeln!(out, "Began read-only transaction {}", id)?,
+                Mode::Snapshot { version, .. } => writeln!(
+                    out,
                     "Began read-only transaction {} in snapshot at version {}",
                     id, version
-                ),
+                )?,
             },
-            ResultSet::Commit { id } => println!("Committed transaction {}", id),
-            ResultSet::Rollback { id } => println!("Rolled back transaction {}", id),
-            ResultSet::Create { count } => println!("Created {} rows", count),
-            ResultSet::Delete { count } => println!("Deleted {} rows", count),
-            ResultSet::Update { count } => println!("Updated {} rows", count),
-            ResultSet::CreateTable { name } => println!("Created table {}", name),
-            ResultSet::DropTable { name } => println!("Dropped table {}", name),
-            ResultSet::Explain(plan) => println!("{}", plan.to_string()),
-            ResultSet::Query { columns, mut rows } => {
-                if self.show_headers {
-                    println!(
-                        "{}",
-                        columns
-                            .iter()
-                            .map(|c| c.name.as_deref().unwrap_or("?"))
-                            .collect::<Vec<_>>()
-                            .join("|")
-                    );
-                }
-                while let Some(row) = rows.next().transpose()? {
-                    println!(
-                        "{}",
-                        row.into_iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join("|")
-                    );
+            ResultSet::Commit { id, chain: None } => writeln!(out, "Committed transaction {}", id)?,
+            ResultSet::Commit { id, chain: Some(chain) } => {
+                writeln!(out, "Committed transaction {}, began transaction {}", id, chain)?
+            }
+            ResultSet::Rollback { id, chain: None } => {
+                writeln!(out, "Rolled back transaction {}", id)?
+            }
+            ResultSet::Rollback { id, chain: Some(chain) } => {
+                writeln!(out, "Rolled back transaction {}, began transaction {}", id, chain)?
+            }
+            ResultSet::Detach { id } => writeln!(out, "Detached transaction {}", id)?,
+            ResultSet::Resume { id, mode } => match mode {
+                Mode::ReadWrite => writeln!(out, "Resumed transaction {}", id)?,
+                Mode::ReadOnly => writeln!(out, "Resumed read-only transaction {}", id)?,
+                Mode::Snapshot { version, .. } => writeln!(
+                    out,
+                    "Resumed read-only transaction {} in snapshot at version {}",
+                    id, version
+                )?,
+            },
+            ResultSet::Declare { name } => writeln!(out, "Declared cursor {}", name)?,
+            ResultSet::Close { name } => writeln!(out, "Closed cursor {}", name)?,
+            ResultSet::Create { count } => writeln!(out, "Created {} rows", count)?,
+            ResultSet::Delete { count } => writeln!(out, "Deleted {} rows", count)?,
+            ResultSet::Update { count } => writeln!(out, "Updated {} rows", count)?,
+            ResultSet::CreateTable { name } => writeln!(out, "Created table {}", name)?,
+            ResultSet::DropTable { name } => writeln!(out, "Dropped table {}", name)?,
+            ResultSet::AddColumn { table, column } => {
+                writeln!(out, "Added column {} to table {}", column, table)?
+            }
+            ResultSet::CreateIndex { table, column } => {
+                writeln!(out, "Created index on {}.{}", table, column)?
+            }
+            ResultSet::AlterColumn { table, column } => {
+                writeln!(out, "Altered column {}.{}", table, column)?
+            }
+            ResultSet::Comment { table, column: Some(column) } => {
+                writeln!(out, "Set comment on column {}.{}", table, column)?
+            }
+            ResultSet::Comment { table, column: None } => {
+                writeln!(out, "Set comment on table {}", table)?
+            }
+            ResultSet::SetSetting { name } => writeln!(out, "Set cluster setting {}", name)?,
+            ResultSet::CreateFunction { name } => writeln!(out, "Created function {}", name)?,
+            ResultSet::DropFunction { name } => writeln!(out, "Dropped function {}", name)?,
+            ResultSet::CreateType { name } => writeln!(out, "Created type {}", name)?,
+            ResultSet::DropType { name } => writeln!(out, "Dropped type {}", name)?,
+            ResultSet::CreatePolicy { table, name } => {
+                writeln!(out, "Created policy {} on table {}", name, table)?
+            }
+            ResultSet::DropPolicy { table, name } => {
+                writeln!(out, "Dropped policy {} on table {}", name, table)?
+            }
+            ResultSet::Explain(explanation) => writeln!(out, "{}", explanation)?,
+            ResultSet::Query { columns, rows } => format.write(&mut out, columns, rows, headers)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Displays output via a pager if it doesn't fit in a terminal of the given height, using the
+    /// PAGER environment variable or less by default. Falls back to stdout if the pager fails.
+    fn page(output: Vec<u8>, height: usize) -> Result<()> {
+        let lines = output.iter().filter(|b| **b == b'\n').count();
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".into());
+        let mut args = pager.split_whitespace();
+        if lines >= height {
+            if let Some(program) = args.next() {
+                let child = std::process::Command::new(program)
+                    .args(args)
+                    .stdin(std::process::Stdio::piped())
+                    .spawn();
+                if let Ok(mut child) = child {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        // The pager may exit before reading all output, so ignore write errors.
+                        stdin.write_all(&output).ok();
+                    }
+                    child.wait()?;
+                    return Ok(());
                 }
             }
         }
+        std::io::stdout().write_all(&output)?;
         Ok(())
     }
 
@@ -219,10 +575,18 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
         };
         match self.editor.readline(&prompt) {
             Ok(input) => {
-                self.editor.add_history_entry(&input);
-                Ok(Some(input.trim().to_string()))
+                let input = input.trim().to_string();
+                // Save the history after every entry, so it persists even if toysql is killed.
+                if !input.is_empty() && self.editor.add_history_entry(&input) {
+                    if let Some(path) = &self.history_path {
+                        self.editor.save_history(path)?;
+                    }
+                }
+                Ok(Some(input))
             }
-            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(None),
+            // Ctrl-C discards the current input, e.g. an incomplete multi-line statement.
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
             Err(err) => Err(err.into()),
         }
     }
@@ -253,12 +617,55 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
                 Err(error) => println!("Error: {}", error.to_string()),
             }
         }
+        Ok(())
+    }
+}
 
-        if let Some(path) = &self.history_path {
-            self.editor.save_history(path)?;
+/// Splits a script into ;-terminated SQL statements and ! commands, along with the line number
+/// they start on. ! commands must be on a separate line, and lines starting with -- are comments.
+fn split_script(script: &str) -> Vec<(usize, String)> {
+    let mut inputs = Vec::new();
+    let mut statement = String::new();
+    let mut start = 0;
+    let mut quote = None;
+    for (i, line) in script.lines().enumerate() {
+        if quote.is_none() && statement.is_empty() {
+            let line = line.trim();
+            if line.starts_with('!') {
+                inputs.push((i + 1, line.to_string()));
+                continue;
+            } else if line.starts_with("--") {
+                continue;
+            }
+        }
+        for c in line.chars() {
+            if statement.is_empty() {
+                if c.is_whitespace() {
+                    continue;
+                }
+                start = i + 1;
+            }
+            statement.push(c);
+            match (quote, c) {
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (Some(q), c) if q == c => quote = None,
+                (None, ';') => {
+                    if statement != ";" {
+                        inputs.push((start, statement.clone()));
+                    }
+                    statement.clear();
+                }
+                _ => {}
+            }
+        }
+        if !statement.is_empty() {
+            statement.push('\n');
         }
-        Ok(())
     }
+    if !statement.trim().is_empty() {
+        inputs.push((start, statement.trim().to_string()));
+    }
+    inputs
 }
 
 /// A Rustyline helper for multiline editing. It parses input lines and determines if they make up a
@@ -292,3 +699,199 @@ impl Validator for InputValidator {
         false
     }
 }
+
+/// An output format for query results
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// Values separated by |, without alignment
+    Unaligned,
+    /// Values aligned in columns
+    Table,
+    /// Comma-separated values, as given by RFC 4180
+    Csv,
+    /// A JSON object per row, keyed by column name
+    Json,
+    /// Each value on a separate line, prefixed by its column name
+    Vertical,
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "unaligned" => Self::Unaligned,
+            "table" => Self::Table,
+            "csv" => Self::Csv,
+            "json" => Self::Json,
+            "vertical" => Self::Vertical,
+            _ => {
+                return Err(Error::Parse(format!(
+                    "Invalid format {}, expected unaligned, table, csv, json, or vertical",
+                    s
+                )))
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Unaligned => "unaligned",
+            Self::Table => "table",
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Vertical => "vertical",
+        })
+    }
+}
+
+impl Format {
+    /// Writes query results in the format. Rows are written as they are received, except for the
+    /// table format which must buffer all rows to align the columns.
+    fn write(
+        self,
+        out: &mut dyn std::io::Write,
+        columns: Columns,
+        mut rows: Rows,
+        headers: bool,
+    ) -> Result<()> {
+        let names: Vec<String> =
+            columns.into_iter().map(|c| c.name.unwrap_or_else(|| "?".into())).collect();
+        match self {
+            Self::Unaligned => {
+                if headers {
+                    writeln!(out, "{}", names.join("|"))?;
+                }
+                while let Some(row) = rows.next().transpose()? {
+                    let row = row.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                    writeln!(out, "{}", row.join("|"))?;
+                }
+            }
+            Self::Table => {
+                let rows = rows.collect::<Result<Vec<_>>>()?;
+                let mut widths = names.iter().map(|n| n.chars().count()).collect::<Vec<_>>();
+                if !headers {
+                    widths.iter_mut().for_each(|w| *w = 0);
+                }
+                for row in &rows {
+                    for (i, value) in row.iter().enumerate() {
+                        widths[i] = widths[i].max(value.to_string().chars().count());
+                    }
+                }
+                if headers {
+                    let header = names
+                        .iter()
+                        .zip(&widths)
+                        .map(|(n, w)| format!("{:<1$}", n, w))
+                        .collect::<Vec<_>>();
+                    writeln!(out, "{}", header.join(" | ").trim_end())?;
+                    let separator = widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>();
+                    writeln!(out, "{}", separator.join("-+-"))?;
+                }
+                for row in rows {
+                    let row = row
+                        .iter()
+                        .zip(&widths)
+                        .map(|(v, w)| match v {
+                            Value::Integer(_) | Value::Float(_) => {
+                                format!("{:>1$}", v.to_string(), w)
+                            }
+                            v => format!("{:<1$}", v.to_string(), w),
+                        })
+                        .collect::<Vec<_>>();
+                    writeln!(out, "{}", row.join(" | ").trim_end())?;
+                }
+            }
+            Self::Csv => {
+                if headers {
+                    let header = names.iter().map(|n| Self::csv_field(n)).collect::<Vec<_>>();
+                    writeln!(out, "{}", header.join(","))?;
+                }
+                while let Some(row) = rows.next().transpose()? {
+                    let row = row
+                        .iter()
+                        .map(|v| match v {
+                            Value::Null => String::new(),
+                            v => Self::csv_field(&v.to_string()),
+                        })
+                        .collect::<Vec<_>>();
+                    writeln!(out, "{}", row.join(","))?;
+                }
+            }
+            Self::Json => {
+                while let Some(row) = rows.next().transpose()? {
+                    let fields = names
+                        .iter()
+                        .zip(row.iter())
+                        .map(|(n, v)| format!("{}:{}", Self::json_string(n), Self::json_value(v)))
+                        .collect::<Vec<_>>();
+                    writeln!(out, "{{{}}}", fields.join(","))?;
+                }
+            }
+            Self::Vertical => {
+                let width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+                let mut record = 0;
+                while let Some(row) = rows.next().transpose()? {
+                    record += 1;
+                    writeln!(out, "-[ RECORD {} ]-", record)?;
+                    for (name, value) in names.iter().zip(row.iter()) {
+                        writeln!(out, "{:<2$} | {}", name, value, width)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats a CSV field, quoting it if necessary.
+    fn csv_field(field: &str) -> String {
+        if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    /// Formats a JSON string.
+    fn json_string(s: &str) -> String {
+        let mut json = String::with_capacity(s.len() + 2);
+        json.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                '\n' => json.push_str("\\n"),
+                '\r' => json.push_str("\\r"),
+                '\t' => json.push_str("\\t"),
+                c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        json.push('"');
+        json
+    }
+
+    /// Formats a JSON value. Non-finite floats are not valid JSON numbers, and are given as strings.
+    /// Points are given as [x, y] arrays, arrays as JSON arrays, and enum values as their labels.
+    fn json_value(value: &Value) -> String {
+        match value {
+            Value::Null => "null".into(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) if f.is_finite() => f.to_string(),
+            Value::Float(f) => Self::json_string(&f.to_string()),
+            Value::String(s) => Self::json_string(s),
+            Value::Point(x, y) => format!(
+                "[{}, {}]",
+                Self::json_value(&Value::Float(*x)),
+                Self::json_value(&Value::Float(*y))
+            ),
+            Value::Array(values) => {
+                format!("[{}]", values.iter().map(Self::json_value).collect::<Vec<_>>().join(", "))
+            }
+            Value::Enum(..) => Self::json_string(&value.to_string()),
+        }
+    }
+}
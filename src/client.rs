@@ -2,7 +2,10 @@ use crate::error::{Error, Result};
 use crate::server::{Request, Response};
 use crate::sql::engine::{Mode, Status};
 use crate::sql::execution::ResultSet;
+use crate::sql::parser::{ast, Parser};
 use crate::sql::schema::Table;
+use crate::sql::types::{DataType, Row, Value};
+use crate::trace::TraceContext;
 
 use futures::future::FutureExt as _;
 use futures::sink::SinkExt as _;
@@ -12,6 +15,7 @@ use std::cell::Cell;
 use std::future::Future;
 use std::ops::{Deref, Drop};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio::sync::{Mutex, MutexGuard};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
@@ -26,57 +30,280 @@ type Connection = tokio_serde::Framed<
 /// Number of serialization retries in with_txn()
 const WITH_TXN_RETRIES: u8 = 8;
 
-/// A toyDB client
+/// Number of retries of idempotent requests on connection loss or Raft leader changes
+const FAILOVER_RETRIES: u8 = 5;
+
+/// The maximum time to wait for a node's status during leader discovery
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A toyDB client. It can be given the addresses of several cluster nodes, in which case it
+/// connects to the current Raft leader such that requests aren't forwarded between nodes. If the
+/// connection is lost or the leader changes outside of a transaction, the client reconnects to
+/// the new leader, retrying the request if it is idempotent (i.e. a read or BEGIN).
 #[derive(Clone)]
 pub struct Client {
-    conn: Arc<Mutex<Connection>>,
+    addrs: Arc<Vec<String>>,
+    conn: Arc<Mutex<Option<Connection>>>,
     txn: Cell<Option<(u64, Mode)>>,
 }
 
 impl Client {
     /// Creates a new client
     pub async fn new<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let conn = Self::open(addr).await?;
+        let addr = conn.get_ref().get_ref().peer_addr()?.to_string();
         Ok(Self {
-            conn: Arc::new(Mutex::new(tokio_serde::Framed::new(
-                Framed::new(TcpStream::connect(addr).await?, LengthDelimitedCodec::new()),
-                tokio_serde::formats::Bincode::default(),
-            ))),
+            addrs: Arc::new(vec![addr]),
+            conn: Arc::new(Mutex::new(Some(conn))),
             txn: Cell::new(None),
         })
     }
 
-    /// Call a server method
-    async fn call(&self, request: Request) -> Result<Response> {
-        let mut conn = self.conn.lock().await;
-        self.call_locked(&mut conn, request).await
+    /// Creates a new client for a cluster with the given node addresses, connecting to the
+    /// current leader, or any reachable node if no leader is found.
+    pub async fn new_cluster<A: Into<String>>(addrs: Vec<A>) -> Result<Self> {
+        let addrs: Vec<String> = addrs.into_iter().map(Into::into).collect();
+        if addrs.is_empty() {
+            return Err(Error::Value("No server addresses given".into()));
+        }
+        let conn = Self::connect(&addrs).await?;
+        Ok(Self {
+            addrs: Arc::new(addrs),
+            conn: Arc::new(Mutex::new(Some(conn))),
+            txn: Cell::new(None),
+        })
+    }
+
+    /// Opens a connection to a server
+    async fn open<A: ToSocketAddrs>(addr: A) -> Result<Connection> {
+        Ok(tokio_serde::Framed::new(
+            Framed::new(TcpStream::connect(addr).await?, LengthDelimitedCodec::new()),
+            tokio_serde::formats::Bincode::default(),
+        ))
+    }
+
+    /// Connects to the cluster leader, discovered by fetching the status of each node in turn
+    /// and checking whether the node is the leader. If no leader is found, it connects to the
+    /// first reachable node, which forwards requests to the leader once there is one.
+    async fn connect(addrs: &[String]) -> Result<Connection> {
+        if addrs.len() == 1 {
+            return Self::open(addrs[0].as_str()).await;
+        }
+        let mut fallback = None;
+        let mut error = None;
+        for addr in addrs {
+            let mut conn = match Self::open(addr.as_str()).await {
+                Ok(conn) => Some(conn),
+                Err(err) => {
+                    error = Some(err);
+                    continue;
+                }
+            };
+            let status = tokio::time::timeout(
+                DISCOVERY_TIMEOUT,
+                Self::roundtrip(&mut conn, Request::Status),
+            )
+            .await;
+            match status {
+                Ok(Ok(Response::Status(status))) if status.raft.server == status.raft.leader => {
+                    return conn.ok_or_else(|| Error::Internal("Server disconnected".into()))
+                }
+                Ok(Err(err)) => error = Some(err),
+                Ok(Ok(_)) => {}
+                // The status response may still arrive, so the connection can't be reused.
+                Err(_) => continue,
+            }
+            if fallback.is_none() {
+                fallback = conn;
+            }
+        }
+        fallback.ok_or_else(|| error.unwrap_or_else(|| Error::Internal("No servers".into())))
     }
 
-    /// Call a server method while holding the mutex lock
-    async fn call_locked(
+    /// Sends a request and receives the first response on the given connection. On connection
+    /// errors the connection is dropped, such that the next request reconnects.
+    async fn roundtrip(conn: &mut Option<Connection>, request: Request) -> Result<Response> {
+        let c = conn.as_mut().ok_or_else(|| Error::Internal("Not connected".into()))?;
+        if let Err(err) = c.send(request).await {
+            *conn = None;
+            return Err(err.into());
+        }
+        Self::receive(conn).await
+    }
+
+    /// Receives a response on the given connection, dropping the connection on errors.
+    async fn receive(conn: &mut Option<Connection>) -> Result<Response> {
+        let c = conn.as_mut().ok_or_else(|| Error::Internal("Not connected".into()))?;
+        match c.try_next().await {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                *conn = None;
+                Err(Error::Internal("Server disconnected".into()))
+            }
+            Err(err) => {
+                *conn = None;
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Handles a failed request attempt, returning true if it should be retried. If the
+    /// connection was lost or the Raft leader changed outside of a transaction, the client
+    /// reconnects to the (new) leader, retrying idempotent requests. If the connection was lost
+    /// in a transaction, the server has rolled it back.
+    async fn failover(
         &self,
-        conn: &mut MutexGuard<'_, Connection>,
-        request: Request,
-    ) -> Result<Response> {
-        conn.send(request).await?;
-        match conn.try_next().await? {
-            Some(result) => result,
-            None => Err(Error::Internal("Server disconnected".into())),
+        conn: &mut Option<Connection>,
+        error: &Error,
+        attempt: u8,
+        idempotent: bool,
+    ) -> bool {
+        let lost = conn.is_none();
+        if lost {
+            self.txn.set(None);
+        } else if *error != Error::Abort || self.txn.get().is_some() {
+            return false;
+        }
+        *conn = None;
+        if !idempotent || attempt >= FAILOVER_RETRIES {
+            return false;
+        }
+        tokio::time::delay_for(Duration::from_millis(
+            2_u64.pow(attempt as u32) * rand::thread_rng().gen_range(25, 75),
+        ))
+        .await;
+        // If no node is reachable yet, the retry attempts to connect again.
+        *conn = Self::connect(&self.addrs).await.ok();
+        true
+    }
+
+    /// Call a server method, retrying on failover if the request is idempotent
+    async fn call(&self, request: Request, idempotent: bool) -> Result<Response> {
+        let mut conn = self.conn.lock().await;
+        let mut attempt = 0;
+        loop {
+            let in_txn = self.txn.get().is_some();
+            let result = match self.connected(&mut conn).await {
+                Ok(()) => Self::roundtrip(&mut conn, request.clone()).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Err(err)
+                    if self.failover(&mut conn, &err, attempt, idempotent && !in_txn).await =>
+                {
+                    attempt += 1
+                }
+                result => return result,
+            }
         }
     }
 
+    /// Reconnects the client if it has lost its connection.
+    async fn connected(&self, conn: &mut Option<Connection>) -> Result<()> {
+        if conn.is_none() {
+            *conn = Some(Self::connect(&self.addrs).await?);
+        }
+        Ok(())
+    }
+
     /// Executes a query
     pub async fn execute(&self, query: &str) -> Result<ResultSet> {
+        self.execute_request(Request::Execute(query.into()), query).await
+    }
+
+    /// Executes a query with values for its ? bind parameters, by position. Values are bound as
+    /// constants, so unlike string formatting they can't alter the statement, and can be used
+    /// wherever constant expressions are allowed.
+    pub async fn execute_params(&self, query: &str, params: &[Value]) -> Result<ResultSet> {
+        self.execute_request(Request::ExecuteParams(query.into(), params.to_vec()), query).await
+    }
+
+    /// Executes a query as part of the given trace, which the server propagates through the
+    /// query path if tracing is enabled.
+    pub async fn execute_traced(&self, query: &str, trace: TraceContext) -> Result<ResultSet> {
+        self.execute_request(Request::ExecuteTraced(query.into(), trace), query).await
+    }
+
+    /// Executes a query request, buffering any result rows. Idempotent queries are retried on
+    /// failover outside of transactions.
+    async fn execute_request(&self, request: Request, query: &str) -> Result<ResultSet> {
         let mut conn = self.conn.lock().await;
-        let mut resultset =
-            match self.call_locked(&mut conn, Request::Execute(query.into())).await? {
-                Response::Execute(rs) => rs,
-                resp => return Err(Error::Internal(format!("Unexpected response {:?}", resp))),
+        let idempotent = Self::is_idempotent(query);
+        let mut attempt = 0;
+        let resultset = loop {
+            let in_txn = self.txn.get().is_some();
+            let result = match self.connected(&mut conn).await {
+                Ok(()) => Self::execute_on(&mut conn, request.clone()).await,
+                Err(err) => Err(err),
             };
+            match result {
+                Err(err)
+                    if self.failover(&mut conn, &err, attempt, idempotent && !in_txn).await =>
+                {
+                    attempt += 1
+                }
+                result => break result?,
+            }
+        };
+        self.track_txn(&resultset);
+        Ok(resultset)
+    }
+
+    /// Executes a batch of queries in a single round trip, returning the result of each executed
+    /// statement in order, with any result rows buffered. A query can contain several
+    /// ;-separated statements, e.g. a script, each of which returns a result. Execution stops at
+    /// the first error, which is returned as the last result, and the remaining statements are
+    /// not executed. Outside of an explicit transaction, each statement runs in its own
+    /// transaction. Batches are not retried on failover.
+    pub async fn execute_batch(&self, queries: &[&str]) -> Result<Vec<Result<ResultSet>>> {
+        let request = Request::ExecuteBatch(queries.iter().map(|q| q.to_string()).collect());
+        let results = match self.call(request, false).await? {
+            Response::ExecuteBatch(results) => results,
+            resp => return Err(Error::Internal(format!("Unexpected response {:?}", resp))),
+        };
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                let resultset = match result? {
+                    (ResultSet::Query { columns, .. }, rows) => {
+                        ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) }
+                    }
+                    (resultset, _) => resultset,
+                };
+                self.track_txn(&resultset);
+                Ok(resultset)
+            })
+            .collect())
+    }
+
+    /// Updates the client's transaction status from a query result.
+    fn track_txn(&self, resultset: &ResultSet) {
+        match resultset {
+            ResultSet::Begin { id, mode } | ResultSet::Resume { id, mode } => {
+                self.txn.set(Some((*id, *mode)))
+            }
+            ResultSet::Detach { .. } => self.txn.set(None),
+            ResultSet::Commit { chain: Some(id), .. }
+            | ResultSet::Rollback { chain: Some(id), .. } => {
+                self.txn.set(self.txn.get().map(|(_, mode)| (*id, mode)))
+            }
+            ResultSet::Commit { chain: None, .. } => self.txn.set(None),
+            ResultSet::Rollback { chain: None, .. } => self.txn.set(None),
+            _ => {}
+        }
+    }
+
+    /// Executes a query request on the given connection, buffering any result rows.
+    async fn execute_on(conn: &mut Option<Connection>, request: Request) -> Result<ResultSet> {
+        let mut resultset = match Self::roundtrip(conn, request).await? {
+            Response::Execute(rs) => rs,
+            resp => return Err(Error::Internal(format!("Unexpected response {:?}", resp))),
+        };
         if let ResultSet::Query { columns, .. } = resultset {
             // FIXME We buffer rows for now to avoid lifetime hassles
             let mut rows = Vec::new();
-            while let Some(result) = conn.try_next().await? {
-                match result? {
+            loop {
+                match Self::receive(conn).await? {
                     Response::Row(Some(row)) => rows.push(row),
                     Response::Row(None) => break,
                     response => {
@@ -86,18 +313,48 @@ impl Client {
             }
             resultset = ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) }
         };
-        match &resultset {
-            ResultSet::Begin { id, mode } => self.txn.set(Some((*id, *mode))),
-            ResultSet::Commit { .. } => self.txn.set(None),
-            ResultSet::Rollback { .. } => self.txn.set(None),
-            _ => {}
-        }
         Ok(resultset)
     }
 
+    /// Checks whether a query can safely be retried, i.e. it only reads data or begins a
+    /// transaction. Unparseable queries are not retried, and fail on the server instead.
+    fn is_idempotent(query: &str) -> bool {
+        matches!(
+            Parser::new(query).parse(),
+            Ok(ast::Statement::Begin { .. })
+                | Ok(ast::Statement::Select { .. })
+                | Ok(ast::Statement::Explain { .. })
+                | Ok(ast::Statement::History { .. })
+                | Ok(ast::Statement::Changefeed { .. })
+        )
+    }
+
+    /// Describes a query without executing it, returning the datatypes of its ? bind parameters
+    /// by position, e.g. for use with execute_params(). Datatypes are either given explicitly as
+    /// ?::type or inferred from their context, and are None if unknown.
+    pub async fn describe(&self, query: &str) -> Result<Vec<Option<DataType>>> {
+        match self.call(Request::Describe(query.into()), true).await? {
+            Response::Describe(types) => Ok(types),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Bulk loads rows into a table, returning the number of rows loaded. This is much faster than
+    /// INSERT statements for large data loads, since rows are replicated in large chunks rather
+    /// than one at a time. Rows must have a value for each column, in order. Outside of an
+    /// explicit transaction, the rows are loaded in a single transaction, so either all or none
+    /// of them are loaded. Requests are limited to 8 MB, so larger loads must be split across
+    /// several calls, e.g. in an explicit transaction. Requests are not retried on failover.
+    pub async fn ingest(&self, table: &str, rows: Vec<Row>) -> Result<u64> {
+        match self.call(Request::Ingest(table.into(), rows), false).await? {
+            Response::Ingest(count) => Ok(count),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
     /// Fetches the table schema as SQL
     pub async fn get_table(&self, table: &str) -> Result<Table> {
-        match self.call(Request::GetTable(table.into())).await? {
+        match self.call(Request::GetTable(table.into()), true).await? {
             Response::GetTable(t) => Ok(t),
             resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
         }
@@ -105,7 +362,7 @@ impl Client {
 
     /// Lists database tables
     pub async fn list_tables(&self) -> Result<Vec<String>> {
-        match self.call(Request::ListTables).await? {
+        match self.call(Request::ListTables, true).await? {
             Response::ListTables(t) => Ok(t),
             resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
         }
@@ -113,19 +370,28 @@ impl Client {
 
     /// Checks server status
     pub async fn status(&self) -> Result<Status> {
-        match self.call(Request::Status).await? {
+        match self.call(Request::Status, true).await? {
             Response::Status(s) => Ok(s),
             resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
         }
     }
 
+    /// Decommissions the connected server's node, removing it from the cluster once open
+    /// transactions have finished. Returns the node ID.
+    pub async fn decommission(&self) -> Result<String> {
+        match self.call(Request::Decommission, false).await? {
+            Response::Decommission(id) => Ok(id),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
     /// Returns the transaction status of the client
     pub fn txn(&self) -> Option<(u64, Mode)> {
         self.txn.get()
     }
 
-    /// Runs a query in a transaction, automatically retrying serialization failures with
-    /// exponential backoff.
+    /// Runs a query in a transaction, automatically retrying serialization failures and
+    /// overloaded servers with exponential backoff.
     pub async fn with_txn<W, F, R>(&self, mut with: W) -> Result<R>
     where
         W: FnMut(Client) -> F,
@@ -147,7 +413,10 @@ impl Client {
             .await;
             if result.is_err() {
                 self.execute("ROLLBACK").await.ok();
-                if matches!(result, Err(Error::Serialization) | Err(Error::Abort)) {
+                if matches!(
+                    result,
+                    Err(Error::Serialization) | Err(Error::Abort) | Err(Error::Overloaded(_))
+                ) {
                     continue;
                 }
             }
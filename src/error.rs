@@ -4,29 +4,81 @@ use std::fmt::{self, Display};
 /// Result returning Error
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// toyDB errors. All except Internal are considered user-facing.
+/// toyDB errors. All except Internal are considered user-facing. Errors are sent to clients as
+/// is, and each kind has an error code (see code()) which applications can branch on.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     Abort,
     Config(String),
+    /// A row violates a constraint, e.g. a duplicate primary key or unique value, a NULL value in
+    /// a NOT NULL column, or a missing or still referenced foreign key.
+    Constraint(String),
     Internal(String),
+    /// A resource limit was exceeded, e.g. too many client connections.
+    Limit(String),
+    /// The server is overloaded and rejected new work, which should be retried later.
+    Overloaded(String),
     Parse(String),
     ReadOnly,
     Serialization,
+    /// An invalid SQL statement, at the given 1-based line and column of the statement. The
+    /// snippet is the statement line containing the error, which is displayed with a caret.
+    Syntax {
+        message: String,
+        line: usize,
+        column: usize,
+        snippet: String,
+    },
     Value(String),
 }
 
+impl Error {
+    /// Returns the error code, a five-character SQLSTATE code as used by the SQL standard and
+    /// e.g. PostgreSQL, such that clients can handle classes of errors without parsing messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Abort => "57014",
+            Error::Config(_) => "F0000",
+            Error::Constraint(_) => "23000",
+            Error::Internal(_) => "XX000",
+            Error::Limit(_) => "53000",
+            Error::Overloaded(_) => "57P03",
+            Error::Parse(_) => "22P02",
+            Error::ReadOnly => "25006",
+            Error::Serialization => "40001",
+            Error::Syntax { .. } => "42601",
+            Error::Value(_) => "22000",
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Config(s) | Error::Internal(s) | Error::Parse(s) | Error::Value(s) => {
-                write!(f, "{}", s)
-            }
+            Error::Config(s)
+            | Error::Constraint(s)
+            | Error::Internal(s)
+            | Error::Limit(s)
+            | Error::Overloaded(s)
+            | Error::Parse(s)
+            | Error::Value(s) => write!(f, "{}", s),
             Error::Abort => write!(f, "Operation aborted"),
             Error::Serialization => write!(f, "Serialization failure, retry transaction"),
             Error::ReadOnly => write!(f, "Read-only transaction"),
+            Error::Syntax { message, line, column, snippet } => {
+                let indent: String = snippet
+                    .chars()
+                    .take(column.saturating_sub(1))
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                write!(
+                    f,
+                    "{} at line {}, column {}\n{}\n{}^",
+                    message, line, column, snippet, indent
+                )
+            }
         }
     }
 }
@@ -67,6 +119,19 @@ impl From<rustyline::error::ReadlineError> for Error {
     }
 }
 
+#[cfg(feature = "sled")]
+impl From<sled::Error> for Error {
+    fn from(err: sled::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+impl From<snap::Error> for Error {
+    fn from(err: snap::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
 impl From<std::array::TryFromSliceError> for Error {
     fn from(err: std::array::TryFromSliceError) -> Self {
         Error::Internal(err.to_string())
@@ -0,0 +1,232 @@
+//! Fault injection, for chaos testing of error handling and recovery paths. Faults are injected
+//! into named operations, e.g. log.append or raft.send.b, which are matched against a set of
+//! rules that fail or delay them with some probability. Rule patterns may use * as a wildcard,
+//! e.g. kv.* or raft.send.*.
+//!
+//! Fault injection is off by default: only stores wrapped in kv::Faulty or log::Faulty, and Raft
+//! servers given a fault set via with_faults(), check for faults, and an empty set injects none.
+//! Fault sets are cheap to clone, and clones share rules, so faults can be changed at runtime.
+
+use crate::error::{Error, Result};
+
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A fault action.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Fails the operation with an error.
+    Error,
+    /// Delays the operation by the given duration.
+    Delay(Duration),
+}
+
+/// A fault injection rule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// The operation pattern, where * matches any sequence of characters.
+    pattern: String,
+    /// The action to take.
+    action: Action,
+    /// The probability of injecting the fault into a matching operation.
+    probability: f64,
+    /// The remaining number of times to inject the fault, if limited.
+    remaining: Option<u64>,
+}
+
+impl Rule {
+    /// Creates a new rule which always injects the given action into matching operations.
+    pub fn new(pattern: &str, action: Action) -> Self {
+        Self { pattern: pattern.to_string(), action, probability: 1.0, remaining: None }
+    }
+
+    /// Creates a new rule which fails matching operations.
+    pub fn error(pattern: &str) -> Self {
+        Self::new(pattern, Action::Error)
+    }
+
+    /// Creates a new rule which delays matching operations.
+    pub fn delay(pattern: &str, delay: Duration) -> Self {
+        Self::new(pattern, Action::Delay(delay))
+    }
+
+    /// Sets the probability (0.0 to 1.0) of injecting the fault into a matching operation.
+    pub fn with_probability(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self
+    }
+
+    /// Limits the number of times the fault is injected, after which the rule is removed.
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.remaining = Some(limit);
+        self
+    }
+
+    /// Checks whether the rule pattern matches an operation.
+    fn matches(&self, operation: &str) -> bool {
+        glob(self.pattern.as_bytes(), operation.as_bytes())
+    }
+}
+
+/// A set of fault injection rules.
+#[derive(Clone)]
+pub struct Faults {
+    rules: Arc<Mutex<Vec<Rule>>>,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Faults {
+    /// Creates a new, empty fault set, which injects no faults.
+    pub fn new() -> Self {
+        Self {
+            rules: Arc::new(Mutex::new(Vec::new())),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+        }
+    }
+
+    /// Seeds the random number generator, such that probabilistic faults are reproducible given
+    /// the same sequence of operations.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))), ..self }
+    }
+
+    /// Adds a rule. Rules are checked in the order they were added, and the first rule which
+    /// injects a fault into an operation wins.
+    pub fn add(&self, rule: Rule) -> Result<()> {
+        if !(0.0..=1.0).contains(&rule.probability) {
+            return Err(Error::Value(format!("Invalid fault probability {}", rule.probability)));
+        }
+        self.rules.lock()?.push(rule);
+        Ok(())
+    }
+
+    /// Removes all rules.
+    pub fn clear(&self) -> Result<()> {
+        self.rules.lock()?.clear();
+        Ok(())
+    }
+
+    /// Returns the fault to inject into an operation, if any.
+    pub fn check(&self, operation: &str) -> Result<Option<Action>> {
+        let mut rules = self.rules.lock()?;
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        let mut rng = self.rng.lock()?;
+        for i in 0..rules.len() {
+            let rule = &mut rules[i];
+            if !rule.matches(operation) || !rng.gen_bool(rule.probability) {
+                continue;
+            }
+            let action = rule.action;
+            match &mut rule.remaining {
+                Some(1) => {
+                    rules.remove(i);
+                }
+                Some(remaining) => *remaining -= 1,
+                None => {}
+            }
+            return Ok(Some(action));
+        }
+        Ok(None)
+    }
+
+    /// Injects any fault into a synchronous operation, by returning an error or sleeping.
+    pub fn inject(&self, operation: &str) -> Result<()> {
+        match self.check(operation)? {
+            Some(Action::Error) => Err(Self::error(operation)),
+            Some(Action::Delay(delay)) => {
+                std::thread::sleep(delay);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the error used for an injected fault.
+    pub fn error(operation: &str) -> Error {
+        Error::Internal(format!("Injected fault in {}", operation))
+    }
+}
+
+/// Matches a string against a glob pattern, where * matches any sequence of characters.
+fn glob(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob(rest, &s[i..])),
+        Some((c, rest)) => s.first() == Some(c) && glob(rest, &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(super::glob(b"kv.set", b"kv.set"));
+        assert!(!super::glob(b"kv.set", b"kv.setx"));
+        assert!(super::glob(b"kv.*", b"kv.set"));
+        assert!(super::glob(b"kv.*", b"kv."));
+        assert!(!super::glob(b"kv.*", b"log.set"));
+        assert!(super::glob(b"*.get*", b"log.get_metadata"));
+        assert!(super::glob(b"*", b""));
+    }
+
+    #[test]
+    fn check() -> Result<()> {
+        let faults = Faults::new();
+        assert_eq!(faults.check("kv.set")?, None);
+
+        let delay = Duration::from_millis(10);
+        faults.add(Rule::error("kv.set").with_limit(2))?;
+        faults.add(Rule::delay("kv.*", delay))?;
+        assert_eq!(faults.check("kv.set")?, Some(Action::Error));
+        assert_eq!(faults.check("kv.get")?, Some(Action::Delay(delay)));
+        assert_eq!(faults.check("kv.set")?, Some(Action::Error));
+        assert_eq!(faults.check("kv.set")?, Some(Action::Delay(delay)));
+        assert_eq!(faults.check("log.append")?, None);
+
+        // Clones share rules.
+        faults.clone().clear()?;
+        assert_eq!(faults.check("kv.set")?, None);
+
+        assert!(faults.add(Rule::error("kv.set").with_probability(1.5)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn probability() -> Result<()> {
+        let faults = Faults::new().with_seed(1);
+        faults.add(Rule::error("kv.set").with_probability(0.0))?;
+        faults.add(Rule::error("kv.get").with_probability(0.5))?;
+        assert!((0..100).all(|_| faults.inject("kv.set").is_ok()));
+        let errors = (0..1000).filter(|_| faults.inject("kv.get").is_err()).count();
+        assert!(errors > 400 && errors < 600, "{} errors", errors);
+
+        // The same seed injects the same faults.
+        let injected = |seed| -> Result<Vec<bool>> {
+            let faults = Faults::new().with_seed(seed);
+            faults.add(Rule::error("*").with_probability(0.5))?;
+            Ok((0..100).map(|_| faults.inject("op").is_err()).collect())
+        };
+        assert_eq!(injected(7)?, injected(7)?);
+        assert_ne!(injected(7)?, injected(8)?);
+        Ok(())
+    }
+
+    #[test]
+    fn inject() -> Result<()> {
+        let faults = Faults::new();
+        faults.add(Rule::error("log.append"))?;
+        faults.add(Rule::delay("log.get", Duration::from_millis(20)))?;
+        assert_eq!(faults.inject("log.append"), Err(Faults::error("log.append")));
+
+        let start = std::time::Instant::now();
+        faults.inject("log.get")?;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        Ok(())
+    }
+}
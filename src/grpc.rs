@@ -0,0 +1,373 @@
+//! A gRPC service for the client protocol, as an alternative to the native Bincode protocol for
+//! non-Rust clients. The service and messages are defined in proto/toydb.proto, and generated
+//! by build.rs when the grpc feature is enabled.
+//!
+//! Each call runs in a new client session, subject to the same limits, draining, and audit
+//! logging as native client sessions. The session is closed when the call completes, rolling
+//! back any open transaction.
+
+use crate::error::{Error, Result};
+use crate::server::{Request, Response, Session, SessionFactory};
+use crate::sql::engine::Mode;
+use crate::sql::execution::ResultSet;
+use crate::sql::types::{Row, Value};
+
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+mod proto {
+    tonic::include_proto!("toydb");
+}
+
+use proto::toy_db_server::{ToyDb, ToyDbServer};
+
+/// The number of streamed messages to buffer before waiting for the client to receive them.
+const STREAM_BUFFER: usize = 16;
+
+/// Serves gRPC clients until the returned future is dropped.
+pub async fn serve(mut listener: TcpListener, service: Service) -> Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(ToyDbServer::new(service))
+        .serve_with_incoming(listener.incoming())
+        .await
+        .map_err(|err| Error::Internal(err.to_string()))
+}
+
+/// The gRPC service, which creates a client session for each call.
+pub struct Service {
+    sessions: SessionFactory,
+}
+
+impl Service {
+    /// Creates a new gRPC service, using the given session factory.
+    pub(crate) fn new(sessions: SessionFactory) -> Self {
+        Self { sessions }
+    }
+
+    /// Creates a client session for a call.
+    fn session<T>(&self, request: &tonic::Request<T>) -> Result<Session> {
+        let peer = request
+            .remote_addr()
+            .ok_or_else(|| Error::Internal("Unknown gRPC client address".into()))?;
+        self.sessions.session(peer)
+    }
+}
+
+#[tonic::async_trait]
+impl ToyDb for Service {
+    async fn execute(
+        &self,
+        request: tonic::Request<proto::ExecuteRequest>,
+    ) -> std::result::Result<tonic::Response<proto::ExecuteResponse>, tonic::Status> {
+        let mut session = self.session(&request)?;
+        let query = request.into_inner().query;
+        let results = match session.call(Request::ExecuteBatch(vec![query])).await.0? {
+            Response::ExecuteBatch(results) => results,
+            response => return Err(unexpected(response).into()),
+        };
+        let results = results
+            .into_iter()
+            .map(|result| result.map(|(resultset, rows)| encode_result(resultset, rows)))
+            .collect::<Result<_>>()?;
+        Ok(tonic::Response::new(proto::ExecuteResponse { results }))
+    }
+
+    type StreamStream = mpsc::Receiver<std::result::Result<proto::StreamResponse, tonic::Status>>;
+
+    async fn stream(
+        &self,
+        request: tonic::Request<proto::StreamRequest>,
+    ) -> std::result::Result<tonic::Response<Self::StreamStream>, tonic::Status> {
+        let mut session = self.session(&request)?;
+        let query = request.into_inner().query;
+        let (response, query_slot) = session.call(Request::Execute(query)).await;
+        let (resultset, rows) = match response? {
+            Response::Execute(ResultSet::Query { columns, rows }) => {
+                (ResultSet::Query { columns, rows: Box::new(std::iter::empty()) }, Some(rows))
+            }
+            Response::Execute(resultset) => (resultset, None),
+            response => return Err(unexpected(response).into()),
+        };
+
+        // The session and query slot are held until all rows have been sent, since the rows are
+        // read lazily from the session's transaction.
+        let (mut tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let _slots = (session, query_slot);
+            let result = proto::stream_response::Response::Result(encode_result(resultset, vec![]));
+            if tx.send(Ok(proto::StreamResponse { response: Some(result) })).await.is_err() {
+                return;
+            }
+            let mut rows = rows.unwrap_or_else(|| Box::new(std::iter::empty()));
+            while let Some(row) = tokio::task::block_in_place(|| rows.next()) {
+                let response = row
+                    .map(|row| proto::StreamResponse {
+                        response: Some(proto::stream_response::Response::Row(encode_row(row))),
+                    })
+                    .map_err(tonic::Status::from);
+                let failed = response.is_err();
+                if tx.send(response).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(tonic::Response::new(rx))
+    }
+
+    async fn status(
+        &self,
+        request: tonic::Request<proto::StatusRequest>,
+    ) -> std::result::Result<tonic::Response<proto::StatusResponse>, tonic::Status> {
+        let mut session = self.session(&request)?;
+        let status = match session.call(Request::Status).await.0? {
+            Response::Status(status) => status,
+            response => return Err(unexpected(response).into()),
+        };
+        Ok(tonic::Response::new(proto::StatusResponse {
+            server: status.raft.server,
+            leader: status.raft.leader,
+            term: status.raft.term,
+            node_last_index: status.raft.node_last_index,
+            commit_index: status.raft.commit_index,
+            apply_index: status.raft.apply_index,
+            raft_storage: status.raft.storage,
+            raft_storage_size: status.raft.storage_size,
+            txns: status.mvcc.txns,
+            txns_active: status.mvcc.txns_active,
+            sql_storage: status.mvcc.storage,
+            sql_keys: status.mvcc.stats.keys,
+            sql_bytes: status.mvcc.stats.bytes,
+        }))
+    }
+}
+
+impl From<Error> for tonic::Status {
+    /// Converts an error into a gRPC status, with the SQLSTATE code in the sqlstate metadata key.
+    fn from(err: Error) -> Self {
+        let code = match err {
+            Error::Abort => tonic::Code::Cancelled,
+            Error::Config(_) | Error::Internal(_) => tonic::Code::Internal,
+            Error::Constraint(_) | Error::ReadOnly => tonic::Code::FailedPrecondition,
+            Error::Limit(_) => tonic::Code::ResourceExhausted,
+            Error::Overloaded(_) => tonic::Code::Unavailable,
+            Error::Parse(_) | Error::Syntax { .. } | Error::Value(_) => {
+                tonic::Code::InvalidArgument
+            }
+            Error::Serialization => tonic::Code::Aborted,
+        };
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        metadata.insert("sqlstate", tonic::metadata::MetadataValue::from_static(err.code()));
+        tonic::Status::with_metadata(code, err.to_string(), metadata)
+    }
+}
+
+/// Returns an error for an unexpected response to a request.
+fn unexpected(response: Response) -> Error {
+    Error::Internal(format!("Unexpected response {:?}", response))
+}
+
+/// Encodes a result set and its rows as a protobuf result.
+fn encode_result(resultset: ResultSet, rows: Vec<Row>) -> proto::Result {
+    use proto::result::{Mode as ProtoMode, Type};
+    fn set_mode(result: &mut proto::Result, mode: Mode) {
+        match mode {
+            Mode::ReadWrite => result.set_mode(ProtoMode::ReadWrite),
+            Mode::ReadOnly => result.set_mode(ProtoMode::ReadOnly),
+            Mode::Snapshot { version } => {
+                result.set_mode(ProtoMode::Snapshot);
+                result.version = version;
+            }
+        }
+    }
+    let mut result = proto::Result::default();
+    let kind = match resultset {
+        ResultSet::Begin { id, mode } => {
+            result.id = id;
+            set_mode(&mut result, mode);
+            Type::Begin
+        }
+        ResultSet::Commit { id, chain } => {
+            result.id = id;
+            result.chain = chain.unwrap_or(0);
+            Type::Commit
+        }
+        ResultSet::Rollback { id, chain } => {
+            result.id = id;
+            result.chain = chain.unwrap_or(0);
+            Type::Rollback
+        }
+        ResultSet::Detach { id } => {
+            result.id = id;
+            Type::Detach
+        }
+        ResultSet::Resume { id, mode } => {
+            result.id = id;
+            set_mode(&mut result, mode);
+            Type::Resume
+        }
+        ResultSet::Declare { name } => {
+            result.cursor = name;
+            Type::Declare
+        }
+        ResultSet::Close { name } => {
+            result.cursor = name;
+            Type::Close
+        }
+        ResultSet::Create { count } => {
+            result.count = count;
+            Type::Create
+        }
+        ResultSet::Delete { count } => {
+            result.count = count;
+            Type::Delete
+        }
+        ResultSet::Update { count } => {
+            result.count = count;
+            Type::Update
+        }
+        ResultSet::CreateTable { name } => {
+            result.table = name;
+            Type::CreateTable
+        }
+        ResultSet::DropTable { name } => {
+            result.table = name;
+            Type::DropTable
+        }
+        ResultSet::AddColumn { table, column } => {
+            result.table = table;
+            result.column = column;
+            Type::AddColumn
+        }
+        ResultSet::CreateIndex { table, column } => {
+            result.table = table;
+            result.column = column;
+            Type::CreateIndex
+        }
+        ResultSet::AlterColumn { table, column } => {
+            result.table = table;
+            result.column = column;
+            Type::AlterColumn
+        }
+        ResultSet::Comment { table, column } => {
+            result.table = table;
+            result.column = column.unwrap_or_default();
+            Type::Comment
+        }
+        ResultSet::SetSetting { name } => {
+            result.setting = name;
+            Type::SetSetting
+        }
+        ResultSet::CreateFunction { name } => {
+            result.function = name;
+            Type::CreateFunction
+        }
+        ResultSet::DropFunction { name } => {
+            result.function = name;
+            Type::DropFunction
+        }
+        ResultSet::CreateType { name } => {
+            result.type_name = name;
+            Type::CreateType
+        }
+        ResultSet::DropType { name } => {
+            result.type_name = name;
+            Type::DropType
+        }
+        ResultSet::CreatePolicy { table, name } => {
+            result.table = table;
+            result.policy = name;
+            Type::CreatePolicy
+        }
+        ResultSet::DropPolicy { table, name } => {
+            result.table = table;
+            result.policy = name;
+            Type::DropPolicy
+        }
+        ResultSet::Explain(explanation) => {
+            result.plan = explanation.to_string();
+            Type::Explain
+        }
+        ResultSet::Query { columns, .. } => {
+            result.columns = columns
+                .into_iter()
+                .map(|c| proto::Column {
+                    name: c.name.unwrap_or_default(),
+                    table: c.table.unwrap_or_default(),
+                    datatype: c.datatype.map(|d| d.to_string()).unwrap_or_default(),
+                })
+                .collect();
+            result.rows = rows.into_iter().map(encode_row).collect();
+            Type::Query
+        }
+    };
+    result.set_type(kind);
+    result
+}
+
+/// Encodes a row as a protobuf row.
+fn encode_row(row: Row) -> proto::Row {
+    proto::Row { values: row.into_iter().map(encode_value).collect() }
+}
+
+/// Encodes a value as a protobuf value.
+fn encode_value(value: Value) -> proto::Value {
+    use proto::value::Value as ProtoValue;
+    proto::Value {
+        value: match value {
+            Value::Null => None,
+            Value::Boolean(b) => Some(ProtoValue::Boolean(b)),
+            Value::Integer(i) => Some(ProtoValue::Integer(i)),
+            Value::Float(f) => Some(ProtoValue::Float(f)),
+            Value::String(s) => Some(ProtoValue::String(s)),
+            Value::Point(x, y) => Some(ProtoValue::Point(proto::Point { x, y })),
+            Value::Array(values) => Some(ProtoValue::Array(proto::Array {
+                values: values.into_iter().map(encode_value).collect(),
+            })),
+            value @ Value::Enum(..) => Some(ProtoValue::String(value.to_string())),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_result() {
+        let result = super::encode_result(
+            ResultSet::Begin { id: 3, mode: Mode::Snapshot { version: 2 } },
+            vec![],
+        );
+        assert_eq!(result.r#type(), proto::result::Type::Begin);
+        assert_eq!(result.mode(), proto::result::Mode::Snapshot);
+        assert_eq!((result.id, result.version), (3, 2));
+
+        let result = super::encode_result(
+            ResultSet::Query {
+                columns: vec![
+                    crate::sql::types::Column::new("id", crate::sql::types::DataType::Integer),
+                    crate::sql::types::Column::default(),
+                ],
+                rows: Box::new(std::iter::empty()),
+            },
+            vec![vec![Value::Integer(1), Value::Null]],
+        );
+        assert_eq!(result.r#type(), proto::result::Type::Query);
+        assert_eq!(
+            result.columns,
+            vec![
+                proto::Column { name: "id".into(), table: "".into(), datatype: "INTEGER".into() },
+                proto::Column::default(),
+            ]
+        );
+        assert_eq!(
+            result.rows,
+            vec![proto::Row {
+                values: vec![
+                    proto::Value { value: Some(proto::value::Value::Integer(1)) },
+                    proto::Value { value: None },
+                ]
+            }]
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! HTTP endpoints, for orchestrators such as Kubernetes to manage toyDB nodes, and for ad hoc SQL
+//! queries e.g. via curl:
+//!
+//! * GET /healthz: liveness. OK if the local Raft node responds and can read its log storage.
+//! * GET /readyz: readiness. OK if the node is live, knows of a Raft leader, is not draining, and
+//!   its state machine has applied the committed log to within the maximum apply lag.
+//! * POST /sql: executes the ;-separated SQL statements in the request body, in a new client
+//!   session which is closed afterwards.
+//!
+//! Health check responses are plain text, with status 200 if OK and 503 otherwise, followed by the
+//! local node health. SQL responses are JSON, with a result for each executed statement, and an
+//! error if a statement failed (execution stops at the first error):
+//!
+//! {"results": [{"columns": [{"name": "id", "table": "t", "type": "INTEGER"}], "rows": [[1]], ...}]}
+//! {"error": {"code": "42601", "message": "Unexpected end of input"}, "results": []}
+//!
+//! Only the small subset of HTTP/1.1 needed for these is implemented.
+
+use crate::error::{Error, Result};
+use crate::raft;
+use crate::server::{Request, Response, SessionFactory};
+use crate::sql::execution::ResultSet;
+use crate::sql::types::{Row, Value};
+
+use ::log::debug;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::StreamExt as _;
+
+/// The maximum time to receive a request, and to fetch the Raft node health.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The maximum size of the request line and headers, in bytes.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// The maximum size of a request body, in bytes.
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Serves HTTP requests until the returned future is dropped.
+pub(crate) async fn serve(
+    mut listener: TcpListener,
+    raft: raft::Client,
+    sessions: SessionFactory,
+    max_apply_lag: u64,
+) -> Result<()> {
+    while let Some(socket) = listener.try_next().await? {
+        let (raft, sessions) = (raft.clone(), sessions.clone());
+        tokio::spawn(async move {
+            if let Err(err) = handle(socket, raft, sessions, max_apply_lag).await {
+                debug!("HTTP request error: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Handles a single HTTP request, closing the connection afterwards.
+async fn handle(
+    mut socket: TcpStream,
+    raft: raft::Client,
+    sessions: SessionFactory,
+    max_apply_lag: u64,
+) -> Result<()> {
+    let peer = socket.peer_addr()?;
+    let (head, body) = tokio::time::timeout(TIMEOUT, read_request(&mut socket))
+        .await
+        .map_err(|_| Error::Internal("Timed out reading request".into()))??;
+    let (method, path) = parse_request(&head)?;
+    let (code, content_type, body) = match (method.as_str(), path.as_str()) {
+        ("GET", "/healthz") | ("HEAD", "/healthz") => {
+            text(check(&raft, false, max_apply_lag).await)
+        }
+        ("GET", "/readyz") | ("HEAD", "/readyz") => text(check(&raft, true, max_apply_lag).await),
+        ("POST", "/sql") => query(&sessions, peer, body).await,
+        (_, "/healthz") | (_, "/readyz") | (_, "/sql") => {
+            text((405, "method not allowed\n".into()))
+        }
+        _ => text((404, "not found\n".into())),
+    };
+    let reason = match code {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        _ => "Service Unavailable",
+    };
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        code,
+        reason,
+        content_type,
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown(std::net::Shutdown::Write)?;
+    Ok(())
+}
+
+/// Reads a request, returning the request line and headers, and the body if the request has a
+/// Content-Length header.
+async fn read_request(socket: &mut TcpStream) -> Result<(String, Vec<u8>)> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    let head_end = loop {
+        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            break request.len();
+        }
+        request.extend_from_slice(&buf[..n]);
+        if request.len() > MAX_REQUEST_SIZE && !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Err(Error::Value("Request too large".into()));
+        }
+    };
+    let mut body = request.split_off(head_end);
+    let head = String::from_utf8(request)?;
+    let length = content_length(&head)?;
+    if length > MAX_BODY_SIZE {
+        return Err(Error::Value("Request body too large".into()));
+    }
+    while body.len() < length {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            return Err(Error::Value("Unexpected end of request body".into()));
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    body.truncate(length);
+    Ok((head, body))
+}
+
+/// Parses the Content-Length header of a request, if any.
+fn content_length(head: &str) -> Result<usize> {
+    for line in head.lines().skip(1) {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                return Ok(value.trim().parse()?);
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Parses the method and path from a request, ignoring any query string.
+fn parse_request(request: &str) -> Result<(String, String)> {
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/") => {
+            let path = target.split('?').next().unwrap_or(target);
+            Ok((method.to_string(), path.to_string()))
+        }
+        _ => Err(Error::Value("Invalid HTTP request".into())),
+    }
+}
+
+/// Pairs a status code and body with the plain text content type.
+fn text((code, body): (u16, String)) -> (u16, &'static str, String) {
+    (code, "text/plain", body)
+}
+
+/// Runs a liveness or readiness check, returning the HTTP status code and response body.
+async fn check(raft: &raft::Client, ready: bool, max_apply_lag: u64) -> (u16, String) {
+    let health = match tokio::time::timeout(TIMEOUT, raft.health()).await {
+        Ok(Ok(health)) => health,
+        Ok(Err(err)) => return (503, format!("unhealthy: {}\n", err)),
+        Err(_) => return (503, "unhealthy: Raft node not responding\n".into()),
+    };
+    let problems = problems(&health, ready, max_apply_lag);
+    let mut body = if problems.is_empty() {
+        "ok\n".to_string()
+    } else if ready {
+        format!("not ready: {}\n", problems.join(", "))
+    } else {
+        format!("unhealthy: {}\n", problems.join(", "))
+    };
+    body.push_str(&format!(
+        "\nserver: {}\nrole: {}\nleader: {}\nterm: {}\nlast_index: {}\ncommit_index: {}\n\
+         apply_index: {}\nleader_seen_ms: {}\ndraining: {}\n",
+        health.server,
+        health.role,
+        health.leader.as_deref().unwrap_or(""),
+        health.term,
+        health.last_index,
+        health.commit_index,
+        health.apply_index,
+        health.leader_seen.map(|ms| ms.to_string()).unwrap_or_default(),
+        health.draining,
+    ));
+    (if problems.is_empty() { 200 } else { 503 }, body)
+}
+
+/// Returns any problems that fail a liveness check, or a readiness check if ready is true.
+fn problems(health: &raft::Health, ready: bool, max_apply_lag: u64) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(err) = &health.storage_error {
+        problems.push(format!("storage error: {}", err));
+    }
+    if !ready {
+        return problems;
+    }
+    if health.leader.is_none() {
+        problems.push("no known leader".into());
+    }
+    if health.draining {
+        problems.push("draining".into());
+    }
+    let lag = health.commit_index.saturating_sub(health.apply_index);
+    if lag > max_apply_lag {
+        problems.push(format!("applied index lags commit index by {} entries", lag));
+    }
+    problems
+}
+
+/// Executes the SQL statements in a request body, returning the HTTP status code, content type,
+/// and JSON response body.
+async fn query(
+    sessions: &SessionFactory,
+    peer: SocketAddr,
+    body: Vec<u8>,
+) -> (u16, &'static str, String) {
+    let mut response = serde_json::Map::new();
+    let mut results = Vec::new();
+    let mut error = None;
+    match execute(sessions, peer, body).await {
+        Ok(batch) => {
+            for result in batch {
+                match result {
+                    Ok((resultset, rows)) => results.push(encode_result(resultset, rows)),
+                    Err(err) => error = Some(err),
+                }
+            }
+        }
+        Err(err) => error = Some(err),
+    }
+    response.insert("results".into(), results.into());
+    let code = match error {
+        Some(err) => {
+            response
+                .insert("error".into(), json!({"code": err.code(), "message": err.to_string()}));
+            match err {
+                Error::Limit(_) => 429,
+                Error::Overloaded(_) => 503,
+                Error::Serialization => 409,
+                Error::Abort | Error::Config(_) | Error::Internal(_) => 500,
+                _ => 400,
+            }
+        }
+        None => 200,
+    };
+    (code, "application/json", format!("{}\n", serde_json::Value::Object(response)))
+}
+
+/// Executes the SQL statements in a request body in a new client session, returning the result
+/// of each executed statement.
+async fn execute(
+    sessions: &SessionFactory,
+    peer: SocketAddr,
+    body: Vec<u8>,
+) -> Result<Vec<Result<(ResultSet, Vec<Row>)>>> {
+    let query = String::from_utf8(body)?;
+    let mut session = sessions.session(peer)?;
+    match session.call(Request::ExecuteBatch(vec![query])).await.0? {
+        Response::ExecuteBatch(results) => Ok(results),
+        response => Err(Error::Internal(format!("Unexpected response {:?}", response))),
+    }
+}
+
+/// Encodes a result set and its rows as JSON. Queries have a column list and rows, EXPLAIN has
+/// the plan text, and other results have the fields of the result set.
+fn encode_result(resultset: ResultSet, rows: Vec<Row>) -> serde_json::Value {
+    match resultset {
+        ResultSet::Query { columns, .. } => {
+            let columns: Vec<_> = columns
+                .into_iter()
+                .map(|column| {
+                    json!({
+                        "name": column.name,
+                        "table": column.table,
+                        "type": column.datatype.map(|datatype| datatype.to_string()),
+                    })
+                })
+                .collect();
+            let rows: Vec<_> = rows
+                .into_iter()
+                .map(|row| row.into_iter().map(encode_value).collect::<Vec<_>>())
+                .collect();
+            json!({"type": "Query", "columns": columns, "rows": rows})
+        }
+        ResultSet::Explain(explanation) => {
+            json!({"type": "Explain", "plan": explanation.to_string()})
+        }
+        // Other result sets serialize as {"Variant": {fields}}, which is flattened.
+        resultset => {
+            let mut result = serde_json::Map::new();
+            if let Ok(serde_json::Value::Object(variant)) = serde_json::to_value(&resultset) {
+                for (kind, fields) in variant {
+                    result.insert("type".into(), kind.into());
+                    if let serde_json::Value::Object(fields) = fields {
+                        result.extend(fields);
+                    }
+                }
+            }
+            result.into()
+        }
+    }
+}
+
+/// Encodes a value as JSON. Non-finite floats, which JSON can't represent, are encoded as
+/// strings. Points are encoded as [x, y] arrays, arrays as JSON arrays, and enum values as their
+/// labels.
+fn encode_value(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Boolean(b) => b.into(),
+        Value::Integer(i) => i.into(),
+        Value::Float(f) if f.is_nan() => "NaN".into(),
+        Value::Float(f) if f.is_infinite() && f > 0.0 => "Infinity".into(),
+        Value::Float(f) if f.is_infinite() => "-Infinity".into(),
+        Value::Float(f) => f.into(),
+        Value::String(s) => s.into(),
+        Value::Point(x, y) => {
+            vec![encode_value(Value::Float(x)), encode_value(Value::Float(y))].into()
+        }
+        Value::Array(values) => values.into_iter().map(encode_value).collect(),
+        value @ Value::Enum(..) => value.to_string().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request() -> Result<()> {
+        assert_eq!(
+            super::parse_request("GET /readyz?verbose=1 HTTP/1.1\r\nHost: toydb\r\n\r\n")?,
+            ("GET".to_string(), "/readyz".to_string())
+        );
+        assert!(super::parse_request("GET /readyz\r\n\r\n").is_err());
+        assert!(super::parse_request("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn content_length() -> Result<()> {
+        assert_eq!(
+            super::content_length("POST /sql HTTP/1.1\r\nHost: toydb\r\ncontent-length: 12\r\n")?,
+            12
+        );
+        assert_eq!(super::content_length("GET /readyz HTTP/1.1\r\n")?, 0);
+        assert!(super::content_length("POST /sql HTTP/1.1\r\nContent-Length: x\r\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode_result() {
+        use crate::sql::types::{Column, DataType};
+        assert_eq!(
+            super::encode_result(ResultSet::Update { count: 3 }, vec![]),
+            json!({"type": "Update", "count": 3})
+        );
+        assert_eq!(
+            super::encode_result(
+                ResultSet::Query {
+                    columns: vec![
+                        Column {
+                            name: Some("id".into()),
+                            table: Some("test".into()),
+                            datatype: Some(DataType::Integer),
+                        },
+                        Column::default(),
+                    ],
+                    rows: Box::new(std::iter::empty()),
+                },
+                vec![
+                    vec![Value::Integer(1), Value::Null],
+                    vec![Value::Integer(2), Value::Float(f64::NAN)],
+                ],
+            ),
+            json!({
+                "type": "Query",
+                "columns": [
+                    {"name": "id", "table": "test", "type": "INTEGER"},
+                    {"name": null, "table": null, "type": null},
+                ],
+                "rows": [[1, null], [2, "NaN"]],
+            })
+        );
+    }
+
+    #[test]
+    fn problems() {
+        let health = raft::Health {
+            server: "a".into(),
+            role: "follower".into(),
+            leader: Some("b".into()),
+            term: 3,
+            last_index: 10,
+            commit_index: 10,
+            apply_index: 8,
+            leader_seen: Some(100),
+            draining: false,
+            storage_error: None,
+        };
+        assert!(super::problems(&health, true, 2).is_empty());
+        assert_eq!(
+            super::problems(&health, true, 1),
+            vec!["applied index lags commit index by 2 entries".to_string()]
+        );
+
+        let health = raft::Health {
+            leader: None,
+            draining: true,
+            storage_error: Some("disk error".into()),
+            ..health
+        };
+        assert_eq!(
+            super::problems(&health, false, 2),
+            vec!["storage error: disk error".to_string()]
+        );
+        assert_eq!(
+            super::problems(&health, true, 2),
+            vec![
+                "storage error: disk error".to_string(),
+                "no known leader".to_string(),
+                "draining".to_string()
+            ]
+        );
+    }
+}
@@ -2,12 +2,18 @@
 #![allow(clippy::new_without_default)]
 #![allow(clippy::unneeded_field_pattern)]
 
+pub mod bench;
 pub mod client;
 pub mod error;
+pub mod fault;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
 pub mod raft;
 pub mod server;
 pub mod sql;
 pub mod storage;
+pub mod trace;
 
 pub use client::Client;
 pub use server::Server;
@@ -1,4 +1,4 @@
-use super::{Request, Response, Status};
+use super::{Health, MembershipChange, Request, Response, Status};
 use crate::error::{Error, Result};
 
 use tokio::sync::{mpsc, oneshot};
@@ -40,11 +40,53 @@ impl Client {
         }
     }
 
+    /// Queries the local node's state machine once it has applied the given log index, without
+    /// involving the leader. The result may be stale.
+    pub async fn query_local(&self, command: Vec<u8>, index: u64) -> Result<Vec<u8>> {
+        match self.request(Request::QueryLocal { command, index }).await? {
+            Response::State(response) => Ok(response),
+            resp => Err(Error::Internal(format!("Unexpected Raft query response {:?}", resp))),
+        }
+    }
+
+    /// Changes the cluster membership, returning once the change has been committed and applied.
+    pub async fn change_membership(&self, change: MembershipChange) -> Result<()> {
+        match self.request(Request::ChangeMembership(change)).await? {
+            Response::State(_) => Ok(()),
+            resp => Err(Error::Internal(format!("Unexpected Raft membership response {:?}", resp))),
+        }
+    }
+
     /// Fetches Raft node status.
     pub async fn status(&self) -> Result<Status> {
         match self.request(Request::Status).await? {
-            Response::Status(status) => Ok(status),
+            Response::Status(status) => Ok(*status),
             resp => Err(Error::Internal(format!("Unexpected Raft status response {:?}", resp))),
         }
     }
+
+    /// Fetches the local Raft node's health. Unlike status, this doesn't require a leader.
+    pub async fn health(&self) -> Result<Health> {
+        match self.request(Request::Health).await? {
+            Response::Health(health) => Ok(health),
+            resp => Err(Error::Internal(format!("Unexpected Raft health response {:?}", resp))),
+        }
+    }
+
+    /// Flushes the local Raft node's log and state machine to storage.
+    pub async fn flush(&self) -> Result<()> {
+        match self.request(Request::Flush).await? {
+            Response::State(_) => Ok(()),
+            resp => Err(Error::Internal(format!("Unexpected Raft flush response {:?}", resp))),
+        }
+    }
+
+    /// Asks the local Raft node to transfer leadership to a peer, if it is the leader. Returns
+    /// once the transfer has been initiated, use health() to check whether it succeeded.
+    pub async fn transfer_leadership(&self) -> Result<()> {
+        match self.request(Request::TransferLeadership).await? {
+            Response::State(_) => Ok(()),
+            resp => Err(Error::Internal(format!("Unexpected Raft transfer response {:?}", resp))),
+        }
+    }
 }
@@ -5,6 +5,7 @@ use crate::storage::log::Range;
 use ::log::debug;
 use serde::{Deserialize, Serialize};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::ops::RangeBounds;
 
 /// A replicated log entry
@@ -14,20 +15,74 @@ pub struct Entry {
     pub index: u64,
     /// The term in which the entry was added.
     pub term: u64,
-    /// The state machine command. None is used to commit noops during leader election.
+    /// The state machine command. None is used to commit noops during leader election, and for
+    /// membership changes.
     pub command: Option<Vec<u8>>,
+    /// A cluster membership change, applied by all nodes once the entry is committed.
+    pub membership: Option<MembershipChange>,
+}
+
+/// A cluster membership change. Only one change can be in progress at a time, such that the
+/// quorums of the old and new memberships always overlap.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    /// Marks a node as draining, in preparation for removal. Draining nodes never campaign for
+    /// leadership, and a draining leader transfers its leadership to another node.
+    Drain(String),
+    /// Removes a node from the cluster.
+    Remove(String),
+}
+
+/// The cluster membership, as changes to the configured set of nodes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Membership {
+    /// Nodes that are draining.
+    pub draining: BTreeSet<String>,
+    /// Nodes that have been removed from the cluster.
+    pub removed: BTreeSet<String>,
+}
+
+impl Membership {
+    /// Applies a membership change.
+    pub fn apply(&mut self, change: &MembershipChange) {
+        match change {
+            MembershipChange::Drain(id) if !self.removed.contains(id) => {
+                self.draining.insert(id.clone());
+            }
+            MembershipChange::Drain(_) => {}
+            MembershipChange::Remove(id) => {
+                self.draining.remove(id);
+                self.removed.insert(id.clone());
+            }
+        }
+    }
+}
+
+/// A state machine snapshot, which replaces all log entries up to and including its index.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The index of the last entry applied to the snapshot.
+    pub index: u64,
+    /// The term of the last entry applied to the snapshot.
+    pub term: u64,
+    /// The state machine data.
+    pub data: Vec<u8>,
 }
 
 /// A metadata key
 #[derive(Clone, Debug, PartialEq)]
 pub enum Key {
     TermVote,
+    Membership,
+    Epoch,
 }
 
 impl Key {
     fn encode(&self) -> Vec<u8> {
         match self {
             Self::TermVote => vec![0x00],
+            Self::Membership => vec![0x01],
+            Self::Epoch => vec![0x02],
         }
     }
 }
@@ -47,13 +102,31 @@ pub struct Log {
     pub(super) commit_index: u64,
     /// The term of the last committed entry.
     pub(super) commit_term: u64,
+    /// The index of the last entry in the snapshot that replaced compacted entries.
+    pub(super) snapshot_index: u64,
+    /// The term of the last entry in the snapshot that replaced compacted entries.
+    pub(super) snapshot_term: u64,
+    /// The cluster membership, as of the last committed entry.
+    pub(super) membership: Membership,
 }
 
 impl Log {
     /// Creates a new log, using a log::Store for storage.
-    pub fn new(store: Box<dyn log::Store>) -> Result<Self> {
+    pub fn new(mut store: Box<dyn log::Store>) -> Result<Self> {
+        let (snapshot_index, snapshot_term) = match store.snapshot()? {
+            Some(bytes) => {
+                let snapshot: Snapshot = Self::deserialize(&bytes)?;
+                // If we crashed while compacting, the store may not have been fully compacted.
+                if snapshot.index > store.compacted() {
+                    store.compact(snapshot.index, bytes)?;
+                }
+                (snapshot.index, snapshot.term)
+            }
+            None => (0, 0),
+        };
         let (commit_index, commit_term) = match store.committed() {
             0 => (0, 0),
+            index if index == snapshot_index => (snapshot_index, snapshot_term),
             index => store
                 .get(index)?
                 .map(|v| Self::deserialize::<Entry>(&v))
@@ -63,6 +136,7 @@ impl Log {
         };
         let (last_index, last_term) = match store.len() {
             0 => (0, 0),
+            index if index == snapshot_index => (snapshot_index, snapshot_term),
             index => store
                 .get(index)?
                 .map(|v| Self::deserialize::<Entry>(&v))
@@ -70,12 +144,40 @@ impl Log {
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal("Last entry not found".into()))?,
         };
-        Ok(Self { store, last_index, last_term, commit_index, commit_term })
+        let membership = store
+            .get_metadata(&Key::Membership.encode())?
+            .map(|v| Self::deserialize(&v))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self {
+            store,
+            last_index,
+            last_term,
+            commit_index,
+            commit_term,
+            snapshot_index,
+            snapshot_term,
+            membership,
+        })
     }
 
     /// Appends a command to the log, returning the entry.
     pub fn append(&mut self, term: u64, command: Option<Vec<u8>>) -> Result<Entry> {
-        let entry = Entry { index: self.last_index + 1, term, command };
+        self.push(Entry { index: self.last_index + 1, term, command, membership: None })
+    }
+
+    /// Appends a membership change to the log, returning the entry.
+    pub fn append_membership(&mut self, term: u64, change: MembershipChange) -> Result<Entry> {
+        self.push(Entry {
+            index: self.last_index + 1,
+            term,
+            command: None,
+            membership: Some(change),
+        })
+    }
+
+    /// Appends an entry to the log store.
+    fn push(&mut self, entry: Entry) -> Result<Entry> {
         debug!("Appending log entry {}: {:?}", entry.index, entry);
         self.store.append(Self::serialize(&entry)?)?;
         self.last_index = entry.index;
@@ -83,11 +185,21 @@ impl Log {
         Ok(entry)
     }
 
-    /// Commits entries up to and including an index.
+    /// Commits entries up to and including an index. Membership changes in the committed entries
+    /// are applied and persisted first, since the entries may be compacted once committed.
     pub fn commit(&mut self, index: u64) -> Result<u64> {
         let entry = self
             .get(index)?
             .ok_or_else(|| Error::Internal(format!("Entry {} not found", index)))?;
+        let mut membership = self.membership.clone();
+        for entry in self.scan((self.commit_index + 1)..=index) {
+            if let Some(change) = entry?.membership {
+                membership.apply(&change);
+            }
+        }
+        if membership != self.membership {
+            self.set_membership(membership)?;
+        }
         self.store.commit(index)?;
         self.commit_index = entry.index;
         self.commit_term = entry.term;
@@ -99,15 +211,75 @@ impl Log {
         self.store.get(index)?.map(|v| Self::deserialize(&v)).transpose()
     }
 
-    /// Checks if the log contains an entry
+    /// Checks if the log contains an entry. Compacted entries are committed, and thus known to
+    /// match any other log's entries, so these are considered to exist for any term (except for
+    /// the last compacted entry, whose term is known).
     pub fn has(&self, index: u64, term: u64) -> Result<bool> {
         match self.get(index)? {
             Some(entry) => Ok(entry.term == term),
             None if index == 0 && term == 0 => Ok(true),
+            None if index == self.snapshot_index => Ok(term == self.snapshot_term),
+            None if index < self.snapshot_index => Ok(true),
             None => Ok(false),
         }
     }
 
+    /// Flushes the log store to the underlying storage medium.
+    pub fn flush(&mut self) -> Result<()> {
+        self.store.flush()
+    }
+
+    /// Compacts the log by replacing all entries up to and including the given committed index
+    /// with a snapshot of the state machine's data at that index. Does nothing if the log has
+    /// already been compacted beyond the index.
+    pub fn compact(&mut self, index: u64, data: Vec<u8>) -> Result<()> {
+        if index <= self.snapshot_index {
+            return Ok(());
+        }
+        if index > self.commit_index {
+            return Err(Error::Internal(format!("Cannot compact uncommitted entry {}", index)));
+        }
+        let term = self
+            .get(index)?
+            .map(|e| e.term)
+            .ok_or_else(|| Error::Internal(format!("Entry {} not found", index)))?;
+        debug!("Compacting log through entry {}", index);
+        self.store.compact(index, Self::serialize(&Snapshot { index, term, data })?)?;
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        Ok(())
+    }
+
+    /// Installs a snapshot received from the leader, replacing any entries up to and including
+    /// its index. If the log contains the snapshot's last entry, following entries are retained,
+    /// otherwise the entire log is discarded. Returns false if the snapshot was not installed
+    /// because its entries are already committed.
+    pub fn install(&mut self, snapshot: &Snapshot) -> Result<bool> {
+        if snapshot.index <= self.commit_index {
+            return Ok(false);
+        }
+        debug!("Installing snapshot at entry {}", snapshot.index);
+        if !self.has(snapshot.index, snapshot.term)? {
+            self.truncate(snapshot.index - 1)?;
+        }
+        let (index, term) = (snapshot.index, snapshot.term);
+        self.store.compact(index, Self::serialize(snapshot)?)?;
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        self.commit_index = index;
+        self.commit_term = term;
+        if self.last_index < index {
+            self.last_index = index;
+            self.last_term = term;
+        }
+        Ok(true)
+    }
+
+    /// Fetches the snapshot that replaced the compacted entries, if any.
+    pub fn snapshot(&self) -> Result<Option<Snapshot>> {
+        self.store.snapshot()?.map(|v| Self::deserialize(&v)).transpose()
+    }
+
     /// Iterates over log entries
     pub fn scan(&self, range: impl RangeBounds<u64>) -> Scan {
         Box::new(self.store.scan(Range::from(range)).map(|r| r.and_then(|v| Self::deserialize(&v))))
@@ -126,13 +298,16 @@ impl Log {
             }
         }
         for entry in entries {
+            if entry.index <= self.snapshot_index {
+                continue;
+            }
             if let Some(ref current) = self.get(entry.index)? {
                 if current.term == entry.term {
                     continue;
                 }
                 self.truncate(entry.index - 1)?;
             }
-            self.append(entry.term, entry.command)?;
+            self.push(Entry { index: self.last_index + 1, ..entry })?;
         }
         Ok(self.last_index)
     }
@@ -143,6 +318,7 @@ impl Log {
         debug!("Truncating log from entry {}", index);
         let (index, term) = match self.store.truncate(index)? {
             0 => (0, 0),
+            i if i == self.snapshot_index => (self.snapshot_index, self.snapshot_term),
             i => self
                 .store
                 .get(i)?
@@ -169,11 +345,34 @@ impl Log {
         Ok((term, voted_for))
     }
 
-    /// Saves information about the most recent term.
+    /// Saves information about the most recent term. The term and vote are stored together under
+    /// a single key, so they are updated atomically, and must be durable before a vote is sent.
     pub fn save_term(&mut self, term: u64, voted_for: Option<&str>) -> Result<()> {
         self.store.set_metadata(&Key::TermVote.encode(), Self::serialize(&(term, voted_for))?)
     }
 
+    /// Increments and persists the local process epoch, returning the new epoch (1 for the first
+    /// process). The epoch is node-local and not replicated, and is persisted before it's returned.
+    pub fn next_epoch(&mut self) -> Result<u64> {
+        let epoch = self
+            .store
+            .get_metadata(&Key::Epoch.encode())?
+            .map(|v| Self::deserialize::<u64>(&v))
+            .transpose()?
+            .unwrap_or(0)
+            + 1;
+        self.store.set_metadata(&Key::Epoch.encode(), Self::serialize(&epoch)?)?;
+        Ok(epoch)
+    }
+
+    /// Sets and persists the cluster membership, e.g. when installing a snapshot.
+    pub fn set_membership(&mut self, membership: Membership) -> Result<()> {
+        debug!("Setting cluster membership {:?}", membership);
+        self.store.set_metadata(&Key::Membership.encode(), Self::serialize(&membership)?)?;
+        self.membership = membership;
+        Ok(())
+    }
+
     /// Serializes a value for the log store.
     fn serialize<V: Serialize>(value: &V) -> Result<Vec<u8>> {
         Ok(bincode::serialize(value)?)
@@ -213,10 +412,13 @@ mod tests {
         assert_eq!(Ok(None), l.get(1));
 
         assert_eq!(
-            Entry { index: 1, term: 3, command: Some(vec![0x01]) },
+            Entry { index: 1, term: 3, command: Some(vec![0x01]), membership: None },
             l.append(3, Some(vec![0x01]))?
         );
-        assert_eq!(Some(Entry { index: 1, term: 3, command: Some(vec![0x01]) }), l.get(1)?);
+        assert_eq!(
+            Some(Entry { index: 1, term: 3, command: Some(vec![0x01]), membership: None }),
+            l.get(1)?
+        );
         assert_eq!(None, l.get(2)?);
 
         assert_eq!(1, l.last_index);
@@ -229,8 +431,11 @@ mod tests {
     #[test]
     fn append_none() -> Result<()> {
         let (mut l, _) = setup()?;
-        assert_eq!(Entry { index: 1, term: 3, command: None }, l.append(3, None)?);
-        assert_eq!(Some(Entry { index: 1, term: 3, command: None }), l.get(1)?);
+        assert_eq!(
+            Entry { index: 1, term: 3, command: None, membership: None },
+            l.append(3, None)?
+        );
+        assert_eq!(Some(Entry { index: 1, term: 3, command: None, membership: None }), l.get(1)?);
         Ok(())
     }
 
@@ -242,9 +447,15 @@ mod tests {
         l.append(2, Some(vec![0x03]))?;
 
         let l = Log::new(store)?;
-        assert_eq!(Some(Entry { index: 1, term: 1, command: Some(vec![0x01]) }), l.get(1)?);
-        assert_eq!(Some(Entry { index: 2, term: 2, command: None }), l.get(2)?);
-        assert_eq!(Some(Entry { index: 3, term: 2, command: Some(vec![0x03]) }), l.get(3)?);
+        assert_eq!(
+            Some(Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None }),
+            l.get(1)?
+        );
+        assert_eq!(Some(Entry { index: 2, term: 2, command: None, membership: None }), l.get(2)?);
+        assert_eq!(
+            Some(Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None }),
+            l.get(3)?
+        );
         Ok(())
     }
 
@@ -265,6 +476,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Membership changes are applied and persisted when committed, and survive compaction.
+    fn commit_membership() -> Result<()> {
+        let (mut l, store) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        let drain = l.append_membership(1, MembershipChange::Drain("b".into()))?;
+        assert_eq!(drain.membership, Some(MembershipChange::Drain("b".into())));
+        l.append_membership(1, MembershipChange::Remove("b".into()))?;
+        l.append_membership(1, MembershipChange::Drain("c".into()))?;
+        assert_eq!(Membership::default(), l.membership);
+
+        l.commit(2)?;
+        assert_eq!(
+            vec!["b".to_string()],
+            l.membership.draining.iter().cloned().collect::<Vec<_>>()
+        );
+        assert!(l.membership.removed.is_empty());
+
+        l.commit(3)?;
+        l.compact(3, vec![])?;
+        let expect = Membership {
+            draining: BTreeSet::new(),
+            removed: vec!["b".to_string()].into_iter().collect(),
+        };
+        assert_eq!(expect, l.membership);
+
+        // Draining a removed node does nothing.
+        let mut membership = expect.clone();
+        membership.apply(&MembershipChange::Drain("b".into()));
+        assert_eq!(expect, membership);
+
+        // Only committed changes are persisted.
+        let l = Log::new(store)?;
+        assert_eq!(expect, l.membership);
+        Ok(())
+    }
+
     #[test]
     fn commit_beyond() -> Result<()> {
         let (mut l, _) = setup()?;
@@ -294,7 +542,10 @@ mod tests {
         assert_eq!(None, l.get(1)?);
 
         l.append(3, Some(vec![0x01]))?;
-        assert_eq!(Some(Entry { index: 1, term: 3, command: Some(vec![0x01]) }), l.get(1)?);
+        assert_eq!(
+            Some(Entry { index: 1, term: 3, command: Some(vec![0x01]), membership: None }),
+            l.get(1)?
+        );
         assert_eq!(None, l.get(2)?);
         Ok(())
     }
@@ -323,16 +574,16 @@ mod tests {
 
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 1, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 1, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(0..).collect::<Result<Vec<_>>>()?
         );
         assert_eq!(
             vec![
-                Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 1, command: Some(vec![0x03]) },
+                Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 1, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(2..).collect::<Result<Vec<_>>>()?
         );
@@ -361,6 +612,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn next_epoch() -> Result<()> {
+        let (mut l, store) = setup()?;
+        assert_eq!(1, l.next_epoch()?);
+        assert_eq!(2, l.next_epoch()?);
+
+        // The epoch survives a restart.
+        let mut l = Log::new(store)?;
+        assert_eq!(3, l.next_epoch()?);
+        Ok(())
+    }
+
+    #[test]
+    // The hard state (term, vote, and commit index) must survive a restart with on-disk storage,
+    // while uncommitted entries are lost.
+    fn hard_state_persistent() -> Result<()> {
+        use crate::storage::{Compression, SyncMode};
+        let dir = tempdir::TempDir::new("toydb")?;
+        let open = || log::Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None);
+
+        let mut l = Log::new(Box::new(open()?))?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, Some(vec![0x02]))?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(2)?;
+        l.save_term(2, Some("a"))?;
+        l.save_term(3, Some("b"))?;
+        drop(l);
+
+        let l = Log::new(Box::new(open()?))?;
+        assert_eq!((3, Some("b".into())), l.load_term()?);
+        assert_eq!((2, 2), (l.commit_index, l.commit_term));
+        assert_eq!((2, 2), (l.last_index, l.last_term));
+        Ok(())
+    }
+
     #[test]
     fn splice() -> Result<()> {
         let (mut l, _) = setup()?;
@@ -371,16 +658,16 @@ mod tests {
         assert_eq!(
             4,
             l.splice(vec![
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -389,6 +676,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Spliced entries retain their membership changes.
+    fn splice_membership() -> Result<()> {
+        let (mut l, _) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        let entry = Entry {
+            index: 2,
+            term: 1,
+            command: None,
+            membership: Some(MembershipChange::Remove("b".into())),
+        };
+        assert_eq!(2, l.splice(vec![entry.clone()])?);
+        assert_eq!(Some(entry), l.get(2)?);
+        Ok(())
+    }
+
     #[test]
     fn splice_all() -> Result<()> {
         let (mut l, _) = setup()?;
@@ -399,14 +702,14 @@ mod tests {
         assert_eq!(
             2,
             l.splice(vec![
-                Entry { index: 1, term: 4, command: Some(vec![0x0a]) },
-                Entry { index: 2, term: 4, command: Some(vec![0x0b]) },
+                Entry { index: 1, term: 4, command: Some(vec![0x0a]), membership: None },
+                Entry { index: 2, term: 4, command: Some(vec![0x0b]), membership: None },
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 4, command: Some(vec![0x0a]) },
-                Entry { index: 2, term: 4, command: Some(vec![0x0b]) },
+                Entry { index: 1, term: 4, command: Some(vec![0x0a]), membership: None },
+                Entry { index: 2, term: 4, command: Some(vec![0x0b]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -424,16 +727,16 @@ mod tests {
         assert_eq!(
             4,
             l.splice(vec![
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -453,15 +756,15 @@ mod tests {
         assert_eq!(
             3,
             l.splice(vec![
-                Entry { index: 2, term: 3, command: Some(vec![0x0b]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x0c]) }
+                Entry { index: 2, term: 3, command: Some(vec![0x0b]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x0c]), membership: None }
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 3, command: Some(vec![0x0b]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x0c]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 3, command: Some(vec![0x0b]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x0c]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -480,15 +783,15 @@ mod tests {
         assert_eq!(
             Err(Error::Internal("Spliced entries must be contiguous".into())),
             l.splice(vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ])
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -505,15 +808,15 @@ mod tests {
         assert_eq!(
             Err(Error::Internal("Spliced entries cannot begin past last index".into())),
             l.splice(vec![
-                Entry { index: 5, term: 3, command: Some(vec![0x05]) },
-                Entry { index: 6, term: 3, command: Some(vec![0x06]) },
+                Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                Entry { index: 6, term: 3, command: Some(vec![0x06]), membership: None },
             ])
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -527,12 +830,20 @@ mod tests {
         l.append(2, Some(vec![0x02]))?;
         l.append(3, Some(vec![0x03]))?;
 
-        assert_eq!(3, l.splice(vec![Entry { index: 2, term: 2, command: Some(vec![0x02]) },])?);
+        assert_eq!(
+            3,
+            l.splice(vec![Entry {
+                index: 2,
+                term: 2,
+                command: Some(vec![0x02]),
+                membership: None
+            },])?
+        );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -549,8 +860,8 @@ mod tests {
         assert_eq!(2, l.truncate(2)?);
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -567,15 +878,100 @@ mod tests {
         assert_eq!(3, l.truncate(4)?);
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
         Ok(())
     }
 
+    #[test]
+    fn compact() -> Result<()> {
+        let (mut l, store) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, Some(vec![0x02]))?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(2)?;
+
+        assert_eq!(
+            Err(Error::Internal("Cannot compact uncommitted entry 3".into())),
+            l.compact(3, vec![0xff])
+        );
+        l.compact(2, vec![0xff])?;
+        assert_eq!(2, l.snapshot_index);
+        assert_eq!(2, l.snapshot_term);
+        assert_eq!(Some(Snapshot { index: 2, term: 2, data: vec![0xff] }), l.snapshot()?);
+        assert_eq!(None, l.get(2)?);
+        assert_eq!(
+            vec![Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None }],
+            l.scan(..).collect::<Result<Vec<_>>>()?
+        );
+
+        // Compacted entries are assumed to exist, since they're committed.
+        assert_eq!(true, l.has(1, 1)?);
+        assert_eq!(true, l.has(2, 2)?);
+        assert_eq!(false, l.has(2, 1)?);
+
+        // Compacting at or below the snapshot does nothing.
+        l.compact(1, vec![0x00])?;
+        assert_eq!(Some(Snapshot { index: 2, term: 2, data: vec![0xff] }), l.snapshot()?);
+
+        // Splicing skips compacted entries, and truncating to the snapshot works.
+        l.splice(vec![
+            Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+        ])?;
+        assert_eq!(3, l.last_index);
+        assert_eq!(3, l.last_term);
+        assert_eq!(2, l.truncate(2)?);
+        assert_eq!(2, l.last_term);
+
+        // The snapshot is loaded when the log is reopened.
+        let l = Log::new(store)?;
+        assert_eq!((2, 2), (l.snapshot_index, l.snapshot_term));
+        assert_eq!((2, 2), (l.commit_index, l.commit_term));
+        assert_eq!((2, 2), (l.last_index, l.last_term));
+        Ok(())
+    }
+
+    #[test]
+    fn install() -> Result<()> {
+        let (mut l, _) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(1, Some(vec![0x02]))?;
+        l.append(2, Some(vec![0x03]))?;
+        l.append(2, Some(vec![0x04]))?;
+        l.commit(1)?;
+
+        // Snapshots of committed entries are not installed.
+        assert_eq!(false, l.install(&Snapshot { index: 1, term: 1, data: vec![] })?);
+
+        // If the log contains the snapshot's last entry, later entries are retained.
+        assert_eq!(true, l.install(&Snapshot { index: 3, term: 2, data: vec![0xaa] })?);
+        assert_eq!((3, 2), (l.commit_index, l.commit_term));
+        assert_eq!((4, 2), (l.last_index, l.last_term));
+        assert_eq!(
+            vec![Entry { index: 4, term: 2, command: Some(vec![0x04]), membership: None }],
+            l.scan(..).collect::<Result<Vec<_>>>()?
+        );
+
+        // Otherwise, the entire log is discarded.
+        l.append(2, Some(vec![0x05]))?;
+        assert_eq!(true, l.install(&Snapshot { index: 5, term: 3, data: vec![0xbb] })?);
+        assert_eq!((5, 3), (l.commit_index, l.commit_term));
+        assert_eq!((5, 3), (l.last_index, l.last_term));
+        assert!(l.scan(..).collect::<Result<Vec<_>>>()?.is_empty());
+        assert_eq!(Some(Snapshot { index: 5, term: 3, data: vec![0xbb] }), l.snapshot()?);
+
+        // Snapshots beyond the end of the log empty it.
+        assert_eq!(true, l.install(&Snapshot { index: 8, term: 4, data: vec![0xcc] })?);
+        assert_eq!((8, 4), (l.last_index, l.last_term));
+        assert_eq!(9, l.append(4, None)?.index);
+        Ok(())
+    }
+
     #[test]
     fn truncate_committed() -> Result<()> {
         let (mut l, _) = setup()?;
@@ -603,4 +999,32 @@ mod tests {
         assert!(l.scan(..).collect::<Result<Vec<_>>>()?.is_empty());
         Ok(())
     }
+
+    #[test]
+    fn faults() -> Result<()> {
+        use crate::fault::{Faults, Rule};
+        let faults = Faults::new();
+        let mut l =
+            Log::new(Box::new(log::Faulty::new(Box::new(log::Memory::new()), faults.clone())))?;
+        l.append(1, Some(vec![0x01]))?;
+
+        // A failed append leaves the log unchanged, and the next append uses the same index.
+        faults.add(Rule::error("log.append").with_limit(1))?;
+        assert_eq!(l.append(1, Some(vec![0x02])), Err(Faults::error("log.append")));
+        assert_eq!((1, 1), (l.last_index, l.last_term));
+        assert_eq!(2, l.append(1, Some(vec![0x02]))?.index);
+
+        // A failed commit leaves the commit index unchanged, and can be retried.
+        faults.add(Rule::error("log.commit").with_limit(1))?;
+        assert_eq!(l.commit(2), Err(Faults::error("log.commit")));
+        assert_eq!((0, 0), (l.commit_index, l.commit_term));
+        assert_eq!(2, l.commit(2)?);
+        assert_eq!((2, 1), (l.commit_index, l.commit_term));
+
+        // A failed read does not affect later reads.
+        faults.add(Rule::error("log.get").with_limit(1))?;
+        assert_eq!(l.get(1), Err(Faults::error("log.get")));
+        assert_eq!(Some(vec![0x01]), l.get(1)?.and_then(|e| e.command));
+        Ok(())
+    }
 }
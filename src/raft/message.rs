@@ -1,4 +1,4 @@
-use super::{Entry, Status};
+use super::{Entry, Health, Membership, MembershipChange, Snapshot, Status};
 use crate::error::Result;
 
 use serde_derive::{Deserialize, Serialize};
@@ -38,6 +38,8 @@ pub enum Event {
         commit_index: u64,
         /// The term of the leader's last committed log entry.
         commit_term: u64,
+        /// The leader's logical clock when sending the heartbeat, in ticks.
+        clock: u64,
     },
     /// Followers confirm loyalty to leader after heartbeats.
     ConfirmLeader {
@@ -47,6 +49,10 @@ pub enum Event {
         /// If false, the follower does not have the entry at commit_index
         /// and would like the leader to replicate it.
         has_committed: bool,
+        /// The clock of the original leader heartbeat, to extend the leader lease.
+        clock: u64,
+        /// The follower's state machine applied index, for cluster status.
+        applied_index: u64,
     },
     /// Candidates solicit votes from all peers.
     SolicitVote {
@@ -54,9 +60,23 @@ pub enum Event {
         last_index: u64,
         // The term of the candidate's last stored log entry
         last_term: u64,
+        // Whether the election was requested by the leader via TimeoutNow, in which case peers
+        // vote even though they have a live leader.
+        transfer: bool,
     },
     /// Followers may grant votes to candidates.
     GrantVote,
+    /// Candidates solicit pre-votes from all peers before starting an election, to check whether
+    /// they could win it without incrementing their term. The message term is the term the
+    /// candidate would campaign in, and recipients do not adopt it.
+    SolicitPreVote {
+        // The index of the candidate's last stored log entry
+        last_index: u64,
+        // The term of the candidate's last stored log entry
+        last_term: u64,
+    },
+    /// Peers may grant pre-votes to candidates, using the term of the pre-vote request.
+    GrantPreVote,
     /// Leaders replicate a set of log entries to followers.
     ReplicateEntries {
         /// The index of the log entry immediately preceding the submitted commands.
@@ -73,6 +93,30 @@ pub enum Event {
     },
     /// Followers may also reject a set of log entries from a leader.
     RejectEntries,
+    /// Leaders send a state machine snapshot to followers that need log entries which have been
+    /// compacted. Followers respond with AcceptEntries for the snapshot's index.
+    InstallSnapshot {
+        /// The snapshot, which replaces all log entries up to and including its index.
+        snapshot: Snapshot,
+        /// The leader's cluster membership, since membership changes in the snapshot's entries
+        /// would otherwise be lost.
+        membership: Membership,
+    },
+    /// Leaders transferring their leadership tell an up-to-date peer to start an election
+    /// immediately, without a pre-vote.
+    TimeoutNow,
+    /// The local state machine driver took a snapshot, which the node uses to compact its log.
+    CompactLog {
+        /// The applied index of the snapshot.
+        index: u64,
+        /// The state machine snapshot.
+        data: Vec<u8>,
+    },
+    /// The local state machine driver applied log entries up to and including the given index.
+    Applied {
+        /// The applied index.
+        index: u64,
+    },
     /// A client request.
     ClientRequest {
         /// The request ID.
@@ -93,13 +137,28 @@ pub enum Event {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Request {
     Query(Vec<u8>),
+    /// Queries the local node's state machine once it has applied the given log index, without
+    /// involving the leader. The result may be stale, but reflects at least the given index.
+    /// Witness nodes have no state machine, and forward this to the leader as a regular query.
+    QueryLocal {
+        command: Vec<u8>,
+        index: u64,
+    },
     Mutate(Vec<u8>),
+    ChangeMembership(MembershipChange),
     Status,
+    /// Fetches the local node's health, without involving the leader.
+    Health,
+    /// Flushes the local node's log and state machine to storage.
+    Flush,
+    /// Asks the local node to transfer leadership to a peer, if it is the leader.
+    TransferLeadership,
 }
 
 /// A client response.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Response {
     State(Vec<u8>),
-    Status(Status),
+    Status(Box<Status>),
+    Health(Health),
 }
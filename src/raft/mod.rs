@@ -5,9 +5,9 @@ mod node;
 mod server;
 mod state;
 
-pub use self::log::{Entry, Log, Scan};
+pub use self::log::{Entry, Log, Membership, MembershipChange, Scan, Snapshot};
 pub use client::Client;
 pub use message::{Address, Event, Message, Request, Response};
-pub use node::{Node, Status};
-pub use server::Server;
-pub use state::{Driver, Instruction, State};
+pub use node::{Health, Node, NodeStatus, Options, Status};
+pub use server::{ticks, Server, DEFAULT_COMPRESSION_THRESHOLD};
+pub use state::{Driver, Instruction, State, WitnessState};
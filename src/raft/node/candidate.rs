@@ -1,11 +1,12 @@
 use super::super::{Address, Event, Message, Response};
-use super::{Follower, Leader, Node, RoleNode, ELECTION_TIMEOUT_MAX, ELECTION_TIMEOUT_MIN};
+use super::{Follower, Health, Leader, Node, RoleNode};
 use crate::error::Result;
 
 use ::log::{debug, info, warn};
-use rand::Rng as _;
 
-/// A candidate is campaigning to become a leader.
+/// A candidate is campaigning to become a leader. Campaigns begin with a pre-vote, where the
+/// candidate checks that it could win an election for the next term before actually incrementing
+/// its term, such that e.g. a node rejoining after a partition can't disrupt a live leader.
 #[derive(Debug)]
 pub struct Candidate {
     /// Ticks elapsed since election start.
@@ -14,27 +15,40 @@ pub struct Candidate {
     election_timeout: u64,
     /// Votes received (including ourself).
     votes: u64,
+    /// Whether the candidate is in the pre-vote phase, counting pre-votes rather than votes.
+    prevote: bool,
 }
 
 impl Candidate {
-    /// Creates a new candidate role.
-    pub fn new() -> Self {
+    /// Creates a new candidate role with the given election timeout.
+    pub fn new(election_timeout: u64) -> Self {
         Self {
             votes: 1, // We always start with a vote for ourselves.
             election_ticks: 0,
-            election_timeout: rand::thread_rng()
-                .gen_range(ELECTION_TIMEOUT_MIN, ELECTION_TIMEOUT_MAX),
+            election_timeout,
+            prevote: false,
         }
     }
+
+    /// Creates a new candidate role in the pre-vote phase, with the given election timeout.
+    pub fn prevote(election_timeout: u64) -> Self {
+        Self { prevote: true, ..Self::new(election_timeout) }
+    }
 }
 
 impl RoleNode<Candidate> {
+    /// Returns the local node health.
+    pub fn health(&self) -> Health {
+        self.health_with("candidate", None, None)
+    }
+
     /// Transition to follower role.
     fn become_follower(mut self, term: u64, leader: &str) -> Result<RoleNode<Follower>> {
         info!("Discovered leader {} for term {}, following", leader, term);
         self.term = term;
         self.log.save_term(term, None)?;
-        let mut node = self.become_role(Follower::new(Some(leader), None))?;
+        let timeout = self.election_timeout();
+        let mut node = self.become_role(Follower::new(Some(leader), None, timeout))?;
         node.abort_proxied()?;
         node.forward_queued(Address::Peer(leader.to_string()))?;
         Ok(node)
@@ -46,25 +60,69 @@ impl RoleNode<Candidate> {
         let peers = self.peers.clone();
         let last_index = self.log.last_index;
         let mut node = self.become_role(Leader::new(peers, last_index))?;
-        node.send(
-            Address::Peers,
-            Event::Heartbeat {
-                commit_index: node.log.commit_index,
-                commit_term: node.log.commit_term,
-            },
-        )?;
+        node.heartbeat()?;
         node.append(None)?;
         node.abort_proxied()?;
         Ok(node)
     }
 
+    /// Picks a new election timeout, e.g. after the options have changed.
+    pub fn reset_election_timeout(&mut self) {
+        self.role.election_timeout = self.election_timeout();
+    }
+
+    /// Solicits pre-votes for the next term from all peers, without incrementing our term.
+    pub fn solicit_prevote(&mut self) -> Result<()> {
+        self.send_term(
+            Address::Peers,
+            self.term + 1,
+            Event::SolicitPreVote {
+                last_index: self.log.last_index,
+                last_term: self.log.last_term,
+            },
+        )
+    }
+
+    /// Starts an election for the next term, after winning the pre-vote or when the leader
+    /// transfers its leadership to us.
+    pub fn start_election(&mut self, transfer: bool) -> Result<()> {
+        if transfer {
+            info!("Starting election for term {} on leader request", self.term + 1);
+        } else {
+            info!("Won pre-vote, starting election for term {}", self.term + 1);
+        }
+        self.term += 1;
+        self.elections += 1;
+        self.log.save_term(self.term, None)?;
+        self.role = Candidate::new(self.election_timeout());
+        self.send(
+            Address::Peers,
+            Event::SolicitVote {
+                last_index: self.log.last_index,
+                last_term: self.log.last_term,
+                transfer,
+            },
+        )
+    }
+
     /// Processes a message.
     pub fn step(mut self, msg: Message) -> Result<Node> {
+        self.reject_stale_leader(&msg)?;
         if let Err(err) = self.validate(&msg) {
             warn!("Ignoring invalid message: {}", err);
             return Ok(self.into());
         }
-        if msg.term > self.term {
+        // Pre-vote messages use the prospective term of the election, which we don't adopt. Client
+        // requests and responses may be proxied by any peer, which mustn't be taken as the leader.
+        if msg.term > self.term
+            && !matches!(
+                msg.event,
+                Event::SolicitPreVote { .. }
+                    | Event::GrantPreVote
+                    | Event::ClientRequest { .. }
+                    | Event::ClientResponse { .. }
+            )
+        {
             if let Address::Peer(from) = &msg.from {
                 return self.become_follower(msg.term, from)?.step(msg);
             }
@@ -77,6 +135,9 @@ impl RoleNode<Candidate> {
                 }
             }
 
+            // Ignore stray votes from a previous election while in the pre-vote phase.
+            Event::GrantVote if self.role.prevote => {}
+
             Event::GrantVote => {
                 debug!("Received term {} vote from {:?}", self.term, msg.from);
                 self.role.votes += 1;
@@ -94,39 +155,53 @@ impl RoleNode<Candidate> {
 
             Event::ClientResponse { id, mut response } => {
                 if let Ok(Response::Status(ref mut status)) = response {
-                    status.server = self.id.clone();
+                    self.set_status(status, "candidate");
                 }
                 self.proxied_reqs.remove(&id);
                 self.send(Address::Client, Event::ClientResponse { id, response })?;
             }
 
+            Event::GrantPreVote => {
+                if self.role.prevote && msg.term == self.term + 1 {
+                    debug!("Received term {} pre-vote from {:?}", msg.term, msg.from);
+                    self.role.votes += 1;
+                    if self.role.votes >= self.quorum() {
+                        self.start_election(false)?;
+                    }
+                }
+            }
+
             // Ignore other candidates when we're also campaigning
             Event::SolicitVote { .. } => {}
 
+            // Grant pre-votes to other candidates with up-to-date logs, since we don't know of a
+            // leader either. This doesn't affect our own term or vote.
+            Event::SolicitPreVote { last_index, last_term } => {
+                if self.is_log_current(last_index, last_term) {
+                    self.send_term(msg.from, msg.term, Event::GrantPreVote)?;
+                }
+            }
+
             Event::ConfirmLeader { .. }
             | Event::ReplicateEntries { .. }
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries { .. } => warn!("Received unexpected message {:?}", msg),
+            | Event::RejectEntries { .. }
+            | Event::InstallSnapshot { .. }
+            | Event::TimeoutNow
+            | Event::CompactLog { .. }
+            | Event::Applied { .. } => warn!("Received unexpected message {:?}", msg),
         }
         Ok(self.into())
     }
 
     /// Processes a logical clock tick.
     pub fn tick(mut self) -> Result<Node> {
-        // If the election times out, start a new one for the next term.
+        // If the election (or pre-vote) times out, start a new pre-vote for the next term.
         self.role.election_ticks += 1;
         if self.role.election_ticks >= self.role.election_timeout {
-            info!("Election timed out, starting new election for term {}", self.term + 1);
-            self.term += 1;
-            self.log.save_term(self.term, None)?;
-            self.role = Candidate::new();
-            self.send(
-                Address::Peers,
-                Event::SolicitVote {
-                    last_index: self.log.last_index,
-                    last_term: self.log.last_term,
-                },
-            )?;
+            info!("Election timed out, starting pre-vote for term {}", self.term + 1);
+            self.role = Candidate::prevote(self.election_timeout());
+            self.solicit_prevote()?;
         }
         Ok(self.into())
     }
@@ -136,6 +211,7 @@ impl RoleNode<Candidate> {
 mod tests {
     use super::super::super::{Entry, Instruction, Log, Request};
     use super::super::tests::{assert_messages, assert_node};
+    use super::super::{Options, ELECTION_TIMEOUT_MIN, HEARTBEAT_INTERVAL};
     use super::*;
     use crate::storage::log;
     use std::collections::HashMap;
@@ -165,7 +241,13 @@ mod tests {
             state_tx,
             queued_reqs: Vec::new(),
             proxied_reqs: HashMap::new(),
-            role: Candidate::new(),
+            lease_skew: None,
+            elections: 0,
+            heartbeat_gap: HEARTBEAT_INTERVAL,
+            applied_index: 0,
+            options: Options::default(),
+            rng: rand::SeedableRng::from_entropy(),
+            role: Candidate::new(ELECTION_TIMEOUT_MIN),
         };
         node = match node.step(Message {
             from: Address::Client,
@@ -189,7 +271,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3);
         assert_messages(
@@ -208,7 +290,12 @@ mod tests {
                     from: Address::Local,
                     to: Address::Peer("b".into()),
                     term: 3,
-                    event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        clock: 0,
+                        applied_index: 0,
+                    },
                 },
             ],
         );
@@ -225,7 +312,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(4);
         assert_messages(
@@ -244,7 +331,12 @@ mod tests {
                     from: Address::Local,
                     to: Address::Peer("b".into()),
                     term: 4,
-                    event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        clock: 0,
+                        applied_index: 0,
+                    },
                 },
             ],
         );
@@ -253,17 +345,25 @@ mod tests {
     }
 
     #[test]
-    // Heartbeat for past term is ignored
+    // Heartbeat for past term is rejected with our term, so the stale leader steps down.
     fn step_heartbeat_past_term() -> Result<()> {
         let (candidate, mut node_rx, mut state_rx) = setup()?;
         let node = candidate.step(Message {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 2,
-            event: Event::Heartbeat { commit_index: 1, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 1, commit_term: 1, clock: 0 },
         })?;
         assert_node(&node).is_candidate().term(3);
-        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::RejectEntries,
+            }],
+        );
         assert_messages(&mut state_rx, vec![]);
         Ok(())
     }
@@ -300,7 +400,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
             },
         );
 
@@ -314,7 +414,7 @@ mod tests {
                     event: Event::ReplicateEntries {
                         base_index: 3,
                         base_term: 2,
-                        entries: vec![Entry { index: 4, term: 3, command: None }],
+                        entries: vec![Entry { index: 4, term: 3, command: None, membership: None }],
                     },
                 }
             )
@@ -327,7 +427,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
             }],
         );
         assert_messages(
@@ -358,15 +458,98 @@ mod tests {
             assert_node(&node).is_candidate().term(3);
             node = node.tick()?;
         }
-        assert_node(&node).is_candidate().term(4);
+        assert_node(&node).is_candidate().term(3);
+
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 4,
+                event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
 
+    #[test]
+    // A quorum of pre-votes starts an election for the next term.
+    fn step_grantprevote() -> Result<()> {
+        let (mut candidate, mut node_rx, mut state_rx) = setup()?;
+        candidate.role = Candidate::prevote(ELECTION_TIMEOUT_MIN);
+        let mut node = Node::Candidate(candidate);
+
+        // Votes are ignored during the pre-vote.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::GrantVote,
+        })?;
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::GrantPreVote,
+        })?;
+        assert_node(&node).is_candidate().term(3).elections(0);
+        assert_messages(&mut node_rx, vec![]);
+
+        node = node.step(Message {
+            from: Address::Peer("d".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::GrantPreVote,
+        })?;
+        assert_node(&node).is_candidate().term(4).elections(1);
         assert_messages(
             &mut node_rx,
             vec![Message {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 4,
-                event: Event::SolicitVote { last_index: 3, last_term: 2 },
+                event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
+            }],
+        );
+
+        // Stray pre-votes are ignored once the election has started.
+        node = node.step(Message {
+            from: Address::Peer("e".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::GrantPreVote,
+        })?;
+        assert_node(&node).is_candidate().term(4);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // SolicitPreVote is granted for up-to-date logs, without changing the term.
+    fn step_solicitprevote() -> Result<()> {
+        let (candidate, mut node_rx, mut state_rx) = setup()?;
+        let mut node = candidate.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+        })?;
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 2, last_term: 2 },
+        })?;
+        assert_node(&node).is_candidate().term(3);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 4,
+                event: Event::GrantPreVote,
             }],
         );
         assert_messages(&mut state_rx, vec![]);
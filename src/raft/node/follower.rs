@@ -1,9 +1,8 @@
-use super::super::{Address, Event, Instruction, Message, Response};
-use super::{Candidate, Node, RoleNode, ELECTION_TIMEOUT_MAX, ELECTION_TIMEOUT_MIN};
-use crate::error::Result;
+use super::super::{Address, Entry, Event, Instruction, Message, Response};
+use super::{Candidate, Health, Node, RoleNode};
+use crate::error::{Error, Result};
 
 use ::log::{debug, info, warn};
-use rand::Rng as _;
 
 // A follower replicates state from a leader.
 #[derive(Debug)]
@@ -19,34 +18,45 @@ pub struct Follower {
 }
 
 impl Follower {
-    /// Creates a new follower role.
-    pub fn new(leader: Option<&str>, voted_for: Option<&str>) -> Self {
+    /// Creates a new follower role with the given election timeout.
+    pub fn new(leader: Option<&str>, voted_for: Option<&str>, election_timeout: u64) -> Self {
         Self {
             leader: leader.map(String::from),
             voted_for: voted_for.map(String::from),
             leader_seen_ticks: 0,
-            leader_seen_timeout: rand::thread_rng()
-                .gen_range(ELECTION_TIMEOUT_MIN, ELECTION_TIMEOUT_MAX),
+            leader_seen_timeout: election_timeout,
         }
     }
 }
 
 impl RoleNode<Follower> {
-    /// Transforms the node into a candidate.
-    fn become_candidate(self) -> Result<RoleNode<Candidate>> {
-        info!("Starting election for term {}", self.term + 1);
-        let mut node = self.become_role(Candidate::new())?;
-        node.term += 1;
-        node.log.save_term(node.term, None)?;
-        node.send(
-            Address::Peers,
-            Event::SolicitVote { last_index: node.log.last_index, last_term: node.log.last_term },
-        )?;
+    /// Returns the local node health.
+    pub fn health(&self) -> Health {
+        let leader_seen = self.role.leader.as_ref().map(|_| self.role.leader_seen_ticks);
+        self.health_with("follower", self.role.leader.clone(), leader_seen)
+    }
+
+    /// Transforms the node into a candidate, starting with a pre-vote for the next term.
+    fn become_candidate(mut self) -> Result<RoleNode<Candidate>> {
+        info!("Starting pre-vote for term {}", self.term + 1);
+        let timeout = self.election_timeout();
+        let mut node = self.become_role(Candidate::prevote(timeout))?;
+        node.solicit_prevote()?;
+        Ok(node)
+    }
+
+    /// Transforms the node into a candidate and starts an election right away, skipping the
+    /// pre-vote, since the leader is transferring its leadership to us.
+    fn become_candidate_now(mut self) -> Result<RoleNode<Candidate>> {
+        let timeout = self.election_timeout();
+        let mut node = self.become_role(Candidate::new(timeout))?;
+        node.start_election(true)?;
         Ok(node)
     }
 
     /// Transforms the node into a follower for a new leader.
     fn become_follower(mut self, leader: &str, term: u64) -> Result<RoleNode<Follower>> {
+        let timeout = self.election_timeout();
         let mut voted_for = None;
         if term > self.term {
             info!("Discovered new term {}, following leader {}", term, leader);
@@ -56,12 +66,17 @@ impl RoleNode<Follower> {
             info!("Discovered leader {}, following", leader);
             voted_for = self.role.voted_for;
         };
-        self.role = Follower::new(Some(leader), voted_for.as_deref());
+        self.role = Follower::new(Some(leader), voted_for.as_deref(), timeout);
         self.abort_proxied()?;
         self.forward_queued(Address::Peer(leader.to_string()))?;
         Ok(self)
     }
 
+    /// Picks a new election timeout, e.g. after the options have changed.
+    pub fn reset_election_timeout(&mut self) {
+        self.role.leader_seen_timeout = self.election_timeout();
+    }
+
     /// Checks if an address is the current leader
     fn is_leader(&self, from: &Address) -> bool {
         match (&self.role.leader, from) {
@@ -70,100 +85,192 @@ impl RoleNode<Follower> {
         }
     }
 
+    /// Checks if we've heard from the leader within the minimum election timeout.
+    fn has_live_leader(&self) -> bool {
+        self.role.leader.is_some()
+            && self.role.leader_seen_ticks < self.options.election_timeout_min
+    }
+
     /// Processes a message.
     pub fn step(mut self, msg: Message) -> Result<Node> {
+        self.reject_stale_leader(&msg)?;
         if let Err(err) = self.validate(&msg) {
             warn!("Ignoring invalid message: {}", err);
             return Ok(self.into());
         }
+        // Ignore votes for future terms while we have a live leader, since the leader may be
+        // relying on our support for its lease. Candidates can only get here by winning a pre-vote
+        // while we were still following the old leader, unless the leader is transferring its
+        // leadership to them.
+        if let Event::SolicitVote { transfer: false, .. } = msg.event {
+            if msg.term > self.term && self.has_live_leader() {
+                debug!("Ignoring vote request from {:?} while leader is live", msg.from);
+                return Ok(self.into());
+            }
+        }
+        // Pre-vote messages use the prospective term of the election, which we don't adopt. Client
+        // requests and responses may be proxied by any peer, which mustn't be taken as the leader.
         if let Address::Peer(from) = &msg.from {
-            if msg.term > self.term || self.role.leader.is_none() {
+            if (msg.term > self.term || self.role.leader.is_none())
+                && !matches!(
+                    msg.event,
+                    Event::SolicitPreVote { .. }
+                        | Event::GrantPreVote
+                        | Event::ClientRequest { .. }
+                        | Event::ClientResponse { .. }
+                )
+            {
                 return self.become_follower(from, msg.term)?.step(msg);
             }
         }
         if self.is_leader(&msg.from) {
+            // Adapt the election timeout to the observed heartbeat gap, i.e. the ticks since we
+            // last heard from the leader, in case heartbeats are delayed by a slow network.
+            if let Event::Heartbeat { .. } = msg.event {
+                if self.observe_heartbeat_gap(self.role.leader_seen_ticks) {
+                    self.reset_election_timeout();
+                }
+            }
             self.role.leader_seen_ticks = 0
         }
 
         match msg.event {
-            Event::Heartbeat { commit_index, commit_term } => {
+            Event::Heartbeat { commit_index, commit_term, clock } => {
                 if self.is_leader(&msg.from) {
                     let has_committed = self.log.has(commit_index, commit_term)?;
                     if has_committed && commit_index > self.log.commit_index {
                         let old_commit_index = self.log.commit_index;
                         self.log.commit(commit_index)?;
+                        self.sync_membership();
                         let mut scan = self.log.scan((old_commit_index + 1)..=commit_index);
                         while let Some(entry) = scan.next().transpose()? {
                             self.state_tx.send(Instruction::Apply { entry })?;
                         }
                     }
-                    self.send(msg.from, Event::ConfirmLeader { commit_index, has_committed })?;
+                    self.send(
+                        msg.from,
+                        Event::ConfirmLeader {
+                            commit_index,
+                            has_committed,
+                            clock,
+                            applied_index: self.applied_index,
+                        },
+                    )?;
                 }
             }
 
-            Event::SolicitVote { last_index, last_term } => {
+            Event::SolicitVote { last_index, last_term, .. } => {
                 if let Some(voted_for) = &self.role.voted_for {
                     if msg.from != Address::Peer(voted_for.clone()) {
                         return Ok(self.into());
                     }
                 }
-                if last_term < self.log.last_term {
-                    return Ok(self.into());
-                }
-                if last_term == self.log.last_term && last_index < self.log.last_index {
+                if !self.is_log_current(last_index, last_term) {
                     return Ok(self.into());
                 }
                 if let Address::Peer(from) = msg.from {
+                    // The vote must be persisted before it is sent, to avoid voting twice in the
+                    // same term if we crash and restart.
                     info!("Voting for {} in term {} election", from, self.term);
-                    self.send(Address::Peer(from.clone()), Event::GrantVote)?;
                     self.log.save_term(self.term, Some(&from))?;
+                    self.send(Address::Peer(from.clone()), Event::GrantVote)?;
                     self.role.voted_for = Some(from);
                 }
             }
 
+            // Pre-votes are granted to candidates with up-to-date logs, unless we've heard from
+            // the leader recently, in which case the candidate must be lagging or partitioned.
+            // They are not recorded, since the candidate hasn't actually incremented its term.
+            Event::SolicitPreVote { last_index, last_term } => {
+                if self.has_live_leader() {
+                    return Ok(self.into());
+                }
+                if self.is_log_current(last_index, last_term) {
+                    debug!("Granting term {} pre-vote to {:?}", msg.term, msg.from);
+                    self.send_term(msg.from, msg.term, Event::GrantPreVote)?;
+                }
+            }
+
             Event::ReplicateEntries { base_index, base_term, entries } => {
                 if self.is_leader(&msg.from) {
                     if base_index > 0 && !self.log.has(base_index, base_term)? {
                         debug!("Rejecting log entries at base {}", base_index);
                         self.send(msg.from, Event::RejectEntries)?
                     } else {
+                        let entries = if self.options.witness {
+                            entries.into_iter().map(|e| Entry { command: None, ..e }).collect()
+                        } else {
+                            entries
+                        };
                         let last_index = self.log.splice(entries)?;
                         self.send(msg.from, Event::AcceptEntries { last_index })?
                     }
                 }
             }
 
-            Event::ClientRequest { ref id, .. } => {
-                if let Some(leader) = self.role.leader.as_deref() {
+            Event::InstallSnapshot { mut snapshot, membership } => {
+                if self.is_leader(&msg.from) {
+                    if self.options.witness {
+                        snapshot.data = Vec::new();
+                    }
+                    let index = snapshot.index;
+                    if self.log.install(&snapshot)? {
+                        info!("Installed snapshot from leader at index {}", index);
+                        self.log.set_membership(membership)?;
+                        self.sync_membership();
+                        self.state_tx.send(Instruction::Restore { snapshot })?;
+                    }
+                    self.send(msg.from, Event::AcceptEntries { last_index: index })?
+                }
+            }
+
+            Event::TimeoutNow => {
+                if self.is_leader(&msg.from) && self.can_campaign() {
+                    info!("Leader {:?} is transferring leadership to us", msg.from);
+                    return Ok(self.become_candidate_now()?.into());
+                }
+            }
+
+            Event::ClientRequest { ref id, .. } => match self.role.leader.as_deref() {
+                // A peer proxied the request to us believing we're the leader, but we aren't.
+                // Abort it rather than proxying it on, since nodes with stale leaders could
+                // otherwise bounce it between them indefinitely.
+                Some(_) if matches!(msg.from, Address::Peer(_)) => self.send(
+                    msg.from,
+                    Event::ClientResponse { id: id.clone(), response: Err(Error::Abort) },
+                )?,
+                Some(leader) => {
                     self.proxied_reqs.insert(id.clone(), msg.from);
                     self.send(Address::Peer(leader.to_string()), msg.event)?
-                } else {
-                    self.queued_reqs.push((msg.from, msg.event));
                 }
-            }
+                None => self.queued_reqs.push((msg.from, msg.event)),
+            },
 
             Event::ClientResponse { id, mut response } => {
                 if let Ok(Response::Status(ref mut status)) = response {
-                    status.server = self.id.clone();
+                    self.set_status(status, "follower");
                 }
                 self.proxied_reqs.remove(&id);
                 self.send(Address::Client, Event::ClientResponse { id, response })?;
             }
 
             // Ignore votes which are usually strays from the previous election that we lost.
-            Event::GrantVote => {}
+            Event::GrantVote | Event::GrantPreVote => {}
 
             Event::ConfirmLeader { .. }
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries { .. } => warn!("Received unexpected message {:?}", msg),
+            | Event::RejectEntries { .. }
+            | Event::CompactLog { .. }
+            | Event::Applied { .. } => warn!("Received unexpected message {:?}", msg),
         };
         Ok(self.into())
     }
 
-    /// Processes a logical clock tick.
+    /// Processes a logical clock tick. Witnesses never campaign, since they can't be leaders, and
+    /// neither do draining or removed nodes.
     pub fn tick(mut self) -> Result<Node> {
         self.role.leader_seen_ticks += 1;
-        if self.role.leader_seen_ticks >= self.role.leader_seen_timeout {
+        if self.role.leader_seen_ticks >= self.role.leader_seen_timeout && self.can_campaign() {
             Ok(self.become_candidate()?.into())
         } else {
             Ok(self.into())
@@ -173,8 +280,9 @@ impl RoleNode<Follower> {
 
 #[cfg(test)]
 pub mod tests {
-    use super::super::super::{Entry, Log, Request};
+    use super::super::super::{Entry, Log, Membership, MembershipChange, Request, Snapshot};
     use super::super::tests::{assert_messages, assert_node};
+    use super::super::{Options, ELECTION_TIMEOUT_MIN, HEARTBEAT_INTERVAL};
     use super::*;
     use crate::error::Error;
     use crate::storage::log;
@@ -189,6 +297,10 @@ pub mod tests {
         node.role.voted_for.clone()
     }
 
+    pub fn follower_timeout(node: &RoleNode<Follower>) -> u64 {
+        node.role.leader_seen_timeout
+    }
+
     #[allow(clippy::type_complexity)]
     fn setup() -> Result<(
         RoleNode<Follower>,
@@ -213,7 +325,13 @@ pub mod tests {
             state_tx,
             proxied_reqs: HashMap::new(),
             queued_reqs: Vec::new(),
-            role: Follower::new(Some("b"), None),
+            lease_skew: None,
+            elections: 0,
+            heartbeat_gap: HEARTBEAT_INTERVAL,
+            applied_index: 0,
+            options: Options::default(),
+            rng: rand::SeedableRng::from_entropy(),
+            role: Follower::new(Some("b"), None, ELECTION_TIMEOUT_MIN),
         };
         Ok((node, node_rx, state_rx))
     }
@@ -226,7 +344,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(3);
         assert_messages(
@@ -235,13 +353,18 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                event: Event::ConfirmLeader {
+                    commit_index: 3,
+                    has_committed: true,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -255,7 +378,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 3, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(
@@ -264,7 +387,12 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: false },
+                event: Event::ConfirmLeader {
+                    commit_index: 3,
+                    has_committed: false,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -279,7 +407,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 5, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(
@@ -288,7 +416,12 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 5, has_committed: false },
+                event: Event::ConfirmLeader {
+                    commit_index: 5,
+                    has_committed: false,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -303,7 +436,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 5, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -315,12 +448,12 @@ pub mod tests {
     // Heartbeat when no current leader makes us follow the leader
     fn step_heartbeat_no_leader() -> Result<()> {
         let (mut follower, mut node_rx, mut state_rx) = setup()?;
-        follower.role = Follower::new(None, None);
+        follower.role = Follower::new(None, None, ELECTION_TIMEOUT_MIN);
         let node = follower.step(Message {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("c")).voted_for(None).committed(3);
         assert_messages(
@@ -329,13 +462,18 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("c".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                event: Event::ConfirmLeader {
+                    commit_index: 3,
+                    has_committed: true,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -349,7 +487,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 1, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 1, commit_term: 1, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(
@@ -358,7 +496,12 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 1, has_committed: true },
+                event: Event::ConfirmLeader {
+                    commit_index: 1,
+                    has_committed: true,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -373,7 +516,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(4).leader(Some("c")).voted_for(None);
         assert_messages(
@@ -382,30 +525,43 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("c".into()),
                 term: 4,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                event: Event::ConfirmLeader {
+                    commit_index: 3,
+                    has_committed: true,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
     }
 
     #[test]
-    // Heartbeat from past term
+    // Heartbeat from past term is rejected with our term, so the stale leader steps down.
     fn step_heartbeat_past_term() -> Result<()> {
         let (follower, mut node_rx, mut state_rx) = setup()?;
         let node = follower.step(Message {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 2,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
-        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::RejectEntries,
+            }],
+        );
         assert_messages(&mut state_rx, vec![]);
         Ok(())
     }
@@ -420,7 +576,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 2 },
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(Some("c"));
         assert_messages(
@@ -439,7 +595,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 2 },
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(Some("c"));
         assert_messages(
@@ -458,7 +614,7 @@ pub mod tests {
             from: Address::Peer("d".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 2 },
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(Some("c"));
         assert_messages(&mut node_rx, vec![]);
@@ -466,6 +622,116 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // SolicitPreVote is ignored while the leader is live, but granted once the leader has been
+    // silent for an election timeout, without changing the term or vote.
+    fn step_solicitprevote() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(&mut node_rx, vec![]);
+
+        for _ in 0..ELECTION_TIMEOUT_MIN {
+            node = match node {
+                Node::Follower(mut n) => {
+                    n.role.leader_seen_ticks += 1;
+                    n.into()
+                }
+                _ => panic!("Expected follower"),
+            };
+        }
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("c".into()),
+                term: 4,
+                event: Event::GrantPreVote,
+            }],
+        );
+
+        // Pre-votes are granted to several candidates, but not to ones with outdated logs.
+        node = node.step(Message {
+            from: Address::Peer("d".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 4, last_term: 2 },
+        })?;
+        node = node.step(Message {
+            from: Address::Peer("e".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 3, last_term: 1 },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("d".into()),
+                term: 4,
+                event: Event::GrantPreVote,
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // SolicitVote for a future term is ignored while the leader is live, since it may hold a
+    // lease, but is granted once the leader has been silent for an election timeout.
+    fn step_solicitvote_live_leader() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(&mut node_rx, vec![]);
+
+        for _ in 0..ELECTION_TIMEOUT_MIN {
+            node = match node {
+                Node::Follower(mut n) => {
+                    n.role.leader_seen_ticks += 1;
+                    n.into()
+                }
+                _ => panic!("Expected follower"),
+            };
+        }
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
+        })?;
+        assert_node(&node).is_follower().term(4).voted_for(Some("c"));
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("c".into()),
+                term: 4,
+                event: Event::GrantVote,
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // GrantVote messages are ignored
     fn step_grantvote_noop() -> Result<()> {
@@ -490,7 +756,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 2, last_term: 2 },
+            event: Event::SolicitVote { last_index: 2, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
         assert_messages(&mut node_rx, vec![]);
@@ -506,7 +772,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 1 },
+            event: Event::SolicitVote { last_index: 3, last_term: 1, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
         assert_messages(&mut node_rx, vec![]);
@@ -526,15 +792,15 @@ pub mod tests {
                 base_index: 0,
                 base_term: 0,
                 entries: vec![
-                    Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                    Entry { index: 2, term: 1, command: Some(vec![0x02]) },
+                    Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                    Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -549,6 +815,98 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // InstallSnapshot from the leader installs the snapshot and restores the state machine
+    fn step_installsnapshot() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let snapshot = Snapshot { index: 5, term: 3, data: vec![0xaa] };
+        let mut membership = Membership::default();
+        membership.apply(&MembershipChange::Remove("e".into()));
+        let node = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot { snapshot: snapshot.clone(), membership },
+        })?;
+        assert_node(&node)
+            .is_follower()
+            .term(3)
+            .committed(5)
+            .last(5)
+            .entries(vec![])
+            .peers(vec!["b", "c", "d"]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![Instruction::Restore { snapshot }]);
+
+        // Later entries can be appended after the snapshot.
+        let node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ReplicateEntries {
+                base_index: 5,
+                base_term: 3,
+                entries: vec![Entry {
+                    index: 6,
+                    term: 3,
+                    command: Some(vec![0x06]),
+                    membership: None,
+                }],
+            },
+        })?;
+        assert_node(&node).is_follower().last(6).entries(vec![Entry {
+            index: 6,
+            term: 3,
+            command: Some(vec![0x06]),
+            membership: None,
+        }]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            }],
+        );
+        Ok(())
+    }
+
+    #[test]
+    // InstallSnapshot for already committed entries is ignored, but accepted
+    fn step_installsnapshot_committed() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let node = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                snapshot: Snapshot { index: 2, term: 1, data: vec![0xaa] },
+                membership: Membership::default(),
+            },
+        })?;
+        assert_node(&node).is_follower().term(3).committed(2).last(3);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 2 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // ReplicateEntries appends entries but does not commit them
     fn step_replicateentries_append() -> Result<()> {
@@ -561,17 +919,55 @@ pub mod tests {
                 base_index: 3,
                 base_term: 2,
                 entries: vec![
-                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
-                    Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                    Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                ],
+            },
+        })?;
+        assert_node(&node).is_follower().term(3).entries(vec![
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+            Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+        ]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // Witnesses store replicated entries without their commands.
+    fn step_replicateentries_witness() -> Result<()> {
+        let (mut follower, mut node_rx, mut state_rx) = setup()?;
+        follower.options.witness = true;
+        let node = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ReplicateEntries {
+                base_index: 3,
+                base_term: 2,
+                entries: vec![
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                    Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
-            Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: None, membership: None },
+            Entry { index: 5, term: 3, command: None, membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -598,17 +994,17 @@ pub mod tests {
                 base_index: 1,
                 base_term: 1,
                 entries: vec![
-                    Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-                    Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                    Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                    Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -635,16 +1031,16 @@ pub mod tests {
                 base_index: 2,
                 base_term: 1,
                 entries: vec![
-                    Entry { index: 3, term: 3, command: Some(vec![0x04]) },
-                    Entry { index: 4, term: 3, command: Some(vec![0x05]) },
+                    Entry { index: 3, term: 3, command: Some(vec![0x04]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x05]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 3, command: Some(vec![0x04]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x05]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 3, command: Some(vec![0x04]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x05]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -671,16 +1067,16 @@ pub mod tests {
                 base_index: 2,
                 base_term: 1,
                 entries: vec![
-                    Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                    Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -706,13 +1102,18 @@ pub mod tests {
             event: Event::ReplicateEntries {
                 base_index: 5,
                 base_term: 2,
-                entries: vec![Entry { index: 6, term: 3, command: Some(vec![0x04]) }],
+                entries: vec![Entry {
+                    index: 6,
+                    term: 3,
+                    command: Some(vec![0x04]),
+                    membership: None,
+                }],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -738,13 +1139,18 @@ pub mod tests {
             event: Event::ReplicateEntries {
                 base_index: 1,
                 base_term: 2,
-                entries: vec![Entry { index: 2, term: 3, command: Some(vec![0x04]) }],
+                entries: vec![Entry {
+                    index: 2,
+                    term: 3,
+                    command: Some(vec![0x04]),
+                    membership: None,
+                }],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -759,6 +1165,31 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // ClientRequest proxied by a peer is aborted rather than proxied on, since the peer wrongly
+    // believes we're the leader. The peer isn't taken as the leader either, even in a new term.
+    fn step_clientrequest_from_peer() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).proxied(vec![]).queued(vec![]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("c".into()),
+                term: 3,
+                event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // ClientRequest is proxied, as is the response.
     fn step_clientrequest_clientresponse() -> Result<()> {
@@ -821,7 +1252,7 @@ pub mod tests {
     // ClientRequest is queued when there is no leader, and forwarded when a leader appears.
     fn step_clientrequest_queued() -> Result<()> {
         let (mut follower, mut node_rx, mut state_rx) = setup()?;
-        follower.role = Follower::new(None, None);
+        follower.role = Follower::new(None, None, ELECTION_TIMEOUT_MIN);
         let mut node = Node::Follower(follower);
 
         node = node.step(Message {
@@ -842,7 +1273,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node)
             .is_follower()
@@ -866,14 +1297,19 @@ pub mod tests {
                     from: Address::Local,
                     to: Address::Peer("c".into()),
                     term: 3,
-                    event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 3,
+                        has_committed: true,
+                        clock: 0,
+                        applied_index: 0,
+                    },
                 },
             ],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -916,7 +1352,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(4).leader(Some("c")).proxied(vec![]).queued(vec![]);
         assert_messages(
@@ -932,14 +1368,19 @@ pub mod tests {
                     from: Address::Local,
                     to: Address::Peer("c".into()),
                     term: 4,
-                    event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 3,
+                        has_committed: true,
+                        clock: 0,
+                        applied_index: 0,
+                    },
                 },
             ],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -960,7 +1401,7 @@ pub mod tests {
                 from: Address::Peer("b".into()),
                 to: Address::Peer("a".into()),
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
             })?;
             assert_messages(
                 &mut node_rx,
@@ -968,7 +1409,12 @@ pub mod tests {
                     from: Address::Local,
                     to: Address::Peer("b".into()),
                     term: 3,
-                    event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        clock: 0,
+                        applied_index: 0,
+                    },
                 }],
             )
         }
@@ -977,7 +1423,7 @@ pub mod tests {
             assert_node(&node).is_follower().term(3).leader(Some("b"));
             node = node.tick()?;
         }
-        assert_node(&node).is_candidate().term(4);
+        assert_node(&node).is_candidate().term(3);
 
         assert_messages(
             &mut node_rx,
@@ -985,10 +1431,161 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 4,
-                event: Event::SolicitVote { last_index: 3, last_term: 2 },
+                event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
         Ok(())
     }
+
+    #[test]
+    // Delayed heartbeats extend the election timeout when adaptive timeouts are enabled.
+    fn tick_adaptive_timeout() -> Result<()> {
+        for adaptive in vec![true, false] {
+            let (mut follower, _node_rx, _state_rx) = setup()?;
+            follower.options.adaptive_timeouts = adaptive;
+            let mut node = Node::Follower(follower);
+
+            // Heartbeats arriving every 3 ticks exceed the interval by 2 ticks, beyond the 1 tick
+            // of jitter allowance, doubling the timeout.
+            for _ in 0..3 {
+                for _ in 0..3 {
+                    node = node.tick()?;
+                }
+                node = node.step(Message {
+                    from: Address::Peer("b".into()),
+                    to: Address::Peer("a".into()),
+                    term: 3,
+                    event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
+                })?;
+            }
+            assert_node(&node).is_follower().term(3).leader(Some("b"));
+            let timeout = match &node {
+                Node::Follower(n) => n.role.leader_seen_timeout,
+                _ => panic!("Expected follower"),
+            };
+            let options = Options::default();
+            if adaptive {
+                assert!(timeout >= 2 * options.election_timeout_min);
+                assert!(timeout < 2 * options.election_timeout_max);
+            } else {
+                assert_eq!(timeout, ELECTION_TIMEOUT_MIN);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    // SolicitVote for a leadership transfer is granted even though the leader is live.
+    fn step_solicitvote_transfer() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: true },
+        })?;
+        assert_node(&node).is_follower().term(4).voted_for(Some("c"));
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("c".into()),
+                term: 4,
+                event: Event::GrantVote,
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // TimeoutNow from the leader starts an election right away, skipping the pre-vote. It's
+    // ignored from other nodes.
+    fn step_timeoutnow() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::TimeoutNow,
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b"));
+        assert_messages(&mut node_rx, vec![]);
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::TimeoutNow,
+        })?;
+        assert_node(&node).is_candidate().term(4).elections(1);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 4,
+                event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: true },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // Membership changes are applied when committed. Draining nodes don't campaign, and ignore
+    // TimeoutNow.
+    fn step_heartbeat_membership() -> Result<()> {
+        let (mut follower, mut node_rx, mut state_rx) = setup()?;
+        follower.log.append_membership(3, MembershipChange::Drain("a".into()))?;
+        follower.log.append_membership(3, MembershipChange::Remove("e".into()))?;
+        let mut node = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, clock: 0 },
+        })?;
+        assert_node(&node).is_follower().committed(5).peers(vec!["b", "c", "d"]);
+        while node_rx.try_recv().is_ok() {}
+        while state_rx.try_recv().is_ok() {}
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::TimeoutNow,
+        })?;
+        for _ in 0..(3 * ELECTION_TIMEOUT_MIN) {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_follower().term(3).leader(Some("b"));
+        assert_messages(&mut node_rx, vec![]);
+
+        // Messages from the removed node are ignored.
+        node = node.step(Message {
+            from: Address::Peer("e".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitVote { last_index: 5, last_term: 3, transfer: true },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b"));
+        assert_messages(&mut node_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // Witnesses never campaign, even when they haven't heard from the leader.
+    fn tick_witness() -> Result<()> {
+        let (mut follower, mut node_rx, mut state_rx) = setup()?;
+        follower.options.witness = true;
+        let mut node = Node::Follower(follower);
+        for _ in 0..(3 * ELECTION_TIMEOUT_MIN) {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_follower().term(3).leader(Some("b"));
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
 }
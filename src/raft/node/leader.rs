@@ -1,9 +1,13 @@
-use super::super::{Address, Event, Instruction, Message, Request, Response, Status};
-use super::{Follower, Node, RoleNode, HEARTBEAT_INTERVAL};
+use super::super::server::TICK;
+use super::super::{
+    Address, Event, Instruction, MembershipChange, Message, NodeStatus, Request, Response, Status,
+};
+use super::{Follower, Health, Node, RoleNode};
 use crate::error::{Error, Result};
 
 use ::log::{debug, info, warn};
-use std::collections::HashMap;
+use rand::seq::IteratorRandom as _;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 // A leader serves requests and replicates the log to followers.
 #[derive(Debug)]
@@ -14,6 +18,20 @@ pub struct Leader {
     peer_next_index: HashMap<String, u64>,
     /// The last index known to be replicated on a peer.
     peer_last_index: HashMap<String, u64>,
+    /// Logical clock, as ticks since becoming leader.
+    clock: u64,
+    /// The clock of the last heartbeat confirmed by a peer, used for leader leases.
+    peer_clock: HashMap<String, u64>,
+    /// Peers we've heard from since the last quorum check.
+    peer_active: HashSet<String>,
+    /// The clock when we last heard from a peer, for cluster status.
+    peer_seen: HashMap<String, u64>,
+    /// The state machine applied index last reported by a peer, for cluster status.
+    peer_applied_index: HashMap<String, u64>,
+    /// The clock when a lagging peer was last caught up, for cluster status.
+    peer_behind_since: HashMap<String, u64>,
+    /// Number of ticks since the last quorum check.
+    check_quorum_ticks: u64,
 }
 
 impl Leader {
@@ -23,6 +41,13 @@ impl Leader {
             heartbeat_ticks: 0,
             peer_next_index: HashMap::new(),
             peer_last_index: HashMap::new(),
+            clock: 0,
+            peer_clock: HashMap::new(),
+            peer_active: HashSet::new(),
+            peer_seen: HashMap::new(),
+            peer_applied_index: HashMap::new(),
+            peer_behind_since: HashMap::new(),
+            check_quorum_ticks: 0,
         };
         for peer in peers {
             leader.peer_next_index.insert(peer.clone(), last_index + 1);
@@ -33,24 +58,204 @@ impl Leader {
 }
 
 impl RoleNode<Leader> {
+    /// Returns the local node health.
+    pub fn health(&self) -> Health {
+        self.health_with("leader", Some(self.id.clone()), Some(0))
+    }
+
     /// Transforms the leader into a follower
     fn become_follower(mut self, term: u64, leader: &str) -> Result<RoleNode<Follower>> {
         info!("Discovered new leader {} for term {}, following", leader, term);
         self.term = term;
         self.log.save_term(term, None)?;
         self.state_tx.send(Instruction::Abort)?;
-        self.become_role(Follower::new(Some(leader), None))
+        let timeout = self.election_timeout();
+        self.become_role(Follower::new(Some(leader), None, timeout))
+    }
+
+    /// Steps down to a follower in the same term, e.g. when we can't reach a quorum.
+    fn step_down(mut self) -> Result<RoleNode<Follower>> {
+        self.state_tx.send(Instruction::Abort)?;
+        let timeout = self.election_timeout();
+        self.become_role(Follower::new(None, None, timeout))
+    }
+
+    /// Checks that we've heard from a quorum of peers since the last check. If we haven't, we're
+    /// likely partitioned from the majority of the cluster, which will elect a new leader.
+    fn check_quorum(&mut self) -> bool {
+        let active = self.role.peer_active.len() as u64 + 1;
+        self.role.peer_active.clear();
+        active >= self.quorum()
     }
 
     /// Appends an entry to the log and replicates it to peers.
     pub fn append(&mut self, command: Option<Vec<u8>>) -> Result<u64> {
         let entry = self.log.append(self.term, command)?;
-        for peer in self.peers.iter() {
-            self.replicate(peer)?;
+        for peer in self.peers.clone() {
+            self.replicate(&peer)?;
+        }
+        Ok(entry.index)
+    }
+
+    /// Appends a membership change to the log and replicates it to peers. Errors if the change
+    /// is invalid, or another change is still in progress.
+    fn append_membership(&mut self, change: MembershipChange) -> Result<u64> {
+        let id = match &change {
+            MembershipChange::Drain(id) | MembershipChange::Remove(id) => id,
+        };
+        if id != &self.id && !self.peers.contains(id) {
+            return Err(Error::Value(format!("Unknown node {}", id)));
+        }
+        if let MembershipChange::Remove(_) = change {
+            if self.peers.is_empty() {
+                return Err(Error::Value("Can't remove the last node in the cluster".into()));
+            }
+        }
+        // A new leader must commit an entry in its term before changing the membership, in case
+        // an uncommitted change from a previous term is still in flight.
+        if self.log.commit_term != self.term {
+            return Err(Error::Abort);
+        }
+        for entry in self.log.scan((self.log.commit_index + 1)..) {
+            if entry?.membership.is_some() {
+                return Err(Error::Value("A membership change is already in progress".into()));
+            }
+        }
+        info!("Changing cluster membership: {:?}", change);
+        let entry = self.log.append_membership(self.term, change)?;
+        for peer in self.peers.clone() {
+            self.replicate(&peer)?;
         }
         Ok(entry.index)
     }
 
+    /// Drops any removed peers from the replication progress, after committing a membership
+    /// change.
+    fn sync_peers(&mut self) {
+        self.sync_membership();
+        let peers = &self.peers;
+        self.role.peer_next_index.retain(|p, _| peers.contains(p));
+        self.role.peer_last_index.retain(|p, _| peers.contains(p));
+        self.role.peer_clock.retain(|p, _| peers.contains(p));
+        self.role.peer_active.retain(|p| peers.contains(p));
+        self.role.peer_seen.retain(|p, _| peers.contains(p));
+        self.role.peer_applied_index.retain(|p, _| peers.contains(p));
+        self.role.peer_behind_since.retain(|p, _| peers.contains(p));
+    }
+
+    /// Records when peers fell behind the leader's log, for cluster status.
+    fn track_lag(&mut self) {
+        for peer in &self.peers {
+            let last_index = self.role.peer_last_index.get(peer).copied().unwrap_or(0);
+            if last_index < self.log.last_index {
+                self.role.peer_behind_since.entry(peer.clone()).or_insert(self.role.clock);
+            } else {
+                self.role.peer_behind_since.remove(peer);
+            }
+        }
+    }
+
+    /// Generates the replication status of each node in the cluster.
+    fn node_status(&self) -> BTreeMap<String, NodeStatus> {
+        let tick = TICK.as_millis() as u64;
+        let clock = self.role.clock;
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            self.id.clone(),
+            NodeStatus {
+                role: "leader".into(),
+                last_index: self.log.last_index,
+                apply_index: self.applied_index,
+                last_seen: Some(0),
+                lag_entries: 0,
+                lag_millis: 0,
+            },
+        );
+        for peer in &self.peers {
+            let last_index = self.role.peer_last_index.get(peer).copied().unwrap_or(0);
+            let lag_entries = self.log.last_index.saturating_sub(last_index);
+            nodes.insert(
+                peer.clone(),
+                NodeStatus {
+                    role: "follower".into(),
+                    last_index,
+                    apply_index: self.role.peer_applied_index.get(peer).copied().unwrap_or(0),
+                    last_seen: self.role.peer_seen.get(peer).map(|seen| (clock - seen) * tick),
+                    lag_entries,
+                    lag_millis: match (lag_entries, self.role.peer_behind_since.get(peer)) {
+                        (0, _) | (_, None) => 0,
+                        (_, Some(since)) => (clock - since) * tick,
+                    },
+                },
+            );
+        }
+        nodes
+    }
+
+    /// Transfers leadership to a random up-to-date peer that isn't draining, by telling it to
+    /// start an election immediately. If no peer is up-to-date, the most recent one is caught up.
+    pub(super) fn transfer_leadership(&mut self) -> Result<()> {
+        let draining = &self.log.membership.draining;
+        let mut candidates: Vec<(String, u64)> = self
+            .role
+            .peer_last_index
+            .iter()
+            .filter(|(p, _)| !draining.contains(*p))
+            .map(|(p, i)| (p.clone(), *i))
+            .collect();
+        // Sort the candidates, such that the choice only depends on the random number generator.
+        candidates.sort();
+        let last_index = self.log.last_index;
+        let target = candidates.iter().filter(|(_, i)| *i == last_index).choose(&mut self.rng);
+        if let Some((peer, _)) = target {
+            info!("Transferring leadership to {}", peer);
+            self.send(Address::Peer(peer.clone()), Event::TimeoutNow)?;
+        } else if let Some((peer, _)) = candidates.iter().max_by_key(|(_, i)| *i) {
+            self.replicate(peer)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a heartbeat to all peers.
+    pub fn heartbeat(&self) -> Result<()> {
+        self.send(
+            Address::Peers,
+            Event::Heartbeat {
+                commit_index: self.log.commit_index,
+                commit_term: self.log.commit_term,
+                clock: self.role.clock,
+            },
+        )
+    }
+
+    /// Checks whether we hold a valid leader lease, and can thus serve reads without confirming
+    /// our leadership with a quorum. A lease is held until the lease duration has elapsed since
+    /// the send time of the latest heartbeat confirmed by a quorum, since these peers won't vote
+    /// for another leader until they haven't heard from us for an election timeout. We must also
+    /// have committed an entry in our term, to know that our commit index is current. Draining
+    /// leaders don't hold a lease, since they're handing off leadership to a peer which may be
+    /// elected before the lease expires.
+    fn has_lease(&self) -> bool {
+        let duration = match self.lease_skew {
+            Some(skew) => self.options.election_timeout_min - skew,
+            None => return false,
+        };
+        if self.is_draining() {
+            return false;
+        }
+        if self.log.commit_term != self.term {
+            return false;
+        }
+        let mut clocks = vec![self.role.clock];
+        clocks.extend(self.role.peer_clock.values());
+        clocks.sort();
+        clocks.reverse();
+        match clocks.get(self.quorum() as usize - 1) {
+            Some(clock) => self.role.clock < clock + duration,
+            None => false,
+        }
+    }
+
     /// Commits any pending log entries.
     fn commit(&mut self) -> Result<u64> {
         let mut last_indexes = vec![self.log.last_index];
@@ -65,6 +270,7 @@ impl RoleNode<Leader> {
                 if entry.term == self.term {
                     let old_commit_index = self.log.commit_index;
                     self.log.commit(quorum_index)?;
+                    self.sync_peers();
                     let mut scan = self.log.scan((old_commit_index + 1)..=self.log.commit_index);
                     while let Some(entry) = scan.next().transpose()? {
                         self.state_tx.send(Instruction::Apply { entry })?;
@@ -75,21 +281,42 @@ impl RoleNode<Leader> {
         Ok(self.log.commit_index)
     }
 
-    /// Replicates the log to a peer.
-    fn replicate(&self, peer: &str) -> Result<()> {
+    /// Replicates the log to a peer. If the peer needs entries that have been compacted, the
+    /// snapshot is sent instead.
+    fn replicate(&mut self, peer: &str) -> Result<()> {
         let peer_next = self
             .role
             .peer_next_index
             .get(peer)
             .cloned()
             .ok_or_else(|| Error::Internal(format!("Unknown peer {}", peer)))?;
+        if peer_next <= self.log.snapshot_index {
+            let snapshot = self
+                .log
+                .snapshot()?
+                .ok_or_else(|| Error::Internal("Log snapshot not found".into()))?;
+            debug!("Sending snapshot at index {} to {}", snapshot.index, peer);
+            // Assume the peer installs the snapshot, to avoid resending it on every append. If it
+            // doesn't, it will reject the next entries and we'll fall back to the snapshot again.
+            self.role.peer_next_index.insert(peer.to_string(), snapshot.index + 1);
+            let membership = self.log.membership.clone();
+            self.send(
+                Address::Peer(peer.to_string()),
+                Event::InstallSnapshot { snapshot, membership },
+            )?;
+            return Ok(());
+        }
         let base_index = if peer_next > 0 { peer_next - 1 } else { 0 };
         let base_term = match self.log.get(base_index)? {
             Some(base) => base.term,
             None if base_index == 0 => 0,
+            None if base_index == self.log.snapshot_index => self.log.snapshot_term,
             None => return Err(Error::Internal(format!("Missing base entry {}", base_index))),
         };
-        let entries = self.log.scan(peer_next..).collect::<Result<Vec<_>>>()?;
+        let entries = match self.options.max_inflight {
+            0 => self.log.scan(peer_next..).collect::<Result<Vec<_>>>()?,
+            max => self.log.scan(peer_next..).take(max as usize).collect::<Result<Vec<_>>>()?,
+        };
         debug!("Replicating {} entries at base {} to {}", entries.len(), base_index, peer);
         self.send(
             Address::Peer(peer.to_string()),
@@ -100,25 +327,46 @@ impl RoleNode<Leader> {
 
     /// Processes a message.
     pub fn step(mut self, msg: Message) -> Result<Node> {
+        self.reject_stale_leader(&msg)?;
         if let Err(err) = self.validate(&msg) {
             warn!("Ignoring invalid message: {}", err);
             return Ok(self.into());
         }
-        if msg.term > self.term {
+        // Pre-vote messages use the prospective term of the election, which we don't adopt. Client
+        // requests and responses may be proxied by any peer, which mustn't be taken as the leader.
+        if msg.term > self.term
+            && !matches!(
+                msg.event,
+                Event::SolicitPreVote { .. }
+                    | Event::GrantPreVote
+                    | Event::ClientRequest { .. }
+                    | Event::ClientResponse { .. }
+            )
+        {
             if let Address::Peer(from) = &msg.from {
                 return self.become_follower(msg.term, from)?.step(msg);
             }
         }
+        if let Address::Peer(from) = &msg.from {
+            self.role.peer_active.insert(from.clone());
+            self.role.peer_seen.insert(from.clone(), self.role.clock);
+        }
 
         match msg.event {
-            Event::ConfirmLeader { commit_index, has_committed } => {
+            Event::ConfirmLeader { commit_index, has_committed, clock, applied_index } => {
                 if let Address::Peer(from) = msg.from.clone() {
+                    self.role.peer_applied_index.insert(from.clone(), applied_index);
+                    let peer_clock = self.role.peer_clock.entry(from.clone()).or_insert(0);
+                    *peer_clock = std::cmp::max(*peer_clock, clock);
                     self.state_tx.send(Instruction::Vote {
                         term: msg.term,
                         index: commit_index,
                         address: msg.from,
                     })?;
-                    if !has_committed {
+                    // Also resend any entries the peer hasn't acknowledged, in case the entries or
+                    // their acknowledgement were lost and no further appends would trigger it.
+                    let peer_next_index = self.role.peer_next_index.get(&from).cloned();
+                    if !has_committed || peer_next_index.unwrap_or(0) <= self.log.last_index {
                         self.replicate(&from)?;
                     }
                 }
@@ -127,7 +375,11 @@ impl RoleNode<Leader> {
             Event::AcceptEntries { last_index } => {
                 if let Address::Peer(from) = msg.from {
                     self.role.peer_last_index.insert(from.clone(), last_index);
-                    self.role.peer_next_index.insert(from, last_index + 1);
+                    self.role.peer_next_index.insert(from.clone(), last_index + 1);
+                    // If the number of in-flight entries is limited, send any remaining entries.
+                    if self.options.max_inflight > 0 && last_index < self.log.last_index {
+                        self.replicate(&from)?;
+                    }
                 }
                 self.commit()?;
             }
@@ -143,6 +395,24 @@ impl RoleNode<Leader> {
                 }
             }
 
+            // With a valid lease, we know we're still the leader and can execute the query as
+            // soon as the commit index has been applied, without a quorum round-trip.
+            Event::ClientRequest { id, request: Request::Query(command) } if self.has_lease() => {
+                self.state_tx.send(Instruction::Query {
+                    id,
+                    address: msg.from,
+                    command,
+                    term: self.term,
+                    index: self.log.commit_index,
+                    quorum: 1,
+                })?;
+                self.state_tx.send(Instruction::Vote {
+                    term: self.term,
+                    index: self.log.commit_index,
+                    address: Address::Local,
+                })?;
+            }
+
             Event::ClientRequest { id, request: Request::Query(command) } => {
                 self.state_tx.send(Instruction::Query {
                     id,
@@ -158,13 +428,7 @@ impl RoleNode<Leader> {
                     address: Address::Local,
                 })?;
                 if !self.peers.is_empty() {
-                    self.send(
-                        Address::Peers,
-                        Event::Heartbeat {
-                            commit_index: self.log.commit_index,
-                            commit_term: self.log.commit_term,
-                        },
-                    )?;
+                    self.heartbeat()?;
                 }
             }
 
@@ -176,53 +440,97 @@ impl RoleNode<Leader> {
                 }
             }
 
+            Event::ClientRequest { id, request: Request::ChangeMembership(change) } => {
+                match self.append_membership(change) {
+                    Ok(index) => {
+                        self.state_tx.send(Instruction::Notify { id, address: msg.from, index })?;
+                        if self.peers.is_empty() {
+                            self.commit()?;
+                        }
+                    }
+                    Err(err) => {
+                        self.send(msg.from, Event::ClientResponse { id, response: Err(err) })?
+                    }
+                }
+            }
+
             Event::ClientRequest { id, request: Request::Status } => {
                 let mut status = Box::new(Status {
-                    server: self.id.clone(),
+                    server: String::new(),
+                    role: String::new(),
+                    elections: 0,
                     leader: self.id.clone(),
                     term: self.term,
                     node_last_index: self.role.peer_last_index.clone(),
+                    node_next_index: self.role.peer_next_index.clone(),
                     commit_index: self.log.commit_index,
                     apply_index: 0,
                     storage: self.log.store.to_string(),
                     storage_size: self.log.store.size(),
+                    draining: self.log.membership.draining.clone(),
+                    nodes: self.node_status(),
                 });
+                self.set_status(&mut status, "leader");
                 status.node_last_index.insert(self.id.clone(), self.log.last_index);
+                status.node_next_index.insert(self.id.clone(), self.log.last_index + 1);
                 self.state_tx.send(Instruction::Status { id, address: msg.from, status })?
             }
 
             Event::ClientResponse { id, mut response } => {
                 if let Ok(Response::Status(ref mut status)) = response {
-                    status.server = self.id.clone();
+                    self.set_status(status, "leader");
                 }
                 self.send(Address::Client, Event::ClientResponse { id, response })?;
             }
 
             // We ignore these messages, since they are typically additional votes from the previous
             // election that we won after a quorum.
-            Event::SolicitVote { .. } | Event::GrantVote => {}
+            Event::SolicitVote { .. } | Event::GrantVote | Event::GrantPreVote => {}
 
-            Event::Heartbeat { .. } | Event::ReplicateEntries { .. } => {
+            // We never grant pre-votes while we're the leader, since the cluster is live.
+            Event::SolicitPreVote { .. } => {}
+
+            Event::Heartbeat { .. }
+            | Event::ReplicateEntries { .. }
+            | Event::InstallSnapshot { .. }
+            | Event::TimeoutNow
+            | Event::CompactLog { .. }
+            | Event::Applied { .. }
+            | Event::ClientRequest { request: Request::Health, .. }
+            | Event::ClientRequest { request: Request::Flush, .. }
+            | Event::ClientRequest { request: Request::TransferLeadership, .. }
+            | Event::ClientRequest { request: Request::QueryLocal { .. }, .. } => {
                 warn!("Received unexpected message {:?}", msg)
             }
         }
 
+        if self.is_removed() {
+            info!("Removed from cluster in term {}, stepping down", self.term);
+            return Ok(self.step_down()?.into());
+        }
         Ok(self.into())
     }
 
     /// Processes a logical clock tick.
     pub fn tick(mut self) -> Result<Node> {
+        self.role.clock += 1;
+        self.track_lag();
+        self.role.check_quorum_ticks += 1;
+        if self.role.check_quorum_ticks >= self.options.election_timeout_min {
+            self.role.check_quorum_ticks = 0;
+            if !self.check_quorum() {
+                info!("Lost contact with quorum in term {}, stepping down", self.term);
+                return Ok(self.step_down()?.into());
+            }
+        }
         if !self.peers.is_empty() {
             self.role.heartbeat_ticks += 1;
-            if self.role.heartbeat_ticks >= HEARTBEAT_INTERVAL {
+            if self.role.heartbeat_ticks >= self.options.heartbeat_interval {
                 self.role.heartbeat_ticks = 0;
-                self.send(
-                    Address::Peers,
-                    Event::Heartbeat {
-                        commit_index: self.log.commit_index,
-                        commit_term: self.log.commit_term,
-                    },
-                )?;
+                self.heartbeat()?;
+                if self.is_draining() {
+                    self.transfer_leadership()?;
+                }
             }
         }
         Ok(self.into())
@@ -231,11 +539,13 @@ impl RoleNode<Leader> {
 
 #[cfg(test)]
 mod tests {
-    use super::super::super::{Entry, Log};
+    use super::super::super::{Entry, Log, Membership, Snapshot};
     use super::super::tests::{assert_messages, assert_node};
+    use super::super::{Options, ELECTION_TIMEOUT_MAX, ELECTION_TIMEOUT_MIN, HEARTBEAT_INTERVAL};
     use super::*;
     use crate::storage::log;
     use pretty_assertions::assert_eq;
+    use std::collections::BTreeSet;
     use tokio::sync::mpsc;
 
     #[allow(clippy::type_complexity)]
@@ -260,6 +570,12 @@ mod tests {
             id: "a".into(),
             peers: peers.clone(),
             term: 3,
+            lease_skew: None,
+            elections: 0,
+            heartbeat_gap: HEARTBEAT_INTERVAL,
+            applied_index: 0,
+            options: Options::default(),
+            rng: rand::SeedableRng::from_entropy(),
             role: Leader::new(peers, log.last_index),
             log,
             node_tx,
@@ -280,7 +596,12 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+            event: Event::ConfirmLeader {
+                commit_index: 2,
+                has_committed: true,
+                clock: 0,
+                applied_index: 0,
+            },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -301,7 +622,12 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::ConfirmLeader { commit_index: 2, has_committed: false },
+            event: Event::ConfirmLeader {
+                commit_index: 2,
+                has_committed: false,
+                clock: 0,
+                applied_index: 0,
+            },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(
@@ -320,6 +646,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // ConfirmLeader from a peer that hasn't acknowledged all entries resends them, in case the
+    // entries or acknowledgements were lost.
+    fn step_confirmleader_unacknowledged() -> Result<()> {
+        let (mut leader, mut node_rx, mut state_rx) = setup()?;
+        leader.role.peer_next_index.insert("b".into(), 4);
+        let mut node: Node = leader.into();
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ConfirmLeader {
+                commit_index: 2,
+                has_committed: true,
+                clock: 0,
+                applied_index: 0,
+            },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::ReplicateEntries {
+                    base_index: 3,
+                    base_term: 2,
+                    entries: vec![
+                        Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                        Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                    ],
+                },
+            }],
+        );
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Vote { term: 3, index: 2, address: Address::Peer("b".into()) }],
+        );
+        Ok(())
+    }
+
     #[test]
     // Heartbeats from other leaders in current term are ignored.
     fn step_heartbeat_current_term() -> Result<()> {
@@ -330,7 +699,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 5, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, clock: 0 },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -348,7 +717,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 7, commit_term: 4 },
+            event: Event::Heartbeat { commit_index: 7, commit_term: 4, clock: 0 },
         })?;
         assert_node(&node).is_follower().term(4).leader(Some("b")).committed(2);
         assert_messages(
@@ -357,7 +726,12 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 4,
-                event: Event::ConfirmLeader { commit_index: 7, has_committed: false },
+                event: Event::ConfirmLeader {
+                    commit_index: 7,
+                    has_committed: false,
+                    clock: 0,
+                    applied_index: 0,
+                },
             }],
         );
         assert_messages(&mut state_rx, vec![Instruction::Abort]);
@@ -365,7 +739,7 @@ mod tests {
     }
 
     #[test]
-    // Heartbeats from other leaders in past terms are ignored.
+    // Heartbeats from other leaders in past terms are rejected with our term.
     fn step_heartbeat_past_term() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
         let mut node: Node = leader.into();
@@ -374,10 +748,18 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 2,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, clock: 0 },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
-        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::RejectEntries,
+            }],
+        );
         assert_messages(&mut state_rx, vec![]);
         Ok(())
     }
@@ -409,10 +791,10 @@ mod tests {
             &mut state_rx,
             vec![
                 Instruction::Apply {
-                    entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                    entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
                 },
                 Instruction::Apply {
-                    entry: Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                    entry: Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
                 },
             ],
         );
@@ -428,7 +810,7 @@ mod tests {
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                entry: Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
             }],
         );
 
@@ -436,6 +818,71 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // With limited in-flight entries, remaining entries are sent once the previous are accepted.
+    fn step_acceptentries_max_inflight() -> Result<()> {
+        let (mut leader, mut node_rx, mut state_rx) = setup()?;
+        leader.options = Options { max_inflight: 2, ..Options::default() };
+        let mut node: Node = leader.into();
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 1 },
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::ReplicateEntries {
+                    base_index: 1,
+                    base_term: 1,
+                    entries: vec![
+                        Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                        Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                    ],
+                },
+            }],
+        );
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 3 },
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::ReplicateEntries {
+                    base_index: 3,
+                    base_term: 2,
+                    entries: vec![
+                        Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                        Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                    ],
+                },
+            }],
+        );
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 5 },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // Duplicate AcceptEntries from single node should not trigger commit.
     fn step_acceptentries_duplicate() -> Result<()> {
@@ -501,13 +948,28 @@ mod tests {
                     &mut state_rx,
                     vec![
                         Instruction::Apply {
-                            entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                            entry: Entry {
+                                index: 3,
+                                term: 2,
+                                command: Some(vec![0x03]),
+                                membership: None,
+                            },
                         },
                         Instruction::Apply {
-                            entry: Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                            entry: Entry {
+                                index: 4,
+                                term: 3,
+                                command: Some(vec![0x04]),
+                                membership: None,
+                            },
                         },
                         Instruction::Apply {
-                            entry: Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                            entry: Entry {
+                                index: 5,
+                                term: 3,
+                                command: Some(vec![0x05]),
+                                membership: None,
+                            },
                         },
                     ],
                 );
@@ -556,6 +1018,144 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // RejectEntries for compacted entries sends the snapshot, and then replicates later entries
+    fn step_rejectentries_snapshot() -> Result<()> {
+        let (mut leader, mut node_rx, _state_rx) = setup()?;
+        leader.log.compact(2, vec![0xaa])?;
+        let mut node: Node = leader.into();
+
+        for _ in 0..3 {
+            node = node.step(Message {
+                from: Address::Peer("b".into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::RejectEntries,
+            })?;
+        }
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index: 4,
+                        base_term: 3,
+                        entries: vec![Entry {
+                            index: 5,
+                            term: 3,
+                            command: Some(vec![0x05]),
+                            membership: None,
+                        }],
+                    },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index: 3,
+                        base_term: 2,
+                        entries: vec![
+                            Entry {
+                                index: 4,
+                                term: 3,
+                                command: Some(vec![0x04]),
+                                membership: None,
+                            },
+                            Entry {
+                                index: 5,
+                                term: 3,
+                                command: Some(vec![0x05]),
+                                membership: None,
+                            },
+                        ],
+                    },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index: 2,
+                        base_term: 1,
+                        entries: vec![
+                            Entry {
+                                index: 3,
+                                term: 2,
+                                command: Some(vec![0x03]),
+                                membership: None,
+                            },
+                            Entry {
+                                index: 4,
+                                term: 3,
+                                command: Some(vec![0x04]),
+                                membership: None,
+                            },
+                            Entry {
+                                index: 5,
+                                term: 3,
+                                command: Some(vec![0x05]),
+                                membership: None,
+                            },
+                        ],
+                    },
+                },
+            ],
+        );
+
+        // The next rejection requires compacted entries, so the snapshot is sent instead.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::RejectEntries,
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::InstallSnapshot {
+                    snapshot: Snapshot { index: 2, term: 1, data: vec![0xaa] },
+                    membership: Membership::default(),
+                },
+            }],
+        );
+
+        // Once the peer accepts the snapshot, later entries are replicated as normal.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 2 },
+        })?;
+        node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0x06]) },
+        })?;
+        let msgs: Vec<Message> = std::iter::from_fn(|| node_rx.try_recv().ok()).collect();
+        assert_eq!(
+            Some(&Event::ReplicateEntries {
+                base_index: 2,
+                base_term: 1,
+                entries: vec![
+                    Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                    Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                    Entry { index: 6, term: 3, command: Some(vec![0x06]), membership: None },
+                ],
+            }),
+            msgs.iter().find(|m| m.to == Address::Peer("b".into())).map(|m| &m.event)
+        );
+        Ok(())
+    }
+
     #[test]
     // Sending a client query request will pass it to the state machine and trigger heartbeats.
     fn step_clientrequest_query() -> Result<()> {
@@ -575,7 +1175,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, clock: 0 },
             }],
         );
         assert_messages(
@@ -595,6 +1195,96 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // With a valid leader lease, queries are executed without a quorum heartbeat. The lease
+    // requires a committed entry in the current term and a quorum of heartbeat confirmations.
+    fn step_clientrequest_query_lease() -> Result<()> {
+        let (mut leader, mut node_rx, mut state_rx) = setup()?;
+        leader.lease_skew = Some(2);
+        leader.log.commit(4)?;
+        let quorum = leader.quorum();
+        let mut node: Node = leader.into();
+
+        let query = |node: Node, id: u8| {
+            node.step(Message {
+                from: Address::Client,
+                to: Address::Local,
+                term: 0,
+                event: Event::ClientRequest { id: vec![id], request: Request::Query(vec![0xaf]) },
+            })
+        };
+        let readindex = |id: u8| {
+            vec![
+                Instruction::Query {
+                    id: vec![id],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 3,
+                    index: 4,
+                    quorum,
+                },
+                Instruction::Vote { term: 3, index: 4, address: Address::Local },
+            ]
+        };
+
+        // Without heartbeat confirmations, we don't have a lease.
+        node = query(node, 0x01)?;
+        assert_eq!(
+            node_rx.try_recv()?.event,
+            Event::Heartbeat { commit_index: 4, commit_term: 3, clock: 0 }
+        );
+        assert_messages(&mut state_rx, readindex(0x01));
+
+        // Once a quorum has confirmed a heartbeat, we do.
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::ConfirmLeader {
+                    commit_index: 4,
+                    has_committed: true,
+                    clock: 0,
+                    applied_index: 0,
+                },
+            })?;
+        }
+        assert_messages(
+            &mut state_rx,
+            vec![
+                Instruction::Vote { term: 3, index: 4, address: Address::Peer("b".into()) },
+                Instruction::Vote { term: 3, index: 4, address: Address::Peer("c".into()) },
+            ],
+        );
+        node = query(node, 0x02)?;
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![
+                Instruction::Query {
+                    id: vec![0x02],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 3,
+                    index: 4,
+                    quorum: 1,
+                },
+                Instruction::Vote { term: 3, index: 4, address: Address::Local },
+            ],
+        );
+
+        // The lease expires when its duration has elapsed since the confirmed heartbeat.
+        for _ in 0..6 {
+            node = node.tick()?;
+        }
+        while node_rx.try_recv().is_ok() {}
+        node = query(node, 0x03)?;
+        assert_node(&node).is_leader().term(3).committed(4);
+        assert!(node_rx.try_recv().is_ok());
+        assert_messages(&mut state_rx, readindex(0x03));
+        Ok(())
+    }
+
     #[test]
     // Sending a mutate request should append it to log, replicate it to peers, and register notification.
     fn step_clientrequest_mutate() -> Result<()> {
@@ -612,6 +1302,7 @@ mod tests {
             index: 6,
             term: 3,
             command: Some(vec![0xaf]),
+            membership: None,
         });
         for peer in peers.iter().cloned() {
             assert_eq!(
@@ -623,7 +1314,12 @@ mod tests {
                     event: Event::ReplicateEntries {
                         base_index: 5,
                         base_term: 3,
-                        entries: vec![Entry { index: 6, term: 3, command: Some(vec![0xaf]) },]
+                        entries: vec![Entry {
+                            index: 6,
+                            term: 3,
+                            command: Some(vec![0xaf]),
+                            membership: None
+                        },]
                     },
                 }
             )
@@ -637,6 +1333,286 @@ mod tests {
         Ok(())
     }
 
+    /// Commits the leader's log through index 5, which is in its own term, via b and c.
+    fn commit_term(mut node: Node) -> Result<Node> {
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            })?;
+        }
+        assert_node(&node).is_leader().committed(5);
+        Ok(node)
+    }
+
+    /// Requests a membership change from the leader.
+    fn change_membership(node: Node, change: MembershipChange) -> Result<Node> {
+        node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![0x01],
+                request: Request::ChangeMembership(change),
+            },
+        })
+    }
+
+    #[test]
+    // Membership change requests are appended to the log and replicated like mutations, and
+    // applied once committed. Messages from removed nodes are then ignored.
+    fn step_clientrequest_changemembership() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        // Changes are rejected until we've committed an entry in our term.
+        node = change_membership(node, MembershipChange::Remove("e".into()))?;
+        assert_node(&node).is_leader().last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Client,
+                term: 3,
+                event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+            }],
+        );
+
+        node = commit_term(node)?;
+        while state_rx.try_recv().is_ok() {}
+        node = change_membership(node, MembershipChange::Remove("e".into()))?;
+        let entry = Entry {
+            index: 6,
+            term: 3,
+            command: None,
+            membership: Some(MembershipChange::Remove("e".into())),
+        };
+        assert_node(&node).is_leader().last(6).entry(entry.clone());
+        for peer in vec!["b", "c", "d", "e"] {
+            assert_eq!(
+                node_rx.try_recv()?,
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer(peer.into()),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index: 5,
+                        base_term: 3,
+                        entries: vec![entry.clone()],
+                    },
+                }
+            )
+        }
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Notify { id: vec![0x01], address: Address::Client, index: 6 }],
+        );
+
+        // Unknown nodes and concurrent changes are rejected.
+        for (change, error) in vec![
+            (MembershipChange::Drain("x".into()), "Unknown node x"),
+            (MembershipChange::Drain("b".into()), "A membership change is already in progress"),
+        ] {
+            node = change_membership(node, change)?;
+            assert_messages(
+                &mut node_rx,
+                vec![Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 3,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Err(Error::Value(error.into())),
+                    },
+                }],
+            );
+        }
+
+        // Once committed, the node is removed.
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            })?;
+        }
+        assert_node(&node).is_leader().committed(6).peers(vec!["b", "c", "d"]);
+        assert_messages(&mut state_rx, vec![Instruction::Apply { entry }]);
+
+        node = node.step(Message {
+            from: Address::Peer("e".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ConfirmLeader {
+                commit_index: 6,
+                has_committed: true,
+                clock: 0,
+                applied_index: 0,
+            },
+        })?;
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        match node {
+            Node::Leader(n) => assert_eq!(None, n.role.peer_last_index.get("e")),
+            _ => panic!("Expected leader"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    // A leader that is removed from the cluster steps down.
+    fn step_clientrequest_changemembership_remove_leader() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node = commit_term(leader.into())?;
+        node = change_membership(node, MembershipChange::Remove("a".into()))?;
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            })?;
+        }
+        assert_node(&node).is_follower().term(3).committed(6).leader(None);
+        while node_rx.try_recv().is_ok() {}
+        while state_rx.try_recv().is_ok() {}
+
+        // It never campaigns.
+        for _ in 0..(4 * ELECTION_TIMEOUT_MAX) {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_follower().term(3);
+        assert_messages(&mut node_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // A draining leader transfers leadership to an up-to-date peer, and stops serving lease reads.
+    fn tick_draining() -> Result<()> {
+        let (mut leader, mut node_rx, mut state_rx) = setup()?;
+        leader.set_lease(2)?;
+        let mut node = commit_term(leader.into())?;
+        node = change_membership(node, MembershipChange::Drain("a".into()))?;
+        for peer in vec!["c", "d"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            })?;
+        }
+        assert_node(&node).is_leader().committed(6);
+        match &node {
+            Node::Leader(n) => assert!(!n.has_lease()),
+            _ => panic!("Expected leader"),
+        }
+        while node_rx.try_recv().is_ok() {}
+        while state_rx.try_recv().is_ok() {}
+
+        // Only c and d are up-to-date, so one of them is the transfer target.
+        for _ in 0..HEARTBEAT_INTERVAL {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_leader();
+        assert_eq!(
+            node_rx.try_recv()?,
+            Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 3,
+                event: Event::Heartbeat { commit_index: 6, commit_term: 3, clock: 1 },
+            }
+        );
+        let msg = node_rx.try_recv()?;
+        assert_eq!(msg.event, Event::TimeoutNow);
+        assert!(vec![Address::Peer("c".into()), Address::Peer("d".into())].contains(&msg.to));
+        assert_messages(&mut node_rx, vec![]);
+
+        // The leader steps down once the peer campaigns.
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peers,
+            term: 4,
+            event: Event::SolicitVote { last_index: 6, last_term: 3, transfer: true },
+        })?;
+        assert_node(&node).is_follower().term(4);
+        Ok(())
+    }
+
+    fn node_status(
+        role: &str,
+        last_index: u64,
+        apply_index: u64,
+        last_seen: Option<u64>,
+        lag_entries: u64,
+        lag_millis: u64,
+    ) -> NodeStatus {
+        NodeStatus {
+            role: role.into(),
+            last_index,
+            apply_index,
+            last_seen,
+            lag_entries,
+            lag_millis,
+        }
+    }
+
+    #[test]
+    // The leader tracks when it last heard from peers, their applied index, and how long they
+    // have been lagging.
+    fn status_nodes() -> Result<()> {
+        let (leader, _node_rx, _state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        let tick = TICK.as_millis() as u64;
+
+        node = node.step(Message {
+            from: Address::Local,
+            to: Address::Local,
+            term: 0,
+            event: Event::Applied { index: 2 },
+        })?;
+        node = node.tick()?;
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 5 },
+        })?;
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ConfirmLeader {
+                commit_index: 2,
+                has_committed: true,
+                clock: 1,
+                applied_index: 2,
+            },
+        })?;
+        node = node.tick()?;
+        node = node.tick()?;
+
+        match node {
+            Node::Leader(leader) => assert_eq!(
+                leader.node_status(),
+                vec![
+                    ("a".into(), node_status("leader", 5, 2, Some(0), 0, 0)),
+                    ("b".into(), node_status("follower", 5, 0, Some(2 * tick), 0, 0)),
+                    ("c".into(), node_status("follower", 0, 2, Some(2 * tick), 5, 2 * tick)),
+                    ("d".into(), node_status("follower", 0, 0, None, 5, 2 * tick)),
+                    ("e".into(), node_status("follower", 0, 0, None, 5, 2 * tick)),
+                ]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+            ),
+            _ => panic!("Expected leader"),
+        }
+        Ok(())
+    }
+
     #[test]
     // Sending a status request should pass it on to state machine, to add status.
     fn step_clientrequest_status() -> Result<()> {
@@ -658,6 +1634,8 @@ mod tests {
                 address: Address::Client,
                 status: Box::new(Status {
                     server: "a".into(),
+                    role: "leader".into(),
+                    elections: 0,
                     leader: "a".into(),
                     term: 3,
                     node_last_index: vec![
@@ -669,10 +1647,29 @@ mod tests {
                     ]
                     .into_iter()
                     .collect(),
+                    node_next_index: vec![
+                        ("a".into(), 6),
+                        ("b".into(), 6),
+                        ("c".into(), 6),
+                        ("d".into(), 6),
+                        ("e".into(), 6),
+                    ]
+                    .into_iter()
+                    .collect(),
                     commit_index: 2,
                     apply_index: 0,
                     storage: "test".into(),
-                    storage_size: 130,
+                    storage_size: 135,
+                    draining: BTreeSet::new(),
+                    nodes: vec![
+                        ("a".into(), node_status("leader", 5, 0, Some(0), 0, 0)),
+                        ("b".into(), node_status("follower", 0, 0, None, 5, 0)),
+                        ("c".into(), node_status("follower", 0, 0, None, 5, 0)),
+                        ("d".into(), node_status("follower", 0, 0, None, 5, 0)),
+                        ("e".into(), node_status("follower", 0, 0, None, 5, 0)),
+                    ]
+                    .into_iter()
+                    .collect(),
                 }),
             }],
         );
@@ -680,11 +1677,127 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Flush requests flush the log and are passed on to the state machine to flush it too.
+    fn step_clientrequest_flush() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Flush },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2).last(5);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Flush { id: vec![0x01], address: Address::Client }],
+        );
+        Ok(())
+    }
+
+    #[test]
+    // TransferLeadership requests tell an up-to-date peer to campaign, without a log entry.
+    fn step_clientrequest_transferleadership() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 5 },
+        })?;
+        while node_rx.try_recv().is_ok() {}
+
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::TransferLeadership },
+        })?;
+        assert_node(&node).is_leader().term(3).last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("c".into()),
+                    term: 3,
+                    event: Event::TimeoutNow,
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::State(vec![])),
+                    },
+                },
+            ],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // SolicitPreVote from a future term is ignored, and doesn't make us step down.
+    fn step_solicitprevote() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let node = leader.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 5, last_term: 3 },
+        })?;
+        assert_node(&node).is_leader().term(3);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // The leader steps down if it hasn't heard from a quorum within an election timeout.
+    fn tick_check_quorum() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            })?;
+        }
+        for _ in 0..ELECTION_TIMEOUT_MIN {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_leader().term(3);
+        while node_rx.try_recv().is_ok() {}
+        while state_rx.try_recv().is_ok() {}
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 5 },
+        })?;
+        for _ in 0..ELECTION_TIMEOUT_MIN {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_follower().term(3).leader(None).voted_for(None);
+        assert_messages(&mut state_rx, vec![Instruction::Abort]);
+        Ok(())
+    }
+
     #[test]
     fn tick() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
         let mut node: Node = leader.into();
-        for _ in 0..5 {
+        for i in 1..=5 {
             for _ in 0..HEARTBEAT_INTERVAL {
                 assert_messages(&mut node_rx, vec![]);
                 assert_messages(&mut state_rx, vec![]);
@@ -697,7 +1810,11 @@ mod tests {
                     from: Address::Local,
                     to: Address::Peers,
                     term: 3,
-                    event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                    event: Event::Heartbeat {
+                        commit_index: 2,
+                        commit_term: 1,
+                        clock: i * HEARTBEAT_INTERVAL
+                    },
                 }
             );
         }
@@ -1,45 +1,159 @@
 mod candidate;
 mod follower;
 mod leader;
+#[cfg(test)]
+mod sim;
 
-use super::{Address, Driver, Event, Instruction, Log, Message, State};
+use super::server::TICK;
+use super::{Address, Driver, Event, Instruction, Log, Message, Request, Response, State};
 use crate::error::{Error, Result};
 use candidate::Candidate;
 use follower::Follower;
 use leader::Leader;
 
 use ::log::{debug, info};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use tokio::sync::mpsc;
 
-/// The interval between leader heartbeats, in ticks.
+/// The default interval between leader heartbeats, in ticks.
 const HEARTBEAT_INTERVAL: u64 = 1;
 
-/// The minimum election timeout, in ticks.
+/// The default minimum election timeout, in ticks.
 const ELECTION_TIMEOUT_MIN: u64 = 8 * HEARTBEAT_INTERVAL;
 
-/// The maximum election timeout, in ticks.
+/// The default maximum election timeout, in ticks.
 const ELECTION_TIMEOUT_MAX: u64 = 15 * HEARTBEAT_INTERVAL;
 
-/// Node status
+/// The maximum factor by which adaptive election timeouts are extended.
+const ADAPTIVE_TIMEOUT_MAX_FACTOR: u64 = 4;
+
+/// Raft node options, with durations given in ticks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// The interval between leader heartbeats.
+    pub heartbeat_interval: u64,
+    /// The minimum election timeout. This is also how long followers keep supporting a leader
+    /// they haven't heard from, and how often the leader checks that it can reach a quorum.
+    pub election_timeout_min: u64,
+    /// The maximum election timeout. Election timeouts are picked randomly between the minimum
+    /// and maximum, such that nodes don't all start competing elections at the same time.
+    pub election_timeout_max: u64,
+    /// The maximum number of entries to send to a follower in a single message, or 0 for no
+    /// limit. Further entries are sent once the follower has accepted the previous ones.
+    pub max_inflight: u64,
+    /// Extends election timeouts when heartbeats from the leader are observed to arrive late,
+    /// e.g. on slow or congested networks, to avoid spurious elections. Timeouts are extended in
+    /// proportion to the heartbeat delay, by up to ADAPTIVE_TIMEOUT_MAX_FACTOR.
+    pub adaptive_timeouts: bool,
+    /// Runs the node as a witness, which votes in elections and counts towards the commit quorum
+    /// but never campaigns for leadership. It stores log entries and snapshots without their
+    /// data, and should be paired with a witness state machine (see WitnessState).
+    pub witness: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+            election_timeout_min: ELECTION_TIMEOUT_MIN,
+            election_timeout_max: ELECTION_TIMEOUT_MAX,
+            max_inflight: 0,
+            adaptive_timeouts: true,
+            witness: false,
+        }
+    }
+}
+
+impl Options {
+    /// Validates the options.
+    fn validate(&self) -> Result<()> {
+        if self.heartbeat_interval == 0 {
+            return Err(Error::Config("Heartbeat interval must be at least 1 tick".into()));
+        }
+        if self.election_timeout_min <= self.heartbeat_interval {
+            return Err(Error::Config(
+                "Minimum election timeout must be greater than the heartbeat interval".into(),
+            ));
+        }
+        if self.election_timeout_max <= self.election_timeout_min {
+            return Err(Error::Config(
+                "Maximum election timeout must be greater than the minimum election timeout".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Node status. This is generated by the leader, except for the server, role, and elections
+/// fields which describe the node that served the request.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
     pub server: String,
+    pub role: String,
+    pub elections: u64,
     pub leader: String,
     pub term: u64,
+    /// The last log index replicated to each node (i.e. the match index).
     pub node_last_index: HashMap<String, u64>,
+    /// The next log index to send to each node.
+    pub node_next_index: HashMap<String, u64>,
     pub commit_index: u64,
     pub apply_index: u64,
     pub storage: String,
     pub storage_size: u64,
+    /// Nodes that are draining, in preparation for removal from the cluster.
+    pub draining: BTreeSet<String>,
+    /// The replication status of each node in the cluster, as seen by the leader.
+    pub nodes: BTreeMap<String, NodeStatus>,
+}
+
+/// Replication status of a node in the cluster, as seen by the leader. Durations are in
+/// milliseconds, with the granularity of a Raft tick.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    /// The node's role, i.e. leader or follower.
+    pub role: String,
+    /// The last log index known to be replicated to the node (i.e. the match index).
+    pub last_index: u64,
+    /// The node's state machine applied index, as last reported by the node.
+    pub apply_index: u64,
+    /// The time since the leader last heard from the node, or None if it never has.
+    pub last_seen: Option<u64>,
+    /// The number of log entries the node is behind the leader.
+    pub lag_entries: u64,
+    /// The time since the node was last caught up with the leader, or 0 if it is caught up.
+    pub lag_millis: u64,
+}
+
+/// Local node health, as seen by the node itself rather than the leader, for health checks. This
+/// is answered by the local node even when there is no leader.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub server: String,
+    pub role: String,
+    /// The current leader, if known.
+    pub leader: Option<String>,
+    pub term: u64,
+    pub last_index: u64,
+    pub commit_index: u64,
+    pub apply_index: u64,
+    /// The time in milliseconds since the node last heard from the leader, 0 if it is the
+    /// leader, or None if there is no known leader.
+    pub leader_seen: Option<u64>,
+    /// Whether the node is draining or removed from the cluster.
+    pub draining: bool,
+    /// The error from reading the log storage, if any.
+    pub storage_error: Option<String>,
 }
 
 /// The local Raft node state machine.
 pub enum Node {
     Candidate(RoleNode<Candidate>),
     Follower(RoleNode<Follower>),
-    Leader(RoleNode<Leader>),
+    Leader(Box<RoleNode<Leader>>),
 }
 
 impl Node {
@@ -51,24 +165,48 @@ impl Node {
         mut state: Box<dyn State>,
         node_tx: mpsc::UnboundedSender<Message>,
     ) -> Result<Self> {
-        let applied_index = state.applied_index();
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let mut driver = Driver::new(state_rx, node_tx.clone());
+        let node = Self::init(id, peers, log, &mut *state, &mut driver, node_tx, state_tx)?;
+        tokio::spawn(driver.drive(state));
+        Ok(node)
+    }
+
+    /// Initializes a new Raft node, restoring and replaying the state machine via the given
+    /// driver, which the caller must then run to process instructions sent via state_tx.
+    fn init(
+        id: &str,
+        peers: Vec<String>,
+        log: Log,
+        state: &mut dyn State,
+        driver: &mut Driver,
+        node_tx: mpsc::UnboundedSender<Message>,
+        state_tx: mpsc::UnboundedSender<Instruction>,
+    ) -> Result<Self> {
+        let mut applied_index = state.applied_index();
+        if applied_index < log.snapshot_index {
+            if let Some(snapshot) = log.snapshot()? {
+                info!("Restoring state machine from snapshot at index {}", snapshot.index);
+                state.restore(snapshot.index, snapshot.data)?;
+                applied_index = snapshot.index;
+            }
+        }
         if applied_index > log.commit_index {
             return Err(Error::Internal(format!(
                 "State machine applied index {} greater than log committed index {}",
                 applied_index, log.commit_index
             )));
         }
-
-        let (state_tx, state_rx) = mpsc::unbounded_channel();
-        let mut driver = Driver::new(state_rx, node_tx.clone());
         if log.commit_index > applied_index {
             info!("Replaying log entries {} to {}", applied_index + 1, log.commit_index);
-            driver.replay(&mut *state, log.scan((applied_index + 1)..=log.commit_index))?;
+            driver.replay(state, log.scan((applied_index + 1)..=log.commit_index))?;
         };
-        tokio::spawn(driver.drive(state));
+        // The driver has replayed all committed entries.
+        let applied_index = log.commit_index;
 
         let (term, voted_for) = log.load_term()?;
-        let node = RoleNode {
+        let peers = peers.into_iter().filter(|p| !log.membership.removed.contains(p)).collect();
+        let mut node = RoleNode {
             id: id.to_owned(),
             peers,
             term,
@@ -77,9 +215,16 @@ impl Node {
             state_tx,
             queued_reqs: Vec::new(),
             proxied_reqs: HashMap::new(),
-            role: Follower::new(None, voted_for.as_deref()),
+            lease_skew: None,
+            elections: 0,
+            heartbeat_gap: HEARTBEAT_INTERVAL,
+            applied_index,
+            options: Options::default(),
+            rng: StdRng::from_entropy(),
+            role: Follower::new(None, voted_for.as_deref(), 0),
         };
-        if node.peers.is_empty() {
+        node.reset_election_timeout();
+        if node.peers.is_empty() && !node.is_removed() {
             info!("No peers specified, starting as leader");
             let last_index = node.log.last_index;
             Ok(node.become_role(Leader::new(vec![], last_index))?.into())
@@ -88,6 +233,55 @@ impl Node {
         }
     }
 
+    /// Sets the node options, replacing the defaults.
+    pub fn with_options(mut self, options: Options) -> Result<Self> {
+        options.validate()?;
+        match &mut self {
+            Node::Candidate(n) => {
+                n.set_options(options)?;
+                n.reset_election_timeout();
+            }
+            Node::Follower(n) => {
+                n.set_options(options)?;
+                n.reset_election_timeout();
+            }
+            Node::Leader(_) if options.witness => {
+                return Err(Error::Config("A witness can't run without peers".into()))
+            }
+            Node::Leader(n) => n.set_options(options)?,
+        }
+        Ok(self)
+    }
+
+    /// Seeds the node's random number generator, used e.g. for election timeouts, such that
+    /// its behavior is reproducible given the same inputs.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        match &mut self {
+            Node::Candidate(n) => {
+                n.rng = StdRng::seed_from_u64(seed);
+                n.reset_election_timeout();
+            }
+            Node::Follower(n) => {
+                n.rng = StdRng::seed_from_u64(seed);
+                n.reset_election_timeout();
+            }
+            Node::Leader(n) => n.rng = StdRng::seed_from_u64(seed),
+        }
+        self
+    }
+
+    /// Enables leader leases, allowing the leader to serve reads without confirming its
+    /// leadership with a quorum, given the maximum clock skew between nodes in ticks. The lease
+    /// is the minimum election timeout less the clock skew, so the skew must be smaller.
+    pub fn with_lease(mut self, max_skew: u64) -> Result<Self> {
+        match &mut self {
+            Node::Candidate(n) => n.set_lease(max_skew)?,
+            Node::Follower(n) => n.set_lease(max_skew)?,
+            Node::Leader(n) => n.set_lease(max_skew)?,
+        }
+        Ok(self)
+    }
+
     /// Returns the node ID.
     pub fn id(&self) -> String {
         match self {
@@ -100,13 +294,153 @@ impl Node {
     /// Processes a message.
     pub fn step(self, msg: Message) -> Result<Self> {
         debug!("Stepping {:?}", msg);
+        match (self, msg) {
+            (
+                node,
+                Message { from: Address::Local, event: Event::CompactLog { index, data }, .. },
+            ) => node.compact(index, data),
+            (node, Message { from: Address::Local, event: Event::Applied { index }, .. }) => {
+                Ok(node.applied(index))
+            }
+            (
+                node,
+                Message {
+                    from, event: Event::ClientRequest { id, request: Request::Health }, ..
+                },
+            ) => node.health(from, id),
+            (
+                node,
+                Message {
+                    from, event: Event::ClientRequest { id, request: Request::Flush }, ..
+                },
+            ) => node.flush(from, id),
+            (
+                node,
+                Message {
+                    from,
+                    event: Event::ClientRequest { id, request: Request::TransferLeadership },
+                    ..
+                },
+            ) => node.transfer_leadership(from, id),
+            (
+                node,
+                Message {
+                    from,
+                    to,
+                    term,
+                    event:
+                        Event::ClientRequest { id, request: Request::QueryLocal { command, index } },
+                },
+            ) => {
+                if node.options().witness {
+                    let event = Event::ClientRequest { id, request: Request::Query(command) };
+                    return node.step(Message { from, to, term, event });
+                }
+                node.query_local(from, id, command, index)
+            }
+            (Node::Candidate(n), msg) => n.step(msg),
+            (Node::Follower(n), msg) => n.step(msg),
+            (Node::Leader(n), msg) => (*n).step(msg),
+        }
+    }
+
+    /// Compacts the log using a state machine snapshot taken at the given applied index.
+    fn compact(mut self, index: u64, data: Vec<u8>) -> Result<Self> {
+        match &mut self {
+            Node::Candidate(n) => n.log.compact(index, data)?,
+            Node::Follower(n) => n.log.compact(index, data)?,
+            Node::Leader(n) => n.log.compact(index, data)?,
+        }
+        Ok(self)
+    }
+
+    /// Records the state machine's applied index.
+    fn applied(mut self, index: u64) -> Self {
+        match &mut self {
+            Node::Candidate(n) => n.applied_index = index,
+            Node::Follower(n) => n.applied_index = index,
+            Node::Leader(n) => n.applied_index = index,
+        }
+        self
+    }
+
+    /// Responds to a health request with the local node's health.
+    fn health(self, from: Address, id: Vec<u8>) -> Result<Self> {
+        let (health, node_tx) = match &self {
+            Node::Candidate(n) => (n.health(), &n.node_tx),
+            Node::Follower(n) => (n.health(), &n.node_tx),
+            Node::Leader(n) => (n.health(), &n.node_tx),
+        };
+        node_tx.send(Message {
+            from: Address::Local,
+            to: from,
+            term: 0,
+            event: Event::ClientResponse { id, response: Ok(Response::Health(health)) },
+        })?;
+        Ok(self)
+    }
+
+    /// Flushes the log to storage, and then has the state machine driver flush the state machine
+    /// and respond to the request.
+    fn flush(mut self, from: Address, id: Vec<u8>) -> Result<Self> {
+        let (result, node_tx, state_tx) = match &mut self {
+            Node::Candidate(n) => (n.log.flush(), &n.node_tx, &n.state_tx),
+            Node::Follower(n) => (n.log.flush(), &n.node_tx, &n.state_tx),
+            Node::Leader(n) => (n.log.flush(), &n.node_tx, &n.state_tx),
+        };
+        match result {
+            Ok(()) => state_tx.send(Instruction::Flush { id, address: from })?,
+            Err(err) => node_tx.send(Message {
+                from: Address::Local,
+                to: from,
+                term: 0,
+                event: Event::ClientResponse { id, response: Err(err) },
+            })?,
+        }
+        Ok(self)
+    }
+
+    /// Transfers leadership to a peer if the node is the leader, otherwise does nothing. Responds
+    /// once the transfer has been initiated, not when it completes.
+    fn transfer_leadership(mut self, from: Address, id: Vec<u8>) -> Result<Self> {
+        let node_tx = match &mut self {
+            Node::Candidate(n) => &n.node_tx,
+            Node::Follower(n) => &n.node_tx,
+            Node::Leader(n) => {
+                n.transfer_leadership()?;
+                &n.node_tx
+            }
+        };
+        node_tx.send(Message {
+            from: Address::Local,
+            to: from,
+            term: 0,
+            event: Event::ClientResponse { id, response: Ok(Response::State(Vec::new())) },
+        })?;
+        Ok(self)
+    }
+
+    /// Returns the node options.
+    fn options(&self) -> &Options {
         match self {
-            Node::Candidate(n) => n.step(msg),
-            Node::Follower(n) => n.step(msg),
-            Node::Leader(n) => n.step(msg),
+            Node::Candidate(n) => &n.options,
+            Node::Follower(n) => &n.options,
+            Node::Leader(n) => &n.options,
         }
     }
 
+    /// Has the state machine driver execute a query against the local state machine once it has
+    /// applied the given index, regardless of the node's role.
+    fn query_local(self, from: Address, id: Vec<u8>, command: Vec<u8>, index: u64) -> Result<Self> {
+        let state_tx = match &self {
+            Node::Candidate(n) => &n.state_tx,
+            Node::Follower(n) => &n.state_tx,
+            Node::Leader(n) => &n.state_tx,
+        };
+        state_tx.send(Instruction::QueryLocal { id, address: from, command, index })?;
+        Ok(self)
+    }
+
     /// Moves time forward by a tick.
     pub fn tick(self) -> Result<Self> {
         match self {
@@ -131,7 +465,7 @@ impl From<RoleNode<Follower>> for Node {
 
 impl From<RoleNode<Leader>> for Node {
     fn from(rn: RoleNode<Leader>) -> Self {
-        Node::Leader(rn)
+        Node::Leader(Box::new(rn))
     }
 }
 
@@ -147,6 +481,17 @@ pub struct RoleNode<R> {
     queued_reqs: Vec<(Address, Event)>,
     /// Keeps track of proxied client requests, to abort on new leader election.
     proxied_reqs: HashMap<Vec<u8>, Address>,
+    /// The maximum clock skew between nodes in ticks, if leader leases are enabled for reads.
+    lease_skew: Option<u64>,
+    /// The number of elections started by this node since it was started.
+    elections: u64,
+    /// The estimated gap between leader heartbeats in ticks, for adaptive election timeouts.
+    heartbeat_gap: u64,
+    /// The state machine's applied index, as reported by the driver.
+    applied_index: u64,
+    options: Options,
+    /// The random number generator, e.g. for election timeouts.
+    rng: StdRng,
     role: R,
 }
 
@@ -162,10 +507,78 @@ impl<R> RoleNode<R> {
             state_tx: self.state_tx,
             queued_reqs: self.queued_reqs,
             proxied_reqs: self.proxied_reqs,
+            lease_skew: self.lease_skew,
+            elections: self.elections,
+            heartbeat_gap: self.heartbeat_gap,
+            applied_index: self.applied_index,
+            options: self.options,
+            rng: self.rng,
             role,
         })
     }
 
+    /// Sets the node options, checking that they're compatible with any leader lease.
+    fn set_options(&mut self, options: Options) -> Result<()> {
+        if let Some(skew) = self.lease_skew {
+            Self::check_lease(&options, skew)?;
+        }
+        self.heartbeat_gap = options.heartbeat_interval;
+        self.options = options;
+        Ok(())
+    }
+
+    /// Enables leader leases with the given maximum clock skew.
+    fn set_lease(&mut self, max_skew: u64) -> Result<()> {
+        Self::check_lease(&self.options, max_skew)?;
+        self.lease_skew = Some(max_skew);
+        Ok(())
+    }
+
+    /// Checks that a leader lease clock skew is valid for the given options.
+    fn check_lease(options: &Options, max_skew: u64) -> Result<()> {
+        if max_skew >= options.election_timeout_min {
+            return Err(Error::Config(format!(
+                "Leader lease clock skew must be less than {} ticks",
+                options.election_timeout_min
+            )));
+        }
+        Ok(())
+    }
+
+    /// Picks a random election timeout between the minimum and maximum. With adaptive timeouts,
+    /// this is extended in proportion to the observed heartbeat gap, allowing a tick of jitter.
+    fn election_timeout(&mut self) -> u64 {
+        let timeout = self
+            .rng
+            .gen_range(self.options.election_timeout_min, self.options.election_timeout_max);
+        timeout * self.timeout_factor() / self.options.heartbeat_interval
+    }
+
+    /// Returns the adaptive timeout extension factor, scaled by the heartbeat interval.
+    fn timeout_factor(&self) -> u64 {
+        let interval = self.options.heartbeat_interval;
+        if !self.options.adaptive_timeouts {
+            return interval;
+        }
+        std::cmp::min(
+            std::cmp::max(self.heartbeat_gap.saturating_sub(1), interval),
+            interval * ADAPTIVE_TIMEOUT_MAX_FACTOR,
+        )
+    }
+
+    /// Records the gap in ticks between two leader heartbeats, updating the heartbeat gap
+    /// estimate. Gaps larger than the estimate are adopted immediately, while smaller ones decay
+    /// the estimate gradually. Returns true if this changed the timeout extension factor.
+    fn observe_heartbeat_gap(&mut self, gap: u64) -> bool {
+        let factor = self.timeout_factor();
+        self.heartbeat_gap = if gap > self.heartbeat_gap {
+            gap
+        } else {
+            std::cmp::max((self.heartbeat_gap * 7 + gap) / 8, self.options.heartbeat_interval)
+        };
+        self.timeout_factor() != factor
+    }
+
     /// Aborts any proxied requests.
     fn abort_proxied(&mut self) -> Result<()> {
         for (id, address) in std::mem::replace(&mut self.proxied_reqs, HashMap::new()) {
@@ -193,6 +606,53 @@ impl<R> RoleNode<R> {
         Ok(())
     }
 
+    /// Sets the status fields describing this node, before responding to a status request.
+    fn set_status(&self, status: &mut Status, role: &str) {
+        status.server = self.id.clone();
+        status.role = if self.options.witness { "witness" } else { role }.to_string();
+        status.elections = self.elections;
+    }
+
+    /// Builds the local node health, given the role name and known leader, if any, along with the
+    /// number of ticks since it was last heard from.
+    fn health_with(&self, role: &str, leader: Option<String>, leader_seen: Option<u64>) -> Health {
+        Health {
+            server: self.id.clone(),
+            role: if self.options.witness { "witness" } else { role }.to_string(),
+            leader,
+            term: self.term,
+            last_index: self.log.last_index,
+            commit_index: self.log.commit_index,
+            apply_index: self.applied_index,
+            leader_seen: leader_seen.map(|ticks| ticks * TICK.as_millis() as u64),
+            draining: self.is_draining(),
+            storage_error: self.log.get(self.log.commit_index).err().map(|e| e.to_string()),
+        }
+    }
+
+    /// Checks whether this node has been removed from the cluster.
+    fn is_removed(&self) -> bool {
+        self.log.membership.removed.contains(&self.id)
+    }
+
+    /// Checks whether this node is draining, or has been removed from the cluster.
+    fn is_draining(&self) -> bool {
+        self.log.membership.draining.contains(&self.id) || self.is_removed()
+    }
+
+    /// Checks whether this node may campaign for leadership, i.e. it isn't a witness and isn't
+    /// draining or removed.
+    fn can_campaign(&self) -> bool {
+        !self.options.witness && !self.is_draining()
+    }
+
+    /// Removes any peers that have been removed from the cluster membership, after committing
+    /// entries or installing a snapshot.
+    fn sync_membership(&mut self) {
+        let removed = &self.log.membership.removed;
+        self.peers.retain(|p| !removed.contains(p));
+    }
+
     /// Returns the quorum size of the cluster.
     fn quorum(&self) -> u64 {
         (self.peers.len() as u64 + 1) / 2 + 1
@@ -200,25 +660,62 @@ impl<R> RoleNode<R> {
 
     /// Sends an event
     fn send(&self, to: Address, event: Event) -> Result<()> {
-        let msg = Message { term: self.term, from: Address::Local, to, event };
+        self.send_term(to, self.term, event)
+    }
+
+    /// Sends an event for a given term, which may differ from the current term for pre-votes.
+    fn send_term(&self, to: Address, term: u64, event: Event) -> Result<()> {
+        let msg = Message { term, from: Address::Local, to, event };
         debug!("Sending {:?}", msg);
         Ok(self.node_tx.send(msg)?)
     }
 
+    /// Checks whether a candidate's log, given by its last index and term, is at least as
+    /// up-to-date as our own log, which is required to grant it a vote.
+    fn is_log_current(&self, last_index: u64, last_term: u64) -> bool {
+        last_term > self.log.last_term
+            || (last_term == self.log.last_term && last_index >= self.log.last_index)
+    }
+
+    /// Responds to a heartbeat from a leader in a past term with our own term, such that the
+    /// leader steps down. Otherwise, if we advanced our term in an election we couldn't win, e.g.
+    /// because our log is behind, we'd be stuck: we ignore the leader's heartbeats, and the
+    /// leader ignores our pre-votes and never learns of our term.
+    fn reject_stale_leader(&self, msg: &Message) -> Result<()> {
+        if let (Event::Heartbeat { .. }, Address::Peer(_)) = (&msg.event, &msg.from) {
+            if msg.term < self.term {
+                debug!("Rejecting heartbeat from stale leader {:?}", msg.from);
+                self.send(msg.from.clone(), Event::RejectEntries)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Validates a message
     fn validate(&self, msg: &Message) -> Result<()> {
         match msg.from {
             Address::Peers => return Err(Error::Internal("Message from broadcast address".into())),
             Address::Local => return Err(Error::Internal("Message from local node".into())),
-            Address::Client if !matches!(msg.event, Event::ClientRequest{..}) => {
+            Address::Client if !matches!(msg.event, Event::ClientRequest { .. }) => {
                 return Err(Error::Internal("Non-request message from client".into()));
             }
+            // Removed nodes may still proxy client requests until they're shut down, but must not
+            // take part in e.g. elections or commits.
+            Address::Peer(ref from)
+                if self.log.membership.removed.contains(from)
+                    && !matches!(
+                        msg.event,
+                        Event::ClientRequest { .. } | Event::ClientResponse { .. }
+                    ) =>
+            {
+                return Err(Error::Internal(format!("Message from removed node {}", from)));
+            }
             _ => {}
         }
 
         // Allowing requests and responses form past terms is fine, since they don't rely on it
         if msg.term < self.term
-            && !matches!(msg.event, Event::ClientRequest{..} | Event::ClientResponse{..})
+            && !matches!(msg.event, Event::ClientRequest { .. } | Event::ClientResponse { .. })
         {
             return Err(Error::Internal(format!("Message from past term {}", msg.term)));
         }
@@ -239,7 +736,7 @@ impl<R> RoleNode<R> {
 mod tests {
     pub use super::super::state::tests::TestState;
     use super::super::Entry;
-    use super::follower::tests::{follower_leader, follower_voted_for};
+    use super::follower::tests::{follower_leader, follower_timeout, follower_voted_for};
     use super::*;
     use crate::storage::log;
     use pretty_assertions::assert_eq;
@@ -346,6 +843,22 @@ mod tests {
             self
         }
 
+        pub fn peers(self, peers: Vec<&str>) -> Self {
+            assert_eq!(
+                peers,
+                match self.node {
+                    Node::Candidate(n) => &n.peers,
+                    Node::Follower(n) => &n.peers,
+                    Node::Leader(n) => &n.peers,
+                }
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+                "Unexpected peers"
+            );
+            self
+        }
+
         pub fn queued(self, queued: Vec<(Address, Event)>) -> Self {
             assert_eq!(
                 &queued,
@@ -358,6 +871,19 @@ mod tests {
             self
         }
 
+        pub fn elections(self, elections: u64) -> Self {
+            assert_eq!(
+                elections,
+                match self.node {
+                    Node::Candidate(n) => n.elections,
+                    Node::Follower(n) => n.elections,
+                    Node::Leader(n) => n.elections,
+                },
+                "Unexpected election count"
+            );
+            self
+        }
+
         pub fn term(self, term: u64) -> Self {
             assert_eq!(
                 term,
@@ -412,6 +938,12 @@ mod tests {
         let (node_tx, node_rx) = mpsc::unbounded_channel();
         let (state_tx, _) = mpsc::unbounded_channel();
         let node = RoleNode {
+            lease_skew: None,
+            elections: 0,
+            heartbeat_gap: HEARTBEAT_INTERVAL,
+            applied_index: 0,
+            options: Options::default(),
+            rng: rand::SeedableRng::from_entropy(),
             role: (),
             id: "a".into(),
             peers,
@@ -447,6 +979,65 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn with_options() -> Result<()> {
+        let new = || async {
+            let (node_tx, _) = mpsc::unbounded_channel();
+            Node::new(
+                "a",
+                vec!["b".into(), "c".into()],
+                Log::new(Box::new(log::Test::new()))?,
+                Box::new(TestState::new(0)),
+                node_tx,
+            )
+            .await
+        };
+        let options =
+            Options { election_timeout_min: 20, election_timeout_max: 30, ..Options::default() };
+
+        // The follower picks a new election timeout using the options.
+        match new().await?.with_options(options.clone())? {
+            Node::Follower(n) => {
+                assert_eq!(n.options, options);
+                assert!(follower_timeout(&n) >= 20 && follower_timeout(&n) < 30);
+            }
+            _ => panic!("Expected node to start as follower"),
+        }
+
+        // Witnesses need peers to lead them.
+        let (node_tx, _) = mpsc::unbounded_channel();
+        let single = Node::new(
+            "a",
+            vec![],
+            Log::new(Box::new(log::Test::new()))?,
+            Box::new(TestState::new(0)),
+            node_tx,
+        )
+        .await?;
+        assert!(single.with_options(Options { witness: true, ..Options::default() }).is_err());
+
+        // Invalid options are rejected.
+        for invalid in vec![
+            Options { heartbeat_interval: 0, ..Options::default() },
+            Options { heartbeat_interval: 8, ..Options::default() },
+            Options { election_timeout_max: 8, ..Options::default() },
+        ] {
+            assert!(new().await?.with_options(invalid).is_err());
+        }
+
+        // The lease clock skew must be less than the minimum election timeout, regardless of
+        // the order in which the options and lease are given.
+        assert!(new().await?.with_lease(10).is_err());
+        assert!(new().await?.with_options(options.clone())?.with_lease(10).is_ok());
+        assert!(new().await?.with_lease(6)?.with_options(options).is_ok());
+        assert!(new()
+            .await?
+            .with_lease(6)?
+            .with_options(Options { election_timeout_min: 5, ..Options::default() })
+            .is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn new_loads_term() -> Result<()> {
         let (node_tx, _) = mpsc::unbounded_channel();
@@ -523,6 +1114,88 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(core_threads = 2)]
+    async fn new_state_restore_snapshot() -> Result<()> {
+        let (node_tx, _) = mpsc::unbounded_channel();
+        let mut log = Log::new(Box::new(log::Test::new()))?;
+        log.append(1, Some(vec![0x01]))?;
+        log.append(2, Some(vec![0x02]))?;
+        log.append(2, Some(vec![0x03]))?;
+        log.commit(3)?;
+        log.compact(2, bincode::serialize(&vec![vec![0x01u8], vec![0x02u8]])?)?;
+        let state = Box::new(TestState::new(0));
+
+        Node::new("a", vec!["b".into(), "c".into()], log, state.clone(), node_tx).await?;
+        tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
+        assert_eq!(state.list(), vec![vec![0x01], vec![0x02], vec![0x03]]);
+        assert_eq!(state.applied_index(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn step_compactlog() -> Result<()> {
+        let (mut node, _) = setup_rolenode()?;
+        node.log.append(1, Some(vec![0x01]))?;
+        node.log.append(1, Some(vec![0x02]))?;
+        node.log.commit(2)?;
+        let node: Node = node.become_role(Follower::new(None, None, ELECTION_TIMEOUT_MIN))?.into();
+
+        let node = node.step(Message {
+            from: Address::Local,
+            to: Address::Local,
+            term: 0,
+            event: Event::CompactLog { index: 1, data: vec![0xaa] },
+        })?;
+        let log = NodeAsserter::new(&node).log();
+        assert_eq!(1, log.snapshot_index);
+        assert_eq!(None, log.get(1)?);
+        assert_eq!(Some(vec![0xaa]), log.snapshot()?.map(|s| s.data));
+        Ok(())
+    }
+
+    #[test]
+    fn step_health() -> Result<()> {
+        let (mut node, mut rx) = setup_rolenode()?;
+        node.log.append(1, Some(vec![0x01]))?;
+        node.log.append(1, Some(vec![0x02]))?;
+        node.log.commit(1)?;
+        node.applied_index = 1;
+        let node: Node =
+            node.become_role(Follower::new(Some("b"), None, ELECTION_TIMEOUT_MIN))?.into();
+
+        let node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Health },
+        })?;
+        assert_node(&node).is_follower();
+        assert_messages(
+            &mut rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Client,
+                term: 0,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Ok(Response::Health(Health {
+                        server: "a".into(),
+                        role: "follower".into(),
+                        leader: Some("b".into()),
+                        term: 1,
+                        last_index: 2,
+                        commit_index: 1,
+                        apply_index: 1,
+                        leader_seen: Some(0),
+                        draining: false,
+                        storage_error: None,
+                    })),
+                },
+            }],
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn new_single() -> Result<()> {
         let (node_tx, _) = mpsc::unbounded_channel();
@@ -572,14 +1245,17 @@ mod tests {
     #[test]
     fn send() -> Result<()> {
         let (node, mut rx) = setup_rolenode()?;
-        node.send(Address::Peer("b".into()), Event::Heartbeat { commit_index: 1, commit_term: 1 })?;
+        node.send(
+            Address::Peer("b".into()),
+            Event::Heartbeat { commit_index: 1, commit_term: 1, clock: 0 },
+        )?;
         assert_messages(
             &mut rx,
             vec![Message {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 1,
-                event: Event::Heartbeat { commit_index: 1, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 1, commit_term: 1, clock: 0 },
             }],
         );
         Ok(())
@@ -0,0 +1,551 @@
+//! A deterministic simulation harness for Raft clusters. Nodes run on a simulated clock and
+//! network, where seeded randomness decides message drops, delays, and reordering, network
+//! partitions, node crashes and restarts, and client writes. State machines are driven
+//! synchronously rather than by spawned tasks, such that a given seed always produces the same
+//! execution, and a failing seed can be replayed to debug it.
+//!
+//! Consensus invariants are checked after every step:
+//!
+//! * Election safety: there is at most one leader per term.
+//! * Log matching: all nodes agree on the entries at every committed index.
+//! * Commit durability: a node's commit index never regresses, not even across restarts.
+//! * State machine safety: every node applies a prefix of the committed commands, in order.
+//! * Write durability: writes acknowledged to clients are committed.
+//!
+//! After the faulty phase, faults are healed and the cluster must converge.
+
+use super::super::state::tests::TestState;
+use super::super::{Address, Driver, Entry, Event, Instruction, Log, Message, Request, Response};
+use super::Node;
+use crate::storage::log;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom as _;
+use rand::{Rng as _, SeedableRng as _};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::hash::{Hash as _, Hasher as _};
+use tokio::sync::mpsc;
+
+/// Simulation parameters. Probabilities are given per message, per tick, or per node and tick.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The number of nodes in the cluster.
+    pub nodes: u64,
+    /// The number of ticks to run with faults, before healing them and settling the cluster.
+    pub ticks: u64,
+    /// The number of ticks to settle the cluster for after healing faults.
+    pub settle_ticks: u64,
+    /// The probability of dropping a message.
+    pub drop: f64,
+    /// The maximum message delay in ticks. Delays are random, so messages are also reordered.
+    pub max_delay: u64,
+    /// The probability per tick of partitioning the network in two, if not partitioned.
+    pub partition: f64,
+    /// The probability per tick of healing a network partition.
+    pub heal: f64,
+    /// The probability per node and tick of crashing a running node.
+    pub crash: f64,
+    /// The probability per node and tick of restarting a crashed node.
+    pub restart: f64,
+    /// The probability per tick of submitting a client write to a random running node.
+    pub write: f64,
+    /// The number of applied entries between state machine snapshots.
+    pub snapshot_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nodes: 5,
+            ticks: 500,
+            settle_ticks: 200,
+            drop: 0.0,
+            max_delay: 0,
+            partition: 0.0,
+            heal: 0.0,
+            crash: 0.0,
+            restart: 0.0,
+            write: 0.2,
+            snapshot_interval: 1000,
+        }
+    }
+}
+
+/// A simulated host, running a Raft node unless it has crashed. The log store survives crashes,
+/// while the state machine is lost and rebuilt from the log on restart.
+struct Host {
+    node: Option<Node>,
+    store: log::Test,
+    state: TestState,
+    driver: Driver,
+    node_rx: mpsc::UnboundedReceiver<Message>,
+    state_rx: mpsc::UnboundedReceiver<Instruction>,
+    /// The last commit index seen, which must never regress.
+    commit_index: u64,
+}
+
+impl Host {
+    /// Creates a new host with an empty log store. The node must then be started.
+    fn new() -> Self {
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let (_, state_rx) = mpsc::unbounded_channel();
+        let (_, driver_rx) = mpsc::unbounded_channel();
+        Self {
+            node: None,
+            store: log::Test::new(),
+            state: TestState::new(0),
+            driver: Driver::new(driver_rx, node_tx),
+            node_rx,
+            state_rx,
+            commit_index: 0,
+        }
+    }
+}
+
+/// A simulated Raft cluster.
+pub struct Sim {
+    config: Config,
+    seed: u64,
+    rng: StdRng,
+    /// The current time, in ticks.
+    now: u64,
+    hosts: BTreeMap<String, Host>,
+    /// Messages in flight, keyed by delivery tick and sequence number, with their recipient.
+    network: BTreeMap<(u64, u64), (String, Message)>,
+    sequence: u64,
+    /// If partitioned, the nodes on one side of the partition.
+    partition: Option<BTreeSet<String>>,
+    /// The leader of each term.
+    leaders: BTreeMap<u64, String>,
+    /// All committed entries, in order, as seen by the first node to commit them.
+    committed: Vec<Entry>,
+    /// The committed commands, in order.
+    commands: Vec<Vec<u8>>,
+    /// The number of client writes submitted.
+    writes: u64,
+    /// Client writes acknowledged as successful.
+    acked: HashSet<Vec<u8>>,
+    /// A hash of all delivered messages, to verify determinism.
+    trace: DefaultHasher,
+}
+
+impl Sim {
+    /// Creates a new simulated cluster with the given config and seed.
+    pub fn new(config: Config, seed: u64) -> Self {
+        let mut sim = Self {
+            config,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            now: 0,
+            hosts: BTreeMap::new(),
+            network: BTreeMap::new(),
+            sequence: 0,
+            partition: None,
+            leaders: BTreeMap::new(),
+            committed: Vec::new(),
+            commands: Vec::new(),
+            writes: 0,
+            acked: HashSet::new(),
+            trace: DefaultHasher::new(),
+        };
+        for id in sim.ids() {
+            sim.hosts.insert(id.clone(), Host::new());
+            sim.start(&id);
+        }
+        sim
+    }
+
+    /// Runs the simulation with faults, then heals all faults and checks that the cluster
+    /// converges. Returns a hash of the execution, which is identical for identical seeds.
+    pub fn run(&mut self) -> u64 {
+        for _ in 0..self.config.ticks {
+            self.inject_faults();
+            if self.rng.gen_bool(self.config.write) {
+                self.submit_write();
+            }
+            self.tick();
+        }
+
+        self.partition = None;
+        for id in self.ids() {
+            if self.hosts[&id].node.is_none() {
+                self.start(&id);
+            }
+        }
+        self.config.drop = 0.0;
+        for _ in 0..self.config.settle_ticks {
+            self.tick();
+        }
+        self.check_converged();
+        self.trace.finish()
+    }
+
+    /// Returns the node IDs.
+    fn ids(&self) -> Vec<String> {
+        (0..self.config.nodes).map(|i| ((b'a' + i as u8) as char).to_string()).collect()
+    }
+
+    /// Panics with a consensus violation, including the seed to replay it.
+    fn violation(&self, message: String) -> ! {
+        panic!("Seed {} at tick {}: {}", self.seed, self.now, message)
+    }
+
+    /// (Re)starts a node from its log store, with an empty state machine.
+    fn start(&mut self, id: &str) {
+        let peers = self.ids().into_iter().filter(|p| p != id).collect();
+        let node_seed = self.rng.gen();
+        let snapshot_interval = self.config.snapshot_interval;
+        let host = self.hosts.get_mut(id).unwrap();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        // The driver's own instruction channel is unused, since we execute instructions directly.
+        let (_, driver_rx) = mpsc::unbounded_channel();
+        host.driver =
+            Driver::new(driver_rx, node_tx.clone()).with_snapshot_interval(snapshot_interval);
+        host.state = TestState::new(0);
+        host.node_rx = node_rx;
+        host.state_rx = state_rx;
+        let result = Log::new(Box::new(host.store.clone())).and_then(|log| {
+            Node::init(id, peers, log, &mut host.state, &mut host.driver, node_tx, state_tx)
+        });
+        match result {
+            Ok(node) => host.node = Some(node.with_seed(node_seed)),
+            Err(err) => self.violation(format!("Node {} failed to start: {}", id, err)),
+        }
+        self.check(id);
+    }
+
+    /// Randomly partitions and heals the network, and crashes and restarts nodes.
+    fn inject_faults(&mut self) {
+        match self.partition {
+            None if self.rng.gen_bool(self.config.partition) => {
+                let mut ids = self.ids();
+                ids.shuffle(&mut self.rng);
+                let split = self.rng.gen_range(1, ids.len());
+                self.partition = Some(ids.into_iter().take(split).collect());
+            }
+            Some(_) if self.rng.gen_bool(self.config.heal) => self.partition = None,
+            _ => {}
+        }
+        for id in self.ids() {
+            if self.hosts[&id].node.is_some() {
+                if self.rng.gen_bool(self.config.crash) {
+                    self.hosts.get_mut(&id).unwrap().node = None;
+                }
+            } else if self.rng.gen_bool(self.config.restart) {
+                self.start(&id);
+            }
+        }
+    }
+
+    /// Submits a client write to a random running node. The command is also the request ID.
+    fn submit_write(&mut self) {
+        let running: Vec<String> =
+            self.ids().into_iter().filter(|id| self.hosts[id].node.is_some()).collect();
+        if let Some(id) = running.choose(&mut self.rng).cloned() {
+            self.writes += 1;
+            let command = format!("write {}", self.writes).into_bytes();
+            self.process(
+                &id,
+                Some(Message {
+                    from: Address::Client,
+                    to: Address::Local,
+                    term: 0,
+                    event: Event::ClientRequest {
+                        id: command.clone(),
+                        request: Request::Mutate(command),
+                    },
+                }),
+            );
+        }
+    }
+
+    /// Ticks all running nodes, then delivers all messages that are due.
+    fn tick(&mut self) {
+        for id in self.ids() {
+            self.process(&id, None);
+        }
+        while let Some(&(at, sequence)) = self.network.keys().next() {
+            if at > self.now {
+                break;
+            }
+            let (to, msg) = self.network.remove(&(at, sequence)).unwrap();
+            if let Address::Peer(from) = &msg.from {
+                if !self.connected(from, &to) {
+                    continue;
+                }
+            }
+            format!("{} {} {:?}", self.now, to, msg).hash(&mut self.trace);
+            self.process(&to, Some(msg));
+        }
+        self.now += 1;
+    }
+
+    /// Checks whether two nodes can communicate, i.e. are on the same side of any partition.
+    fn connected(&self, a: &str, b: &str) -> bool {
+        match &self.partition {
+            Some(side) => side.contains(a) == side.contains(b),
+            None => true,
+        }
+    }
+
+    /// Steps a message into a node if running, or ticks it if None, along with any resulting
+    /// local messages and state machine instructions, and routes outbound messages to the
+    /// network or client.
+    fn process(&mut self, id: &str, msg: Option<Message>) {
+        let mut inputs = VecDeque::new();
+        inputs.push_back(msg);
+        while let Some(input) = inputs.pop_front() {
+            let host = self.hosts.get_mut(id).unwrap();
+            let node = match host.node.take() {
+                Some(node) => node,
+                None => return,
+            };
+            let result = match input {
+                Some(msg) => node.step(msg),
+                None => node.tick(),
+            };
+            match result {
+                Ok(node) => host.node = Some(node),
+                Err(err) => self.violation(format!("Node {} failed: {}", id, err)),
+            }
+            self.check(id);
+
+            let host = self.hosts.get_mut(id).unwrap();
+            let mut error = None;
+            while let Ok(instruction) = host.state_rx.try_recv() {
+                let result = host.driver.execute(instruction, &mut host.state);
+                if let Err(err) = futures::executor::block_on(result) {
+                    error = Some(err);
+                    break;
+                }
+            }
+            if let Some(err) = error {
+                self.violation(format!("Node {} state machine failed: {}", id, err))
+            }
+
+            let host = self.hosts.get_mut(id).unwrap();
+            let mut outbox = Vec::new();
+            while let Ok(msg) = host.node_rx.try_recv() {
+                outbox.push(msg);
+            }
+            // Messages to different recipients, or client responses, may be emitted in hash map
+            // order. Sort them such that the random number generator is used deterministically.
+            outbox.sort_by_cached_key(|msg| {
+                let id = match &msg.event {
+                    Event::ClientResponse { id, .. } => id.clone(),
+                    _ => Vec::new(),
+                };
+                (format!("{:?}", msg.to), id)
+            });
+            for mut msg in outbox {
+                match msg.to.clone() {
+                    Address::Local => inputs.push_back(Some(msg)),
+                    Address::Client => self.respond(msg),
+                    Address::Peer(to) => {
+                        msg.from = Address::Peer(id.to_string());
+                        self.send(id, to, msg)
+                    }
+                    Address::Peers => {
+                        msg.from = Address::Peer(id.to_string());
+                        for to in self.ids().into_iter().filter(|to| to != id) {
+                            self.send(id, to, msg.clone())
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a message across the network, unless it's dropped or partitioned.
+    fn send(&mut self, from: &str, to: String, msg: Message) {
+        if !self.connected(from, &to) || self.rng.gen_bool(self.config.drop) {
+            return;
+        }
+        let at = self.now + self.rng.gen_range(0, self.config.max_delay + 1);
+        self.sequence += 1;
+        self.network.insert((at, self.sequence), (to, msg));
+    }
+
+    /// Handles a client response, checking that acknowledged writes have been committed.
+    fn respond(&mut self, msg: Message) {
+        if let Event::ClientResponse { id, response: Ok(Response::State(command)) } = msg.event {
+            if !self.commands.contains(&command) {
+                self.violation(format!("Acknowledged write {:?} not committed", id))
+            }
+            self.acked.insert(command);
+        }
+    }
+
+    /// Checks consensus invariants for a node.
+    fn check(&mut self, id: &str) {
+        let host = &self.hosts[id];
+        let node = match &host.node {
+            Some(node) => node,
+            None => return,
+        };
+        let (term, log) = match node {
+            Node::Candidate(n) => (n.term, &n.log),
+            Node::Follower(n) => (n.term, &n.log),
+            Node::Leader(n) => (n.term, &n.log),
+        };
+
+        if let Node::Leader(_) = node {
+            match self.leaders.get(&term) {
+                Some(leader) if leader != id => {
+                    self.violation(format!("Term {} has leaders {} and {}", term, leader, id))
+                }
+                _ => {}
+            }
+        }
+
+        if log.commit_index < host.commit_index {
+            self.violation(format!(
+                "Node {} commit index regressed from {} to {}",
+                id, host.commit_index, log.commit_index
+            ))
+        }
+        let from = std::cmp::max(host.commit_index, log.snapshot_index) + 1;
+        let mut committed = Vec::new();
+        for entry in log.scan(from..=log.commit_index) {
+            let entry = entry.unwrap_or_else(|err| self.violation(format!("Node {}: {}", id, err)));
+            match self.committed.get(entry.index as usize - 1) {
+                Some(e) if *e != entry => self.violation(format!(
+                    "Node {} committed {:?}, but {:?} was committed",
+                    id, entry, e
+                )),
+                Some(_) => {}
+                None if entry.index as usize == self.committed.len() + committed.len() + 1 => {
+                    committed.push(entry)
+                }
+                None => self
+                    .violation(format!("Node {} committed index {} out of order", id, entry.index)),
+            }
+        }
+
+        let applied = host.state.list();
+        let commit_index = log.commit_index;
+        let is_leader = matches!(node, Node::Leader(_));
+        for entry in committed {
+            if let Some(command) = &entry.command {
+                self.commands.push(command.clone());
+            }
+            self.committed.push(entry);
+        }
+        if is_leader {
+            self.leaders.insert(term, id.to_string());
+        }
+        self.hosts.get_mut(id).unwrap().commit_index = commit_index;
+
+        if applied.len() > self.commands.len() || applied[..] != self.commands[..applied.len()] {
+            self.violation(format!("Node {} applied uncommitted or misordered commands", id))
+        }
+    }
+
+    /// Checks that all nodes have converged on the same committed log and state, with a single
+    /// leader, and that every acknowledged write was applied.
+    fn check_converged(&self) {
+        let leaders: Vec<&String> = self
+            .hosts
+            .iter()
+            .filter(|(_, h)| matches!(h.node, Some(Node::Leader(_))))
+            .map(|(id, _)| id)
+            .collect();
+        if leaders.len() != 1 {
+            self.violation(format!("Expected a single leader after settling, got {:?}", leaders))
+        }
+        for (id, host) in &self.hosts {
+            if host.commit_index != self.committed.len() as u64 {
+                self.violation(format!(
+                    "Node {} commit index {} did not converge to {}",
+                    id,
+                    host.commit_index,
+                    self.committed.len()
+                ))
+            }
+            if host.state.list() != self.commands {
+                self.violation(format!("Node {} did not apply all committed commands", id))
+            }
+        }
+        if let Some(command) = self.acked.iter().find(|c| !self.commands.contains(c)) {
+            self.violation(format!("Acknowledged write {:?} was lost", command))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the seeds to run, or only the seed given by TOYDB_SIM_SEED if set, e.g. to replay
+    /// a failing seed.
+    fn seeds(count: u64) -> Vec<u64> {
+        match std::env::var("TOYDB_SIM_SEED") {
+            Ok(seed) => vec![seed.parse().expect("Invalid TOYDB_SIM_SEED")],
+            Err(_) => (0..count).collect(),
+        }
+    }
+
+    /// A config with all fault types enabled.
+    fn faulty() -> Config {
+        Config {
+            drop: 0.1,
+            max_delay: 3,
+            partition: 0.01,
+            heal: 0.05,
+            crash: 0.002,
+            restart: 0.05,
+            snapshot_interval: 20,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    // The same seed must produce the same execution, and different seeds different ones.
+    fn deterministic() {
+        assert_eq!(Sim::new(faulty(), 1).run(), Sim::new(faulty(), 1).run());
+        assert_ne!(Sim::new(faulty(), 1).run(), Sim::new(faulty(), 2).run());
+    }
+
+    #[test]
+    // Without faults, all writes are committed and acknowledged.
+    fn reliable() {
+        for seed in seeds(10) {
+            let mut sim = Sim::new(Config::default(), seed);
+            sim.run();
+            assert_eq!(sim.acked.len() as u64, sim.writes, "Seed {}", seed);
+            assert_eq!(sim.leaders.len(), 1, "Seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn drops_and_delays() {
+        for seed in seeds(10) {
+            Sim::new(Config { drop: 0.2, max_delay: 5, ..Config::default() }, seed).run();
+        }
+    }
+
+    #[test]
+    fn partitions() {
+        for seed in seeds(10) {
+            Sim::new(Config { partition: 0.02, heal: 0.02, ..Config::default() }, seed).run();
+        }
+    }
+
+    #[test]
+    fn crashes() {
+        for seed in seeds(10) {
+            let config =
+                Config { crash: 0.005, restart: 0.05, snapshot_interval: 10, ..Config::default() };
+            Sim::new(config, seed).run();
+        }
+    }
+
+    #[test]
+    fn all_faults() {
+        for seed in seeds(20) {
+            Sim::new(faulty(), seed).run();
+        }
+    }
+}
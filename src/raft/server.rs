@@ -1,8 +1,11 @@
 use super::{Address, Event, Log, Message, Node, Request, Response, State};
 use crate::error::{Error, Result};
+use crate::fault::{Action, Faults};
+use crate::storage::Compression;
 
 use ::log::{debug, error};
 use futures::{sink::SinkExt as _, FutureExt as _};
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
@@ -12,13 +15,62 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use uuid::Uuid;
 
 /// The duration of a Raft tick, the unit of time for e.g. heartbeats and elections.
-const TICK: Duration = Duration::from_millis(100);
+pub(super) const TICK: Duration = Duration::from_millis(100);
+
+/// Converts a duration to Raft ticks, rounding up to whole ticks.
+pub fn ticks(duration: Duration) -> u64 {
+    duration.as_millis().div_ceil(TICK.as_millis()) as u64
+}
+
+/// The default size above which messages are compressed, in bytes.
+pub const DEFAULT_COMPRESSION_THRESHOLD: u64 = 4096;
+
+/// A message frame sent between Raft peers via TCP. Large messages, e.g. log entries sent while
+/// a follower catches up or snapshots, can be compressed to reduce network bandwidth.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Frame {
+    /// An uncompressed message.
+    Message(Box<Message>),
+    /// A Snappy-compressed, bincode-encoded message.
+    Snappy(Vec<u8>),
+}
+
+impl Frame {
+    /// Creates a frame for a message, compressing it if its encoded size is at or above the
+    /// threshold and compression makes it smaller.
+    fn encode(message: Message, compression: Compression, threshold: u64) -> Result<Self> {
+        let frame: fn(Vec<u8>) -> Self = match compression {
+            Compression::None => return Ok(Self::Message(Box::new(message))),
+            Compression::Snappy => Self::Snappy,
+        };
+        if bincode::serialized_size(&message)? < threshold {
+            return Ok(Self::Message(Box::new(message)));
+        }
+        match compression.compress(&bincode::serialize(&message)?)? {
+            Some(compressed) => Ok(frame(compressed)),
+            None => Ok(Self::Message(Box::new(message))),
+        }
+    }
+
+    /// Decodes the message contained in the frame.
+    fn decode(self) -> Result<Message> {
+        match self {
+            Self::Message(message) => Ok(*message),
+            Self::Snappy(compressed) => {
+                Ok(bincode::deserialize(&Compression::Snappy.decompress(&compressed)?)?)
+            }
+        }
+    }
+}
 
 /// A Raft server.
 pub struct Server {
     node: Node,
     peers: HashMap<String, String>,
     node_rx: mpsc::UnboundedReceiver<Message>,
+    compression: Compression,
+    compression_threshold: u64,
+    faults: Faults,
 }
 
 impl Server {
@@ -41,9 +93,40 @@ impl Server {
             .await?,
             peers,
             node_rx,
+            compression: Compression::None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            faults: Faults::new(),
         })
     }
 
+    /// Compresses messages sent to peers whose encoded size is at or above the given threshold,
+    /// in bytes. Peers can always receive compressed messages, regardless of their own setting.
+    pub fn with_compression(mut self, compression: Compression, threshold: u64) -> Self {
+        self.compression = compression;
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Injects faults into messages sent to peers, for chaos testing. Sends are named
+    /// raft.send.<peer>: errors drop the message, and delays deliver it late and out of order.
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Sets the Raft node options, with durations given in ticks (see ticks()).
+    pub fn with_options(mut self, options: super::Options) -> Result<Self> {
+        self.node = self.node.with_options(options)?;
+        Ok(self)
+    }
+
+    /// Enables leader leases for reads, given the maximum clock skew between nodes. The skew is
+    /// rounded up to whole ticks.
+    pub fn with_lease(mut self, max_skew: Duration) -> Result<Self> {
+        self.node = self.node.with_lease(ticks(max_skew))?;
+        Ok(self)
+    }
+
     /// Connects to peers and serves requests.
     pub async fn serve(
         self,
@@ -54,8 +137,15 @@ impl Server {
         let (tcp_out_tx, tcp_out_rx) = mpsc::unbounded_channel::<Message>();
         let (task, tcp_receiver) = Self::tcp_receive(listener, tcp_in_tx).remote_handle();
         tokio::spawn(task);
-        let (task, tcp_sender) =
-            Self::tcp_send(self.node.id(), self.peers, tcp_out_rx).remote_handle();
+        let (task, tcp_sender) = Self::tcp_send(
+            self.node.id(),
+            self.peers,
+            tcp_out_rx,
+            self.compression,
+            self.compression_threshold,
+            self.faults,
+        )
+        .remote_handle();
         tokio::spawn(task);
         let (task, eventloop) =
             Self::eventloop(self.node, self.node_rx, client_rx, tcp_in_rx, tcp_out_tx)
@@ -78,14 +168,18 @@ impl Server {
         let mut requests = HashMap::<Vec<u8>, oneshot::Sender<Result<Response>>>::new();
         loop {
             tokio::select! {
-                _ = ticker.tick() => node = node.tick()?,
+                _ = ticker.tick() => node = tokio::task::block_in_place(|| node.tick())?,
 
-                Some(msg) = tcp_rx.next() => node = node.step(msg)?,
+                Some(msg) = tcp_rx.next() => node = tokio::task::block_in_place(|| node.step(msg))?,
 
                 Some(msg) = node_rx.next() => {
                     match msg {
                         Message{to: Address::Peer(_), ..} => tcp_tx.send(msg)?,
                         Message{to: Address::Peers, ..} => tcp_tx.send(msg)?,
+                        Message{to: Address::Local, event: Event::CompactLog{..}, ..} => {
+                            node = tokio::task::block_in_place(|| node.step(msg))?
+                        }
+                        Message{to: Address::Local, event: Event::Applied{..}, ..} => node = node.step(msg)?,
                         Message{to: Address::Client, event: Event::ClientResponse{ id, response }, ..} => {
                             if let Some(response_tx) = requests.remove(&id) {
                                 response_tx
@@ -100,12 +194,13 @@ impl Server {
                 Some((request, response_tx)) = client_rx.next() => {
                     let id = Uuid::new_v4().as_bytes().to_vec();
                     requests.insert(id.clone(), response_tx);
-                    node = node.step(Message{
+                    let msg = Message{
                         from: Address::Client,
                         to: Address::Local,
                         term: 0,
                         event: Event::ClientRequest{id, request},
-                    })?;
+                    };
+                    node = tokio::task::block_in_place(|| node.step(msg))?;
                 }
             }
         }
@@ -135,12 +230,12 @@ impl Server {
         socket: TcpStream,
         in_tx: mpsc::UnboundedSender<Message>,
     ) -> Result<()> {
-        let mut stream = tokio_serde::SymmetricallyFramed::<_, Message, _>::new(
+        let mut stream = tokio_serde::SymmetricallyFramed::<_, Frame, _>::new(
             Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::SymmetricalBincode::<Message>::default(),
+            tokio_serde::formats::SymmetricalBincode::<Frame>::default(),
         );
-        while let Some(message) = stream.try_next().await? {
-            in_tx.send(message)?;
+        while let Some(frame) = stream.try_next().await? {
+            in_tx.send(frame.decode()?)?;
         }
         Ok(())
     }
@@ -150,13 +245,16 @@ impl Server {
         node_id: String,
         peers: HashMap<String, String>,
         mut out_rx: mpsc::UnboundedReceiver<Message>,
+        compression: Compression,
+        compression_threshold: u64,
+        faults: Faults,
     ) -> Result<()> {
         let mut peer_txs: HashMap<String, mpsc::Sender<Message>> = HashMap::new();
 
         for (id, addr) in peers.into_iter() {
             let (tx, rx) = mpsc::channel::<Message>(1000);
             peer_txs.insert(id, tx);
-            tokio::spawn(Self::tcp_send_peer(addr, rx));
+            tokio::spawn(Self::tcp_send_peer(addr, rx, compression, compression_threshold));
         }
 
         while let Some(mut message) = out_rx.next().await {
@@ -172,6 +270,23 @@ impl Server {
                 }
             };
             for id in to {
+                match faults.check(&format!("raft.send.{}", id))? {
+                    Some(Action::Error) => {
+                        debug!("Injected fault, discarding message to peer {}", id);
+                        continue;
+                    }
+                    Some(Action::Delay(delay)) => {
+                        if let Some(tx) = peer_txs.get(&id) {
+                            let (mut tx, message) = (tx.clone(), message.clone());
+                            tokio::spawn(async move {
+                                tokio::time::delay_for(delay).await;
+                                tx.try_send(message).ok();
+                            });
+                            continue;
+                        }
+                    }
+                    None => {}
+                }
                 match peer_txs.get_mut(&id) {
                     Some(tx) => match tx.try_send(message.clone()) {
                         Ok(()) => {}
@@ -188,12 +303,24 @@ impl Server {
     }
 
     /// Sends outbound messages to a peer, continuously reconnecting.
-    async fn tcp_send_peer(addr: String, mut out_rx: mpsc::Receiver<Message>) {
+    async fn tcp_send_peer(
+        addr: String,
+        mut out_rx: mpsc::Receiver<Message>,
+        compression: Compression,
+        compression_threshold: u64,
+    ) {
         loop {
             match TcpStream::connect(&addr).await {
                 Ok(socket) => {
                     debug!("Connected to Raft peer {}", addr);
-                    match Self::tcp_send_peer_session(socket, &mut out_rx).await {
+                    match Self::tcp_send_peer_session(
+                        socket,
+                        &mut out_rx,
+                        compression,
+                        compression_threshold,
+                    )
+                    .await
+                    {
                         Ok(()) => break,
                         Err(err) => error!("Failed sending to Raft peer {}: {}", addr, err),
                     }
@@ -209,14 +336,64 @@ impl Server {
     async fn tcp_send_peer_session(
         socket: TcpStream,
         out_rx: &mut mpsc::Receiver<Message>,
+        compression: Compression,
+        compression_threshold: u64,
     ) -> Result<()> {
-        let mut stream = tokio_serde::SymmetricallyFramed::<_, Message, _>::new(
+        let mut stream = tokio_serde::SymmetricallyFramed::<_, Frame, _>::new(
             Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::SymmetricalBincode::<Message>::default(),
+            tokio_serde::formats::SymmetricalBincode::<Frame>::default(),
         );
         while let Some(message) = out_rx.next().await {
-            stream.send(message).await?;
+            stream.send(Frame::encode(message, compression, compression_threshold)?).await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::Entry;
+    use super::*;
+
+    fn replicate(command: Vec<u8>) -> Message {
+        Message {
+            term: 1,
+            from: Address::Local,
+            to: Address::Peer("b".into()),
+            event: Event::ReplicateEntries {
+                base_index: 0,
+                base_term: 0,
+                entries: vec![Entry {
+                    index: 1,
+                    term: 1,
+                    command: Some(command),
+                    membership: None,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn frame() -> Result<()> {
+        // Large, compressible messages are compressed.
+        let message = replicate(b"abcdefgh".repeat(1024));
+        let frame = Frame::encode(message.clone(), Compression::Snappy, 4096)?;
+        match &frame {
+            Frame::Snappy(compressed) => assert!(compressed.len() < 4096),
+            frame => panic!("Unexpected frame {:?}", frame),
+        }
+        assert_eq!(frame.decode()?, message);
+
+        // Messages below the threshold, or sent without compression, are not.
+        let small = replicate(b"abcdefgh".repeat(8));
+        assert_eq!(
+            Frame::encode(small.clone(), Compression::Snappy, 4096)?,
+            Frame::Message(Box::new(small))
+        );
+        assert_eq!(
+            Frame::encode(message.clone(), Compression::None, 0)?,
+            Frame::Message(Box::new(message))
+        );
+        Ok(())
+    }
+}
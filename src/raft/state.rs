@@ -1,11 +1,20 @@
-use super::{Address, Entry, Event, Message, Response, Scan, Status};
+use super::server::TICK;
+use super::{Address, Entry, Event, Message, Response, Scan, Snapshot, Status};
 use crate::error::{Error, Result};
 
 use log::{debug, error};
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
 use tokio::stream::StreamExt as _;
 use tokio::sync::mpsc;
 
+/// The number of applied entries between state machine snapshots, used to compact the log.
+const SNAPSHOT_INTERVAL: u64 = 1000;
+
+/// How long a local query waits for its index to be applied before it is aborted, e.g. when the
+/// node is lagging or partitioned from the leader.
+const QUERY_LOCAL_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// A Raft-managed state machine.
 pub trait State: Send {
     /// Returns the last applied index from the state machine, used when initializing the driver.
@@ -17,6 +26,57 @@ pub trait State: Send {
 
     /// Queries the state machine. All errors are propagated to the caller.
     fn query(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Flushes any buffered state machine data to the underlying storage medium, e.g. before
+    /// shutting down. Does nothing by default.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Takes a snapshot of the entire state machine at its applied index, used to compact the
+    /// log. Restoring the snapshot must reproduce the exact state, such that later log entries can
+    /// be applied to it.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Restores the state machine from a snapshot taken at the given index, replacing its state.
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()>;
+}
+
+/// A state machine for witness nodes, which only track the applied index and store no data.
+/// Witnesses never serve queries, since they can't be leaders.
+pub struct WitnessState {
+    applied_index: u64,
+}
+
+impl WitnessState {
+    /// Creates a new witness state machine.
+    pub fn new() -> Self {
+        Self { applied_index: 0 }
+    }
+}
+
+impl State for WitnessState {
+    fn applied_index(&self) -> u64 {
+        self.applied_index
+    }
+
+    fn mutate(&mut self, index: u64, _: Vec<u8>) -> Result<Vec<u8>> {
+        self.applied_index = index;
+        Ok(Vec::new())
+    }
+
+    fn query(&self, _: Vec<u8>) -> Result<Vec<u8>> {
+        Err(Error::Internal("Witness nodes can't serve queries".into()))
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn restore(&mut self, index: u64, _: Vec<u8>) -> Result<()> {
+        self.applied_index = index;
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -26,10 +86,16 @@ pub enum Instruction {
     Abort,
     /// Apply a log entry.
     Apply { entry: Entry },
+    /// Flush the state machine to storage, and notify the given address when done.
+    Flush { id: Vec<u8>, address: Address },
     /// Notify the given address with the result of applying the entry at the given index.
     Notify { id: Vec<u8>, address: Address, index: u64 },
     /// Query the state machine when the given term and index has been confirmed by vote.
     Query { id: Vec<u8>, address: Address, command: Vec<u8>, term: u64, index: u64, quorum: u64 },
+    /// Query the state machine once the given index has been applied, without confirmation.
+    QueryLocal { id: Vec<u8>, address: Address, command: Vec<u8>, index: u64 },
+    /// Restore the state machine from a snapshot installed by the leader.
+    Restore { snapshot: Snapshot },
     /// Extend the given server status and return it to the given address.
     Status { id: Vec<u8>, address: Address, status: Box<Status> },
     /// Votes for queries at the given term and commit index.
@@ -44,6 +110,8 @@ struct Query {
     command: Vec<u8>,
     quorum: u64,
     votes: HashSet<Address>,
+    /// When the query is aborted if it hasn't executed yet, for local queries.
+    deadline: Option<Instant>,
 }
 
 /// Drives a state machine, taking operations from state_rx and sending results via node_tx.
@@ -55,6 +123,12 @@ pub struct Driver {
     notify: HashMap<u64, (Address, Vec<u8>)>,
     /// Execute client queries when they receive a quorum. <index, <id, query>>
     queries: BTreeMap<u64, BTreeMap<Vec<u8>, Query>>,
+    /// The applied index of the last state machine snapshot taken or restored by the driver.
+    snapshot_index: u64,
+    /// The number of applied entries between snapshots.
+    snapshot_interval: u64,
+    /// How long local queries wait for their index to be applied.
+    query_local_timeout: Duration,
 }
 
 impl Driver {
@@ -69,14 +143,37 @@ impl Driver {
             applied_index: 0,
             notify: HashMap::new(),
             queries: BTreeMap::new(),
+            snapshot_index: 0,
+            snapshot_interval: SNAPSHOT_INTERVAL,
+            query_local_timeout: QUERY_LOCAL_TIMEOUT,
         }
     }
 
+    /// Sets the number of applied entries between state machine snapshots.
+    pub fn with_snapshot_interval(mut self, interval: u64) -> Self {
+        self.snapshot_interval = interval;
+        self
+    }
+
+    /// Sets how long local queries wait for their index to be applied before being aborted.
+    pub fn with_query_local_timeout(mut self, timeout: Duration) -> Self {
+        self.query_local_timeout = timeout;
+        self
+    }
+
     /// Drives a state machine.
     pub async fn drive(mut self, mut state: Box<dyn State>) -> Result<()> {
         debug!("Starting state machine driver");
-        while let Some(instruction) = self.state_rx.next().await {
-            if let Err(error) = self.execute(instruction, &mut *state).await {
+        let mut ticker = tokio::time::interval(TICK);
+        loop {
+            let result = tokio::select! {
+                instruction = self.state_rx.next() => match instruction {
+                    Some(instruction) => self.execute(instruction, &mut *state).await,
+                    None => break,
+                },
+                _ = ticker.tick() => self.query_expire(Instant::now()),
+            };
+            if let Err(error) = result {
                 error!("Halting state machine due to error: {}", error);
                 return Err(error);
             }
@@ -109,19 +206,34 @@ impl Driver {
             }
 
             Instruction::Apply { entry: Entry { index, command, .. } } => {
-                if let Some(command) = command {
+                let result = if let Some(command) = command {
                     debug!("Applying state machine command {}: {:?}", index, command);
                     match tokio::task::block_in_place(|| state.mutate(index, command)) {
                         Err(error @ Error::Internal(_)) => return Err(error),
-                        result => self.notify_applied(index, result)?,
-                    };
-                }
+                        result => result,
+                    }
+                } else {
+                    // Noops and membership changes have no result, but clients may await them.
+                    Ok(Vec::new())
+                };
                 // We have to track applied_index here, separately from the state machine, because
                 // no-op log entries are significant for whether a query should be executed.
                 self.applied_index = index;
+                // The node is told before the client, so its status reflects what clients saw.
+                self.send(Address::Local, Event::Applied { index })?;
+                self.notify_applied(index, result)?;
                 // Try to execute any pending queries, since they may have been submitted for a
                 // commit_index which hadn't been applied yet.
                 self.query_execute(state)?;
+                if index >= self.snapshot_index + self.snapshot_interval {
+                    self.snapshot(index, state)?;
+                }
+            }
+
+            Instruction::Flush { id, address } => {
+                let response = tokio::task::block_in_place(|| state.flush())
+                    .map(|_| Response::State(Vec::new()));
+                self.send(address, Event::ClientResponse { id, response })?;
             }
 
             Instruction::Notify { id, address, index } => {
@@ -135,15 +247,48 @@ impl Driver {
             Instruction::Query { id, address, command, index, term, quorum } => {
                 self.queries.entry(index).or_default().insert(
                     id.clone(),
-                    Query { id, term, address, command, quorum, votes: HashSet::new() },
+                    Query {
+                        id,
+                        term,
+                        address,
+                        command,
+                        quorum,
+                        votes: HashSet::new(),
+                        deadline: None,
+                    },
+                );
+            }
+
+            Instruction::QueryLocal { id, address, command, index } => {
+                self.queries.entry(index).or_default().insert(
+                    id.clone(),
+                    Query {
+                        id,
+                        term: 0,
+                        address,
+                        command,
+                        quorum: 0,
+                        votes: HashSet::new(),
+                        deadline: Some(Instant::now() + self.query_local_timeout),
+                    },
                 );
+                self.query_execute(state)?;
+            }
+
+            Instruction::Restore { snapshot: Snapshot { index, data, .. } } => {
+                debug!("Restoring state machine from snapshot at index {}", index);
+                tokio::task::block_in_place(|| state.restore(index, data))?;
+                self.applied_index = index;
+                self.snapshot_index = index;
+                self.send(Address::Local, Event::Applied { index })?;
+                self.query_execute(state)?;
             }
 
             Instruction::Status { id, address, mut status } => {
                 status.apply_index = state.applied_index();
                 self.send(
                     address,
-                    Event::ClientResponse { id, response: Ok(Response::Status(*status)) },
+                    Event::ClientResponse { id, response: Ok(Response::Status(status)) },
                 )?;
             }
 
@@ -155,6 +300,23 @@ impl Driver {
         Ok(())
     }
 
+    /// Takes a state machine snapshot at the given applied index and sends it to the node for log
+    /// compaction. Snapshot failures are logged but otherwise ignored, since the log can still
+    /// grow.
+    fn snapshot(&mut self, index: u64, state: &mut dyn State) -> Result<()> {
+        debug!("Taking state machine snapshot at index {}", index);
+        match tokio::task::block_in_place(|| state.snapshot()) {
+            Ok(data) => {
+                self.snapshot_index = index;
+                self.send(Address::Local, Event::CompactLog { index, data })
+            }
+            Err(err) => {
+                error!("Failed to take state machine snapshot: {}", err);
+                Ok(())
+            }
+        }
+    }
+
     /// Aborts all pending notifications.
     fn notify_abort(&mut self) -> Result<()> {
         for (_, (address, id)) in std::mem::replace(&mut self.notify, HashMap::new()) {
@@ -184,6 +346,27 @@ impl Driver {
         Ok(())
     }
 
+    /// Aborts any pending queries whose deadline has passed.
+    fn query_expire(&mut self, now: Instant) -> Result<()> {
+        let mut expired = Vec::new();
+        for queries in self.queries.values_mut() {
+            let ids: Vec<Vec<u8>> = queries
+                .iter()
+                .filter(|(_, q)| q.deadline.map(|d| d <= now).unwrap_or(false))
+                .map(|(id, _)| id.clone())
+                .collect();
+            expired.extend(ids.into_iter().filter_map(|id| queries.remove(&id)));
+        }
+        self.queries.retain(|_, queries| !queries.is_empty());
+        for query in expired {
+            self.send(
+                query.address,
+                Event::ClientResponse { id: query.id, response: Err(Error::Abort) },
+            )?;
+        }
+        Ok(())
+    }
+
     /// Executes any queries that are ready.
     fn query_execute(&mut self, state: &mut dyn State) -> Result<()> {
         for query in self.query_ready(self.applied_index) {
@@ -247,6 +430,7 @@ impl Driver {
 
 #[cfg(test)]
 pub mod tests {
+    use super::super::MembershipChange;
     use super::*;
     use pretty_assertions::assert_eq;
     use std::sync::{Arc, Mutex};
@@ -287,6 +471,18 @@ pub mod tests {
             self.commands.lock()?.push(command.clone());
             Ok(command)
         }
+
+        // Serializes the internal commands list.
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(bincode::serialize(&*self.commands.lock()?)?)
+        }
+
+        // Replaces the internal commands list.
+        fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+            *self.commands.lock()? = bincode::deserialize(&snapshot)?;
+            *self.applied_index.lock()? = index;
+            Ok(())
+        }
     }
 
     async fn setup() -> Result<(
@@ -301,6 +497,16 @@ pub mod tests {
         Ok((state, state_tx, node_rx))
     }
 
+    /// The message the driver sends to the node after applying an entry.
+    fn applied(index: u64) -> Message {
+        Message {
+            from: Address::Local,
+            to: Address::Local,
+            term: 0,
+            event: Event::Applied { index },
+        }
+    }
+
     #[tokio::test(core_threads = 2)]
     async fn driver_abort() -> Result<()> {
         let (state, state_tx, node_rx) = setup().await?;
@@ -345,6 +551,28 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(core_threads = 2)]
+    async fn driver_flush() -> Result<()> {
+        let (_, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::Flush { id: vec![0x01], address: Address::Client })?;
+        std::mem::drop(state_tx);
+
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Client,
+                term: 0,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Ok(Response::State(Vec::new()))
+                }
+            }]
+        );
+        Ok(())
+    }
+
     #[tokio::test(core_threads = 2)]
     async fn driver_apply() -> Result<()> {
         let (state, state_tx, node_rx) = setup().await?;
@@ -354,22 +582,52 @@ pub mod tests {
             index: 2,
             address: Address::Client,
         })?;
-        state_tx.send(Instruction::Apply { entry: Entry { index: 1, term: 1, command: None } })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 2, term: 1, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 1, command: None, membership: None },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 2, term: 1, command: Some(vec![0xaf]), membership: None },
+        })?;
+        // Membership changes are notified with an empty result.
+        state_tx.send(Instruction::Notify {
+            id: vec![0x02],
+            index: 3,
+            address: Address::Client,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry {
+                index: 3,
+                term: 1,
+                command: None,
+                membership: Some(MembershipChange::Drain("a".into())),
+            },
         })?;
         std::mem::drop(state_tx);
         assert_eq!(
             node_rx.collect::<Vec<_>>().await,
-            vec![Message {
-                from: Address::Local,
-                to: Address::Client,
-                term: 0,
-                event: Event::ClientResponse {
-                    id: vec![0x01],
-                    response: Ok(Response::State(vec![0xaf]))
+            vec![
+                applied(1),
+                applied(2),
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::State(vec![0xaf]))
+                    }
+                },
+                applied(3),
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x02],
+                        response: Ok(Response::State(vec![]))
+                    }
                 }
-            }]
+            ]
         );
         assert_eq!(state.list(), vec![vec![0xaf]]);
         assert_eq!(state.applied_index(), 2);
@@ -377,6 +635,66 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(core_threads = 2)]
+    async fn driver_snapshot() -> Result<()> {
+        let state = Box::new(TestState::new(0));
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        tokio::spawn(Driver::new(state_rx, node_tx).with_snapshot_interval(2).drive(state.clone()));
+
+        for index in 1..=3 {
+            state_tx.send(Instruction::Apply {
+                entry: Entry { index, term: 1, command: Some(vec![index as u8]), membership: None },
+            })?;
+        }
+        std::mem::drop(state_tx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![
+                applied(1),
+                applied(2),
+                Message {
+                    from: Address::Local,
+                    to: Address::Local,
+                    term: 0,
+                    event: Event::CompactLog {
+                        index: 2,
+                        data: bincode::serialize(&vec![vec![0x01u8], vec![0x02u8]])?,
+                    },
+                },
+                applied(3),
+            ]
+        );
+        assert_eq!(state.applied_index(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test(core_threads = 2)]
+    async fn driver_restore() -> Result<()> {
+        let (state, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+        })?;
+        state_tx.send(Instruction::Restore {
+            snapshot: Snapshot {
+                index: 3,
+                term: 2,
+                data: bincode::serialize(&vec![vec![0x0au8], vec![0x0bu8]])?,
+            },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 4, term: 2, command: Some(vec![0x0c]), membership: None },
+        })?;
+        std::mem::drop(state_tx);
+        assert_eq!(node_rx.collect::<Vec<_>>().await, vec![applied(1), applied(3), applied(4)]);
+        assert_eq!(state.list(), vec![vec![0x0a], vec![0x0b], vec![0x0c]]);
+        assert_eq!(state.applied_index(), 4);
+
+        Ok(())
+    }
+
     #[tokio::test(core_threads = 2)]
     async fn driver_query() -> Result<()> {
         let (_, state_tx, node_rx) = setup().await?;
@@ -390,7 +708,7 @@ pub mod tests {
             quorum: 2,
         })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 1, term: 2, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 2, command: Some(vec![0xaf]), membership: None },
         })?;
         state_tx.send(Instruction::Vote { term: 2, index: 1, address: Address::Local })?;
         state_tx.send(Instruction::Vote {
@@ -402,15 +720,18 @@ pub mod tests {
 
         assert_eq!(
             node_rx.collect::<Vec<_>>().await,
-            vec![Message {
-                from: Address::Local,
-                to: Address::Client,
-                term: 0,
-                event: Event::ClientResponse {
-                    id: vec![0x01],
-                    response: Ok(Response::State(vec![0xf0]))
+            vec![
+                applied(1),
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::State(vec![0xf0]))
+                    }
                 }
-            }]
+            ]
         );
 
         Ok(())
@@ -430,7 +751,7 @@ pub mod tests {
             quorum: 2,
         })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
         })?;
         state_tx.send(Instruction::Vote { term: 2, index: 1, address: Address::Local })?;
         state_tx.send(Instruction::Vote {
@@ -440,7 +761,7 @@ pub mod tests {
         })?;
         std::mem::drop(state_tx);
 
-        assert_eq!(node_rx.collect::<Vec<_>>().await, vec![]);
+        assert_eq!(node_rx.collect::<Vec<_>>().await, vec![applied(1)]);
         Ok(())
     }
 
@@ -457,12 +778,89 @@ pub mod tests {
             quorum: 2,
         })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
         })?;
         state_tx.send(Instruction::Vote { term: 1, index: 1, address: Address::Local })?;
         std::mem::drop(state_tx);
 
-        assert_eq!(node_rx.collect::<Vec<_>>().await, vec![]);
+        assert_eq!(node_rx.collect::<Vec<_>>().await, vec![applied(1)]);
+
+        Ok(())
+    }
+
+    // A local query is executed once its index has been applied, without any votes.
+    #[tokio::test(core_threads = 2)]
+    async fn driver_query_local() -> Result<()> {
+        let (_, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::QueryLocal {
+            id: vec![0x01],
+            address: Address::Client,
+            command: vec![0xf0],
+            index: 1,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
+        })?;
+        state_tx.send(Instruction::QueryLocal {
+            id: vec![0x02],
+            address: Address::Client,
+            command: vec![0xf1],
+            index: 1,
+        })?;
+        std::mem::drop(state_tx);
+
+        let response = |id: u8, command: u8| Message {
+            from: Address::Local,
+            to: Address::Client,
+            term: 0,
+            event: Event::ClientResponse {
+                id: vec![id],
+                response: Ok(Response::State(vec![command])),
+            },
+        };
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![applied(1), response(0x01, 0xf0), response(0x02, 0xf1)]
+        );
+
+        Ok(())
+    }
+
+    // A local query is aborted if its index isn't applied before the timeout.
+    #[tokio::test(core_threads = 2)]
+    async fn driver_query_local_timeout() -> Result<()> {
+        let state = Box::new(TestState::new(0));
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let driver =
+            Driver::new(state_rx, node_tx).with_query_local_timeout(Duration::from_millis(100));
+        tokio::spawn(driver.drive(state));
+
+        state_tx.send(Instruction::QueryLocal {
+            id: vec![0x01],
+            address: Address::Client,
+            command: vec![0xf0],
+            index: 2,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
+        })?;
+        tokio::time::delay_for(Duration::from_millis(500)).await;
+        std::mem::drop(state_tx);
+
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![
+                applied(1),
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse { id: vec![0x01], response: Err(Error::Abort) },
+                }
+            ]
+        );
 
         Ok(())
     }
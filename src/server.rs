@@ -1,26 +1,141 @@
 use crate::error::{Error, Result};
+use crate::fault::Faults;
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::http;
 use crate::raft;
 use crate::sql;
 use crate::sql::engine::{Engine as _, Mode};
 use crate::sql::execution::ResultSet;
+use crate::sql::parser::split_statements;
 use crate::sql::schema::{Catalog as _, Table};
-use crate::sql::types::Row;
-use crate::storage::{kv, log};
+use crate::sql::types::{DataType, Row, Value};
+use crate::storage::{kv, log, Compression};
+use crate::trace::{span, TraceContext};
 
-use ::log::{error, info};
+use ::log::{error, info, warn};
+use futures::future::FutureExt as _;
 use futures::sink::SinkExt as _;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::stream::StreamExt as _;
 use tokio::sync::mpsc;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+/// The interval at which decommissioning polls for open transactions and leadership transfer.
+const DECOMMISSION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum time decommissioning waits for Raft leadership to be transferred away before
+/// removing the node anyway, in which case it steps down as leader on removal.
+const DECOMMISSION_TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default maximum number of committed log entries a node's state machine can lag behind
+/// before the node is considered not ready by health checks.
+pub const DEFAULT_MAX_APPLY_LAG: u64 = 1000;
+
+/// The default time a graceful shutdown waits for open transactions to finish.
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// The interval at which a graceful shutdown polls for open transactions and leadership transfer.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The interval at which a graceful shutdown retries Raft leadership transfers, e.g. if the
+/// chosen peer failed to win the election.
+const SHUTDOWN_TRANSFER_RETRY: Duration = Duration::from_secs(1);
+
+/// The maximum time a graceful shutdown waits for Raft leadership to be transferred away.
+const SHUTDOWN_TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum time to wait for the first request of a rejected client connection, in order to
+/// respond with an error.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The interval at which rolling back orphaned transactions on startup is retried, e.g. while
+/// there is no Raft leader.
+const ORPHANED_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The interval at which detached transactions are checked for expiry, if enabled.
+const DETACHED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The interval at which interrupted online schema changes are checked for and completed.
+const SCHEMA_CHANGE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The interval at which the server's load is sampled for admission control.
+const ADMISSION_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Client connection and per-user resource limits, where 0 means unlimited. Users are identified
+/// by their client IP address.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// The maximum number of client connections.
+    pub max_connections: u64,
+    /// The maximum number of concurrently executing queries per user.
+    pub max_user_queries: u64,
+    /// The maximum number of open transactions per user.
+    pub max_user_txns: u64,
+}
+
+/// Admission control thresholds, where 0 means unlimited. While any threshold is exceeded, new
+/// statements outside of open transactions are rejected with Error::Overloaded, such that clients
+/// back off and retry instead of piling up requests and letting latency collapse. Statements in
+/// open transactions are still admitted, so that they can finish and release their locks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Admission {
+    /// The maximum number of local Raft log entries not yet applied to the state machine. On the
+    /// leader, this is the backlog of proposals waiting to be committed and applied.
+    pub max_raft_backlog: u64,
+    /// The maximum resident memory of the server process, in bytes. Only measured on Linux.
+    pub max_memory: u64,
+    /// The maximum system CPU utilization, as a percentage of all cores. Only measured on Linux.
+    pub max_cpu: u64,
+}
+
+/// Client session timeouts, where 0 means no timeout.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timeouts {
+    /// The maximum time a session can be idle with an open transaction, after which the
+    /// transaction is rolled back and the connection closed.
+    pub idle_in_transaction: Duration,
+    /// The maximum time a session can be idle outside of a transaction, after which the
+    /// connection is closed.
+    pub idle_session: Duration,
+    /// The maximum time a transaction can remain detached from any session, after which it is
+    /// rolled back.
+    pub detached: Duration,
+}
+
+impl Timeouts {
+    /// Returns the idle timeout for a session, depending on whether it has an open transaction.
+    fn idle(&self, has_txn: bool) -> Option<Duration> {
+        let timeout = if has_txn { self.idle_in_transaction } else { self.idle_session };
+        Some(timeout).filter(|t| *t > Duration::from_secs(0))
+    }
+}
+
 /// A toyDB server.
 pub struct Server {
+    id: String,
+    /// The process epoch, incremented on every start, which owns the server's transactions.
+    epoch: u64,
     raft: raft::Server,
     raft_listener: Option<TcpListener>,
     sql_listener: Option<TcpListener>,
+    http_listener: Option<TcpListener>,
+    #[cfg(feature = "grpc")]
+    grpc_listener: Option<TcpListener>,
+    audit_log: bool,
+    max_apply_lag: u64,
+    limits: Limits,
+    admission: Admission,
+    timeouts: Timeouts,
+    gc_interval: Duration,
+    shutdown_grace_period: Duration,
 }
 
 impl Server {
@@ -31,19 +146,140 @@ impl Server {
         raft_store: Box<dyn log::Store>,
         sql_store: Box<dyn kv::Store>,
     ) -> Result<Self> {
+        let mut log = raft::Log::new(raft_store)?;
+        let epoch = log.next_epoch()?;
         Ok(Server {
+            id: id.to_string(),
+            epoch,
             raft: raft::Server::new(
                 id,
                 peers,
-                raft::Log::new(raft_store)?,
+                log,
                 Box::new(sql::engine::Raft::new_state(kv::MVCC::new(sql_store))?),
             )
             .await?,
             raft_listener: None,
             sql_listener: None,
+            http_listener: None,
+            #[cfg(feature = "grpc")]
+            grpc_listener: None,
+            audit_log: false,
+            max_apply_lag: DEFAULT_MAX_APPLY_LAG,
+            limits: Limits::default(),
+            admission: Admission::default(),
+            timeouts: Timeouts::default(),
+            gc_interval: Duration::from_secs(0),
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+        })
+    }
+
+    /// Creates a new toyDB witness server, which takes part in Raft elections and commits but
+    /// stores no SQL data, e.g. as a tiebreaker for two-datacenter deployments. SQL clients can
+    /// still connect to it, and have their requests forwarded to the leader.
+    pub async fn new_witness(
+        id: &str,
+        peers: HashMap<String, String>,
+        raft_store: Box<dyn log::Store>,
+    ) -> Result<Self> {
+        let mut log = raft::Log::new(raft_store)?;
+        let epoch = log.next_epoch()?;
+        Ok(Server {
+            id: id.to_string(),
+            epoch,
+            raft: raft::Server::new(id, peers, log, Box::new(raft::WitnessState::new()))
+                .await?
+                .with_options(raft::Options { witness: true, ..raft::Options::default() })?,
+            raft_listener: None,
+            sql_listener: None,
+            http_listener: None,
+            #[cfg(feature = "grpc")]
+            grpc_listener: None,
+            audit_log: false,
+            max_apply_lag: DEFAULT_MAX_APPLY_LAG,
+            limits: Limits::default(),
+            admission: Admission::default(),
+            timeouts: Timeouts::default(),
+            gc_interval: Duration::from_secs(0),
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
         })
     }
 
+    /// Injects faults into Raft messages sent to peers, for chaos testing. See
+    /// raft::Server::with_faults() for details.
+    pub fn with_raft_faults(mut self, faults: Faults) -> Self {
+        self.raft = self.raft.with_faults(faults);
+        self
+    }
+
+    /// Sets the Raft node options, e.g. heartbeat and election timeouts.
+    pub fn with_raft_options(mut self, options: raft::Options) -> Result<Self> {
+        self.raft = self.raft.with_options(options)?;
+        Ok(self)
+    }
+
+    /// Compresses Raft messages sent to peers whose encoded size is at or above the given
+    /// threshold in bytes, e.g. log entries sent to lagging followers and snapshots.
+    pub fn with_raft_compression(mut self, compression: Compression, threshold: u64) -> Self {
+        self.raft = self.raft.with_compression(compression, threshold);
+        self
+    }
+
+    /// Enables Raft leader leases, allowing the leader to serve reads locally without a network
+    /// round-trip, given the maximum clock skew between nodes.
+    pub fn with_lease(mut self, max_skew: std::time::Duration) -> Result<Self> {
+        self.raft = self.raft.with_lease(max_skew)?;
+        Ok(self)
+    }
+
+    /// Enables the audit log, which records DDL and DML statements executed by clients in the
+    /// system.audit table, keyed by the client address.
+    pub fn with_audit_log(mut self, audit_log: bool) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Sets the maximum number of committed log entries the node's state machine can lag behind
+    /// before the node is considered not ready by the /readyz health check.
+    pub fn with_max_apply_lag(mut self, max_apply_lag: u64) -> Self {
+        self.max_apply_lag = max_apply_lag;
+        self
+    }
+
+    /// Sets client connection and per-user resource limits. Requests exceeding them fail with
+    /// Error::Limit.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets admission control thresholds. New statements are rejected with Error::Overloaded while
+    /// the server's load exceeds them.
+    pub fn with_admission(mut self, admission: Admission) -> Self {
+        self.admission = admission;
+        self
+    }
+
+    /// Sets client session timeouts, which close idle connections and roll back abandoned
+    /// transactions.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Sets the interval between MVCC garbage collection passes, which remove history older than
+    /// the mvcc_retention_ms cluster setting, or 0 to disable garbage collection.
+    pub fn with_gc_interval(mut self, gc_interval: Duration) -> Self {
+        self.gc_interval = gc_interval;
+        self
+    }
+
+    /// Sets the time a graceful shutdown waits for open transactions to finish before shutting
+    /// down anyway, see serve_until().
+    pub fn with_shutdown_grace_period(mut self, grace_period: Duration) -> Self {
+        self.shutdown_grace_period = grace_period;
+        self
+    }
+
     /// Starts listening on the given ports. Must be called before serve.
     pub async fn listen(mut self, sql_addr: &str, raft_addr: &str) -> Result<Self> {
         let (sql, raft) =
@@ -54,8 +290,35 @@ impl Server {
         Ok(self)
     }
 
+    /// Starts listening for HTTP requests on the given address, serving the /healthz and /readyz
+    /// health checks and the /sql query endpoint. Optional, but must be called before serve.
+    pub async fn listen_http(mut self, http_addr: &str) -> Result<Self> {
+        let http = TcpListener::bind(http_addr).await?;
+        info!("Listening on {} (HTTP)", http.local_addr()?);
+        self.http_listener = Some(http);
+        Ok(self)
+    }
+
+    /// Starts listening for gRPC clients on the given address, serving the client protocol defined
+    /// in proto/toydb.proto. Optional, but must be called before serve.
+    #[cfg(feature = "grpc")]
+    pub async fn listen_grpc(mut self, grpc_addr: &str) -> Result<Self> {
+        let grpc = TcpListener::bind(grpc_addr).await?;
+        info!("Listening on {} (gRPC)", grpc.local_addr()?);
+        self.grpc_listener = Some(grpc);
+        Ok(self)
+    }
+
     /// Serves Raft and SQL requests until the returned future is dropped. Consumes the server.
     pub async fn serve(self) -> Result<()> {
+        self.serve_until(futures::future::pending()).await
+    }
+
+    /// Serves Raft and SQL requests until the given shutdown future completes, and then shuts
+    /// down gracefully: new client connections are refused, open transactions are given the
+    /// shutdown grace period to finish, Raft leadership is transferred to a peer, and the Raft
+    /// log and SQL storage are flushed. Consumes the server.
+    pub async fn serve_until(self, shutdown: impl Future<Output = ()>) -> Result<()> {
         let sql_listener = self
             .sql_listener
             .ok_or_else(|| Error::Internal("Must listen before serving".into()))?;
@@ -63,20 +326,93 @@ impl Server {
             .raft_listener
             .ok_or_else(|| Error::Internal("Must listen before serving".into()))?;
         let (raft_tx, raft_rx) = mpsc::unbounded_channel();
-        let sql_engine = sql::engine::Raft::new(raft::Client::new(raft_tx));
+        let raft_client = raft::Client::new(raft_tx);
+        let sql_engine =
+            sql::engine::Raft::new(raft_client.clone()).with_owner(&self.id, self.epoch);
 
-        tokio::try_join!(
-            self.raft.serve(raft_listener, raft_rx),
-            Self::serve_sql(sql_listener, sql_engine),
-        )?;
-        Ok(())
+        let drain = Arc::new(Drain::default());
+        let admission = Arc::new(AdmissionController::new(self.admission));
+        let sessions = SessionFactory {
+            engine: sql_engine.clone(),
+            raft: raft_client.clone(),
+            drain: drain.clone(),
+            limiter: Arc::new(Limiter::new(self.limits)),
+            admission: admission.clone(),
+            timeouts: self.timeouts,
+            audit_log: self.audit_log,
+        };
+
+        let (http_listener, http_raft, http_sessions, max_apply_lag) =
+            (self.http_listener, raft_client.clone(), sessions.clone(), self.max_apply_lag);
+        let serve_http = async move {
+            match http_listener {
+                Some(listener) => {
+                    http::serve(listener, http_raft, http_sessions, max_apply_lag).await
+                }
+                None => Ok(()),
+            }
+        };
+
+        #[cfg(feature = "grpc")]
+        let serve_grpc = {
+            let (grpc_listener, service) =
+                (self.grpc_listener, grpc::Service::new(sessions.clone()));
+            async move {
+                match grpc_listener {
+                    Some(listener) => grpc::serve(listener, service).await,
+                    None => Ok(()),
+                }
+            }
+        };
+        #[cfg(not(feature = "grpc"))]
+        let serve_grpc = futures::future::ok(());
+
+        // Raft, HTTP, and gRPC keep running during shutdown, until their handles are dropped on
+        // return. HTTP and gRPC sessions are drained along with SQL sessions.
+        let (task, raft) = self.raft.serve(raft_listener, raft_rx).remote_handle();
+        tokio::spawn(task);
+        let (task, http) = serve_http.remote_handle();
+        tokio::spawn(task);
+        let (task, grpc) = serve_grpc.remote_handle();
+        tokio::spawn(task);
+        let (task, _sampler) = Self::sample_load(admission, raft_client.clone()).remote_handle();
+        if self.admission != Admission::default() {
+            tokio::spawn(task);
+        }
+        tokio::spawn(Self::rollback_orphaned(sql_engine.clone()));
+        let (task, _reaper) =
+            Self::rollback_detached(sql_engine.clone(), self.timeouts.detached).remote_handle();
+        if self.timeouts.detached > Duration::from_secs(0) {
+            tokio::spawn(task);
+        }
+        let (task, _schema_changes) =
+            Self::complete_schema_changes(sql_engine.clone()).remote_handle();
+        tokio::spawn(task);
+        let (task, _gc) = Self::gc(sql_engine.clone(), self.gc_interval).remote_handle();
+        if self.gc_interval > Duration::from_secs(0) {
+            tokio::spawn(task);
+        }
+        let serving = futures::future::try_join3(raft, http, grpc);
+        tokio::select! {
+            result = serving => return result.map(|_| ()),
+            result = Self::serve_sql(sql_listener, sessions) => return result,
+            _ = shutdown => {}
+        }
+        Self::shutdown(&raft_client, &drain, self.shutdown_grace_period).await
     }
 
     /// Serves SQL clients.
-    async fn serve_sql(mut listener: TcpListener, engine: sql::engine::Raft) -> Result<()> {
+    async fn serve_sql(mut listener: TcpListener, sessions: SessionFactory) -> Result<()> {
         while let Some(socket) = listener.try_next().await? {
             let peer = socket.peer_addr()?;
-            let session = Session::new(engine.clone())?;
+            let session = match sessions.session(peer) {
+                Ok(session) => session,
+                Err(err) => {
+                    warn!("Rejecting client {}: {}", peer, err);
+                    tokio::spawn(Self::reject(socket, err));
+                    continue;
+                }
+            };
             tokio::spawn(async move {
                 info!("Client {} connected", peer);
                 match session.handle(socket).await {
@@ -87,47 +423,570 @@ impl Server {
         }
         Ok(())
     }
+
+    /// Rolls back transactions orphaned by a previous process of this node, e.g. after a crash,
+    /// since their sessions no longer exist and they would otherwise block conflicting writers
+    /// forever. Retries until it succeeds, e.g. once a Raft leader is elected.
+    async fn rollback_orphaned(engine: sql::engine::Raft) {
+        loop {
+            let engine = engine.clone();
+            match tokio::task::spawn_blocking(move || engine.rollback_orphaned()).await {
+                Ok(Ok(count)) => {
+                    if count > 0 {
+                        info!("Rolled back {} orphaned transactions", count);
+                    }
+                    return;
+                }
+                Ok(Err(err)) => warn!("Failed to roll back orphaned transactions: {}", err),
+                Err(err) => {
+                    error!("Failed to roll back orphaned transactions: {}", err);
+                    return;
+                }
+            }
+            tokio::time::delay_for(ORPHANED_RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Rolls back transactions that have been detached for longer than the given timeout at
+    /// regular intervals, until dropped, since no session will otherwise finish them.
+    async fn rollback_detached(engine: sql::engine::Raft, timeout: Duration) {
+        let mut ticker = tokio::time::interval(DETACHED_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let engine = engine.clone();
+            let before = std::time::SystemTime::now()
+                .checked_sub(timeout)
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+            match tokio::task::spawn_blocking(move || engine.rollback_detached(before)).await {
+                Ok(Ok(count)) => {
+                    if count > 0 {
+                        info!("Rolled back {} expired detached transactions", count);
+                    }
+                }
+                Ok(Err(err)) => warn!("Failed to roll back detached transactions: {}", err),
+                Err(err) => {
+                    error!("Failed to roll back detached transactions: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Completes online schema changes interrupted e.g. by a crash at regular intervals, until
+    /// dropped. Otherwise, writes would maintain a partially built column or index until the next
+    /// schema change. The first check is after an interval, since checking begins a transaction.
+    async fn complete_schema_changes(engine: sql::engine::Raft) {
+        let start = tokio::time::Instant::now() + SCHEMA_CHANGE_CHECK_INTERVAL;
+        let mut ticker = tokio::time::interval_at(start, SCHEMA_CHANGE_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let engine = engine.clone();
+            match tokio::task::spawn_blocking(move || {
+                engine.session()?.complete_schema_changes(sql::engine::SCHEMA_CHANGE_TIMEOUT)
+            })
+            .await
+            {
+                Ok(Ok(count)) => {
+                    if count > 0 {
+                        info!("Completed {} interrupted schema changes", count);
+                    }
+                }
+                Ok(Err(err)) => warn!("Failed to complete schema changes: {}", err),
+                Err(err) => {
+                    error!("Failed to complete schema changes: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Garbage collects old MVCC versions at regular intervals, until dropped. Each pass runs as a
+    /// series of Raft commands that each garbage collect a batch of records, such that the state
+    /// machine isn't blocked for long.
+    async fn gc(engine: sql::engine::Raft, interval: Duration) {
+        loop {
+            tokio::time::delay_for(interval).await;
+            let mut removed = 0;
+            loop {
+                let engine = engine.clone();
+                match tokio::task::spawn_blocking(move || engine.gc()).await {
+                    Ok(Ok((count, done))) => {
+                        removed += count;
+                        if done {
+                            break;
+                        }
+                    }
+                    Ok(Err(err)) => {
+                        warn!("Failed to garbage collect MVCC versions: {}", err);
+                        break;
+                    }
+                    Err(err) => {
+                        error!("Failed to garbage collect MVCC versions: {}", err);
+                        return;
+                    }
+                }
+            }
+            if removed > 0 {
+                info!("Garbage collected {} MVCC versions", removed);
+            }
+        }
+    }
+
+    /// Samples the server's load at regular intervals for admission control, until dropped.
+    async fn sample_load(admission: Arc<AdmissionController>, raft: raft::Client) {
+        let mut cpu = CpuSampler::default();
+        let mut ticker = tokio::time::interval(ADMISSION_SAMPLE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let raft_backlog = match raft.health().await {
+                Ok(health) => health.last_index.saturating_sub(health.apply_index),
+                Err(err) => {
+                    warn!("Failed to sample Raft backlog: {}", err);
+                    continue;
+                }
+            };
+            let load = Load { raft_backlog, memory: process_memory(), cpu: cpu.sample() };
+            if let Err(err) = admission.update(load) {
+                error!("Failed to update admission control: {}", err);
+                return;
+            }
+        }
+    }
+
+    /// Shuts down gracefully, once the SQL listener has been closed. Existing sessions are
+    /// drained, rejecting requests outside of open transactions, and open transactions are given
+    /// the grace period to finish. Raft leadership is then transferred to a peer, and finally the
+    /// Raft log and state machine are flushed to storage.
+    async fn shutdown(raft: &raft::Client, drain: &Drain, grace_period: Duration) -> Result<()> {
+        info!("Shutting down, waiting up to {:?} for open transactions", grace_period);
+        drain.draining.store(true, Ordering::SeqCst);
+        let started = Instant::now();
+        loop {
+            let txns = drain.txns.load(Ordering::SeqCst);
+            if txns == 0 {
+                break;
+            } else if started.elapsed() >= grace_period {
+                warn!("Shutting down with {} open transactions", txns);
+                break;
+            }
+            tokio::time::delay_for(SHUTDOWN_POLL_INTERVAL).await;
+        }
+
+        if let Err(err) = Self::shutdown_transfer(raft).await {
+            warn!("Failed to transfer Raft leadership: {}", err);
+        }
+
+        info!("Flushing storage");
+        raft.flush().await?;
+        info!("Shut down");
+        Ok(())
+    }
+
+    /// Transfers Raft leadership away from the local node during shutdown, if it is the leader
+    /// and has peers, and waits for the transfer to complete.
+    async fn shutdown_transfer(raft: &raft::Client) -> Result<()> {
+        if raft.health().await?.role != "leader" || raft.status().await?.node_last_index.len() < 2 {
+            return Ok(());
+        }
+        info!("Transferring Raft leadership");
+        let started = Instant::now();
+        let mut transferred = None;
+        while raft.health().await?.role == "leader" {
+            if started.elapsed() >= SHUTDOWN_TRANSFER_TIMEOUT {
+                return Err(Error::Internal("Timed out".into()));
+            }
+            if transferred.is_none_or(|t: Instant| t.elapsed() >= SHUTDOWN_TRANSFER_RETRY) {
+                raft.transfer_leadership().await?;
+                transferred = Some(Instant::now());
+            }
+            tokio::time::delay_for(SHUTDOWN_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Rejects a client connection, by responding to its first request with the given error.
+    async fn reject(socket: TcpStream, error: Error) {
+        let mut stream = tokio_serde::Framed::<_, Request, Result<Response>, _>::new(
+            Framed::new(socket, LengthDelimitedCodec::new()),
+            tokio_serde::formats::Bincode::default(),
+        );
+        if let Ok(Some(Ok(_))) = tokio::time::timeout(REJECT_TIMEOUT, stream.next()).await {
+            stream.send(Err(error)).await.ok();
+        }
+    }
 }
 
 /// A client request.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Request {
     Execute(String),
+    ExecuteTraced(String, TraceContext),
+    /// Executes a query with values for its ? bind parameters, by position.
+    ExecuteParams(String, Vec<Value>),
+    /// Executes a batch of queries in order, stopping at the first error. Each query may contain
+    /// several ;-separated statements, which are executed separately.
+    ExecuteBatch(Vec<String>),
+    /// Describes a query without executing it, returning the datatypes of its ? bind parameters.
+    Describe(String),
+    /// Bulk loads rows into a table, bypassing SQL execution.
+    Ingest(String, Vec<Row>),
     GetTable(String),
     ListTables,
     Status,
+    Decommission,
 }
 
 /// A server response.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Execute(ResultSet),
+    /// The results of the executed statements in a batch, with any query result rows buffered
+    /// alongside the result set since they aren't serialized with it.
+    ExecuteBatch(Vec<Result<(ResultSet, Vec<Row>)>>),
+    /// The datatypes of the described query's bind parameters by position, None if unknown.
+    Describe(Vec<Option<DataType>>),
+    /// The number of rows loaded by an ingest request.
+    Ingest(u64),
     Row(Option<Row>),
     GetTable(Table),
     ListTables(Vec<String>),
     Status(sql::engine::Status),
+    Decommission(String),
+}
+
+/// Tracks open transactions across client sessions, such that the server can be drained.
+#[derive(Default)]
+struct Drain {
+    /// Set when the server is draining, rejecting requests outside of open transactions.
+    draining: AtomicBool,
+    /// The number of sessions with an open transaction.
+    txns: AtomicU64,
+}
+
+/// A resource subject to limits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Resource {
+    Connection,
+    Query(IpAddr),
+    Txn(IpAddr),
+}
+
+/// Resource usage for a single user.
+#[derive(Default)]
+struct UserUsage {
+    queries: u64,
+    txns: u64,
+}
+
+/// Enforces client connection and per-user resource limits across client sessions.
+struct Limiter {
+    limits: Limits,
+    connections: Mutex<u64>,
+    users: Mutex<HashMap<IpAddr, UserUsage>>,
+}
+
+impl Limiter {
+    /// Creates a new limiter with the given limits.
+    fn new(limits: Limits) -> Self {
+        Self { limits, connections: Mutex::new(0), users: Mutex::new(HashMap::new()) }
+    }
+
+    /// Acquires a resource, returning a slot which releases it when dropped, or errors if this
+    /// would exceed the limit.
+    fn acquire(self: &Arc<Self>, resource: Resource) -> Result<Slot> {
+        let mut connections = self.connections.lock()?;
+        let mut users = self.users.lock()?;
+        let (count, limit, what) = match resource {
+            Resource::Connection => {
+                (&mut *connections, self.limits.max_connections, "connections".to_string())
+            }
+            Resource::Query(ip) => (
+                &mut users.entry(ip).or_default().queries,
+                self.limits.max_user_queries,
+                format!("concurrent queries for user {}", ip),
+            ),
+            Resource::Txn(ip) => (
+                &mut users.entry(ip).or_default().txns,
+                self.limits.max_user_txns,
+                format!("open transactions for user {}", ip),
+            ),
+        };
+        if limit > 0 && *count >= limit {
+            return Err(Error::Limit(format!("Too many {} (max {})", what, limit)));
+        }
+        *count += 1;
+        Ok(Slot { limiter: self.clone(), resource })
+    }
+
+    /// Releases a resource acquired by a slot.
+    fn release(&self, resource: Resource) -> Result<()> {
+        let ip = match resource {
+            Resource::Connection => {
+                *self.connections.lock()? -= 1;
+                return Ok(());
+            }
+            Resource::Query(ip) | Resource::Txn(ip) => ip,
+        };
+        let mut users = self.users.lock()?;
+        if let Some(usage) = users.get_mut(&ip) {
+            match resource {
+                Resource::Query(_) => usage.queries -= 1,
+                _ => usage.txns -= 1,
+            }
+            if usage.queries == 0 && usage.txns == 0 {
+                users.remove(&ip);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An acquired resource, which is released when dropped.
+pub(crate) struct Slot {
+    limiter: Arc<Limiter>,
+    resource: Resource,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        // Errors can only be caused by a poisoned lock, and are ignored.
+        self.limiter.release(self.resource).ok();
+    }
+}
+
+/// A sample of the server's load, for admission control. Metrics that can't be measured on the
+/// current platform are None.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Load {
+    /// The number of local Raft log entries not yet applied to the state machine.
+    raft_backlog: u64,
+    /// The resident memory of the server process, in bytes.
+    memory: Option<u64>,
+    /// The system CPU utilization since the previous sample, as a percentage of all cores.
+    cpu: Option<u64>,
+}
+
+/// Admits new statements based on the most recent load sample, see Admission.
+struct AdmissionController {
+    admission: Admission,
+    /// The reason the server is overloaded as of the last load sample, if it is.
+    overloaded: Mutex<Option<String>>,
+}
+
+impl AdmissionController {
+    /// Creates a new admission controller with the given thresholds.
+    fn new(admission: Admission) -> Self {
+        Self { admission, overloaded: Mutex::new(None) }
+    }
+
+    /// Admits a new statement, or errors if the server is overloaded.
+    fn admit(&self) -> Result<()> {
+        match &*self.overloaded.lock()? {
+            Some(reason) => {
+                Err(Error::Overloaded(format!("Server overloaded ({}), retry later", reason)))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Updates the overload status from a new load sample.
+    fn update(&self, load: Load) -> Result<()> {
+        let reason = self.check(load);
+        let mut overloaded = self.overloaded.lock()?;
+        match (&*overloaded, &reason) {
+            (None, Some(reason)) => {
+                warn!("Server overloaded, rejecting new statements: {}", reason)
+            }
+            (Some(_), None) => info!("Server no longer overloaded, admitting new statements"),
+            _ => {}
+        }
+        *overloaded = reason;
+        Ok(())
+    }
+
+    /// Checks a load sample against the thresholds, returning the reason if any is exceeded.
+    fn check(&self, load: Load) -> Option<String> {
+        let Admission { max_raft_backlog, max_memory, max_cpu } = self.admission;
+        if max_raft_backlog > 0 && load.raft_backlog > max_raft_backlog {
+            return Some(format!(
+                "Raft backlog of {} entries exceeds {}",
+                load.raft_backlog, max_raft_backlog
+            ));
+        }
+        match load.memory {
+            Some(memory) if max_memory > 0 && memory > max_memory => {
+                return Some(format!("memory usage of {} bytes exceeds {}", memory, max_memory))
+            }
+            _ => {}
+        }
+        match load.cpu {
+            Some(cpu) if max_cpu > 0 && cpu > max_cpu => {
+                return Some(format!("CPU usage of {}% exceeds {}%", cpu, max_cpu))
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Returns the resident memory of the server process in bytes, if it can be measured, i.e. on
+/// Linux.
+fn process_memory() -> Option<u64> {
+    parse_memory(&std::fs::read_to_string("/proc/self/status").ok()?)
+}
+
+/// Parses the resident memory in bytes from the contents of /proc/self/status.
+fn parse_memory(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Measures the system CPU utilization between samples, from the cumulative CPU times in
+/// /proc/stat, i.e. on Linux.
+#[derive(Default)]
+struct CpuSampler {
+    /// The busy and total CPU times as of the previous sample.
+    previous: Option<(u64, u64)>,
+}
+
+impl CpuSampler {
+    /// Returns the CPU utilization since the previous sample as a percentage, if it can be
+    /// measured. The first sample only records the current CPU times, and returns None.
+    fn sample(&mut self) -> Option<u64> {
+        let (busy, total) = parse_cpu_times(&std::fs::read_to_string("/proc/stat").ok()?)?;
+        let (previous_busy, previous_total) = self.previous.replace((busy, total))?;
+        match total.saturating_sub(previous_total) {
+            0 => None,
+            elapsed => Some(busy.saturating_sub(previous_busy) * 100 / elapsed),
+        }
+    }
+}
+
+/// Parses the cumulative busy and total CPU times across all cores from the contents of
+/// /proc/stat, i.e. the user, nice, system, idle, iowait, irq, softirq, and steal times of the
+/// first line. Idle and iowait time count as idle.
+fn parse_cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let times = stat
+        .lines()
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .take(8)
+        .map(|time| time.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let total: u64 = times.iter().sum();
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some((total.saturating_sub(idle), total))
+}
+
+/// Creates client sessions, sharing drain and limit state across all client protocols.
+#[derive(Clone)]
+pub(crate) struct SessionFactory {
+    engine: sql::engine::Raft,
+    raft: raft::Client,
+    drain: Arc<Drain>,
+    limiter: Arc<Limiter>,
+    admission: Arc<AdmissionController>,
+    timeouts: Timeouts,
+    audit_log: bool,
+}
+
+impl SessionFactory {
+    /// Creates a new client session for the given client address.
+    pub(crate) fn session(&self, peer: SocketAddr) -> Result<Session> {
+        Session::new(self, peer)
+    }
 }
 
 /// A client session coupled to a SQL session.
 pub struct Session {
     engine: sql::engine::Raft,
     sql: sql::engine::Session<sql::engine::Raft>,
+    raft: raft::Client,
+    drain: Arc<Drain>,
+    /// Whether the session is counted as having an open transaction in drain.
+    has_txn: bool,
+    limiter: Arc<Limiter>,
+    admission: Arc<AdmissionController>,
+    /// The user, i.e. the client IP address, for per-user limits.
+    user: IpAddr,
+    /// The client address.
+    peer: SocketAddr,
+    timeouts: Timeouts,
+    /// The session's connection slot.
+    _connection: Slot,
+    /// The open transaction slot for the user, if the session has an open transaction.
+    txn_slot: Option<Slot>,
 }
 
 impl Session {
-    /// Creates a new client session.
-    fn new(engine: sql::engine::Raft) -> Result<Self> {
-        Ok(Self { sql: engine.session()?, engine })
+    /// Creates a new client session for the given client address, recording statements in the
+    /// audit log if enabled. Errors if the connection limit is exceeded.
+    fn new(factory: &SessionFactory, peer: SocketAddr) -> Result<Self> {
+        let connection = factory.limiter.acquire(Resource::Connection)?;
+        let mut sql = factory.engine.session()?.with_user(&peer.ip().to_string());
+        if factory.audit_log {
+            sql = sql.with_audit(&peer.to_string());
+        }
+        Ok(Self {
+            sql,
+            engine: factory.engine.clone(),
+            raft: factory.raft.clone(),
+            drain: factory.drain.clone(),
+            has_txn: false,
+            limiter: factory.limiter.clone(),
+            admission: factory.admission.clone(),
+            user: peer.ip(),
+            peer,
+            timeouts: factory.timeouts,
+            _connection: connection,
+            txn_slot: None,
+        })
     }
 
-    /// Handles a client connection.
+    /// Handles a client connection, until the client disconnects or the session is idle for
+    /// longer than its timeout. Any open transaction is then rolled back.
     async fn handle(mut self, socket: TcpStream) -> Result<()> {
+        let result = self.handle_requests(socket).await;
+        if self.sql.has_txn() {
+            if let Err(err) = tokio::task::block_in_place(|| self.sql.execute("ROLLBACK")) {
+                error!("Failed to roll back transaction for {}: {}", self.peer, err);
+            }
+        }
+        result
+    }
+
+    /// Handles client requests on a connection, for handle().
+    async fn handle_requests(&mut self, socket: TcpStream) -> Result<()> {
         let mut stream = tokio_serde::Framed::new(
             Framed::new(socket, LengthDelimitedCodec::new()),
             tokio_serde::formats::Bincode::default(),
         );
-        while let Some(request) = stream.try_next().await? {
-            let mut response = tokio::task::block_in_place(|| self.request(request));
+        loop {
+            let request = match self.timeouts.idle(self.has_txn) {
+                Some(timeout) => match tokio::time::timeout(timeout, stream.try_next()).await {
+                    Ok(request) => request?,
+                    Err(_) => {
+                        // Any open transaction is rolled back once the connection is closed.
+                        let state = if self.has_txn { "in transaction" } else { "session" };
+                        warn!(
+                            "Client {} exceeded idle {} timeout, disconnecting",
+                            self.peer, state
+                        );
+                        break;
+                    }
+                },
+                None => stream.try_next().await?,
+            };
+            let request = match request {
+                Some(request) => request,
+                None => break,
+            };
+            // The query slot is held until any result rows have been sent.
+            let (mut response, _query) = self.call(request).await;
             let mut rows: Box<dyn Iterator<Item = Result<Response>> + Send> =
                 Box::new(std::iter::empty());
             if let Ok(Response::Execute(ResultSet::Query { rows: ref mut resultrows, .. })) =
@@ -154,10 +1013,84 @@ impl Session {
         Ok(())
     }
 
+    /// Executes a request on behalf of the client, and tracks the session's transaction status.
+    /// Execute requests outside of open transactions are subject to admission control, and
+    /// acquire a query slot for the user, which is returned such that it can be held until any
+    /// result rows have been sent.
+    pub(crate) async fn call(&mut self, request: Request) -> (Result<Response>, Option<Slot>) {
+        let query = match request {
+            Request::Execute(_)
+            | Request::ExecuteTraced(..)
+            | Request::ExecuteParams(..)
+            | Request::ExecuteBatch(_)
+            | Request::Ingest(..) => {
+                if !self.sql.has_txn() {
+                    if let Err(err) = self.admission.admit() {
+                        return (Err(err), None);
+                    }
+                }
+                match self.limiter.acquire(Resource::Query(self.user)) {
+                    Ok(slot) => Some(slot),
+                    Err(err) => return (Err(err), None),
+                }
+            }
+            _ => None,
+        };
+        let mut response = match request {
+            Request::Decommission => self.decommission().await,
+            request => tokio::task::block_in_place(|| self.request(request)),
+        };
+        if let Err(err) = self.track_txn() {
+            response = Err(err);
+        }
+        (response, query)
+    }
+
     /// Executes a request.
     pub fn request(&mut self, request: Request) -> Result<Response> {
+        if self.drain.draining.load(Ordering::SeqCst)
+            && !self.sql.has_txn()
+            && !matches!(request, Request::Status)
+        {
+            return Err(Error::Value("Server is draining".into()));
+        }
         Ok(match request {
-            Request::Execute(query) => Response::Execute(self.sql.execute(&query)?),
+            Request::Execute(query) => Response::Execute(span!("query"; self.sql.execute(&query))?),
+            Request::ExecuteTraced(query, trace) => {
+                // The trace context is only used by the span, when tracing is enabled.
+                #[cfg(not(feature = "trace"))]
+                let _ = trace;
+                Response::Execute(span!(
+                    "query",
+                    (trace_id = %trace.trace_id_hex(), parent_span_id = %trace.span_id_hex());
+                    self.sql.execute(&query)
+                )?)
+            }
+            Request::ExecuteParams(query, params) => {
+                Response::Execute(span!("query"; self.sql.execute_params(&query, &params))?)
+            }
+            Request::ExecuteBatch(queries) => {
+                let mut results = Vec::with_capacity(queries.len());
+                for query in queries.iter().flat_map(|query| split_statements(query)) {
+                    let result = span!("query"; self.sql.execute(query)).and_then(|rs| match rs {
+                        ResultSet::Query { columns, rows } => Ok((
+                            ResultSet::Query { columns, rows: Box::new(std::iter::empty()) },
+                            rows.collect::<Result<_>>()?,
+                        )),
+                        rs => Ok((rs, Vec::new())),
+                    });
+                    let failed = result.is_err();
+                    results.push(result);
+                    if failed {
+                        break;
+                    }
+                }
+                Response::ExecuteBatch(results)
+            }
+            Request::Describe(query) => Response::Describe(self.sql.describe(&query)?),
+            Request::Ingest(table, rows) => {
+                Response::Ingest(span!("ingest"; self.sql.ingest(&table, rows))?)
+            }
             Request::GetTable(table) => Response::GetTable(
                 self.sql.with_txn(Mode::ReadOnly, |txn| txn.must_read_table(&table))?,
             ),
@@ -167,12 +1100,188 @@ impl Session {
                 })?)
             }
             Request::Status => Response::Status(self.engine.status()?),
+            Request::Decommission => {
+                return Err(Error::Internal("Decommission must be handled asynchronously".into()))
+            }
         })
     }
+
+    /// Decommissions the local node. It is marked as draining, which makes it transfer away any
+    /// Raft leadership and reject new transactions, then waits for open transactions to finish
+    /// before removing the node from the cluster. Returns the node ID.
+    async fn decommission(&mut self) -> Result<Response> {
+        if self.sql.has_txn() {
+            return Err(Error::Value("Can't decommission in a transaction".into()));
+        }
+        let status = self.raft.status().await?;
+        let id = status.server;
+        if status.node_last_index.len() < 2 {
+            return Err(Error::Value("Can't decommission the last node in the cluster".into()));
+        }
+
+        info!("Decommissioning node {}, draining", id);
+        self.raft.change_membership(raft::MembershipChange::Drain(id.clone())).await?;
+        self.drain.draining.store(true, Ordering::SeqCst);
+
+        let started = std::time::Instant::now();
+        loop {
+            let txns = self.drain.txns.load(Ordering::SeqCst);
+            let leader = self.raft.status().await?.leader == id;
+            if txns == 0 && (!leader || started.elapsed() >= DECOMMISSION_TRANSFER_TIMEOUT) {
+                break;
+            }
+            tokio::time::delay_for(DECOMMISSION_POLL_INTERVAL).await;
+        }
+
+        info!("Removing node {} from the cluster", id);
+        self.raft.change_membership(raft::MembershipChange::Remove(id.clone())).await?;
+        Ok(Response::Decommission(id))
+    }
+
+    /// Updates the drain transaction count and the user's open transaction slot with the
+    /// session's transaction status. If a new transaction exceeds the user's open transaction
+    /// limit, it is rolled back and an error returned.
+    fn track_txn(&mut self) -> Result<()> {
+        let has_txn = self.sql.has_txn();
+        if has_txn && !self.has_txn {
+            match self.limiter.acquire(Resource::Txn(self.user)) {
+                Ok(slot) => self.txn_slot = Some(slot),
+                Err(err) => {
+                    tokio::task::block_in_place(|| self.sql.execute("ROLLBACK"))?;
+                    return Err(err);
+                }
+            }
+            self.drain.txns.fetch_add(1, Ordering::SeqCst);
+        } else if !has_txn && self.has_txn {
+            self.txn_slot = None;
+            self.drain.txns.fetch_sub(1, Ordering::SeqCst);
+        }
+        self.has_txn = has_txn;
+        Ok(())
+    }
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
-        tokio::task::block_in_place(|| self.sql.execute("ROLLBACK").ok());
+        // The session is only dropped with an open transaction if handle() was interrupted, e.g.
+        // during runtime shutdown. Blocking on the rollback here could then deadlock if the Raft
+        // event loop is dropped after us, so it's attempted on a blocking task if the runtime is
+        // still around, and otherwise left to RollbackOrphaned when the node restarts.
+        if self.sql.has_txn() {
+            match (tokio::runtime::Handle::try_current(), self.engine.session()) {
+                (Ok(_), Ok(sql)) => {
+                    let mut sql = std::mem::replace(&mut self.sql, sql);
+                    let peer = self.peer;
+                    tokio::task::spawn_blocking(move || {
+                        if let Err(err) = sql.execute("ROLLBACK") {
+                            error!("Failed to roll back transaction for {}: {}", peer, err);
+                        }
+                    });
+                }
+                (Err(_), _) => {
+                    warn!("Leaving transaction for {} to be rolled back on restart", self.peer)
+                }
+                (_, Err(err)) => {
+                    error!("Failed to roll back transaction for {}: {}", self.peer, err)
+                }
+            }
+        }
+        if self.has_txn {
+            self.drain.txns.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiter() -> Result<()> {
+        let limiter = Arc::new(Limiter::new(Limits {
+            max_connections: 2,
+            max_user_queries: 1,
+            max_user_txns: 0,
+        }));
+        let (a, b) = ("10.0.0.1".parse()?, "10.0.0.2".parse()?);
+
+        let c1 = limiter.acquire(Resource::Connection)?;
+        let _c2 = limiter.acquire(Resource::Connection)?;
+        assert_eq!(
+            limiter.acquire(Resource::Connection).err(),
+            Some(Error::Limit("Too many connections (max 2)".into()))
+        );
+        drop(c1);
+        let _c3 = limiter.acquire(Resource::Connection)?;
+
+        let q1 = limiter.acquire(Resource::Query(a))?;
+        let _q2 = limiter.acquire(Resource::Query(b))?;
+        assert_eq!(
+            limiter.acquire(Resource::Query(a)).err(),
+            Some(Error::Limit("Too many concurrent queries for user 10.0.0.1 (max 1)".into()))
+        );
+        drop(q1);
+        let _q3 = limiter.acquire(Resource::Query(a))?;
+
+        // A limit of 0 is unlimited.
+        let txns =
+            (0..10).map(|_| limiter.acquire(Resource::Txn(a))).collect::<Result<Vec<_>>>()?;
+        drop(txns);
+        assert_eq!(limiter.users.lock()?.get(&a).map(|u| (u.queries, u.txns)), Some((1, 0)));
+        Ok(())
+    }
+
+    #[test]
+    fn admission() -> Result<()> {
+        let admission = AdmissionController::new(Admission {
+            max_raft_backlog: 100,
+            max_memory: 0,
+            max_cpu: 80,
+        });
+        admission.admit()?;
+
+        admission.update(Load { raft_backlog: 101, memory: Some(1 << 40), cpu: None })?;
+        assert_eq!(
+            admission.admit().err(),
+            Some(Error::Overloaded(
+                "Server overloaded (Raft backlog of 101 entries exceeds 100), retry later".into()
+            ))
+        );
+
+        // A threshold of 0 is unlimited, and unmeasured metrics are ignored.
+        admission.update(Load { raft_backlog: 100, memory: Some(1 << 40), cpu: None })?;
+        admission.admit()?;
+
+        admission.update(Load { raft_backlog: 0, memory: None, cpu: Some(95) })?;
+        assert_eq!(
+            admission.admit().err(),
+            Some(Error::Overloaded(
+                "Server overloaded (CPU usage of 95% exceeds 80%), retry later".into()
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_load() {
+        let status = "Name:\ttoydb\nVmPeak:\t  20000 kB\nVmRSS:\t   1234 kB\nThreads:\t4\n";
+        assert_eq!(parse_memory(status), Some(1234 * 1024));
+        assert_eq!(parse_memory("Name:\ttoydb\n"), None);
+
+        let stat = "cpu  10 2 8 70 10 0 0 0 5 0\ncpu0 5 1 4 35 5 0 0 0 0 0\n";
+        assert_eq!(parse_cpu_times(stat), Some((20, 100)));
+        assert_eq!(parse_cpu_times("intr 1 2 3\n"), None);
+    }
+
+    #[test]
+    fn timeouts() {
+        let timeouts = Timeouts {
+            idle_in_transaction: Duration::from_secs(1),
+            idle_session: Duration::from_secs(0),
+            detached: Duration::from_secs(0),
+        };
+        assert_eq!(timeouts.idle(true), Some(Duration::from_secs(1)));
+        // A timeout of 0 is disabled.
+        assert_eq!(timeouts.idle(false), None);
     }
 }
@@ -0,0 +1,417 @@
+//! Session schema caching. Sessions cache table schemas under a lease on a catalog version, such
+//! that planning doesn't read schemas from storage for every statement. Since every schema change
+//! increments the catalog version, and transactions read the version when they begin (which for
+//! the Raft engine goes through the replicated log), a cached schema is only used by transactions
+//! that see the exact catalog version it was read at. Cluster settings and stored functions are
+//! cached the same way, since changing them also increments the catalog version.
+use super::super::schema::{Catalog, Column, ColumnAlteration, Function, Policy, Table, Tables};
+use super::super::settings::Settings;
+use super::super::types::{Enum, Value};
+use crate::error::Result;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The default lease duration. Leases are renewed by the catalog version rather than by time, so
+/// this only bounds how long unused schemas are kept around.
+const LEASE_DURATION: Duration = Duration::from_secs(60);
+
+/// A session schema cache.
+pub struct SchemaCache {
+    /// The current lease, if any.
+    lease: Option<Lease>,
+    /// The lease duration.
+    duration: Duration,
+}
+
+/// A lease on a catalog version, with the table schemas read at that version.
+struct Lease {
+    /// The catalog version.
+    version: u64,
+    /// The time at which the lease expires.
+    expires: Instant,
+    /// Cached table schemas by name, or None if the table does not exist.
+    tables: RefCell<HashMap<String, Option<Table>>>,
+    /// The cached cluster settings, if read.
+    settings: RefCell<Option<Settings>>,
+    /// Cached stored functions by name, or None if the function does not exist.
+    functions: RefCell<HashMap<String, Option<Function>>>,
+}
+
+impl Default for SchemaCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchemaCache {
+    /// Creates a new, empty schema cache.
+    pub fn new() -> Self {
+        Self { lease: None, duration: LEASE_DURATION }
+    }
+
+    /// Sets the lease duration.
+    pub fn with_lease_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Acquires a lease on the given catalog version, keeping the cached schemas if the current
+    /// lease is for the same version and has not expired, and discarding them otherwise.
+    pub fn acquire(&mut self, version: u64) {
+        match &self.lease {
+            Some(lease) if lease.version == version && lease.expires > Instant::now() => {}
+            _ => {
+                self.lease = Some(Lease {
+                    version,
+                    expires: Instant::now() + self.duration,
+                    tables: RefCell::new(HashMap::new()),
+                    settings: RefCell::new(None),
+                    functions: RefCell::new(HashMap::new()),
+                })
+            }
+        }
+    }
+
+    /// Invalidates the current lease, discarding all cached schemas. This is necessary when a
+    /// transaction that changed the schema is rolled back, since another transaction may later
+    /// commit a different schema with the same catalog version.
+    pub fn invalidate(&mut self) {
+        self.lease = None;
+    }
+
+    /// Returns the catalog version of the current lease, if any.
+    pub fn version(&self) -> Option<u64> {
+        self.lease.as_ref().map(|l| l.version)
+    }
+
+    /// Returns the cached cluster settings, if any.
+    pub fn settings(&self) -> Option<Settings> {
+        self.lease.as_ref().and_then(|l| l.settings.borrow().clone())
+    }
+
+    /// Returns the number of cached table schemas.
+    pub fn len(&self) -> usize {
+        self.lease.as_ref().map(|l| l.tables.borrow().len()).unwrap_or(0)
+    }
+
+    /// Returns true if no table schemas are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A catalog which reads table schemas through a schema cache, and passes everything else
+/// through to an underlying catalog. The cache must hold a lease on the catalog's version.
+pub struct CachedCatalog<'a, C: Catalog> {
+    catalog: &'a mut C,
+    cache: &'a SchemaCache,
+}
+
+impl<'a, C: Catalog> CachedCatalog<'a, C> {
+    /// Creates a new cached catalog.
+    pub fn new(catalog: &'a mut C, cache: &'a SchemaCache) -> Self {
+        Self { catalog, cache }
+    }
+}
+
+impl<'a, C: Catalog> Catalog for CachedCatalog<'a, C> {
+    fn create_table(&mut self, table: Table) -> Result<()> {
+        self.catalog.create_table(table)
+    }
+
+    fn delete_table(&mut self, table: &str) -> Result<()> {
+        self.catalog.delete_table(table)
+    }
+
+    fn read_table(&self, table: &str) -> Result<Option<Table>> {
+        let lease = match &self.cache.lease {
+            Some(lease) => lease,
+            None => return self.catalog.read_table(table),
+        };
+        if let Some(cached) = lease.tables.borrow().get(table) {
+            return Ok(cached.clone());
+        }
+        let schema = self.catalog.read_table(table)?;
+        lease.tables.borrow_mut().insert(table.to_string(), schema.clone());
+        Ok(schema)
+    }
+
+    fn scan_tables(&self) -> Result<Tables> {
+        self.catalog.scan_tables()
+    }
+
+    fn catalog_version(&self) -> Result<u64> {
+        self.catalog.catalog_version()
+    }
+
+    fn row_count(&self, table: &str) -> Result<u64> {
+        self.catalog.row_count(table)
+    }
+
+    fn add_column(&mut self, table: &str, column: Column) -> Result<()> {
+        self.catalog.add_column(table, column)
+    }
+
+    fn create_index(&mut self, table: &str, column: &str) -> Result<()> {
+        self.catalog.create_index(table, column)
+    }
+
+    fn alter_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        alteration: ColumnAlteration,
+    ) -> Result<()> {
+        self.catalog.alter_column(table, column, alteration)
+    }
+
+    fn set_comment(
+        &mut self,
+        table: &str,
+        column: Option<&str>,
+        comment: Option<String>,
+    ) -> Result<()> {
+        self.catalog.set_comment(table, column, comment)
+    }
+
+    fn settings(&self) -> Result<Settings> {
+        let lease = match &self.cache.lease {
+            Some(lease) => lease,
+            None => return self.catalog.settings(),
+        };
+        if let Some(cached) = lease.settings.borrow().as_ref() {
+            return Ok(cached.clone());
+        }
+        let settings = self.catalog.settings()?;
+        *lease.settings.borrow_mut() = Some(settings.clone());
+        Ok(settings)
+    }
+
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()> {
+        self.catalog.set_setting(name, value)
+    }
+
+    fn create_function(&mut self, function: Function) -> Result<()> {
+        self.catalog.create_function(function)
+    }
+
+    fn delete_function(&mut self, function: &str) -> Result<()> {
+        self.catalog.delete_function(function)
+    }
+
+    fn read_function(&self, function: &str) -> Result<Option<Function>> {
+        let lease = match &self.cache.lease {
+            Some(lease) => lease,
+            None => return self.catalog.read_function(function),
+        };
+        if let Some(cached) = lease.functions.borrow().get(function) {
+            return Ok(cached.clone());
+        }
+        let stored = self.catalog.read_function(function)?;
+        lease.functions.borrow_mut().insert(function.to_string(), stored.clone());
+        Ok(stored)
+    }
+
+    fn scan_functions(&self) -> Result<Vec<Function>> {
+        self.catalog.scan_functions()
+    }
+
+    fn create_enum(&mut self, r#enum: Enum) -> Result<()> {
+        self.catalog.create_enum(r#enum)
+    }
+
+    fn delete_enum(&mut self, r#enum: &str) -> Result<()> {
+        self.catalog.delete_enum(r#enum)
+    }
+
+    fn read_enum(&self, r#enum: &str) -> Result<Option<Enum>> {
+        self.catalog.read_enum(r#enum)
+    }
+
+    fn create_policy(&mut self, table: &str, policy: Policy) -> Result<()> {
+        self.catalog.create_policy(table, policy)
+    }
+
+    fn delete_policy(&mut self, table: &str, policy: &str) -> Result<()> {
+        self.catalog.delete_policy(table, policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::types::DataType;
+    use super::super::{Engine as _, Mode, Transaction as _, KV};
+    use super::*;
+    use crate::storage::kv::{Memory, MVCC};
+
+    fn table(name: &str) -> Table {
+        Table {
+            id: 0,
+            name: name.into(),
+            columns: vec![Column {
+                id: 1,
+                name: "id".into(),
+                datatype: DataType::Integer,
+                primary_key: true,
+                nullable: false,
+                default: None,
+                missing: None,
+                unique: true,
+                index: false,
+                references: None,
+                references_id: None,
+                comment: None,
+            }],
+            change: None,
+            comment: None,
+            partitions: Vec::new(),
+            owner: None,
+            policies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn catalog_version() -> Result<()> {
+        let engine = KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        assert_eq!(txn.catalog_version()?, 0);
+        txn.create_table(table("a"))?;
+        txn.create_table(table("b"))?;
+        assert_eq!(txn.catalog_version()?, 2);
+        txn.commit()?;
+
+        // Concurrent schema changes conflict, even on different tables.
+        let mut t1 = engine.begin(Mode::ReadWrite)?;
+        let mut t2 = engine.begin(Mode::ReadWrite)?;
+        t1.delete_table("a")?;
+        assert_eq!(t2.delete_table("b"), Err(crate::error::Error::Serialization));
+        t2.rollback()?;
+        t1.commit()?;
+
+        let txn = engine.begin(Mode::ReadOnly)?;
+        assert_eq!(txn.catalog_version()?, 3);
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn cache() -> Result<()> {
+        let engine = KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut cache = SchemaCache::new();
+
+        // Lookups are cached, including missing tables.
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        cache.acquire(txn.catalog_version()?);
+        assert_eq!(cache.version(), Some(0));
+        assert_eq!(CachedCatalog::new(&mut txn, &cache).read_table("a")?, None);
+        assert_eq!(cache.len(), 1);
+        txn.create_table(table("a"))?;
+        txn.commit()?;
+
+        // A new catalog version discards the cached schemas.
+        let a = Table { id: 1, ..table("a") };
+        let mut txn = engine.begin(Mode::ReadOnly)?;
+        cache.acquire(txn.catalog_version()?);
+        assert_eq!(cache.version(), Some(1));
+        assert!(cache.is_empty());
+        let catalog = CachedCatalog::new(&mut txn, &cache);
+        assert_eq!(catalog.read_table("a")?, Some(a.clone()));
+        assert_eq!(catalog.read_table("a")?, Some(a.clone()));
+        assert_eq!(cache.len(), 1);
+
+        // The same version keeps them, until invalidated.
+        cache.acquire(1);
+        assert_eq!(cache.len(), 1);
+        cache.invalidate();
+        assert_eq!(cache.version(), None);
+        assert_eq!(CachedCatalog::new(&mut txn, &cache).read_table("a")?, Some(a));
+        assert!(cache.is_empty());
+        txn.rollback()?;
+
+        // Expired leases discard them too.
+        let mut cache = SchemaCache::new().with_lease_duration(Duration::from_secs(0));
+        cache.acquire(1);
+        let mut txn = engine.begin(Mode::ReadOnly)?;
+        CachedCatalog::new(&mut txn, &cache).read_table("a")?;
+        assert_eq!(cache.len(), 1);
+        cache.acquire(1);
+        assert!(cache.is_empty());
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn settings() -> Result<()> {
+        use super::super::super::settings::SLOW_QUERY_THRESHOLD_MS;
+        let engine = KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut cache = SchemaCache::new();
+
+        // Settings are cached with the lease.
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        cache.acquire(txn.catalog_version()?);
+        assert_eq!(cache.settings(), None);
+        assert_eq!(CachedCatalog::new(&mut txn, &cache).settings()?, Settings::default());
+        assert_eq!(cache.settings(), Some(Settings::default()));
+
+        // Changing a setting bumps the catalog version, discarding the cached settings.
+        txn.set_setting(SLOW_QUERY_THRESHOLD_MS, Some(Value::Integer(100)))?;
+        assert_eq!(txn.catalog_version()?, 1);
+        txn.commit()?;
+
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        cache.acquire(txn.catalog_version()?);
+        assert_eq!(cache.settings(), None);
+        let settings = CachedCatalog::new(&mut txn, &cache).settings()?;
+        assert_eq!(settings.get(SLOW_QUERY_THRESHOLD_MS)?, Value::Integer(100));
+        assert_eq!(settings.slow_query_threshold(), Some(Duration::from_millis(100)));
+
+        // Resetting a setting restores its default.
+        txn.set_setting(SLOW_QUERY_THRESHOLD_MS, None)?;
+        assert_eq!(txn.settings()?, Settings::default());
+        assert_eq!(txn.catalog_version()?, 2);
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn functions() -> Result<()> {
+        let engine = KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut cache = SchemaCache::new();
+        let double = Function {
+            name: "double".into(),
+            parameters: vec![("x".into(), DataType::Integer)],
+            body: "x * 2".into(),
+        };
+
+        // Creating a function bumps the catalog version, and lookups are cached.
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        txn.create_function(double.clone())?;
+        assert_eq!(
+            txn.create_function(double.clone()),
+            Err(crate::error::Error::Value("Function double already exists".into()))
+        );
+        assert_eq!(txn.catalog_version()?, 1);
+        txn.commit()?;
+
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        cache.acquire(txn.catalog_version()?);
+        let catalog = CachedCatalog::new(&mut txn, &cache);
+        assert_eq!(catalog.read_function("double")?, Some(double.clone()));
+        assert_eq!(catalog.read_function("missing")?, None);
+        assert_eq!(cache.lease.as_ref().map(|l| l.functions.borrow().len()), Some(2));
+        assert_eq!(catalog.scan_functions()?, vec![double]);
+
+        // Deleting a function bumps the catalog version too.
+        txn.delete_function("double")?;
+        assert_eq!(
+            txn.delete_function("double"),
+            Err(crate::error::Error::Value("Function double does not exist".into()))
+        );
+        assert_eq!(txn.catalog_version()?, 2);
+        assert_eq!(txn.scan_functions()?, vec![]);
+        txn.rollback()?;
+        Ok(())
+    }
+}
@@ -1,31 +1,64 @@
-use super::super::schema::{Catalog, Table, Tables};
-use super::super::types::{Expression, Row, Value};
-use super::Transaction as _;
+use super::super::schema::{
+    Catalog, Column, ColumnAlteration, Function, Policy, SchemaChangeKind, SchemaState, Table,
+    Tables,
+};
+use super::super::settings::{self, Settings};
+use super::super::types::{DataType, Enum, Expression, Row, Value};
+use super::system;
+use super::{
+    AuditRecord, Detached, Engine as _, QueryCache, StatementStats, Transaction as _,
+    BACKFILL_BATCH_SIZE,
+};
 use crate::error::{Error, Result};
 use crate::storage::kv;
 
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::clone::Clone;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+/// The number of row count deltas of a table at which a writer folds them into its own delta
+const ROW_COUNT_FOLD_THRESHOLD: usize = 64;
+
+/// The maximum number of MVCC records scanned by a garbage collection batch
+const GC_BATCH_SIZE: usize = 1000;
 
 /// A SQL engine based on an underlying MVCC key/value store
 pub struct KV {
     /// The underlying key/value store
     pub(super) kv: kv::MVCC,
+    /// Statement statistics for the engine's sessions
+    stats: StatementStats,
+    /// The query result cache for the engine's sessions
+    cache: QueryCache,
+    /// Serializes changes to metadata that is read and written as a whole, i.e. detached
+    /// transaction markers and garbage collection checkpoints
+    metadata: Arc<Mutex<()>>,
 }
 
 // FIXME Implement Clone manually due to https://github.com/rust-lang/rust/issues/26925
 impl Clone for KV {
     fn clone(&self) -> Self {
-        KV::new(self.kv.clone())
+        Self {
+            kv: self.kv.clone(),
+            stats: self.stats.clone(),
+            cache: self.cache.clone(),
+            metadata: self.metadata.clone(),
+        }
     }
 }
 
 impl KV {
     /// Creates a new key/value-based SQL engine
     pub fn new(kv: kv::MVCC) -> Self {
-        Self { kv }
+        Self {
+            kv,
+            stats: StatementStats::new(),
+            cache: QueryCache::new(),
+            metadata: Arc::new(Mutex::new(())),
+        }
     }
 
     /// Fetches an unversioned metadata value
@@ -37,17 +70,138 @@ impl KV {
     pub fn set_metadata(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.kv.set_metadata(key, value)
     }
+
+    /// Loads the markers of detached transactions, by transaction ID
+    pub(super) fn load_detached(&self) -> Result<BTreeMap<u64, Detached>> {
+        self.kv
+            .get_metadata(b"txn_detached")?
+            .map(|b| deserialize(&b))
+            .unwrap_or_else(|| Ok(BTreeMap::new()))
+    }
+
+    /// Saves the markers of detached transactions
+    fn save_detached(&self, detached: &BTreeMap<u64, Detached>) -> Result<()> {
+        self.kv.set_metadata(b"txn_detached", serialize(detached)?)
+    }
+
+    /// Runs a batch of MVCC garbage collection at the given time, in milliseconds since the Unix
+    /// epoch, returning the number of versions removed and whether the garbage collection pass is
+    /// complete. History is retained for the duration given by the mvcc_retention_ms cluster
+    /// setting, if set.
+    ///
+    /// Versions are transaction IDs rather than times, so each run records the current version
+    /// as a checkpoint for the given time, and garbage collects versions before the latest
+    /// checkpoint that is outside of the retention window. The time is given by the caller, such
+    /// that all Raft replicas garbage collect the same versions.
+    pub fn gc(&self, timestamp: u64) -> Result<(u64, bool)> {
+        let txn = self.begin(super::Mode::ReadOnly)?;
+        let (version, settings) = (txn.txn.id(), txn.settings());
+        txn.rollback()?;
+        let retention = match settings?.mvcc_retention() {
+            Some(retention) => retention.as_millis() as u64,
+            None => return Ok((0, true)),
+        };
+
+        let _guard = self.metadata.lock()?;
+        let mut checkpoints: Vec<(u64, u64)> = self
+            .kv
+            .get_metadata(b"gc_checkpoints")?
+            .map(|b| deserialize(&b))
+            .unwrap_or_else(|| Ok(Vec::new()))?;
+        checkpoints.push((timestamp, version));
+        // Only the latest checkpoint outside the retention window is needed.
+        let horizon = timestamp.saturating_sub(retention);
+        let expired = checkpoints.iter().take_while(|(t, _)| *t <= horizon).count();
+        checkpoints.drain(..expired.saturating_sub(1));
+        self.kv.set_metadata(b"gc_checkpoints", serialize(&checkpoints)?)?;
+        match checkpoints.first() {
+            Some((t, version)) if *t <= horizon => self.kv.gc(*version, GC_BATCH_SIZE),
+            _ => Ok((0, true)),
+        }
+    }
 }
 
 impl super::Engine for KV {
     type Transaction = Transaction;
 
-    fn begin(&self, mode: super::Mode) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin_with_mode(mode)?))
+    fn begin_with_priority(
+        &self,
+        mode: super::Mode,
+        priority: super::Priority,
+    ) -> Result<Self::Transaction> {
+        Ok(Self::Transaction::new(self.kv.begin_with_priority(mode, priority)?, self.stats.clone()))
     }
 
     fn resume(&self, id: u64) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.resume(id)?))
+        Ok(Self::Transaction::new(self.kv.resume(id)?, self.stats.clone()))
+    }
+
+    fn detach(&self, id: u64, detached: Detached) -> Result<()> {
+        let _guard = self.metadata.lock()?;
+        self.kv.resume(id)?;
+        let mut markers = self.load_detached()?;
+        markers.insert(id, detached);
+        self.save_detached(&markers)
+    }
+
+    fn attach(&self, id: u64, user: Option<&str>, role: Option<&str>) -> Result<Self::Transaction> {
+        let _guard = self.metadata.lock()?;
+        let mut markers = self.load_detached()?;
+        match markers.get(&id) {
+            None => return Err(Error::Value(format!("Transaction {} is not detached", id))),
+            Some(d) if d.user.as_deref() != user || d.role.as_deref() != role => {
+                return Err(Error::Value(format!(
+                    "Transaction {} was detached by a different user or role",
+                    id
+                )))
+            }
+            Some(_) => {}
+        }
+        let txn = self.resume(id)?;
+        markers.remove(&id);
+        self.save_detached(&markers)?;
+        Ok(txn)
+    }
+
+    fn rollback_detached(&self, before: u64) -> Result<u64> {
+        let _guard = self.metadata.lock()?;
+        let mut markers = self.load_detached()?;
+        let expired: Vec<u64> =
+            markers.iter().filter(|(_, d)| d.timestamp < before).map(|(id, _)| *id).collect();
+        // Transactions that are no longer active only leave a stale marker behind. A failed
+        // rollback doesn't stop the others, and is returned once they've been rolled back.
+        let mut count = 0;
+        let mut error = None;
+        for id in expired {
+            match self.kv.resume(id) {
+                Ok(txn) => match txn.rollback() {
+                    Ok(()) => count += 1,
+                    Err(err) => {
+                        error.get_or_insert(err);
+                        continue;
+                    }
+                },
+                Err(Error::Value(_)) => {}
+                Err(err) => {
+                    error.get_or_insert(err);
+                    continue;
+                }
+            }
+            markers.remove(&id);
+        }
+        self.save_detached(&markers)?;
+        match error {
+            Some(err) => Err(err),
+            None => Ok(count),
+        }
+    }
+
+    fn statement_stats(&self) -> &StatementStats {
+        &self.stats
+    }
+
+    fn query_cache(&self) -> &QueryCache {
+        &self.cache
     }
 }
 
@@ -61,22 +215,308 @@ fn deserialize<'a, V: Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
     Ok(bincode::deserialize(bytes)?)
 }
 
+/// Encodes a row of a table's full schema for storage. Values are tagged with their column ID
+/// rather than stored by position, such that adding a column doesn't require rewriting existing
+/// rows, which simply lack a value for it. Enum values are stored as their Integer ordinal.
+fn encode_row(table: &Table, row: &[Value]) -> Result<Vec<u8>> {
+    let values = row.iter().map(|v| match v {
+        Value::Enum(_, ordinal) => Cow::Owned(Value::Integer(*ordinal as i64)),
+        v => Cow::Borrowed(v),
+    });
+    serialize(&table.columns.iter().map(|c| c.id).zip(values).collect::<Vec<_>>())
+}
+
+/// Decodes a stored row into the column order of a table's full schema. Columns added after the
+/// row was stored take their missing value, and values of unknown columns are ignored.
+fn decode_row(table: &Table, bytes: &[u8]) -> Result<Row> {
+    let mut values: HashMap<u64, Value> = deserialize::<Vec<_>>(bytes)?.into_iter().collect();
+    table
+        .columns
+        .iter()
+        .map(|c| {
+            let value = values.remove(&c.id).map(|v| decode_enum(&c.datatype, v));
+            value.or_else(|| c.missing.clone()).ok_or_else(|| {
+                Error::Internal(format!("No value for column {} in stored row", c.name))
+            })
+        })
+        .collect()
+}
+
+/// Converts a stored value of an enum datatype from its Integer ordinal back to an enum value.
+/// Values of other datatypes are returned as is.
+fn decode_enum(datatype: &DataType, value: Value) -> Value {
+    match (datatype, value) {
+        (DataType::Enum(e), Value::Integer(ordinal)) => Value::Enum(e.clone(), ordinal as u64),
+        (_, value) => value,
+    }
+}
+
 /// An SQL transaction based on an MVCC key/value transaction
 pub struct Transaction {
     txn: kv::mvcc::Transaction,
+    /// The engine's statement statistics, for the system.statement_stats table
+    stats: StatementStats,
 }
 
 impl Transaction {
     /// Creates a new SQL transaction from an MVCC transaction
-    fn new(txn: kv::mvcc::Transaction) -> Self {
-        Self { txn }
+    fn new(txn: kv::mvcc::Transaction, stats: StatementStats) -> Self {
+        Self { txn, stats }
+    }
+
+    /// Reads a table's full schema, including any column or index still being added by an online
+    /// schema change, which is hidden from the catalog.
+    fn read_schema(&self, table: &str) -> Result<Option<Table>> {
+        if system::is_system_table(table) {
+            return Ok(system::table(table));
+        }
+        match self.txn.get(&Key::TableName(table.into()).encode())? {
+            Some(id) => self.read_schema_by_id(deserialize(&id)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a table's full schema by table ID, resolving the names of referenced tables
+    fn read_schema_by_id(&self, id: u64) -> Result<Option<Table>> {
+        let mut table: Table = match self.txn.get(&Key::Table(Some(id)).encode())? {
+            Some(v) => deserialize(&v)?,
+            None => return Ok(None),
+        };
+        for column in table.columns.iter_mut() {
+            column.references = match column.references_id {
+                Some(id) if id == table.id => Some(table.name.clone()),
+                Some(id) => Some(
+                    self.txn
+                        .get(&Key::Table(Some(id)).encode())?
+                        .map(|v| deserialize::<Table>(&v))
+                        .transpose()?
+                        .ok_or_else(|| Error::Internal(format!("Table ID {} not found", id)))?
+                        .name,
+                ),
+                None => None,
+            };
+        }
+        Ok(Some(table))
+    }
+
+    /// Reads a table's full schema, and errors if it does not exist
+    fn must_read_schema(&self, table: &str) -> Result<Table> {
+        self.read_schema(table)?
+            .ok_or_else(|| Error::Value(format!("Table {} does not exist", table)))
+    }
+
+    /// Saves a table's full schema, incrementing the catalog version. Foreign keys given by
+    /// table name are resolved to table IDs, and the referenced tables must exist.
+    fn save_schema(&mut self, table: &Table) -> Result<()> {
+        let mut table = table.clone();
+        for column in table.columns.iter_mut().filter(|c| c.references_id.is_none()) {
+            column.references_id = match &column.references {
+                Some(name) if name == &table.name => Some(table.id),
+                Some(name) => Some(self.must_read_schema(name)?.id),
+                None => None,
+            };
+        }
+        self.bump_catalog_version()?;
+        self.txn.set(&Key::Table(Some(table.id)).encode(), serialize(&table)?)
+    }
+
+    /// Allocates a new table ID
+    fn next_table_id(&mut self) -> Result<u64> {
+        let id = self
+            .txn
+            .get(&Key::NextTableId.encode())?
+            .map(|v| deserialize(&v))
+            .transpose()?
+            .unwrap_or(1);
+        self.txn.set(&Key::NextTableId.encode(), serialize(&(id + 1))?)?;
+        Ok(id)
+    }
+
+    /// Adjusts a table's row count by the given delta. Each transaction records its own delta
+    /// under a separate key, such that concurrent writers don't conflict, and the row count is
+    /// the sum of the visible deltas. When a transaction first writes a delta and there are many
+    /// of them, it folds them into its own delta to bound their number.
+    fn adjust_row_count(&mut self, table: &Table, delta: i64) -> Result<()> {
+        let key = Key::RowCount(table.id, Some(self.txn.id())).encode();
+        let count: i64 = match self.txn.get(&key)? {
+            Some(v) => deserialize(&v)?,
+            None => {
+                let deltas = self.row_count_deltas(table.id)?;
+                if deltas.len() < ROW_COUNT_FOLD_THRESHOLD {
+                    0
+                } else {
+                    for (key, _) in &deltas {
+                        self.txn.delete(key)?;
+                    }
+                    deltas.into_iter().map(|(_, delta)| delta).sum()
+                }
+            }
+        };
+        self.txn.set(&key, serialize(&(count + delta))?)
+    }
+
+    /// Returns the visible row count deltas of a table, as key/delta pairs
+    fn row_count_deltas(&self, table_id: u64) -> Result<Vec<(Vec<u8>, i64)>> {
+        self.txn
+            .scan_prefix(&Key::RowCount(table_id, None).encode())?
+            .map(|r| r.and_then(|(k, v)| Ok((k, deserialize(&v)?))))
+            .collect()
+    }
+
+    /// Increments the catalog version. Since every schema change writes the version, concurrent
+    /// schema changes always conflict, and one of them fails with a serialization error.
+    fn bump_catalog_version(&mut self) -> Result<()> {
+        let version = self.catalog_version()? + 1;
+        self.txn.set(&Key::CatalogVersion.encode(), serialize(&version)?)
+    }
+
+    /// Returns the number of visible columns of a table's full schema, if it has a column still
+    /// being added, which stored rows must be truncated to.
+    fn visible_width(table: &Table) -> Option<usize> {
+        match table.change.as_ref().map(|c| &c.kind) {
+            Some(SchemaChangeKind::AddColumn(_)) => Some(table.columns.len() - 1),
+            _ => None,
+        }
+    }
+
+    /// Returns the IDs that a table's rows are stored under, in key order: the partition IDs
+    /// for partitioned tables, otherwise the table ID.
+    fn storage_ids(table: &Table) -> Vec<u64> {
+        match table.partitions.is_empty() {
+            true => vec![table.id],
+            false => table.partitions.iter().map(|p| p.id).collect(),
+        }
+    }
+
+    /// Returns the storage key of a table row, under its partition for partitioned tables
+    fn row_key<'a>(table: &Table, id: &'a Value) -> Result<Key<'a>> {
+        let storage_id = table.get_row_partition(id)?.map(|p| p.id).unwrap_or(table.id);
+        Ok(Key::Row(storage_id, Some(id.into())))
+    }
+
+    /// Reads a stored row, including the values of any column still being added
+    fn read_row(&self, table: &Table, id: &Value) -> Result<Option<Row>> {
+        self.txn
+            .get(&Self::row_key(table, id)?.encode())?
+            .map(|v| decode_row(table, &v))
+            .transpose()
+    }
+
+    /// Scans the stored rows of a table after the given row, in order, or all rows if none is
+    /// given, including the values of any column still being added.
+    fn scan_rows_after(
+        &self,
+        table: &Table,
+        after: Option<&Value>,
+    ) -> Result<impl DoubleEndedIterator<Item = Result<(Vec<u8>, Row)>>> {
+        let after = match after {
+            Some(id) => Bound::Excluded(Self::row_key(table, id)?.encode()),
+            None => Bound::Unbounded,
+        };
+        let scans = Self::storage_ids(table)
+            .into_iter()
+            .map(|id| {
+                let prefix = Key::Row(id, None).encode();
+                self.txn.scan_prefix_range(&prefix, (after.clone(), Bound::Unbounded))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let table = table.clone();
+        Ok(scans
+            .into_iter()
+            .flatten()
+            .map(move |r| r.and_then(|(k, v)| Ok((k, decode_row(&table, &v)?)))))
+    }
+
+    /// Scans the stored rows of a table under the given storage IDs, in order
+    fn scan_storage(
+        &self,
+        table: &Table,
+        storage_ids: Vec<u64>,
+    ) -> Result<impl DoubleEndedIterator<Item = Result<(Vec<u8>, Row)>>> {
+        let table = table.clone();
+        let scans = storage_ids
+            .into_iter()
+            .map(|id| self.txn.scan_prefix(&Key::Row(id, None).encode()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(scans
+            .into_iter()
+            .flatten()
+            .map(move |r| r.and_then(|(k, v)| Ok((k, decode_row(&table, &v)?)))))
+    }
+
+    /// Scans a table's stored rows under the given storage IDs as visible rows, filtered by
+    /// the given expression
+    fn scan_visible(
+        &self,
+        table: &Table,
+        storage_ids: Vec<u64>,
+        filter: Option<Expression>,
+    ) -> Result<super::Scan> {
+        let width = Self::visible_width(table);
+        let rows = self.scan_storage(table, storage_ids)?.map(move |r| {
+            r.map(|(_, mut row)| {
+                if let Some(width) = width {
+                    row.truncate(width);
+                }
+                row
+            })
+        });
+        Ok(super::filter_scan(Box::new(rows), filter))
+    }
+
+    /// Reads the next batch of rows to backfill or verify after the given cursor row, if any
+    fn backfill_batch(&self, table: &Table, after: Option<&Value>) -> Result<Vec<Row>> {
+        self.scan_rows_after(table, after)?
+            .take(BACKFILL_BATCH_SIZE)
+            .map(|r| r.map(|(_, row)| row))
+            .collect()
+    }
+
+    /// Backfills a row for the column added or indexed by an online schema change, returning
+    /// true if the row was missing its index entry. Rows themselves are never rewritten, since
+    /// rows stored before a column was added take its missing value. With dry_run, nothing is
+    /// written.
+    fn backfill_row(
+        &mut self,
+        table: &Table,
+        column: u64,
+        row: Row,
+        dry_run: bool,
+    ) -> Result<bool> {
+        let i = table.get_column_index_by_id(column)?;
+        let column = &table.columns[i];
+        let id = table.get_row_key(&row)?;
+        let mut missing = false;
+        if column.index {
+            let mut index = self.index_load(table, column, &row[i])?;
+            if index.insert(id) {
+                missing = true;
+                if !dry_run {
+                    self.index_save(table, column, &row[i], index)?;
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Decodes a scan of index entries into index values and primary keys.
+    fn index_entries(scan: kv::Scan, column: &Column) -> Result<super::IndexScan> {
+        let datatype = column.datatype.clone();
+        Ok(Box::new(scan.map(move |r| -> Result<(Value, HashSet<Value>)> {
+            let (k, v) = r?;
+            let value = match Key::decode(&k)? {
+                Key::Index(_, _, Some(value)) => decode_enum(&datatype, value.into_owned()),
+                _ => return Err(Error::Internal("Invalid index key".into())),
+            };
+            Ok((value, deserialize(&v)?))
+        })))
     }
 
     /// Loads an index entry
-    fn index_load(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
+    fn index_load(&self, table: &Table, column: &Column, value: &Value) -> Result<HashSet<Value>> {
         Ok(self
             .txn
-            .get(&Key::Index(table.into(), column.into(), Some(value.into())).encode())?
+            .get(&Key::Index(table.id, column.id, Some(value.into())).encode())?
             .map(|v| deserialize(&v))
             .transpose()?
             .unwrap_or_else(HashSet::new))
@@ -85,18 +525,81 @@ impl Transaction {
     /// Saves an index entry.
     fn index_save(
         &mut self,
-        table: &str,
-        column: &str,
+        table: &Table,
+        column: &Column,
         value: &Value,
         index: HashSet<Value>,
     ) -> Result<()> {
-        let key = Key::Index(table.into(), column.into(), Some(value.into())).encode();
+        let key = Key::Index(table.id, column.id, Some(value.into())).encode();
         if index.is_empty() {
             self.txn.delete(&key)
         } else {
             self.txn.set(&key, serialize(&index)?)
         }
     }
+
+    /// Generates the rows of a system table.
+    fn scan_system(&self, table: &Table) -> Result<Vec<Row>> {
+        match table.name.as_str() {
+            system::STORAGE_STATS => self
+                .scan_tables()?
+                .map(|t| {
+                    let mut stats = kv::mvcc::Stats::default();
+                    for id in Self::storage_ids(&t) {
+                        stats += self.txn.stats_prefix(&Key::Row(id, None).encode())?;
+                    }
+                    for column in t.columns.iter().filter(|c| c.index) {
+                        stats +=
+                            self.txn.stats_prefix(&Key::Index(t.id, column.id, None).encode())?;
+                    }
+                    Ok(vec![
+                        Value::String(t.name),
+                        Value::Integer(stats.keys as i64),
+                        Value::Integer(stats.bytes as i64),
+                        Value::Integer(stats.versions as i64),
+                        Value::Integer(stats.dead_versions as i64),
+                    ])
+                })
+                .collect(),
+            system::AUDIT => self
+                .txn
+                .scan_prefix(&Key::Audit(None).encode())?
+                .map(|r| {
+                    let (k, v) = r?;
+                    match Key::decode(&k)? {
+                        Key::Audit(Some((txn_id, seq))) => {
+                            Ok(system::audit_row(txn_id, seq, deserialize(&v)?))
+                        }
+                        _ => Err(Error::Internal("Invalid audit key".into())),
+                    }
+                })
+                .collect(),
+            system::JOBS => self
+                .scan_tables()?
+                .filter_map(|t| Some((t.name, t.change?)))
+                .map(|(name, change)| {
+                    let column = self
+                        .must_read_schema(&name)?
+                        .columns
+                        .into_iter()
+                        .find(|c| c.id == change.column())
+                        .map(|c| c.name)
+                        .unwrap_or_default();
+                    let rows = self.row_count(&name)?;
+                    Ok(system::job_row(name, column, &change, rows, super::now()))
+                })
+                .collect(),
+            system::FUNCTIONS => Ok(system::function_rows(self.scan_functions()?)),
+            system::SETTINGS => system::settings_rows(&self.settings()?),
+            system::STATEMENT_STATS => system::statement_stats_rows(&self.stats),
+            system::TABLES => Ok(system::table_rows(self.scan_tables()?)),
+            system::COLUMNS => Ok(system::column_rows(self.scan_tables()?)),
+            system::RAFT => {
+                Err(Error::Value(format!("{} is only available with Raft", system::RAFT)))
+            }
+            name => Err(Error::Internal(format!("Unknown system table {}", name))),
+        }
+    }
 }
 
 impl super::Transaction for Transaction {
@@ -108,6 +611,14 @@ impl super::Transaction for Transaction {
         self.txn.mode()
     }
 
+    fn priority(&self) -> super::Priority {
+        self.txn.priority()
+    }
+
+    fn data_version(&self) -> u64 {
+        self.txn.data_version()
+    }
+
     fn commit(self) -> Result<()> {
         self.txn.commit()
     }
@@ -117,31 +628,46 @@ impl super::Transaction for Transaction {
     }
 
     fn create(&mut self, table: &str, row: Row) -> Result<()> {
-        let table = self.must_read_table(&table)?;
-        table.validate_row(&row, self)?;
+        let table = self.must_read_schema(&table)?;
+        system::ensure_writable(&table.name)?;
+        let mut row = table.coerce_row(row);
+        // Rows are validated against the visible columns, and any column still being added is
+        // set to its default value, unless given by an update.
+        let visible = table.clone().visible();
+        let width = visible.columns.len();
+        if row.len() > table.columns.len() {
+            return Err(Error::Value(format!("Invalid row size for table {}", table.name)));
+        }
+        visible.validate_row(&row[..row.len().min(width)], self)?;
+        for column in &table.columns[row.len()..] {
+            row.push(column.default.clone().unwrap_or(Value::Null));
+        }
         let id = table.get_row_key(&row)?;
         if self.read(&table.name, &id)?.is_some() {
-            return Err(Error::Value(format!(
+            return Err(Error::Constraint(format!(
                 "Primary key {} already exists for table {}",
                 id, table.name
             )));
         }
-        self.txn.set(
-            &Key::Row(Cow::Borrowed(&table.name), Some(Cow::Borrowed(&id))).encode(),
-            serialize(&row)?,
-        )?;
+        self.txn.set(&Self::row_key(&table, &id)?.encode(), encode_row(&table, &row)?)?;
+        self.adjust_row_count(&table, 1)?;
 
         // Update indexes
         for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
-            let mut index = self.index_load(&table.name, &column.name, &row[i])?;
+            let mut index = self.index_load(&table, column, &row[i])?;
             index.insert(id.clone());
-            self.index_save(&table.name, &column.name, &row[i], index)?;
+            self.index_save(&table, column, &row[i], index)?;
         }
         Ok(())
     }
 
+    fn create_batch(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        rows.into_iter().try_for_each(|row| self.create(table, row))
+    }
+
     fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
-        let table = self.must_read_table(&table)?;
+        let table = self.must_read_schema(&table)?;
+        system::ensure_writable(&table.name)?;
         for (t, cs) in self.table_references(&table.name, true)? {
             let t = self.must_read_table(&t)?;
             let cs = cs
@@ -152,7 +678,7 @@ impl super::Transaction for Transaction {
             while let Some(row) = scan.next().transpose()? {
                 for (i, c) in &cs {
                     if &row[*i] == id && (table.name != t.name || id != &table.get_row_key(&row)?) {
-                        return Err(Error::Value(format!(
+                        return Err(Error::Constraint(format!(
                             "Primary key {} is referenced by table {} column {}",
                             id, t.name, c
                         )));
@@ -163,53 +689,66 @@ impl super::Transaction for Transaction {
 
         let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
         if !indexes.is_empty() {
-            if let Some(row) = self.read(&table.name, id)? {
+            if let Some(row) = self.read_row(&table, id)? {
                 for (i, column) in indexes {
-                    let mut index = self.index_load(&table.name, &column.name, &row[i])?;
+                    let mut index = self.index_load(&table, column, &row[i])?;
                     index.remove(id);
-                    self.index_save(&table.name, &column.name, &row[i], index)?;
+                    self.index_save(&table, column, &row[i], index)?;
                 }
             }
         }
-        self.txn.delete(&Key::Row(table.name.into(), Some(id.into())).encode())
+        let key = Self::row_key(&table, id)?.encode();
+        if self.txn.get(&key)?.is_some() {
+            self.adjust_row_count(&table, -1)?;
+        }
+        self.txn.delete(&key)
     }
 
     fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
-        self.txn
-            .get(&Key::Row(table.into(), Some(id.into())).encode())?
-            .map(|v| deserialize(&v))
-            .transpose()
+        if system::is_system_table(table) {
+            let table = self.must_read_table(table)?;
+            for row in self.scan_system(&table)? {
+                if &table.get_row_key(&row)? == id {
+                    return Ok(Some(row));
+                }
+            }
+            return Ok(None);
+        }
+        let table = self.must_read_schema(table)?;
+        let mut row = self.read_row(&table, id)?;
+        if let (Some(row), Some(width)) = (&mut row, Self::visible_width(&table)) {
+            row.truncate(width);
+        }
+        Ok(row)
     }
 
     fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
-        if !self.must_read_table(table)?.get_column(column)?.index {
-            return Err(Error::Value(format!("No index on {}.{}", table, column)));
+        let table = self.must_read_table(table)?;
+        let column = table.get_column(column)?;
+        if !column.index {
+            return Err(Error::Value(format!("No index on {}.{}", table.name, column.name)));
         }
-        self.index_load(table, column, value)
+        self.index_load(&table, column, value)
     }
 
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<super::Scan> {
-        let table = self.must_read_table(&table)?;
-        Ok(Box::new(
-            self.txn
-                .scan_prefix(&Key::Row((&table.name).into(), None).encode())?
-                .map(|r| r.and_then(|(_, v)| deserialize(&v)))
-                .filter_map(move |r| match r {
-                    Ok(row) => match &filter {
-                        Some(filter) => match filter.evaluate(Some(&row)) {
-                            Ok(Value::Boolean(b)) if b => Some(Ok(row)),
-                            Ok(Value::Boolean(_)) | Ok(Value::Null) => None,
-                            Ok(v) => Some(Err(Error::Value(format!(
-                                "Filter returned {}, expected boolean",
-                                v
-                            )))),
-                            Err(err) => Some(Err(err)),
-                        },
-                        None => Some(Ok(row)),
-                    },
-                    err => Some(err),
-                }),
-        ))
+        let table = self.must_read_schema(&table)?;
+        if system::is_system_table(&table.name) {
+            let rows = self.scan_system(&table)?.into_iter().map(Ok);
+            return Ok(super::filter_scan(Box::new(rows), filter));
+        }
+        self.scan_visible(&table, Self::storage_ids(&table), filter)
+    }
+
+    fn scan_partition(
+        &self,
+        table: &str,
+        partition: &str,
+        filter: Option<Expression>,
+    ) -> Result<super::Scan> {
+        let table = self.must_read_schema(table)?;
+        let id = table.get_partition(partition)?.id;
+        self.scan_visible(&table, vec![id], filter)
     }
 
     fn scan_index(&self, table: &str, column: &str) -> Result<super::IndexScan> {
@@ -218,24 +757,49 @@ impl super::Transaction for Transaction {
         if !column.index {
             return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
         }
-        Ok(Box::new(
-            self.txn
-                .scan_prefix(
-                    &Key::Index((&table.name).into(), (&column.name).into(), None).encode(),
-                )?
-                .map(|r| -> Result<(Value, HashSet<Value>)> {
-                    let (k, v) = r?;
-                    let value = match Key::decode(&k)? {
-                        Key::Index(_, _, Some(pk)) => pk.into_owned(),
-                        _ => return Err(Error::Internal("Invalid index key".into())),
-                    };
-                    Ok((value, deserialize(&v)?))
-                }),
-        ))
+        Self::index_entries(
+            self.txn.scan_prefix(&Key::Index(table.id, column.id, None).encode())?,
+            column,
+        )
     }
 
-    fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
+    fn scan_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        lower: &Value,
+        upper: &Value,
+    ) -> Result<super::IndexScan> {
         let table = self.must_read_table(&table)?;
+        let column = table.get_column(column)?;
+        if !column.index {
+            return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
+        }
+        let lower = Key::Index(table.id, column.id, Some(lower.into())).encode();
+        let upper = Key::Index(table.id, column.id, Some(upper.into())).encode();
+        if lower > upper {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        Self::index_entries(self.txn.scan(lower..=upper)?, column)
+    }
+
+    fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
+        let table = self.must_read_schema(&table)?;
+        system::ensure_writable(&table.name)?;
+        let mut row = table.coerce_row(row);
+        // Keep the stored values of any column still being added, which the caller can't see,
+        // or use the column default if the row doesn't exist.
+        let old = self.read_row(&table, id)?;
+        let width = row.len();
+        for (i, column) in table.columns.iter().enumerate().skip(width) {
+            row.push(
+                old.as_ref()
+                    .and_then(|old| old.get(i).cloned())
+                    .or_else(|| column.default.clone())
+                    .unwrap_or(Value::Null),
+            );
+        }
+
         // If the primary key changes we do a delete and create, otherwise we replace the row
         if id != &table.get_row_key(&row)? {
             self.delete(&table.name, id)?;
@@ -246,37 +810,186 @@ impl super::Transaction for Transaction {
         // Update indexes, knowing that the primary key has not changed
         let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
         if !indexes.is_empty() {
-            let old = self.read(&table.name, id)?.unwrap();
+            let old = old.unwrap();
             for (i, column) in indexes {
-                if old[i] == row[i] {
+                if old.get(i) == row.get(i) {
                     continue;
                 }
-                let mut index = self.index_load(&table.name, &column.name, &old[i])?;
-                index.remove(id);
-                self.index_save(&table.name, &column.name, &old[i], index)?;
+                if let Some(value) = old.get(i) {
+                    let mut index = self.index_load(&table, column, value)?;
+                    index.remove(id);
+                    self.index_save(&table, column, value, index)?;
+                }
 
-                let mut index = self.index_load(&table.name, &column.name, &row[i])?;
+                let mut index = self.index_load(&table, column, &row[i])?;
                 index.insert(id.clone());
-                self.index_save(&table.name, &column.name, &row[i], index)?;
+                self.index_save(&table, column, &row[i], index)?;
             }
         }
 
-        table.validate_row(&row, self)?;
-        self.txn.set(&Key::Row(table.name.into(), Some(id.into())).encode(), serialize(&row)?)
+        table.clone().visible().validate_row(&row[..width], self)?;
+        self.txn.set(&Self::row_key(&table, id)?.encode(), encode_row(&table, &row)?)
+    }
+
+    fn changes(&self, table: &str, from: u64) -> Result<(Vec<super::Change>, u64)> {
+        let table = self.must_read_schema(&table)?;
+        let width = Self::visible_width(&table);
+        let pk_datatype = table.get_primary_key()?.datatype.clone();
+        let mut changes = Vec::new();
+        let mut resolved = u64::MAX;
+        for id in Self::storage_ids(&table) {
+            let (c, r) = self.txn.changes_prefix(&Key::Row(id, None).encode(), from)?;
+            changes.extend(c);
+            resolved = resolved.min(r);
+        }
+        // Changes of partitions are ordered by commit, and then by key within each commit.
+        changes.sort_by_key(|c| c.commit);
+        let changes = changes
+            .into_iter()
+            .filter(|c| c.commit < resolved)
+            .map(|c| {
+                let id = match Key::decode(&c.key)? {
+                    Key::Row(_, Some(pk)) => decode_enum(&pk_datatype, pk.into_owned()),
+                    _ => return Err(Error::Internal("Invalid row key".into())),
+                };
+                let mut row = c.value.map(|v| decode_row(&table, &v)).transpose()?;
+                if let (Some(row), Some(width)) = (&mut row, width) {
+                    row.truncate(width);
+                }
+                Ok(super::Change { version: c.version, id, row })
+            })
+            .collect::<Result<_>>()?;
+        Ok((changes, resolved))
+    }
+
+    fn history(&self, table: &str, id: &Value) -> Result<Vec<super::Change>> {
+        let table = self.must_read_schema(table)?;
+        let width = Self::visible_width(&table);
+        self.txn
+            .get_versions(&Self::row_key(&table, id)?.encode())?
+            .into_iter()
+            .map(|(version, value)| {
+                let mut row = value.map(|v| decode_row(&table, &v)).transpose()?;
+                if let (Some(row), Some(width)) = (&mut row, width) {
+                    row.truncate(width);
+                }
+                Ok(super::Change { version, id: id.clone(), row })
+            })
+            .collect()
+    }
+
+    fn row_versions(&self, table: &str, id: &Value) -> Result<Option<(u64, u64)>> {
+        if system::is_system_table(table) {
+            return Ok(None);
+        }
+        let table = self.must_read_schema(table)?;
+        self.txn.get_lifetime(&Self::row_key(&table, id)?.encode())
+    }
+
+    fn audit(&mut self, record: AuditRecord) -> Result<()> {
+        let txn_id = self.txn.id();
+        // The transaction's last record, if any, is found with a reverse scan.
+        let seq = match self.txn.scan_prefix(&Key::AuditTxn(txn_id).encode())?.next_back() {
+            Some(r) => match Key::decode(&r?.0)? {
+                Key::Audit(Some((_, seq))) => seq + 1,
+                _ => return Err(Error::Internal("Invalid audit key".into())),
+            },
+            None => 1,
+        };
+        self.txn.set(&Key::Audit(Some((txn_id, seq))).encode(), serialize(&record)?)
+    }
+
+    fn advance_schema_change(
+        &mut self,
+        table: &str,
+        timestamp: u64,
+    ) -> Result<Option<SchemaState>> {
+        let mut table = self.must_read_schema(table)?;
+        let mut change = match table.change.take() {
+            Some(change) => change,
+            None => return Ok(Some(SchemaState::ReadVisible)),
+        };
+        match change.state {
+            // Transactions that began before the change committed don't maintain the column or
+            // index, so the first step records a transaction known to see the change, and
+            // backfilling waits until all transactions older than it have finished.
+            SchemaState::Backfilling if change.since.is_none() => {
+                change.since = Some(self.txn.id());
+                change.started = Some(timestamp);
+            }
+            SchemaState::Backfilling => {
+                let since = change.since.unwrap_or_default();
+                if self.txn.concurrent().iter().any(|id| *id < since) {
+                    return Ok(None);
+                }
+                let rows = self.backfill_batch(&table, change.backfilled.as_ref())?;
+                change.backfilled_rows += rows.len() as u64;
+                change.backfilled = match rows.last() {
+                    Some(row) => Some(table.get_row_key(row)?),
+                    None => change.backfilled,
+                };
+                // The cursor is reset for verification.
+                if rows.len() < BACKFILL_BATCH_SIZE {
+                    change.state = SchemaState::WriteVisible;
+                    change.backfilled = None;
+                }
+                for row in rows {
+                    self.backfill_row(&table, change.column(), row, false)?;
+                }
+            }
+            // Verify in batches that all rows were backfilled before making the change visible
+            // to reads, and start over otherwise. Rows written since are maintained by writes.
+            SchemaState::WriteVisible | SchemaState::ReadVisible => {
+                let rows = self.backfill_batch(&table, change.backfilled.as_ref())?;
+                let done = rows.len() < BACKFILL_BATCH_SIZE;
+                change.backfilled = match rows.last() {
+                    Some(row) => Some(table.get_row_key(row)?),
+                    None => change.backfilled,
+                };
+                let mut complete = true;
+                for row in rows {
+                    if self.backfill_row(&table, change.column(), row, true)? {
+                        complete = false;
+                        break;
+                    }
+                }
+                if complete && done {
+                    self.save_schema(&table)?;
+                    return Ok(Some(SchemaState::ReadVisible));
+                }
+                if !complete {
+                    change.state = SchemaState::Backfilling;
+                    change.backfilled = None;
+                    change.backfilled_rows = 0;
+                }
+            }
+        }
+        let state = change.state;
+        table.change = Some(change);
+        self.save_schema(&table)?;
+        Ok(Some(state))
     }
 }
 
 impl Catalog for Transaction {
-    fn create_table(&mut self, table: Table) -> Result<()> {
+    fn create_table(&mut self, mut table: Table) -> Result<()> {
+        system::ensure_writable(&table.name)?;
         if self.read_table(&table.name)?.is_some() {
             return Err(Error::Value(format!("Table {} already exists", table.name)));
         }
         table.validate(self)?;
-        self.txn.set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)
+        table.id = self.next_table_id()?;
+        // Partitions are allocated IDs in order, such that their row keys are ordered too.
+        for partition in table.partitions.iter_mut() {
+            partition.id = self.next_table_id()?;
+        }
+        self.txn.set(&Key::TableName((&table.name).into()).encode(), serialize(&table.id)?)?;
+        self.save_schema(&table)
     }
 
     fn delete_table(&mut self, table: &str) -> Result<()> {
-        let table = self.must_read_table(&table)?;
+        let table = self.must_read_schema(&table)?;
+        system::ensure_writable(&table.name)?;
         if let Some((t, cs)) = self.table_references(&table.name, false)?.first() {
             return Err(Error::Value(format!(
                 "Table {} is referenced by table {} column {}",
@@ -287,35 +1000,245 @@ impl Catalog for Transaction {
         while let Some(row) = scan.next().transpose()? {
             self.delete(&table.name, &table.get_row_key(&row)?)?
         }
-        self.txn.delete(&Key::Table(Some(table.name.into())).encode())
+        for (key, _) in self.row_count_deltas(table.id)? {
+            self.txn.delete(&key)?;
+        }
+        self.bump_catalog_version()?;
+        self.txn.delete(&Key::TableName(table.name.into()).encode())?;
+        self.txn.delete(&Key::Table(Some(table.id)).encode())
     }
 
     fn read_table(&self, table: &str) -> Result<Option<Table>> {
-        self.txn.get(&Key::Table(Some(table.into())).encode())?.map(|v| deserialize(&v)).transpose()
+        Ok(self.read_schema(table)?.map(Table::visible))
     }
 
     fn scan_tables(&self) -> Result<Tables> {
-        Ok(Box::new(
-            self.txn
-                .scan_prefix(&Key::Table(None).encode())?
-                .map(|r| r.and_then(|(_, v)| deserialize(&v)))
-                .collect::<Result<Vec<_>>>()?
-                .into_iter(),
-        ))
+        let mut tables = self
+            .txn
+            .scan_prefix(&Key::Table(None).encode())?
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect::<Result<Vec<Table>>>()?;
+        let names: HashMap<u64, String> = tables.iter().map(|t| (t.id, t.name.clone())).collect();
+        for column in tables.iter_mut().flat_map(|t| t.columns.iter_mut()) {
+            column.references = column.references_id.and_then(|id| names.get(&id).cloned());
+        }
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Box::new(tables.into_iter().map(Table::visible)))
+    }
+
+    fn catalog_version(&self) -> Result<u64> {
+        self.txn
+            .get(&Key::CatalogVersion.encode())?
+            .map(|v| deserialize(&v))
+            .transpose()
+            .map(|v| v.unwrap_or(0))
+    }
+
+    fn row_count(&self, table: &str) -> Result<u64> {
+        let table = self.must_read_schema(table)?;
+        if system::is_system_table(&table.name) {
+            return Ok(self.scan_system(&table)?.len() as u64);
+        }
+        let count: i64 = self.row_count_deltas(table.id)?.into_iter().map(|(_, d)| d).sum();
+        Ok(count.max(0) as u64)
+    }
+
+    fn add_column(&mut self, table: &str, column: Column) -> Result<()> {
+        let mut table = self.must_read_schema(table)?;
+        system::ensure_writable(&table.name)?;
+        table.add_column(column, self)?;
+        self.save_schema(&table)
+    }
+
+    fn create_index(&mut self, table: &str, column: &str) -> Result<()> {
+        let mut table = self.must_read_schema(table)?;
+        system::ensure_writable(&table.name)?;
+        table.create_index(column)?;
+        self.save_schema(&table)
+    }
+
+    fn alter_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        alteration: ColumnAlteration,
+    ) -> Result<()> {
+        let mut table = self.must_read_schema(table)?;
+        system::ensure_writable(&table.name)?;
+        table.alter_column(column, alteration, self)?;
+        self.save_schema(&table)
+    }
+
+    fn set_comment(
+        &mut self,
+        table: &str,
+        column: Option<&str>,
+        comment: Option<String>,
+    ) -> Result<()> {
+        let mut table = self.must_read_schema(table)?;
+        system::ensure_writable(&table.name)?;
+        match column {
+            Some(column) => {
+                // Columns that are still being added can't be commented on yet.
+                table.clone().visible().get_column(column)?;
+                table.columns.iter_mut().find(|c| c.name == column).unwrap().comment = comment;
+            }
+            None => table.comment = comment,
+        }
+        self.save_schema(&table)
+    }
+
+    fn settings(&self) -> Result<Settings> {
+        self.txn
+            .scan_prefix(&Key::Setting(None).encode())?
+            .map(|r| {
+                let (k, v) = r?;
+                match Key::decode(&k)? {
+                    Key::Setting(Some(name)) => Ok((name.into_owned(), deserialize(&v)?)),
+                    _ => Err(Error::Internal("Invalid setting key".into())),
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Settings::new)
+    }
+
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()> {
+        let key = Key::Setting(Some(name.into())).encode();
+        match value {
+            Some(value) => self.txn.set(&key, serialize(&settings::validate(name, value)?)?)?,
+            None => {
+                settings::definition(name)?;
+                self.txn.delete(&key)?
+            }
+        }
+        // Sessions cache settings by catalog version, so bump it to make them reload.
+        self.bump_catalog_version()
+    }
+
+    fn create_function(&mut self, function: Function) -> Result<()> {
+        if self.read_function(&function.name)?.is_some() {
+            return Err(Error::Value(format!("Function {} already exists", function.name)));
+        }
+        self.bump_catalog_version()?;
+        self.txn.set(&Key::Function(Some((&function.name).into())).encode(), serialize(&function)?)
+    }
+
+    fn delete_function(&mut self, function: &str) -> Result<()> {
+        if self.read_function(function)?.is_none() {
+            return Err(Error::Value(format!("Function {} does not exist", function)));
+        }
+        self.bump_catalog_version()?;
+        self.txn.delete(&Key::Function(Some(function.into())).encode())
+    }
+
+    fn read_function(&self, function: &str) -> Result<Option<Function>> {
+        self.txn
+            .get(&Key::Function(Some(function.into())).encode())?
+            .map(|v| deserialize(&v))
+            .transpose()
+    }
+
+    fn scan_functions(&self) -> Result<Vec<Function>> {
+        self.txn
+            .scan_prefix(&Key::Function(None).encode())?
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect()
+    }
+
+    fn create_enum(&mut self, r#enum: Enum) -> Result<()> {
+        if self.read_enum(&r#enum.name)?.is_some() {
+            return Err(Error::Value(format!("Type {} already exists", r#enum.name)));
+        }
+        self.bump_catalog_version()?;
+        self.txn.set(&Key::Enum(Some((&r#enum.name).into())).encode(), serialize(&r#enum)?)
+    }
+
+    fn delete_enum(&mut self, r#enum: &str) -> Result<()> {
+        if self.read_enum(r#enum)?.is_none() {
+            return Err(Error::Value(format!("Type {} does not exist", r#enum)));
+        }
+        for table in self.scan_tables()? {
+            for column in &table.columns {
+                let datatype = match &column.datatype {
+                    DataType::Array(element) => element,
+                    datatype => datatype,
+                };
+                if matches!(datatype, DataType::Enum(e) if e.name == r#enum) {
+                    return Err(Error::Value(format!(
+                        "Type {} is used by table {} column {}",
+                        r#enum, table.name, column.name
+                    )));
+                }
+            }
+        }
+        self.bump_catalog_version()?;
+        self.txn.delete(&Key::Enum(Some(r#enum.into())).encode())
+    }
+
+    fn create_policy(&mut self, table: &str, policy: Policy) -> Result<()> {
+        let mut table = self.must_read_schema(table)?;
+        system::ensure_writable(&table.name)?;
+        if table.policies.iter().any(|p| p.name == policy.name) {
+            return Err(Error::Value(format!(
+                "Policy {} already exists for table {}",
+                policy.name, table.name
+            )));
+        }
+        table.policies.push(policy);
+        self.save_schema(&table)
+    }
+
+    fn delete_policy(&mut self, table: &str, policy: &str) -> Result<()> {
+        let mut table = self.must_read_schema(table)?;
+        system::ensure_writable(&table.name)?;
+        match table.policies.iter().position(|p| p.name == policy) {
+            Some(i) => table.policies.remove(i),
+            None => {
+                return Err(Error::Value(format!(
+                    "Policy {} does not exist for table {}",
+                    policy, table.name
+                )))
+            }
+        };
+        self.save_schema(&table)
+    }
+
+    fn read_enum(&self, r#enum: &str) -> Result<Option<Enum>> {
+        self.txn.get(&Key::Enum(Some(r#enum.into())).encode())?.map(|v| deserialize(&v)).transpose()
     }
 }
 
 /// Encodes SQL keys, using an order-preserving encoding - see kv::encoding for details. Options can
-/// be None to get a keyspace prefix. We use table and column names directly as identifiers, to
-/// avoid additional indirection and associated overhead. It is not possible to change names, so
-/// this is ok. Uses Cows since we want to borrow when encoding but return owned when decoding.
+/// be None to get a keyspace prefix. Tables and columns are identified by internal IDs rather than
+/// names, such that renaming them doesn't require rewriting rows, index entries, and references,
+/// at the cost of looking up the table ID by name. Uses Cows since we want to borrow when encoding
+/// but return owned when decoding.
 enum Key<'a> {
-    /// A table schema key for the given table name
-    Table(Option<Cow<'a, str>>),
-    /// A key for an index entry
-    Index(Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, Value>>),
-    /// A key for a row identified by table name and row primary key
-    Row(Cow<'a, str>, Option<Cow<'a, Value>>),
+    /// A table schema key for the given table ID
+    Table(Option<u64>),
+    /// A key for an index entry identified by table ID, column ID, and column value. NULL values
+    /// are ordered first, like the default ascending ORDER BY.
+    Index(u64, u64, Option<Cow<'a, Value>>),
+    /// A key for a row identified by table ID and row primary key
+    Row(u64, Option<Cow<'a, Value>>),
+    /// A key for an audit log record identified by transaction ID and sequence number
+    Audit(Option<(u64, u64)>),
+    /// A keyspace prefix for the audit log records of a transaction
+    AuditTxn(u64),
+    /// The catalog version, incremented by every schema change
+    CatalogVersion,
+    /// A key mapping a table name to its table ID
+    TableName(Cow<'a, str>),
+    /// The next table ID to assign
+    NextTableId,
+    /// A key for a transaction's row count delta, identified by table ID and transaction ID
+    RowCount(u64, Option<u64>),
+    /// A cluster setting key for the given setting name
+    Setting(Option<Cow<'a, str>>),
+    /// A stored function key for the given function name
+    Function(Option<Cow<'a, str>>),
+    /// An enum type key for the given type name
+    Enum(Option<Cow<'a, str>>),
 }
 
 impl<'a> Key<'a> {
@@ -324,21 +1247,36 @@ impl<'a> Key<'a> {
         use kv::encoding::*;
         match self {
             Self::Table(None) => vec![0x01],
-            Self::Table(Some(name)) => [&[0x01][..], &encode_string(&name)].concat(),
+            Self::Table(Some(id)) => [&[0x01][..], &encode_u64(id)].concat(),
             Self::Index(table, column, None) => {
-                [&[0x02][..], &encode_string(&table), &encode_string(&column)].concat()
+                [&[0x02][..], &encode_u64(table), &encode_u64(column)].concat()
             }
-            Self::Index(table, column, Some(value)) => [
-                &[0x02][..],
-                &encode_string(&table),
-                &encode_string(&column),
-                &encode_value(&value),
-            ]
-            .concat(),
-            Self::Row(table, None) => [&[0x03][..], &encode_string(&table)].concat(),
+            Self::Index(table, column, Some(value)) => {
+                [&[0x02][..], &encode_u64(table), &encode_u64(column), &encode_value(&value)]
+                    .concat()
+            }
+            Self::Row(table, None) => [&[0x03][..], &encode_u64(table)].concat(),
             Self::Row(table, Some(pk)) => {
-                [&[0x03][..], &encode_string(&table), &encode_value(&pk)].concat()
+                [&[0x03][..], &encode_u64(table), &encode_value(&pk)].concat()
+            }
+            Self::Audit(None) => vec![0x04],
+            Self::Audit(Some((txn_id, seq))) => {
+                [&[0x04][..], &encode_u64(txn_id), &encode_u64(seq)].concat()
             }
+            Self::AuditTxn(txn_id) => [&[0x04][..], &encode_u64(txn_id)].concat(),
+            Self::CatalogVersion => vec![0x05],
+            Self::TableName(name) => [&[0x06][..], &encode_string(&name)].concat(),
+            Self::NextTableId => vec![0x07],
+            Self::RowCount(table, None) => [&[0x08][..], &encode_u64(table)].concat(),
+            Self::RowCount(table, Some(txn_id)) => {
+                [&[0x08][..], &encode_u64(table), &encode_u64(txn_id)].concat()
+            }
+            Self::Setting(None) => vec![0x09],
+            Self::Setting(Some(name)) => [&[0x09][..], &encode_string(&name)].concat(),
+            Self::Function(None) => vec![0x0a],
+            Self::Function(Some(name)) => [&[0x0a][..], &encode_string(&name)].concat(),
+            Self::Enum(None) => vec![0x0b],
+            Self::Enum(Some(name)) => [&[0x0b][..], &encode_string(&name)].concat(),
         }
     }
 
@@ -347,13 +1285,19 @@ impl<'a> Key<'a> {
         use kv::encoding::*;
         let bytes = &mut bytes;
         let key = match take_byte(bytes)? {
-            0x01 => Self::Table(Some(take_string(bytes)?.into())),
-            0x02 => Self::Index(
-                take_string(bytes)?.into(),
-                take_string(bytes)?.into(),
-                Some(take_value(bytes)?.into()),
-            ),
-            0x03 => Self::Row(take_string(bytes)?.into(), Some(take_value(bytes)?.into())),
+            0x01 => Self::Table(Some(take_u64(bytes)?)),
+            0x02 => {
+                Self::Index(take_u64(bytes)?, take_u64(bytes)?, Some(take_value(bytes)?.into()))
+            }
+            0x03 => Self::Row(take_u64(bytes)?, Some(take_value(bytes)?.into())),
+            0x04 => Self::Audit(Some((take_u64(bytes)?, take_u64(bytes)?))),
+            0x05 => Self::CatalogVersion,
+            0x06 => Self::TableName(take_string(bytes)?.into()),
+            0x07 => Self::NextTableId,
+            0x08 => Self::RowCount(take_u64(bytes)?, Some(take_u64(bytes)?)),
+            0x09 => Self::Setting(Some(take_string(bytes)?.into())),
+            0x0a => Self::Function(Some(take_string(bytes)?.into())),
+            0x0b => Self::Enum(Some(take_string(bytes)?.into())),
             b => return Err(Error::Internal(format!("Unknown SQL key prefix {:x?}", b))),
         };
         if !bytes.is_empty() {
@@ -362,3 +1306,149 @@ impl<'a> Key<'a> {
         Ok(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine as _, Mode};
+    use super::*;
+    use crate::sql::execution::ResultSet;
+    use crate::storage::kv::{Memory, MVCC};
+
+    #[test]
+    fn check_index() -> Result<()> {
+        let engine = super::super::KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut session = engine.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING INDEX)")?;
+        session.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")?;
+        let mut check = || -> Result<Vec<Row>> {
+            match session.execute("CHECK INDEX ON t")? {
+                ResultSet::Query { rows, .. } => rows.collect(),
+                result => Err(Error::Internal(format!("Unexpected result {:?}", result))),
+            }
+        };
+        assert_eq!(check()?, Vec::<Row>::new());
+
+        // Corrupt the index: remove the entries of rows 1 and 3, add an entry for a nonexistent
+        // row 4, and file row 3 under the wrong value.
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let table = txn.must_read_schema("t")?;
+        let column = table.get_column("value")?;
+        txn.index_save(&table, column, &"a".into(), HashSet::new())?;
+        txn.index_save(
+            &table,
+            column,
+            &"b".into(),
+            vec![2.into(), 4.into()].into_iter().collect(),
+        )?;
+        txn.index_save(&table, column, &"c".into(), HashSet::new())?;
+        txn.index_save(&table, column, &"x".into(), vec![3.into()].into_iter().collect())?;
+        txn.commit()?;
+
+        let row = |value: &str, id: i64, problem: &str| -> Row {
+            vec!["value".into(), value.into(), id.into(), problem.into()]
+        };
+        assert_eq!(
+            check()?,
+            vec![
+                row("b", 4, "extra"),
+                row("x", 3, "incorrect"),
+                row("a", 1, "missing"),
+                row("c", 3, "missing"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn gc() -> Result<()> {
+        let engine = super::super::KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut session = engine.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER)")?;
+        session.execute("INSERT INTO t VALUES (1, 0)")?;
+        assert_eq!(engine.gc(10_000)?, (0, true));
+
+        // Each run records the current version as a checkpoint, here 5, 16 and 17, but nothing is
+        // garbage collected until a checkpoint is outside the retention window. The first one is
+        // at 11_000, which removes a single superseded version.
+        session.execute("SET CLUSTER SETTING mvcc_retention_ms = 1000")?;
+        assert_eq!(engine.gc(10_000)?, (0, true));
+        for _ in 0..10 {
+            session.execute("UPDATE t SET value = value + 1")?;
+        }
+        assert_eq!(engine.gc(10_500)?, (0, true));
+        assert_eq!(engine.gc(11_000)?, (1, true));
+        assert_eq!(
+            session.execute("BEGIN READ ONLY AS OF SYSTEM TIME 4"),
+            Err(Error::Value(
+                "Version 4 is outside the retention window, the earliest available version is 5"
+                    .into()
+            ))
+        );
+
+        // Once the second checkpoint is outside the retention window, the row versions before the
+        // one visible at version 16 are removed.
+        assert_eq!(engine.gc(11_600)?, (10, true));
+        let txn = engine.begin(Mode::ReadOnly)?;
+        let id = txn.must_read_table("t")?.id;
+        assert_eq!(txn.txn.stats_prefix(&Key::Row(id, None).encode())?.versions, 1);
+        txn.rollback()?;
+        assert_eq!(
+            session.execute("BEGIN READ ONLY AS OF SYSTEM TIME 15"),
+            Err(Error::Value(
+                "Version 15 is outside the retention window, the earliest available version is 16"
+                    .into()
+            ))
+        );
+        session.execute("BEGIN READ ONLY AS OF SYSTEM TIME 16")?;
+        match session.execute("SELECT value FROM t")? {
+            ResultSet::Query { rows, .. } => {
+                assert_eq!(rows.collect::<Result<Vec<_>>>()?, vec![vec![Value::Integer(10)]])
+            }
+            result => return Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        }
+        session.execute("COMMIT")?;
+        Ok(())
+    }
+
+    #[test]
+    fn row_count() -> Result<()> {
+        let engine = super::super::KV::new(MVCC::new(Box::new(Memory::new())));
+        let mut session = engine.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+        let count = || -> Result<u64> { engine.begin(Mode::ReadOnly)?.row_count("t") };
+        assert_eq!(count()?, 0);
+
+        session.execute("INSERT INTO t VALUES (1), (2), (3)")?;
+        session.execute("UPDATE t SET id = 4 WHERE id = 3")?;
+        session.execute("DELETE FROM t WHERE id = 1")?;
+        assert_eq!(count()?, 2);
+
+        // Rolled back writes aren't counted, and concurrent writers don't conflict.
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        txn.create("t", vec![5.into()])?;
+        txn.rollback()?;
+        let mut a = engine.begin(Mode::ReadWrite)?;
+        let mut b = engine.begin(Mode::ReadWrite)?;
+        a.create("t", vec![5.into()])?;
+        b.create("t", vec![6.into()])?;
+        a.commit()?;
+        b.commit()?;
+        assert_eq!(count()?, 4);
+
+        // Writers fold the deltas once there are many of them.
+        let threshold = ROW_COUNT_FOLD_THRESHOLD as i64;
+        for id in 0..2 * threshold {
+            session.execute(&format!("INSERT INTO t VALUES ({})", 10 + id))?;
+        }
+        assert_eq!(count()?, 4 + 2 * threshold as u64);
+        let txn = engine.begin(Mode::ReadOnly)?;
+        let id = txn.must_read_schema("t")?.id;
+        assert!(txn.row_count_deltas(id)?.len() <= ROW_COUNT_FOLD_THRESHOLD);
+        txn.commit()?;
+
+        // Dropping the table removes its deltas.
+        session.execute("DROP TABLE t")?;
+        assert!(engine.begin(Mode::ReadOnly)?.row_count_deltas(id)?.is_empty());
+        Ok(())
+    }
+}
@@ -1,33 +1,77 @@
 //! The SQL engine provides fundamental CRUD storage operations.
+mod cache;
 mod kv;
+mod query_cache;
 pub mod raft;
+mod stats;
+mod system;
+pub use cache::{CachedCatalog, SchemaCache};
 pub use kv::KV;
+pub use query_cache::{QueryCache, QueryKey};
 pub use raft::{Raft, Status};
+pub use stats::{fingerprint, StatementStat, StatementStats};
 
 use super::execution::ResultSet;
 use super::parser::{ast, Parser};
-use super::plan::Plan;
-use super::schema::Catalog;
-use super::types::{Expression, Row, Value};
+use super::plan::{Explanation, Format, Plan};
+use super::schema::{Catalog, SchemaState};
+use super::types::{Column, Columns, DataType, Expression, Row, Rows, Value};
 use crate::error::{Error, Result};
+use crate::trace::span;
 
-use std::collections::HashSet;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
 /// The SQL engine interface
 pub trait Engine: Clone {
     /// The transaction type
     type Transaction: Transaction;
 
-    /// Begins a transaction in the given mode
-    fn begin(&self, mode: Mode) -> Result<Self::Transaction>;
+    /// Begins a transaction in the given mode, with normal priority
+    fn begin(&self, mode: Mode) -> Result<Self::Transaction> {
+        self.begin_with_priority(mode, Priority::default())
+    }
+
+    /// Begins a transaction in the given mode and priority
+    fn begin_with_priority(&self, mode: Mode, priority: Priority) -> Result<Self::Transaction>;
 
     /// Begins a session for executing individual statements
     fn session(&self) -> Result<Session<Self>> {
-        Ok(Session { engine: self.clone(), txn: None })
+        Ok(Session {
+            engine: self.clone(),
+            txn: None,
+            audit_user: None,
+            user: None,
+            role: None,
+            stats: StatementStats::new(),
+            cursors: HashMap::new(),
+            schema: SchemaCache::new(),
+            schema_dirty: false,
+        })
     }
 
+    /// Returns the statement statistics of all sessions of the engine
+    fn statement_stats(&self) -> &StatementStats;
+
+    /// Returns the query result cache shared by all sessions of the engine
+    fn query_cache(&self) -> &QueryCache;
+
     /// Resumes an active transaction with the given ID
     fn resume(&self, id: u64) -> Result<Self::Transaction>;
+
+    /// Detaches an active transaction from its session, recording the given marker such that
+    /// it can only be attached again by a session of the same user and role
+    fn detach(&self, id: u64, detached: Detached) -> Result<()>;
+
+    /// Attaches a detached transaction to a session of the given user and role, removing its
+    /// detached marker. Errors if the transaction isn't detached, or was detached by a session
+    /// of a different user or role.
+    fn attach(&self, id: u64, user: Option<&str>, role: Option<&str>) -> Result<Self::Transaction>;
+
+    /// Rolls back transactions detached before the given time, in milliseconds since the Unix
+    /// epoch, returning the number of transactions rolled back
+    fn rollback_detached(&self, before: u64) -> Result<u64>;
 }
 
 /// An SQL transaction
@@ -36,6 +80,11 @@ pub trait Transaction: Catalog {
     fn id(&self) -> u64;
     /// The transaction mode
     fn mode(&self) -> Mode;
+    /// The transaction priority
+    fn priority(&self) -> Priority;
+    /// The data version when the transaction began. Read-only transactions with the same data
+    /// version see the same data.
+    fn data_version(&self) -> u64;
     /// Commits the transaction
     fn commit(self) -> Result<()>;
     /// Rolls back the transaction
@@ -43,6 +92,8 @@ pub trait Transaction: Catalog {
 
     /// Creates a new table row
     fn create(&mut self, table: &str, row: Row) -> Result<()>;
+    /// Creates a batch of new table rows as a single write, stopping at the first error
+    fn create_batch(&mut self, table: &str, rows: Vec<Row>) -> Result<()>;
     /// Deletes a table row
     fn delete(&mut self, table: &str, id: &Value) -> Result<()>;
     /// Reads a table row, if it exists
@@ -51,10 +102,81 @@ pub trait Transaction: Catalog {
     fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>>;
     /// Scans a table's rows
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Scan>;
+    /// Scans the rows of a table partition
+    fn scan_partition(
+        &self,
+        table: &str,
+        partition: &str,
+        filter: Option<Expression>,
+    ) -> Result<Scan>;
     /// Scans a column's index entries
     fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan>;
+    /// Scans a column's index entries between the given inclusive bounds, in key encoding order
+    fn scan_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        lower: &Value,
+        upper: &Value,
+    ) -> Result<IndexScan>;
     /// Updates a table row
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()>;
+
+    /// Fetches committed changes to a table's rows in commit order from the given commit position,
+    /// along with the resolved position up to which the changes are complete
+    fn changes(&self, table: &str, from: u64) -> Result<(Vec<Change>, u64)>;
+    /// Fetches all committed versions of a table row, ordered by version
+    fn history(&self, table: &str, id: &Value) -> Result<Vec<Change>>;
+    /// Fetches the versions that created and last updated a table row, if it exists, including
+    /// the transaction's own writes
+    fn row_versions(&self, table: &str, id: &Value) -> Result<Option<(u64, u64)>>;
+
+    /// Appends a record to the audit log, which is committed along with the transaction
+    fn audit(&mut self, record: AuditRecord) -> Result<()>;
+
+    /// Advances a table's online schema change by one step, returning its new state, or None if
+    /// it must wait for older transactions to finish first. Returns ReadVisible once complete.
+    /// The timestamp is the current time in milliseconds since the Unix epoch, recorded as the
+    /// change's start time by the first step.
+    fn advance_schema_change(&mut self, table: &str, timestamp: u64)
+        -> Result<Option<SchemaState>>;
+}
+
+/// A committed change to a table row, as returned by changefeeds and row history
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The version (i.e. transaction ID) that made the change
+    pub version: u64,
+    /// The primary key of the changed row
+    pub id: Value,
+    /// The new row, or None if the row was deleted
+    pub row: Option<Row>,
+}
+
+/// An audit log record of a DDL or DML statement, written in the statement's transaction such
+/// that it is only recorded if the transaction commits
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The time the statement was executed, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// The user that executed the statement, i.e. the client address
+    pub user: String,
+    /// The statement kind, either DDL or DML
+    pub kind: String,
+    /// The SQL statement text
+    pub statement: String,
+}
+
+/// The marker of a transaction detached from its session, which only a session of the same user
+/// and role can attach again
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Detached {
+    /// The user of the detaching session, if any
+    pub user: Option<String>,
+    /// The role of the detaching session, if any
+    pub role: Option<String>,
+    /// The time the transaction was detached, in milliseconds since the Unix epoch
+    pub timestamp: u64,
 }
 
 /// An SQL session, which handles transaction control and simplified query execution
@@ -63,57 +185,193 @@ pub struct Session<E: Engine> {
     engine: E,
     /// The current session transaction, if any
     txn: Option<E::Transaction>,
+    /// The user to record DDL and DML statements for in the audit log, if enabled
+    audit_user: Option<String>,
+    /// The user the session belongs to, if any, which is the only one allowed to resume its
+    /// detached transactions
+    user: Option<String>,
+    /// The role the session's statements run as, if any, which is restricted by the row-level
+    /// security policies of tables it doesn't own
+    role: Option<String>,
+    /// Statement statistics for the session
+    stats: StatementStats,
+    /// Open cursors in the current transaction, by name
+    cursors: HashMap<String, (Columns, Rows)>,
+    /// Cached table schemas, used when planning statements
+    schema: SchemaCache,
+    /// Whether the current transaction has changed the schema, such that the schema cache must
+    /// be invalidated if it doesn't commit
+    schema_dirty: bool,
 }
 
 impl<E: Engine + 'static> Session<E> {
-    /// Executes a query, managing transaction status for the session
+    /// Enables the audit log for the session, recording DDL and DML statements for the given user
+    pub fn with_audit(mut self, user: &str) -> Self {
+        self.audit_user = Some(user.to_string());
+        self
+    }
+
+    /// Sets the user the session belongs to, e.g. the client address. Transactions detached by
+    /// the session can only be resumed by sessions of the same user and role.
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /// Runs the session's statements as the given role. Tables created by the session are owned
+    /// by the role, and other tables only show it the rows allowed by their policies for it.
+    pub fn with_role(mut self, role: &str) -> Self {
+        self.role = Some(role.to_string());
+        self
+    }
+
+    /// Executes a query, managing transaction status for the session, and records statement
+    /// statistics for it if successful
     pub fn execute(&mut self, query: &str) -> Result<ResultSet> {
+        self.execute_params(query, &[])
+    }
+
+    /// Executes a query like execute(), binding the given values to its ? parameters by
+    /// position. The number of values must match the number of parameters.
+    pub fn execute_params(&mut self, query: &str, params: &[Value]) -> Result<ResultSet> {
+        let started = Instant::now();
+        let result = self.execute_query(query, params)?;
+        let latency = started.elapsed();
+        if let Some(threshold) = self.schema.settings().and_then(|s| s.slow_query_threshold()) {
+            if latency >= threshold {
+                ::log::warn!("Slow query ({}ms): {}", latency.as_millis(), query);
+            }
+        }
+        let stats = vec![self.stats.clone(), self.engine.statement_stats().clone()];
+        let fingerprint = fingerprint(query);
+        Ok(match result {
+            // Query rows are counted as they're consumed, and recorded once the rows are dropped.
+            ResultSet::Query { columns, rows } => ResultSet::Query {
+                columns,
+                rows: Box::new(StatsRows { rows, count: 0, stats, fingerprint, latency }),
+            },
+            result => {
+                let count = match result {
+                    ResultSet::Create { count }
+                    | ResultSet::Delete { count }
+                    | ResultSet::Update { count } => count,
+                    _ => 0,
+                };
+                for stats in stats {
+                    stats.record(&fingerprint, latency, count)?;
+                }
+                result
+            }
+        })
+    }
+
+    /// Describes a query without executing it, returning the datatypes of its ? parameters by
+    /// position. These are either given explicitly as ?::type or inferred from their context, and
+    /// are None if unknown.
+    pub fn describe(&mut self, query: &str) -> Result<Vec<Option<DataType>>> {
+        let mut parser = Parser::new(query);
+        let statement = match parser.parse()? {
+            ast::Statement::Explain { statement, .. } => *statement,
+            ast::Statement::Declare { query, .. } => *query,
+            statement => statement,
+        };
+        let parameters = parser.parameters();
+        match statement {
+            // Transaction control and cursor statements can't contain parameters.
+            ast::Statement::Begin { .. }
+            | ast::Statement::Commit { .. }
+            | ast::Statement::Rollback { .. }
+            | ast::Statement::Detach
+            | ast::Statement::Resume(_)
+            | ast::Statement::ShowTransaction
+            | ast::Statement::ShowCreateTable(_)
+            | ast::Statement::Fetch { .. }
+            | ast::Statement::Close(_) => Ok(Vec::new()),
+            statement => {
+                self.with_txn(Mode::ReadOnly, |txn| Plan::describe(statement, txn, parameters))
+            }
+        }
+    }
+
+    /// Returns the statement statistics of the session
+    pub fn statement_stats(&self) -> &StatementStats {
+        &self.stats
+    }
+
+    /// Executes a query, managing transaction status for the session
+    fn execute_query(&mut self, query: &str, params: &[Value]) -> Result<ResultSet> {
+        let mut parser = Parser::new(query);
+        let statement = span!("parse"; parser.parse())?;
+        if parser.parameters() != params.len() {
+            return Err(Error::Value(format!(
+                "Expected {} parameters, got {}",
+                parser.parameters(),
+                params.len()
+            )));
+        }
+        let audit = self.audit_record(query, &statement);
         // FIXME We should match on self.txn as well, but get this error:
         // error[E0009]: cannot bind by-move and by-ref in the same pattern
         // ...which seems like an arbitrary compiler limitation
-        match Parser::new(query).parse()? {
+        match statement {
             ast::Statement::Begin { .. } if self.txn.is_some() => {
                 Err(Error::Value("Already in a transaction".into()))
             }
-            ast::Statement::Begin { readonly: true, version: None } => {
-                let txn = self.engine.begin(Mode::ReadOnly)?;
-                let result = ResultSet::Begin { id: txn.id(), mode: txn.mode() };
-                self.txn = Some(txn);
-                Ok(result)
+            ast::Statement::Begin { readonly: true, priority: Some(_), .. } => {
+                Err(Error::Value("Can't set priority of read-only transaction".into()))
             }
-            ast::Statement::Begin { readonly: true, version: Some(version) } => {
-                let txn = self.engine.begin(Mode::Snapshot { version })?;
-                let result = ResultSet::Begin { id: txn.id(), mode: txn.mode() };
-                self.txn = Some(txn);
-                Ok(result)
+            ast::Statement::Begin { readonly: true, version: None, .. } => {
+                let txn = self.begin(Mode::ReadOnly, Priority::default())?;
+                Ok(ResultSet::Begin { id: txn.id(), mode: txn.mode() })
+            }
+            ast::Statement::Begin { readonly: true, version: Some(version), .. } => {
+                let txn = self.begin(Mode::Snapshot { version }, Priority::default())?;
+                Ok(ResultSet::Begin { id: txn.id(), mode: txn.mode() })
             }
-            ast::Statement::Begin { readonly: false, version: Some(_) } => {
+            ast::Statement::Begin { readonly: false, version: Some(_), .. } => {
                 Err(Error::Value("Can't start read-write transaction in a given version".into()))
             }
-            ast::Statement::Begin { readonly: false, version: None } => {
-                let txn = self.engine.begin(Mode::ReadWrite)?;
-                let result = ResultSet::Begin { id: txn.id(), mode: txn.mode() };
+            ast::Statement::Begin { readonly: false, version: None, priority } => {
+                let txn = self.begin(Mode::ReadWrite, priority.unwrap_or_default())?;
+                Ok(ResultSet::Begin { id: txn.id(), mode: txn.mode() })
+            }
+            ast::Statement::Resume(_) if self.txn.is_some() => {
+                Err(Error::Value("Already in a transaction".into()))
+            }
+            ast::Statement::Resume(id) => {
+                let txn = self.engine.attach(id, self.user.as_deref(), self.role.as_deref())?;
+                let result = ResultSet::Resume { id: txn.id(), mode: txn.mode() };
                 self.txn = Some(txn);
                 Ok(result)
             }
-            ast::Statement::Commit | ast::Statement::Rollback if self.txn.is_none() => {
+            ast::Statement::Commit { .. }
+            | ast::Statement::Rollback { .. }
+            | ast::Statement::Detach
+                if self.txn.is_none() =>
+            {
                 Err(Error::Value("Not in a transaction".into()))
             }
-            ast::Statement::Commit => {
+            ast::Statement::Commit { chain } => {
                 let txn = self.txn.take().unwrap();
-                let id = txn.id();
+                let (id, mode, priority) = (txn.id(), txn.mode(), txn.priority());
                 if let Err(err) = txn.commit() {
                     // If the commit fails, we try to recover the transaction.
                     if let Ok(t) = self.engine.resume(id) {
                         self.txn = Some(t);
                     }
+                    if self.schema_dirty {
+                        self.schema.invalidate();
+                    }
                     return Err(err);
                 }
-                Ok(ResultSet::Commit { id })
+                self.cursors.clear();
+                self.schema_dirty = false;
+                let chain = if chain { Some(self.begin(mode, priority)?.id()) } else { None };
+                Ok(ResultSet::Commit { id, chain })
             }
-            ast::Statement::Rollback => {
+            ast::Statement::Rollback { chain } => {
                 let txn = self.txn.take().unwrap();
-                let id = txn.id();
+                let (id, mode, priority) = (txn.id(), txn.mode(), txn.priority());
                 if let Err(err) = txn.rollback() {
                     // If the rollback fails, we try to recover the transaction.
                     if let Ok(t) = self.engine.resume(id) {
@@ -121,37 +379,440 @@ impl<E: Engine + 'static> Session<E> {
                     }
                     return Err(err);
                 }
-                Ok(ResultSet::Rollback { id })
-            }
-            ast::Statement::Explain(statement) => self.with_txn(Mode::ReadOnly, |txn| {
-                Ok(ResultSet::Explain(Plan::build(*statement, txn)?.optimize(txn)?.0))
-            }),
-            statement if self.txn.is_some() => Plan::build(statement, self.txn.as_mut().unwrap())?
-                .optimize(self.txn.as_mut().unwrap())?
-                .execute(self.txn.as_mut().unwrap()),
+                self.cursors.clear();
+                if self.schema_dirty {
+                    self.schema.invalidate();
+                    self.schema_dirty = false;
+                }
+                let chain = if chain { Some(self.begin(mode, priority)?.id()) } else { None };
+                Ok(ResultSet::Rollback { id, chain })
+            }
+            // The transaction is left active, and can be resumed with RESUME by a session of the
+            // same user and role. The session's cached schema changes aren't visible elsewhere,
+            // so they're discarded.
+            ast::Statement::Detach => {
+                let id = self.txn.as_ref().unwrap().id();
+                let detached =
+                    Detached { user: self.user.clone(), role: self.role.clone(), timestamp: now() };
+                self.engine.detach(id, detached)?;
+                self.txn = None;
+                self.cursors.clear();
+                if self.schema_dirty {
+                    self.schema.invalidate();
+                    self.schema_dirty = false;
+                }
+                Ok(ResultSet::Detach { id })
+            }
+            ast::Statement::ShowTransaction => {
+                let row = match &self.txn {
+                    Some(txn) => {
+                        let (read_only, as_of) = match txn.mode() {
+                            Mode::ReadWrite => (false, Value::Null),
+                            Mode::ReadOnly => (true, Value::Null),
+                            Mode::Snapshot { version } => (true, Value::Integer(version as i64)),
+                        };
+                        vec![
+                            Value::String("active".into()),
+                            Value::Integer(txn.id() as i64),
+                            Value::Boolean(read_only),
+                            as_of,
+                        ]
+                    }
+                    None => {
+                        vec![Value::String("idle".into()), Value::Null, Value::Null, Value::Null]
+                    }
+                };
+                Ok(ResultSet::Query {
+                    columns: vec![
+                        Column::new("status", DataType::String),
+                        Column::new("id", DataType::Integer),
+                        Column::new("read_only", DataType::Boolean),
+                        Column::new("as_of", DataType::Integer),
+                    ],
+                    rows: Box::new(std::iter::once(Ok(row))),
+                })
+            }
+            // Returns the table's schema as SQL, including its comments and policies.
+            ast::Statement::ShowCreateTable(table) => {
+                let table = self.with_txn(Mode::ReadOnly, |txn| txn.must_read_table(&table))?;
+                Ok(ResultSet::Query {
+                    columns: vec![Column::new("create_table", DataType::String)],
+                    rows: Box::new(std::iter::once(Ok(vec![Value::String(table.to_string())]))),
+                })
+            }
+            ast::Statement::Declare { .. }
+            | ast::Statement::Fetch { .. }
+            | ast::Statement::Close(_)
+                if self.txn.is_none() =>
+            {
+                Err(Error::Value("Cursors can only be used in a transaction".into()))
+            }
+            ast::Statement::Declare { name, query } => {
+                if self.cursors.contains_key(&name) {
+                    return Err(Error::Value(format!("Cursor {} already exists", name)));
+                }
+                let txn = self.txn.as_mut().unwrap();
+                match Self::run(*query, params, self.role.as_deref(), txn, &mut self.schema)? {
+                    ResultSet::Query { columns, rows } => {
+                        self.cursors.insert(name.clone(), (columns, rows));
+                        Ok(ResultSet::Declare { name })
+                    }
+                    result => {
+                        Err(Error::Internal(format!("Unexpected cursor result {:?}", result)))
+                    }
+                }
+            }
+            ast::Statement::Fetch { name, count } => {
+                let (columns, rows) = self
+                    .cursors
+                    .get_mut(&name)
+                    .ok_or_else(|| Error::Value(format!("Cursor {} does not exist", name)))?;
+                let rows = rows.by_ref().take(count as usize).collect::<Result<Vec<_>>>()?;
+                Ok(ResultSet::Query {
+                    columns: columns.clone(),
+                    rows: Box::new(rows.into_iter().map(Ok)),
+                })
+            }
+            ast::Statement::Close(name) => match self.cursors.remove(&name) {
+                Some(_) => Ok(ResultSet::Close { name }),
+                None => Err(Error::Value(format!("Cursor {} does not exist", name))),
+            },
+            ast::Statement::Explain { statement, format, verbose } => {
+                let format = match format {
+                    ast::ExplainFormat::Text => Format::Text,
+                    ast::ExplainFormat::Json => Format::Json,
+                    ast::ExplainFormat::Dot => Format::Dot,
+                };
+                let role = self.role.clone();
+                self.with_txn(Mode::ReadOnly, |txn| {
+                    let plan = Plan::build_for_role(*statement, txn, params, role.as_deref())?;
+                    let mut trace = Vec::new();
+                    if verbose {
+                        trace.push(("Planner".to_string(), plan.0.clone()));
+                    }
+                    let plan = plan.optimize_with(txn, |name, node| {
+                        if verbose {
+                            trace.push((name.to_string(), node.clone()))
+                        }
+                    })?;
+                    Ok(ResultSet::Explain(Explanation { plan: plan.0, format, trace }))
+                })
+            }
+            ast::Statement::AddColumn { .. } | ast::Statement::CreateIndex { .. }
+                if self.txn.is_some() =>
+            {
+                Err(Error::Value("Schema changes can't be run in a transaction".into()))
+            }
+            statement if self.txn.is_some() => {
+                let txn = self.txn.as_mut().unwrap();
+                if let ast::Statement::CreateTable { .. }
+                | ast::Statement::DropTable(_)
+                | ast::Statement::AlterColumn { .. }
+                | ast::Statement::Comment { .. }
+                | ast::Statement::SetSetting { .. }
+                | ast::Statement::CreateFunction { .. }
+                | ast::Statement::DropFunction(_)
+                | ast::Statement::CreateType { .. }
+                | ast::Statement::DropType(_)
+                | ast::Statement::CreatePolicy { .. }
+                | ast::Statement::DropPolicy { .. } = statement
+                {
+                    self.schema_dirty = true;
+                }
+                let result =
+                    Self::run(statement, params, self.role.as_deref(), txn, &mut self.schema)?;
+                if let Some(record) = audit {
+                    txn.audit(record)?;
+                }
+                Ok(result)
+            }
             statement @ ast::Statement::Select { .. } => {
+                let mut txn = self.engine.begin(Mode::ReadOnly)?;
+                let result = self.run_cached(query, statement, params, &mut txn);
+                txn.rollback()?;
+                result
+            }
+            statement @ ast::Statement::Changefeed { .. }
+            | statement @ ast::Statement::History { .. }
+            | statement @ ast::Statement::CheckIndex { .. } => {
                 let mut txn = self.engine.begin(Mode::ReadOnly)?;
                 let result =
-                    Plan::build(statement, &mut txn)?.optimize(&mut txn)?.execute(&mut txn);
+                    Self::run(statement, params, self.role.as_deref(), &mut txn, &mut self.schema);
                 txn.rollback()?;
                 result
             }
-            statement => {
-                let mut txn = self.engine.begin(Mode::ReadWrite)?;
-                match Plan::build(statement, &mut txn)?.optimize(&mut txn)?.execute(&mut txn) {
-                    Ok(result) => {
-                        txn.commit()?;
-                        Ok(result)
+            // Online schema changes are started in their own transaction, and then completed
+            // in separate transactions, such that they don't block concurrent statements. Any
+            // interrupted schema changes are completed first.
+            statement @ ast::Statement::AddColumn { .. }
+            | statement @ ast::Statement::CreateIndex { .. } => {
+                self.complete_schema_changes(SCHEMA_CHANGE_TIMEOUT)?;
+                let result = self.run_write(statement, params, audit)?;
+                match &result {
+                    ResultSet::AddColumn { table, .. } | ResultSet::CreateIndex { table, .. } => {
+                        let deadline = Instant::now() + SCHEMA_CHANGE_TIMEOUT;
+                        self.complete_schema_change(table, deadline)?
                     }
-                    Err(error) => {
-                        txn.rollback()?;
-                        Err(error)
+                    _ => {}
+                }
+                Ok(result)
+            }
+            statement => self.run_write(statement, params, audit),
+        }
+    }
+
+    /// Runs a statement in a new read-write transaction, recording it in the audit log if given
+    fn run_write(
+        &mut self,
+        statement: ast::Statement,
+        params: &[Value],
+        audit: Option<AuditRecord>,
+    ) -> Result<ResultSet> {
+        let mut txn = self.engine.begin(Mode::ReadWrite)?;
+        let role = self.role.as_deref();
+        match Self::run(statement, params, role, &mut txn, &mut self.schema).and_then(|result| {
+            if let Some(record) = audit {
+                txn.audit(record)?;
+            }
+            Ok(result)
+        }) {
+            Ok(result) => {
+                txn.commit()?;
+                Ok(result)
+            }
+            Err(error) => {
+                txn.rollback()?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Completes all online schema changes in progress, e.g. ones interrupted by a crash, and
+    /// returns the number of schema changes completed. Returns Error::Abort if they don't
+    /// complete within the given timeout, in which case they can be completed by a later call.
+    pub fn complete_schema_changes(&mut self, timeout: Duration) -> Result<u64> {
+        let deadline = Instant::now() + timeout;
+        let tables = self.with_txn(Mode::ReadOnly, |txn| {
+            Ok(txn
+                .scan_tables()?
+                .filter(|t| t.change.is_some())
+                .map(|t| t.name)
+                .collect::<Vec<_>>())
+        })?;
+        for table in &tables {
+            self.complete_schema_change(table, deadline)?;
+        }
+        Ok(tables.len() as u64)
+    }
+
+    /// Completes a table's online schema change, advancing it by one step per transaction until
+    /// it is read-visible. Waits for older transactions to finish when needed, and retries steps
+    /// that conflict with concurrent writes, backing off exponentially while no progress is made.
+    /// Steps are paced according to the backfill_rows_per_second cluster setting, such that
+    /// backfilling a large table doesn't saturate the Raft log. Returns Error::Abort if the
+    /// change isn't complete by the given deadline.
+    fn complete_schema_change(&self, table: &str, deadline: Instant) -> Result<()> {
+        let mut wait = SCHEMA_CHANGE_WAIT;
+        let mut backoff = |wait: &mut Duration| -> Result<()> {
+            if Instant::now() + *wait > deadline {
+                return Err(Error::Abort);
+            }
+            std::thread::sleep(*wait);
+            *wait = std::cmp::min(*wait * 2, SCHEMA_CHANGE_MAX_WAIT);
+            Ok(())
+        };
+        loop {
+            let start = Instant::now();
+            let mut txn = self.engine.begin(Mode::ReadWrite)?;
+            let rate = txn.settings()?.backfill_rate();
+            let state = match txn.advance_schema_change(table, now()) {
+                Ok(state) => state,
+                Err(Error::Serialization) => {
+                    txn.rollback()?;
+                    backoff(&mut wait)?;
+                    continue;
+                }
+                Err(error) => {
+                    txn.rollback()?;
+                    return Err(error);
+                }
+            };
+            txn.commit()?;
+            match state {
+                Some(SchemaState::ReadVisible) => return Ok(()),
+                Some(_) if Instant::now() >= deadline => return Err(Error::Abort),
+                Some(_) => {
+                    wait = SCHEMA_CHANGE_WAIT;
+                    if let Some(rate) = rate {
+                        let step =
+                            Duration::from_secs_f64(BACKFILL_BATCH_SIZE as f64 / rate as f64);
+                        if let Some(wait) = step.checked_sub(start.elapsed()) {
+                            std::thread::sleep(wait)
+                        }
                     }
                 }
+                None => backoff(&mut wait)?,
+            }
+        }
+    }
+
+    /// Builds an audit log record for a DDL or DML statement, if the audit log is enabled
+    fn audit_record(&self, query: &str, statement: &ast::Statement) -> Option<AuditRecord> {
+        let user = self.audit_user.clone()?;
+        let kind = match statement {
+            ast::Statement::CreateTable { .. }
+            | ast::Statement::DropTable(_)
+            | ast::Statement::AddColumn { .. }
+            | ast::Statement::CreateIndex { .. }
+            | ast::Statement::AlterColumn { .. }
+            | ast::Statement::Comment { .. }
+            | ast::Statement::SetSetting { .. }
+            | ast::Statement::CreateFunction { .. }
+            | ast::Statement::DropFunction(_)
+            | ast::Statement::CreateType { .. }
+            | ast::Statement::DropType(_)
+            | ast::Statement::CreatePolicy { .. }
+            | ast::Statement::DropPolicy { .. } => "DDL",
+            ast::Statement::Insert { .. }
+            | ast::Statement::Update { .. }
+            | ast::Statement::Delete { .. } => "DML",
+            _ => return None,
+        };
+        Some(AuditRecord {
+            // The timestamp is taken here rather than when applied, so all replicas agree on it.
+            timestamp: now(),
+            user,
+            kind: kind.into(),
+            statement: query.trim().to_string(),
+        })
+    }
+
+    /// Plans, optimizes, and executes a statement in a transaction with the given bind parameter
+    /// values and session role, tracing each stage.
+    fn run(
+        statement: ast::Statement,
+        params: &[Value],
+        role: Option<&str>,
+        txn: &mut E::Transaction,
+        schema: &mut SchemaCache,
+    ) -> Result<ResultSet> {
+        let plan = Self::plan(statement, params, role, txn, schema)?;
+        span!("execute"; plan.execute(txn))
+    }
+
+    /// Plans and optimizes a statement like run(). The plan is built using cached schemas, if the
+    /// cache holds a lease on the transaction's catalog version. The cluster settings are also
+    /// cached, for use by the session after the statement completes.
+    fn plan(
+        statement: ast::Statement,
+        params: &[Value],
+        role: Option<&str>,
+        txn: &mut E::Transaction,
+        schema: &mut SchemaCache,
+    ) -> Result<Plan> {
+        schema.acquire(txn.catalog_version()?);
+        let mut catalog = CachedCatalog::new(txn, schema);
+        catalog.settings()?;
+        let plan = span!("plan"; Plan::build_for_role(statement, &mut catalog, params, role))?;
+        span!("optimize"; plan.optimize(txn))
+    }
+
+    /// Runs a query in a read-only transaction like run(), using the engine's query cache if
+    /// enabled by the query_cache_entries cluster setting. The results of cacheable queries are
+    /// materialized, and served from the cache until the catalog or data version advances.
+    fn run_cached(
+        &mut self,
+        query: &str,
+        statement: ast::Statement,
+        params: &[Value],
+        txn: &mut E::Transaction,
+    ) -> Result<ResultSet> {
+        let plan = Self::plan(statement, params, self.role.as_deref(), txn, &mut self.schema)?;
+        let capacity = match self.schema.settings().and_then(|s| s.query_cache_entries()) {
+            Some(capacity) if query_cache::is_cacheable(&plan.0)? => capacity,
+            _ => return span!("execute"; plan.execute(txn)),
+        };
+        let cache = self.engine.query_cache();
+        let key =
+            QueryKey { query: query.to_string(), params: params.to_vec(), role: self.role.clone() };
+        let (catalog_version, data_version) = (txn.catalog_version()?, txn.data_version());
+        if let Some((columns, rows)) = cache.get(&key, catalog_version, data_version)? {
+            return Ok(ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) });
+        }
+        match span!("execute"; plan.execute(txn))? {
+            ResultSet::Query { columns, rows } => {
+                let rows = rows.collect::<Result<Vec<_>>>()?;
+                cache.insert(
+                    key,
+                    catalog_version,
+                    data_version,
+                    columns.clone(),
+                    rows.clone(),
+                    capacity,
+                )?;
+                Ok(ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) })
             }
+            result => Ok(result),
         }
     }
 
+    /// Begins a new session transaction in the given mode and priority
+    fn begin(&mut self, mode: Mode, priority: Priority) -> Result<&E::Transaction> {
+        self.txn = Some(self.engine.begin_with_priority(mode, priority)?);
+        Ok(self.txn.as_ref().unwrap())
+    }
+
+    /// Bulk loads rows into a table, e.g. for initial data loads. Rows are created in chunks of
+    /// at most INGEST_BATCH_SIZE rows and INGEST_BATCH_BYTES serialized bytes (or a single larger
+    /// row), each of which is a single write (i.e. Raft proposal), rather than one write per row
+    /// as with INSERT. Rows must have a value for each column, in order. Runs
+    /// in the session's transaction if any, otherwise in a new transaction which is only
+    /// committed if all rows are created. Returns the number of rows created.
+    pub fn ingest(&mut self, table: &str, rows: impl IntoIterator<Item = Row>) -> Result<u64> {
+        let ingest = |txn: &mut E::Transaction| -> Result<u64> {
+            let mut count = 0;
+            let mut batch = Vec::new();
+            let mut batch_bytes = 0;
+            for row in rows {
+                let bytes = bincode::serialized_size(&row)? as usize;
+                if !batch.is_empty()
+                    && (batch.len() >= INGEST_BATCH_SIZE
+                        || batch_bytes + bytes > INGEST_BATCH_BYTES)
+                {
+                    count += batch.len() as u64;
+                    txn.create_batch(table, std::mem::take(&mut batch))?;
+                    batch_bytes = 0;
+                }
+                batch_bytes += bytes;
+                batch.push(row);
+            }
+            if !batch.is_empty() {
+                count += batch.len() as u64;
+                txn.create_batch(table, batch)?;
+            }
+            Ok(count)
+        };
+        if self.txn.is_some() {
+            return self.with_txn(Mode::ReadWrite, ingest);
+        }
+        let mut txn = self.engine.begin(Mode::ReadWrite)?;
+        match ingest(&mut txn) {
+            Ok(count) => {
+                txn.commit()?;
+                Ok(count)
+            }
+            Err(error) => {
+                txn.rollback()?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns true if the session has an active transaction
+    pub fn has_txn(&self) -> bool {
+        self.txn.is_some()
+    }
+
     /// Runs a closure in the session's transaction, or a new transaction if none is active.
     pub fn with_txn<R, F>(&mut self, mode: Mode, f: F) -> Result<R>
     where
@@ -172,11 +833,92 @@ impl<E: Engine + 'static> Session<E> {
     }
 }
 
+/// The number of rows created per write when bulk loading rows with Session::ingest()
+const INGEST_BATCH_SIZE: usize = 1000;
+
+/// The maximum serialized size of the rows created per write when bulk loading rows with
+/// Session::ingest(), well below the maximum size of a Raft mutation
+const INGEST_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+/// The number of rows backfilled or verified per step of an online schema change
+const BACKFILL_BATCH_SIZE: usize = 100;
+
+/// The initial time to wait for older transactions to finish, or before retrying a conflicting
+/// step, during an online schema change. It doubles up to SCHEMA_CHANGE_MAX_WAIT while waiting.
+const SCHEMA_CHANGE_WAIT: Duration = Duration::from_millis(10);
+
+/// The maximum time to wait between steps of an online schema change
+const SCHEMA_CHANGE_MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// How long a schema change statement waits for the schema change to complete, before returning
+/// Error::Abort and leaving it to be completed in the background
+pub const SCHEMA_CHANGE_TIMEOUT: Duration = Duration::from_secs(300);
+
 /// The transaction mode
 pub type Mode = crate::storage::kv::mvcc::Mode;
 
+/// The transaction priority, which determines which transaction is aborted on write conflicts
+pub type Priority = crate::storage::kv::mvcc::Priority;
+
+/// A query row iterator which counts the rows returned, and records them in statement statistics
+/// when dropped
+struct StatsRows {
+    rows: super::types::Rows,
+    count: u64,
+    stats: Vec<StatementStats>,
+    fingerprint: String,
+    latency: Duration,
+}
+
+impl Iterator for StatsRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next();
+        if let Some(Ok(_)) = row {
+            self.count += 1;
+        }
+        row
+    }
+}
+
+impl Drop for StatsRows {
+    fn drop(&mut self) {
+        for stats in &self.stats {
+            // Errors can only be caused by a poisoned lock, and are ignored.
+            stats.record(&self.fingerprint, self.latency, self.count).ok();
+        }
+    }
+}
+
 /// A row scan iterator
 pub type Scan = Box<dyn DoubleEndedIterator<Item = Result<Row>> + Send>;
 
+/// Filters a row scan using an optional filter expression
+fn filter_scan(rows: Scan, filter: Option<Expression>) -> Scan {
+    Box::new(rows.filter_map(move |r| match r {
+        Ok(row) => match &filter {
+            Some(filter) => match filter.evaluate(Some(&row)) {
+                Ok(Value::Boolean(b)) if b => Some(Ok(row)),
+                Ok(Value::Boolean(_)) | Ok(Value::Null) => None,
+                Ok(v) => {
+                    Some(Err(Error::Value(format!("Filter returned {}, expected boolean", v))))
+                }
+                Err(err) => Some(Err(err)),
+            },
+            None => Some(Ok(row)),
+        },
+        err => Some(err),
+    }))
+}
+
+/// Returns the current time in milliseconds since the Unix epoch
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 /// An index scan iterator
 pub type IndexScan = Box<dyn DoubleEndedIterator<Item = Result<(Value, HashSet<Value>)>> + Send>;
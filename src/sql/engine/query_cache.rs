@@ -0,0 +1,232 @@
+//! Query result caching, for repeated read-only queries such as dashboards. The results of SELECT
+//! queries run outside of an explicit transaction are cached by query text, parameter values, and
+//! session role. A result is only valid at the catalog and data versions it was read at, i.e.
+//! until a schema change or read-write transaction commits, so the cache is cleared whenever either
+//! version advances. Queries are assumed to be deterministic, which holds for built-in and stored
+//! functions, and must hold for registered functions. Queries that read system tables are never
+//! cached, since these are not versioned.
+use super::super::plan::Node;
+use super::super::types::{Columns, Row, Value};
+use super::system;
+use crate::error::Result;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Results with more rows than this are not cached.
+pub const MAX_ROWS: usize = 1000;
+
+/// A query cache key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct QueryKey {
+    /// The query text.
+    pub query: String,
+    /// The query's parameter values.
+    pub params: Vec<Value>,
+    /// The session role, if any, which may be restricted by row-level security policies.
+    pub role: Option<String>,
+}
+
+/// A cached query result.
+struct Entry {
+    columns: Columns,
+    rows: Vec<Row>,
+    /// The cache clock when the entry was last used, for LRU eviction.
+    used: u64,
+}
+
+/// The cache state, for the current catalog and data versions.
+#[derive(Default)]
+struct State {
+    catalog_version: u64,
+    data_version: u64,
+    entries: HashMap<QueryKey, Entry>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl State {
+    /// Advances the cache to the given versions if they're newer than the current ones, clearing
+    /// all entries. Returns false if they're older, in which case the cache can't be used.
+    fn advance(&mut self, catalog_version: u64, data_version: u64) -> bool {
+        if (catalog_version, data_version) == (self.catalog_version, self.data_version) {
+            return true;
+        }
+        if catalog_version < self.catalog_version || data_version < self.data_version {
+            return false;
+        }
+        self.catalog_version = catalog_version;
+        self.data_version = data_version;
+        self.entries.clear();
+        true
+    }
+}
+
+/// A query result cache. It is cheap to clone, and clones share the underlying cache.
+#[derive(Clone, Default)]
+pub struct QueryCache {
+    state: Arc<Mutex<State>>,
+}
+
+impl QueryCache {
+    /// Creates a new, empty query cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached result of a query at the given catalog and data versions, if any.
+    pub fn get(
+        &self,
+        key: &QueryKey,
+        catalog_version: u64,
+        data_version: u64,
+    ) -> Result<Option<(Columns, Vec<Row>)>> {
+        let mut state = self.state.lock()?;
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = match state.advance(catalog_version, data_version) {
+            true => state.entries.get_mut(key),
+            false => None,
+        };
+        let result = entry.map(|entry| {
+            entry.used = clock;
+            (entry.columns.clone(), entry.rows.clone())
+        });
+        match result {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        Ok(result)
+    }
+
+    /// Caches the result of a query at the given catalog and data versions, evicting the least
+    /// recently used entry if the cache already holds the given number of entries. Results with
+    /// more than MAX_ROWS rows are not cached.
+    pub fn insert(
+        &self,
+        key: QueryKey,
+        catalog_version: u64,
+        data_version: u64,
+        columns: Columns,
+        rows: Vec<Row>,
+        capacity: usize,
+    ) -> Result<()> {
+        let mut state = self.state.lock()?;
+        if rows.len() > MAX_ROWS || !state.advance(catalog_version, data_version) {
+            return Ok(());
+        }
+        while state.entries.len() >= capacity && !state.entries.contains_key(&key) {
+            match state.entries.iter().min_by_key(|(_, e)| e.used).map(|(k, _)| k.clone()) {
+                Some(evict) => state.entries.remove(&evict),
+                None => return Ok(()),
+            };
+        }
+        state.clock += 1;
+        let used = state.clock;
+        state.entries.insert(key, Entry { columns, rows, used });
+        Ok(())
+    }
+
+    /// Returns the number of cached results.
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.state.lock()?.entries.len())
+    }
+
+    /// Returns true if no results are cached.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns the number of cache hits and misses.
+    pub fn hits_misses(&self) -> Result<(u64, u64)> {
+        let state = self.state.lock()?;
+        Ok((state.hits, state.misses))
+    }
+}
+
+/// Checks whether a plan can be cached, i.e. whether it only reads versioned tables.
+pub fn is_cacheable(node: &Node) -> Result<bool> {
+    let cacheable = Cell::new(true);
+    node.clone().transform(
+        &|n| {
+            match &n {
+                Node::IndexLookup { table, .. }
+                | Node::IndexRange { table, .. }
+                | Node::KeyLookup { table, .. }
+                | Node::PartitionScan { table, .. }
+                | Node::RowVersions { table, .. }
+                | Node::Scan { table, .. }
+                    if system::is_system_table(table) =>
+                {
+                    cacheable.set(false)
+                }
+                _ => {}
+            }
+            Ok(n)
+        },
+        &Ok,
+    )?;
+    Ok(cacheable.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::{Column, DataType};
+
+    fn key(query: &str) -> QueryKey {
+        QueryKey { query: query.into(), params: vec![], role: None }
+    }
+
+    fn rows(n: i64) -> (Columns, Vec<Row>) {
+        (vec![Column::new("n", DataType::Integer)], vec![vec![Value::Integer(n)]])
+    }
+
+    #[test]
+    fn versions() -> Result<()> {
+        let cache = QueryCache::new();
+        let (columns, rows1) = rows(1);
+        assert_eq!(cache.get(&key("a"), 1, 1)?, None);
+        cache.insert(key("a"), 1, 1, columns.clone(), rows1.clone(), 10)?;
+        assert_eq!(cache.get(&key("a"), 1, 1)?, Some((columns.clone(), rows1.clone())));
+
+        // Older versions can't use or populate the cache.
+        assert_eq!(cache.get(&key("a"), 1, 0)?, None);
+        cache.insert(key("b"), 0, 1, columns.clone(), rows1.clone(), 10)?;
+        assert_eq!(cache.len()?, 1);
+
+        // Newer versions clear it.
+        assert_eq!(cache.get(&key("a"), 1, 2)?, None);
+        assert_eq!(cache.len()?, 0);
+        cache.insert(key("a"), 2, 2, columns.clone(), rows1.clone(), 10)?;
+        assert_eq!(cache.get(&key("a"), 1, 2)?, None);
+        assert_eq!(cache.get(&key("a"), 2, 2)?, Some((columns, rows1)));
+        assert_eq!(cache.hits_misses()?, (2, 4));
+        Ok(())
+    }
+
+    #[test]
+    fn eviction() -> Result<()> {
+        let cache = QueryCache::new();
+        for (i, query) in ["a", "b", "c"].iter().enumerate() {
+            let (columns, rows) = rows(i as i64);
+            cache.insert(key(query), 0, 0, columns, rows, 2)?;
+            if *query == "b" {
+                cache.get(&key("a"), 0, 0)?;
+            }
+        }
+        assert_eq!(cache.len()?, 2);
+        assert_eq!(cache.get(&key("a"), 0, 0)?, Some(rows(0)));
+        assert_eq!(cache.get(&key("b"), 0, 0)?, None);
+        assert_eq!(cache.get(&key("c"), 0, 0)?, Some(rows(2)));
+
+        // Large results aren't cached.
+        let (columns, _) = rows(0);
+        let large = vec![vec![Value::Null]; MAX_ROWS + 1];
+        cache.insert(key("d"), 0, 0, columns, large, 2)?;
+        assert_eq!(cache.get(&key("d"), 0, 0)?, None);
+        Ok(())
+    }
+}
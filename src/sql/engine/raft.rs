@@ -1,26 +1,56 @@
-use super::super::schema::{Catalog, Table, Tables};
-use super::super::types::{Expression, Row, Value};
-use super::{Engine as _, IndexScan, Mode, Scan, Transaction as _};
+use super::super::schema::{
+    Catalog, Column, ColumnAlteration, Function, Policy, SchemaState, Table, Tables,
+};
+use super::super::settings::Settings;
+use super::super::types::{Enum, Expression, Row, Value};
+use super::system;
+use super::{
+    AuditRecord, Change, Detached, Engine as _, IndexScan, Mode, Priority, QueryCache, Scan,
+    StatementStats, Transaction as _,
+};
 use crate::error::{Error, Result};
 use crate::raft;
 use crate::storage::kv;
+use crate::trace::span;
 
 use serde::{Deserialize, Serialize};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+
+/// The maximum size of a serialized mutation proposed to Raft. Large values are only chunked by
+/// the MVCC storage layer, so each mutation is still replicated as a single Raft log entry.
+const MAX_MUTATION_SIZE: usize = 16 * 1024 * 1024;
 
 /// A Raft state machine mutation
 #[derive(Clone, Serialize, Deserialize)]
 enum Mutation {
-    /// Begins a transaction in the given mode
-    Begin(Mode),
+    /// Begins a transaction in the given mode and priority for the given owner, if any,
+    /// returning its ID, catalog version, and log index
+    Begin { mode: Mode, priority: Priority, owner: Option<Owner> },
     /// Commits the transaction with the given ID
     Commit(u64),
     /// Rolls back the transaction with the given ID
     Rollback(u64),
+    /// Rolls back transactions orphaned by a restart of the given owner's node, i.e. those owned
+    /// by a different process of the node, returning the number of transactions rolled back
+    RollbackOrphaned(Owner),
+    /// Detaches the transaction with the given ID from its session, recording the given marker.
+    /// Its owner is removed, since it no longer belongs to a session of the owner's node.
+    Detach { txn_id: u64, detached: Detached },
+    /// Attaches a detached transaction to a session of the given user and role, recording the
+    /// given owner for it, and returning the same as Query::Resume
+    Attach { txn_id: u64, user: Option<String>, role: Option<String>, owner: Option<Owner> },
+    /// Rolls back transactions detached before the given time, in milliseconds since the Unix
+    /// epoch, returning the number of transactions rolled back
+    RollbackDetached { before: u64 },
+    /// Runs a batch of MVCC garbage collection at the given time, in milliseconds since the Unix
+    /// epoch, returning the number of versions removed and whether the pass is complete
+    Gc { timestamp: u64 },
 
     /// Creates a new row
     Create { txn_id: u64, table: String, row: Row },
+    /// Creates a batch of new rows
+    CreateBatch { txn_id: u64, table: String, rows: Vec<Row> },
     /// Deletes a row
     Delete { txn_id: u64, table: String, id: Value },
     /// Updates a row
@@ -30,6 +60,33 @@ enum Mutation {
     CreateTable { txn_id: u64, schema: Table },
     /// Deletes a table
     DeleteTable { txn_id: u64, table: String },
+    /// Starts adding a column to a table
+    AddColumn { txn_id: u64, table: String, column: Column },
+    /// Starts creating an index on a column
+    CreateIndex { txn_id: u64, table: String, column: String },
+    /// Alters a column's nullability or default value
+    AlterColumn { txn_id: u64, table: String, column: String, alteration: ColumnAlteration },
+    /// Advances a table's schema change by one step
+    AdvanceSchemaChange { txn_id: u64, table: String, timestamp: u64 },
+    /// Sets or removes a table or column comment
+    SetComment { txn_id: u64, table: String, column: Option<String>, comment: Option<String> },
+    /// Sets or resets a cluster setting
+    SetSetting { txn_id: u64, name: String, value: Option<Value> },
+    /// Creates a stored function
+    CreateFunction { txn_id: u64, function: Function },
+    /// Deletes a stored function
+    DeleteFunction { txn_id: u64, function: String },
+    /// Creates an enum type
+    CreateEnum { txn_id: u64, r#enum: Enum },
+    /// Deletes an enum type
+    DeleteEnum { txn_id: u64, r#enum: String },
+    /// Adds a row-level security policy to a table
+    CreatePolicy { txn_id: u64, table: String, policy: Policy },
+    /// Removes a row-level security policy from a table
+    DeletePolicy { txn_id: u64, table: String, policy: String },
+
+    /// Appends an audit log record
+    Audit { txn_id: u64, record: AuditRecord },
 }
 
 /// A Raft state machine query
@@ -39,6 +96,8 @@ enum Query {
     Status,
     /// Resumes the active transaction with the given ID
     Resume(u64),
+    /// Fetches the catalog version seen by a transaction
+    CatalogVersion { txn_id: u64 },
 
     /// Reads a row
     Read { txn_id: u64, table: String, id: Value },
@@ -46,15 +105,38 @@ enum Query {
     ReadIndex { txn_id: u64, table: String, column: String, value: Value },
     /// Scans a table's rows
     Scan { txn_id: u64, table: String, filter: Option<Expression> },
+    /// Scans a table partition's rows
+    ScanPartition { txn_id: u64, table: String, partition: String, filter: Option<Expression> },
     /// Scans an index
     ScanIndex { txn_id: u64, table: String, column: String },
+    /// Scans a range of an index
+    ScanIndexRange { txn_id: u64, table: String, column: String, lower: Value, upper: Value },
+    /// Fetches committed changes to a table's rows
+    Changes { txn_id: u64, table: String, from: u64 },
+    /// Fetches all committed versions of a row
+    History { txn_id: u64, table: String, id: Value },
+    /// Fetches the versions that created and last updated a row
+    RowVersions { txn_id: u64, table: String, id: Value },
 
     /// Scans the tables
     ScanTables { txn_id: u64 },
     /// Reads a table
     ReadTable { txn_id: u64, table: String },
+    /// Fetches a table's approximate row count
+    RowCount { txn_id: u64, table: String },
+    /// Reads the cluster settings
+    Settings { txn_id: u64 },
+    /// Reads a stored function
+    ReadFunction { txn_id: u64, function: String },
+    /// Scans the stored functions
+    ScanFunctions { txn_id: u64 },
+    /// Reads an enum type
+    ReadEnum { txn_id: u64, r#enum: String },
 }
 
+/// The state of a resumed transaction: its ID, mode, priority, catalog version, and data version.
+type Resumed = (u64, Mode, Priority, u64, u64);
+
 /// Status for the Raft SQL engine.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
@@ -62,16 +144,66 @@ pub struct Status {
     pub mvcc: kv::mvcc::Status,
 }
 
+/// The owner of a transaction, i.e. the node process whose session began it. A node's sessions
+/// don't survive a restart, so any remaining transactions owned by a previous process of the
+/// node are orphaned and can be rolled back.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Owner {
+    /// The node ID
+    pub node: String,
+    /// The node process epoch, incremented on every start of the node
+    pub epoch: u64,
+}
+
 /// An SQL engine that wraps a Raft cluster.
 #[derive(Clone)]
 pub struct Raft {
     client: raft::Client,
+    /// Statement statistics for the engine's sessions on this node.
+    stats: StatementStats,
+    /// The query result cache for the engine's sessions on this node.
+    cache: QueryCache,
+    /// The owner to record for transactions begun by the engine, if any.
+    owner: Option<Owner>,
 }
 
 impl Raft {
     /// Creates a new Raft SQL engine.
     pub fn new(client: raft::Client) -> Self {
-        Self { client }
+        Self { client, stats: StatementStats::new(), cache: QueryCache::new(), owner: None }
+    }
+
+    /// Records the given node's current process, with the given epoch, as the owner of
+    /// transactions begun by the engine, such that they can be rolled back by rollback_orphaned()
+    /// if the node restarts. The epoch must be persisted and incremented on every start, see
+    /// raft::Log::next_epoch().
+    pub fn with_owner(mut self, node: &str, epoch: u64) -> Self {
+        self.owner = Some(Owner { node: node.to_string(), epoch });
+        self
+    }
+
+    /// Rolls back transactions orphaned by a previous process of the engine owner's node, e.g.
+    /// after a crash, returning the number of transactions rolled back.
+    pub fn rollback_orphaned(&self) -> Result<u64> {
+        let owner = self
+            .owner
+            .clone()
+            .ok_or_else(|| Error::Internal("Engine has no transaction owner".into()))?;
+        Raft::deserialize(&futures::executor::block_on(
+            self.client.mutate(Raft::serialize(&Mutation::RollbackOrphaned(owner))?),
+        )?)
+    }
+
+    /// Runs a batch of MVCC garbage collection as of now, returning the number of versions removed
+    /// and whether the garbage collection pass is complete. See KV::gc() for details.
+    pub fn gc(&self) -> Result<(u64, bool)> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Raft::deserialize(&futures::executor::block_on(
+            self.client.mutate(Raft::serialize(&Mutation::Gc { timestamp })?),
+        )?)
     }
 
     /// Creates an underlying state machine for a Raft engine.
@@ -94,6 +226,19 @@ impl Raft {
         Ok(bincode::serialize(value)?)
     }
 
+    /// Serializes a mutation for proposal to Raft, rejecting it if it exceeds MAX_MUTATION_SIZE.
+    fn serialize_mutation(mutation: &Mutation) -> Result<Vec<u8>> {
+        let command = Self::serialize(mutation)?;
+        if command.len() > MAX_MUTATION_SIZE {
+            return Err(Error::Value(format!(
+                "Mutation too large ({} bytes, maximum is {})",
+                command.len(),
+                MAX_MUTATION_SIZE
+            )));
+        }
+        Ok(command)
+    }
+
     /// Deserializes a command for the Raft SQL state machine.
     fn deserialize<'a, V: Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
         Ok(bincode::deserialize(bytes)?)
@@ -103,12 +248,51 @@ impl Raft {
 impl super::Engine for Raft {
     type Transaction = Transaction;
 
-    fn begin(&self, mode: Mode) -> Result<Self::Transaction> {
-        Transaction::begin(self.client.clone(), mode)
+    fn begin_with_priority(&self, mode: Mode, priority: Priority) -> Result<Self::Transaction> {
+        Transaction::begin(
+            self.client.clone(),
+            self.stats.clone(),
+            mode,
+            priority,
+            self.owner.clone(),
+        )
     }
 
     fn resume(&self, id: u64) -> Result<Self::Transaction> {
-        Transaction::resume(self.client.clone(), id)
+        Transaction::resume(self.client.clone(), self.stats.clone(), id)
+    }
+
+    fn detach(&self, id: u64, detached: Detached) -> Result<()> {
+        Raft::deserialize(&futures::executor::block_on(
+            self.client.mutate(Raft::serialize(&Mutation::Detach { txn_id: id, detached })?),
+        )?)
+    }
+
+    fn attach(&self, id: u64, user: Option<&str>, role: Option<&str>) -> Result<Self::Transaction> {
+        let mutation = Mutation::Attach {
+            txn_id: id,
+            user: user.map(|u| u.to_string()),
+            role: role.map(|r| r.to_string()),
+            owner: self.owner.clone(),
+        };
+        let resumed = Raft::deserialize(&futures::executor::block_on(
+            self.client.mutate(Raft::serialize(&mutation)?),
+        )?)?;
+        Ok(Transaction::resumed(self.client.clone(), self.stats.clone(), resumed))
+    }
+
+    fn rollback_detached(&self, before: u64) -> Result<u64> {
+        Raft::deserialize(&futures::executor::block_on(
+            self.client.mutate(Raft::serialize(&Mutation::RollbackDetached { before })?),
+        )?)
+    }
+
+    fn statement_stats(&self) -> &StatementStats {
+        &self.stats
+    }
+
+    fn query_cache(&self) -> &QueryCache {
+        &self.cache
     }
 }
 
@@ -121,33 +305,108 @@ pub struct Transaction {
     id: u64,
     /// The transaction mode
     mode: Mode,
+    /// The transaction priority
+    priority: Priority,
+    /// The engine's statement statistics
+    stats: StatementStats,
+    /// The catalog version seen by the transaction when it began
+    catalog_version: u64,
+    /// The data version seen by the transaction when it began
+    data_version: u64,
+    /// Whether the transaction has changed the schema, such that its catalog version must be
+    /// fetched from the state machine
+    schema_changed: bool,
+    /// For read-only transactions, the log index at which the transaction began. Queries are
+    /// then served by the local state machine once it has applied this index, even on followers,
+    /// since the transaction's MVCC snapshot hides any later changes. Read-write transactions
+    /// query the leader, since they must see their own writes.
+    local_index: Option<u64>,
 }
 
 impl Transaction {
-    /// Starts a transaction in the given mode
-    fn begin(client: raft::Client, mode: Mode) -> Result<Self> {
-        let id = Raft::deserialize(&futures::executor::block_on(
-            client.mutate(Raft::serialize(&Mutation::Begin(mode))?),
-        )?)?;
-        Ok(Self { client, id, mode })
+    /// Starts a transaction in the given mode and priority
+    fn begin(
+        client: raft::Client,
+        stats: StatementStats,
+        mode: Mode,
+        priority: Priority,
+        owner: Option<Owner>,
+    ) -> Result<Self> {
+        let command = Raft::serialize(&Mutation::Begin { mode, priority, owner })?;
+        let (id, catalog_version, data_version, index) = span!("raft_propose", (bytes = command.len()); {
+            Raft::deserialize(&futures::executor::block_on(client.mutate(command))?)
+        })?;
+        let local_index = if mode.mutable() { None } else { Some(index) };
+        Ok(Self {
+            client,
+            id,
+            mode,
+            priority,
+            stats,
+            catalog_version,
+            data_version,
+            schema_changed: false,
+            local_index,
+        })
     }
 
     /// Resumes an active transaction
-    fn resume(client: raft::Client, id: u64) -> Result<Self> {
-        let (id, mode) = Raft::deserialize(&futures::executor::block_on(
+    fn resume(client: raft::Client, stats: StatementStats, id: u64) -> Result<Self> {
+        let resumed = Raft::deserialize(&futures::executor::block_on(
             client.query(Raft::serialize(&Query::Resume(id))?),
         )?)?;
-        Ok(Self { client, id, mode })
+        Ok(Self::resumed(client, stats, resumed))
+    }
+
+    /// Creates a resumed transaction from its state, as returned by Query::Resume
+    fn resumed(client: raft::Client, stats: StatementStats, resumed: Resumed) -> Self {
+        let (id, mode, priority, catalog_version, data_version) = resumed;
+        Self {
+            client,
+            id,
+            mode,
+            priority,
+            stats,
+            catalog_version,
+            data_version,
+            schema_changed: false,
+            local_index: None,
+        }
     }
 
-    /// Executes a mutation
+    /// Executes a mutation, by proposing it to the Raft cluster
     fn mutate(&self, mutation: Mutation) -> Result<Vec<u8>> {
-        futures::executor::block_on(self.client.mutate(Raft::serialize(&mutation)?))
+        let command = Raft::serialize_mutation(&mutation)?;
+        span!("raft_propose", (bytes = command.len()); {
+            futures::executor::block_on(self.client.mutate(command))
+        })
     }
 
-    /// Executes a query
+    /// Executes a query, on the local state machine if possible
     fn query(&self, query: Query) -> Result<Vec<u8>> {
-        futures::executor::block_on(self.client.query(Raft::serialize(&query)?))
+        let command = Raft::serialize(&query)?;
+        span!("raft_query", (bytes = command.len()); {
+            match self.local_index {
+                Some(index) => futures::executor::block_on(self.client.query_local(command, index)),
+                None => futures::executor::block_on(self.client.query(command)),
+            }
+        })
+    }
+
+    /// Generates the rows of the system.raft table. This is Raft node state rather than state
+    /// machine state, so it's generated from the Raft status instead of the underlying KV engine.
+    fn scan_raft(&self) -> Result<Vec<Row>> {
+        Ok(system::raft_rows(&futures::executor::block_on(self.client.status())?))
+    }
+
+    /// Generates the rows of a system table that is local to this node rather than part of the
+    /// state machine, if the given table is one.
+    fn scan_local(&self, table: &str) -> Result<Option<Vec<Row>>> {
+        match table {
+            system::RAFT => self.scan_raft().map(Some),
+            system::STATEMENT_STATS => system::statement_stats_rows(&self.stats).map(Some),
+            _ => Ok(None),
+        }
     }
 }
 
@@ -160,6 +419,14 @@ impl super::Transaction for Transaction {
         self.mode
     }
 
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn data_version(&self) -> u64 {
+        self.data_version
+    }
+
     fn commit(self) -> Result<()> {
         Raft::deserialize(&self.mutate(Mutation::Commit(self.id))?)
     }
@@ -176,6 +443,14 @@ impl super::Transaction for Transaction {
         })?)
     }
 
+    fn create_batch(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::CreateBatch {
+            txn_id: self.id,
+            table: table.to_string(),
+            rows,
+        })?)
+    }
+
     fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
         Raft::deserialize(&self.mutate(Mutation::Delete {
             txn_id: self.id,
@@ -185,6 +460,9 @@ impl super::Transaction for Transaction {
     }
 
     fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
+        if let Some(rows) = self.scan_local(table)? {
+            return Ok(rows.into_iter().find(|row| &row[0] == id));
+        }
         Raft::deserialize(&self.query(Query::Read {
             txn_id: self.id,
             table: table.to_string(),
@@ -202,6 +480,9 @@ impl super::Transaction for Transaction {
     }
 
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Scan> {
+        if let Some(rows) = self.scan_local(table)? {
+            return Ok(super::filter_scan(Box::new(rows.into_iter().map(Ok)), filter));
+        }
         Ok(Box::new(
             Raft::deserialize::<Vec<_>>(&self.query(Query::Scan {
                 txn_id: self.id,
@@ -213,6 +494,24 @@ impl super::Transaction for Transaction {
         ))
     }
 
+    fn scan_partition(
+        &self,
+        table: &str,
+        partition: &str,
+        filter: Option<Expression>,
+    ) -> Result<Scan> {
+        Ok(Box::new(
+            Raft::deserialize::<Vec<_>>(&self.query(Query::ScanPartition {
+                txn_id: self.id,
+                table: table.to_string(),
+                partition: partition.to_string(),
+                filter,
+            })?)?
+            .into_iter()
+            .map(Ok),
+        ))
+    }
+
     fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan> {
         Ok(Box::new(
             Raft::deserialize::<Vec<_>>(&self.query(Query::ScanIndex {
@@ -225,6 +524,26 @@ impl super::Transaction for Transaction {
         ))
     }
 
+    fn scan_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        lower: &Value,
+        upper: &Value,
+    ) -> Result<IndexScan> {
+        Ok(Box::new(
+            Raft::deserialize::<Vec<_>>(&self.query(Query::ScanIndexRange {
+                txn_id: self.id,
+                table: table.to_string(),
+                column: column.to_string(),
+                lower: lower.clone(),
+                upper: upper.clone(),
+            })?)?
+            .into_iter()
+            .map(Ok),
+        ))
+    }
+
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
         Raft::deserialize(&self.mutate(Mutation::Update {
             txn_id: self.id,
@@ -233,14 +552,57 @@ impl super::Transaction for Transaction {
             row,
         })?)
     }
+
+    fn changes(&self, table: &str, from: u64) -> Result<(Vec<Change>, u64)> {
+        Raft::deserialize(&self.query(Query::Changes {
+            txn_id: self.id,
+            table: table.to_string(),
+            from,
+        })?)
+    }
+
+    fn history(&self, table: &str, id: &Value) -> Result<Vec<Change>> {
+        Raft::deserialize(&self.query(Query::History {
+            txn_id: self.id,
+            table: table.to_string(),
+            id: id.clone(),
+        })?)
+    }
+
+    fn row_versions(&self, table: &str, id: &Value) -> Result<Option<(u64, u64)>> {
+        Raft::deserialize(&self.query(Query::RowVersions {
+            txn_id: self.id,
+            table: table.to_string(),
+            id: id.clone(),
+        })?)
+    }
+
+    fn audit(&mut self, record: AuditRecord) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::Audit { txn_id: self.id, record })?)
+    }
+
+    fn advance_schema_change(
+        &mut self,
+        table: &str,
+        timestamp: u64,
+    ) -> Result<Option<SchemaState>> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::AdvanceSchemaChange {
+            txn_id: self.id,
+            table: table.to_string(),
+            timestamp,
+        })?)
+    }
 }
 
 impl Catalog for Transaction {
     fn create_table(&mut self, table: Table) -> Result<()> {
+        self.schema_changed = true;
         Raft::deserialize(&self.mutate(Mutation::CreateTable { txn_id: self.id, schema: table })?)
     }
 
     fn delete_table(&mut self, table: &str) -> Result<()> {
+        self.schema_changed = true;
         Raft::deserialize(
             &self.mutate(Mutation::DeleteTable { txn_id: self.id, table: table.to_string() })?,
         )
@@ -258,6 +620,142 @@ impl Catalog for Transaction {
                 .into_iter(),
         ))
     }
+
+    fn catalog_version(&self) -> Result<u64> {
+        // The version seen at begin can only change by the transaction's own schema changes.
+        if !self.schema_changed {
+            return Ok(self.catalog_version);
+        }
+        Raft::deserialize(&self.query(Query::CatalogVersion { txn_id: self.id })?)
+    }
+
+    fn row_count(&self, table: &str) -> Result<u64> {
+        Raft::deserialize(
+            &self.query(Query::RowCount { txn_id: self.id, table: table.to_string() })?,
+        )
+    }
+
+    fn add_column(&mut self, table: &str, column: Column) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::AddColumn {
+            txn_id: self.id,
+            table: table.to_string(),
+            column,
+        })?)
+    }
+
+    fn create_index(&mut self, table: &str, column: &str) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::CreateIndex {
+            txn_id: self.id,
+            table: table.to_string(),
+            column: column.to_string(),
+        })?)
+    }
+
+    fn alter_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        alteration: ColumnAlteration,
+    ) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::AlterColumn {
+            txn_id: self.id,
+            table: table.to_string(),
+            column: column.to_string(),
+            alteration,
+        })?)
+    }
+
+    fn set_comment(
+        &mut self,
+        table: &str,
+        column: Option<&str>,
+        comment: Option<String>,
+    ) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::SetComment {
+            txn_id: self.id,
+            table: table.to_string(),
+            column: column.map(|c| c.to_string()),
+            comment,
+        })?)
+    }
+
+    fn settings(&self) -> Result<Settings> {
+        Raft::deserialize(&self.query(Query::Settings { txn_id: self.id })?)
+    }
+
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::SetSetting {
+            txn_id: self.id,
+            name: name.to_string(),
+            value,
+        })?)
+    }
+
+    fn create_function(&mut self, function: Function) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::CreateFunction { txn_id: self.id, function })?)
+    }
+
+    fn delete_function(&mut self, function: &str) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(
+            &self.mutate(Mutation::DeleteFunction {
+                txn_id: self.id,
+                function: function.to_string(),
+            })?,
+        )
+    }
+
+    fn read_function(&self, function: &str) -> Result<Option<Function>> {
+        Raft::deserialize(
+            &self.query(Query::ReadFunction { txn_id: self.id, function: function.to_string() })?,
+        )
+    }
+
+    fn scan_functions(&self) -> Result<Vec<Function>> {
+        Raft::deserialize(&self.query(Query::ScanFunctions { txn_id: self.id })?)
+    }
+
+    fn create_enum(&mut self, r#enum: Enum) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::CreateEnum { txn_id: self.id, r#enum })?)
+    }
+
+    fn delete_enum(&mut self, r#enum: &str) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(
+            &self.mutate(Mutation::DeleteEnum { txn_id: self.id, r#enum: r#enum.to_string() })?,
+        )
+    }
+
+    fn create_policy(&mut self, table: &str, policy: Policy) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::CreatePolicy {
+            txn_id: self.id,
+            table: table.to_string(),
+            policy,
+        })?)
+    }
+
+    fn delete_policy(&mut self, table: &str, policy: &str) -> Result<()> {
+        self.schema_changed = true;
+        Raft::deserialize(&self.mutate(Mutation::DeletePolicy {
+            txn_id: self.id,
+            table: table.to_string(),
+            policy: policy.to_string(),
+        })?)
+    }
+
+    fn read_enum(&self, r#enum: &str) -> Result<Option<Enum>> {
+        Raft::deserialize(
+            &self.query(Query::ReadEnum { txn_id: self.id, r#enum: r#enum.to_string() })?,
+        )
+    }
 }
 
 /// The Raft state machine for the Raft-based SQL engine, using a KV SQL engine
@@ -266,6 +764,8 @@ pub struct State {
     engine: super::KV,
     /// The last applied index
     applied_index: u64,
+    /// The owners of active transactions, by transaction ID, persisted as metadata
+    owners: BTreeMap<u64, Owner>,
 }
 
 impl State {
@@ -276,19 +776,117 @@ impl State {
             .get_metadata(b"applied_index")?
             .map(|b| Raft::deserialize(&b))
             .unwrap_or(Ok(0))?;
-        Ok(State { engine, applied_index })
+        let owners = Self::load_owners(&engine)?;
+        Ok(State { engine, applied_index, owners })
+    }
+
+    /// Loads the transaction owners from metadata
+    fn load_owners(engine: &super::KV) -> Result<BTreeMap<u64, Owner>> {
+        engine
+            .get_metadata(b"txn_owners")?
+            .map(|b| Raft::deserialize(&b))
+            .unwrap_or_else(|| Ok(BTreeMap::new()))
+    }
+
+    /// Removes a finished transaction's owner, if any
+    fn remove_owner(&mut self, txn_id: u64) -> Result<()> {
+        if self.owners.remove(&txn_id).is_some() {
+            self.engine.set_metadata(b"txn_owners", Raft::serialize(&self.owners)?)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the state of a resumed transaction, for Query::Resume and Mutation::Attach
+    fn resumed(txn: &<super::KV as super::Engine>::Transaction) -> Result<Resumed> {
+        Ok((txn.id(), txn.mode(), txn.priority(), txn.catalog_version()?, txn.data_version()))
     }
 
     /// Applies a state machine mutation
-    fn apply(&mut self, mutation: Mutation) -> Result<Vec<u8>> {
+    fn apply(&mut self, index: u64, mutation: Mutation) -> Result<Vec<u8>> {
         match mutation {
-            Mutation::Begin(mode) => Raft::serialize(&self.engine.begin(mode)?.id()),
-            Mutation::Commit(txn_id) => Raft::serialize(&self.engine.resume(txn_id)?.commit()?),
-            Mutation::Rollback(txn_id) => Raft::serialize(&self.engine.resume(txn_id)?.rollback()?),
+            Mutation::Begin { mode, priority, owner } => {
+                let txn = self.engine.begin_with_priority(mode, priority)?;
+                if let Some(owner) = owner {
+                    self.owners.insert(txn.id(), owner);
+                    self.engine.set_metadata(b"txn_owners", Raft::serialize(&self.owners)?)?;
+                }
+                Raft::serialize(&(txn.id(), txn.catalog_version()?, txn.data_version(), index))
+            }
+            // The transaction is finished even if the commit fails, e.g. because it was aborted
+            // by a higher-priority transaction, so its owner is removed either way.
+            Mutation::Commit(txn_id) => {
+                let result = self.engine.resume(txn_id)?.commit();
+                self.remove_owner(txn_id)?;
+                Raft::serialize(&result?)
+            }
+            Mutation::Rollback(txn_id) => {
+                self.engine.resume(txn_id)?.rollback()?;
+                self.remove_owner(txn_id)?;
+                Raft::serialize(&())
+            }
+            // Only transactions of earlier processes are orphaned, since a stale or retried
+            // mutation from an earlier process may be applied after the node has restarted.
+            // Detached transactions outlive their session, and are left to RollbackDetached.
+            Mutation::RollbackOrphaned(owner) => {
+                let detached = self.engine.load_detached()?;
+                let orphaned: Vec<u64> = self
+                    .owners
+                    .iter()
+                    .filter(|(_, o)| o.node == owner.node && o.epoch < owner.epoch)
+                    .filter(|(id, _)| !detached.contains_key(id))
+                    .map(|(id, _)| *id)
+                    .collect();
+                // Transactions that are no longer active only leave a stale owner behind. A failed
+                // rollback doesn't stop the others, and is returned once they've been rolled back,
+                // such that the caller retries it.
+                let mut count = 0;
+                let mut error = None;
+                for txn_id in orphaned {
+                    match self.engine.resume(txn_id) {
+                        Ok(txn) => match txn.rollback() {
+                            Ok(()) => count += 1,
+                            Err(err) => {
+                                error.get_or_insert(err);
+                                continue;
+                            }
+                        },
+                        Err(Error::Value(_)) => {}
+                        Err(err) => {
+                            error.get_or_insert(err);
+                            continue;
+                        }
+                    }
+                    self.remove_owner(txn_id)?;
+                }
+                match error {
+                    Some(err) => Err(err),
+                    None => Raft::serialize(&(count as u64)),
+                }
+            }
+            Mutation::Detach { txn_id, detached } => {
+                self.engine.detach(txn_id, detached)?;
+                self.remove_owner(txn_id)?;
+                Raft::serialize(&())
+            }
+            Mutation::Attach { txn_id, user, role, owner } => {
+                let txn = self.engine.attach(txn_id, user.as_deref(), role.as_deref())?;
+                if let Some(owner) = owner {
+                    self.owners.insert(txn_id, owner);
+                    self.engine.set_metadata(b"txn_owners", Raft::serialize(&self.owners)?)?;
+                }
+                Raft::serialize(&Self::resumed(&txn)?)
+            }
+            Mutation::RollbackDetached { before } => {
+                Raft::serialize(&self.engine.rollback_detached(before)?)
+            }
+            Mutation::Gc { timestamp } => Raft::serialize(&self.engine.gc(timestamp)?),
 
             Mutation::Create { txn_id, table, row } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.create(&table, row)?)
             }
+            Mutation::CreateBatch { txn_id, table, rows } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_batch(&table, rows)?)
+            }
             Mutation::Delete { txn_id, table, id } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.delete(&table, &id)?)
             }
@@ -302,6 +900,46 @@ impl State {
             Mutation::DeleteTable { txn_id, table } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.delete_table(&table)?)
             }
+            Mutation::AddColumn { txn_id, table, column } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.add_column(&table, column)?)
+            }
+            Mutation::CreateIndex { txn_id, table, column } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_index(&table, &column)?)
+            }
+            Mutation::AlterColumn { txn_id, table, column, alteration } => Raft::serialize(
+                &self.engine.resume(txn_id)?.alter_column(&table, &column, alteration)?,
+            ),
+            Mutation::AdvanceSchemaChange { txn_id, table, timestamp } => Raft::serialize(
+                &self.engine.resume(txn_id)?.advance_schema_change(&table, timestamp)?,
+            ),
+            Mutation::SetComment { txn_id, table, column, comment } => Raft::serialize(
+                &self.engine.resume(txn_id)?.set_comment(&table, column.as_deref(), comment)?,
+            ),
+            Mutation::SetSetting { txn_id, name, value } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.set_setting(&name, value)?)
+            }
+            Mutation::CreateFunction { txn_id, function } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_function(function)?)
+            }
+            Mutation::DeleteFunction { txn_id, function } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.delete_function(&function)?)
+            }
+            Mutation::CreateEnum { txn_id, r#enum } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_enum(r#enum)?)
+            }
+            Mutation::DeleteEnum { txn_id, r#enum } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.delete_enum(&r#enum)?)
+            }
+            Mutation::CreatePolicy { txn_id, table, policy } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_policy(&table, policy)?)
+            }
+            Mutation::DeletePolicy { txn_id, table, policy } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.delete_policy(&table, &policy)?)
+            }
+
+            Mutation::Audit { txn_id, record } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.audit(record)?)
+            }
         }
     }
 }
@@ -314,7 +952,7 @@ impl raft::State for State {
     fn mutate(&mut self, index: u64, command: Vec<u8>) -> Result<Vec<u8>> {
         // We don't check that index == applied_index + 1, since the Raft log commits no-op
         // entries during leader election which we need to ignore.
-        match self.apply(Raft::deserialize(&command)?) {
+        match self.apply(index, Raft::deserialize(&command)?) {
             error @ Err(Error::Internal(_)) => error,
             result => {
                 self.engine.set_metadata(b"applied_index", Raft::serialize(&(index))?)?;
@@ -324,11 +962,29 @@ impl raft::State for State {
         }
     }
 
+    fn flush(&mut self) -> Result<()> {
+        self.engine.kv.flush()
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let mut snapshot = Vec::new();
+        self.engine.kv.dump(&mut snapshot)?;
+        Ok(snapshot)
+    }
+
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        self.engine.kv.load(&snapshot[..])?;
+        self.engine.set_metadata(b"applied_index", Raft::serialize(&(index))?)?;
+        self.applied_index = index;
+        self.owners = Self::load_owners(&self.engine)?;
+        Ok(())
+    }
+
     fn query(&self, command: Vec<u8>) -> Result<Vec<u8>> {
         match Raft::deserialize(&command)? {
-            Query::Resume(id) => {
-                let txn = self.engine.resume(id)?;
-                Raft::serialize(&(txn.id(), txn.mode()))
+            Query::Resume(id) => Raft::serialize(&Self::resumed(&self.engine.resume(id)?)?),
+            Query::CatalogVersion { txn_id } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.catalog_version()?)
             }
 
             Query::Read { txn_id, table, id } => {
@@ -341,6 +997,13 @@ impl raft::State for State {
             Query::Scan { txn_id, table, filter } => Raft::serialize(
                 &self.engine.resume(txn_id)?.scan(&table, filter)?.collect::<Result<Vec<_>>>()?,
             ),
+            Query::ScanPartition { txn_id, table, partition, filter } => Raft::serialize(
+                &self
+                    .engine
+                    .resume(txn_id)?
+                    .scan_partition(&table, &partition, filter)?
+                    .collect::<Result<Vec<_>>>()?,
+            ),
             Query::ScanIndex { txn_id, table, column } => Raft::serialize(
                 &self
                     .engine
@@ -348,14 +1011,284 @@ impl raft::State for State {
                     .scan_index(&table, &column)?
                     .collect::<Result<Vec<_>>>()?,
             ),
+            Query::ScanIndexRange { txn_id, table, column, lower, upper } => Raft::serialize(
+                &self
+                    .engine
+                    .resume(txn_id)?
+                    .scan_index_range(&table, &column, &lower, &upper)?
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            Query::Changes { txn_id, table, from } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.changes(&table, from)?)
+            }
+            Query::History { txn_id, table, id } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.history(&table, &id)?)
+            }
+            Query::RowVersions { txn_id, table, id } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.row_versions(&table, &id)?)
+            }
             Query::Status => Raft::serialize(&self.engine.kv.status()?),
 
             Query::ReadTable { txn_id, table } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.read_table(&table)?)
             }
+            Query::RowCount { txn_id, table } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.row_count(&table)?)
+            }
+            Query::Settings { txn_id } => Raft::serialize(&self.engine.resume(txn_id)?.settings()?),
+            Query::ReadFunction { txn_id, function } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.read_function(&function)?)
+            }
+            Query::ScanFunctions { txn_id } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.scan_functions()?)
+            }
+            Query::ReadEnum { txn_id, r#enum } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.read_enum(&r#enum)?)
+            }
             Query::ScanTables { txn_id } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.scan_tables()?.collect::<Vec<_>>())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::{Memory, MVCC};
+
+    /// Returns a Raft client which applies requests directly to the given state machine, and
+    /// checks that mutations don't exceed MAX_MUTATION_SIZE.
+    fn local_client(mut state: State) -> raft::Client {
+        let (request_tx, mut request_rx) = tokio::sync::mpsc::unbounded_channel::<(
+            raft::Request,
+            tokio::sync::oneshot::Sender<Result<raft::Response>>,
+        )>();
+        std::thread::spawn(move || {
+            let mut index = 0;
+            while let Some((request, response_tx)) = futures::executor::block_on(request_rx.recv())
+            {
+                let response = match request {
+                    raft::Request::Mutate(command) => {
+                        assert!(command.len() <= MAX_MUTATION_SIZE);
+                        index += 1;
+                        raft::State::mutate(&mut state, index, command)
+                    }
+                    raft::Request::Query(command) | raft::Request::QueryLocal { command, .. } => {
+                        raft::State::query(&state, command)
+                    }
+                    request => Err(Error::Internal(format!("Unexpected request {:?}", request))),
+                };
+                response_tx.send(response.map(raft::Response::State)).ok();
+            }
+        });
+        raft::Client::new(request_tx)
+    }
+
+    // Ingested rows are split into batches that fit in a Raft mutation, even when together they
+    // exceed MAX_MUTATION_SIZE.
+    #[test]
+    fn ingest_large() -> Result<()> {
+        let engine = Raft::new(local_client(State::new(MVCC::new(Box::new(Memory::new())))?));
+        let mut session = engine.session()?;
+        let columns: Vec<String> = (0..20).map(|i| format!(", c{} STRING", i)).collect();
+        session
+            .execute(&format!("CREATE TABLE test (id INTEGER PRIMARY KEY{})", columns.concat()))?;
+
+        // 1000 rows of 20 KB each, which is a single batch by row count.
+        let value = Value::String("x".repeat(1000));
+        let rows = (0..1000).map(|id| {
+            std::iter::once(Value::Integer(id))
+                .chain(std::iter::repeat_n(value.clone(), 20))
+                .collect()
+        });
+        assert_eq!(session.ingest("test", rows)?, 1000);
+        match session.execute("SELECT COUNT(*) FROM test")? {
+            super::super::super::execution::ResultSet::Query { mut rows, .. } => {
+                assert_eq!(rows.next().transpose()?, Some(vec![Value::Integer(1000)]))
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_mutation() -> Result<()> {
+        let mutation = |size: usize| Mutation::Create {
+            txn_id: 1,
+            table: "t".into(),
+            row: vec![Value::String("x".repeat(size))],
+        };
+        assert!(Raft::serialize_mutation(&mutation(1024))?.len() < MAX_MUTATION_SIZE);
+        assert!(matches!(
+            Raft::serialize_mutation(&mutation(MAX_MUTATION_SIZE)),
+            Err(Error::Value(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_orphaned() -> Result<()> {
+        let mut state = State::new(MVCC::new(Box::new(Memory::new())))?;
+        let owner = |node: &str, epoch: u64| Owner { node: node.to_string(), epoch };
+        let mut begin = |owner: Option<Owner>| -> Result<u64> {
+            let mutation =
+                Mutation::Begin { mode: Mode::ReadWrite, priority: Priority::Normal, owner };
+            let (id, _, _): (u64, u64, u64) = Raft::deserialize(&state.apply(0, mutation)?)?;
+            Ok(id)
+        };
+        let orphaned = begin(Some(owner("a", 1)))?;
+        let committed = begin(Some(owner("a", 1)))?;
+        let current = begin(Some(owner("a", 2)))?;
+        let other = begin(Some(owner("b", 1)))?;
+        let unowned = begin(None)?;
+        state.apply(0, Mutation::Commit(committed))?;
+
+        // Only the active transaction owned by a previous process of node a is rolled back, and
+        // the owners are persisted across state machine restarts.
+        let mut state = State::new(state.engine.kv.clone())?;
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackOrphaned(owner("a", 2)))?)?;
+        assert_eq!(count, 1);
+        assert!(state.engine.resume(orphaned).is_err());
+        for id in &[current, other, unowned] {
+            state.engine.resume(*id)?;
+        }
+        assert_eq!(state.owners.keys().copied().collect::<Vec<_>>(), vec![current, other]);
+
+        // Owners of transactions that are no longer active are removed, without preventing
+        // other orphaned transactions from being rolled back.
+        let stale = begin_owned(&mut state, owner("a", 2))?;
+        let orphaned = begin_owned(&mut state, owner("a", 2))?;
+        state.engine.kv.resume(stale)?.commit()?;
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackOrphaned(owner("a", 3)))?)?;
+        assert_eq!(count, 2);
+        assert!(state.engine.resume(current).is_err());
+        assert!(state.engine.resume(orphaned).is_err());
+        assert_eq!(state.owners.keys().copied().collect::<Vec<_>>(), vec![other]);
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_orphaned_stale() -> Result<()> {
+        let mut state = State::new(MVCC::new(Box::new(Memory::new())))?;
+        let owner = |node: &str, epoch: u64| Owner { node: node.to_string(), epoch };
+        let orphaned = begin_owned(&mut state, owner("a", 1))?;
+        let current = begin_owned(&mut state, owner("a", 3))?;
+
+        // A stale mutation from an earlier process only rolls back transactions of processes
+        // before it, not those of the current process.
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackOrphaned(owner("a", 2)))?)?;
+        assert_eq!(count, 1);
+        assert!(state.engine.resume(orphaned).is_err());
+        state.engine.resume(current)?;
+        assert_eq!(state.owners.keys().copied().collect::<Vec<_>>(), vec![current]);
+
+        // Detached transactions are left alone, even if their owner wasn't removed.
+        state
+            .engine
+            .detach(current, Detached { user: Some("u".into()), role: None, timestamp: 100 })?;
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackOrphaned(owner("a", 4)))?)?;
+        assert_eq!(count, 0);
+        state.engine.resume(current)?;
+        Ok(())
+    }
+
+    /// Begins a read-write transaction owned by the given owner, returning its ID
+    fn begin_owned(state: &mut State, owner: Owner) -> Result<u64> {
+        let mutation = Mutation::Begin {
+            mode: Mode::ReadWrite,
+            priority: Priority::Normal,
+            owner: Some(owner),
+        };
+        let (id, _, _, _): (u64, u64, u64, u64) = Raft::deserialize(&state.apply(0, mutation)?)?;
+        Ok(id)
+    }
+
+    #[test]
+    fn detach_attach() -> Result<()> {
+        let mut state = State::new(MVCC::new(Box::new(Memory::new())))?;
+        let owner = |node: &str, epoch: u64| Owner { node: node.to_string(), epoch };
+        let detached = |user: &str, timestamp: u64| Detached {
+            user: Some(user.to_string()),
+            role: None,
+            timestamp,
+        };
+        let attach = |user: &str, role: Option<&str>, owner: Owner| Mutation::Attach {
+            txn_id: 1,
+            user: Some(user.to_string()),
+            role: role.map(|r| r.to_string()),
+            owner: Some(owner),
+        };
+        let id = begin_owned(&mut state, owner("a", 1))?;
+        assert_eq!(id, 1);
+
+        // Active transactions can't be attached unless they're detached.
+        assert_eq!(
+            state.apply(0, attach("u", None, owner("a", 1))),
+            Err(Error::Value("Transaction 1 is not detached".into()))
+        );
+
+        // A detached transaction no longer belongs to its owner's node, so it isn't rolled back
+        // as orphaned when the node restarts.
+        state.apply(0, Mutation::Detach { txn_id: id, detached: detached("u", 100) })?;
+        assert!(state.owners.is_empty());
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackOrphaned(owner("a", 2)))?)?;
+        assert_eq!(count, 0);
+
+        // Only the detaching user and role can attach it, which moves its ownership to the
+        // attaching node, and only once.
+        let hijacked =
+            Err(Error::Value("Transaction 1 was detached by a different user or role".into()));
+        assert_eq!(state.apply(0, attach("v", None, owner("b", 1))), hijacked);
+        assert_eq!(state.apply(0, attach("u", Some("r"), owner("b", 1))), hijacked);
+        let mut state = State::new(state.engine.kv.clone())?;
+        let (attached, _, _, _, _): Resumed =
+            Raft::deserialize(&state.apply(0, attach("u", None, owner("b", 1)))?)?;
+        assert_eq!(attached, id);
+        assert_eq!(state.owners.get(&id), Some(&owner("b", 1)));
+        assert_eq!(
+            state.apply(0, attach("u", None, owner("b", 1))),
+            Err(Error::Value("Transaction 1 is not detached".into()))
+        );
+
+        // Detached transactions are rolled back once they expire.
+        state.apply(0, Mutation::Detach { txn_id: id, detached: detached("u", 100) })?;
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackDetached { before: 100 })?)?;
+        assert_eq!(count, 0);
+        state.engine.resume(id)?;
+        let count: u64 =
+            Raft::deserialize(&state.apply(0, Mutation::RollbackDetached { before: 101 })?)?;
+        assert_eq!(count, 1);
+        assert!(state.engine.resume(id).is_err());
+        assert_eq!(
+            state.apply(0, attach("u", None, owner("b", 1))),
+            Err(Error::Value("Transaction 1 is not detached".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn commit_aborted() -> Result<()> {
+        let mut state = State::new(MVCC::new(Box::new(Memory::new())))?;
+        let owner = Owner { node: "a".into(), epoch: 1 };
+        let mutation =
+            Mutation::Begin { mode: Mode::ReadWrite, priority: Priority::Low, owner: Some(owner) };
+        let (id, _, _, _): (u64, u64, u64, u64) = Raft::deserialize(&state.apply(0, mutation)?)?;
+        state.engine.kv.resume(id)?.set(b"key", vec![0x01])?;
+
+        // A higher-priority transaction aborts it, so its commit fails, but its owner is removed.
+        let mut txn = state.engine.kv.begin_with_priority(Mode::ReadWrite, Priority::High)?;
+        txn.set(b"key", vec![0x02])?;
+        txn.commit()?;
+        assert_eq!(state.apply(0, Mutation::Commit(id)), Err(Error::Serialization));
+        assert!(state.owners.is_empty());
+        assert!(State::new(state.engine.kv.clone())?.owners.is_empty());
+        Ok(())
+    }
+}
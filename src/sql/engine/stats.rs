@@ -0,0 +1,172 @@
+//! Statement statistics, similar to PostgreSQL's pg_stat_statements. Statements are grouped by
+//! fingerprint, i.e. the normalized statement text with literals replaced by placeholders, and
+//! each fingerprint tracks its execution count, latency, and rows returned or affected.
+use super::super::parser::{Lexer, Token};
+use crate::error::Result;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The number of most recent executions per fingerprint used to compute latency percentiles.
+const LATENCY_SAMPLES: usize = 1000;
+
+/// The maximum number of fingerprints to track. When exceeded, the least executed fingerprint is
+/// evicted.
+const MAX_FINGERPRINTS: usize = 1000;
+
+/// Statistics for a single statement fingerprint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatementStat {
+    /// The number of successful executions.
+    pub calls: u64,
+    /// The total number of rows returned or affected.
+    pub rows: u64,
+    /// The total execution time.
+    pub total: Duration,
+    /// The maximum execution time.
+    pub max: Duration,
+    /// The execution times of the most recent executions, for percentiles.
+    latencies: VecDeque<Duration>,
+}
+
+impl StatementStat {
+    /// Returns the mean execution time.
+    pub fn mean(&self) -> Duration {
+        match self.calls {
+            0 => Duration::default(),
+            calls => Duration::from_nanos((self.total.as_nanos() / calls as u128) as u64),
+        }
+    }
+
+    /// Returns the given percentile (0-100) of the most recent execution times, using the
+    /// nearest-rank method.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::default();
+        }
+        let mut latencies = self.latencies.iter().copied().collect::<Vec<_>>();
+        latencies.sort();
+        let rank = (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies[rank.max(1).min(latencies.len()) - 1]
+    }
+
+    /// Records an execution.
+    fn record(&mut self, latency: Duration, rows: u64) {
+        self.calls += 1;
+        self.rows += rows;
+        self.total += latency;
+        self.max = self.max.max(latency);
+        if self.latencies.len() >= LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+}
+
+/// A set of statement statistics, keyed by fingerprint. It is cheap to clone, and clones share
+/// the underlying statistics.
+#[derive(Clone, Default)]
+pub struct StatementStats {
+    stats: Arc<Mutex<BTreeMap<String, StatementStat>>>,
+}
+
+impl StatementStats {
+    /// Creates a new, empty set of statement statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an execution of a statement with the given fingerprint.
+    pub fn record(&self, fingerprint: &str, latency: Duration, rows: u64) -> Result<()> {
+        let mut stats = self.stats.lock()?;
+        if !stats.contains_key(fingerprint) && stats.len() >= MAX_FINGERPRINTS {
+            if let Some(evict) = stats.iter().min_by_key(|(_, s)| s.calls).map(|(f, _)| f.clone()) {
+                stats.remove(&evict);
+            }
+        }
+        stats.entry(fingerprint.to_string()).or_default().record(latency, rows);
+        Ok(())
+    }
+
+    /// Returns the statistics for each fingerprint, ordered by fingerprint.
+    pub fn get(&self) -> Result<Vec<(String, StatementStat)>> {
+        Ok(self.stats.lock()?.iter().map(|(f, s)| (f.clone(), s.clone())).collect())
+    }
+
+    /// Clears all statistics.
+    pub fn reset(&self) -> Result<()> {
+        self.stats.lock()?.clear();
+        Ok(())
+    }
+}
+
+/// Returns the fingerprint of a query, by normalizing whitespace and keyword case and replacing
+/// literals with ?, e.g. SELECT * FROM movies WHERE id = ?. Queries that can't be tokenized are
+/// only trimmed.
+pub fn fingerprint(query: &str) -> String {
+    let tokens = match Lexer::new(query).collect::<Result<Vec<_>>>() {
+        Ok(tokens) => tokens,
+        Err(_) => return query.trim().to_string(),
+    };
+    let mut fingerprint = String::new();
+    let mut prev: Option<&Token> = None;
+    for token in tokens.iter().filter(|t| **t != Token::Semicolon) {
+        match (prev, token) {
+            (None, _) | (Some(Token::OpenParen), _) | (Some(Token::Period), _) => {}
+            (_, Token::CloseParen) | (_, Token::Comma) | (_, Token::Period) => {}
+            _ => fingerprint.push(' '),
+        }
+        match token {
+            Token::Number(_) | Token::String(_) => fingerprint.push('?'),
+            Token::Ident(ident) if ident.contains(|c: char| !c.is_alphanumeric() && c != '_') => {
+                fingerprint.push_str(&format!("\"{}\"", ident))
+            }
+            token => fingerprint.push_str(&token.to_string()),
+        }
+        prev = Some(token);
+    }
+    fingerprint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint() {
+        let f = super::fingerprint;
+        assert_eq!(f("select *  from Movies where id = 1;"), "SELECT * FROM movies WHERE id = ?");
+        assert_eq!(
+            f("INSERT INTO t (id, name) VALUES (1, 'a')"),
+            "INSERT INTO t (id, name) VALUES (?, ?)"
+        );
+        assert_eq!(f("SELECT t.a FROM \"system.raft\" t"), "SELECT t.a FROM \"system.raft\" t");
+        assert_eq!(f("SELECT 'unterminated"), "SELECT 'unterminated");
+    }
+
+    #[test]
+    fn record() -> Result<()> {
+        let stats = StatementStats::new();
+        let clone = stats.clone();
+        for ms in 1..=100 {
+            clone.record("SELECT ?", Duration::from_millis(ms), 2)?;
+        }
+        stats.record("BEGIN", Duration::from_millis(7), 0)?;
+
+        let stats = stats.get()?;
+        assert_eq!(
+            stats.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>(),
+            ["BEGIN", "SELECT ?"]
+        );
+        let select = &stats[1].1;
+        assert_eq!(select.calls, 100);
+        assert_eq!(select.rows, 200);
+        assert_eq!(select.max, Duration::from_millis(100));
+        assert_eq!(select.mean(), Duration::from_micros(50500));
+        assert_eq!(select.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(select.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(select.percentile(100.0), Duration::from_millis(100));
+        Ok(())
+    }
+}
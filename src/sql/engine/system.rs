@@ -0,0 +1,315 @@
+//! System tables are read-only virtual tables in the system schema, which expose internal engine
+//! state to SQL queries. They are not stored as regular tables, but have their rows generated when
+//! scanned, e.g. from node state or from internal keyspaces such as the audit log.
+use super::super::schema::{
+    Column, Function, SchemaChange, SchemaChangeKind, SchemaState, Table, Tables,
+};
+use super::super::settings::{self, Settings};
+use super::super::types::{DataType, Row, Value};
+use super::{AuditRecord, StatementStats};
+use crate::error::{Error, Result};
+use crate::raft;
+
+/// The prefix of system table names
+const PREFIX: &str = "system.";
+
+/// The audit log of DDL and DML statements, if enabled
+pub const AUDIT: &str = "system.audit";
+
+/// The columns of each table, similar to information_schema.columns
+pub const COLUMNS: &str = "system.columns";
+
+/// Stored functions, with their parameters and bodies
+pub const FUNCTIONS: &str = "system.functions";
+
+/// Background jobs in progress, i.e. online schema changes, with their progress
+pub const JOBS: &str = "system.jobs";
+
+/// Raft replication state for each node, as seen by the leader
+pub const RAFT: &str = "system.raft";
+
+/// Cluster settings, with their current and default values
+pub const SETTINGS: &str = "system.settings";
+
+/// Execution statistics for each statement fingerprint on the local node
+pub const STATEMENT_STATS: &str = "system.statement_stats";
+
+/// Storage statistics for each table
+pub const STORAGE_STATS: &str = "system.storage_stats";
+
+/// The tables in the database, similar to information_schema.tables
+pub const TABLES: &str = "system.tables";
+
+/// Checks whether a table name is in the system schema
+pub fn is_system_table(name: &str) -> bool {
+    name.starts_with(PREFIX)
+}
+
+/// Errors if the given table is a system table, which can't be modified
+pub fn ensure_writable(name: &str) -> Result<()> {
+    if is_system_table(name) {
+        return Err(Error::Value(format!("Can't modify system table {}", name)));
+    }
+    Ok(())
+}
+
+/// Returns the schema for a system table, if it exists
+pub fn table(name: &str) -> Option<Table> {
+    let columns = match name {
+        AUDIT => vec![
+            column("id", DataType::String, true),
+            column("txn_id", DataType::Integer, false),
+            column("timestamp", DataType::Integer, false),
+            column("user", DataType::String, false),
+            column("kind", DataType::String, false),
+            column("statement", DataType::String, false),
+        ],
+        COLUMNS => vec![
+            column("id", DataType::String, true),
+            column("table_name", DataType::String, false),
+            column("column_name", DataType::String, false),
+            column("position", DataType::Integer, false),
+            column("datatype", DataType::String, false),
+            Column { nullable: true, ..column("max_length", DataType::Integer, false) },
+            column("nullable", DataType::Boolean, false),
+            column("primary_key", DataType::Boolean, false),
+            Column { nullable: true, ..column("description", DataType::String, false) },
+        ],
+        FUNCTIONS => vec![
+            column("name", DataType::String, true),
+            column("parameters", DataType::String, false),
+            column("body", DataType::String, false),
+        ],
+        JOBS => vec![
+            column("table_name", DataType::String, true),
+            column("kind", DataType::String, false),
+            column("column_name", DataType::String, false),
+            column("state", DataType::String, false),
+            column("rows_processed", DataType::Integer, false),
+            column("rows_total", DataType::Integer, false),
+            Column { nullable: true, ..column("started", DataType::Integer, false) },
+            Column { nullable: true, ..column("eta_ms", DataType::Integer, false) },
+        ],
+        RAFT => vec![
+            column("node_id", DataType::String, true),
+            column("role", DataType::String, false),
+            column("term", DataType::Integer, false),
+            column("next_index", DataType::Integer, false),
+            column("match_index", DataType::Integer, false),
+            column("lag", DataType::Integer, false),
+            column("apply_index", DataType::Integer, false),
+            column("lag_ms", DataType::Integer, false),
+            Column { nullable: true, ..column("last_seen_ms", DataType::Integer, false) },
+        ],
+        SETTINGS => vec![
+            column("name", DataType::String, true),
+            column("value", DataType::String, false),
+            column("default_value", DataType::String, false),
+            column("datatype", DataType::String, false),
+            column("description", DataType::String, false),
+        ],
+        STATEMENT_STATS => vec![
+            column("fingerprint", DataType::String, true),
+            column("calls", DataType::Integer, false),
+            column("rows", DataType::Integer, false),
+            column("mean_ms", DataType::Float, false),
+            column("p50_ms", DataType::Float, false),
+            column("p95_ms", DataType::Float, false),
+            column("p99_ms", DataType::Float, false),
+            column("max_ms", DataType::Float, false),
+        ],
+        STORAGE_STATS => vec![
+            column("table_name", DataType::String, true),
+            column("keys", DataType::Integer, false),
+            column("bytes", DataType::Integer, false),
+            column("versions", DataType::Integer, false),
+            column("dead_versions", DataType::Integer, false),
+        ],
+        TABLES => vec![
+            column("table_name", DataType::String, true),
+            column("columns", DataType::Integer, false),
+            Column { nullable: true, ..column("description", DataType::String, false) },
+        ],
+        _ => return None,
+    };
+    Table::new(name.to_string(), columns).ok()
+}
+
+/// Generates a row of the system.audit table from an audit record. The ID is the transaction ID
+/// and the record's sequence number within the transaction, e.g. 7.1.
+pub fn audit_row(txn_id: u64, seq: u64, record: AuditRecord) -> Row {
+    vec![
+        Value::String(format!("{}.{}", txn_id, seq)),
+        Value::Integer(txn_id as i64),
+        Value::Integer(record.timestamp as i64),
+        Value::String(record.user),
+        Value::String(record.kind),
+        Value::String(record.statement),
+    ]
+}
+
+/// Generates the rows of the system.tables table from the table schemas
+pub fn table_rows(tables: Tables) -> Vec<Row> {
+    tables
+        .map(|t| {
+            vec![
+                Value::String(t.name),
+                Value::Integer(t.columns.len() as i64),
+                t.comment.map(Value::String).unwrap_or(Value::Null),
+            ]
+        })
+        .collect()
+}
+
+/// Generates the rows of the system.functions table from the stored functions. The parameters
+/// are given as an SQL parameter list, e.g. a INTEGER, b STRING.
+pub fn function_rows(functions: Vec<Function>) -> Vec<Row> {
+    functions
+        .into_iter()
+        .map(|f| {
+            vec![
+                Value::String(f.name.clone()),
+                Value::String(f.format_parameters()),
+                Value::String(f.body),
+            ]
+        })
+        .collect()
+}
+
+/// Generates the rows of the system.columns table from the table schemas. The ID is the table
+/// and column name, e.g. movies.title, and the position is the column's 1-based ordinal.
+pub fn column_rows(tables: Tables) -> Vec<Row> {
+    tables
+        .flat_map(|t| {
+            let table = t.name;
+            t.columns.into_iter().enumerate().map(move |(i, c)| {
+                vec![
+                    Value::String(format!("{}.{}", table, c.name)),
+                    Value::String(table.clone()),
+                    Value::String(c.name),
+                    Value::Integer(i as i64 + 1),
+                    Value::String(c.datatype.to_string()),
+                    c.datatype
+                        .max_length()
+                        .map(|l| Value::Integer(l as i64))
+                        .unwrap_or(Value::Null),
+                    Value::Boolean(c.nullable),
+                    Value::Boolean(c.primary_key),
+                    c.comment.map(Value::String).unwrap_or(Value::Null),
+                ]
+            })
+        })
+        .collect()
+}
+
+/// Generates a row of the system.jobs table from a table's online schema change, given the
+/// column name, the table's row count, and the current time in milliseconds since the Unix
+/// epoch. The ETA extrapolates the backfill rate so far to the remaining rows, and is NULL until
+/// some rows have been backfilled.
+pub fn job_row(table: String, column: String, change: &SchemaChange, rows: u64, now: u64) -> Row {
+    let kind = match change.kind {
+        SchemaChangeKind::AddColumn(_) => "ADD COLUMN",
+        SchemaChangeKind::CreateIndex(_) => "CREATE INDEX",
+    };
+    let state = match change.state {
+        SchemaState::Backfilling => "backfilling",
+        SchemaState::WriteVisible | SchemaState::ReadVisible => "validating",
+    };
+    let eta = match (change.started, change.backfilled_rows) {
+        (Some(started), processed) if processed > 0 => {
+            let remaining = rows.saturating_sub(processed);
+            Value::Integer((now.saturating_sub(started) * remaining / processed) as i64)
+        }
+        _ => Value::Null,
+    };
+    vec![
+        Value::String(table),
+        Value::String(kind.into()),
+        Value::String(column),
+        Value::String(state.into()),
+        Value::Integer(change.backfilled_rows as i64),
+        Value::Integer(rows as i64),
+        change.started.map(|t| Value::Integer(t as i64)).unwrap_or(Value::Null),
+        eta,
+    ]
+}
+
+/// Generates the rows of the system.settings table, with one row per defined setting ordered by
+/// name. Values are given as strings, since settings have different datatypes.
+pub fn settings_rows(settings: &Settings) -> Result<Vec<Row>> {
+    settings::definitions()
+        .into_iter()
+        .map(|d| {
+            Ok(vec![
+                Value::String(d.name.into()),
+                Value::String(settings.get(d.name)?.to_string()),
+                Value::String(d.default.to_string()),
+                Value::String(d.datatype.to_string()),
+                Value::String(d.description.into()),
+            ])
+        })
+        .collect()
+}
+
+/// Generates the rows of the system.raft table from the Raft status, ordered by node ID. The
+/// leader is the only node that knows the replication progress of all nodes, so all rows reflect
+/// the leader's view: the lag is the number of log entries the node is behind the leader.
+pub fn raft_rows(status: &raft::Status) -> Vec<Row> {
+    status
+        .nodes
+        .iter()
+        .map(|(id, node)| {
+            let next_index = status.node_next_index.get(id).copied().unwrap_or(node.last_index + 1);
+            vec![
+                Value::String(id.clone()),
+                Value::String(node.role.clone()),
+                Value::Integer(status.term as i64),
+                Value::Integer(next_index as i64),
+                Value::Integer(node.last_index as i64),
+                Value::Integer(node.lag_entries as i64),
+                Value::Integer(node.apply_index as i64),
+                Value::Integer(node.lag_millis as i64),
+                node.last_seen.map(|ms| Value::Integer(ms as i64)).unwrap_or(Value::Null),
+            ]
+        })
+        .collect()
+}
+
+/// Generates the rows of the system.statement_stats table
+pub fn statement_stats_rows(stats: &StatementStats) -> Result<Vec<Row>> {
+    let ms = |d: std::time::Duration| Value::Float(d.as_secs_f64() * 1000.0);
+    Ok(stats
+        .get()?
+        .into_iter()
+        .map(|(fingerprint, s)| {
+            vec![
+                Value::String(fingerprint),
+                Value::Integer(s.calls as i64),
+                Value::Integer(s.rows as i64),
+                ms(s.mean()),
+                ms(s.percentile(50.0)),
+                ms(s.percentile(95.0)),
+                ms(s.percentile(99.0)),
+                ms(s.max),
+            ]
+        })
+        .collect())
+}
+
+/// Builds a system table column
+fn column(name: &str, datatype: DataType, primary_key: bool) -> Column {
+    Column {
+        id: 0,
+        name: name.to_string(),
+        datatype,
+        primary_key,
+        nullable: false,
+        default: None,
+        missing: None,
+        unique: primary_key,
+        references: None,
+        references_id: None,
+        index: false,
+        comment: None,
+    }
+}
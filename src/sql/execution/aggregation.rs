@@ -23,7 +23,6 @@ impl<T: Transaction> Aggregation<T> {
 impl<T: Transaction> Executor<T> for Aggregation<T> {
     #[allow(clippy::or_fun_call)]
     fn execute(mut self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        let agg_count = self.aggregates.len();
         match self.source.execute(txn)? {
             ResultSet::Query { columns, mut rows } => {
                 while let Some(mut row) = rows.next().transpose()? {
@@ -44,11 +43,18 @@ impl<T: Transaction> Executor<T> for Aggregation<T> {
                         self.aggregates.iter().map(|agg| Accumulator::from(agg)).collect(),
                     );
                 }
+                let aggregates = &self.aggregates;
                 Ok(ResultSet::Query {
                     columns: columns
                         .into_iter()
                         .enumerate()
-                        .map(|(i, c)| if i < agg_count { Column { name: None } } else { c })
+                        .map(|(i, c)| match aggregates.get(i) {
+                            Some(agg) => Column {
+                                datatype: agg.datatype(c.datatype.as_ref()),
+                                ..Column::default()
+                            },
+                            None => c,
+                        })
                         .collect(),
                     rows: Box::new(self.accumulators.into_iter().map(|(bucket, accs)| {
                         Ok(accs
@@ -76,6 +82,7 @@ pub trait Accumulator: std::fmt::Debug + Send {
 impl dyn Accumulator {
     fn from(aggregate: &Aggregate) -> Box<dyn Accumulator> {
         match aggregate {
+            Aggregate::ArrayAgg => Box::new(ArrayAgg::new()),
             Aggregate::Average => Box::new(Average::new()),
             Aggregate::Count => Box::new(Count::new()),
             Aggregate::Max => Box::new(Max::new()),
@@ -85,6 +92,43 @@ impl dyn Accumulator {
     }
 }
 
+// Array of non-null values, in input order
+#[derive(Debug)]
+pub struct ArrayAgg {
+    values: Vec<Value>,
+}
+
+impl ArrayAgg {
+    pub fn new() -> Self {
+        Self { values: Vec::new() }
+    }
+}
+
+impl Accumulator for ArrayAgg {
+    fn accumulate(&mut self, value: &Value) -> Result<()> {
+        match (value, value.datatype(), self.values.first().and_then(|v| v.datatype())) {
+            (Value::Null, _, _) => return Ok(()),
+            (Value::Array(_), _, _) => return Err(Error::Value("Arrays can't be nested".into())),
+            (_, Some(datatype), Some(expect)) if datatype != expect => {
+                return Err(Error::Value(format!(
+                    "Array elements must have the same datatype, found {} and {}",
+                    expect, datatype
+                )))
+            }
+            _ => {}
+        }
+        self.values.push(value.clone());
+        Ok(())
+    }
+
+    fn aggregate(&self) -> Value {
+        match self.values.is_empty() {
+            true => Value::Null,
+            false => Value::Array(self.values.clone()),
+        }
+    }
+}
+
 // Count non-null values
 #[derive(Debug)]
 pub struct Count {
@@ -111,7 +155,7 @@ impl Accumulator for Count {
     }
 }
 
-// Average value
+// Average of non-null values
 #[derive(Debug)]
 pub struct Average {
     count: Count,
@@ -140,7 +184,7 @@ impl Accumulator for Average {
     }
 }
 
-// Maximum value
+// Maximum value, ignoring nulls
 #[derive(Debug)]
 pub struct Max {
     max: Option<Value>,
@@ -154,6 +198,9 @@ impl Max {
 
 impl Accumulator for Max {
     fn accumulate(&mut self, value: &Value) -> Result<()> {
+        if value == &Value::Null {
+            return Ok(());
+        }
         if let Some(max) = &mut self.max {
             match value.partial_cmp(max) {
                 _ if max.datatype() != value.datatype() => *max = Value::Null,
@@ -175,7 +222,7 @@ impl Accumulator for Max {
     }
 }
 
-// Minimum value
+// Minimum value, ignoring nulls
 #[derive(Debug)]
 pub struct Min {
     min: Option<Value>,
@@ -189,6 +236,9 @@ impl Min {
 
 impl Accumulator for Min {
     fn accumulate(&mut self, value: &Value) -> Result<()> {
+        if value == &Value::Null {
+            return Ok(());
+        }
         if let Some(min) = &mut self.min {
             match value.partial_cmp(min) {
                 _ if min.datatype() != value.datatype() => *min = Value::Null,
@@ -210,7 +260,7 @@ impl Accumulator for Min {
     }
 }
 
-// Sum of values
+// Sum of non-null values
 #[derive(Debug)]
 pub struct Sum {
     sum: Option<Value>,
@@ -225,10 +275,14 @@ impl Sum {
 impl Accumulator for Sum {
     fn accumulate(&mut self, value: &Value) -> Result<()> {
         self.sum = match (&self.sum, value) {
-            (Some(Value::Integer(s)), Value::Integer(i)) => Some(Value::Integer(s + i)),
+            (Some(Value::Integer(s)), Value::Integer(i)) => Some(Value::Integer(
+                s.checked_add(*i)
+                    .ok_or_else(|| Error::Value(format!("Integer overflow: {} + {}", s, i)))?,
+            )),
             (Some(Value::Float(s)), Value::Float(f)) => Some(Value::Float(s + f)),
             (None, Value::Integer(i)) => Some(Value::Integer(*i)),
             (None, Value::Float(f)) => Some(Value::Float(*f)),
+            (sum, Value::Null) => sum.clone(),
             _ => Some(Value::Null),
         };
         Ok(())
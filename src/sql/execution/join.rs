@@ -1,9 +1,12 @@
 use super::super::engine::Transaction;
-use super::super::types::{Expression, Rows};
+use super::super::types::{Columns, Expression, Rows};
 use super::{Executor, ResultSet, Row, Value};
 use crate::error::{Error, Result};
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
 
 /// A nested loop join executor, which checks each row in the left source against every row in
 /// the right source using the given predicate.
@@ -74,7 +77,7 @@ impl NestedLoopRows {
             left,
             right: Box::new(right.clone().into_iter()),
             right_vec: right,
-            right_empty: std::iter::repeat(Value::Null).take(right_width).collect(),
+            right_empty: std::iter::repeat_n(Value::Null, right_width).collect(),
             right_hit: false,
             predicate,
             outer,
@@ -140,16 +143,28 @@ impl Iterator for NestedLoopRows {
     }
 }
 
-/// A hash join executor
+/// A hash join executor. The right source is read into a hash table first, and for inner joins
+/// its keys are then passed to any runtime filter (see RuntimeFilter) on the left source before
+/// it's read. Inner joins can instead build the hash table from the left source, when it's
+/// smaller, in which case the runtime filter applies to the right source. Inner joins without a
+/// runtime filter switch sources while executing if the build source turns out to be larger.
 pub struct HashJoin<T: Transaction> {
     left: Box<dyn Executor<T>>,
     left_field: usize,
     right: Box<dyn Executor<T>>,
     right_field: usize,
     outer: bool,
+    build_left: bool,
+    runtime_filter: Option<RuntimeFilterSlot>,
 }
 
 impl<T: Transaction> HashJoin<T> {
+    /// The number of build rows after which an inner hash join without a runtime filter checks
+    /// whether its probe source is in fact smaller, since the optimizer's row estimates ignore
+    /// the selectivity of filters and are unknown for e.g. joins. If so, it switches to building
+    /// the hash table from the probe source instead.
+    const REPLAN_ROWS: usize = 1000;
+
     pub fn new(
         left: Box<dyn Executor<T>>,
         left_field: usize,
@@ -157,46 +172,282 @@ impl<T: Transaction> HashJoin<T> {
         right_field: usize,
         outer: bool,
     ) -> Box<Self> {
-        Box::new(Self { left, left_field, right, right_field, outer })
+        Box::new(Self {
+            left,
+            left_field,
+            right,
+            right_field,
+            outer,
+            build_left: false,
+            runtime_filter: None,
+        })
+    }
+
+    /// Builds the hash table from the left source rather than the right one, and reads the right
+    /// source afterwards. Must only be used for inner joins.
+    pub fn with_build_left(mut self: Box<Self>, build_left: bool) -> Box<Self> {
+        self.build_left = build_left;
+        self
+    }
+
+    /// Sets a runtime filter slot for the source that's read last, which is filled with the join
+    /// keys of the other source before it's read. Must only be used for inner joins.
+    pub fn with_runtime_filter(mut self: Box<Self>, slot: RuntimeFilterSlot) -> Box<Self> {
+        self.runtime_filter = Some(slot);
+        self
+    }
+
+    /// Reads the build and probe sources in lockstep, once the build source has reached
+    /// REPLAN_ROWS rows, until either is exhausted. Build rows are added to the buffer. Returns
+    /// the probe rows read, and whether the probe source was exhausted first, in which case the
+    /// join should switch sources.
+    fn read_lockstep(
+        build: &mut Rows,
+        buffer: &mut Vec<Row>,
+        probe: &mut Rows,
+    ) -> Result<(Vec<Row>, bool)> {
+        let mut probed = Vec::new();
+        loop {
+            if probed.len() < buffer.len() {
+                match probe.next().transpose()? {
+                    Some(row) => probed.push(row),
+                    None => return Ok((probed, true)),
+                }
+            } else {
+                match build.next().transpose()? {
+                    Some(row) => buffer.push(row),
+                    None => return Ok((probed, false)),
+                }
+            }
+        }
+    }
+
+    /// Builds a hash table from the given rows, keyed by the given field. NULL keys never match,
+    /// since NULL = NULL is not true. Keys may have several matching rows, e.g. when the build
+    /// source is not joined on its primary key.
+    fn hash(
+        rows: impl Iterator<Item = Result<Row>>,
+        field: usize,
+        side: &str,
+    ) -> Result<HashMap<Value, Vec<Row>>> {
+        let mut table: HashMap<Value, Vec<Row>> = HashMap::new();
+        for row in rows {
+            let row = row?;
+            if row.len() <= field {
+                return Err(Error::Internal(format!("{} index {} out of bounds", side, field)));
+            }
+            if row[field] != Value::Null {
+                table.entry(row[field].clone()).or_default().push(row);
+            }
+        }
+        Ok(table)
     }
 }
 
 impl<T: Transaction> Executor<T> for HashJoin<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Query { mut columns, rows } = self.left.execute(txn)? {
-            if let ResultSet::Query { columns: rcolumns, rows: rrows } = self.right.execute(txn)? {
-                let (l, r, outer) = (self.left_field, self.right_field, self.outer);
-                let right: HashMap<Value, Row> = rrows
-                    .map(|res| match res {
-                        Ok(row) if row.len() <= r => {
-                            Err(Error::Internal(format!("Right index {} out of bounds", r)))
-                        }
-                        Ok(row) => Ok((row[r].clone(), row)),
-                        Err(err) => Err(err),
-                    })
-                    .collect::<Result<_>>()?;
-                let empty = std::iter::repeat(Value::Null).take(rcolumns.len());
-                columns.extend(rcolumns);
-                let rows = Box::new(rows.filter_map(move |res| match res {
-                    Ok(row) if row.len() <= l => {
-                        Some(Err(Error::Value(format!("Left index {} out of bounds", l))))
+        if self.build_left && self.outer {
+            return Err(Error::Internal("Can't build outer hash join from left source".into()));
+        }
+        let mut build_left = self.build_left;
+        let (build, mut b, mut build_side, probe, mut p, mut probe_side) = if build_left {
+            (self.left, self.left_field, "Left", self.right, self.right_field, "Right")
+        } else {
+            (self.right, self.right_field, "Right", self.left, self.left_field, "Left")
+        };
+        let (mut bcolumns, mut brows) = match build.execute(txn)? {
+            ResultSet::Query { columns, rows } => (columns, rows),
+            _ => return Err(Error::Internal("Unexpected result set".into())),
+        };
+
+        // Inner joins without a runtime filter read the probe source early if the build source
+        // is large, and switch sources if the probe source turns out to be smaller.
+        let mut buffer = Vec::new();
+        let mut probe = Some(probe);
+        let mut probed: Option<(Columns, Rows)> = None;
+        if !self.outer && self.runtime_filter.is_none() {
+            while buffer.len() < Self::REPLAN_ROWS {
+                match brows.next().transpose()? {
+                    Some(row) => buffer.push(row),
+                    None => break,
+                }
+            }
+            if buffer.len() == Self::REPLAN_ROWS {
+                let probe =
+                    probe.take().ok_or_else(|| Error::Internal("No probe source".into()))?;
+                let (pcolumns, mut prows) = match probe.execute(txn)? {
+                    ResultSet::Query { columns, rows } => (columns, rows),
+                    _ => return Err(Error::Internal("Unexpected result set".into())),
+                };
+                let (rows, switch) = Self::read_lockstep(&mut brows, &mut buffer, &mut prows)?;
+                if switch {
+                    build_left = !build_left;
+                    std::mem::swap(&mut b, &mut p);
+                    std::mem::swap(&mut build_side, &mut probe_side);
+                    let rest = std::mem::replace(&mut brows, Box::new(std::iter::empty()));
+                    probed = Some((bcolumns, Box::new(buffer.into_iter().map(Ok).chain(rest))));
+                    bcolumns = pcolumns;
+                    buffer = rows;
+                } else {
+                    probed = Some((pcolumns, Box::new(rows.into_iter().map(Ok).chain(prows))));
+                }
+            }
+        }
+
+        let table = Self::hash(buffer.into_iter().map(Ok).chain(brows), b, build_side)?;
+        let (pcolumns, rows) = match (probed, probe) {
+            (Some(probed), _) => probed,
+            (None, Some(probe)) => {
+                if let Some(slot) = self.runtime_filter {
+                    slot.lock()?.replace(RuntimeFilter::new(table.keys().cloned().collect()));
+                }
+                match probe.execute(txn)? {
+                    ResultSet::Query { columns, rows } => (columns, rows),
+                    _ => return Err(Error::Internal("Unexpected result set".into())),
+                }
+            }
+            (None, None) => return Err(Error::Internal("No probe source".into())),
+        };
+        let outer = self.outer;
+        let empty = std::iter::repeat_n(Value::Null, bcolumns.len());
+        let columns = if build_left {
+            bcolumns.into_iter().chain(pcolumns).collect()
+        } else {
+            pcolumns.into_iter().chain(bcolumns).collect()
+        };
+        let rows = Box::new(rows.flat_map(move |res| {
+            match res {
+                Ok(row) if row.len() <= p => {
+                    vec![Err(Error::Value(format!("{} index {} out of bounds", probe_side, p)))]
+                }
+                Ok(mut row) => match table.get(&row[p]) {
+                    Some(hits) => hits
+                        .iter()
+                        .map(|hit| {
+                            let (mut joined, rest) =
+                                if build_left { (hit.clone(), &row) } else { (row.clone(), hit) };
+                            joined.extend(rest.iter().cloned());
+                            Ok(joined)
+                        })
+                        .collect(),
+                    None if outer => {
+                        row.extend(empty.clone());
+                        vec![Ok(row)]
                     }
-                    Ok(mut row) => match right.get(&row[l]) {
-                        Some(hit) => {
-                            row.extend(hit.clone());
-                            Some(Ok(row))
-                        }
-                        None if outer => {
-                            row.extend(empty.clone());
-                            Some(Ok(row))
-                        }
-                        None => None,
-                    },
-                    Err(err) => Some(Err(err)),
-                }));
-                return Ok(ResultSet::Query { columns, rows });
+                    None => vec![],
+                },
+                Err(err) => vec![Err(err)],
+            }
+        }));
+        Ok(ResultSet::Query { columns, rows })
+    }
+}
+
+/// A shared slot for a runtime filter, filled by a hash join and taken by a scan of the source it
+/// reads last.
+pub type RuntimeFilterSlot = Arc<Mutex<Option<RuntimeFilter>>>;
+
+/// A runtime filter, built from the join keys of a hash join's build source (normally the right
+/// one) and applied to a scan of its other source. This skips rows that can't match before they
+/// reach the join, which is significant for selective joins since the other source is otherwise
+/// read in full.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeFilter {
+    /// The join keys, when there are few of them. These are added to the scan filter, such that
+    /// the storage engine skips non-matching rows before returning them, e.g. across Raft.
+    In(Vec<Value>),
+    /// A bloom filter of the join keys, when there are many of them. This is applied to rows as
+    /// they're scanned, and may let through some rows that don't match.
+    Bloom(BloomFilter),
+}
+
+impl RuntimeFilter {
+    /// The maximum number of join keys to use an IN filter for.
+    const MAX_IN_KEYS: usize = 16;
+
+    /// Builds a runtime filter for a set of join keys, which must not contain NULL.
+    pub fn new(keys: Vec<Value>) -> Self {
+        if keys.len() <= Self::MAX_IN_KEYS {
+            Self::In(keys)
+        } else {
+            let mut bloom = BloomFilter::new(keys.len());
+            for key in &keys {
+                bloom.insert(key);
             }
+            Self::Bloom(bloom)
+        }
+    }
+}
+
+/// A bloom filter of values, which can tell whether a value may have been inserted into it. It
+/// never gives false negatives, and gives false positives for about 1% of values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u64,
+}
+
+impl BloomFilter {
+    /// Creates a new bloom filter sized for the given number of values.
+    pub fn new(size: usize) -> Self {
+        // For a false positive rate p, the optimal number of bits is -n·ln(p)/ln(2)² and the
+        // optimal number of hashes is ln(2)·bits/n, i.e. about 9.6 bits and 7 hashes per value
+        // for p = 0.01.
+        let bits = (size as f64 * 9.6).ceil() as usize;
+        Self { bits: vec![0; bits / 64 + 1], hashes: 7 }
+    }
+
+    /// Inserts a value into the filter.
+    pub fn insert(&mut self, value: &Value) {
+        for bit in self.bits_for(value) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Checks whether the filter may contain a value.
+    pub fn contains(&self, value: &Value) -> bool {
+        self.bits_for(value).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Returns the bit positions for a value, by combining two hashes of it (Kirsch-Mitzenmacher).
+    fn bits_for(&self, value: &Value) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let h1 = hasher.finish();
+        0_u64.hash(&mut hasher);
+        let h2 = hasher.finish();
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_filter() {
+        let mut bloom = BloomFilter::new(1000);
+        for i in 0..1000 {
+            bloom.insert(&Value::Integer(i));
+        }
+        assert!((0..1000).all(|i| bloom.contains(&Value::Integer(i))));
+
+        let false_positives = (1000..11000).filter(|i| bloom.contains(&Value::Integer(*i))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        assert!(!BloomFilter::new(0).contains(&Value::String("a".into())));
+    }
+
+    #[test]
+    fn runtime_filter() {
+        let keys: Vec<Value> = (0..16).map(Value::Integer).collect();
+        assert_eq!(RuntimeFilter::new(keys.clone()), RuntimeFilter::In(keys));
+
+        let keys: Vec<Value> = (0..17).map(Value::Integer).collect();
+        match RuntimeFilter::new(keys.clone()) {
+            RuntimeFilter::Bloom(bloom) => assert!(keys.iter().all(|k| bloom.contains(k))),
+            filter => panic!("Expected bloom filter, got {:?}", filter),
         }
-        Err(Error::Internal("Unexpected result set".into()))
     }
 }
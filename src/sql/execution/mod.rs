@@ -6,20 +6,25 @@ mod schema;
 mod source;
 
 use aggregation::Aggregation;
-use join::{HashJoin, NestedLoopJoin};
+use join::{HashJoin, NestedLoopJoin, RuntimeFilterSlot};
 use mutation::{Delete, Insert, Update};
-use query::{Filter, Limit, Offset, Order, Projection};
-use schema::{CreateTable, DropTable};
-use source::{IndexLookup, KeyLookup, Nothing, Scan};
+use query::{Filter, Limit, Offset, Order, Projection, Spool, SpoolSlot};
+use schema::{
+    AddColumn, AlterColumn, CheckIndex, Comment, CreateFunction, CreateIndex, CreatePolicy,
+    CreateTable, CreateType, DropFunction, DropPolicy, DropTable, DropType, SetSetting,
+};
+use source::{Changefeed, History, IndexLookup, IndexRange, KeyLookup, Nothing, RowVersions, Scan};
 
 use super::engine::{Mode, Transaction};
-use super::plan::Node;
+use super::plan::{Explanation, Node};
 use super::types::{Columns, Row, Rows, Value};
 use crate::error::{Error, Result};
 
 use derivative::Derivative;
 use serde_derive::{Deserialize, Serialize};
 
+use std::collections::HashMap;
+
 /// A plan executor
 pub trait Executor<T: Transaction> {
     /// Executes the executor, consuming it and returning a result set
@@ -29,64 +34,179 @@ pub trait Executor<T: Transaction> {
 impl<T: Transaction + 'static> dyn Executor<T> {
     /// Builds an executor for a plan node, consuming it
     pub fn build(node: Node) -> Box<dyn Executor<T>> {
+        Self::build_with(node, &mut HashMap::new())
+    }
+
+    /// Builds an executor for a plan node, where spools share slots by id.
+    fn build_with(node: Node, spools: &mut HashMap<usize, SpoolSlot>) -> Box<dyn Executor<T>> {
         match node {
+            Node::AddColumn { table, column } => AddColumn::new(table, column),
             Node::Aggregation { source, aggregates } => {
-                Aggregation::new(Self::build(*source), aggregates)
+                Aggregation::new(Self::build_with(*source, spools), aggregates)
             }
+            Node::AlterColumn { table, column, alteration } => {
+                AlterColumn::new(table, column, alteration)
+            }
+            Node::Changefeed { table, from } => Changefeed::new(table, from),
+            Node::CheckIndex { table, column } => CheckIndex::new(table, column),
+            Node::Comment { table, column, comment } => Comment::new(table, column, comment),
+            Node::SetSetting { name, value } => SetSetting::new(name, value),
+            Node::CreateIndex { table, column } => CreateIndex::new(table, column),
+            Node::CreateFunction { function } => CreateFunction::new(function),
             Node::CreateTable { schema } => CreateTable::new(schema),
-            Node::Delete { table, source } => Delete::new(table, Self::build(*source)),
+            Node::Delete { table, source } => Delete::new(table, Self::build_with(*source, spools)),
+            Node::DropFunction { name } => DropFunction::new(name),
+            Node::CreateType { r#enum } => CreateType::new(r#enum),
+            Node::DropType { name } => DropType::new(name),
+            Node::CreatePolicy { table, policy } => CreatePolicy::new(table, policy),
+            Node::DropPolicy { table, name } => DropPolicy::new(table, name),
             Node::DropTable { table } => DropTable::new(table),
-            Node::Filter { source, predicate } => Filter::new(Self::build(*source), predicate),
-            Node::HashJoin { left, left_field, right, right_field, outer } => HashJoin::new(
-                Self::build(*left),
-                left_field.0,
-                Self::build(*right),
-                right_field.0,
-                outer,
-            ),
+            Node::Filter { source, predicate } => {
+                Filter::new(Self::build_with(*source, spools), predicate)
+            }
+            Node::HashJoin { left, left_field, right, right_field, outer, build_left }
+                if !outer =>
+            {
+                // Inner joins pass the join keys of the source they build the hash table from to
+                // a scan of the other source, if any.
+                let slot = RuntimeFilterSlot::default();
+                let (left, right, filtered) = if build_left {
+                    let (right, filtered) =
+                        Self::build_filtered(*right, right_field.0, &slot, spools);
+                    (Self::build_with(*left, spools), right, filtered)
+                } else {
+                    let (left, filtered) = Self::build_filtered(*left, left_field.0, &slot, spools);
+                    (left, Self::build_with(*right, spools), filtered)
+                };
+                let join = HashJoin::new(left, left_field.0, right, right_field.0, false)
+                    .with_build_left(build_left);
+                if filtered {
+                    join.with_runtime_filter(slot)
+                } else {
+                    join
+                }
+            }
+            Node::HashJoin { left, left_field, right, right_field, outer, build_left } => {
+                HashJoin::new(
+                    Self::build_with(*left, spools),
+                    left_field.0,
+                    Self::build_with(*right, spools),
+                    right_field.0,
+                    outer,
+                )
+                .with_build_left(build_left)
+            }
+            Node::History { table, key } => History::new(table, key),
             Node::IndexLookup { table, alias: _, column, values } => {
                 IndexLookup::new(table, column, values)
             }
+            Node::IndexRange { table, alias: _, column, lower, upper } => {
+                IndexRange::new(table, column, lower, upper)
+            }
             Node::Insert { table, columns, expressions } => {
                 Insert::new(table, columns, expressions)
             }
             Node::KeyLookup { table, alias: _, keys } => KeyLookup::new(table, keys),
-            Node::Limit { source, limit } => Limit::new(Self::build(*source), limit),
+            Node::Limit { source, limit } => Limit::new(Self::build_with(*source, spools), limit),
             Node::NestedLoopJoin { left, left_size: _, right, predicate, outer } => {
-                NestedLoopJoin::new(Self::build(*left), Self::build(*right), predicate, outer)
+                NestedLoopJoin::new(
+                    Self::build_with(*left, spools),
+                    Self::build_with(*right, spools),
+                    predicate,
+                    outer,
+                )
             }
             Node::Nothing => Nothing::new(),
-            Node::Offset { source, offset } => Offset::new(Self::build(*source), offset),
-            Node::Order { source, orders } => Order::new(Self::build(*source), orders),
+            Node::Offset { source, offset } => {
+                Offset::new(Self::build_with(*source, spools), offset)
+            }
+            Node::Order { source, orders } => Order::new(Self::build_with(*source, spools), orders),
+            Node::PartitionScan { table, alias: _, partitions, filter } => {
+                Scan::new(table, filter).with_partitions(partitions)
+            }
             Node::Projection { source, expressions } => {
-                Projection::new(Self::build(*source), expressions)
+                Projection::new(Self::build_with(*source, spools), expressions)
+            }
+            Node::RowVersions { source, table, size: _ } => {
+                RowVersions::new(Self::build_with(*source, spools), table)
             }
             Node::Scan { table, filter, alias: _ } => Scan::new(table, filter),
+            Node::Spool { source, id } => {
+                let slot = spools.entry(id).or_default().clone();
+                Spool::new(Self::build_with(*source, spools), slot)
+            }
             Node::Update { table, source, expressions } => Update::new(
                 table,
-                Self::build(*source),
+                Self::build_with(*source, spools),
                 expressions.into_iter().map(|(i, _, e)| (i, e)).collect(),
             ),
         }
     }
+
+    /// Builds an executor for a plan node, applying a runtime filter for the given field to its
+    /// table scan if it has one, i.e. if it's a scan or filters of a scan. Returns the executor
+    /// and whether the runtime filter was applied.
+    fn build_filtered(
+        node: Node,
+        field: usize,
+        slot: &RuntimeFilterSlot,
+        spools: &mut HashMap<usize, SpoolSlot>,
+    ) -> (Box<dyn Executor<T>>, bool) {
+        match node {
+            Node::Scan { table, filter, alias: _ } => {
+                (Scan::new(table, filter).with_runtime_filter(field, slot.clone()), true)
+            }
+            Node::PartitionScan { table, alias: _, partitions, filter } => (
+                Scan::new(table, filter)
+                    .with_partitions(partitions)
+                    .with_runtime_filter(field, slot.clone()),
+                true,
+            ),
+            Node::Filter { source, predicate } => {
+                let (source, filtered) = Self::build_filtered(*source, field, slot, spools);
+                (Filter::new(source, predicate), filtered)
+            }
+            node => (Self::build_with(node, spools), false),
+        }
+    }
 }
 
 /// An executor result set
 #[derive(Derivative, Serialize, Deserialize)]
 #[derivative(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum ResultSet {
     // Transaction started
     Begin {
         id: u64,
         mode: Mode,
     },
-    // Transaction committed
+    // Transaction committed, and the ID of a new transaction chained with AND CHAIN, if any
     Commit {
         id: u64,
+        chain: Option<u64>,
     },
-    // Transaction rolled back
+    // Transaction rolled back, and the ID of a new transaction chained with AND CHAIN, if any
     Rollback {
         id: u64,
+        chain: Option<u64>,
+    },
+    // Transaction detached from the session, to be resumed by another session
+    Detach {
+        id: u64,
+    },
+    // Detached transaction resumed
+    Resume {
+        id: u64,
+        mode: Mode,
+    },
+    // Cursor declared
+    Declare {
+        name: String,
+    },
+    // Cursor closed
+    Close {
+        name: String,
     },
     // Rows created
     Create {
@@ -108,6 +228,56 @@ pub enum ResultSet {
     DropTable {
         name: String,
     },
+    // Column added
+    AddColumn {
+        table: String,
+        column: String,
+    },
+    // Index created
+    CreateIndex {
+        table: String,
+        column: String,
+    },
+    // Column altered
+    AlterColumn {
+        table: String,
+        column: String,
+    },
+    // Comment set on a table or column
+    Comment {
+        table: String,
+        column: Option<String>,
+    },
+    // Cluster setting changed
+    SetSetting {
+        name: String,
+    },
+    // Function created
+    CreateFunction {
+        name: String,
+    },
+    // Function dropped
+    DropFunction {
+        name: String,
+    },
+    // Type created
+    CreateType {
+        name: String,
+    },
+    // Type dropped
+    DropType {
+        name: String,
+    },
+    // Policy created
+    CreatePolicy {
+        table: String,
+        name: String,
+    },
+    // Policy dropped
+    DropPolicy {
+        table: String,
+        name: String,
+    },
     // Query result
     Query {
         columns: Columns,
@@ -117,7 +287,7 @@ pub enum ResultSet {
         rows: Rows,
     },
     // Explain result
-    Explain(Node),
+    Explain(Explanation),
 }
 
 impl ResultSet {
@@ -1,9 +1,11 @@
 use super::super::engine::Transaction;
-use super::super::plan::Direction;
-use super::super::types::{Column, Expression, Row, Value};
+use super::super::plan::{Direction, Nulls};
+use super::super::types::{Column, Columns, Expression, Row, Value};
 use super::{Executor, ResultSet};
 use crate::error::{Error, Result};
 
+use std::sync::{Arc, Mutex};
+
 /// A filter executor
 pub struct Filter<T: Transaction> {
     source: Box<dyn Executor<T>>,
@@ -65,13 +67,14 @@ impl<T: Transaction> Executor<T> for Projection<T> {
                 .iter()
                 .enumerate()
                 .map(|(i, e)| {
+                    let mut column = match e {
+                        Expression::Field(i, _) => columns.get(*i).cloned().unwrap_or_default(),
+                        e => Column { datatype: e.datatype(&columns), ..Column::default() },
+                    };
                     if let Some(Some(label)) = labels.get(i) {
-                        Column { name: Some(label.clone()) }
-                    } else if let Expression::Field(i, _) = e {
-                        columns.get(*i).cloned().unwrap_or(Column { name: None })
-                    } else {
-                        Column { name: None }
+                        column.name = Some(label.clone());
                     }
+                    column
                 })
                 .collect();
             let rows = Box::new(rows.map(move |r| {
@@ -89,11 +92,14 @@ impl<T: Transaction> Executor<T> for Projection<T> {
 /// An ORDER BY executor
 pub struct Order<T: Transaction> {
     source: Box<dyn Executor<T>>,
-    order: Vec<(Expression, Direction)>,
+    order: Vec<(Expression, Direction, Nulls)>,
 }
 
 impl<T: Transaction> Order<T> {
-    pub fn new(source: Box<dyn Executor<T>>, order: Vec<(Expression, Direction)>) -> Box<Self> {
+    pub fn new(
+        source: Box<dyn Executor<T>>,
+        order: Vec<(Expression, Direction, Nulls)>,
+    ) -> Box<Self> {
         Box::new(Self { source, order })
     }
 }
@@ -113,7 +119,7 @@ impl<T: Transaction> Executor<T> for Order<T> {
                 let mut items = Vec::new();
                 while let Some(row) = rows.next().transpose()? {
                     let mut values = Vec::new();
-                    for (expr, _) in self.order.iter() {
+                    for (expr, _, _) in self.order.iter() {
                         values.push(expr.evaluate(Some(&row))?);
                     }
                     items.push(Item { row, values })
@@ -121,9 +127,22 @@ impl<T: Transaction> Executor<T> for Order<T> {
 
                 let order = &self.order;
                 items.sort_by(|a, b| {
-                    for (i, (_, order)) in order.iter().enumerate() {
+                    for (i, (_, order, nulls)) in order.iter().enumerate() {
                         let value_a = &a.values[i];
                         let value_b = &b.values[i];
+                        // NULL placement is independent of the sort direction.
+                        match (value_a, value_b) {
+                            (Value::Null, Value::Null) => continue,
+                            (Value::Null, _) if *nulls == Nulls::First => {
+                                return std::cmp::Ordering::Less
+                            }
+                            (Value::Null, _) => return std::cmp::Ordering::Greater,
+                            (_, Value::Null) if *nulls == Nulls::First => {
+                                return std::cmp::Ordering::Greater
+                            }
+                            (_, Value::Null) => return std::cmp::Ordering::Less,
+                            (_, _) => {}
+                        }
                         match value_a.partial_cmp(value_b) {
                             Some(std::cmp::Ordering::Equal) => {}
                             Some(o) => {
@@ -188,3 +207,38 @@ impl<T: Transaction> Executor<T> for Offset<T> {
         }
     }
 }
+
+/// A spool executor, which shares the result of its source with other spools of identical
+/// sources. The first spool to execute runs its source and buffers the result in the shared slot,
+/// while the others return the buffered result without running their source.
+pub struct Spool<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    slot: SpoolSlot,
+}
+
+impl<T: Transaction> Spool<T> {
+    pub fn new(source: Box<dyn Executor<T>>, slot: SpoolSlot) -> Box<Self> {
+        Box::new(Self { source, slot })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Spool<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut buffer = self.slot.lock()?;
+        if buffer.is_none() {
+            if let ResultSet::Query { columns, rows } = self.source.execute(txn)? {
+                *buffer = Some((columns, rows.collect::<Result<_>>()?));
+            }
+        }
+        match &*buffer {
+            Some((columns, rows)) => Ok(ResultSet::Query {
+                columns: columns.clone(),
+                rows: Box::new(rows.clone().into_iter().map(Ok)),
+            }),
+            None => Err(Error::Internal("Unexpected result".into())),
+        }
+    }
+}
+
+/// A shared slot for the buffered result of spools with the same id.
+pub type SpoolSlot = Arc<Mutex<Option<(Columns, Vec<Row>)>>>;
@@ -1,7 +1,10 @@
 use super::super::engine::Transaction;
-use super::super::schema::Table;
+use super::super::schema::{Column, ColumnAlteration, Function, Policy, Table};
+use super::super::types::{self, DataType, Enum, Row, Value};
 use super::{Executor, ResultSet};
-use crate::error::Result;
+use crate::error::{Error, Result};
+
+use std::collections::{HashMap, HashSet};
 
 /// A CREATE TABLE executor
 pub struct CreateTable {
@@ -39,3 +42,301 @@ impl<T: Transaction> Executor<T> for DropTable {
         Ok(ResultSet::DropTable { name: self.table })
     }
 }
+
+/// An ALTER TABLE ADD COLUMN executor. This only starts the schema change, which is then
+/// completed by the session in separate transactions.
+pub struct AddColumn {
+    table: String,
+    column: Column,
+}
+
+impl AddColumn {
+    pub fn new(table: String, column: Column) -> Box<Self> {
+        Box::new(Self { table, column })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AddColumn {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let column = self.column.name.clone();
+        txn.add_column(&self.table, self.column)?;
+        Ok(ResultSet::AddColumn { table: self.table, column })
+    }
+}
+
+/// An ALTER TABLE ALTER COLUMN executor, which alters a column's nullability or default value
+pub struct AlterColumn {
+    table: String,
+    column: String,
+    alteration: ColumnAlteration,
+}
+
+impl AlterColumn {
+    pub fn new(table: String, column: String, alteration: ColumnAlteration) -> Box<Self> {
+        Box::new(Self { table, column, alteration })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AlterColumn {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.alter_column(&self.table, &self.column, self.alteration)?;
+        Ok(ResultSet::AlterColumn { table: self.table, column: self.column })
+    }
+}
+
+/// A COMMENT ON TABLE or COLUMN executor. A None comment removes any existing comment.
+pub struct Comment {
+    table: String,
+    column: Option<String>,
+    comment: Option<String>,
+}
+
+impl Comment {
+    pub fn new(table: String, column: Option<String>, comment: Option<String>) -> Box<Self> {
+        Box::new(Self { table, column, comment })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Comment {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.set_comment(&self.table, self.column.as_deref(), self.comment)?;
+        Ok(ResultSet::Comment { table: self.table, column: self.column })
+    }
+}
+
+/// A SET CLUSTER SETTING executor. A None value resets the setting to its default.
+pub struct SetSetting {
+    name: String,
+    value: Option<Value>,
+}
+
+impl SetSetting {
+    pub fn new(name: String, value: Option<Value>) -> Box<Self> {
+        Box::new(Self { name, value })
+    }
+}
+
+impl<T: Transaction> Executor<T> for SetSetting {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.set_setting(&self.name, self.value)?;
+        Ok(ResultSet::SetSetting { name: self.name })
+    }
+}
+
+/// A CREATE FUNCTION executor
+pub struct CreateFunction {
+    function: Function,
+}
+
+impl CreateFunction {
+    pub fn new(function: Function) -> Box<Self> {
+        Box::new(Self { function })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateFunction {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.function.name.clone();
+        txn.create_function(self.function)?;
+        Ok(ResultSet::CreateFunction { name })
+    }
+}
+
+/// A DROP FUNCTION executor
+pub struct DropFunction {
+    name: String,
+}
+
+impl DropFunction {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropFunction {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_function(&self.name)?;
+        Ok(ResultSet::DropFunction { name: self.name })
+    }
+}
+
+/// A CREATE TYPE executor
+pub struct CreateType {
+    r#enum: Enum,
+}
+
+impl CreateType {
+    pub fn new(r#enum: Enum) -> Box<Self> {
+        Box::new(Self { r#enum })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateType {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.r#enum.name.clone();
+        txn.create_enum(self.r#enum)?;
+        Ok(ResultSet::CreateType { name })
+    }
+}
+
+/// A CREATE POLICY executor
+pub struct CreatePolicy {
+    table: String,
+    policy: Policy,
+}
+
+impl CreatePolicy {
+    pub fn new(table: String, policy: Policy) -> Box<Self> {
+        Box::new(Self { table, policy })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreatePolicy {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.policy.name.clone();
+        txn.create_policy(&self.table, self.policy)?;
+        Ok(ResultSet::CreatePolicy { table: self.table, name })
+    }
+}
+
+/// A DROP POLICY executor
+pub struct DropPolicy {
+    table: String,
+    name: String,
+}
+
+impl DropPolicy {
+    pub fn new(table: String, name: String) -> Box<Self> {
+        Box::new(Self { table, name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropPolicy {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_policy(&self.table, &self.name)?;
+        Ok(ResultSet::DropPolicy { table: self.table, name: self.name })
+    }
+}
+
+/// A DROP TYPE executor
+pub struct DropType {
+    name: String,
+}
+
+impl DropType {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropType {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_enum(&self.name)?;
+        Ok(ResultSet::DropType { name: self.name })
+    }
+}
+
+/// A CREATE INDEX executor. This only starts the schema change, which is then completed by the
+/// session in separate transactions.
+pub struct CreateIndex {
+    table: String,
+    column: String,
+}
+
+impl CreateIndex {
+    pub fn new(table: String, column: String) -> Box<Self> {
+        Box::new(Self { table, column })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.create_index(&self.table, &self.column)?;
+        Ok(ResultSet::CreateIndex { table: self.table, column: self.column })
+    }
+}
+
+/// A CHECK INDEX executor, which scans a table and its secondary indexes (or only the given
+/// column's index) in the transaction's snapshot, and emits a row for every inconsistent index
+/// entry: missing entries for rows, extra entries for nonexistent rows, and incorrect entries
+/// for rows with a different value.
+pub struct CheckIndex {
+    table: String,
+    column: Option<String>,
+}
+
+impl CheckIndex {
+    pub fn new(table: String, column: Option<String>) -> Box<Self> {
+        Box::new(Self { table, column })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CheckIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_read_table(&self.table)?;
+        let columns = match &self.column {
+            Some(column) => {
+                let column = table.get_column(column)?;
+                if !column.index {
+                    return Err(Error::Value(format!(
+                        "No index on {}.{}",
+                        table.name, column.name
+                    )));
+                }
+                vec![column]
+            }
+            None => table.columns.iter().filter(|c| c.index).collect(),
+        };
+
+        let rows = txn.scan(&table.name, None)?.collect::<Result<Vec<Row>>>()?;
+        let ids = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| Ok((table.get_row_key(row)?, i)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        // The value column has the indexed column's datatype, if only one column is checked.
+        let value_type = match columns.as_slice() {
+            [column] => Some(column.datatype.clone()),
+            _ => None,
+        };
+        let id_type = table.get_primary_key()?.datatype.clone();
+        let mut problems = Vec::new();
+        for column in columns {
+            let index = table.get_column_index(&column.name)?;
+            let problem = |value: &Value, id: &Value, problem: &str| -> Row {
+                vec![column.name.clone().into(), value.clone(), id.clone(), problem.into()]
+            };
+            let mut indexed = HashSet::new();
+            let mut scan = txn.scan_index(&table.name, &column.name)?;
+            while let Some((value, entries)) = scan.next().transpose()? {
+                let mut entries = entries.into_iter().collect::<Vec<_>>();
+                entries.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                for id in entries {
+                    match ids.get(&id).map(|i| &rows[*i][index]) {
+                        None => problems.push(problem(&value, &id, "extra")),
+                        Some(v) if v != &value => problems.push(problem(&value, &id, "incorrect")),
+                        Some(_) => {
+                            indexed.insert(id);
+                        }
+                    }
+                }
+            }
+            for row in &rows {
+                let id = table.get_row_key(row)?;
+                if !indexed.contains(&id) {
+                    problems.push(problem(&row[index], &id, "missing"));
+                }
+            }
+        }
+
+        Ok(ResultSet::Query {
+            columns: vec![
+                types::Column::new("column_name", DataType::String),
+                types::Column { name: Some("value".into()), table: None, datatype: value_type },
+                types::Column::new("id", id_type),
+                types::Column::new("problem", DataType::String),
+            ],
+            rows: Box::new(problems.into_iter().map(Ok)),
+        })
+    }
+}
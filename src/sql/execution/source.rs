@@ -1,7 +1,9 @@
-use super::super::engine::Transaction;
-use super::super::types::{Column, Expression, Row, Value};
+use super::super::engine::{Change, Transaction};
+use super::super::schema::{Table, CREATED_VERSION, UPDATED_VERSION};
+use super::super::types::{Column, DataType, Expression, Row, Value};
+use super::join::{RuntimeFilter, RuntimeFilterSlot};
 use super::{Executor, ResultSet};
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 use std::collections::HashSet;
 
@@ -9,21 +11,73 @@ use std::collections::HashSet;
 pub struct Scan {
     table: String,
     filter: Option<Expression>,
+    partitions: Option<Vec<String>>,
+    runtime_filter: Option<(usize, RuntimeFilterSlot)>,
 }
 
 impl Scan {
     pub fn new(table: String, filter: Option<Expression>) -> Box<Self> {
-        Box::new(Self { table, filter })
+        Box::new(Self { table, filter, partitions: None, runtime_filter: None })
+    }
+
+    /// Only scans the given partitions of the table, in order. Each partition is scanned
+    /// independently, with the filter applied to each.
+    pub fn with_partitions(mut self: Box<Self>, partitions: Vec<String>) -> Box<Self> {
+        self.partitions = Some(partitions);
+        self
+    }
+
+    /// Sets a runtime filter slot for the given field, which is applied to the scan if it has
+    /// been filled when the scan is executed.
+    pub fn with_runtime_filter(
+        mut self: Box<Self>,
+        field: usize,
+        slot: RuntimeFilterSlot,
+    ) -> Box<Self> {
+        self.runtime_filter = Some((field, slot));
+        self
     }
 }
 
 impl<T: Transaction> Executor<T> for Scan {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
-        Ok(ResultSet::Query {
-            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
-            rows: Box::new(txn.scan(&table.name, self.filter)?),
-        })
+        let mut filter = self.filter;
+        let mut bloom = None;
+        if let Some((field, slot)) = self.runtime_filter {
+            match slot.lock()?.take() {
+                Some(RuntimeFilter::In(keys)) => {
+                    let lookup = Expression::from_lookup(field, None, keys);
+                    filter = Some(match filter {
+                        Some(filter) => Expression::And(filter.into(), lookup.into()),
+                        None => lookup,
+                    });
+                }
+                Some(RuntimeFilter::Bloom(filter)) => bloom = Some((field, filter)),
+                None => {}
+            }
+        }
+        let mut rows = match self.partitions {
+            Some(partitions) => {
+                let mut scans = Vec::new();
+                for partition in partitions {
+                    scans.push(txn.scan_partition(&table.name, &partition, filter.clone())?);
+                }
+                Box::new(scans.into_iter().flatten())
+            }
+            None => txn.scan(&table.name, filter)?,
+        };
+        if let Some((field, bloom)) = bloom {
+            rows = Box::new(rows.filter(move |r| match r {
+                Ok(row) => match row.get(field) {
+                    Some(Value::Null) => false,
+                    Some(value) => bloom.contains(value),
+                    None => true,
+                },
+                Err(_) => true,
+            }));
+        }
+        Ok(ResultSet::Query { columns: table.result_columns(), rows: Box::new(rows) })
     }
 }
 
@@ -51,7 +105,7 @@ impl<T: Transaction> Executor<T> for KeyLookup {
             .collect::<Result<Vec<Row>>>()?;
 
         Ok(ResultSet::Query {
-            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            columns: table.result_columns(),
             rows: Box::new(rows.into_iter().map(Ok)),
         })
     }
@@ -86,7 +140,43 @@ impl<T: Transaction> Executor<T> for IndexLookup {
             .collect::<Result<Vec<Row>>>()?;
 
         Ok(ResultSet::Query {
-            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            columns: table.result_columns(),
+            rows: Box::new(rows.into_iter().map(Ok)),
+        })
+    }
+}
+
+/// An index range scan executor, which reads the rows of the index entries between two values
+pub struct IndexRange {
+    table: String,
+    column: String,
+    lower: Value,
+    upper: Value,
+}
+
+impl IndexRange {
+    pub fn new(table: String, column: String, lower: Value, upper: Value) -> Box<Self> {
+        Box::new(Self { table, column, lower, upper })
+    }
+}
+
+impl<T: Transaction> Executor<T> for IndexRange {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_read_table(&self.table)?;
+
+        let mut pks: HashSet<Value> = HashSet::new();
+        for entry in txn.scan_index_range(&self.table, &self.column, &self.lower, &self.upper)? {
+            pks.extend(entry?.1);
+        }
+
+        // FIXME Is there a way to pass the txn into an iterator closure instead?
+        let rows = pks
+            .into_iter()
+            .filter_map(|pk| txn.read(&table.name, &pk).transpose())
+            .collect::<Result<Vec<Row>>>()?;
+
+        Ok(ResultSet::Query {
+            columns: table.result_columns(),
             rows: Box::new(rows.into_iter().map(Ok)),
         })
     }
@@ -109,3 +199,131 @@ impl<T: Transaction> Executor<T> for Nothing {
         })
     }
 }
+
+/// A changefeed executor, which emits committed row changes for a table in commit order,
+/// followed by a resolved row giving the commit position to fetch subsequent changes from.
+pub struct Changefeed {
+    table: String,
+    from: u64,
+}
+
+impl Changefeed {
+    pub fn new(table: String, from: u64) -> Box<Self> {
+        Box::new(Self { table, from })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Changefeed {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_read_table(&self.table)?;
+        let (changes, resolved) = txn.changes(&table.name, self.from)?;
+        let mut rows = changes_to_rows(&table, changes)?;
+        let mut row = vec![Value::Integer(resolved as i64), Value::String("resolved".into())];
+        row.extend(std::iter::repeat_n(Value::Null, table.columns.len()));
+        rows.push(row);
+        Ok(ResultSet::Query {
+            columns: change_columns(&table),
+            rows: Box::new(rows.into_iter().map(Ok)),
+        })
+    }
+}
+
+/// A row history executor, which emits all committed versions of a row ordered by version
+pub struct History {
+    table: String,
+    key: Value,
+}
+
+impl History {
+    pub fn new(table: String, key: Value) -> Box<Self> {
+        Box::new(Self { table, key })
+    }
+}
+
+impl<T: Transaction> Executor<T> for History {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_read_table(&self.table)?;
+        let rows = changes_to_rows(&table, txn.history(&table.name, &self.key)?)?;
+        Ok(ResultSet::Query {
+            columns: change_columns(&table),
+            rows: Box::new(rows.into_iter().map(Ok)),
+        })
+    }
+}
+
+/// A row versions executor, which appends the versions that created and last updated each row
+/// emitted by a scan or lookup of the given table, as the system columns _created_version and
+/// _updated_version.
+pub struct RowVersions<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    table: String,
+}
+
+impl<T: Transaction> RowVersions<T> {
+    pub fn new(source: Box<dyn Executor<T>>, table: String) -> Box<Self> {
+        Box::new(Self { source, table })
+    }
+}
+
+impl<T: Transaction> Executor<T> for RowVersions<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_read_table(&self.table)?;
+        if let ResultSet::Query { mut columns, rows } = self.source.execute(txn)? {
+            // The rows are fetched eagerly, since looking up versions needs the transaction.
+            let mut output = Vec::new();
+            for row in rows {
+                let mut row = row?;
+                let versions = txn.row_versions(&table.name, &table.get_row_key(&row)?)?;
+                row.extend(match versions {
+                    Some((created, updated)) => {
+                        vec![Value::Integer(created as i64), Value::Integer(updated as i64)]
+                    }
+                    None => vec![Value::Null, Value::Null],
+                });
+                output.push(row);
+            }
+            for name in &[CREATED_VERSION, UPDATED_VERSION] {
+                columns.push(Column {
+                    name: Some(name.to_string()),
+                    table: Some(table.name.clone()),
+                    datatype: Some(DataType::Integer),
+                });
+            }
+            Ok(ResultSet::Query { columns, rows: Box::new(output.into_iter().map(Ok)) })
+        } else {
+            Err(Error::Internal("Unexpected result".into()))
+        }
+    }
+}
+
+/// Returns the result columns for row changes: version, operation, and the table's columns.
+fn change_columns(table: &Table) -> Vec<Column> {
+    let mut columns =
+        vec![Column::new("version", DataType::Integer), Column::new("operation", DataType::String)];
+    columns.extend(table.result_columns());
+    columns
+}
+
+/// Converts row changes into result rows, see change_columns(). Deleted rows only have the
+/// primary key set.
+fn changes_to_rows(table: &Table, changes: Vec<Change>) -> Result<Vec<Row>> {
+    let pk = table.get_column_index(&table.get_primary_key()?.name)?;
+    Ok(changes
+        .into_iter()
+        .map(|change| {
+            let mut row = vec![Value::Integer(change.version as i64)];
+            match change.row {
+                Some(r) => {
+                    row.push(Value::String("upsert".into()));
+                    row.extend(r);
+                }
+                None => {
+                    row.push(Value::String("delete".into()));
+                    row.extend(std::iter::repeat_n(Value::Null, table.columns.len()));
+                    row[2 + pk] = change.id;
+                }
+            }
+            row
+        })
+        .collect())
+}
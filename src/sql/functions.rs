@@ -0,0 +1,283 @@
+//! Custom scalar functions, which embedders can register to extend the SQL expression language
+//! with functions implemented in Rust. For example:
+//!
+//! ```
+//! use toydb::sql::functions::{self, ScalarFunction};
+//! use toydb::sql::types::{DataType, Value};
+//!
+//! functions::register(ScalarFunction::new(
+//!     "reverse",
+//!     vec![DataType::String],
+//!     DataType::String,
+//!     |args| match &args[0] {
+//!         Value::String(s) => Ok(Value::String(s.chars().rev().collect())),
+//!         _ => unreachable!("arguments have the parameter datatypes"),
+//!     },
+//! ))?;
+//! # Ok::<(), toydb::error::Error>(())
+//! ```
+//!
+//! Functions are registered for the entire process rather than for an engine, since expressions
+//! may be evaluated by the storage engine, e.g. pushed-down scan filters, which for the Raft engine
+//! is done by the state machine on every node. The same functions must therefore be registered
+//! on all nodes before serving queries. Calls are planned as Expression::Call, which refers to
+//! the function by name and looks it up when evaluated. Functions must be deterministic, since
+//! calls with constant arguments are evaluated once during planning.
+//!
+//! The built-in geospatial functions are also scalar functions, and are always registered:
+//!
+//! * point(x, y): a point with the given coordinates.
+//! * point_x(point), point_y(point): the coordinates of a point.
+//! * distance(a, b): the Euclidean distance between two points.
+//! * contains(a, b, point): whether a point is within the bounding box with corners a and b,
+//!   inclusive.
+//!
+//! The optimizer uses indexes on POINT columns for contains() and distance() filters, see
+//! Expression::as_bounding_box().
+use super::plan::Aggregate;
+use super::types::{DataType, Value};
+use crate::error::{Error, Result};
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    /// The registered scalar functions, by name
+    static ref FUNCTIONS: RwLock<HashMap<String, Arc<ScalarFunction>>> =
+        RwLock::new(builtins());
+}
+
+/// Returns the built-in functions, by name
+fn builtins() -> HashMap<String, Arc<ScalarFunction>> {
+    use DataType::{Boolean, Float, Point};
+    vec![
+        ScalarFunction::new("point", vec![Float, Float], Point, |args| match args {
+            [Value::Float(x), Value::Float(y)] => Ok(Value::Point(*x, *y)),
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        }),
+        ScalarFunction::new("point_x", vec![Point], Float, |args| match args {
+            [Value::Point(x, _)] => Ok(Value::Float(*x)),
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        }),
+        ScalarFunction::new("point_y", vec![Point], Float, |args| match args {
+            [Value::Point(_, y)] => Ok(Value::Float(*y)),
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        }),
+        ScalarFunction::new("distance", vec![Point, Point], Float, |args| match args {
+            [Value::Point(ax, ay), Value::Point(bx, by)] => {
+                Ok(Value::Float((ax - bx).hypot(ay - by)))
+            }
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        }),
+        ScalarFunction::new("contains", vec![Point, Point, Point], Boolean, |args| match args {
+            [Value::Point(ax, ay), Value::Point(bx, by), Value::Point(x, y)] => Ok(Value::Boolean(
+                *x >= ax.min(*bx) && *x <= ax.max(*bx) && *y >= ay.min(*by) && *y <= ay.max(*by),
+            )),
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        }),
+    ]
+    .into_iter()
+    .map(|f| (f.name.clone(), Arc::new(f)))
+    .collect()
+}
+
+/// A function evaluator, which is given the evaluated arguments of a call
+type Evaluator = Box<dyn Fn(&[Value]) -> Result<Value> + Send + Sync>;
+
+/// A custom scalar function
+pub struct ScalarFunction {
+    /// The function name, which is case-insensitive like other identifiers
+    pub name: String,
+    /// The datatypes of the function parameters
+    pub parameters: Vec<DataType>,
+    /// The datatype of the function's return values
+    pub returns: DataType,
+    evaluator: Evaluator,
+}
+
+impl ScalarFunction {
+    /// Creates a new scalar function. The evaluator is given arguments of the parameter
+    /// datatypes, and must return a value of the return datatype.
+    pub fn new<F>(name: &str, parameters: Vec<DataType>, returns: DataType, evaluator: F) -> Self
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        Self { name: name.to_lowercase(), parameters, returns, evaluator: Box::new(evaluator) }
+    }
+
+    /// Calls the function with the given arguments. Calls with NULL arguments return NULL
+    /// without calling the evaluator, and integers are converted to floats for FLOAT parameters.
+    pub fn call(&self, mut args: Vec<Value>) -> Result<Value> {
+        if args.len() != self.parameters.len() {
+            return Err(Error::Value(format!(
+                "Function {} takes {} arguments, given {}",
+                self.name,
+                self.parameters.len(),
+                args.len()
+            )));
+        }
+        for (i, (arg, datatype)) in args.iter_mut().zip(&self.parameters).enumerate() {
+            match (&*arg, datatype.value_type()) {
+                (Value::Null, _) => return Ok(Value::Null),
+                (Value::Integer(int), DataType::Float) => {
+                    let float = *int as f64;
+                    *arg = Value::Float(float)
+                }
+                (arg, datatype) if !datatype.accepts(arg) => {
+                    return Err(Error::Value(format!(
+                        "Invalid argument {} for parameter {} of function {}, expected {}",
+                        arg,
+                        i + 1,
+                        self.name,
+                        datatype
+                    )))
+                }
+                _ => {}
+            }
+        }
+        match (self.evaluator)(&args)? {
+            Value::Integer(int) if self.returns.value_type() == DataType::Float => {
+                Ok(Value::Float(int as f64))
+            }
+            value
+                if value == Value::Null || self.returns.accepts(&value) =>
+            {
+                Ok(value)
+            }
+            value => Err(Error::Value(format!(
+                "Function {} returned invalid value {}, expected {}",
+                self.name, value, self.returns
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScalarFunction")
+            .field("name", &self.name)
+            .field("parameters", &self.parameters)
+            .field("returns", &self.returns)
+            .finish()
+    }
+}
+
+/// Registers a scalar function. Errors if a function with the same name is already registered,
+/// or if the name is an aggregate function.
+pub fn register(function: ScalarFunction) -> Result<()> {
+    if Aggregate::from_name(&function.name).is_some() {
+        return Err(Error::Value(format!(
+            "Function name {} is reserved for an aggregate function",
+            function.name
+        )));
+    }
+    let mut functions = FUNCTIONS.write()?;
+    if functions.contains_key(&function.name) {
+        return Err(Error::Value(format!("Function {} is already registered", function.name)));
+    }
+    functions.insert(function.name.clone(), Arc::new(function));
+    Ok(())
+}
+
+/// Looks up a registered scalar function by name
+pub fn get(name: &str) -> Result<Option<Arc<ScalarFunction>>> {
+    Ok(FUNCTIONS.read()?.get(name).cloned())
+}
+
+/// Calls a registered scalar function by name, erroring if it isn't registered
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value> {
+    get(name)?.ok_or_else(|| Error::Value(format!("Unknown function {}", name)))?.call(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call() -> Result<()> {
+        let half =
+            ScalarFunction::new(
+                "Half",
+                vec![DataType::Float],
+                DataType::Float,
+                |args| match args {
+                    [Value::Float(f)] => Ok(Value::Float(f / 2.0)),
+                    _ => Err(Error::Internal("Unexpected arguments".into())),
+                },
+            );
+        assert_eq!(half.name, "half");
+        assert_eq!(half.call(vec![Value::Float(3.0)])?, Value::Float(1.5));
+        assert_eq!(half.call(vec![Value::Integer(3)])?, Value::Float(1.5));
+        assert_eq!(half.call(vec![Value::Null])?, Value::Null);
+        assert_eq!(
+            half.call(vec![Value::String("3".into())]),
+            Err(Error::Value(
+                "Invalid argument 3 for parameter 1 of function half, expected FLOAT".into()
+            ))
+        );
+        assert_eq!(
+            half.call(vec![]),
+            Err(Error::Value("Function half takes 1 arguments, given 0".into()))
+        );
+
+        let invalid =
+            ScalarFunction::new("invalid", vec![], DataType::Integer, |_| Ok(Value::Boolean(true)));
+        assert_eq!(
+            invalid.call(vec![]),
+            Err(Error::Value(
+                "Function invalid returned invalid value TRUE, expected INTEGER".into()
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn builtins() -> Result<()> {
+        let (a, b) = (Value::Point(0.0, 0.0), Value::Point(3.0, -4.0));
+        assert_eq!(super::call("point", vec![3.into(), (-4.0).into()])?, b);
+        assert_eq!(super::call("point_x", vec![b.clone()])?, Value::Float(3.0));
+        assert_eq!(super::call("point_y", vec![b.clone()])?, Value::Float(-4.0));
+        assert_eq!(super::call("distance", vec![a.clone(), b.clone()])?, Value::Float(5.0));
+        assert_eq!(
+            super::call("contains", vec![b.clone(), a.clone(), Value::Point(1.0, -1.0)])?,
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            super::call("contains", vec![a.clone(), b.clone(), Value::Point(1.0, 1.0)])?,
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            super::register(ScalarFunction::new("distance", vec![], DataType::Float, |_| Ok(
+                Value::Null
+            ))),
+            Err(Error::Value("Function distance is already registered".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn register() -> Result<()> {
+        let one = || {
+            ScalarFunction::new("test_one", vec![], DataType::Integer, |_| Ok(Value::Integer(1)))
+        };
+        super::register(one())?;
+        assert_eq!(super::call("test_one", vec![])?, Value::Integer(1));
+        assert_eq!(
+            super::register(one()),
+            Err(Error::Value("Function test_one is already registered".into()))
+        );
+        assert_eq!(
+            super::register(ScalarFunction::new("sum", vec![], DataType::Integer, |_| Ok(
+                Value::Null
+            ))),
+            Err(Error::Value("Function name sum is reserved for an aggregate function".into()))
+        );
+        assert_eq!(
+            super::call("test_missing", vec![]),
+            Err(Error::Value("Unknown function test_missing".into()))
+        );
+        Ok(())
+    }
+}
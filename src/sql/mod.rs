@@ -1,6 +1,8 @@
 pub mod engine;
 pub mod execution;
+pub mod functions;
 pub mod parser;
 pub mod plan;
 pub mod schema;
+pub mod settings;
 pub mod types;
@@ -1,3 +1,4 @@
+use super::super::engine::Priority;
 use super::super::types::DataType;
 use crate::error::Result;
 
@@ -11,16 +12,96 @@ pub enum Statement {
     Begin {
         readonly: bool,
         version: Option<u64>,
+        priority: Option<Priority>,
+    },
+    Commit {
+        chain: bool,
+    },
+    Rollback {
+        chain: bool,
+    },
+    Detach,
+    Resume(u64),
+    ShowTransaction,
+    ShowCreateTable(String),
+    Explain {
+        statement: Box<Statement>,
+        format: ExplainFormat,
+        verbose: bool,
+    },
+
+    Declare {
+        name: String,
+        query: Box<Statement>,
+    },
+    Fetch {
+        name: String,
+        count: u64,
+    },
+    Close(String),
+
+    Changefeed {
+        table: String,
+        from: Option<u64>,
+    },
+    History {
+        table: String,
+        key: Expression,
+    },
+    CheckIndex {
+        table: String,
+        column: Option<String>,
     },
-    Commit,
-    Rollback,
-    Explain(Box<Statement>),
 
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        partition_by: Option<PartitionBy>,
     },
     DropTable(String),
+    AddColumn {
+        table: String,
+        column: Column,
+    },
+    CreateIndex {
+        table: String,
+        column: String,
+    },
+    AlterColumn {
+        table: String,
+        column: String,
+        alteration: ColumnAlteration,
+    },
+    Comment {
+        table: String,
+        column: Option<String>,
+        comment: Option<String>,
+    },
+    SetSetting {
+        name: String,
+        value: Option<Expression>,
+    },
+    CreateFunction {
+        name: String,
+        parameters: Vec<(String, DataType)>,
+        body: String,
+    },
+    DropFunction(String),
+    CreateType {
+        name: String,
+        labels: Vec<String>,
+    },
+    DropType(String),
+    CreatePolicy {
+        name: String,
+        table: String,
+        role: String,
+        predicate: Expression,
+    },
+    DropPolicy {
+        name: String,
+        table: String,
+    },
 
     Delete {
         table: String,
@@ -43,12 +124,22 @@ pub enum Statement {
         r#where: Option<Expression>,
         group_by: Vec<Expression>,
         having: Option<Expression>,
-        order: Vec<(Expression, Order)>,
+        order: Vec<(Expression, Order, Option<Nulls>)>,
         offset: Option<Expression>,
         limit: Option<Expression>,
+        hints: Vec<Hint>,
     },
 }
 
+/// Query hints, given as /*+ */ comments after SELECT
+#[derive(Clone, Debug, PartialEq)]
+pub enum Hint {
+    NoIndex(Vec<String>),
+    JoinOrder(Vec<String>),
+    AuditOuterJoins,
+    WrappingArithmetic,
+}
+
 /// A FROM item
 #[derive(Clone, Debug, PartialEq)]
 pub enum FromItem {
@@ -86,6 +177,37 @@ pub struct Column {
     pub references: Option<String>,
 }
 
+/// A PARTITION BY RANGE clause, partitioning a table by ranges of a column
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionBy {
+    pub column: String,
+    pub partitions: Vec<Partition>,
+}
+
+/// A range partition, with its exclusive upper bound, or None for MAXVALUE
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub name: String,
+    pub upper: Option<Expression>,
+}
+
+/// An ALTER COLUMN alteration
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnAlteration {
+    SetNotNull,
+    DropNotNull,
+    SetDefault(Expression),
+    DropDefault,
+}
+
+/// EXPLAIN output formats
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExplainFormat {
+    Text,
+    Json,
+    Dot,
+}
+
 /// Sort orders
 #[derive(Clone, Debug, PartialEq)]
 pub enum Order {
@@ -93,14 +215,25 @@ pub enum Order {
     Descending,
 }
 
+/// Sort placement of NULL values
+#[derive(Clone, Debug, PartialEq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
 /// Expressions
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
     Field(Option<String>, String),
     Column(usize), // only used during plan building to break off expression subtrees
     Literal(Literal),
+    Parameter(usize, Option<DataType>), // a ? bind parameter by 0-based position, and ::type if any
     Function(String, Vec<Expression>),
     Operation(Operation),
+    Array(Vec<Expression>), // an ARRAY[...] literal
+    Any(Box<Expression>),   // ANY(array), only on the right-hand side of a comparison
+    All(Box<Expression>),   // ALL(array), only on the right-hand side of a comparison
 }
 
 impl From<Literal> for Expression {
@@ -155,9 +288,17 @@ pub enum Operation {
 
     // String operators
     Like(Box<Expression>, Box<Expression>),
+
+    // Array operators
+    Element(Box<Expression>, Box<Expression>),
 }
 
 impl Expression {
+    /// Returns true if the expression is an ANY() or ALL() array quantifier.
+    pub fn is_quantified(&self) -> bool {
+        matches!(self, Self::Any(_) | Self::All(_))
+    }
+
     /// Walks the expression tree while calling a closure. Returns true as soon as the closure
     /// returns true. This is the inverse of walk().
     pub fn contains<F: Fn(&Expression) -> bool>(&self, visitor: &F) -> bool {
@@ -185,6 +326,7 @@ impl Expression {
             Self::Operation(Add(lhs, rhs))
             | Self::Operation(And(lhs, rhs))
             | Self::Operation(Divide(lhs, rhs))
+            | Self::Operation(Element(lhs, rhs))
             | Self::Operation(Equal(lhs, rhs))
             | Self::Operation(Exponentiate(lhs, rhs))
             | Self::Operation(GreaterThan(lhs, rhs))
@@ -205,17 +347,17 @@ impl Expression {
             | Self::Operation(Factorial(expr))
             | Self::Operation(IsNull(expr))
             | Self::Operation(Negate(expr))
-            | Self::Operation(Not(expr)) => {
-                Self::replace_with(expr, |e| e.transform(before, after))?
-            }
+            | Self::Operation(Not(expr))
+            | Self::Any(expr)
+            | Self::All(expr) => Self::replace_with(expr, |e| e.transform(before, after))?,
 
-            Self::Function(_, exprs) => {
+            Self::Function(_, exprs) | Self::Array(exprs) => {
                 for expr in exprs {
                     Self::replace_with(expr, |e| e.transform(before, after))?;
                 }
             }
 
-            Self::Literal(_) | Self::Field(_, _) | Self::Column(_) => {}
+            Self::Literal(_) | Self::Parameter(..) | Self::Field(_, _) | Self::Column(_) => {}
         };
         after(self)
     }
@@ -237,6 +379,7 @@ impl Expression {
                 Self::Operation(Add(lhs, rhs))
                 | Self::Operation(And(lhs, rhs))
                 | Self::Operation(Divide(lhs, rhs))
+                | Self::Operation(Element(lhs, rhs))
                 | Self::Operation(Equal(lhs, rhs))
                 | Self::Operation(Exponentiate(lhs, rhs))
                 | Self::Operation(GreaterThan(lhs, rhs))
@@ -254,9 +397,11 @@ impl Expression {
                 | Self::Operation(Factorial(expr))
                 | Self::Operation(IsNull(expr))
                 | Self::Operation(Negate(expr))
-                | Self::Operation(Not(expr)) => expr.walk(visitor),
+                | Self::Operation(Not(expr))
+                | Self::Any(expr)
+                | Self::All(expr) => expr.walk(visitor),
 
-                Self::Function(_, exprs) => {
+                Self::Function(_, exprs) | Self::Array(exprs) => {
                     for expr in exprs {
                         if !expr.walk(visitor) {
                             return false;
@@ -265,7 +410,9 @@ impl Expression {
                     true
                 }
 
-                Self::Literal(_) | Self::Field(_, _) | Self::Column(_) => true,
+                Self::Literal(_) | Self::Parameter(..) | Self::Field(_, _) | Self::Column(_) => {
+                    true
+                }
             }
     }
 }
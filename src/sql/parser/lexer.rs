@@ -26,10 +26,15 @@ pub enum Token {
     Exclamation,
     NotEqual,
     Question,
+    Colon,
+    DoubleColon,
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
     Comma,
     Semicolon,
+    Hint(String),
 }
 
 impl std::fmt::Display for Token {
@@ -55,10 +60,15 @@ impl std::fmt::Display for Token {
             Token::Exclamation => "!",
             Token::NotEqual => "!=",
             Token::Question => "?",
+            Token::Colon => ":",
+            Token::DoubleColon => "::",
             Token::OpenParen => "(",
             Token::CloseParen => ")",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
             Token::Comma => ",",
             Token::Semicolon => ";",
+            Token::Hint(h) => return write!(f, "/*+ {} */", h),
         })
     }
 }
@@ -72,28 +82,47 @@ impl From<Keyword> for Token {
 /// Lexer keywords
 #[derive(Clone, Debug, PartialEq)]
 pub enum Keyword {
+    Add,
+    All,
+    Alter,
     And,
+    Any,
+    Array,
     As,
     Asc,
     Begin,
+    BigInt,
     Bool,
     Boolean,
     By,
+    Chain,
+    Changefeed,
     Char,
+    Check,
+    Close,
+    Column,
+    Comment,
     Commit,
     Create,
     Cross,
+    Cursor,
+    Declare,
     Default,
     Delete,
     Desc,
+    Detach,
     Double,
     Drop,
     Explain,
     False,
+    Fetch,
+    First,
     Float,
+    For,
     From,
     Group,
     Having,
+    History,
     Index,
     Infinity,
     Inner,
@@ -104,12 +133,16 @@ pub enum Keyword {
     Is,
     Join,
     Key,
+    Last,
     Left,
+    Less,
     Like,
     Limit,
+    MaxValue,
     NaN,
     Not,
     Null,
+    Nulls,
     Of,
     Offset,
     On,
@@ -117,17 +150,22 @@ pub enum Keyword {
     Or,
     Order,
     Outer,
+    Partition,
     Primary,
+    Range,
     Read,
     References,
+    Resume,
     Right,
     Rollback,
     Select,
     Set,
+    Show,
     String,
     System,
     Table,
     Text,
+    Than,
     Time,
     Transaction,
     True,
@@ -143,28 +181,47 @@ impl Keyword {
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(ident: &str) -> Option<Self> {
         Some(match ident.to_uppercase().as_ref() {
+            "ADD" => Self::Add,
+            "ALL" => Self::All,
+            "ALTER" => Self::Alter,
             "AS" => Self::As,
             "ASC" => Self::Asc,
             "AND" => Self::And,
+            "ANY" => Self::Any,
+            "ARRAY" => Self::Array,
             "BEGIN" => Self::Begin,
+            "BIGINT" => Self::BigInt,
             "BOOL" => Self::Bool,
             "BOOLEAN" => Self::Boolean,
             "BY" => Self::By,
+            "CHAIN" => Self::Chain,
+            "CHANGEFEED" => Self::Changefeed,
             "CHAR" => Self::Char,
+            "CHECK" => Self::Check,
+            "CLOSE" => Self::Close,
+            "COLUMN" => Self::Column,
+            "COMMENT" => Self::Comment,
             "COMMIT" => Self::Commit,
             "CREATE" => Self::Create,
             "CROSS" => Self::Cross,
+            "CURSOR" => Self::Cursor,
+            "DECLARE" => Self::Declare,
             "DEFAULT" => Self::Default,
             "DELETE" => Self::Delete,
             "DESC" => Self::Desc,
+            "DETACH" => Self::Detach,
             "DOUBLE" => Self::Double,
             "DROP" => Self::Drop,
             "EXPLAIN" => Self::Explain,
             "FALSE" => Self::False,
+            "FETCH" => Self::Fetch,
+            "FIRST" => Self::First,
             "FLOAT" => Self::Float,
+            "FOR" => Self::For,
             "FROM" => Self::From,
             "GROUP" => Self::Group,
             "HAVING" => Self::Having,
+            "HISTORY" => Self::History,
             "INDEX" => Self::Index,
             "INFINITY" => Self::Infinity,
             "INNER" => Self::Inner,
@@ -175,12 +232,16 @@ impl Keyword {
             "IS" => Self::Is,
             "JOIN" => Self::Join,
             "KEY" => Self::Key,
+            "LAST" => Self::Last,
             "LEFT" => Self::Left,
+            "LESS" => Self::Less,
             "LIKE" => Self::Like,
             "LIMIT" => Self::Limit,
+            "MAXVALUE" => Self::MaxValue,
             "NAN" => Self::NaN,
             "NOT" => Self::Not,
             "NULL" => Self::Null,
+            "NULLS" => Self::Nulls,
             "OF" => Self::Of,
             "OFFSET" => Self::Offset,
             "ON" => Self::On,
@@ -188,17 +249,22 @@ impl Keyword {
             "OR" => Self::Or,
             "ORDER" => Self::Order,
             "OUTER" => Self::Outer,
+            "PARTITION" => Self::Partition,
             "PRIMARY" => Self::Primary,
+            "RANGE" => Self::Range,
             "READ" => Self::Read,
             "REFERENCES" => Self::References,
+            "RESUME" => Self::Resume,
             "RIGHT" => Self::Right,
             "ROLLBACK" => Self::Rollback,
             "SELECT" => Self::Select,
             "SET" => Self::Set,
+            "SHOW" => Self::Show,
             "STRING" => Self::String,
             "SYSTEM" => Self::System,
             "TABLE" => Self::Table,
             "TEXT" => Self::Text,
+            "THAN" => Self::Than,
             "TIME" => Self::Time,
             "TRANSACTION" => Self::Transaction,
             "TRUE" => Self::True,
@@ -214,28 +280,47 @@ impl Keyword {
 
     pub fn to_str(&self) -> &str {
         match self {
+            Self::Add => "ADD",
+            Self::All => "ALL",
+            Self::Alter => "ALTER",
             Self::As => "AS",
             Self::Asc => "ASC",
             Self::And => "AND",
+            Self::Any => "ANY",
+            Self::Array => "ARRAY",
             Self::Begin => "BEGIN",
+            Self::BigInt => "BIGINT",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
+            Self::Chain => "CHAIN",
+            Self::Changefeed => "CHANGEFEED",
             Self::Char => "CHAR",
+            Self::Check => "CHECK",
+            Self::Close => "CLOSE",
+            Self::Column => "COLUMN",
+            Self::Comment => "COMMENT",
             Self::Commit => "COMMIT",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
+            Self::Cursor => "CURSOR",
+            Self::Declare => "DECLARE",
             Self::Default => "DEFAULT",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
+            Self::Detach => "DETACH",
             Self::Double => "DOUBLE",
             Self::Drop => "DROP",
             Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
+            Self::Fetch => "FETCH",
+            Self::First => "FIRST",
             Self::Float => "FLOAT",
+            Self::For => "FOR",
             Self::From => "FROM",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
+            Self::History => "HISTORY",
             Self::Index => "INDEX",
             Self::Infinity => "INFINITY",
             Self::Inner => "INNER",
@@ -246,12 +331,16 @@ impl Keyword {
             Self::Is => "IS",
             Self::Join => "JOIN",
             Self::Key => "KEY",
+            Self::Last => "LAST",
             Self::Left => "LEFT",
+            Self::Less => "LESS",
             Self::Like => "LIKE",
             Self::Limit => "LIMIT",
+            Self::MaxValue => "MAXVALUE",
             Self::NaN => "NAN",
             Self::Not => "NOT",
             Self::Null => "NULL",
+            Self::Nulls => "NULLS",
             Self::Of => "OF",
             Self::Offset => "OFFSET",
             Self::On => "ON",
@@ -259,17 +348,22 @@ impl Keyword {
             Self::Outer => "OUTER",
             Self::Or => "OR",
             Self::Order => "ORDER",
+            Self::Partition => "PARTITION",
             Self::Primary => "PRIMARY",
+            Self::Range => "RANGE",
             Self::Read => "READ",
             Self::References => "REFERENCES",
+            Self::Resume => "RESUME",
             Self::Right => "RIGHT",
             Self::Rollback => "ROLLBACK",
             Self::Select => "SELECT",
             Self::Set => "SET",
+            Self::Show => "SHOW",
             Self::String => "STRING",
             Self::System => "SYSTEM",
             Self::Table => "TABLE",
             Self::Text => "TEXT",
+            Self::Than => "THAN",
             Self::Time => "TIME",
             Self::Transaction => "TRANSACTION",
             Self::True => "TRUE",
@@ -281,6 +375,57 @@ impl Keyword {
             Self::Write => "WRITE",
         }
     }
+
+    /// Returns true if the keyword is reserved, i.e. can't be used as an unquoted identifier.
+    /// Non-reserved keywords are only used in positions where an identifier can't appear, e.g.
+    /// statement prefixes and data types, and are otherwise parsed as (lowercase) identifiers.
+    pub fn is_reserved(&self) -> bool {
+        !matches!(
+            self,
+            Self::Add
+                | Self::Begin
+                | Self::BigInt
+                | Self::Bool
+                | Self::Boolean
+                | Self::Chain
+                | Self::Changefeed
+                | Self::Char
+                | Self::Close
+                | Self::Comment
+                | Self::Commit
+                | Self::Cursor
+                | Self::Declare
+                | Self::Detach
+                | Self::Double
+                | Self::Explain
+                | Self::Fetch
+                | Self::First
+                | Self::Float
+                | Self::History
+                | Self::Int
+                | Self::Integer
+                | Self::Key
+                | Self::Last
+                | Self::Less
+                | Self::MaxValue
+                | Self::Nulls
+                | Self::Of
+                | Self::Only
+                | Self::Partition
+                | Self::Range
+                | Self::Read
+                | Self::Resume
+                | Self::Rollback
+                | Self::Show
+                | Self::String
+                | Self::Text
+                | Self::Than
+                | Self::Time
+                | Self::Transaction
+                | Self::Varchar
+                | Self::Write
+        )
+    }
 }
 
 impl std::fmt::Display for Keyword {
@@ -292,6 +437,12 @@ impl std::fmt::Display for Keyword {
 /// A lexer tokenizes an input string as an iterator
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
+    /// The byte offset, and 1-based line and column, of the next character
+    offset: usize,
+    line: usize,
+    column: usize,
+    /// The line and column of the most recently scanned token
+    start: (usize, usize),
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -300,7 +451,7 @@ impl<'a> Iterator for Lexer<'a> {
     fn next(&mut self) -> Option<Result<Token>> {
         match self.scan() {
             Ok(Some(token)) => Some(Ok(token)),
-            Ok(None) => match self.iter.peek() {
+            Ok(None) => match self.next_char() {
                 Some(c) => Some(Err(Error::Parse(format!("Unexpected character {}", c)))),
                 None => None,
             },
@@ -313,7 +464,31 @@ impl<'a> Lexer<'a> {
     /// Creates a new lexer for the given input string
     #[allow(dead_code)]
     pub fn new(input: &'a str) -> Lexer<'a> {
-        Lexer { iter: input.chars().peekable() }
+        Lexer { iter: input.chars().peekable(), offset: 0, line: 1, column: 1, start: (1, 1) }
+    }
+
+    /// Returns the 1-based line and column of the most recently scanned token (or the failing
+    /// token if scanning errored, or the end of input).
+    pub fn position(&self) -> (usize, usize) {
+        self.start
+    }
+
+    /// Returns the byte offset in the input string following the most recently scanned token.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Grabs the next character, keeping track of the offset, line, and column
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.iter.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
     }
 
     /// Consumes any whitespace characters
@@ -324,13 +499,13 @@ impl<'a> Lexer<'a> {
     /// Grabs the next character if it matches the predicate function
     fn next_if<F: Fn(char) -> bool>(&mut self, predicate: F) -> Option<char> {
         self.iter.peek().filter(|&c| predicate(*c))?;
-        self.iter.next()
+        self.next_char()
     }
 
     /// Grabs the next single-character token if the tokenizer function returns one
     fn next_if_token<F: Fn(char) -> Option<Token>>(&mut self, tokenizer: F) -> Option<Token> {
         let token = self.iter.peek().and_then(|&c| tokenizer(c))?;
-        self.iter.next();
+        self.next_char();
         Some(token)
     }
 
@@ -343,22 +518,46 @@ impl<'a> Lexer<'a> {
         Some(value).filter(|v| !v.is_empty())
     }
 
-    /// Scans the input for the next token if any, ignoring leading whitespace
+    /// Scans the input for the next token if any, ignoring leading whitespace and comments
     fn scan(&mut self) -> Result<Option<Token>> {
         self.consume_whitespace();
+        self.start = (self.line, self.column);
+        let comment = self.iter.clone().take(2).eq("/*".chars());
         match self.iter.peek() {
+            Some('/') if comment => self.scan_comment(),
             Some('\'') => self.scan_string(),
             Some('"') => self.scan_ident_quoted(),
             Some(c) if c.is_digit(10) => Ok(self.scan_number()),
-            Some(c) if c.is_alphabetic() => Ok(self.scan_ident()),
+            Some(c) if c.is_alphabetic() || *c == '_' => Ok(self.scan_ident()),
             Some(_) => Ok(self.scan_symbol()),
             None => Ok(None),
         }
     }
 
+    /// Scans the input for a /* */ comment, returning the contents as a hint token if it is a
+    /// /*+ */ hint comment, or otherwise skipping it and returning the next token
+    fn scan_comment(&mut self) -> Result<Option<Token>> {
+        self.next_char();
+        self.next_char();
+        let hint = self.next_if(|c| c == '+').is_some();
+        let mut comment = String::new();
+        loop {
+            match self.next_char() {
+                Some('*') if self.next_if(|c| c == '/').is_some() => break,
+                Some(c) => comment.push(c),
+                None => return Err(Error::Parse("Unexpected end of comment".into())),
+            }
+        }
+        if hint {
+            Ok(Some(Token::Hint(comment.trim().to_string())))
+        } else {
+            self.scan()
+        }
+    }
+
     /// Scans the input for the next ident or keyword token, if any
     fn scan_ident(&mut self) -> Option<Token> {
-        let mut name = self.next_if(|c| c.is_alphabetic())?.to_string();
+        let mut name = self.next_if(|c| c.is_alphabetic() || c == '_')?.to_string();
         while let Some(c) = self.next_if(|c| c.is_alphanumeric() || c == '_') {
             name.push(c)
         }
@@ -374,7 +573,7 @@ impl<'a> Lexer<'a> {
         }
         let mut ident = String::new();
         loop {
-            match self.iter.next() {
+            match self.next_char() {
                 Some('"') if self.next_if(|c| c == '"').is_some() => ident.push('"'),
                 Some('"') => break,
                 Some(c) => ident.push(c),
@@ -412,7 +611,7 @@ impl<'a> Lexer<'a> {
         }
         let mut s = String::new();
         loop {
-            match self.iter.next() {
+            match self.next_char() {
                 Some('\'') if self.next_if(|c| c == '\'').is_some() => s.push('\''),
                 Some('\'') => break,
                 Some(c) => s.push(c),
@@ -438,8 +637,11 @@ impl<'a> Lexer<'a> {
             '%' => Some(Token::Percent),
             '!' => Some(Token::Exclamation),
             '?' => Some(Token::Question),
+            ':' => Some(Token::Colon),
             '(' => Some(Token::OpenParen),
             ')' => Some(Token::CloseParen),
+            '[' => Some(Token::OpenBracket),
+            ']' => Some(Token::CloseBracket),
             ',' => Some(Token::Comma),
             ';' => Some(Token::Semicolon),
             _ => None,
@@ -468,6 +670,13 @@ impl<'a> Lexer<'a> {
                     token
                 }
             }
+            Token::Colon => {
+                if self.next_if(|c| c == ':').is_some() {
+                    Token::DoubleColon
+                } else {
+                    token
+                }
+            }
             _ => token,
         })
     }
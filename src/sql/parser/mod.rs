@@ -2,35 +2,180 @@ pub mod ast;
 mod lexer;
 pub use lexer::{Keyword, Lexer, Token};
 
-use super::types::DataType;
+use super::engine::Priority;
+use super::types::{DataType, Enum};
 use crate::error::{Error, Result};
 
-use lazy_static::lazy_static;
-use regex::Regex;
 use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The maximum nesting depth of expressions, counting both parentheses and operators (e.g. a
+/// chain of 64 additions). Planning and evaluation recurse into nested expressions, so untrusted
+/// input could otherwise overflow the stack.
+pub const MAX_EXPRESSION_DEPTH: usize = 64;
 
 /// An SQL parser
 pub struct Parser<'a> {
-    lexer: std::iter::Peekable<Lexer<'a>>,
+    input: &'a str,
+    lexer: Lexer<'a>,
+    /// A token peeked from the lexer, if any
+    peeked: Option<Option<Result<Token>>>,
+    /// The line and column of the most recent token read from the lexer, used for syntax errors
+    position: (usize, usize),
+    /// Whether the most recently consumed token was a semicolon, used for error recovery
+    terminated: bool,
+    depth: usize,
+    /// The number of ? bind parameters parsed in the current statement
+    parameters: usize,
 }
 
 impl<'a> Parser<'a> {
     /// Creates a new parser for the given string input
     pub fn new(query: &str) -> Parser {
-        Parser { lexer: Lexer::new(query).peekable() }
+        Parser {
+            input: query,
+            lexer: Lexer::new(query),
+            peeked: None,
+            position: (1, 1),
+            terminated: false,
+            depth: 0,
+            parameters: 0,
+        }
     }
 
-    /// Parses the input string into an AST statement
+    /// Returns the number of ? bind parameters in the most recently parsed statement
+    pub fn parameters(&self) -> usize {
+        self.parameters
+    }
+
+    /// Parses the input string into an AST statement. Parse errors are returned as syntax errors
+    /// at the position of the most recently read token.
     pub fn parse(&mut self) -> Result<ast::Statement> {
-        let statement = self.parse_statement()?;
-        self.next_if_token(Token::Semicolon);
-        self.next_expect(None)?;
-        Ok(statement)
+        let parse = |parser: &mut Self| {
+            parser.parameters = 0;
+            let statement = parser.parse_statement()?;
+            parser.next_if_token(Token::Semicolon);
+            parser.next_expect(None)?;
+            Ok(statement)
+        };
+        parse(self).map_err(|err| self.syntax_error(err))
+    }
+
+    /// Parses the input string as the body of a stored function, which is either a single
+    /// expression or a SELECT of a single expression without any other clauses, e.g. x * 2 or
+    /// SELECT x * 2.
+    pub fn parse_function_body(&mut self) -> Result<ast::Expression> {
+        let parse = |parser: &mut Self| {
+            let expr = match parser.peek()? {
+                Some(Token::Keyword(Keyword::Select)) => match parser.parse_statement_select()? {
+                    ast::Statement::Select {
+                        mut select,
+                        from,
+                        r#where: None,
+                        group_by,
+                        having: None,
+                        order,
+                        offset: None,
+                        limit: None,
+                        hints,
+                    } if select.len() == 1
+                        && from.is_empty()
+                        && group_by.is_empty()
+                        && order.is_empty()
+                        && hints.is_empty() =>
+                    {
+                        select.remove(0).0
+                    }
+                    _ => {
+                        return Err(Error::Value(
+                            "Function body must select a single expression, without any other \
+                             clauses"
+                                .into(),
+                        ))
+                    }
+                },
+                _ => parser.parse_expression(0)?,
+            };
+            parser.next_if_token(Token::Semicolon);
+            parser.next_expect(None)?;
+            Ok(expr)
+        };
+        parse(self).map_err(|err| self.syntax_error(err))
+    }
+
+    /// Parses the input string into a sequence of ;-separated AST statements. If any statements
+    /// fail to parse, the parser recovers by skipping to the next semicolon, such that the syntax
+    /// errors of all invalid statements are returned.
+    pub fn parse_statements(&mut self) -> std::result::Result<Vec<ast::Statement>, Vec<Error>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            while self.next_if_token(Token::Semicolon).is_some() {}
+            if let Ok(None) = self.peek() {
+                break;
+            }
+            self.terminated = false;
+            self.parameters = 0;
+            let parse = |parser: &mut Self| {
+                let statement = parser.parse_statement()?;
+                if parser.next_if_token(Token::Semicolon).is_none() {
+                    parser.next_expect(None)?;
+                }
+                Ok(statement)
+            };
+            match parse(self) {
+                Ok(statement) => statements.push(statement),
+                Err(err) => {
+                    errors.push(self.syntax_error(err));
+                    self.recover();
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(statements),
+            false => Err(errors),
+        }
+    }
+
+    /// Converts a parse error into a syntax error at the position of the most recent token.
+    fn syntax_error(&self, err: Error) -> Error {
+        match err {
+            Error::Parse(message) => {
+                let (line, column) = self.position;
+                let snippet = self.input.lines().nth(line - 1).unwrap_or("").to_string();
+                Error::Syntax { message, line, column, snippet }
+            }
+            err => err,
+        }
+    }
+
+    /// Recovers from a syntax error by skipping past the next semicolon, unless the erroring token
+    /// was itself a semicolon.
+    fn recover(&mut self) {
+        self.depth = 0;
+        while !self.terminated {
+            match self.peeked.take().unwrap_or_else(|| self.scan()) {
+                Some(Ok(Token::Semicolon)) | None => break,
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Reads the next token from the lexer, recording its position.
+    fn scan(&mut self) -> Option<Result<Token>> {
+        let token = self.lexer.next();
+        self.position = self.lexer.position();
+        token
     }
 
     /// Grabs the next lexer token, or throws an error if none is found.
     fn next(&mut self) -> Result<Token> {
-        self.lexer.next().unwrap_or_else(|| Err(Error::Parse("Unexpected end of input".into())))
+        let token = match self.peeked.take() {
+            Some(token) => token,
+            None => self.scan(),
+        };
+        self.terminated = matches!(token, Some(Ok(Token::Semicolon)));
+        token.unwrap_or_else(|| Err(Error::Parse("Unexpected end of input".into())))
     }
 
     /// Grabs the next lexer token, and returns it if it was expected or
@@ -50,14 +195,31 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Grabs the next identifier, or errors if not found
+    /// Grabs the next identifier, or errors if not found. Non-reserved keywords are accepted as
+    /// identifiers.
     fn next_ident(&mut self) -> Result<String> {
         match self.next()? {
             Token::Ident(ident) => Ok(ident),
+            Token::Keyword(keyword) if !keyword.is_reserved() => {
+                Ok(keyword.to_str().to_lowercase())
+            }
             token => Err(Error::Parse(format!("Expected identifier, got {}", token))),
         }
     }
 
+    /// Grabs the next identifier, or errors if it isn't the expected one. Used for words which
+    /// are only meaningful in a specific statement, and thus aren't keywords.
+    fn next_expect_ident(&mut self, expect: &str) -> Result<()> {
+        match self.next()? {
+            Token::Ident(ident) if ident == expect => Ok(()),
+            token => Err(Error::Parse(format!(
+                "Expected token {}, found {}",
+                expect.to_uppercase(),
+                token
+            ))),
+        }
+    }
+
     /// Grabs the next lexer token if it satisfies the predicate function
     fn next_if<F: Fn(&Token) -> bool>(&mut self, predicate: F) -> Option<Token> {
         self.peek().unwrap_or(None).filter(|t| predicate(&t))?;
@@ -96,7 +258,10 @@ impl<'a> Parser<'a> {
     /// Option<Result<Token>> to Result<Option<Token>> which is
     /// more convenient to work with (the Iterator trait requires Option<T>).
     fn peek(&mut self) -> Result<Option<Token>> {
-        self.lexer.peek().cloned().transpose()
+        if self.peeked.is_none() {
+            self.peeked = Some(self.scan());
+        }
+        self.peeked.clone().flatten().transpose()
     }
 
     /// Parses an SQL statement
@@ -105,7 +270,17 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Begin)) => self.parse_transaction(),
             Some(Token::Keyword(Keyword::Commit)) => self.parse_transaction(),
             Some(Token::Keyword(Keyword::Rollback)) => self.parse_transaction(),
+            Some(Token::Keyword(Keyword::Detach)) => self.parse_transaction(),
+            Some(Token::Keyword(Keyword::Resume)) => self.parse_transaction(),
+            Some(Token::Keyword(Keyword::Show)) => self.parse_show(),
+            Some(Token::Keyword(Keyword::Set)) => self.parse_set(),
 
+            Some(Token::Keyword(Keyword::Declare)) => self.parse_cursor(),
+            Some(Token::Keyword(Keyword::Fetch)) => self.parse_cursor(),
+            Some(Token::Keyword(Keyword::Close)) => self.parse_cursor(),
+
+            Some(Token::Keyword(Keyword::Alter)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Comment)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
 
@@ -114,22 +289,82 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select(),
             Some(Token::Keyword(Keyword::Update)) => self.parse_statement_update(),
 
+            Some(Token::Keyword(Keyword::Changefeed)) => self.parse_statement_changefeed(),
+            Some(Token::Keyword(Keyword::Check)) => self.parse_statement_check_index(),
             Some(Token::Keyword(Keyword::Explain)) => self.parse_statement_explain(),
+            Some(Token::Keyword(Keyword::History)) => self.parse_statement_history(),
 
             Some(token) => Err(Error::Parse(format!("Unexpected token {}", token))),
             None => Err(Error::Parse("Unexpected end of input".into())),
         }
     }
 
+    /// Parses a cursor statement
+    fn parse_cursor(&mut self) -> Result<ast::Statement> {
+        match self.next()? {
+            Token::Keyword(Keyword::Declare) => {
+                let name = self.next_ident()?;
+                self.next_expect(Some(Keyword::Cursor.into()))?;
+                self.next_expect(Some(Keyword::For.into()))?;
+                match self.peek()? {
+                    Some(Token::Keyword(Keyword::Select)) => Ok(ast::Statement::Declare {
+                        name,
+                        query: Box::new(self.parse_statement_select()?),
+                    }),
+                    Some(token) => {
+                        Err(Error::Parse(format!("Unexpected token {}, wanted SELECT", token)))
+                    }
+                    None => Err(Error::Parse("Unexpected end of input".into())),
+                }
+            }
+            Token::Keyword(Keyword::Fetch) => {
+                let count = match self.peek()? {
+                    Some(Token::Number(_)) => match self.next()? {
+                        Token::Number(n) => n.parse::<u64>()?,
+                        token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                    },
+                    _ => 1,
+                };
+                self.next_if_token(Keyword::From.into());
+                Ok(ast::Statement::Fetch { name: self.next_ident()?, count })
+            }
+            Token::Keyword(Keyword::Close) => Ok(ast::Statement::Close(self.next_ident()?)),
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
     /// Parses a DDL statement
     fn parse_ddl(&mut self) -> Result<ast::Statement> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_create_index(),
+                Token::Ident(ident) if ident == "function" => self.parse_ddl_create_function(),
+                Token::Ident(ident) if ident == "type" => self.parse_ddl_create_type(),
+                Token::Ident(ident) if ident == "policy" => self.parse_ddl_create_policy(),
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
+            Token::Keyword(Keyword::Alter) => match self.next()? {
+                Token::Keyword(Keyword::Table) => self.parse_ddl_alter_table(),
+                token => Err(Error::Parse(format!("Unexpected token {}", token))),
+            },
+            Token::Keyword(Keyword::Comment) => {
+                self.next_expect(Some(Keyword::On.into()))?;
+                self.parse_ddl_comment()
+            }
             Token::Keyword(Keyword::Drop) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_drop_table(),
+                Token::Ident(ident) if ident == "function" => {
+                    Ok(ast::Statement::DropFunction(self.next_ident()?))
+                }
+                Token::Ident(ident) if ident == "type" => {
+                    Ok(ast::Statement::DropType(self.next_ident()?))
+                }
+                Token::Ident(ident) if ident == "policy" => {
+                    let name = self.next_ident()?;
+                    self.next_expect(Some(Keyword::On.into()))?;
+                    Ok(ast::Statement::DropPolicy { name, table: self.next_ident()? })
+                }
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             token => Err(Error::Parse(format!("Unexpected token {}", token))),
@@ -150,7 +385,114 @@ impl<'a> Parser<'a> {
             }
         }
         self.next_expect(Some(Token::CloseParen))?;
-        Ok(ast::Statement::CreateTable { name, columns })
+        let partition_by = match self.next_if_token(Keyword::Partition.into()) {
+            Some(_) => Some(self.parse_ddl_partition_by()?),
+            None => None,
+        };
+        Ok(ast::Statement::CreateTable { name, columns, partition_by })
+    }
+
+    /// Parses a PARTITION BY RANGE clause of a CREATE TABLE statement. The PARTITION keyword
+    /// has already been consumed.
+    fn parse_ddl_partition_by(&mut self) -> Result<ast::PartitionBy> {
+        self.next_expect(Some(Keyword::By.into()))?;
+        self.next_expect(Some(Keyword::Range.into()))?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let column = self.next_ident()?;
+        self.next_expect(Some(Token::CloseParen))?;
+        self.next_expect(Some(Token::OpenParen))?;
+
+        let mut partitions = Vec::new();
+        loop {
+            self.next_expect(Some(Keyword::Partition.into()))?;
+            let name = self.next_ident()?;
+            self.next_expect(Some(Keyword::Values.into()))?;
+            self.next_expect(Some(Keyword::Less.into()))?;
+            self.next_expect(Some(Keyword::Than.into()))?;
+            self.next_expect(Some(Token::OpenParen))?;
+            let upper = match self.next_if_token(Keyword::MaxValue.into()) {
+                Some(_) => None,
+                None => Some(self.parse_expression(0)?),
+            };
+            self.next_expect(Some(Token::CloseParen))?;
+            partitions.push(ast::Partition { name, upper });
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(ast::PartitionBy { column, partitions })
+    }
+
+    /// Parses a CREATE INDEX DDL statement. The CREATE INDEX prefix has
+    /// already been consumed.
+    fn parse_ddl_create_index(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::On.into()))?;
+        let table = self.next_ident()?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let column = self.next_ident()?;
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(ast::Statement::CreateIndex { table, column })
+    }
+
+    /// Parses an ALTER TABLE DDL statement. The ALTER TABLE prefix has
+    /// already been consumed.
+    fn parse_ddl_alter_table(&mut self) -> Result<ast::Statement> {
+        let table = self.next_ident()?;
+        match self.next()? {
+            Token::Keyword(Keyword::Add) => {
+                self.next_if_token(Keyword::Column.into());
+                Ok(ast::Statement::AddColumn { table, column: self.parse_ddl_columnspec()? })
+            }
+            Token::Keyword(Keyword::Alter) => {
+                self.next_if_token(Keyword::Column.into());
+                let column = self.next_ident()?;
+                let alteration = match self.next()? {
+                    Token::Keyword(Keyword::Set) => match self.next()? {
+                        Token::Keyword(Keyword::Not) => {
+                            self.next_expect(Some(Keyword::Null.into()))?;
+                            ast::ColumnAlteration::SetNotNull
+                        }
+                        Token::Keyword(Keyword::Default) => {
+                            ast::ColumnAlteration::SetDefault(self.parse_expression(0)?)
+                        }
+                        token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                    },
+                    Token::Keyword(Keyword::Drop) => match self.next()? {
+                        Token::Keyword(Keyword::Not) => {
+                            self.next_expect(Some(Keyword::Null.into()))?;
+                            ast::ColumnAlteration::DropNotNull
+                        }
+                        Token::Keyword(Keyword::Default) => ast::ColumnAlteration::DropDefault,
+                        token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                    },
+                    token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                };
+                Ok(ast::Statement::AlterColumn { table, column, alteration })
+            }
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
+    /// Parses a COMMENT ON DDL statement. The COMMENT ON prefix has
+    /// already been consumed.
+    fn parse_ddl_comment(&mut self) -> Result<ast::Statement> {
+        let (table, column) = match self.next()? {
+            Token::Keyword(Keyword::Table) => (self.next_ident()?, None),
+            Token::Keyword(Keyword::Column) => {
+                let table = self.next_ident()?;
+                self.next_expect(Some(Token::Period))?;
+                (table, Some(self.next_ident()?))
+            }
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        self.next_expect(Some(Keyword::Is.into()))?;
+        let comment = match self.next()? {
+            Token::String(comment) => Some(comment),
+            Token::Keyword(Keyword::Null) => None,
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        Ok(ast::Statement::Comment { table, column, comment })
     }
 
     /// Parses a DROP TABLE DDL statement. The DROP TABLE prefix has
@@ -159,23 +501,106 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::DropTable(self.next_ident()?))
     }
 
+    /// Parses a CREATE FUNCTION DDL statement. The CREATE FUNCTION prefix has already been
+    /// consumed. The body is given as a string literal, like in PostgreSQL, and is parsed when
+    /// the function is planned.
+    fn parse_ddl_create_function(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut parameters = Vec::new();
+        while self.next_if_token(Token::CloseParen).is_none() {
+            if !parameters.is_empty() {
+                self.next_expect(Some(Token::Comma))?;
+            }
+            parameters.push((self.next_ident()?, self.parse_datatype()?));
+        }
+        self.next_expect(Some(Keyword::As.into()))?;
+        let body = match self.next()? {
+            Token::String(body) => body,
+            token => return Err(Error::Parse(format!("Expected string literal, found {}", token))),
+        };
+        Ok(ast::Statement::CreateFunction { name, parameters, body })
+    }
+
+    /// Parses a CREATE TYPE DDL statement. The CREATE TYPE prefix has already been consumed.
+    /// Only enum types are supported, given as AS ENUM with a list of string labels.
+    fn parse_ddl_create_type(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Keyword::As.into()))?;
+        self.next_expect_ident("enum")?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut labels = Vec::new();
+        loop {
+            match self.next()? {
+                Token::String(label) => labels.push(label),
+                token => {
+                    return Err(Error::Parse(format!("Expected string literal, found {}", token)))
+                }
+            }
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(ast::Statement::CreateType { name, labels })
+    }
+
+    /// Parses a CREATE POLICY DDL statement. The CREATE POLICY prefix has already been consumed.
+    fn parse_ddl_create_policy(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Keyword::On.into()))?;
+        let table = self.next_ident()?;
+        self.next_expect_ident("to")?;
+        let role = self.next_ident()?;
+        self.next_expect_ident("using")?;
+        self.next_expect(Some(Token::OpenParen))?;
+        let predicate = self.parse_expression(0)?;
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(ast::Statement::CreatePolicy { name, table, role, predicate })
+    }
+
+    /// Parses a datatype. POINT is not a keyword, since it's also the name of the point function.
+    /// Other identifiers name user-defined enum types, which the planner resolves from the catalog.
+    /// A [] suffix gives an array of the datatype.
+    fn parse_datatype(&mut self) -> Result<DataType> {
+        let datatype = match self.next()? {
+            Token::Keyword(Keyword::BigInt) => DataType::Integer,
+            Token::Keyword(Keyword::Bool) => DataType::Boolean,
+            Token::Keyword(Keyword::Boolean) => DataType::Boolean,
+            Token::Keyword(Keyword::Char) => DataType::String,
+            Token::Keyword(Keyword::Double) => DataType::Float,
+            Token::Keyword(Keyword::Float) => DataType::Float,
+            Token::Keyword(Keyword::Int) => DataType::Int,
+            Token::Keyword(Keyword::Integer) => DataType::Integer,
+            Token::Ident(ident) if ident == "point" => DataType::Point,
+            Token::Ident(name) => DataType::Enum(Arc::new(Enum { name, labels: Vec::new() })),
+            Token::Keyword(Keyword::String) => DataType::String,
+            Token::Keyword(Keyword::Text) => DataType::String,
+            Token::Keyword(Keyword::Varchar) => match self.next_if_token(Token::OpenParen) {
+                Some(_) => {
+                    let length = match self.next()? {
+                        Token::Number(n) => n.parse::<usize>()?,
+                        token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                    };
+                    self.next_expect(Some(Token::CloseParen))?;
+                    DataType::Varchar(length)
+                }
+                None => DataType::String,
+            },
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        if self.next_if_token(Token::OpenBracket).is_some() {
+            self.next_expect(Some(Token::CloseBracket))?;
+            return Ok(DataType::Array(Box::new(datatype)));
+        }
+        Ok(datatype)
+    }
+
     /// Parses a column specification
     fn parse_ddl_columnspec(&mut self) -> Result<ast::Column> {
         let mut column = ast::Column {
             name: self.next_ident()?,
-            datatype: match self.next()? {
-                Token::Keyword(Keyword::Bool) => DataType::Boolean,
-                Token::Keyword(Keyword::Boolean) => DataType::Boolean,
-                Token::Keyword(Keyword::Char) => DataType::String,
-                Token::Keyword(Keyword::Double) => DataType::Float,
-                Token::Keyword(Keyword::Float) => DataType::Float,
-                Token::Keyword(Keyword::Int) => DataType::Integer,
-                Token::Keyword(Keyword::Integer) => DataType::Integer,
-                Token::Keyword(Keyword::String) => DataType::String,
-                Token::Keyword(Keyword::Text) => DataType::String,
-                Token::Keyword(Keyword::Varchar) => DataType::String,
-                token => return Err(Error::Parse(format!("Unexpected token {}", token))),
-            },
+            datatype: self.parse_datatype()?,
             primary_key: false,
             nullable: None,
             default: None,
@@ -218,6 +643,41 @@ impl<'a> Parser<'a> {
         Ok(column)
     }
 
+    /// Parses a changefeed statement
+    fn parse_statement_changefeed(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Changefeed.into()))?;
+        self.next_expect(Some(Keyword::For.into()))?;
+        self.next_expect(Some(Keyword::Table.into()))?;
+        let table = self.next_ident()?;
+        let from = if self.next_if_token(Keyword::From.into()).is_some() {
+            match self.next()? {
+                Token::Number(n) => Some(n.parse::<u64>()?),
+                token => {
+                    return Err(Error::Parse(format!("Unexpected token {}, wanted number", token)))
+                }
+            }
+        } else {
+            None
+        };
+        Ok(ast::Statement::Changefeed { table, from })
+    }
+
+    /// Parses a CHECK INDEX statement
+    fn parse_statement_check_index(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Check.into()))?;
+        self.next_expect(Some(Keyword::Index.into()))?;
+        self.next_expect(Some(Keyword::On.into()))?;
+        let table = self.next_ident()?;
+        let column = if self.next_if_token(Token::OpenParen).is_some() {
+            let column = self.next_ident()?;
+            self.next_expect(Some(Token::CloseParen))?;
+            Some(column)
+        } else {
+            None
+        };
+        Ok(ast::Statement::CheckIndex { table, column })
+    }
+
     /// Parses a delete statement
     fn parse_statement_delete(&mut self) -> Result<ast::Statement> {
         self.next_expect(Some(Keyword::Delete.into()))?;
@@ -226,13 +686,56 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::Delete { table, r#where: self.parse_clause_where()? })
     }
 
-    /// Parses a delete statement
+    /// Parses an explain statement
     fn parse_statement_explain(&mut self) -> Result<ast::Statement> {
         self.next_expect(Some(Keyword::Explain.into()))?;
+        let mut format = ast::ExplainFormat::Text;
+        let mut verbose = false;
+        if self.next_if_token(Token::OpenParen).is_some() {
+            loop {
+                match self.next_ident()?.as_str() {
+                    "format" => {
+                        format = match self.next()? {
+                            Token::Keyword(Keyword::Text) => ast::ExplainFormat::Text,
+                            Token::Ident(ident) if ident == "json" => ast::ExplainFormat::Json,
+                            Token::Ident(ident) if ident == "dot" => ast::ExplainFormat::Dot,
+                            token => {
+                                return Err(Error::Parse(format!(
+                                    "Unknown EXPLAIN format {}",
+                                    token
+                                )))
+                            }
+                        }
+                    }
+                    "verbose" => verbose = true,
+                    option => {
+                        return Err(Error::Parse(format!("Unknown EXPLAIN option {}", option)))
+                    }
+                }
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+            self.next_expect(Some(Token::CloseParen))?;
+        }
         if let Some(Token::Keyword(Keyword::Explain)) = self.peek()? {
             return Err(Error::Parse("Cannot nest EXPLAIN statements".into()));
         }
-        Ok(ast::Statement::Explain(Box::new(self.parse_statement()?)))
+        Ok(ast::Statement::Explain {
+            statement: Box::new(self.parse_statement()?),
+            format,
+            verbose,
+        })
+    }
+
+    /// Parses a row history statement
+    fn parse_statement_history(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::History.into()))?;
+        self.next_expect(Some(Keyword::For.into()))?;
+        self.next_expect(Some(Keyword::Table.into()))?;
+        let table = self.next_ident()?;
+        self.next_expect(Some(Keyword::Key.into()))?;
+        Ok(ast::Statement::History { table, key: self.parse_expression(0)? })
     }
 
     /// Parses an insert statement
@@ -280,7 +783,9 @@ impl<'a> Parser<'a> {
 
     /// Parses a select statement
     fn parse_statement_select(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Select.into()))?;
         Ok(ast::Statement::Select {
+            hints: self.parse_hints()?,
             select: self.parse_clause_select()?,
             from: self.parse_clause_from()?,
             r#where: self.parse_clause_where()?,
@@ -351,14 +856,99 @@ impl<'a> Parser<'a> {
                         }
                     }
                 }
-                Ok(ast::Statement::Begin { readonly, version })
+                let mut priority = None;
+                if self.next_if(|t| t == &Token::Ident("priority".into())).is_some() {
+                    priority = Some(match self.next()? {
+                        Token::Ident(ident) if ident == "low" => Priority::Low,
+                        Token::Ident(ident) if ident == "normal" => Priority::Normal,
+                        Token::Ident(ident) if ident == "high" => Priority::High,
+                        token => {
+                            return Err(Error::Parse(format!(
+                                "Unexpected token {}, wanted LOW, NORMAL, or HIGH",
+                                token
+                            )))
+                        }
+                    })
+                }
+                Ok(ast::Statement::Begin { readonly, version, priority })
+            }
+            Token::Keyword(Keyword::Commit) => {
+                Ok(ast::Statement::Commit { chain: self.parse_transaction_chain()? })
+            }
+            Token::Keyword(Keyword::Rollback) => {
+                Ok(ast::Statement::Rollback { chain: self.parse_transaction_chain()? })
+            }
+            Token::Keyword(Keyword::Detach) => {
+                self.next_if_token(Keyword::Transaction.into());
+                Ok(ast::Statement::Detach)
+            }
+            Token::Keyword(Keyword::Resume) => {
+                self.next_if_token(Keyword::Transaction.into());
+                match self.next()? {
+                    Token::Number(n) => Ok(ast::Statement::Resume(n.parse::<u64>()?)),
+                    token => {
+                        Err(Error::Parse(format!("Unexpected token {}, wanted number", token)))
+                    }
+                }
             }
-            Token::Keyword(Keyword::Commit) => Ok(ast::Statement::Commit),
-            Token::Keyword(Keyword::Rollback) => Ok(ast::Statement::Rollback),
             token => Err(Error::Parse(format!("Unexpected token {}", token))),
         }
     }
 
+    /// Parses a SHOW statement. SHOW JOBS and SHOW CLUSTER SETTINGS are shorthands for
+    /// SELECT * FROM system.jobs and system.settings respectively.
+    fn parse_show(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Show.into()))?;
+        let table = match self.next()? {
+            Token::Keyword(Keyword::Transaction) => return Ok(ast::Statement::ShowTransaction),
+            Token::Keyword(Keyword::Create) => {
+                self.next_expect(Some(Keyword::Table.into()))?;
+                return Ok(ast::Statement::ShowCreateTable(self.next_ident()?));
+            }
+            Token::Ident(ident) if ident == "jobs" => "system.jobs",
+            Token::Ident(ident) if ident == "cluster" => {
+                self.next_expect_ident("settings")?;
+                "system.settings"
+            }
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        Ok(ast::Statement::Select {
+            select: Vec::new(),
+            from: vec![ast::FromItem::Table { name: table.into(), alias: None }],
+            r#where: None,
+            group_by: Vec::new(),
+            having: None,
+            order: Vec::new(),
+            offset: None,
+            limit: None,
+            hints: Vec::new(),
+        })
+    }
+
+    /// Parses a SET CLUSTER SETTING statement. A DEFAULT value resets the setting.
+    fn parse_set(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Set.into()))?;
+        self.next_expect_ident("cluster")?;
+        self.next_expect_ident("setting")?;
+        let name = self.next_ident()?;
+        self.next_expect(Some(Token::Equal))?;
+        let value = match self.next_if_token(Keyword::Default.into()) {
+            Some(_) => None,
+            None => Some(self.parse_expression(0)?),
+        };
+        Ok(ast::Statement::SetSetting { name, value })
+    }
+
+    /// Parses an optional AND CHAIN clause of a COMMIT or ROLLBACK statement
+    fn parse_transaction_chain(&mut self) -> Result<bool> {
+        self.next_if_token(Keyword::Transaction.into());
+        if self.next_if_token(Keyword::And.into()).is_some() {
+            self.next_expect(Some(Keyword::Chain.into()))?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     /// Parses a from clause
     fn parse_clause_from(&mut self) -> Result<Vec<ast::FromItem>> {
         let mut from = Vec::new();
@@ -395,7 +985,12 @@ impl<'a> Parser<'a> {
 
     // Parses a from clause table
     fn parse_clause_from_table(&mut self) -> Result<ast::FromItem> {
-        let name = self.next_ident()?;
+        let name = if self.next_if_token(Keyword::System.into()).is_some() {
+            self.next_expect(Some(Token::Period))?;
+            format!("system.{}", self.next_ident()?)
+        } else {
+            self.next_ident()?
+        };
         let alias = if self.next_if_token(Keyword::As.into()).is_some() {
             Some(self.next_ident()?)
         } else if let Some(Token::Ident(_)) = self.peek()? {
@@ -454,7 +1049,9 @@ impl<'a> Parser<'a> {
     }
 
     /// Parses an order clause
-    fn parse_clause_order(&mut self) -> Result<Vec<(ast::Expression, ast::Order)>> {
+    fn parse_clause_order(
+        &mut self,
+    ) -> Result<Vec<(ast::Expression, ast::Order, Option<ast::Nulls>)>> {
         if self.next_if_token(Keyword::Order.into()).is_none() {
             return Ok(Vec::new());
         }
@@ -470,6 +1067,7 @@ impl<'a> Parser<'a> {
                 } else {
                     ast::Order::Ascending
                 },
+                self.parse_clause_order_nulls()?,
             ));
             if self.next_if_token(Token::Comma).is_none() {
                 break;
@@ -478,12 +1076,21 @@ impl<'a> Parser<'a> {
         Ok(orders)
     }
 
+    /// Parses an optional NULLS FIRST or NULLS LAST order clause
+    fn parse_clause_order_nulls(&mut self) -> Result<Option<ast::Nulls>> {
+        if self.next_if_token(Keyword::Nulls.into()).is_none() {
+            return Ok(None);
+        }
+        match self.next()? {
+            Token::Keyword(Keyword::First) => Ok(Some(ast::Nulls::First)),
+            Token::Keyword(Keyword::Last) => Ok(Some(ast::Nulls::Last)),
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
     /// Parses a select clause
     fn parse_clause_select(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
         let mut select = Vec::new();
-        if self.next_if_token(Keyword::Select.into()).is_none() {
-            return Ok(select);
-        }
         loop {
             if self.next_if_token(Token::Asterisk).is_some() && select.is_empty() {
                 break;
@@ -505,6 +1112,50 @@ impl<'a> Parser<'a> {
         Ok(select)
     }
 
+    /// Parses any query hints, given as /*+ */ comments containing one or more hints, e.g.
+    /// /*+ NO_INDEX(movies) JOIN_ORDER(m, g) */
+    fn parse_hints(&mut self) -> Result<Vec<ast::Hint>> {
+        let mut hints = Vec::new();
+        while let Some(Token::Hint(text)) = self.next_if(|t| matches!(t, Token::Hint(_))) {
+            let mut parser = Parser::new(&text);
+            while parser.peek()?.is_some() {
+                hints.push(parser.parse_hint()?);
+                parser.next_if_token(Token::Comma);
+            }
+        }
+        Ok(hints)
+    }
+
+    /// Parses a single query hint, e.g. NO_INDEX(movies)
+    fn parse_hint(&mut self) -> Result<ast::Hint> {
+        let name = self.next_ident()?;
+        let hint = match name.as_str() {
+            "audit_outer_joins" => Some(ast::Hint::AuditOuterJoins),
+            "wrapping_arithmetic" => Some(ast::Hint::WrappingArithmetic),
+            _ => None,
+        };
+        if let Some(hint) = hint {
+            if self.next_if_token(Token::OpenParen).is_some() {
+                return Err(Error::Parse(format!("Hint {} takes no arguments", name)));
+            }
+            return Ok(hint);
+        }
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut tables = Vec::new();
+        loop {
+            tables.push(self.next_ident()?);
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Some(Token::CloseParen))?;
+        match name.as_str() {
+            "no_index" => Ok(ast::Hint::NoIndex(tables)),
+            "join_order" => Ok(ast::Hint::JoinOrder(tables)),
+            _ => Err(Error::Parse(format!("Unknown hint {}", name))),
+        }
+    }
+
     /// Parses a WHERE clause
     fn parse_clause_where(&mut self) -> Result<Option<ast::Expression>> {
         if self.next_if_token(Keyword::Where.into()).is_none() {
@@ -516,23 +1167,49 @@ impl<'a> Parser<'a> {
     /// Parses an expression consisting of at least one atom operated on by any
     /// number of operators, using the precedence climbing algorithm.
     fn parse_expression(&mut self, min_prec: u8) -> Result<ast::Expression> {
+        let depth = self.depth;
+        self.nest()?;
         let mut lhs = if let Some(prefix) = self.next_if_operator::<PrefixOperator>(min_prec)? {
             prefix.build(self.parse_expression(prefix.prec() + prefix.assoc())?)
         } else {
             self.parse_expression_atom()?
         };
+        // Operators nest the expression parsed so far one level deeper, since chains of
+        // left-associative operators don't recurse.
         while let Some(postfix) = self.next_if_operator::<PostfixOperator>(min_prec)? {
-            lhs = postfix.build(lhs)
+            lhs = postfix.build(lhs);
+            self.nest()?;
         }
         while let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec)? {
-            lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?)
+            lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?);
+            self.nest()?;
         }
+        self.depth = depth;
         Ok(lhs)
     }
 
-    /// Parses an expression atom
+    /// Nests the expression being parsed one level deeper, erroring if this exceeds
+    /// MAX_EXPRESSION_DEPTH.
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_EXPRESSION_DEPTH {
+            return Err(Error::Parse(format!(
+                "Expression nesting exceeds maximum depth {}",
+                MAX_EXPRESSION_DEPTH
+            )));
+        }
+        Ok(())
+    }
+
+    /// Parses an expression atom. Non-reserved keywords are parsed as identifiers.
     fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
-        Ok(match self.next()? {
+        let token = match self.next()? {
+            Token::Keyword(keyword) if !keyword.is_reserved() => {
+                Token::Ident(keyword.to_str().to_lowercase())
+            }
+            token => token,
+        };
+        Ok(match token {
             Token::Ident(i) => {
                 if self.next_if_token(Token::OpenParen).is_some() {
                     let mut args = Vec::new();
@@ -571,6 +1248,34 @@ impl<'a> Parser<'a> {
                 expr
             }
             Token::String(s) => ast::Literal::String(s).into(),
+            Token::Keyword(Keyword::Array) => {
+                self.next_expect(Some(Token::OpenBracket))?;
+                let mut elements = Vec::new();
+                while self.next_if_token(Token::CloseBracket).is_none() {
+                    if !elements.is_empty() {
+                        self.next_expect(Some(Token::Comma))?;
+                    }
+                    elements.push(self.parse_expression(0)?);
+                }
+                ast::Expression::Array(elements)
+            }
+            Token::Keyword(keyword @ Keyword::Any) | Token::Keyword(keyword @ Keyword::All) => {
+                self.next_expect(Some(Token::OpenParen))?;
+                let expr = Box::new(self.parse_expression(0)?);
+                self.next_expect(Some(Token::CloseParen))?;
+                match keyword {
+                    Keyword::Any => ast::Expression::Any(expr),
+                    _ => ast::Expression::All(expr),
+                }
+            }
+            Token::Question => {
+                self.parameters += 1;
+                let datatype = match self.next_if_token(Token::DoubleColon) {
+                    Some(_) => Some(self.parse_datatype()?),
+                    None => None,
+                };
+                ast::Expression::Parameter(self.parameters - 1, datatype)
+            }
             Token::Keyword(Keyword::False) => ast::Literal::Boolean(false).into(),
             Token::Keyword(Keyword::Infinity) => ast::Literal::Float(std::f64::INFINITY).into(),
             Token::Keyword(Keyword::NaN) => ast::Literal::Float(std::f64::NAN).into(),
@@ -729,6 +1434,8 @@ impl Operator for InfixOperator {
 }
 
 enum PostfixOperator {
+    /// Array element access, with the index expression parsed by augment().
+    Element(Box<ast::Expression>),
     Factorial,
     // FIXME Compiler bug? Why is this considered dead code?
     #[allow(dead_code)]
@@ -738,9 +1445,10 @@ enum PostfixOperator {
 }
 
 impl PostfixOperator {
-    fn build(&self, lhs: ast::Expression) -> ast::Expression {
+    fn build(self, lhs: ast::Expression) -> ast::Expression {
         let lhs = Box::new(lhs);
         match self {
+            Self::Element(index) => ast::Operation::Element(lhs, index),
             Self::IsNull { not } => match not {
                 true => ast::Operation::Not(Box::new(ast::Operation::IsNull(lhs).into())),
                 false => ast::Operation::IsNull(lhs),
@@ -755,21 +1463,25 @@ impl Operator for PostfixOperator {
     fn from(token: &Token) -> Option<Self> {
         match token {
             Token::Exclamation => Some(Self::Factorial),
+            Token::OpenBracket => Some(Self::Element(Box::new(ast::Literal::Null.into()))),
             Token::Keyword(Keyword::Is) => Some(Self::IsNull { not: false }),
             _ => None,
         }
     }
 
     fn augment(mut self, parser: &mut Parser) -> Result<Self> {
-        #[allow(clippy::single_match)]
         match &mut self {
+            Self::Element(ref mut index) => {
+                **index = parser.parse_expression(0)?;
+                parser.next_expect(Some(Token::CloseBracket))?;
+            }
             Self::IsNull { ref mut not } => {
                 if parser.next_if_token(Keyword::Not.into()).is_some() {
                     *not = true
                 };
                 parser.next_expect(Some(Keyword::Null.into()))?;
             }
-            _ => {}
+            Self::Factorial => {}
         };
         Ok(self)
     }
@@ -779,19 +1491,130 @@ impl Operator for PostfixOperator {
     }
 
     fn prec(&self) -> u8 {
-        8
+        match self {
+            // Binds tighter than prefix operators, such that -a[1] negates the element.
+            Self::Element(_) => 10,
+            _ => 8,
+        }
     }
 }
 
-// Formats an identifier by quoting it as appropriate
-pub(super) fn format_ident(ident: &str) -> String {
-    lazy_static! {
-        static ref RE_IDENT: Regex = Regex::new(r#"^\w[\w_]*$"#).unwrap();
+/// Splits an input string into ;-separated statements, without surrounding whitespace. Semicolons
+/// in string literals, quoted identifiers, and comments don't separate statements, and empty
+/// statements are skipped. Lexer errors are left for the parser to report.
+pub fn split_statements(input: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut lexer = Lexer::new(input);
+    let (mut start, mut empty) = (0, true);
+    while let Some(token) = lexer.next() {
+        match token {
+            Ok(Token::Semicolon) => {
+                if !empty {
+                    statements.push(input[start..lexer.offset()].trim());
+                }
+                start = lexer.offset();
+                empty = true;
+            }
+            _ => empty = false,
+        }
     }
+    if !empty {
+        statements.push(input[start..].trim());
+    }
+    statements
+}
 
-    if RE_IDENT.is_match(ident) && Keyword::from_str(ident).is_none() {
+/// Formats an identifier by quoting it as appropriate, i.e. unless it would be lexed as the same
+/// unquoted identifier: a letter or _ followed by letters, digits, and _, without uppercase letters
+/// (unquoted identifiers are lowercased), and not a reserved keyword.
+pub fn format_ident(ident: &str) -> String {
+    let mut chars = ident.chars();
+    if chars.next().filter(|c| c.is_alphabetic() || *c == '_').is_some()
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && ident == ident.to_lowercase()
+        && Keyword::from_str(ident).filter(|k| k.is_reserved()).is_none()
+    {
         ident.to_string()
     } else {
         format!("\"{}\"", ident.replace("\"", "\"\""))
     }
 }
+
+// Formats a string literal by quoting it
+pub(super) fn format_string(s: &str) -> String {
+    format!("'{}'", s.replace("'", "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_error() {
+        let err = Parser::new("SELECT 1\nFROM\n  WHERE id = 1").parse().unwrap_err();
+        assert_eq!(
+            err,
+            Error::Syntax {
+                message: "Expected identifier, got WHERE".into(),
+                line: 3,
+                column: 3,
+                snippet: "  WHERE id = 1".into(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Expected identifier, got WHERE at line 3, column 3\n  WHERE id = 1\n  ^"
+        );
+    }
+
+    #[test]
+    fn parse_statements() {
+        let statements = Parser::new("SELECT 1; ;SELECT 2").parse_statements().unwrap();
+        assert_eq!(statements.len(), 2);
+
+        let errors = Parser::new("SELECT 1;\nSELECT FROM x;\nSELECT 3;\nSELECT 😀 ; SELECT ;")
+            .parse_statements()
+            .unwrap_err();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| e.to_string().lines().next().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "Expected expression atom, found FROM at line 2, column 8",
+                "Unexpected character 😀 at line 4, column 8",
+                "Expected expression atom, found ; at line 4, column 19",
+            ]
+        );
+    }
+
+    #[test]
+    fn format_ident() {
+        let f = super::format_ident;
+        assert_eq!(f("movies"), "movies");
+        assert_eq!(f("key"), "key");
+        assert_eq!(f("表_1"), "表_1");
+        assert_eq!(f("table"), r#""table""#);
+        assert_eq!(f("Movies"), r#""Movies""#);
+        assert_eq!(f("_id"), "_id");
+        assert_eq!(f("1a"), r#""1a""#);
+        assert_eq!(f("a b"), r#""a b""#);
+        assert_eq!(f(r#"a"b"#), r#""a""b""#);
+    }
+
+    #[test]
+    fn split_statements() {
+        let split = super::split_statements;
+        assert_eq!(split(""), Vec::<&str>::new());
+        assert_eq!(split(" ; /* comment */ ;"), Vec::<&str>::new());
+        assert_eq!(split("SELECT 1"), vec!["SELECT 1"]);
+        assert_eq!(
+            split("SELECT 1;\n  SELECT ';', \"a;b\" /* ; */ FROM t ;; SELECT 3 /* end */"),
+            vec!["SELECT 1;", "SELECT ';', \"a;b\" /* ; */ FROM t ;", "SELECT 3 /* end */"]
+        );
+        assert_eq!(
+            split("SELECT 'ü'; SELECT 'unterminated; SELECT 2"),
+            vec!["SELECT 'ü';", "SELECT 'unterminated; SELECT 2"]
+        );
+    }
+}
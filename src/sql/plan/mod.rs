@@ -1,21 +1,22 @@
-mod optimizer;
+pub mod optimizer;
 mod planner;
-use optimizer::Optimizer as _;
+use optimizer::Optimizers;
 use planner::Planner;
 
 use super::engine::Transaction;
 use super::execution::{Executor, ResultSet};
 use super::parser::ast;
-use super::schema::{Catalog, Table};
-use super::types::{Expression, Value};
-use crate::error::Result;
+use super::schema::{Catalog, Column, ColumnAlteration, Function, Policy, Table};
+use super::types::{DataType, Enum, Expression, Row, Value};
+use crate::error::{Error, Result};
 
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fmt::{self, Display};
 
-/// A query plan
+/// A query plan, along with any query hints for the optimizers
 #[derive(Debug)]
-pub struct Plan(pub Node);
+pub struct Plan(pub Node, pub Hints);
 
 impl Display for Plan {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -29,30 +30,234 @@ impl Plan {
         Planner::new(catalog).build(statement)
     }
 
+    /// Builds a plan from an AST statement, binding the given values to its ? parameters.
+    pub fn build_with_params<C: Catalog>(
+        statement: ast::Statement,
+        catalog: &mut C,
+        params: &[Value],
+    ) -> Result<Self> {
+        Planner::new(catalog).with_params(params).build(statement)
+    }
+
+    /// Builds a plan from an AST statement for the given session role, if any, binding the given
+    /// values to its ? parameters. Scans of tables with row-level security policies are filtered
+    /// for roles other than the table owner.
+    pub fn build_for_role<C: Catalog>(
+        statement: ast::Statement,
+        catalog: &mut C,
+        params: &[Value],
+        role: Option<&str>,
+    ) -> Result<Self> {
+        Planner::new(catalog).with_params(params).with_role(role).build(statement)
+    }
+
+    /// Infers the datatypes of an AST statement's given number of ? parameters, by planning it
+    /// without values for them. Datatypes that can't be inferred are None.
+    pub fn describe<C: Catalog>(
+        statement: ast::Statement,
+        catalog: &mut C,
+        parameters: usize,
+    ) -> Result<Vec<Option<DataType>>> {
+        Planner::new(catalog).describe(statement, parameters)
+    }
+
     /// Executes the plan, consuming it.
     pub fn execute<T: Transaction + 'static>(self, txn: &mut T) -> Result<ResultSet> {
         Executor::build(self.0).execute(txn)
     }
 
     /// Optimizes the plan, consuming it.
-    pub fn optimize<C: Catalog>(self, catalog: &mut C) -> Result<Self> {
-        let mut root = self.0;
-        root = optimizer::ConstantFolder.optimize(root)?;
-        root = optimizer::FilterPushdown.optimize(root)?;
-        root = optimizer::IndexLookup::new(catalog).optimize(root)?;
-        root = optimizer::NoopCleaner.optimize(root)?;
-        root = optimizer::JoinType.optimize(root)?;
-        Ok(Plan(root))
+    ///
+    /// With the AUDIT_OUTER_JOINS hint, plans with outer joins are also audited: the unoptimized
+    /// plan and the plan produced by each optimizer that changed it are executed, and an error is
+    /// returned if an optimizer removed an outer join or changed the result. This catches e.g.
+    /// WHERE predicates moved into an outer join, which drops its unmatched rows like an inner join.
+    pub fn optimize<T: Transaction + 'static>(self, txn: &mut T) -> Result<Self> {
+        if !self.1.audit_outer_joins || self.0.outer_joins() == 0 {
+            return self.optimize_with(txn, |_, _| {});
+        }
+        let planned = self.0.clone();
+        let mut steps = Vec::new();
+        let plan = self.optimize_with(txn, |name, node| steps.push((name, node.clone())))?;
+
+        let outer_joins = planned.outer_joins();
+        let expected = Self::audit_rows(planned.clone(), txn)?;
+        let mut previous = planned;
+        for (name, node) in steps {
+            if node == previous {
+                continue;
+            }
+            if node.outer_joins() < outer_joins {
+                return Err(Error::Internal(format!("Optimizer {} removed an outer join", name)));
+            }
+            if Self::audit_rows(node.clone(), txn)? != expected {
+                return Err(Error::Internal(format!(
+                    "Optimizer {} changed the result of an outer join",
+                    name
+                )));
+            }
+            previous = node;
+        }
+        Ok(plan)
+    }
+
+    /// Executes a plan node for auditing, returning its rows in a canonical order. Limits and
+    /// offsets are removed, since they may return different rows for plans that order them
+    /// differently.
+    fn audit_rows<T: Transaction + 'static>(node: Node, txn: &mut T) -> Result<Vec<Row>> {
+        let node = node.transform(&|n| Ok(n), &|n| match n {
+            Node::Limit { source, .. } | Node::Offset { source, .. } => Ok(*source),
+            n => Ok(n),
+        })?;
+        match <dyn Executor<T>>::build(node).execute(txn)? {
+            ResultSet::Query { rows, .. } => {
+                let mut rows = rows.collect::<Result<Vec<_>>>()?;
+                rows.sort_by_cached_key(|row| format!("{:?}", row));
+                Ok(rows)
+            }
+            result => Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        }
+    }
+
+    /// Optimizes the plan with the standard optimizers, consuming it, and calls trace with the
+    /// name of each optimizer and the plan it produced.
+    pub fn optimize_with<C, F>(self, catalog: &mut C, trace: F) -> Result<Self>
+    where
+        C: Catalog,
+        F: FnMut(&'static str, &Node),
+    {
+        let root = Optimizers::standard(catalog, &self.1).optimize(self.0, trace)?;
+        Ok(Plan(root, self.1))
     }
 }
 
-/// A plan node
+/// Query hints, given as /*+ */ comments after SELECT, which the planner and optimizers respect.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hints {
+    /// Tables, by query name (i.e. alias or actual name), which must not use index or primary key
+    /// lookups.
+    pub no_index: BTreeSet<String>,
+    /// Tables, by query name, in the order they should be joined. Unlisted FROM items are joined
+    /// afterwards, in the given order.
+    pub join_order: Vec<String>,
+    /// Whether to check that the optimizers preserve the semantics of outer joins, see
+    /// Plan::optimize().
+    pub audit_outer_joins: bool,
+    /// Whether integer arithmetic wraps around on overflow instead of erroring.
+    pub wrapping_arithmetic: bool,
+}
+
+/// A plan explanation, as returned by EXPLAIN. Verbose explanations also contain a trace of the
+/// plan produced by the planner and by each subsequent optimizer, the last of which is the final
+/// plan.
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Explanation {
+    pub plan: Node,
+    pub format: Format,
+    pub trace: Vec<(String, Node)>,
+}
+
+impl Explanation {
+    /// Returns the trace steps along with whether each step changed the plan.
+    fn steps(&self) -> impl Iterator<Item = (&str, &Node, bool)> {
+        let previous = std::iter::once(None).chain(self.trace.iter().map(|(_, node)| Some(node)));
+        self.trace
+            .iter()
+            .zip(previous)
+            .map(|((name, node), previous)| (name.as_str(), node, previous != Some(node)))
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.trace.is_empty() {
+            return write!(f, "{}", self.plan.explain(self.format));
+        }
+        match self.format {
+            Format::Text => {
+                for (i, (name, node, changed)) in self.steps().enumerate() {
+                    if i > 0 {
+                        write!(f, "\n\n")?;
+                    }
+                    match changed {
+                        true => write!(f, "{}:\n{}", name, node)?,
+                        false => write!(f, "{}: no changes", name)?,
+                    }
+                }
+                Ok(())
+            }
+            Format::Json => write!(
+                f,
+                "{{\"plan\":{},\"trace\":[{}]}}",
+                self.plan.format_json(),
+                self.steps()
+                    .map(|(name, node, changed)| format!(
+                        "{{\"step\":{},\"changed\":{},\"plan\":{}}}",
+                        json_string(name),
+                        changed,
+                        node.format_json()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            Format::Dot => {
+                let mut s = "digraph plan {\n    node [shape=box];\n".to_string();
+                let mut next_id = 0;
+                for (i, (name, node, changed)) in self.steps().enumerate() {
+                    let label =
+                        if changed { name.to_string() } else { format!("{} (no changes)", name) };
+                    s += &format!("    subgraph cluster_{} {{\n", i);
+                    s += &format!("        label=\"{}\";\n", dot_escape(&label));
+                    node.format_dot_nodes(&mut s, &mut next_id, "        ");
+                    s += "    }\n";
+                }
+                s += "}";
+                write!(f, "{}", s)
+            }
+        }
+    }
+}
+
+/// A plan node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Node {
+    AddColumn {
+        table: String,
+        column: Column,
+    },
     Aggregation {
         source: Box<Node>,
         aggregates: Vec<Aggregate>,
     },
+    AlterColumn {
+        table: String,
+        column: String,
+        alteration: ColumnAlteration,
+    },
+    Changefeed {
+        table: String,
+        from: u64,
+    },
+    CheckIndex {
+        table: String,
+        column: Option<String>,
+    },
+    Comment {
+        table: String,
+        column: Option<String>,
+        comment: Option<String>,
+    },
+    SetSetting {
+        name: String,
+        value: Option<Value>,
+    },
+    CreateFunction {
+        function: Function,
+    },
+    CreateIndex {
+        table: String,
+        column: String,
+    },
     CreateTable {
         schema: Table,
     },
@@ -60,6 +265,23 @@ pub enum Node {
         table: String,
         source: Box<Node>,
     },
+    DropFunction {
+        name: String,
+    },
+    CreateType {
+        r#enum: Enum,
+    },
+    DropType {
+        name: String,
+    },
+    CreatePolicy {
+        table: String,
+        policy: Policy,
+    },
+    DropPolicy {
+        table: String,
+        name: String,
+    },
     DropTable {
         table: String,
     },
@@ -73,6 +295,11 @@ pub enum Node {
         right: Box<Node>,
         right_field: (usize, Option<(Option<String>, String)>),
         outer: bool,
+        build_left: bool,
+    },
+    History {
+        table: String,
+        key: Value,
     },
     IndexLookup {
         table: String,
@@ -80,6 +307,13 @@ pub enum Node {
         column: String,
         values: Vec<Value>,
     },
+    IndexRange {
+        table: String,
+        alias: Option<String>,
+        column: String,
+        lower: Value,
+        upper: Value,
+    },
     Insert {
         table: String,
         columns: Vec<String>,
@@ -108,17 +342,32 @@ pub enum Node {
     },
     Order {
         source: Box<Node>,
-        orders: Vec<(Expression, Direction)>,
+        orders: Vec<(Expression, Direction, Nulls)>,
+    },
+    PartitionScan {
+        table: String,
+        alias: Option<String>,
+        partitions: Vec<String>,
+        filter: Option<Expression>,
     },
     Projection {
         source: Box<Node>,
         expressions: Vec<(Expression, Option<String>)>,
     },
+    RowVersions {
+        source: Box<Node>,
+        table: String,
+        size: usize,
+    },
     Scan {
         table: String,
         alias: Option<String>,
         filter: Option<Expression>,
     },
+    Spool {
+        source: Box<Node>,
+        id: usize,
+    },
     Update {
         table: String,
         source: Box<Node>,
@@ -135,13 +384,29 @@ impl Node {
     {
         self = before(self)?;
         self = match self {
-            n @ Self::CreateTable { .. }
+            n @ Self::AddColumn { .. }
+            | n @ Self::AlterColumn { .. }
+            | n @ Self::Changefeed { .. }
+            | n @ Self::CheckIndex { .. }
+            | n @ Self::Comment { .. }
+            | n @ Self::CreateIndex { .. }
+            | n @ Self::CreateFunction { .. }
+            | n @ Self::CreateTable { .. }
+            | n @ Self::CreatePolicy { .. }
+            | n @ Self::CreateType { .. }
+            | n @ Self::DropFunction { .. }
+            | n @ Self::DropPolicy { .. }
             | n @ Self::DropTable { .. }
+            | n @ Self::DropType { .. }
+            | n @ Self::History { .. }
             | n @ Self::IndexLookup { .. }
+            | n @ Self::IndexRange { .. }
             | n @ Self::Insert { .. }
             | n @ Self::KeyLookup { .. }
             | n @ Self::Nothing
-            | n @ Self::Scan { .. } => n,
+            | n @ Self::PartitionScan { .. }
+            | n @ Self::Scan { .. }
+            | n @ Self::SetSetting { .. } => n,
 
             Self::Aggregation { source, aggregates } => {
                 Self::Aggregation { source: source.transform(before, after)?.into(), aggregates }
@@ -152,13 +417,16 @@ impl Node {
             Self::Filter { source, predicate } => {
                 Self::Filter { source: source.transform(before, after)?.into(), predicate }
             }
-            Self::HashJoin { left, left_field, right, right_field, outer } => Self::HashJoin {
-                left: left.transform(before, after)?.into(),
-                left_field,
-                right: right.transform(before, after)?.into(),
-                right_field,
-                outer,
-            },
+            Self::HashJoin { left, left_field, right, right_field, outer, build_left } => {
+                Self::HashJoin {
+                    left: left.transform(before, after)?.into(),
+                    left_field,
+                    right: right.transform(before, after)?.into(),
+                    right_field,
+                    outer,
+                    build_left,
+                }
+            }
             Self::Limit { source, limit } => {
                 Self::Limit { source: source.transform(before, after)?.into(), limit }
             }
@@ -180,6 +448,12 @@ impl Node {
             Self::Projection { source, expressions } => {
                 Self::Projection { source: source.transform(before, after)?.into(), expressions }
             }
+            Self::RowVersions { source, table, size } => {
+                Self::RowVersions { source: source.transform(before, after)?.into(), table, size }
+            }
+            Self::Spool { source, id } => {
+                Self::Spool { source: source.transform(before, after)?.into(), id }
+            }
             Self::Update { table, source, expressions } => {
                 Self::Update { table, source: source.transform(before, after)?.into(), expressions }
             }
@@ -194,18 +468,36 @@ impl Node {
         A: Fn(Expression) -> Result<Expression>,
     {
         Ok(match self {
-            n @ Self::Aggregation { .. }
+            n @ Self::AddColumn { .. }
+            | n @ Self::Aggregation { .. }
+            | n @ Self::AlterColumn { .. }
+            | n @ Self::Changefeed { .. }
+            | n @ Self::CheckIndex { .. }
+            | n @ Self::Comment { .. }
+            | n @ Self::CreateFunction { .. }
+            | n @ Self::CreateIndex { .. }
             | n @ Self::CreateTable { .. }
+            | n @ Self::CreatePolicy { .. }
+            | n @ Self::CreateType { .. }
             | n @ Self::Delete { .. }
+            | n @ Self::DropFunction { .. }
+            | n @ Self::DropPolicy { .. }
             | n @ Self::DropTable { .. }
+            | n @ Self::DropType { .. }
             | n @ Self::HashJoin { .. }
+            | n @ Self::History { .. }
             | n @ Self::IndexLookup { .. }
+            | n @ Self::IndexRange { .. }
             | n @ Self::KeyLookup { .. }
             | n @ Self::Limit { .. }
             | n @ Self::NestedLoopJoin { predicate: None, .. }
             | n @ Self::Nothing
             | n @ Self::Offset { .. }
-            | n @ Self::Scan { filter: None, .. } => n,
+            | n @ Self::PartitionScan { filter: None, .. }
+            | n @ Self::RowVersions { .. }
+            | n @ Self::Scan { filter: None, .. }
+            | n @ Self::SetSetting { .. }
+            | n @ Self::Spool { .. } => n,
 
             Self::Filter { source, predicate } => {
                 Self::Filter { source, predicate: predicate.transform(before, after)? }
@@ -222,7 +514,7 @@ impl Node {
                 source,
                 orders: orders
                     .into_iter()
-                    .map(|(e, o)| e.transform(before, after).map(|e| (e, o)))
+                    .map(|(e, o, n)| e.transform(before, after).map(|e| (e, o, n)))
                     .collect::<Result<_>>()?,
             },
             Self::NestedLoopJoin { left, left_size, right, predicate: Some(predicate), outer } => {
@@ -241,6 +533,14 @@ impl Node {
                     .map(|(e, l)| Ok((e.transform(before, after)?, l)))
                     .collect::<Result<_>>()?,
             },
+            Self::PartitionScan { table, alias, partitions, filter: Some(filter) } => {
+                Self::PartitionScan {
+                    table,
+                    alias,
+                    partitions,
+                    filter: Some(filter.transform(before, after)?),
+                }
+            }
             Self::Scan { table, alias, filter: Some(filter) } => {
                 Self::Scan { table, alias, filter: Some(filter.transform(before, after)?) }
             }
@@ -255,58 +555,106 @@ impl Node {
         })
     }
 
-    // Displays the node, where prefix gives the node prefix.
-    pub fn format(&self, mut indent: String, root: bool, last: bool) -> String {
-        let mut s = indent.clone();
-        if !last {
-            s += "├─ ";
-            indent += "│  "
-        } else if !root {
-            s += "└─ ";
-            indent += "   ";
-        }
+    /// Returns the number of outer joins in the node and its children.
+    fn outer_joins(&self) -> usize {
+        let outer = match self {
+            Self::HashJoin { outer, .. } | Self::NestedLoopJoin { outer, .. } => *outer as usize,
+            _ => 0,
+        };
+        outer + self.children().into_iter().map(|c| c.outer_joins()).sum::<usize>()
+    }
+
+    /// Returns the node's child nodes, in display order.
+    fn children(&self) -> Vec<&Node> {
         match self {
-            Self::Aggregation { source, aggregates } => {
-                s += &format!(
-                    "Aggregation: {}\n",
-                    aggregates.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
-                );
-                s += &source.format(indent, false, true);
-            }
-            Self::CreateTable { schema } => {
-                s += &format!("CreateTable: {}\n", schema.name);
+            Self::AddColumn { .. }
+            | Self::AlterColumn { .. }
+            | Self::Changefeed { .. }
+            | Self::CheckIndex { .. }
+            | Self::Comment { .. }
+            | Self::CreateIndex { .. }
+            | Self::CreateFunction { .. }
+            | Self::CreateTable { .. }
+            | Self::CreatePolicy { .. }
+            | Self::CreateType { .. }
+            | Self::DropFunction { .. }
+            | Self::DropPolicy { .. }
+            | Self::DropTable { .. }
+            | Self::DropType { .. }
+            | Self::History { .. }
+            | Self::IndexLookup { .. }
+            | Self::IndexRange { .. }
+            | Self::Insert { .. }
+            | Self::KeyLookup { .. }
+            | Self::Nothing
+            | Self::PartitionScan { .. }
+            | Self::Scan { .. }
+            | Self::SetSetting { .. } => vec![],
+
+            Self::Aggregation { source, .. }
+            | Self::Delete { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. }
+            | Self::Projection { source, .. }
+            | Self::RowVersions { source, .. }
+            | Self::Spool { source, .. }
+            | Self::Update { source, .. } => vec![source],
+
+            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
+                vec![left, right]
             }
-            Self::Delete { source, table } => {
-                s += &format!("Delete: {}\n", table);
-                s += &source.format(indent, false, true);
+        }
+    }
+
+    /// Describes the node (without children) as its name and any details, e.g. Scan and
+    /// "movies as m".
+    fn describe(&self) -> (&'static str, Option<String>) {
+        let details = match self {
+            Self::AddColumn { table, column } => format!("{}.{}", table, column.name),
+            Self::Aggregation { aggregates, .. } => {
+                aggregates.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
             }
-            Self::DropTable { table } => {
-                s += &format!("DropTable: {}\n", table);
-            }
-            Self::Filter { source, predicate } => {
-                s += &format!("Filter: {}\n", predicate);
-                s += &source.format(indent, false, true);
-            }
-            Self::HashJoin { left, left_field, right, right_field, outer } => {
-                s += &format!(
-                    "HashJoin: {} on {} = {}\n",
-                    if *outer { "outer" } else { "inner" },
-                    match left_field {
-                        (_, Some((Some(t), n))) => format!("{}.{}", t, n),
-                        (_, Some((None, n))) => n.clone(),
-                        (i, None) => format!("left #{}", i),
-                    },
-                    match right_field {
-                        (_, Some((Some(t), n))) => format!("{}.{}", t, n),
-                        (_, Some((None, n))) => n.clone(),
-                        (i, None) => format!("right #{}", i),
-                    },
-                );
-                s += &left.format(indent.clone(), false, false);
-                s += &right.format(indent, false, true);
+            Self::AlterColumn { table, column, alteration } => {
+                format!("{}.{} {:?}", table, column, alteration)
             }
+            Self::Changefeed { table, from } => format!("{} from {}", table, from),
+            Self::CheckIndex { table, column: Some(column) } => format!("{}.{}", table, column),
+            Self::CheckIndex { table, column: None } => table.clone(),
+            Self::Comment { table, column: Some(column), .. } => format!("{}.{}", table, column),
+            Self::Comment { table, column: None, .. } => table.clone(),
+            Self::SetSetting { name, value: Some(value) } => format!("{} = {}", name, value),
+            Self::SetSetting { name, value: None } => format!("{} = DEFAULT", name),
+            Self::CreateIndex { table, column } => format!("{}.{}", table, column),
+            Self::CreateFunction { function } => function.name.clone(),
+            Self::CreateTable { schema } => schema.name.clone(),
+            Self::CreatePolicy { table, policy } => format!("{} ON {}", policy.name, table),
+            Self::CreateType { r#enum } => r#enum.name.clone(),
+            Self::Delete { table, .. } => table.clone(),
+            Self::DropFunction { name } => name.clone(),
+            Self::DropPolicy { table, name } => format!("{} ON {}", name, table),
+            Self::DropTable { table } => table.clone(),
+            Self::DropType { name } => name.clone(),
+            Self::Filter { predicate, .. } => predicate.to_string(),
+            Self::HashJoin { left_field, right_field, outer, build_left, .. } => format!(
+                "{} on {} = {}{}",
+                if *outer { "outer" } else { "inner" },
+                match left_field {
+                    (_, Some((Some(t), n))) => format!("{}.{}", t, n),
+                    (_, Some((None, n))) => n.clone(),
+                    (i, None) => format!("left #{}", i),
+                },
+                match right_field {
+                    (_, Some((Some(t), n))) => format!("{}.{}", t, n),
+                    (_, Some((None, n))) => n.clone(),
+                    (i, None) => format!("right #{}", i),
+                },
+                if *build_left { " (build left)" } else { "" },
+            ),
+            Self::History { table, key } => format!("{} key {}", table, key),
             Self::IndexLookup { table, column, alias, values } => {
-                s += &format!("IndexLookup: {}", table);
+                let mut s = table.clone();
                 if let Some(alias) = alias {
                     s += &format!(" as {}", alias);
                 }
@@ -319,13 +667,21 @@ impl Node {
                 } else {
                     s += &format!(" ({} values)", values.len());
                 }
-                s += "\n";
+                s
+            }
+            Self::IndexRange { table, alias, column, lower, upper } => {
+                let mut s = table.clone();
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" column {} ({} to {})", column, lower, upper);
+                s
             }
             Self::Insert { table, columns: _, expressions } => {
-                s += &format!("Insert: {} ({} rows)\n", table, expressions.len());
+                format!("{} ({} rows)", table, expressions.len())
             }
             Self::KeyLookup { table, alias, keys } => {
-                s += &format!("KeyLookup: {}", table);
+                let mut s = table.clone();
                 if let Some(alias) = alias {
                     s += &format!(" as {}", alias);
                 }
@@ -337,82 +693,201 @@ impl Node {
                 } else {
                     s += &format!(" ({} keys)", keys.len());
                 }
-                s += "\n";
-            }
-            Self::Limit { source, limit } => {
-                s += &format!("Limit: {}\n", limit);
-                s += &source.format(indent, false, true);
+                s
             }
-            Self::NestedLoopJoin { left, left_size: _, right, predicate, outer } => {
-                s += &format!("NestedLoopJoin: {}", if *outer { "outer" } else { "inner" });
+            Self::Limit { limit, .. } => limit.to_string(),
+            Self::NestedLoopJoin { predicate, outer, .. } => {
+                let mut s = if *outer { "outer" } else { "inner" }.to_string();
                 if let Some(expr) = predicate {
                     s += &format!(" on {}", expr);
                 }
-                s += "\n";
-                s += &left.format(indent.clone(), false, false);
-                s += &right.format(indent, false, true);
-            }
-            Self::Nothing {} => {
-                s += "Nothing\n";
-            }
-            Self::Offset { source, offset } => {
-                s += &format!("Offset: {}\n", offset);
-                s += &source.format(indent, false, true);
+                s
             }
-            Self::Order { source, orders } => {
-                s += &format!(
-                    "Order: {}\n",
-                    orders
-                        .iter()
-                        .map(|(expr, dir)| format!("{} {}", expr, dir))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                );
-                s += &source.format(indent, false, true);
+            Self::Nothing => return ("Nothing", None),
+            Self::Offset { offset, .. } => offset.to_string(),
+            Self::Order { orders, .. } => orders
+                .iter()
+                .map(|(expr, dir, nulls)| match nulls {
+                    n if *n == Nulls::default_for(dir) => format!("{} {}", expr, dir),
+                    n => format!("{} {} {}", expr, dir, n),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            Self::PartitionScan { table, alias, partitions, filter } => {
+                let mut s = table.clone();
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" partitions {}", partitions.join(", "));
+                if let Some(expr) = filter {
+                    s += &format!(" ({})", expr);
+                }
+                s
             }
-            Self::Projection { source, expressions } => {
-                s += &format!(
-                    "Projection: {}\n",
-                    expressions
-                        .iter()
-                        .map(|(expr, _)| expr.to_string())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                );
-                s += &source.format(indent, false, true);
+            Self::Projection { expressions, .. } => {
+                expressions.iter().map(|(expr, _)| expr.to_string()).collect::<Vec<_>>().join(", ")
             }
+            Self::RowVersions { table, .. } => table.clone(),
             Self::Scan { table, alias, filter } => {
-                s += &format!("Scan: {}", table);
+                let mut s = table.clone();
                 if let Some(alias) = alias {
                     s += &format!(" as {}", alias);
                 }
                 if let Some(expr) = filter {
                     s += &format!(" ({})", expr);
                 }
-                s += "\n";
-            }
-            Self::Update { source, table, expressions } => {
-                s += &format!(
-                    "Update: {} ({})\n",
-                    table,
-                    expressions
-                        .iter()
-                        .map(|(i, l, e)| format!(
-                            "{}={}",
-                            l.clone().unwrap_or_else(|| format!("#{}", i)),
-                            e
-                        ))
-                        .collect::<Vec<_>>()
-                        .join(",")
-                );
-                s += &source.format(indent, false, true);
+                s
             }
+            Self::Spool { id, .. } => format!("#{}", id),
+            Self::Update { table, expressions, .. } => format!(
+                "{} ({})",
+                table,
+                expressions
+                    .iter()
+                    .map(|(i, l, e)| format!(
+                        "{}={}",
+                        l.clone().unwrap_or_else(|| format!("#{}", i)),
+                        e
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
         };
+        let name = match self {
+            Self::AddColumn { .. } => "AddColumn",
+            Self::Aggregation { .. } => "Aggregation",
+            Self::AlterColumn { .. } => "AlterColumn",
+            Self::Changefeed { .. } => "Changefeed",
+            Self::CheckIndex { .. } => "CheckIndex",
+            Self::Comment { .. } => "Comment",
+            Self::CreateIndex { .. } => "CreateIndex",
+            Self::CreateFunction { .. } => "CreateFunction",
+            Self::CreateTable { .. } => "CreateTable",
+            Self::CreatePolicy { .. } => "CreatePolicy",
+            Self::CreateType { .. } => "CreateType",
+            Self::Delete { .. } => "Delete",
+            Self::DropFunction { .. } => "DropFunction",
+            Self::DropPolicy { .. } => "DropPolicy",
+            Self::DropTable { .. } => "DropTable",
+            Self::DropType { .. } => "DropType",
+            Self::Filter { .. } => "Filter",
+            Self::HashJoin { .. } => "HashJoin",
+            Self::History { .. } => "History",
+            Self::IndexLookup { .. } => "IndexLookup",
+            Self::IndexRange { .. } => "IndexRange",
+            Self::Insert { .. } => "Insert",
+            Self::KeyLookup { .. } => "KeyLookup",
+            Self::Limit { .. } => "Limit",
+            Self::NestedLoopJoin { .. } => "NestedLoopJoin",
+            Self::Nothing => "Nothing",
+            Self::Offset { .. } => "Offset",
+            Self::Order { .. } => "Order",
+            Self::PartitionScan { .. } => "PartitionScan",
+            Self::Projection { .. } => "Projection",
+            Self::RowVersions { .. } => "RowVersions",
+            Self::Scan { .. } => "Scan",
+            Self::SetSetting { .. } => "SetSetting",
+            Self::Spool { .. } => "Spool",
+            Self::Update { .. } => "Update",
+        };
+        (name, Some(details))
+    }
+
+    /// Returns a one-line label for the node, e.g. "Scan: movies as m".
+    fn label(&self) -> String {
+        match self.describe() {
+            (name, Some(details)) => format!("{}: {}", name, details),
+            (name, None) => name.to_string(),
+        }
+    }
+
+    /// Displays the node in the given format.
+    pub fn explain(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_string(),
+            Format::Json => self.format_json(),
+            Format::Dot => self.format_dot(),
+        }
+    }
+
+    // Displays the node, where prefix gives the node prefix.
+    pub fn format(&self, mut indent: String, root: bool, last: bool) -> String {
+        let mut s = indent.clone();
+        if !last {
+            s += "├─ ";
+            indent += "│  "
+        } else if !root {
+            s += "└─ ";
+            indent += "   ";
+        }
+        s += &self.label();
+        s += "\n";
+        let children = self.children();
+        for (i, child) in children.iter().enumerate() {
+            s += &child.format(indent.clone(), false, i == children.len() - 1);
+        }
         if root {
             s = s.trim_end().to_string()
         }
         s
     }
+
+    /// Displays the node as a JSON object with node, details, and children fields, where
+    /// children contains the child nodes and details is null if the node has none.
+    pub fn format_json(&self) -> String {
+        let (name, details) = self.describe();
+        format!(
+            "{{\"node\":{},\"details\":{},\"children\":[{}]}}",
+            json_string(name),
+            details.as_deref().map(json_string).unwrap_or_else(|| "null".into()),
+            self.children().iter().map(|c| c.format_json()).collect::<Vec<_>>().join(",")
+        )
+    }
+
+    /// Displays the node as a Graphviz DOT digraph, with an edge from each node to its children.
+    pub fn format_dot(&self) -> String {
+        let mut s = "digraph plan {\n    node [shape=box];\n".to_string();
+        self.format_dot_nodes(&mut s, &mut 0, "    ");
+        s += "}";
+        s
+    }
+
+    /// Appends the indented DOT statements for the node and its children, using sequential node
+    /// IDs starting at next_id. Returns the node's ID.
+    fn format_dot_nodes(&self, s: &mut String, next_id: &mut usize, indent: &str) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        s.push_str(&format!("{}n{} [label=\"{}\"];\n", indent, id, dot_escape(&self.label())));
+        for child in self.children() {
+            let child_id = child.format_dot_nodes(s, next_id, indent);
+            s.push_str(&format!("{}n{} -> n{};\n", indent, id, child_id));
+        }
+        id
+    }
+}
+
+/// Escapes a DOT string, for use within double quotes.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Formats a JSON string.
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
 }
 
 impl Display for Node {
@@ -422,8 +897,9 @@ impl Display for Node {
 }
 
 /// An aggregate operation
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Aggregate {
+    ArrayAgg,
     Average,
     Count,
     Max,
@@ -431,12 +907,38 @@ pub enum Aggregate {
     Sum,
 }
 
+impl Aggregate {
+    /// Returns the aggregate with the given function name, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "array_agg" => Some(Self::ArrayAgg),
+            "avg" => Some(Self::Average),
+            "count" => Some(Self::Count),
+            "max" => Some(Self::Max),
+            "min" => Some(Self::Min),
+            "sum" => Some(Self::Sum),
+            _ => None,
+        }
+    }
+
+    /// Returns the datatype of the aggregate's result, given the datatype of its input column.
+    pub fn datatype(&self, input: Option<&DataType>) -> Option<DataType> {
+        match self {
+            Self::ArrayAgg => input.map(|d| DataType::Array(Box::new(d.value_type()))),
+            Self::Count => Some(DataType::Integer),
+            Self::Max | Self::Min => input.cloned(),
+            Self::Average | Self::Sum => input.map(|d| d.value_type()),
+        }
+    }
+}
+
 impl Display for Aggregate {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "{}",
             match self {
+                Self::ArrayAgg => "array",
                 Self::Average => "average",
                 Self::Count => "count",
                 Self::Max => "maximum",
@@ -449,8 +951,16 @@ impl Display for Aggregate {
 
 pub type Aggregates = Vec<Aggregate>;
 
+/// An EXPLAIN output format
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Format {
+    Text,
+    Json,
+    Dot,
+}
+
 /// A sort order direction
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Ascending,
     Descending,
@@ -468,3 +978,35 @@ impl Display for Direction {
         )
     }
 }
+
+/// The sort placement of NULL values
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Nulls {
+    /// Returns the default NULL placement for a sort direction. NULL sorts before all other
+    /// values, as in the index key encoding, so it comes first in ascending order and last in
+    /// descending order.
+    pub fn default_for(direction: &Direction) -> Self {
+        match direction {
+            Direction::Ascending => Self::First,
+            Direction::Descending => Self::Last,
+        }
+    }
+}
+
+impl Display for Nulls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::First => "nulls first",
+                Self::Last => "nulls last",
+            }
+        )
+    }
+}
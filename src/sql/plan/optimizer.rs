@@ -1,8 +1,11 @@
 use super::super::schema::Catalog;
 use super::super::types::{Expression, Value};
-use super::Node;
+use super::{Hints, Node};
 use crate::error::Result;
 
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::mem::replace;
 
 /// A plan optimizer
@@ -10,8 +13,63 @@ pub trait Optimizer {
     fn optimize(&self, node: Node) -> Result<Node>;
 }
 
+/// A registry of named optimizers, which are applied in order.
+#[derive(Default)]
+pub struct Optimizers<'a> {
+    optimizers: Vec<(&'static str, Box<dyn Optimizer + 'a>)>,
+}
+
+impl<'a> Optimizers<'a> {
+    /// Creates a registry with the standard optimizers, configured by the given hints.
+    pub fn standard<C: Catalog>(catalog: &'a mut C, hints: &Hints) -> Self {
+        let catalog = &*catalog;
+        Self::default()
+            .with("ConstantFolder", ConstantFolder)
+            .with("PredicateInference", PredicateInference)
+            .with("FilterPushdown", FilterPushdown)
+            .with("IndexLookup", IndexLookup::new(catalog).with_no_index(hints.no_index.clone()))
+            .with("NoopCleaner", NoopCleaner)
+            .with("PartitionPruning", PartitionPruning::new(catalog))
+            .with("JoinType", JoinType::new(catalog))
+            .with("SubplanReuse", SubplanReuse)
+    }
+
+    /// Appends an optimizer to the registry.
+    pub fn with(mut self, name: &'static str, optimizer: impl Optimizer + 'a) -> Self {
+        self.optimizers.push((name, Box::new(optimizer)));
+        self
+    }
+
+    /// Removes an optimizer from the registry, by name.
+    pub fn without(mut self, name: &str) -> Self {
+        self.optimizers.retain(|(n, _)| *n != name);
+        self
+    }
+
+    /// Returns the names of the registered optimizers, in order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.optimizers.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Optimizes a node with each optimizer in order, and calls trace with the name of each
+    /// optimizer and the node it produced.
+    pub fn optimize<F>(&self, mut node: Node, mut trace: F) -> Result<Node>
+    where
+        F: FnMut(&'static str, &Node),
+    {
+        for (name, optimizer) in &self.optimizers {
+            node = optimizer.optimize(node)?;
+            trace(name, &node);
+        }
+        Ok(node)
+    }
+}
+
 /// A constant folding optimizer, which replaces constant expressions with their evaluated value, to
-/// prevent it from being re-evaluated over and over again during plan execution.
+/// prevent it from being re-evaluated over and over again during plan execution. It also simplifies
+/// expressions whose value is known regardless of their field values, following three-valued logic:
+/// x AND FALSE is FALSE, x OR TRUE is TRUE, NOT NOT x is x, comparisons with NULL are NULL, and
+/// conjunctions of contradictory ranges such as a > 5 AND a < 3 are FALSE.
 pub struct ConstantFolder;
 
 impl Optimizer for ConstantFolder {
@@ -25,13 +83,334 @@ impl Optimizer for ConstantFolder {
                         Ok(e)
                     }
                 },
-                &|e| Ok(e),
+                &|e| Ok(self.simplify(e)),
             )
         })
     }
 }
 
-/// A filter pushdown optimizer, which moves filter predicates into or closer to the source node.
+/// A range of values for a field, as optional lower and upper bounds. Each bound is given as a
+/// value and whether the bound is inclusive.
+type Range = (Option<(Value, bool)>, Option<(Value, bool)>);
+
+impl ConstantFolder {
+    /// Simplifies an expression whose children have already been simplified.
+    fn simplify(&self, expr: Expression) -> Expression {
+        use Expression::*;
+        match expr {
+            And(lhs, rhs) => match (*lhs, *rhs) {
+                (Constant(Value::Boolean(false)), _) | (_, Constant(Value::Boolean(false))) => {
+                    Constant(Value::Boolean(false))
+                }
+                (lhs, rhs) => {
+                    let expr = And(lhs.into(), rhs.into());
+                    if Self::contradicts(&expr) {
+                        Constant(Value::Boolean(false))
+                    } else {
+                        expr
+                    }
+                }
+            },
+            Or(lhs, rhs) => match (*lhs, *rhs) {
+                (Constant(Value::Boolean(true)), _) | (_, Constant(Value::Boolean(true))) => {
+                    Constant(Value::Boolean(true))
+                }
+                (lhs, rhs) => Or(lhs.into(), rhs.into()),
+            },
+            Not(expr) => match *expr {
+                Not(inner) => *inner,
+                expr => Not(expr.into()),
+            },
+            Equal(lhs, rhs) | GreaterThan(lhs, rhs) | LessThan(lhs, rhs)
+                if matches!(*lhs, Constant(Value::Null))
+                    || matches!(*rhs, Constant(Value::Null)) =>
+            {
+                Constant(Value::Null)
+            }
+            expr => expr,
+        }
+    }
+
+    /// Checks whether a conjunction contains field ranges that can never be satisfied, e.g.
+    /// a > 5 AND a < 3. Conjuncts that aren't field ranges are ignored.
+    fn contradicts(expr: &Expression) -> bool {
+        let mut conjuncts = vec![expr];
+        let mut ranges: HashMap<usize, Range> = HashMap::new();
+        while let Some(expr) = conjuncts.pop() {
+            if let Expression::And(lhs, rhs) = expr {
+                conjuncts.push(rhs);
+                conjuncts.push(lhs);
+                continue;
+            }
+            if let Some((field, (lower, upper))) = Self::as_range(expr) {
+                let range = ranges.entry(field).or_insert((None, None));
+                match (
+                    Self::bound(range.0.take(), lower, Ordering::Greater, false),
+                    Self::bound(range.1.take(), upper, Ordering::Less, false),
+                ) {
+                    (Some(lower), Some(upper)) => *range = (lower, upper),
+                    // The values can't be compared, so give up.
+                    _ => return false,
+                }
+            }
+        }
+        ranges.values().any(|range| match range {
+            (Some((lower, lower_incl)), Some((upper, upper_incl))) => {
+                match lower.partial_cmp(upper) {
+                    Some(Ordering::Greater) => true,
+                    Some(Ordering::Equal) => !(*lower_incl && *upper_incl),
+                    _ => false,
+                }
+            }
+            _ => false,
+        })
+    }
+
+    /// Returns the field range of a comparison between a field and a non-null constant, if any.
+    /// Disjunctions of ranges for the same field give the smallest range spanning both, which may
+    /// include values that neither matches, but is sufficient to detect contradictions.
+    fn as_range(expr: &Expression) -> Option<(usize, Range)> {
+        use Expression::*;
+        let (lhs, rhs) = match expr {
+            Equal(lhs, rhs) | GreaterThan(lhs, rhs) | LessThan(lhs, rhs) => (&**lhs, &**rhs),
+            Or(lhs, rhs) => {
+                let (lfield, (llower, lupper)) = Self::as_range(lhs)?;
+                let (rfield, (rlower, rupper)) = Self::as_range(rhs)?;
+                if lfield != rfield {
+                    return None;
+                }
+                return Some((
+                    lfield,
+                    (
+                        Self::bound(llower, rlower, Ordering::Greater, true)?,
+                        Self::bound(lupper, rupper, Ordering::Less, true)?,
+                    ),
+                ));
+            }
+            _ => return None,
+        };
+        let (field, value, flipped) = match (lhs, rhs) {
+            (Field(_, _), Constant(Value::Null)) | (Constant(Value::Null), Field(_, _)) => {
+                return None
+            }
+            (Field(i, _), Constant(v)) => (*i, v.clone(), false),
+            (Constant(v), Field(i, _)) => (*i, v.clone(), true),
+            _ => return None,
+        };
+        let range = match (expr, flipped) {
+            (Equal(_, _), _) => (Some((value.clone(), true)), Some((value, true))),
+            (GreaterThan(_, _), false) | (LessThan(_, _), true) => (Some((value, false)), None),
+            (GreaterThan(_, _), true) | (LessThan(_, _), false) => (None, Some((value, false))),
+            _ => return None,
+        };
+        Some((field, range))
+    }
+
+    /// Combines two optional bounds, where None is unbounded. For an intersection this picks the
+    /// bound with the given ordering relative to the other (i.e. the tighter one), while for a
+    /// union it picks the looser one and stays unbounded if either is. Returns None if the values
+    /// can't be compared.
+    fn bound(
+        a: Option<(Value, bool)>,
+        b: Option<(Value, bool)>,
+        ordering: Ordering,
+        union: bool,
+    ) -> Option<Option<(Value, bool)>> {
+        match (a, b) {
+            (Some((a, a_incl)), Some((b, b_incl))) => match a.partial_cmp(&b)? {
+                Ordering::Equal if union => Some(Some((a, a_incl || b_incl))),
+                Ordering::Equal => Some(Some((a, a_incl && b_incl))),
+                o if (o == ordering) != union => Some(Some((a, a_incl))),
+                _ => Some(Some((b, b_incl))),
+            },
+            (None, bound) | (bound, None) => Some(if union { None } else { bound }),
+        }
+    }
+}
+
+/// A predicate inference optimizer, which derives predicates implied by inner equijoins, e.g.
+/// a.id = b.id AND a.id = 5 implies b.id = 5. This allows FilterPushdown to push filters into both
+/// sides of joins, and IndexLookup to use lookups on both sides. Equalities are followed
+/// transitively, also across joins further down in the same query, and any predicate that only
+/// compares a single field with constants is transferred to all fields equal to it.
+pub struct PredicateInference;
+
+impl Optimizer for PredicateInference {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        // Infer predicates bottom-up, such that inferred predicates from lower joins are known
+        // to the nodes above them and won't be inferred again.
+        node.transform(&|n| Ok(n), &|n| match n {
+            Node::Filter { source, predicate } => {
+                let predicate = self.infer(predicate, &[(&source, 0)]);
+                Ok(Node::Filter { source, predicate })
+            }
+            Node::NestedLoopJoin {
+                left,
+                left_size,
+                right,
+                predicate: Some(predicate),
+                outer: false,
+            } => {
+                let predicate = self.infer(predicate, &[(&left, 0), (&right, left_size)]);
+                Ok(Node::NestedLoopJoin {
+                    left,
+                    left_size,
+                    right,
+                    predicate: Some(predicate),
+                    outer: false,
+                })
+            }
+            n => Ok(n),
+        })
+    }
+}
+
+impl PredicateInference {
+    /// Infers additional predicates for a predicate evaluated on rows from the given sources,
+    /// where source fields start at the given offsets. Returns the predicate with any inferred
+    /// predicates appended.
+    fn infer(&self, predicate: Expression, sources: &[(&Node, usize)]) -> Expression {
+        use Expression::*;
+        let mut facts = predicate.clone().into_cnf_vec();
+        for (source, offset) in sources {
+            self.collect(source, *offset, &mut facts);
+        }
+
+        // Build equivalence classes of fields from equalities, mapping each field to the lowest
+        // field in its class, and keep the labels of equal fields for use in inferred predicates.
+        let mut classes: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut labels = HashMap::new();
+        for fact in &facts {
+            if let Equal(lhs, rhs) = fact {
+                if let (Field(l, llabel), Field(r, rlabel)) = (&**lhs, &**rhs) {
+                    if l == r {
+                        continue;
+                    }
+                    labels.insert(*l, llabel.clone());
+                    labels.insert(*r, rlabel.clone());
+                    let (lroot, rroot) = (Self::root(&classes, *l), Self::root(&classes, *r));
+                    let (root, other) = (lroot.min(rroot), lroot.max(rroot));
+                    classes.insert(other, root);
+                    classes.entry(root).or_insert(root);
+                }
+            }
+        }
+        if classes.is_empty() {
+            return predicate;
+        }
+
+        // Transfer single-field predicates to all other fields in the same class.
+        let mut inferred: Vec<Expression> = Vec::new();
+        for fact in &facts {
+            let field = match Self::single_field(fact) {
+                Some(field) if classes.contains_key(&field) => field,
+                _ => continue,
+            };
+            let root = Self::root(&classes, field);
+            for other in classes.keys().copied().filter(|f| *f != field) {
+                if Self::root(&classes, other) != root {
+                    continue;
+                }
+                let expr = fact
+                    .clone()
+                    .transform(
+                        &|e| match e {
+                            Field(i, _) if i == field => Ok(Field(other, labels[&other].clone())),
+                            e => Ok(e),
+                        },
+                        &|e| Ok(e),
+                    )
+                    .unwrap();
+                if !facts.contains(&expr) && !inferred.contains(&expr) {
+                    inferred.push(expr);
+                }
+            }
+        }
+        match Expression::from_cnf_vec(inferred) {
+            Some(inferred) => And(predicate.into(), inferred.into()),
+            None => predicate,
+        }
+    }
+
+    /// Collects known facts about the rows emitted by a node, as conjunctions of predicates that
+    /// hold for all of them, with field references shifted by the given offset. Only nodes that
+    /// pass their source fields through unchanged are considered, and only the left side of outer
+    /// joins since unmatched rows are emitted with nulls on the right.
+    fn collect(&self, node: &Node, offset: usize, facts: &mut Vec<Expression>) {
+        let mut add = |predicate: &Expression| {
+            facts.extend(predicate.clone().into_cnf_vec().into_iter().map(|e| {
+                e.transform(
+                    &|e| match e {
+                        Expression::Field(i, label) => Ok(Expression::Field(i + offset, label)),
+                        e => Ok(e),
+                    },
+                    &|e| Ok(e),
+                )
+                .unwrap()
+            }))
+        };
+        match node {
+            Node::Filter { source, predicate } => {
+                add(predicate);
+                self.collect(source, offset, facts);
+            }
+            Node::NestedLoopJoin { left, left_size, right, predicate, outer } => {
+                if !outer {
+                    if let Some(predicate) = predicate {
+                        add(predicate);
+                    }
+                    self.collect(right, offset + left_size, facts);
+                }
+                self.collect(left, offset, facts);
+            }
+            Node::Scan { filter: Some(filter), .. } => add(filter),
+            _ => {}
+        }
+    }
+
+    /// Returns the root field of a field's equivalence class.
+    fn root(classes: &BTreeMap<usize, usize>, mut field: usize) -> usize {
+        while let Some(parent) = classes.get(&field).filter(|p| **p != field) {
+            field = *parent;
+        }
+        field
+    }
+
+    /// Returns the field referenced by a predicate, if the predicate only compares a single field
+    /// with constants. Only comparisons and logical operators are allowed, since these give the
+    /// same result for all equal values, e.g. unlike 1 / 2 and 1.0 / 2 even though 1 = 1.0.
+    fn single_field(expr: &Expression) -> Option<usize> {
+        use Expression::*;
+        let field = Cell::new(None);
+        let valid = expr.walk(&|e| match e {
+            Field(i, _) => match field.get() {
+                None => {
+                    field.set(Some(*i));
+                    true
+                }
+                Some(f) => f == *i,
+            },
+            Constant(_)
+            | And(_, _)
+            | Or(_, _)
+            | Not(_)
+            | Equal(_, _)
+            | GreaterThan(_, _)
+            | LessThan(_, _)
+            | IsNull(_) => true,
+            _ => false,
+        });
+        if valid {
+            field.get()
+        } else {
+            None
+        }
+    }
+}
+
+/// A filter pushdown optimizer, which moves filter predicates into or closer to the source node,
+/// e.g. through projections, into either side of joins, and below aggregations for predicates on
+/// group values.
 pub struct FilterPushdown;
 
 impl Optimizer for FilterPushdown {
@@ -58,7 +437,8 @@ impl Optimizer for FilterPushdown {
                     predicate: Some(predicate),
                     outer,
                 } => {
-                    let predicate = self.pushdown_join(predicate, &mut left, &mut right, left_size);
+                    let predicate =
+                        self.pushdown_join(predicate, &mut left, &mut right, left_size, outer);
                     Ok(Node::NestedLoopJoin { left, left_size, right, predicate, outer })
                 }
                 n => Ok(n),
@@ -78,29 +458,109 @@ impl FilterPushdown {
                 }
                 filter.replace(expression)
             }
-            Node::NestedLoopJoin { ref mut predicate, .. } => {
+            // Filters can't be merged into outer join predicates, since the join would then emit
+            // unmatched rows instead of filtering them out, but they can be pushed into the left
+            // source if they only reference it.
+            Node::NestedLoopJoin { ref mut left, left_size, outer: true, .. } => {
+                let (push_left, mut cnf): (Vec<Expression>, Vec<Expression>) =
+                    expression.into_cnf_vec().into_iter().partition(|e| {
+                        !e.contains(&|e| matches!(e, Expression::Field(i, _) if i >= left_size))
+                    });
+                if let Some(push_left) = Expression::from_cnf_vec(push_left) {
+                    if let Some(remainder) = self.pushdown(push_left, left) {
+                        cnf.push(remainder)
+                    }
+                }
+                Expression::from_cnf_vec(cnf)
+            }
+            // Inner joins take the filter as part of their predicate, which is then pushed down
+            // into either source based on the fields it references.
+            Node::NestedLoopJoin {
+                ref mut left,
+                left_size,
+                ref mut right,
+                ref mut predicate,
+                ..
+            } => {
                 if let Some(predicate) = predicate.take() {
                     expression = Expression::And(Box::new(expression), Box::new(predicate));
                 }
-                predicate.replace(expression)
+                *predicate = self.pushdown_join(expression, left, right, *left_size, false);
+                None
             }
             Node::Filter { ref mut predicate, .. } => {
                 let p = replace(predicate, Expression::Constant(Value::Null));
                 replace(predicate, Expression::And(Box::new(p), Box::new(expression)));
                 None
             }
+            // Projections can be bypassed by replacing field references with the projected
+            // expressions, as long as the fields are known.
+            Node::Projection { ref mut source, ref expressions }
+                if !expression.contains(
+                    &|e| matches!(e, Expression::Field(i, _) if *i >= expressions.len()),
+                ) =>
+            {
+                let expression = expression
+                    .transform(&|e| Ok(e), &|e| match e {
+                        Expression::Field(i, _) => Ok(expressions[i].0.clone()),
+                        e => Ok(e),
+                    })
+                    .unwrap();
+                self.pushdown_into(expression, source);
+                None
+            }
+            // Aggregations pass group values through in the fields following the aggregates, so
+            // predicates that only reference these can be evaluated before aggregating. Predicates
+            // without any field references are not pushed down, since an aggregation without
+            // groups emits a row even when its source is empty.
+            Node::Aggregation { ref mut source, ref aggregates } => {
+                let (push, cnf): (Vec<Expression>, Vec<Expression>) =
+                    expression.into_cnf_vec().into_iter().partition(|e| {
+                        e.contains(&|e| matches!(e, Expression::Field(_, _)))
+                            && !e.contains(
+                                &|e| matches!(e, Expression::Field(i, _) if *i < aggregates.len()),
+                            )
+                    });
+                if let Some(push) = Expression::from_cnf_vec(push) {
+                    self.pushdown_into(push, source);
+                }
+                Expression::from_cnf_vec(cnf)
+            }
+            // Row versions are appended after the source fields, so predicates that don't
+            // reference them can be evaluated before looking up the versions.
+            Node::RowVersions { ref mut source, size, .. } => {
+                let (push, cnf): (Vec<Expression>, Vec<Expression>) =
+                    expression.into_cnf_vec().into_iter().partition(|e| {
+                        !e.contains(&|e| matches!(e, Expression::Field(i, _) if i >= size))
+                    });
+                if let Some(push) = Expression::from_cnf_vec(push) {
+                    self.pushdown_into(push, source);
+                }
+                Expression::from_cnf_vec(cnf)
+            }
             _ => Some(expression),
         }
     }
 
+    /// Pushes an expression down into a target node, wrapping the target in a filter node for any
+    /// remaining expression.
+    fn pushdown_into(&self, expression: Expression, target: &mut Node) {
+        if let Some(remainder) = self.pushdown(expression, target) {
+            let source = replace(target, Node::Nothing);
+            *target = Node::Filter { source: Box::new(source), predicate: remainder };
+        }
+    }
+
     /// Attempts to partition a join predicate and push parts of it down into either source,
-    /// returning any remaining expression.
+    /// returning any remaining expression. For outer joins, nothing is pushed into the left source,
+    /// since its rows must be emitted even when the predicate doesn't match.
     fn pushdown_join(
         &self,
         predicate: Expression,
         left: &mut Node,
         right: &mut Node,
         boundary: usize,
+        outer: bool,
     ) -> Option<Expression> {
         // Convert the predicate into conjunctive normal form, and partition into expressions
         // only referencing the left or right sources, leaving cross-source expressions.
@@ -113,7 +573,7 @@ impl FilterPushdown {
                     _ => false,
                 })
             });
-        let (mut push_right, mut cnf): (Vec<Expression>, Vec<Expression>) =
+        let (push_right, mut cnf): (Vec<Expression>, Vec<Expression>) =
             cnf.into_iter().partition(|e| {
                 // Partition only if no expressions reference the left-hand source.
                 !e.contains(&|e| match e {
@@ -122,23 +582,10 @@ impl FilterPushdown {
                 })
             });
 
-        // Look for equijoins that have constant lookups on either side, and transfer the constants
-        // to the other side of the join as well. This allows index lookup optimization in both
-        // sides. We already know that the remaining cnf expressions span both sources.
-        for e in &cnf {
-            if let Expression::Equal(ref lhs, ref rhs) = e {
-                if let (Expression::Field(l, ln), Expression::Field(r, rn)) = (&**lhs, &**rhs) {
-                    let (l, ln, r, rn) = if l > r { (r, rn, l, ln) } else { (l, ln, r, rn) };
-                    if let Some(lvals) = push_left.iter().find_map(|e| e.as_lookup(*l)) {
-                        push_right.push(Expression::from_lookup(*r, rn.clone(), lvals));
-                    } else if let Some(rvals) = push_right.iter().find_map(|e| e.as_lookup(*r)) {
-                        push_left.push(Expression::from_lookup(*l, ln.clone(), rvals));
-                    }
-                }
-            }
-        }
-
         // Push predicates down into the sources.
+        if outer {
+            cnf.append(&mut push_left);
+        }
         if let Some(push_left) = Expression::from_cnf_vec(push_left) {
             if let Some(remainder) = self.pushdown(push_left, left) {
                 cnf.push(remainder)
@@ -163,14 +610,22 @@ impl FilterPushdown {
     }
 }
 
-/// An index lookup optimizer, which converts table scans to index lookups.
+/// An index lookup optimizer, which converts table scans to index lookups, or to index range
+/// scans for bounding box filters on indexed POINT columns (see Expression::as_bounding_box).
 pub struct IndexLookup<'a, C: Catalog> {
-    catalog: &'a mut C,
+    catalog: &'a C,
+    no_index: BTreeSet<String>,
 }
 
 impl<'a, C: Catalog> IndexLookup<'a, C> {
-    pub fn new(catalog: &'a mut C) -> Self {
-        Self { catalog }
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog, no_index: BTreeSet::new() }
+    }
+
+    /// Excludes the given tables, by query name (i.e. alias or actual name), from lookups.
+    pub fn with_no_index(mut self, tables: BTreeSet<String>) -> Self {
+        self.no_index = tables;
+        self
     }
 
     // Wraps a node in a filter for the given CNF vector, if any, otherwise returns the bare node.
@@ -186,7 +641,9 @@ impl<'a, C: Catalog> IndexLookup<'a, C> {
 impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
     fn optimize(&self, node: Node) -> Result<Node> {
         node.transform(&|n| Ok(n), &|n| match n {
-            Node::Scan { table, alias, filter: Some(filter) } => {
+            Node::Scan { table, alias, filter: Some(filter) }
+                if !self.no_index.contains(alias.as_ref().unwrap_or(&table)) =>
+            {
                 let columns = self.catalog.must_read_table(&table)?.columns;
                 let pk = columns.iter().position(|c| c.primary_key).unwrap();
 
@@ -214,6 +671,26 @@ impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
                         }
                     }
                 }
+
+                // Otherwise, try to convert a sub-expression into a bounding box of an indexed
+                // point column, and scan the box's range of the column's Z-curve encoded index.
+                // The box may contain points that don't match, so the filter is kept.
+                for expr in &cnf {
+                    for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.index) {
+                        if let Some((lower, upper)) = expr.as_bounding_box(ci) {
+                            return Ok(self.wrap_cnf(
+                                Node::IndexRange {
+                                    table,
+                                    alias,
+                                    column: column.name.clone(),
+                                    lower,
+                                    upper,
+                                },
+                                cnf,
+                            ));
+                        }
+                    }
+                }
                 Ok(Node::Scan { table, alias, filter: Some(filter) })
             }
             n => Ok(n),
@@ -221,9 +698,10 @@ impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
     }
 }
 
-/// Cleans up noops, e.g. filters with constant true/false predicates.
+/// Cleans up noops, e.g. filters with constant true/false predicates. Filters that can never match
+/// prune their source, by replacing table reads with empty key lookups.
 /// FIXME This should perhaps replace nodes that can never return anything with a Nothing node,
-/// but that requires propagating the column names.
+/// but that requires propagating the column names, which empty key lookups retain.
 pub struct NoopCleaner;
 
 impl Optimizer for NoopCleaner {
@@ -233,20 +711,20 @@ impl Optimizer for NoopCleaner {
             // While descending the node tree, clean up boolean expressions.
             &|n| {
                 n.transform_expressions(&|e| Ok(e), &|e| match &e {
+                    // NULL can't be simplified away like FALSE, since e.g. TRUE AND NULL is
+                    // NULL rather than FALSE, which matters in projections and under NOT.
                     And(lhs, rhs) => match (&**lhs, &**rhs) {
                         (Constant(Value::Boolean(false)), _)
-                        | (Constant(Value::Null), _)
-                        | (_, Constant(Value::Boolean(false)))
-                        | (_, Constant(Value::Null)) => Ok(Constant(Value::Boolean(false))),
+                        | (_, Constant(Value::Boolean(false))) => {
+                            Ok(Constant(Value::Boolean(false)))
+                        }
                         (Constant(Value::Boolean(true)), e)
                         | (e, Constant(Value::Boolean(true))) => Ok(e.clone()),
                         _ => Ok(e),
                     },
                     Or(lhs, rhs) => match (&**lhs, &**rhs) {
                         (Constant(Value::Boolean(false)), e)
-                        | (Constant(Value::Null), e)
-                        | (e, Constant(Value::Boolean(false)))
-                        | (e, Constant(Value::Null)) => Ok(e.clone()),
+                        | (e, Constant(Value::Boolean(false))) => Ok(e.clone()),
                         (Constant(Value::Boolean(true)), _)
                         | (_, Constant(Value::Boolean(true))) => Ok(Constant(Value::Boolean(true))),
                         _ => Ok(e),
@@ -256,22 +734,196 @@ impl Optimizer for NoopCleaner {
                 })
             },
             // While ascending the node tree, remove any unnecessary filters or nodes.
-            // FIXME This should replace scan and join predicates with None as well.
+            // FIXME This should replace join predicates with None as well.
             &|n| match n {
                 Node::Filter { source, predicate } => match predicate {
                     Expression::Constant(Value::Boolean(true)) => Ok(*source),
+                    predicate if Self::never_true(&predicate) => {
+                        Ok(Node::Filter { source: Box::new(self.prune(*source)?), predicate })
+                    }
                     predicate => Ok(Node::Filter { source, predicate }),
                 },
+                Node::NestedLoopJoin {
+                    left,
+                    left_size,
+                    right,
+                    predicate: Some(predicate),
+                    outer,
+                } if Self::never_true(&predicate) => Ok(Node::NestedLoopJoin {
+                    left: if outer { left } else { Box::new(self.prune(*left)?) },
+                    left_size,
+                    right: Box::new(self.prune(*right)?),
+                    predicate: Some(predicate),
+                    outer,
+                }),
+                Node::Scan { table, alias, filter } => match filter {
+                    Some(Expression::Constant(Value::Boolean(true))) => {
+                        Ok(Node::Scan { table, alias, filter: None })
+                    }
+                    Some(filter) if Self::never_true(&filter) => {
+                        Ok(Node::KeyLookup { table, alias, keys: Vec::new() })
+                    }
+                    filter => Ok(Node::Scan { table, alias, filter }),
+                },
                 n => Ok(n),
             },
         )
     }
 }
 
+impl NoopCleaner {
+    /// Checks whether a predicate can never be true, i.e. it's FALSE or NULL, a conjunction with
+    /// such a predicate, or a disjunction of them.
+    fn never_true(predicate: &Expression) -> bool {
+        match predicate {
+            Expression::Constant(Value::Boolean(false)) | Expression::Constant(Value::Null) => true,
+            Expression::And(lhs, rhs) => Self::never_true(lhs) || Self::never_true(rhs),
+            Expression::Or(lhs, rhs) => Self::never_true(lhs) && Self::never_true(rhs),
+            _ => false,
+        }
+    }
+
+    /// Prunes a node that can never return anything, by replacing all table reads with empty key
+    /// lookups. This avoids reading the tables, but retains their columns.
+    fn prune(&self, node: Node) -> Result<Node> {
+        node.transform(&|n| Ok(n), &|n| match n {
+            Node::Scan { table, alias, .. }
+            | Node::IndexLookup { table, alias, .. }
+            | Node::IndexRange { table, alias, .. } => {
+                Ok(Node::KeyLookup { table, alias, keys: Vec::new() })
+            }
+            n => Ok(n),
+        })
+    }
+}
+
+/// A partition pruning optimizer, which converts scans of partitioned tables into partition scans
+/// of only the partitions that the scan filter may match, by comparing the primary key ranges of
+/// its comparisons (as given by ConstantFolder) with the partition bounds. A scan where no
+/// partition can match is replaced by an empty key lookup.
+pub struct PartitionPruning<'a, C: Catalog> {
+    catalog: &'a C,
+}
+
+impl<'a, C: Catalog> PartitionPruning<'a, C> {
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog }
+    }
+
+    /// Checks whether an expression may match rows of a partition with the given inclusive lower
+    /// and exclusive upper bound, where None is unbounded. This is the case unless it's a range of
+    /// the given field that doesn't overlap the partition, or a conjunction or disjunction of such
+    /// ranges that can't. Since ranges are intervals, conjunctions can check each side separately.
+    fn may_match(
+        expr: &Expression,
+        field: usize,
+        lower: Option<&Value>,
+        upper: Option<&Value>,
+    ) -> bool {
+        match expr {
+            Expression::And(lhs, rhs) => {
+                Self::may_match(lhs, field, lower, upper)
+                    && Self::may_match(rhs, field, lower, upper)
+            }
+            Expression::Or(lhs, rhs) => {
+                Self::may_match(lhs, field, lower, upper)
+                    || Self::may_match(rhs, field, lower, upper)
+            }
+            expr => match ConstantFolder::as_range(expr) {
+                Some((f, range)) if f == field => Self::overlaps(lower, upper, &range),
+                _ => true,
+            },
+        }
+    }
+
+    /// Checks whether a partition with the given inclusive lower and exclusive upper bound, where
+    /// None is unbounded, may contain values in a range. Values that can't be compared may.
+    fn overlaps(lower: Option<&Value>, upper: Option<&Value>, range: &Range) -> bool {
+        if let (Some(lower), Some((value, inclusive))) = (lower, &range.1) {
+            match value.partial_cmp(lower) {
+                Some(Ordering::Less) => return false,
+                Some(Ordering::Equal) if !inclusive => return false,
+                _ => {}
+            }
+        }
+        if let (Some(upper), Some((value, _))) = (upper, &range.0) {
+            if let Some(Ordering::Greater) | Some(Ordering::Equal) = value.partial_cmp(upper) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl<'a, C: Catalog> Optimizer for PartitionPruning<'a, C> {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(&|n| Ok(n), &|n| match n {
+            Node::Scan { table, alias, filter } => {
+                let schema = self.catalog.must_read_table(&table)?;
+                if schema.partitions.is_empty() {
+                    return Ok(Node::Scan { table, alias, filter });
+                }
+                let pk = schema.columns.iter().position(|c| c.primary_key).unwrap();
+                let mut lower = None;
+                let mut partitions = Vec::new();
+                for partition in &schema.partitions {
+                    let upper = partition.upper.as_ref();
+                    if filter.as_ref().map(|f| Self::may_match(f, pk, lower, upper)).unwrap_or(true)
+                    {
+                        partitions.push(partition.name.clone());
+                    }
+                    lower = upper;
+                }
+                if partitions.is_empty() {
+                    return Ok(Node::KeyLookup { table, alias, keys: Vec::new() });
+                }
+                Ok(Node::PartitionScan { table, alias, partitions, filter })
+            }
+            n => Ok(n),
+        })
+    }
+}
+
 // Optimizes join types, currently by swapping nested-loop joins with hash joins where appropriate.
-pub struct JoinType;
+// Inner hash joins build their hash table from the source with the fewest estimated rows.
+pub struct JoinType<'a, C: Catalog> {
+    catalog: &'a C,
+}
+
+impl<'a, C: Catalog> JoinType<'a, C> {
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog }
+    }
 
-impl Optimizer for JoinType {
+    /// Estimates the number of rows emitted by a node, if known. Table reads are estimated by the
+    /// catalog's approximate row counts, ignoring the selectivity of any filters, except lookups
+    /// of a primary key or unique index which emit at most one row per key.
+    fn estimate(&self, node: &Node) -> Result<Option<u64>> {
+        Ok(match node {
+            Node::Scan { table, .. }
+            | Node::PartitionScan { table, .. }
+            | Node::IndexRange { table, .. } => Some(self.catalog.row_count(table)?),
+            Node::KeyLookup { keys, .. } => Some(keys.len() as u64),
+            Node::IndexLookup { table, column, values, .. } => {
+                if self.catalog.must_read_table(table)?.get_column(column)?.unique {
+                    Some(values.len() as u64)
+                } else {
+                    Some(self.catalog.row_count(table)?)
+                }
+            }
+            Node::Filter { source, .. }
+            | Node::Order { source, .. }
+            | Node::Projection { source, .. }
+            | Node::Spool { source, .. } => self.estimate(source)?,
+            Node::Limit { source, limit } => {
+                Some(self.estimate(source)?.map_or(*limit, |n| n.min(*limit)))
+            }
+            _ => None,
+        })
+    }
+}
+
+impl<'a, C: Catalog> Optimizer for JoinType<'a, C> {
     fn optimize(&self, node: Node) -> Result<Node> {
         node.transform(
             &|n| match n {
@@ -289,7 +941,19 @@ impl Optimizer for JoinType {
                         } else {
                             ((b, b_label), (a - left_size, a_label))
                         };
-                        Ok(Node::HashJoin { left, left_field, right, right_field, outer })
+                        let build_left = !outer
+                            && match (self.estimate(&left)?, self.estimate(&right)?) {
+                                (Some(l), Some(r)) => l < r,
+                                _ => false,
+                            };
+                        Ok(Node::HashJoin {
+                            left,
+                            left_field,
+                            right,
+                            right_field,
+                            outer,
+                            build_left,
+                        })
                     }
                     (a, b) => Ok(Node::NestedLoopJoin {
                         left,
@@ -305,3 +969,90 @@ impl Optimizer for JoinType {
         )
     }
 }
+
+/// Reuses identical table reads within a plan, e.g. both sides of a self-join, by wrapping them in
+/// spools with a shared id. Only one of them is executed, and its rows are shared with the others.
+/// A table read is a scan or lookup along with any filters on it, and reads are identical if they
+/// only differ by table aliases and field labels.
+pub struct SubplanReuse;
+
+impl Optimizer for SubplanReuse {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        let mut reads = Vec::new();
+        self.collect(&node, &mut reads)?;
+        let shared: Vec<Node> =
+            reads.into_iter().filter(|(_, count)| *count > 1).map(|(read, _)| read).collect();
+        if shared.is_empty() {
+            return Ok(node);
+        }
+        node.transform(&|n| Ok(n), &|n| {
+            if !self.is_read(&n) {
+                return Ok(n);
+            }
+            let normalized = self.normalize(&n)?;
+            match shared.iter().position(|read| *read == normalized) {
+                Some(i) => Ok(Node::Spool { source: Box::new(n), id: i + 1 }),
+                None => Ok(n),
+            }
+        })
+    }
+}
+
+impl SubplanReuse {
+    /// Counts the outermost table reads in a node by their normalized form.
+    fn collect(&self, node: &Node, reads: &mut Vec<(Node, usize)>) -> Result<()> {
+        if self.is_read(node) {
+            let normalized = self.normalize(node)?;
+            match reads.iter_mut().find(|(read, _)| *read == normalized) {
+                Some((_, count)) => *count += 1,
+                None => reads.push((normalized, 1)),
+            }
+            return Ok(());
+        }
+        for child in node.children() {
+            self.collect(child, reads)?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether a node is a table read, i.e. a scan or lookup with any filters on it. Empty
+    /// key lookups, e.g. from pruning, don't read anything and are not considered.
+    fn is_read(&self, node: &Node) -> bool {
+        match node {
+            Node::Scan { .. }
+            | Node::PartitionScan { .. }
+            | Node::IndexLookup { .. }
+            | Node::IndexRange { .. } => true,
+            Node::KeyLookup { keys, .. } => !keys.is_empty(),
+            Node::Filter { source, .. } => self.is_read(source),
+            _ => false,
+        }
+    }
+
+    /// Normalizes a table read by removing table aliases and field labels, which don't affect
+    /// the rows it returns.
+    fn normalize(&self, node: &Node) -> Result<Node> {
+        node.clone().transform(&|n| Ok(n), &|n| {
+            let n = match n {
+                Node::IndexLookup { table, alias: _, column, values } => {
+                    Node::IndexLookup { table, alias: None, column, values }
+                }
+                Node::IndexRange { table, alias: _, column, lower, upper } => {
+                    Node::IndexRange { table, alias: None, column, lower, upper }
+                }
+                Node::KeyLookup { table, alias: _, keys } => {
+                    Node::KeyLookup { table, alias: None, keys }
+                }
+                Node::Scan { table, alias: _, filter } => Node::Scan { table, alias: None, filter },
+                Node::PartitionScan { table, alias: _, partitions, filter } => {
+                    Node::PartitionScan { table, alias: None, partitions, filter }
+                }
+                n => n,
+            };
+            n.transform_expressions(&|e| Ok(e), &|e| match e {
+                Expression::Field(i, _) => Ok(Expression::Field(i, None)),
+                e => Ok(e),
+            })
+        })
+    }
+}
@@ -1,112 +1,309 @@
-use super::super::parser::ast;
-use super::super::schema::{Catalog, Column, Table};
-use super::super::types::{Expression, Value};
-use super::{Aggregate, Direction, Node, Plan};
+use super::super::functions;
+use super::super::parser::{ast, Parser};
+use super::super::schema::{
+    is_system_column, Catalog, Column, ColumnAlteration, Function, Partition, Policy, Table,
+    CREATED_VERSION, UPDATED_VERSION,
+};
+use super::super::types::{self, DataType, Enum, Expression, Value};
+use super::{Aggregate, Direction, Hints, Node, Nulls, Plan};
 use crate::error::{Error, Result};
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::mem::replace;
+use std::sync::Arc;
 
 /// A query plan builder.
 pub struct Planner<'a, C: Catalog> {
     catalog: &'a mut C,
+    /// Values for the statement's ? bind parameters, by position
+    params: &'a [Value],
+    /// Datatypes of the statement's ? bind parameters, by position, either given explicitly or
+    /// inferred from their context during planning
+    param_types: RefCell<HashMap<usize, DataType>>,
+    /// If true, the statement is planned without parameter values to infer their datatypes
+    describe: bool,
+    /// The stored functions currently being inlined, used to detect recursive calls
+    inlining: RefCell<Vec<String>>,
+    /// The session role to plan the statement for, if any
+    role: Option<&'a str>,
 }
 
 impl<'a, C: Catalog> Planner<'a, C> {
     /// Creates a new planner.
     pub fn new(catalog: &'a mut C) -> Self {
-        Self { catalog }
+        Self {
+            catalog,
+            params: &[],
+            param_types: RefCell::new(HashMap::new()),
+            describe: false,
+            inlining: RefCell::new(Vec::new()),
+            role: None,
+        }
+    }
+
+    /// Plans the statement for the given session role, if any. Tables created by the role are
+    /// owned by it, and scans of other tables are filtered by their policies for the role.
+    pub fn with_role(mut self, role: Option<&'a str>) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Binds values to the statement's ? parameters. These are planned as constants, so they can
+    /// be used anywhere a constant expression is allowed, e.g. LIMIT and OFFSET.
+    pub fn with_params(mut self, params: &'a [Value]) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Infers the datatypes of an AST statement's given number of ? parameters, without values
+    /// for them, by planning the statement. Datatypes that can't be inferred are None.
+    pub fn describe(
+        &mut self,
+        statement: ast::Statement,
+        parameters: usize,
+    ) -> Result<Vec<Option<DataType>>> {
+        self.describe = true;
+        self.build(statement)?;
+        let param_types = self.param_types.borrow();
+        Ok((0..parameters).map(|i| param_types.get(&i).cloned()).collect())
     }
 
     /// Builds a plan for an AST statement.
     pub fn build(&mut self, statement: ast::Statement) -> Result<Plan> {
-        Ok(Plan(self.build_statement(statement)?))
+        let hints = match &statement {
+            ast::Statement::Select { hints, .. } => self.build_hints(hints)?,
+            _ => Hints::default(),
+        };
+        let mut node = self.build_statement(statement, &hints)?;
+        if hints.wrapping_arithmetic {
+            node = node.transform(&Ok, &|n| n.transform_expressions(&Ok, &|e| Ok(e.wrapping())))?;
+        }
+        Ok(Plan(node, hints))
     }
 
-    /// Builds a plan node for a statement.
-    fn build_statement(&self, statement: ast::Statement) -> Result<Node> {
+    /// Builds query hints.
+    fn build_hints(&self, hints: &[ast::Hint]) -> Result<Hints> {
+        let mut result = Hints::default();
+        for hint in hints {
+            match hint {
+                ast::Hint::NoIndex(tables) => result.no_index.extend(tables.iter().cloned()),
+                ast::Hint::JoinOrder(_) if !result.join_order.is_empty() => {
+                    return Err(Error::Value("Duplicate JOIN_ORDER hint".into()))
+                }
+                ast::Hint::JoinOrder(tables) => result.join_order = tables.clone(),
+                ast::Hint::AuditOuterJoins => result.audit_outer_joins = true,
+                ast::Hint::WrappingArithmetic => result.wrapping_arithmetic = true,
+            }
+        }
+        Ok(result)
+    }
+
+    /// Builds a plan node for a statement, respecting the given query hints.
+    fn build_statement(&self, statement: ast::Statement, hints: &Hints) -> Result<Node> {
         Ok(match statement {
             // Transaction control and explain statements should have been handled by session.
-            ast::Statement::Begin { .. } | ast::Statement::Commit | ast::Statement::Rollback => {
+            ast::Statement::Begin { .. }
+            | ast::Statement::Commit { .. }
+            | ast::Statement::Rollback { .. }
+            | ast::Statement::Detach
+            | ast::Statement::Resume(_)
+            | ast::Statement::ShowTransaction
+            | ast::Statement::ShowCreateTable(_) => {
                 return Err(Error::Internal(format!(
                     "Unexpected transaction statement {:?}",
                     statement
                 )))
             }
 
-            ast::Statement::Explain(_) => {
+            ast::Statement::Explain { .. } => {
                 return Err(Error::Internal("Unexpected explain statement".into()))
             }
 
+            ast::Statement::Declare { .. }
+            | ast::Statement::Fetch { .. }
+            | ast::Statement::Close(_) => {
+                return Err(Error::Internal(format!("Unexpected cursor statement {:?}", statement)))
+            }
+
             // DDL statements (schema changes).
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable {
-                schema: Table::new(
+            ast::Statement::CreateTable { name, columns, partition_by } => {
+                let mut schema = Table::new(
                     name,
-                    columns
-                        .into_iter()
-                        .map(|c| {
-                            let nullable = c.nullable.unwrap_or(!c.primary_key);
-                            let default = match c.default {
-                                Some(expr) => Some(self.evaluate_constant(expr)?),
-                                None if nullable => Some(Value::Null),
-                                None => None,
-                            };
-                            Ok(Column {
-                                name: c.name,
-                                datatype: c.datatype,
-                                primary_key: c.primary_key,
-                                nullable,
-                                default,
-                                index: c.index && !c.primary_key,
-                                unique: c.unique || c.primary_key,
-                                references: c.references,
-                            })
-                        })
-                        .collect::<Result<_>>()?,
-                )?,
-            },
+                    columns.into_iter().map(|c| self.build_column(c)).collect::<Result<_>>()?,
+                )?;
+                if let Some(partition_by) = partition_by {
+                    schema.partitions = self.build_partitions(&schema, partition_by)?;
+                }
+                schema.owner = self.role.map(String::from);
+                Node::CreateTable { schema }
+            }
 
             ast::Statement::DropTable(table) => Node::DropTable { table },
 
+            ast::Statement::AddColumn { table, column } => {
+                Node::AddColumn { table, column: self.build_column(column)? }
+            }
+
+            ast::Statement::CreateIndex { table, column } => Node::CreateIndex { table, column },
+
+            ast::Statement::AlterColumn { table, column, alteration } => Node::AlterColumn {
+                table,
+                column,
+                alteration: match alteration {
+                    ast::ColumnAlteration::SetNotNull => ColumnAlteration::SetNotNull,
+                    ast::ColumnAlteration::DropNotNull => ColumnAlteration::DropNotNull,
+                    ast::ColumnAlteration::SetDefault(expr) => {
+                        ColumnAlteration::SetDefault(self.evaluate_constant(expr)?)
+                    }
+                    ast::ColumnAlteration::DropDefault => ColumnAlteration::DropDefault,
+                },
+            },
+
+            ast::Statement::Comment { table, column, comment } => {
+                Node::Comment { table, column, comment }
+            }
+
+            ast::Statement::SetSetting { name, value } => Node::SetSetting {
+                name,
+                value: value.map(|expr| self.evaluate_constant(expr)).transpose()?,
+            },
+
+            ast::Statement::CreateFunction { name, parameters, body } => {
+                let parameters = parameters
+                    .into_iter()
+                    .map(|(name, datatype)| Ok((name, self.resolve_datatype(datatype)?)))
+                    .collect::<Result<_>>()?;
+                let function = Function { name, parameters, body };
+                self.validate_function(&function)?;
+                Node::CreateFunction { function }
+            }
+
+            ast::Statement::DropFunction(name) => Node::DropFunction { name },
+
+            ast::Statement::CreateType { name, labels } => {
+                if name == "point" {
+                    return Err(Error::Value(format!("Type {} already exists", name)));
+                }
+                let mut seen = HashSet::new();
+                for label in &labels {
+                    if !seen.insert(label) {
+                        return Err(Error::Value(format!(
+                            "Duplicate label {} for type {}",
+                            label, name
+                        )));
+                    }
+                }
+                Node::CreateType { r#enum: Enum { name, labels } }
+            }
+
+            ast::Statement::DropType(name) => Node::DropType { name },
+
+            ast::Statement::CreatePolicy { name, table, role, predicate } => {
+                let schema = self.catalog.must_read_table(&table)?;
+                self.ensure_owner(&schema)?;
+                let scope = &mut Scope::from_table(schema)?;
+                let predicate = self.build_expression(scope, predicate)?;
+                match scope.datatype(&predicate) {
+                    Some(DataType::Boolean) | None => {}
+                    Some(datatype) => {
+                        return Err(Error::Value(format!(
+                            "Policy predicate must be a BOOLEAN expression, found {}",
+                            datatype
+                        )))
+                    }
+                }
+                Node::CreatePolicy { table, policy: Policy { name, role, predicate } }
+            }
+
+            ast::Statement::DropPolicy { name, table } => {
+                self.ensure_owner(&self.catalog.must_read_table(&table)?)?;
+                Node::DropPolicy { table, name }
+            }
+
+            // Changefeeds.
+            ast::Statement::Changefeed { table, from } => {
+                self.ensure_unrestricted(&self.catalog.must_read_table(&table)?)?;
+                Node::Changefeed { table, from: from.unwrap_or(0) }
+            }
+
+            ast::Statement::History { table, key } => {
+                self.ensure_unrestricted(&self.catalog.must_read_table(&table)?)?;
+                Node::History { table, key: self.evaluate_constant(key)? }
+            }
+
+            ast::Statement::CheckIndex { table, column } => {
+                self.catalog.must_read_table(&table)?;
+                Node::CheckIndex { table, column }
+            }
+
             // DML statements (mutations).
             ast::Statement::Delete { table, r#where } => {
-                let scope = &mut Scope::from_table(self.catalog.must_read_table(&table)?)?;
+                let schema = self.catalog.must_read_table(&table)?;
+                let policy = self.policy_filter(&schema);
+                let scope = &mut Scope::from_table(schema)?;
+                let filter = r#where.map(|e| self.build_expression(scope, e)).transpose()?;
                 Node::Delete {
                     table: table.clone(),
                     source: Box::new(Node::Scan {
                         table,
                         alias: None,
-                        filter: r#where.map(|e| self.build_expression(scope, e)).transpose()?,
+                        filter: Self::and_filters(policy, filter),
                     }),
                 }
             }
 
-            ast::Statement::Insert { table, columns, values } => Node::Insert {
-                table,
-                columns: columns.unwrap_or_else(Vec::new),
-                expressions: values
-                    .into_iter()
-                    .map(|exprs| {
-                        exprs
-                            .into_iter()
-                            .map(|expr| self.build_expression(&mut Scope::constant(), expr))
-                            .collect::<Result<_>>()
-                    })
-                    .collect::<Result<_>>()?,
-            },
+            ast::Statement::Insert { table, columns, values } => {
+                // Parameters take the datatype of the column they're inserted into.
+                if self.describe {
+                    if let Some(schema) = self.catalog.read_table(&table)? {
+                        let names = match &columns {
+                            Some(columns) => columns.clone(),
+                            None => schema.columns.iter().map(|c| c.name.clone()).collect(),
+                        };
+                        for exprs in &values {
+                            for (expr, name) in exprs.iter().zip(&names) {
+                                if let Ok(column) = schema.get_column(name) {
+                                    self.infer_parameter(expr, Some(column.datatype.clone()));
+                                }
+                            }
+                        }
+                    }
+                }
+                Node::Insert {
+                    table,
+                    columns: columns.unwrap_or_else(Vec::new),
+                    expressions: values
+                        .into_iter()
+                        .map(|exprs| {
+                            exprs
+                                .into_iter()
+                                .map(|expr| self.build_expression(&mut Scope::constant(), expr))
+                                .collect::<Result<_>>()
+                        })
+                        .collect::<Result<_>>()?,
+                }
+            }
 
             ast::Statement::Update { table, set, r#where } => {
-                let scope = &mut Scope::from_table(self.catalog.must_read_table(&table)?)?;
+                let schema = self.catalog.must_read_table(&table)?;
+                let policy = self.policy_filter(&schema);
+                let scope = &mut Scope::from_table(schema.clone())?;
+                let filter = r#where.map(|e| self.build_expression(scope, e)).transpose()?;
                 Node::Update {
                     table: table.clone(),
                     source: Box::new(Node::Scan {
                         table,
                         alias: None,
-                        filter: r#where.map(|e| self.build_expression(scope, e)).transpose()?,
+                        filter: Self::and_filters(policy, filter),
                     }),
                     expressions: set
                         .into_iter()
                         .map(|(c, e)| {
+                            // Parameters take the datatype of the column they're assigned to.
+                            if let Ok(column) = schema.get_column(&c) {
+                                self.infer_parameter(&e, Some(column.datatype.clone()));
+                            }
                             Ok((
                                 scope.resolve(None, &c)?,
                                 Some(c),
@@ -122,22 +319,55 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 mut select,
                 from,
                 r#where,
-                group_by,
+                mut group_by,
                 mut having,
                 mut order,
                 offset,
                 limit,
+                hints: _,
             } => {
                 let scope = &mut Scope::new();
 
+                // Add system columns to the FROM tables if the query references them anywhere,
+                // including join predicates.
+                let mut exprs: Vec<&ast::Expression> = select
+                    .iter()
+                    .map(|(e, _)| e)
+                    .chain(r#where.iter())
+                    .chain(group_by.iter())
+                    .chain(having.iter())
+                    .chain(order.iter().map(|(e, _, _)| e))
+                    .collect();
+                let mut items: Vec<&ast::FromItem> = from.iter().collect();
+                while let Some(item) = items.pop() {
+                    if let ast::FromItem::Join { left, right, predicate, .. } = item {
+                        items.push(left);
+                        items.push(right);
+                        exprs.extend(predicate);
+                    }
+                }
+                scope.system_columns = exprs.into_iter().any(|e| {
+                    e.contains(
+                        &|e| matches!(e, ast::Expression::Field(_, name) if is_system_column(name)),
+                    )
+                });
+
                 // Build FROM clause.
-                let mut node = if !from.is_empty() {
-                    self.build_from_clause(scope, from)?
+                let (mut node, restore) = if !from.is_empty() {
+                    self.build_from_clause(scope, from, &hints.join_order)?
                 } else if select.is_empty() {
                     return Err(Error::Value("Can't select * without a table".into()));
                 } else {
-                    Node::Nothing
+                    (Node::Nothing, None)
                 };
+                for table in &hints.no_index {
+                    if !scope.tables.contains_key(table) {
+                        return Err(Error::Value(format!(
+                            "Unknown table {} in NO_INDEX hint",
+                            table
+                        )));
+                    }
+                }
 
                 // Build WHERE clause.
                 if let Some(expr) = r#where {
@@ -147,6 +377,58 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     };
                 };
 
+                // If FROM items were joined out of order, restore the column order for SELECT *.
+                // This is done after the WHERE clause, to allow filters to be pushed down into the
+                // joins.
+                if let (Some(expressions), true) = (restore, select.is_empty()) {
+                    scope.project(&expressions)?;
+                    node = Node::Projection { source: Box::new(node), expressions };
+                }
+
+                // SELECT * doesn't include system columns, so expand it to the table columns.
+                if select.is_empty() && scope.system_columns {
+                    select = scope
+                        .columns
+                        .iter()
+                        .filter_map(|(table, label)| match label {
+                            Some(label) if !is_system_column(label) => {
+                                Some((ast::Expression::Field(table.clone(), label.clone()), None))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                }
+
+                // Resolve GROUP BY ordinals, i.e. 1-based positions of SELECT expressions, to the
+                // expressions themselves. This must be done before hidden columns are added.
+                for expr in group_by.iter_mut() {
+                    if let ast::Expression::Literal(ast::Literal::Integer(i)) = *expr {
+                        if i < 1 || i as usize > select.len() {
+                            return Err(Error::Value(format!(
+                                "GROUP BY position {} is not in select list",
+                                i
+                            )));
+                        }
+                        *expr = select[i as usize - 1].0.clone();
+                    }
+                }
+
+                // Resolve ORDER BY ordinals, i.e. 1-based positions of SELECT output columns, to
+                // column references. Only plain integer literals are ordinals, other constant
+                // expressions are sorted on as usual.
+                let columns = if select.is_empty() { scope.len() } else { select.len() };
+                for (expr, _, _) in order.iter_mut() {
+                    if let ast::Expression::Literal(ast::Literal::Integer(i)) = *expr {
+                        if i < 1 || i as usize > columns {
+                            return Err(Error::Value(format!(
+                                "ORDER BY position {} is not in select list",
+                                i
+                            )));
+                        }
+                        *expr = ast::Expression::Column(i as usize - 1);
+                    }
+                }
+
                 // Build SELECT clause.
                 let mut hidden = 0;
                 if !select.is_empty() {
@@ -156,10 +438,21 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     if let Some(ref mut expr) = having {
                         hidden += self.inject_hidden(expr, &mut select)?;
                     }
-                    for (expr, _) in order.iter_mut() {
+                    for (expr, _, _) in order.iter_mut() {
                         hidden += self.inject_hidden(expr, &mut select)?;
                     }
 
+                    // Derive output column names for unlabeled expressions, before aggregates
+                    // are extracted. They're only applied to the output columns below, not the
+                    // scope, so they can't be referenced like AS labels.
+                    let names: Vec<Option<String>> = select
+                        .iter()
+                        .map(|(e, l)| match l {
+                            Some(_) => None,
+                            None => self.build_column_name(scope, e),
+                        })
+                        .collect();
+
                     // Extract any aggregate functions and GROUP BY expressions, replacing them with
                     // Column placeholders. Aggregations are handled by evaluating group expressions
                     // and aggregate function arguments in a pre-projection, passing the results
@@ -176,17 +469,23 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     // - Aggregation: max(#0), min(#1) group by #2
                     // - Projection: (#0 - #1) / 100
                     let aggregates = self.extract_aggregates(&mut select)?;
-                    let groups = self.extract_groups(&mut select, group_by, aggregates.len())?;
+                    let groups =
+                        self.extract_groups(scope, &mut select, group_by, aggregates.len())?;
                     if !aggregates.is_empty() || !groups.is_empty() {
                         node = self.build_aggregation(scope, node, groups, aggregates)?;
                     }
 
                     // Build the remaining non-aggregate projection.
-                    let expressions: Vec<(Expression, Option<String>)> = select
+                    let mut expressions: Vec<(Expression, Option<String>)> = select
                         .into_iter()
                         .map(|(e, l)| Ok((self.build_expression(scope, e)?, l)))
                         .collect::<Result<_>>()?;
                     scope.project(&expressions)?;
+                    for ((_, label), name) in expressions.iter_mut().zip(names) {
+                        if label.is_none() {
+                            *label = name;
+                        }
+                    }
                     node = Node::Projection { source: Box::new(node), expressions };
                 };
 
@@ -204,14 +503,17 @@ impl<'a, C: Catalog> Planner<'a, C> {
                         source: Box::new(node),
                         orders: order
                             .into_iter()
-                            .map(|(e, o)| {
-                                Ok((
-                                    self.build_expression(scope, e)?,
-                                    match o {
-                                        ast::Order::Ascending => Direction::Ascending,
-                                        ast::Order::Descending => Direction::Descending,
-                                    },
-                                ))
+                            .map(|(e, o, n)| {
+                                let direction = match o {
+                                    ast::Order::Ascending => Direction::Ascending,
+                                    ast::Order::Descending => Direction::Descending,
+                                };
+                                let nulls = match n {
+                                    Some(ast::Nulls::First) => Nulls::First,
+                                    Some(ast::Nulls::Last) => Nulls::Last,
+                                    None => Nulls::default_for(&direction),
+                                };
+                                Ok((self.build_expression(scope, e)?, direction, nulls))
                             })
                             .collect::<Result<_>>()?,
                     };
@@ -219,6 +521,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
                 // Build OFFSET clause.
                 if let Some(expr) = offset {
+                    self.infer_parameter(&expr, Some(DataType::Integer));
                     node = Node::Offset {
                         source: Box::new(node),
                         offset: match self.evaluate_constant(expr)? {
@@ -230,6 +533,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
                 // Build LIMIT clause.
                 if let Some(expr) = limit {
+                    self.infer_parameter(&expr, Some(DataType::Integer));
                     node = Node::Limit {
                         source: Box::new(node),
                         limit: match self.evaluate_constant(expr)? {
@@ -257,26 +561,91 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// Builds a FROM clause consisting of several items. Each item is either a single table or a
     /// join of an arbitrary number of tables. All of the items are joined, since e.g. 'SELECT * FROM
     /// a, b' is an implicit join of a and b.
-    fn build_from_clause(&self, scope: &mut Scope, from: Vec<ast::FromItem>) -> Result<Node> {
+    ///
+    /// The items are joined in the order given by the join_order hint, if any. If this differs
+    /// from the FROM order, a projection that restores the FROM column order is returned too.
+    #[allow(clippy::type_complexity)]
+    fn build_from_clause(
+        &self,
+        scope: &mut Scope,
+        from: Vec<ast::FromItem>,
+        join_order: &[String],
+    ) -> Result<(Node, Option<Vec<(Expression, Option<String>)>>)> {
+        let order = self.build_join_order(&from, join_order)?;
         let base_scope = scope.clone();
-        let mut items = from.into_iter();
-        let mut node = match items.next() {
-            Some(item) => self.build_from_item(scope, item)?,
-            None => return Err(Error::Value("No from items given".into())),
-        };
-        for item in items {
-            let mut right_scope = base_scope.clone();
-            let right = self.build_from_item(&mut right_scope, item)?;
-            node = Node::NestedLoopJoin {
-                left: Box::new(node),
-                left_size: scope.len(),
-                right: Box::new(right),
-                predicate: None,
-                outer: false,
-            };
-            scope.merge(right_scope)?;
+        let mut items: Vec<Option<ast::FromItem>> = from.into_iter().map(Some).collect();
+        let mut columns = vec![0..0; items.len()];
+        let mut node = None;
+        for &i in &order {
+            let item = items[i].take().ok_or_else(|| Error::Internal("Missing item".into()))?;
+            let mut item_scope = base_scope.clone();
+            let right = self.build_from_item(&mut item_scope, item)?;
+            node = Some(match node {
+                None => {
+                    *scope = item_scope;
+                    columns[i] = 0..scope.len();
+                    right
+                }
+                Some(left) => {
+                    let left_size = scope.len();
+                    scope.merge(item_scope)?;
+                    columns[i] = left_size..scope.len();
+                    Node::NestedLoopJoin {
+                        left: Box::new(left),
+                        left_size,
+                        right: Box::new(right),
+                        predicate: None,
+                        outer: false,
+                    }
+                }
+            });
         }
-        Ok(node)
+        let node = node.ok_or_else(|| Error::Value("No from items given".into()))?;
+        if order.iter().enumerate().all(|(i, &item)| i == item) {
+            return Ok((node, None));
+        }
+        let restore = columns
+            .into_iter()
+            .flatten()
+            .map(|i| Ok((Expression::Field(i, scope.get_label(i)?), None)))
+            .collect::<Result<_>>()?;
+        Ok((node, Some(restore)))
+    }
+
+    /// Builds the order in which to join FROM items, as item indexes, given a join_order hint of
+    /// table names. An item which is an explicit join can be given by any of its tables, and is
+    /// joined as a whole. Unlisted items are joined last, in FROM order.
+    fn build_join_order(
+        &self,
+        from: &[ast::FromItem],
+        join_order: &[String],
+    ) -> Result<Vec<usize>> {
+        fn contains(item: &ast::FromItem, table: &str) -> bool {
+            match item {
+                ast::FromItem::Table { name, alias } => alias.as_ref().unwrap_or(name) == table,
+                ast::FromItem::Join { left, right, .. } => {
+                    contains(left, table) || contains(right, table)
+                }
+            }
+        }
+        let mut order = Vec::new();
+        for table in join_order {
+            match from.iter().position(|item| contains(item, table)) {
+                Some(i) if order.contains(&i) => {
+                    return Err(Error::Value(format!(
+                        "Table {} in JOIN_ORDER hint is already joined",
+                        table
+                    )))
+                }
+                Some(i) => order.push(i),
+                None => {
+                    return Err(Error::Value(format!("Unknown table {} in JOIN_ORDER hint", table)))
+                }
+            }
+        }
+        let unlisted: Vec<usize> = (0..from.len()).filter(|i| !order.contains(i)).collect();
+        order.extend(unlisted);
+        Ok(order)
     }
 
     /// Builds FROM items, which can either be a single table or a chained join of multiple tables,
@@ -286,11 +655,16 @@ impl<'a, C: Catalog> Planner<'a, C> {
     fn build_from_item(&self, scope: &mut Scope, item: ast::FromItem) -> Result<Node> {
         Ok(match item {
             ast::FromItem::Table { name, alias } => {
-                scope.add_table(
-                    alias.clone().unwrap_or_else(|| name.clone()),
-                    self.catalog.must_read_table(&name)?,
-                )?;
-                Node::Scan { table: name, alias, filter: None }
+                let table = self.catalog.must_read_table(&name)?;
+                let size = table.columns.len();
+                let filter = self.policy_filter(&table);
+                scope.add_table(alias.clone().unwrap_or_else(|| name.clone()), table)?;
+                let node = Node::Scan { table: name.clone(), alias, filter };
+                if scope.system_columns {
+                    Node::RowVersions { source: Box::new(node), table: name, size }
+                } else {
+                    node
+                }
             }
 
             ast::FromItem::Join { left, right, r#type, predicate } => {
@@ -366,7 +740,8 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
     /// Extracts aggregate functions from an AST expression tree. This finds the aggregate
     /// function calls, replaces them with ast::Expression::Column(i), maps the aggregate functions
-    /// to aggregates, and returns them along with their argument expressions.
+    /// to aggregates, and returns them along with their argument expressions. The arguments can be
+    /// arbitrary non-aggregate expressions, and identical aggregate calls are only computed once.
     fn extract_aggregates(
         &self,
         exprs: &mut [(ast::Expression, Option<String>)],
@@ -375,13 +750,26 @@ impl<'a, C: Catalog> Planner<'a, C> {
         for (expr, _) in exprs {
             expr.transform_mut(
                 &mut |mut e| match &mut e {
-                    ast::Expression::Function(f, args) if args.len() == 1 => {
-                        if let Some(aggregate) = self.aggregate_from_name(f) {
-                            aggregates.push((aggregate, args.remove(0)));
-                            Ok(ast::Expression::Column(aggregates.len() - 1))
-                        } else {
-                            Ok(e)
+                    ast::Expression::Function(f, args) => {
+                        let aggregate = match Aggregate::from_name(f) {
+                            Some(aggregate) => aggregate,
+                            None => return Ok(e),
+                        };
+                        if args.len() != 1 {
+                            return Err(Error::Value(format!(
+                                "Aggregate function {} takes 1 argument, got {}",
+                                f,
+                                args.len()
+                            )));
+                        }
+                        let arg = args.remove(0);
+                        if let Some(i) =
+                            aggregates.iter().position(|(a, e)| a == &aggregate && e == &arg)
+                        {
+                            return Ok(ast::Expression::Column(i));
                         }
+                        aggregates.push((aggregate, arg));
+                        Ok(ast::Expression::Column(aggregates.len() - 1))
                     }
                     _ => Ok(e),
                 },
@@ -403,8 +791,12 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// SELECT released / 100 AS century, COUNT(*) FROM movies GROUP BY century
     /// SELECT released / 100, COUNT(*) FROM movies GROUP BY released / 100
     /// SELECT COUNT(*) FROM movies GROUP BY released / 100
+    ///
+    /// A SELECT label that is also the name of a field in scope is ambiguous, unless the labeled
+    /// expression is that field, e.g. SELECT title AS name FROM movies, genres GROUP BY name.
     fn extract_groups(
         &self,
+        scope: &Scope,
         exprs: &mut Vec<(ast::Expression, Option<String>)>,
         group_by: Vec<ast::Expression>,
         offset: usize,
@@ -414,6 +806,19 @@ impl<'a, C: Catalog> Planner<'a, C> {
             // Look for references to SELECT columns with AS labels
             if let ast::Expression::Field(None, label) = &g {
                 if let Some(i) = exprs.iter().position(|(_, l)| l.as_deref() == Some(label)) {
+                    if scope.unqualified.contains_key(label) || scope.ambiguous.contains(label) {
+                        let field = scope.resolve(None, label).ok();
+                        let labeled = match &exprs[i].0 {
+                            ast::Expression::Field(t, n) => scope.resolve(t.as_deref(), n).ok(),
+                            _ => None,
+                        };
+                        if field.is_none() || field != labeled {
+                            return Err(Error::Value(format!(
+                                "GROUP BY {} is ambiguous, it is both a SELECT label and a field",
+                                label
+                            )));
+                        }
+                    }
                     groups.push((
                         replace(&mut exprs[i].0, ast::Expression::Column(offset + groups.len())),
                         exprs[i].1.clone(),
@@ -471,50 +876,92 @@ impl<'a, C: Catalog> Planner<'a, C> {
             }
         }
         // Any remaining aggregate functions and field references must be extracted as hidden
-        // columns.
+        // columns, unless they're identical to an existing (or already hidden) column.
         let mut hidden = 0;
         expr.transform_mut(
-            &mut |e| match &e {
-                ast::Expression::Function(f, a) if self.aggregate_from_name(f).is_some() => {
-                    if let ast::Expression::Column(c) = a[0] {
-                        if self.is_aggregate(&select[c].0) {
-                            return Err(Error::Value(
-                                "Aggregate function cannot reference aggregate".into(),
-                            ));
+            &mut |e| {
+                if let Some(i) = select.iter().position(|(sexpr, _)| sexpr == &e) {
+                    return Ok(ast::Expression::Column(i));
+                }
+                match &e {
+                    ast::Expression::Function(f, a) if Aggregate::from_name(f).is_some() => {
+                        if let Some(ast::Expression::Column(c)) = a.first() {
+                            if self.is_aggregate(&select[*c].0) {
+                                return Err(Error::Value(
+                                    "Aggregate function cannot reference aggregate".into(),
+                                ));
+                            }
                         }
+                        select.push((e, None));
+                        hidden += 1;
+                        Ok(ast::Expression::Column(select.len() - 1))
                     }
-                    select.push((e, None));
-                    hidden += 1;
-                    Ok(ast::Expression::Column(select.len() - 1))
-                }
-                ast::Expression::Field(_, _) => {
-                    select.push((e, None));
-                    hidden += 1;
-                    Ok(ast::Expression::Column(select.len() - 1))
+                    ast::Expression::Field(_, _) => {
+                        select.push((e, None));
+                        hidden += 1;
+                        Ok(ast::Expression::Column(select.len() - 1))
+                    }
+                    _ => Ok(e),
                 }
-                _ => Ok(e),
             },
             &mut |e| Ok(e),
         )?;
         Ok(hidden)
     }
 
-    /// Returns the aggregate corresponding to the given aggregate function name.
-    fn aggregate_from_name(&self, name: &str) -> Option<Aggregate> {
-        match name {
-            "avg" => Some(Aggregate::Average),
-            "count" => Some(Aggregate::Count),
-            "max" => Some(Aggregate::Max),
-            "min" => Some(Aggregate::Min),
-            "sum" => Some(Aggregate::Sum),
-            _ => None,
+    /// Builds the output column name of an unlabeled SELECT expression, if it doesn't get one
+    /// from its source column: a function call is named after the function, e.g. count, and any
+    /// other expression by its text, e.g. max(rating) - min(rating), or ?column? if that fails.
+    fn build_column_name(&self, scope: &Scope, expr: &ast::Expression) -> Option<String> {
+        match expr {
+            ast::Expression::Field(_, _) | ast::Expression::Column(_) => None,
+            ast::Expression::Function(name, _) => Some(name.to_lowercase()),
+            expr => {
+                // Aggregates can't be built as expressions, so they're replaced by fields named
+                // after their text in a scratch scope.
+                let mut scope = scope.clone();
+                let expr = expr.clone().transform(
+                    &mut |e| match e {
+                        ast::Expression::Function(f, args)
+                            if Aggregate::from_name(&f).is_some() =>
+                        {
+                            // COUNT(*) is parsed as COUNT(TRUE).
+                            let args = match (f.to_lowercase().as_str(), args.as_slice()) {
+                                (
+                                    "count",
+                                    [ast::Expression::Literal(ast::Literal::Boolean(true))],
+                                ) => {
+                                    vec!["*".to_string()]
+                                }
+                                _ => args
+                                    .into_iter()
+                                    .map(|a| {
+                                        Ok(self
+                                            .build_expression(&mut scope.clone(), a)?
+                                            .to_string())
+                                    })
+                                    .collect::<Result<Vec<_>>>()?,
+                            };
+                            let name = format!("{}({})", f.to_lowercase(), args.join(", "));
+                            if !scope.unqualified.contains_key(&name) {
+                                scope.add_column(None, Some(name.clone()));
+                            }
+                            Ok(ast::Expression::Field(None, name))
+                        }
+                        e => Ok(e),
+                    },
+                    &mut |e| Ok(e),
+                );
+                let name = expr.and_then(|e| self.build_expression(&mut scope, e));
+                Some(name.map(|e| e.to_string()).unwrap_or_else(|_| "?column?".to_string()))
+            }
         }
     }
 
     /// Checks whether a given expression is an aggregate expression.
     fn is_aggregate(&self, expr: &ast::Expression) -> bool {
         expr.contains(&|e| match e {
-            ast::Expression::Function(f, _) => self.aggregate_from_name(f).is_some(),
+            ast::Expression::Function(f, _) => Aggregate::from_name(f).is_some(),
             _ => false,
         })
     }
@@ -522,6 +969,9 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// Builds an expression from an AST expression
     fn build_expression(&self, scope: &mut Scope, expr: ast::Expression) -> Result<Expression> {
         use Expression::*;
+        if let ast::Expression::Operation(op) = &expr {
+            self.infer_operands(scope, op)?;
+        }
         Ok(match expr {
             ast::Expression::Literal(l) => Constant(match l {
                 ast::Literal::Null => Value::Null,
@@ -530,14 +980,103 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ast::Literal::Float(f) => Value::Float(f),
                 ast::Literal::String(s) => Value::String(s),
             }),
+            ast::Expression::Parameter(i, datatype) => {
+                let datatype = datatype.map(|d| self.resolve_datatype(d)).transpose()?;
+                if let Some(datatype) = &datatype {
+                    self.param_types.borrow_mut().insert(i, datatype.clone());
+                }
+                let value = match self.params.get(i) {
+                    Some(value) => value.clone(),
+                    // When describing, parameters are planned as placeholder values of their
+                    // datatype, such that e.g. LIMIT ? is valid, or NULL if unknown.
+                    None if self.describe => {
+                        match self.param_types.borrow().get(&i).map(|d| d.value_type()) {
+                            Some(DataType::Boolean) => Value::Boolean(false),
+                            Some(DataType::Integer) => Value::Integer(0),
+                            Some(DataType::Float) => Value::Float(0.0),
+                            Some(DataType::String) => Value::String("".into()),
+                            _ => Value::Null,
+                        }
+                    }
+                    None => {
+                        return Err(Error::Value(format!("No value given for parameter {}", i + 1)))
+                    }
+                };
+                let value = match &datatype {
+                    Some(datatype) => datatype.coerce(value),
+                    None => value,
+                };
+                match datatype {
+                    Some(datatype)
+                        if value != Value::Null
+                            && (!datatype.accepts(&value) || !datatype.in_range(&value)) =>
+                    {
+                        return Err(Error::Value(format!(
+                            "Invalid value {} for parameter {} of type {}",
+                            value,
+                            i + 1,
+                            datatype
+                        )))
+                    }
+                    _ => Constant(value),
+                }
+            }
             ast::Expression::Column(i) => Field(i, scope.get_label(i)?),
             ast::Expression::Field(table, name) => {
                 Field(scope.resolve(table.as_deref(), &name)?, Some((table, name)))
             }
-            ast::Expression::Function(name, _) => {
-                return Err(Error::Value(format!("Unknown function {}", name,)))
+            ast::Expression::Function(name, _) if Aggregate::from_name(&name).is_some() => {
+                return Err(Error::Value(format!(
+                    "Aggregate function {} is not allowed here",
+                    name
+                )))
+            }
+            ast::Expression::Function(name, args) => {
+                if let Some(function) = functions::get(&name)? {
+                    let parameters = (1..)
+                        .map(|i: usize| i.to_string())
+                        .zip(function.parameters.iter().cloned())
+                        .collect::<Vec<_>>();
+                    let args = self.build_arguments(scope, &name, &parameters, args)?;
+                    return Ok(Call(name, args));
+                }
+                match self.catalog.read_function(&name)? {
+                    Some(function) => return self.inline_function(scope, function, args),
+                    None => return Err(Error::Value(format!("Unknown function {}", name,))),
+                }
+            }
+            ast::Expression::Any(_) | ast::Expression::All(_) => {
+                return Err(Error::Value(
+                    "ANY and ALL can only be used on the right-hand side of a comparison".into(),
+                ))
             }
+            ast::Expression::Array(exprs) => Array(
+                exprs
+                    .into_iter()
+                    .map(|e| self.build_expression(scope, e))
+                    .collect::<Result<_>>()?,
+            ),
             ast::Expression::Operation(op) => match op {
+                // Array comparisons, which must come before the plain comparisons
+                ast::Operation::Equal(lhs, rhs) if rhs.is_quantified() => {
+                    self.build_quantified(scope, *lhs, types::Comparison::Equal, *rhs)?
+                }
+                ast::Operation::NotEqual(lhs, rhs) if rhs.is_quantified() => {
+                    self.build_quantified(scope, *lhs, types::Comparison::NotEqual, *rhs)?
+                }
+                ast::Operation::GreaterThan(lhs, rhs) if rhs.is_quantified() => {
+                    self.build_quantified(scope, *lhs, types::Comparison::GreaterThan, *rhs)?
+                }
+                ast::Operation::GreaterThanOrEqual(lhs, rhs) if rhs.is_quantified() => {
+                    self.build_quantified(scope, *lhs, types::Comparison::GreaterThanOrEqual, *rhs)?
+                }
+                ast::Operation::LessThan(lhs, rhs) if rhs.is_quantified() => {
+                    self.build_quantified(scope, *lhs, types::Comparison::LessThan, *rhs)?
+                }
+                ast::Operation::LessThanOrEqual(lhs, rhs) if rhs.is_quantified() => {
+                    self.build_quantified(scope, *lhs, types::Comparison::LessThanOrEqual, *rhs)?
+                }
+
                 // Logical operators
                 ast::Operation::And(lhs, rhs) => And(
                     self.build_expression(scope, *lhs)?.into(),
@@ -550,52 +1089,29 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ),
 
                 // Comparison operators
-                ast::Operation::Equal(lhs, rhs) => Equal(
-                    self.build_expression(scope, *lhs)?.into(),
-                    self.build_expression(scope, *rhs)?.into(),
-                ),
-                ast::Operation::GreaterThan(lhs, rhs) => GreaterThan(
-                    self.build_expression(scope, *lhs)?.into(),
-                    self.build_expression(scope, *rhs)?.into(),
-                ),
-                ast::Operation::GreaterThanOrEqual(lhs, rhs) => Or(
-                    GreaterThan(
-                        self.build_expression(scope, *lhs.clone())?.into(),
-                        self.build_expression(scope, *rhs.clone())?.into(),
-                    )
-                    .into(),
-                    Equal(
-                        self.build_expression(scope, *lhs)?.into(),
-                        self.build_expression(scope, *rhs)?.into(),
-                    )
-                    .into(),
-                ),
+                ast::Operation::Equal(lhs, rhs) => {
+                    self.build_comparison(scope, *lhs, types::Comparison::Equal, *rhs)?
+                }
+                ast::Operation::GreaterThan(lhs, rhs) => {
+                    self.build_comparison(scope, *lhs, types::Comparison::GreaterThan, *rhs)?
+                }
+                ast::Operation::GreaterThanOrEqual(lhs, rhs) => {
+                    self.build_comparison(scope, *lhs, types::Comparison::GreaterThanOrEqual, *rhs)?
+                }
                 ast::Operation::IsNull(expr) => IsNull(self.build_expression(scope, *expr)?.into()),
-                ast::Operation::LessThan(lhs, rhs) => LessThan(
-                    self.build_expression(scope, *lhs)?.into(),
-                    self.build_expression(scope, *rhs)?.into(),
-                ),
-                ast::Operation::LessThanOrEqual(lhs, rhs) => Or(
-                    LessThan(
-                        self.build_expression(scope, *lhs.clone())?.into(),
-                        self.build_expression(scope, *rhs.clone())?.into(),
-                    )
-                    .into(),
-                    Equal(
-                        self.build_expression(scope, *lhs)?.into(),
-                        self.build_expression(scope, *rhs)?.into(),
-                    )
-                    .into(),
-                ),
+                ast::Operation::LessThan(lhs, rhs) => {
+                    self.build_comparison(scope, *lhs, types::Comparison::LessThan, *rhs)?
+                }
+                ast::Operation::LessThanOrEqual(lhs, rhs) => {
+                    self.build_comparison(scope, *lhs, types::Comparison::LessThanOrEqual, *rhs)?
+                }
                 ast::Operation::Like(lhs, rhs) => Like(
                     self.build_expression(scope, *lhs)?.into(),
                     self.build_expression(scope, *rhs)?.into(),
                 ),
-                ast::Operation::NotEqual(lhs, rhs) => Not(Equal(
-                    self.build_expression(scope, *lhs)?.into(),
-                    self.build_expression(scope, *rhs)?.into(),
-                )
-                .into()),
+                ast::Operation::NotEqual(lhs, rhs) => {
+                    self.build_comparison(scope, *lhs, types::Comparison::NotEqual, *rhs)?
+                }
 
                 // Mathematical operators
                 ast::Operation::Assert(expr) => Assert(self.build_expression(scope, *expr)?.into()),
@@ -627,10 +1143,364 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     self.build_expression(scope, *lhs)?.into(),
                     self.build_expression(scope, *rhs)?.into(),
                 ),
+
+                // Array operators
+                ast::Operation::Element(lhs, rhs) => Element(
+                    self.build_expression(scope, *lhs)?.into(),
+                    self.build_expression(scope, *rhs)?.into(),
+                ),
             },
         })
     }
 
+    /// Builds a comparison of two operands. A string constant compared with an enum operand is
+    /// converted to the enum's value.
+    fn build_comparison(
+        &self,
+        scope: &mut Scope,
+        lhs: ast::Expression,
+        comparison: types::Comparison,
+        rhs: ast::Expression,
+    ) -> Result<Expression> {
+        let lhs = self.build_expression(scope, lhs)?;
+        let rhs = self.build_expression(scope, rhs)?;
+        let rhs = Self::coerce_enum(scope.datatype(&lhs), rhs)?;
+        let lhs = Self::coerce_enum(scope.datatype(&rhs), lhs)?;
+        Ok(comparison.build(lhs, rhs))
+    }
+
+    /// Builds an ANY or ALL array comparison, given the comparison's lhs and quantified rhs.
+    fn build_quantified(
+        &self,
+        scope: &mut Scope,
+        lhs: ast::Expression,
+        comparison: types::Comparison,
+        rhs: ast::Expression,
+    ) -> Result<Expression> {
+        let lhs = self.build_expression(scope, lhs)?;
+        let datatype = scope.datatype(&lhs);
+        let lhs = lhs.into();
+        Ok(match rhs {
+            ast::Expression::Any(array) => {
+                let array = Self::coerce_enum(datatype, self.build_expression(scope, *array)?)?;
+                Expression::Any(lhs, comparison, array.into())
+            }
+            ast::Expression::All(array) => {
+                let array = Self::coerce_enum(datatype, self.build_expression(scope, *array)?)?;
+                Expression::All(lhs, comparison, array.into())
+            }
+            rhs => return Err(Error::Internal(format!("Expected ANY or ALL, got {:?}", rhs))),
+        })
+    }
+
+    /// Converts a string constant used as a value of an enum datatype, or the string constant
+    /// elements of an array expression, to enum values. Errors if a string isn't one of the
+    /// enum's labels. Other expressions are returned as is.
+    fn coerce_enum(datatype: Option<DataType>, expr: Expression) -> Result<Expression> {
+        Ok(match (datatype, expr) {
+            (Some(DataType::Enum(e)), Expression::Constant(Value::String(s))) => {
+                match e.ordinal(&s) {
+                    Some(ordinal) => Expression::Constant(Value::Enum(e, ordinal)),
+                    None => {
+                        return Err(Error::Value(format!(
+                            "Invalid value {} for type {}",
+                            s, e.name
+                        )))
+                    }
+                }
+            }
+            (Some(datatype @ DataType::Enum(_)), Expression::Array(exprs)) => Expression::Array(
+                exprs
+                    .into_iter()
+                    .map(|e| Self::coerce_enum(Some(datatype.clone()), e))
+                    .collect::<Result<_>>()?,
+            ),
+            (_, expr) => expr,
+        })
+    }
+
+    /// Resolves user-defined enum datatypes from the catalog, which the parser gives by name
+    /// only. Errors if a type doesn't exist.
+    fn resolve_datatype(&self, datatype: DataType) -> Result<DataType> {
+        Ok(match datatype {
+            DataType::Array(element) => DataType::Array(Box::new(self.resolve_datatype(*element)?)),
+            DataType::Enum(e) => DataType::Enum(Arc::new(
+                self.catalog
+                    .read_enum(&e.name)?
+                    .ok_or_else(|| Error::Value(format!("Unknown type {}", e.name)))?,
+            )),
+            datatype => datatype,
+        })
+    }
+
+    /// Infers the datatype of a ? parameter when describing a statement, unless it was given
+    /// explicitly or already inferred. Other expressions are ignored.
+    fn infer_parameter(&self, expr: &ast::Expression, datatype: Option<DataType>) {
+        if let (true, ast::Expression::Parameter(i, None), Some(datatype)) =
+            (self.describe, expr, datatype)
+        {
+            self.param_types.borrow_mut().entry(*i).or_insert(datatype);
+        }
+    }
+
+    /// Validates a new stored function. Its name can't be an aggregate function, its parameter
+    /// names must be unique, and its body must be a valid expression over its parameters.
+    fn validate_function(&self, function: &Function) -> Result<()> {
+        if Aggregate::from_name(&function.name).is_some() {
+            return Err(Error::Value(format!(
+                "Function name {} is reserved for an aggregate function",
+                function.name
+            )));
+        }
+        if functions::get(&function.name)?.is_some() {
+            return Err(Error::Value(format!(
+                "Function name {} is reserved for a registered function",
+                function.name
+            )));
+        }
+        let mut names = HashSet::new();
+        for (name, _) in &function.parameters {
+            if !names.insert(name) {
+                return Err(Error::Value(format!("Duplicate parameter {}", name)));
+            }
+        }
+        self.build_function_body(function)?;
+        Ok(())
+    }
+
+    /// Builds a stored function's body as an expression over its parameters, where field i is
+    /// parameter i. Calls to other stored functions in the body are inlined in turn.
+    fn build_function_body(&self, function: &Function) -> Result<Expression> {
+        let body = Parser::new(&function.body).parse_function_body()?;
+        if body.contains(&|e| matches!(e, ast::Expression::Parameter(..))) {
+            return Err(Error::Value(format!(
+                "Function {} can't contain bind parameters",
+                function.name
+            )));
+        }
+        // Functions can only call existing functions, so this can't normally happen.
+        if self.inlining.borrow().contains(&function.name) {
+            return Err(Error::Value(format!("Function {} can't call itself", function.name)));
+        }
+        let mut scope = Scope::new();
+        for (name, _) in &function.parameters {
+            scope.add_column(None, Some(name.clone()));
+        }
+        self.inlining.borrow_mut().push(function.name.clone());
+        let body = self.build_expression(&mut scope, body);
+        self.inlining.borrow_mut().pop();
+        body
+    }
+
+    /// Builds the arguments of a function call in the caller's scope, given the function's
+    /// parameter names and datatypes. Arguments must have the parameter's datatype if known,
+    /// except that integers are accepted for floats.
+    fn build_arguments(
+        &self,
+        scope: &mut Scope,
+        function: &str,
+        parameters: &[(String, DataType)],
+        args: Vec<ast::Expression>,
+    ) -> Result<Vec<Expression>> {
+        if args.len() != parameters.len() {
+            return Err(Error::Value(format!(
+                "Function {} takes {} arguments, given {}",
+                function,
+                parameters.len(),
+                args.len()
+            )));
+        }
+        let mut arguments = Vec::new();
+        for ((name, datatype), arg) in parameters.iter().zip(args) {
+            let datatype = datatype.value_type();
+            self.infer_parameter(&arg, Some(datatype.clone()));
+            let arg =
+                Self::coerce_enum(Some(datatype.clone()), self.build_expression(scope, arg)?)?;
+            match scope.datatype(&arg).map(|d| d.value_type()) {
+                Some(DataType::Integer) if datatype == DataType::Float => {}
+                Some(actual) if actual != datatype => {
+                    return Err(Error::Value(format!(
+                        "Invalid {} argument for parameter {} of function {}, expected {}",
+                        actual, name, function, datatype
+                    )))
+                }
+                _ => {}
+            }
+            arguments.push(arg);
+        }
+        Ok(arguments)
+    }
+
+    /// Inlines a call to a stored function, by substituting the arguments for the parameters in
+    /// the function body.
+    fn inline_function(
+        &self,
+        scope: &mut Scope,
+        function: Function,
+        args: Vec<ast::Expression>,
+    ) -> Result<Expression> {
+        let arguments = self.build_arguments(scope, &function.name, &function.parameters, args)?;
+        // Substitute arguments after transforming each node, such that fields of the caller's
+        // scope in the arguments aren't substituted themselves.
+        self.build_function_body(&function)?.transform(&Ok, &|e| match e {
+            Expression::Field(i, _) => Ok(arguments[i].clone()),
+            e => Ok(e),
+        })
+    }
+
+    /// Infers the datatypes of any ? parameter operands of an operation when describing a
+    /// statement. Logical operands are booleans, LIKE operands are strings, and operands of
+    /// comparisons and arithmetic take the datatype of the other operand.
+    fn infer_operands(&self, scope: &mut Scope, op: &ast::Operation) -> Result<()> {
+        use ast::Operation::*;
+        if !self.describe {
+            return Ok(());
+        }
+        match op {
+            And(lhs, rhs) | Or(lhs, rhs) => {
+                self.infer_parameter(lhs, Some(DataType::Boolean));
+                self.infer_parameter(rhs, Some(DataType::Boolean));
+            }
+            Not(expr) => self.infer_parameter(expr, Some(DataType::Boolean)),
+            Like(lhs, rhs) => {
+                self.infer_parameter(lhs, Some(DataType::String));
+                self.infer_parameter(rhs, Some(DataType::String));
+            }
+            Factorial(expr) => self.infer_parameter(expr, Some(DataType::Integer)),
+            Element(_, index) => self.infer_parameter(index, Some(DataType::Integer)),
+            Equal(_, rhs)
+            | GreaterThan(_, rhs)
+            | GreaterThanOrEqual(_, rhs)
+            | LessThan(_, rhs)
+            | LessThanOrEqual(_, rhs)
+            | NotEqual(_, rhs)
+                if rhs.is_quantified() => {}
+            Equal(lhs, rhs)
+            | GreaterThan(lhs, rhs)
+            | GreaterThanOrEqual(lhs, rhs)
+            | LessThan(lhs, rhs)
+            | LessThanOrEqual(lhs, rhs)
+            | NotEqual(lhs, rhs)
+            | Add(lhs, rhs)
+            | Divide(lhs, rhs)
+            | Exponentiate(lhs, rhs)
+            | Modulo(lhs, rhs)
+            | Multiply(lhs, rhs)
+            | Subtract(lhs, rhs) => {
+                for (param, other) in &[(lhs, rhs), (rhs, lhs)] {
+                    if let ast::Expression::Parameter(..) = ***param {
+                        let other = self.build_expression(scope, (***other).clone())?;
+                        self.infer_parameter(param, scope.datatype(&other));
+                    }
+                }
+            }
+            Assert(_) | IsNull(_) | Negate(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Returns the row-level security filter for scans of a table, if the session role is
+    /// restricted by the table's policies. A role other than the table owner only sees the rows
+    /// satisfying any of its policies on the table, and no rows if it has none. Sessions without
+    /// a role, and tables without policies, are unrestricted.
+    fn policy_filter(&self, table: &Table) -> Option<Expression> {
+        let role = self.role?;
+        if table.policies.is_empty() || table.owner.as_deref() == Some(role) {
+            return None;
+        }
+        Some(
+            table
+                .policies
+                .iter()
+                .filter(|p| p.role == role)
+                .map(|p| p.predicate.clone())
+                .reduce(|lhs, rhs| Expression::Or(lhs.into(), rhs.into()))
+                .unwrap_or(Expression::Constant(Value::Boolean(false))),
+        )
+    }
+
+    /// Combines two optional filters into a conjunction.
+    fn and_filters(lhs: Option<Expression>, rhs: Option<Expression>) -> Option<Expression> {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => Some(Expression::And(lhs.into(), rhs.into())),
+            (lhs, rhs) => lhs.or(rhs),
+        }
+    }
+
+    /// Errors if the session role isn't the owner of a table, and thus can't change its policies.
+    /// Sessions without a role can change the policies of any table.
+    fn ensure_owner(&self, table: &Table) -> Result<()> {
+        match self.role {
+            Some(role) if table.owner.as_deref() != Some(role) => {
+                Err(Error::Value(format!("Role {} is not the owner of table {}", role, table.name)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Errors if the session role is restricted by the policies of a table, for statements that
+    /// read its rows without filtering them, i.e. HISTORY and CHANGEFEED.
+    fn ensure_unrestricted(&self, table: &Table) -> Result<()> {
+        match (self.role, self.policy_filter(table)) {
+            (Some(role), Some(_)) => Err(Error::Value(format!(
+                "Role {} is restricted by the policies of table {}",
+                role, table.name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds a column schema from an AST column specification.
+    fn build_column(&self, column: ast::Column) -> Result<Column> {
+        let nullable = column.nullable.unwrap_or(!column.primary_key);
+        let datatype = self.resolve_datatype(column.datatype)?;
+        let default = match column.default {
+            Some(expr) => Some(datatype.coerce(self.evaluate_constant(expr)?)),
+            None if nullable => Some(Value::Null),
+            None => None,
+        };
+        Ok(Column {
+            id: 0,
+            name: column.name,
+            datatype,
+            primary_key: column.primary_key,
+            nullable,
+            default,
+            missing: None,
+            index: column.index && !column.primary_key,
+            unique: column.unique || column.primary_key,
+            references: column.references,
+            references_id: None,
+            comment: None,
+        })
+    }
+
+    /// Builds the range partitions of a table schema, which can only be partitioned by its
+    /// primary key.
+    fn build_partitions(
+        &self,
+        table: &Table,
+        partition_by: ast::PartitionBy,
+    ) -> Result<Vec<Partition>> {
+        if !table.get_column(&partition_by.column)?.primary_key {
+            return Err(Error::Value(format!(
+                "Table {} can only be partitioned by its primary key, not {}",
+                table.name, partition_by.column
+            )));
+        }
+        partition_by
+            .partitions
+            .into_iter()
+            .map(|p| {
+                Ok(Partition {
+                    id: 0,
+                    name: p.name,
+                    upper: p.upper.map(|e| self.evaluate_constant(e)).transpose()?,
+                })
+            })
+            .collect()
+    }
+
     /// Builds and evaluates a constant AST expression.
     fn evaluate_constant(&self, expr: ast::Expression) -> Result<Value> {
         self.build_expression(&mut Scope::constant(), expr)?.evaluate(None)
@@ -652,6 +1522,8 @@ pub struct Scope {
     unqualified: HashMap<String, usize>,
     // Unqialified ambiguous names.
     ambiguous: HashSet<String>,
+    // If true, added tables include the system columns after their columns.
+    system_columns: bool,
 }
 
 impl Scope {
@@ -664,6 +1536,7 @@ impl Scope {
             qualified: HashMap::new(),
             unqualified: HashMap::new(),
             ambiguous: HashSet::new(),
+            system_columns: false,
         }
     }
 
@@ -711,6 +1584,10 @@ impl Scope {
         for column in &table.columns {
             self.add_column(Some(label.clone()), Some(column.name.clone()));
         }
+        if self.system_columns {
+            self.add_column(Some(label.clone()), Some(CREATED_VERSION.into()));
+            self.add_column(Some(label.clone()), Some(UPDATED_VERSION.into()));
+        }
         self.tables.insert(label, table);
         Ok(())
     }
@@ -780,6 +1657,28 @@ impl Scope {
         }
     }
 
+    /// Returns the datatype of an expression's values in the scope, if known. Fields have the
+    /// datatype of the table column they refer to.
+    fn datatype(&self, expr: &Expression) -> Option<DataType> {
+        let columns: Vec<types::Column> = self
+            .columns
+            .iter()
+            .map(|(table, label)| types::Column {
+                datatype: match (table, label) {
+                    (Some(_), Some(label)) if is_system_column(label) => Some(DataType::Integer),
+                    (Some(table), Some(label)) => self
+                        .tables
+                        .get(table)
+                        .and_then(|t| t.get_column(label).ok())
+                        .map(|c| c.datatype.clone()),
+                    _ => None,
+                },
+                ..types::Column::default()
+            })
+            .collect();
+        expr.datatype(&columns)
+    }
+
     /// Number of columns in the current scope.
     fn len(&self) -> usize {
         self.columns.len()
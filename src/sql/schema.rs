@@ -1,9 +1,11 @@
 use super::engine::Transaction;
-use super::parser::format_ident;
-use super::types::{DataType, Value};
+use super::parser::{format_ident, format_string};
+use super::settings::Settings;
+use super::types::{self, DataType, Enum, Expression, Row, Value};
 use crate::error::{Error, Result};
 
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt::{self, Display};
 
 /// The catalog stores schema information
@@ -16,6 +18,51 @@ pub trait Catalog {
     fn read_table(&self, table: &str) -> Result<Option<Table>>;
     /// Iterates over all tables
     fn scan_tables(&self) -> Result<Tables>;
+    /// Returns the catalog version, which is incremented by every schema change
+    fn catalog_version(&self) -> Result<u64>;
+    /// Returns the approximate number of rows in a table, as maintained by writes
+    fn row_count(&self, table: &str) -> Result<u64>;
+    /// Starts an online schema change adding a column to an existing table
+    fn add_column(&mut self, table: &str, column: Column) -> Result<()>;
+    /// Starts an online schema change creating an index on an existing column
+    fn create_index(&mut self, table: &str, column: &str) -> Result<()>;
+    /// Alters a column's nullability or default value
+    fn alter_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        alteration: ColumnAlteration,
+    ) -> Result<()>;
+    /// Sets or removes the comment of a table, or of one of its columns if given
+    fn set_comment(
+        &mut self,
+        table: &str,
+        column: Option<&str>,
+        comment: Option<String>,
+    ) -> Result<()>;
+    /// Reads the cluster settings
+    fn settings(&self) -> Result<Settings>;
+    /// Sets a cluster setting, or resets it to its default if None. Errors if the setting does not
+    /// exist or the value is invalid.
+    fn set_setting(&mut self, name: &str, value: Option<Value>) -> Result<()>;
+    /// Creates a new stored function, or errors if it already exists
+    fn create_function(&mut self, function: Function) -> Result<()>;
+    /// Deletes an existing stored function, or errors if it does not exist
+    fn delete_function(&mut self, function: &str) -> Result<()>;
+    /// Reads a stored function, if it exists
+    fn read_function(&self, function: &str) -> Result<Option<Function>>;
+    /// Returns all stored functions, ordered by name
+    fn scan_functions(&self) -> Result<Vec<Function>>;
+    /// Creates a new enum type, or errors if it already exists
+    fn create_enum(&mut self, r#enum: Enum) -> Result<()>;
+    /// Deletes an existing enum type, or errors if it does not exist or is used by a table
+    fn delete_enum(&mut self, r#enum: &str) -> Result<()>;
+    /// Reads an enum type, if it exists
+    fn read_enum(&self, r#enum: &str) -> Result<Option<Enum>>;
+    /// Adds a row-level security policy to a table, or errors if it already has one by that name
+    fn create_policy(&mut self, table: &str, policy: Policy) -> Result<()>;
+    /// Removes a row-level security policy from a table, or errors if it does not exist
+    fn delete_policy(&mut self, table: &str, policy: &str) -> Result<()>;
 
     /// Reads a table, and errors if it does not exist
     fn must_read_table(&self, table: &str) -> Result<Table> {
@@ -43,23 +90,217 @@ pub trait Catalog {
     }
 }
 
+/// The system column giving the version (i.e. transaction ID) that created a row. It can be
+/// selected from any table, but isn't included in SELECT *.
+pub const CREATED_VERSION: &str = "_created_version";
+
+/// The system column giving the version (i.e. transaction ID) that last updated a row. It can be
+/// selected from any table, but isn't included in SELECT *.
+pub const UPDATED_VERSION: &str = "_updated_version";
+
+/// Returns whether a column name is a system column
+pub fn is_system_column(name: &str) -> bool {
+    name == CREATED_VERSION || name == UPDATED_VERSION
+}
+
+/// Returns the name of a non-null value's datatype for error messages, where arrays without any
+/// non-null elements are simply ARRAY.
+fn datatype_name(value: &Value) -> String {
+    value.datatype().map(|d| d.to_string()).unwrap_or_else(|| "ARRAY".into())
+}
+
 /// A table scan iterator
 pub type Tables = Box<dyn DoubleEndedIterator<Item = Table> + Send>;
 
 /// A table schema
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Table {
+    /// The table's internal ID, assigned by the catalog when the table is created. Storage keys
+    /// and foreign keys refer to tables by ID rather than name, so they're unaffected by renames.
+    pub id: u64,
     pub name: String,
     pub columns: Vec<Column>,
+    /// An online schema change in progress, if any
+    pub change: Option<SchemaChange>,
+    /// A comment documenting the table, if any
+    pub comment: Option<String>,
+    /// Range partitions of the table by primary key, ordered by upper bound, or empty if the
+    /// table isn't partitioned
+    pub partitions: Vec<Partition>,
+    /// The role that created the table, if any, which isn't restricted by its policies
+    pub owner: Option<String>,
+    /// Row-level security policies, restricting the rows other roles can see
+    pub policies: Vec<Policy>,
 }
 
 impl Table {
-    /// Creates a new table schema
-    pub fn new(name: String, columns: Vec<Column>) -> Result<Self> {
-        let table = Self { name, columns };
+    /// Creates a new table schema, assigning column IDs in order. The table ID is assigned by
+    /// the catalog once the table is created.
+    pub fn new(name: String, mut columns: Vec<Column>) -> Result<Self> {
+        for (i, column) in columns.iter_mut().enumerate() {
+            column.id = i as u64 + 1;
+        }
+        let table = Self {
+            id: 0,
+            name,
+            columns,
+            change: None,
+            comment: None,
+            partitions: Vec::new(),
+            owner: None,
+            policies: Vec::new(),
+        };
         Ok(table)
     }
 
+    /// Returns the table schema as seen by reads, i.e. without any column or index that is
+    /// still being added by an online schema change.
+    pub fn visible(mut self) -> Self {
+        match self.change.as_ref().map(|c| &c.kind) {
+            Some(SchemaChangeKind::AddColumn(id)) => self.columns.retain(|c| &c.id != id),
+            Some(SchemaChangeKind::CreateIndex(id)) => {
+                self.columns.iter_mut().filter(|c| &c.id == id).for_each(|c| c.index = false)
+            }
+            None => {}
+        }
+        self
+    }
+
+    /// Starts an online schema change adding a column, which is appended to the existing
+    /// columns and given the next column ID. Existing rows aren't rewritten, but take the
+    /// column's current default value when read.
+    pub fn add_column(&mut self, mut column: Column, txn: &mut dyn Transaction) -> Result<()> {
+        self.ensure_no_change()?;
+        if self.columns.iter().any(|c| c.name == column.name) {
+            return Err(Error::Value(format!(
+                "Column {} already exists in table {}",
+                column.name, self.name
+            )));
+        }
+        if column.primary_key {
+            return Err(Error::Value(format!(
+                "Can't add primary key column {} to existing table {}",
+                column.name, self.name
+            )));
+        }
+        if column.unique {
+            return Err(Error::Value(format!(
+                "Can't add unique column {} to existing table {}",
+                column.name, self.name
+            )));
+        }
+        match (&column.default, &column.references) {
+            (None, _) => {
+                return Err(Error::Value(format!(
+                    "Column {} added to existing table {} must have a default value",
+                    column.name, self.name
+                )))
+            }
+            (Some(default), Some(reference)) if default != &Value::Null => {
+                return Err(Error::Value(format!(
+                    "Column {} referencing table {} must default to NULL",
+                    column.name, reference
+                )))
+            }
+            _ => {}
+        }
+        column.validate(self, txn)?;
+        // Columns can't be dropped, so IDs are never reused.
+        column.id = self.columns.iter().map(|c| c.id).max().unwrap_or(0) + 1;
+        column.missing = column.default.clone();
+        self.change = Some(SchemaChange::new(SchemaChangeKind::AddColumn(column.id)));
+        self.columns.push(column);
+        Ok(())
+    }
+
+    /// Starts an online schema change creating an index on an existing column. Existing rows
+    /// are backfilled into the index.
+    pub fn create_index(&mut self, column: &str) -> Result<()> {
+        self.ensure_no_change()?;
+        let index = self.get_column_index(column)?;
+        let column = &mut self.columns[index];
+        if column.primary_key {
+            return Err(Error::Value(format!("Can't index primary key column {}", column.name)));
+        }
+        if column.index {
+            return Err(Error::Value(format!("Column {} is already indexed", column.name)));
+        }
+        column.index = true;
+        self.change = Some(SchemaChange::new(SchemaChangeKind::CreateIndex(column.id)));
+        Ok(())
+    }
+
+    /// Alters a column's nullability or default value. Setting NOT NULL scans the table, and
+    /// errors if any existing row has a NULL value in the column.
+    pub fn alter_column(
+        &mut self,
+        column: &str,
+        alteration: ColumnAlteration,
+        txn: &mut dyn Transaction,
+    ) -> Result<()> {
+        // Columns that are still being added can't be altered yet.
+        self.clone().visible().get_column(column)?;
+        let index = self.get_column_index(column)?;
+        let mut altered = self.columns[index].clone();
+        match alteration {
+            ColumnAlteration::SetNotNull => {
+                if altered.default == Some(Value::Null) {
+                    altered.default = None;
+                }
+                altered.nullable = false;
+            }
+            ColumnAlteration::DropNotNull => {
+                altered.nullable = true;
+                altered.default.get_or_insert(Value::Null);
+            }
+            ColumnAlteration::SetDefault(value) => altered.default = Some(value),
+            ColumnAlteration::DropDefault if altered.nullable => {
+                altered.default = Some(Value::Null)
+            }
+            ColumnAlteration::DropDefault => altered.default = None,
+        }
+        altered.validate(self, txn)?;
+
+        if self.columns[index].nullable && !altered.nullable {
+            let mut scan = txn.scan(&self.name, None)?;
+            while let Some(row) = scan.next().transpose()? {
+                if row[index] == Value::Null {
+                    return Err(Error::Value(format!(
+                        "Column {} contains NULL value for row {}",
+                        altered.name,
+                        self.get_row_key(&row)?
+                    )));
+                }
+            }
+        }
+        self.columns[index] = altered;
+        Ok(())
+    }
+
+    /// Errors if the table has an online schema change in progress, since only one change can
+    /// run at a time.
+    fn ensure_no_change(&self) -> Result<()> {
+        match self.change {
+            Some(_) => Err(Error::Value(format!(
+                "Table {} already has a schema change in progress",
+                self.name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the table's columns as result set columns, with their origin table and datatype.
+    pub fn result_columns(&self) -> types::Columns {
+        self.columns
+            .iter()
+            .map(|c| types::Column {
+                name: Some(c.name.clone()),
+                table: Some(self.name.clone()),
+                datatype: Some(c.datatype.clone()),
+            })
+            .collect()
+    }
+
     /// Fetches a column by name
     pub fn get_column(&self, name: &str) -> Result<&Column> {
         self.columns.iter().find(|c| c.name == name).ok_or_else(|| {
@@ -74,6 +315,38 @@ impl Table {
         })
     }
 
+    /// Fetches a column index by column ID
+    pub fn get_column_index_by_id(&self, id: u64) -> Result<usize> {
+        self.columns.iter().position(|c| c.id == id).ok_or_else(|| {
+            Error::Internal(format!("Column ID {} not found in table {}", id, self.name))
+        })
+    }
+
+    /// Fetches a partition by name
+    pub fn get_partition(&self, name: &str) -> Result<&Partition> {
+        self.partitions.iter().find(|p| p.name == name).ok_or_else(|| {
+            Error::Value(format!("Partition {} not found in table {}", name, self.name))
+        })
+    }
+
+    /// Returns the partition containing a primary key value, or None if the table isn't
+    /// partitioned. Errors if no partition contains the value.
+    pub fn get_row_partition(&self, id: &Value) -> Result<Option<&Partition>> {
+        if self.partitions.is_empty() {
+            return Ok(None);
+        }
+        self.partitions
+            .iter()
+            .find(|p| p.upper.as_ref().map(|upper| id < upper).unwrap_or(true))
+            .map(Some)
+            .ok_or_else(|| {
+                Error::Constraint(format!(
+                    "No partition of table {} for primary key {}",
+                    self.name, id
+                ))
+            })
+    }
+
     /// Returns the primary key column of the table
     pub fn get_primary_key(&self) -> Result<&Column> {
         self.columns
@@ -107,6 +380,69 @@ impl Table {
         for column in &self.columns {
             column.validate(self, txn)?;
         }
+        self.validate_partitions()
+    }
+
+    /// Validates the table's partitions, which must have unique names and increasing upper
+    /// bounds of the primary key's datatype, where only the last may be unbounded.
+    fn validate_partitions(&self) -> Result<()> {
+        let datatype = &self.get_primary_key()?.datatype;
+        let mut names = HashSet::new();
+        let mut lower: Option<&Value> = None;
+        for (i, partition) in self.partitions.iter().enumerate() {
+            if !names.insert(&partition.name) {
+                return Err(Error::Value(format!(
+                    "Duplicate partition {} in table {}",
+                    partition.name, self.name
+                )));
+            }
+            let upper = match &partition.upper {
+                Some(upper) => upper,
+                None if i == self.partitions.len() - 1 => break,
+                None => {
+                    return Err(Error::Value(format!(
+                        "Only the last partition of table {} can be MAXVALUE",
+                        self.name
+                    )))
+                }
+            };
+            match upper {
+                Value::Null => {
+                    return Err(Error::Value(format!(
+                        "Upper bound of partition {} can't be NULL",
+                        partition.name
+                    )))
+                }
+                Value::Float(f) if f.is_nan() => {
+                    return Err(Error::Value(format!(
+                        "Upper bound of partition {} can't be NaN",
+                        partition.name
+                    )))
+                }
+                v if !datatype.accepts(v) => {
+                    return Err(Error::Value(format!(
+                        "Upper bound of partition {} has datatype {}, must be {}",
+                        partition.name,
+                        datatype_name(v),
+                        datatype
+                    )))
+                }
+                v if !datatype.in_range(v) => {
+                    return Err(Error::Value(format!(
+                        "Upper bound {} of partition {} is out of range for {}",
+                        v, partition.name, datatype
+                    )))
+                }
+                _ => {}
+            }
+            if let Some(lower) = lower.filter(|lower| *lower >= upper) {
+                return Err(Error::Value(format!(
+                    "Upper bound {} of partition {} must be greater than previous bound {}",
+                    upper, partition.name, lower
+                )));
+            }
+            lower = Some(upper);
+        }
         Ok(())
     }
 
@@ -121,6 +457,18 @@ impl Table {
         }
         Ok(())
     }
+
+    /// Converts the values of a (possibly partial) row to the column datatypes where SQL allows
+    /// implicit conversion, i.e. strings to enum values. See DataType::coerce().
+    pub fn coerce_row(&self, row: Row) -> Row {
+        let mut columns = self.columns.iter();
+        row.into_iter()
+            .map(|v| match columns.next() {
+                Some(column) => column.datatype.coerce(v),
+                None => v,
+            })
+            .collect()
+    }
 }
 
 impl Display for Table {
@@ -130,13 +478,158 @@ impl Display for Table {
             "CREATE TABLE {} (\n{}\n)",
             format_ident(&self.name),
             self.columns.iter().map(|c| format!("  {}", c)).collect::<Vec<String>>().join(",\n")
-        )
+        )?;
+        if !self.partitions.is_empty() {
+            write!(
+                f,
+                " PARTITION BY RANGE ({}) (\n{}\n)",
+                format_ident(&self.get_primary_key().map_err(|_| fmt::Error)?.name),
+                self.partitions
+                    .iter()
+                    .map(|p| format!("  {}", p))
+                    .collect::<Vec<String>>()
+                    .join(",\n")
+            )?;
+        }
+        // Comments are given as separate COMMENT ON statements, like in PostgreSQL.
+        if let Some(comment) = &self.comment {
+            write!(
+                f,
+                ";\nCOMMENT ON TABLE {} IS {}",
+                format_ident(&self.name),
+                format_string(comment)
+            )?;
+        }
+        for column in &self.columns {
+            if let Some(comment) = &column.comment {
+                write!(
+                    f,
+                    ";\nCOMMENT ON COLUMN {}.{} IS {}",
+                    format_ident(&self.name),
+                    format_ident(&column.name),
+                    format_string(comment)
+                )?;
+            }
+        }
+        for policy in &self.policies {
+            write!(
+                f,
+                ";\nCREATE POLICY {} ON {} {}",
+                format_ident(&policy.name),
+                format_ident(&self.name),
+                policy
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A range partition of a table, containing the rows whose primary key is below its upper bound
+/// and at or above the upper bound of the previous partition.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Partition {
+    /// The partition's internal ID, assigned by the catalog from the same sequence as table IDs.
+    /// Rows of partitioned tables are stored by partition ID rather than table ID.
+    pub id: u64,
+    /// Partition name
+    pub name: String,
+    /// The exclusive upper bound of the partition's primary keys, or None for MAXVALUE
+    pub upper: Option<Value>,
+}
+
+impl Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let upper = match &self.upper {
+            Some(Value::String(s)) => format_string(s),
+            Some(v) => v.to_string(),
+            None => "MAXVALUE".into(),
+        };
+        write!(f, "PARTITION {} VALUES LESS THAN ({})", format_ident(&self.name), upper)
+    }
+}
+
+/// An online schema change, which adds a column or index to an existing table without blocking
+/// concurrent reads and writes. The change moves through the states of SchemaState in order,
+/// one transaction at a time, and its progress is stored in the table schema such that it can
+/// be resumed if interrupted.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SchemaChange {
+    /// The column or index being added
+    pub kind: SchemaChangeKind,
+    /// The current state of the change
+    pub state: SchemaState,
+    /// The first transaction known to see the change. Backfilling waits for all older
+    /// transactions to finish, since they may still write rows without the column or index.
+    pub since: Option<u64>,
+    /// The primary key of the last backfilled row, or of the last verified row once
+    /// write-visible, if any
+    pub backfilled: Option<Value>,
+    /// The number of rows backfilled so far, for progress reporting
+    pub backfilled_rows: u64,
+    /// The time the change started, in milliseconds since the Unix epoch, once it has
+    pub started: Option<u64>,
+}
+
+impl SchemaChange {
+    /// Creates a new schema change, in the backfilling state
+    pub fn new(kind: SchemaChangeKind) -> Self {
+        Self {
+            kind,
+            state: SchemaState::Backfilling,
+            since: None,
+            backfilled: None,
+            backfilled_rows: 0,
+            started: None,
+        }
+    }
+
+    /// Returns the ID of the column being added or indexed
+    pub fn column(&self) -> u64 {
+        match self.kind {
+            SchemaChangeKind::AddColumn(id) | SchemaChangeKind::CreateIndex(id) => id,
+        }
     }
 }
 
+/// The kind of online schema change, with the affected column ID
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SchemaChangeKind {
+    /// Adds a column, along with any index on it
+    AddColumn(u64),
+    /// Creates an index on an existing column
+    CreateIndex(u64),
+}
+
+/// The state of an online schema change
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SchemaState {
+    /// Writes maintain the column or index, while existing rows are backfilled in batches
+    Backfilling,
+    /// All rows have been backfilled, but reads don't see the column or index yet
+    WriteVisible,
+    /// The change is complete, and the column or index is visible to reads
+    ReadVisible,
+}
+
+/// An alteration of a column's nullability or default value
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ColumnAlteration {
+    /// Disallows NULL values, removing any NULL default
+    SetNotNull,
+    /// Allows NULL values, defaulting to NULL if the column has no default
+    DropNotNull,
+    /// Sets the default value
+    SetDefault(Value),
+    /// Removes the default value, or sets it to NULL for nullable columns
+    DropDefault,
+}
+
 /// A table column schema
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Column {
+    /// The column's ID within its table, assigned when the column is added to the table. Index
+    /// keys refer to columns by ID rather than name.
+    pub id: u64,
     /// Column name
     pub name: String,
     /// Column datatype
@@ -147,17 +640,29 @@ pub struct Column {
     pub nullable: bool,
     /// The default value of the column
     pub default: Option<Value>,
+    /// The value of the column in rows stored before it was added, which don't contain it. This
+    /// is the default value when the column was added, and None for the table's initial columns.
+    pub missing: Option<Value>,
     /// Whether the column should only take unique values
     pub unique: bool,
     /// The table which is referenced by this foreign key
     pub references: Option<String>,
+    /// The ID of the table referenced by this foreign key, assigned by the catalog. This is
+    /// authoritative, and the referenced table name is resolved from it when the schema is read.
+    pub references_id: Option<u64>,
     /// Whether the column should be indexed
     pub index: bool,
+    /// A comment documenting the column, if any
+    pub comment: Option<String>,
 }
 
 impl Column {
     /// Validates the column schema
     pub fn validate(&self, table: &Table, txn: &mut dyn Transaction) -> Result<()> {
+        if is_system_column(&self.name) {
+            return Err(Error::Value(format!("Column name {} is reserved", self.name)));
+        }
+
         // Validate primary key
         if self.primary_key && self.nullable {
             return Err(Error::Value(format!("Primary key {} cannot be nullable", self.name)));
@@ -166,13 +671,39 @@ impl Column {
             return Err(Error::Value(format!("Primary key {} must be unique", self.name)));
         }
 
+        // Validate datatype
+        if let DataType::Varchar(length) = self.datatype {
+            if length == 0 || length > 1024 {
+                return Err(Error::Value(format!(
+                    "Length of {} column {} must be between 1 and 1024",
+                    self.datatype, self.name
+                )));
+            }
+        }
+
         // Validate default value
         if let Some(default) = &self.default {
-            if let Some(datatype) = default.datatype() {
-                if datatype != self.datatype {
+            if default != &Value::Null {
+                if !self.datatype.accepts(default) {
                     return Err(Error::Value(format!(
                         "Default value for column {} has datatype {}, must be {}",
-                        self.name, datatype, self.datatype
+                        self.name,
+                        datatype_name(default),
+                        self.datatype
+                    )));
+                }
+                if let (Value::String(s), false) = (default, self.datatype.in_range(default)) {
+                    return Err(Error::Value(format!(
+                        "Default value for column {} is too long for {} ({} characters)",
+                        self.name,
+                        self.datatype,
+                        s.chars().count()
+                    )));
+                }
+                if !self.datatype.in_range(default) {
+                    return Err(Error::Value(format!(
+                        "Default value {} for column {} is out of range for {}",
+                        default, self.name, self.datatype
                     )));
                 }
             } else if !self.nullable {
@@ -200,7 +731,7 @@ impl Column {
                     reference, self.name
                 )));
             };
-            if self.datatype != target.get_primary_key()?.datatype {
+            if self.datatype.value_type() != target.get_primary_key()?.datatype.value_type() {
                 return Err(Error::Value(format!(
                     "Can't reference {} primary key of table {} from {} column {}",
                     target.get_primary_key()?.datatype,
@@ -223,12 +754,30 @@ impl Column {
         txn: &mut dyn Transaction,
     ) -> Result<()> {
         // Validate datatype
-        match value.datatype() {
-            None if self.nullable => Ok(()),
-            None => Err(Error::Value(format!("NULL value not allowed for column {}", self.name))),
-            Some(ref datatype) if datatype != &self.datatype => Err(Error::Value(format!(
+        match value {
+            Value::Null if self.nullable => Ok(()),
+            Value::Null => {
+                Err(Error::Constraint(format!("NULL value not allowed for column {}", self.name)))
+            }
+            Value::String(s) if matches!(self.datatype, DataType::Enum(_)) => Err(Error::Value(
+                format!("Invalid value {} for {} column {}", s, self.datatype, self.name),
+            )),
+            value if !self.datatype.accepts(value) => Err(Error::Value(format!(
                 "Invalid datatype {} for {} column {}",
-                datatype, self.datatype, self.name
+                datatype_name(value),
+                self.datatype,
+                self.name
+            ))),
+            // Only VARCHAR constrains the range of strings, namely their length.
+            Value::String(s) if !self.datatype.in_range(value) => Err(Error::Value(format!(
+                "Value for column {} is too long for {} ({} characters)",
+                self.name,
+                self.datatype,
+                s.chars().count()
+            ))),
+            _ if !self.datatype.in_range(value) => Err(Error::Value(format!(
+                "Value {} is out of range for {} column {}",
+                value, self.datatype, self.name
             ))),
             _ => Ok(()),
         }?;
@@ -247,7 +796,7 @@ impl Column {
                 Value::Null => Ok(()),
                 Value::Float(f) if f.is_nan() => Ok(()),
                 v if target == &table.name && v == pk => Ok(()),
-                v if txn.read(target, v)?.is_none() => Err(Error::Value(format!(
+                v if txn.read(target, v)?.is_none() => Err(Error::Constraint(format!(
                     "Referenced primary key {} in table {} does not exist",
                     v, target,
                 ))),
@@ -263,7 +812,7 @@ impl Column {
                 if row.get(index).unwrap_or(&Value::Null) == value
                     && &table.get_row_key(&row)? != pk
                 {
-                    return Err(Error::Value(format!(
+                    return Err(Error::Constraint(format!(
                         "Unique value {} already exists for column {}",
                         value, self.name
                     )));
@@ -300,3 +849,54 @@ impl Display for Column {
         write!(f, "{}", sql)
     }
 }
+
+/// A row-level security policy, created with CREATE POLICY. Roles other than the table owner only
+/// see the rows that satisfy the predicate of any of their policies on the table.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Policy {
+    pub name: String,
+    /// The role the policy applies to
+    pub role: String,
+    /// The predicate over the table's columns that visible rows must satisfy
+    pub predicate: Expression,
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TO {} USING ({})", format_ident(&self.role), self.predicate)
+    }
+}
+
+/// A stored SQL function, created with CREATE FUNCTION. The body is a single expression over the
+/// parameters, which is stored as SQL text and inlined by the planner at call sites.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Function {
+    pub name: String,
+    /// The function parameters, as names and datatypes
+    pub parameters: Vec<(String, DataType)>,
+    /// The SQL text of the function body, either an expression or a SELECT of one expression
+    pub body: String,
+}
+
+impl Function {
+    /// Returns the parameter list formatted as SQL, e.g. a INTEGER, b STRING
+    pub fn format_parameters(&self) -> String {
+        self.parameters
+            .iter()
+            .map(|(name, datatype)| format!("{} {}", format_ident(name), datatype))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE FUNCTION {} ({}) AS {}",
+            format_ident(&self.name),
+            self.format_parameters(),
+            format_string(&self.body)
+        )
+    }
+}
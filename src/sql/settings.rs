@@ -0,0 +1,197 @@
+//! Cluster settings are knobs that apply to all nodes in the cluster, changed with SET CLUSTER
+//! SETTING. They are stored in the catalog alongside table schemas, and are thus replicated via
+//! Raft and versioned by MVCC like any other catalog change. Changing a setting increments the
+//! catalog version, such that sessions on all nodes pick up the new value with their next
+//! transaction.
+use super::types::{DataType, Value};
+use crate::error::{Error, Result};
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Backfills at most this many rows per second during online schema changes, or 0 for no limit
+pub const BACKFILL_ROWS_PER_SECOND: &str = "backfill_rows_per_second";
+
+/// Retains history for time travel queries for this many milliseconds before garbage collecting
+/// older versions, or 0 to retain all versions
+pub const MVCC_RETENTION_MS: &str = "mvcc_retention_ms";
+
+/// Caches the results of up to this many read-only queries, or 0 to disable
+pub const QUERY_CACHE_ENTRIES: &str = "query_cache_entries";
+
+/// Logs statements that take longer than this many milliseconds to execute, or 0 to disable
+pub const SLOW_QUERY_THRESHOLD_MS: &str = "slow_query_threshold_ms";
+
+/// A cluster setting definition
+pub struct Definition {
+    /// The setting name
+    pub name: &'static str,
+    /// The setting's datatype
+    pub datatype: DataType,
+    /// The value of the setting if it hasn't been set
+    pub default: Value,
+    /// A description of the setting
+    pub description: &'static str,
+}
+
+/// Returns the definitions of all cluster settings, ordered by name
+pub fn definitions() -> Vec<Definition> {
+    vec![
+        Definition {
+            name: BACKFILL_ROWS_PER_SECOND,
+            datatype: DataType::Integer,
+            default: Value::Integer(0),
+            description: "Rows backfilled per second by online schema changes, or 0 for no limit",
+        },
+        Definition {
+            name: MVCC_RETENTION_MS,
+            datatype: DataType::Integer,
+            default: Value::Integer(0),
+            description:
+                "Milliseconds of history retained for time travel queries, or 0 to retain all",
+        },
+        Definition {
+            name: QUERY_CACHE_ENTRIES,
+            datatype: DataType::Integer,
+            default: Value::Integer(0),
+            description: "Results of read-only queries cached per node, or 0 to disable",
+        },
+        Definition {
+            name: SLOW_QUERY_THRESHOLD_MS,
+            datatype: DataType::Integer,
+            default: Value::Integer(0),
+            description: "Logs statements slower than this many milliseconds, or 0 to disable",
+        },
+    ]
+}
+
+/// Returns the definition of a cluster setting, or errors if it does not exist
+pub fn definition(name: &str) -> Result<Definition> {
+    definitions()
+        .into_iter()
+        .find(|d| d.name == name)
+        .ok_or_else(|| Error::Value(format!("Unknown cluster setting {}", name)))
+}
+
+/// Validates a value for a cluster setting, converting integers to floats where needed. Settings
+/// can't be NULL, and numeric settings can't be negative.
+pub fn validate(name: &str, value: Value) -> Result<Value> {
+    let definition = definition(name)?;
+    let value = match (&definition.datatype, value) {
+        (DataType::Float, Value::Integer(i)) => Value::Float(i as f64),
+        (_, value) => value,
+    };
+    let valid = match &value {
+        Value::Integer(i) if *i < 0 => false,
+        Value::Float(f) if *f < 0.0 || f.is_nan() => false,
+        value => value.datatype() == Some(definition.datatype),
+    };
+    match valid {
+        true => Ok(value),
+        false => Err(Error::Value(format!("Invalid value {} for cluster setting {}", value, name))),
+    }
+}
+
+/// Cluster settings that have been explicitly set, by name. Other settings take their default
+/// value.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Settings(BTreeMap<String, Value>);
+
+impl Settings {
+    /// Creates a new set of cluster settings from explicitly set values
+    pub fn new(values: BTreeMap<String, Value>) -> Self {
+        Self(values)
+    }
+
+    /// Returns a setting's value, or its default if it hasn't been set
+    pub fn get(&self, name: &str) -> Result<Value> {
+        match self.0.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(definition(name)?.default),
+        }
+    }
+
+    /// Returns whether a setting has been explicitly set
+    pub fn is_set(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Returns the maximum number of rows to backfill per second, if limited
+    pub fn backfill_rate(&self) -> Option<u64> {
+        match self.get(BACKFILL_ROWS_PER_SECOND) {
+            Ok(Value::Integer(rows)) if rows > 0 => Some(rows as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns how long to retain history before garbage collection, if enabled
+    pub fn mvcc_retention(&self) -> Option<Duration> {
+        match self.get(MVCC_RETENTION_MS) {
+            Ok(Value::Integer(ms)) if ms > 0 => Some(Duration::from_millis(ms as u64)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of read-only query results to cache, if enabled
+    pub fn query_cache_entries(&self) -> Option<usize> {
+        match self.get(QUERY_CACHE_ENTRIES) {
+            Ok(Value::Integer(entries)) if entries > 0 => Some(entries as usize),
+            _ => None,
+        }
+    }
+
+    /// Returns the threshold above which statements are logged as slow, if enabled
+    pub fn slow_query_threshold(&self) -> Option<Duration> {
+        match self.get(SLOW_QUERY_THRESHOLD_MS) {
+            Ok(Value::Integer(ms)) if ms > 0 => Some(Duration::from_millis(ms as u64)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        assert_eq!(
+            super::validate(SLOW_QUERY_THRESHOLD_MS, Value::Integer(100)),
+            Ok(Value::Integer(100))
+        );
+        assert_eq!(
+            super::validate(SLOW_QUERY_THRESHOLD_MS, Value::Integer(-1)),
+            Err(Error::Value(
+                "Invalid value -1 for cluster setting slow_query_threshold_ms".into()
+            ))
+        );
+        assert_eq!(
+            super::validate(SLOW_QUERY_THRESHOLD_MS, Value::String("1s".into())),
+            Err(Error::Value(
+                "Invalid value 1s for cluster setting slow_query_threshold_ms".into()
+            ))
+        );
+        assert_eq!(
+            super::validate(SLOW_QUERY_THRESHOLD_MS, Value::Null),
+            Err(Error::Value(
+                "Invalid value NULL for cluster setting slow_query_threshold_ms".into()
+            ))
+        );
+        assert_eq!(
+            super::validate("unknown", Value::Integer(1)),
+            Err(Error::Value("Unknown cluster setting unknown".into()))
+        );
+    }
+
+    #[test]
+    fn settings() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.get(SLOW_QUERY_THRESHOLD_MS), Ok(Value::Integer(0)));
+        assert_eq!(settings.slow_query_threshold(), None);
+
+        settings.0.insert(SLOW_QUERY_THRESHOLD_MS.into(), Value::Integer(250));
+        assert!(settings.is_set(SLOW_QUERY_THRESHOLD_MS));
+        assert_eq!(settings.slow_query_threshold(), Some(Duration::from_millis(250)));
+    }
+}
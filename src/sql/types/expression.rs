@@ -1,8 +1,11 @@
-use super::{Row, Value};
+use super::super::functions;
+use super::{cmp_floats, Column, DataType, Row, Value};
 use crate::error::{Error, Result};
 
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering::{Equal, Greater, Less};
+use std::convert::TryFrom;
 use std::fmt::{self, Display};
 use std::mem::replace;
 
@@ -34,9 +37,24 @@ pub enum Expression {
     Multiply(Box<Expression>, Box<Expression>),
     Negate(Box<Expression>),
     Subtract(Box<Expression>, Box<Expression>),
+    /// Evaluates an integer arithmetic operation with wrapping (modular) semantics, instead of
+    /// erroring on overflow. See Expression::wrapping().
+    Wrapping(Box<Expression>),
 
     // String operations
     Like(Box<Expression>, Box<Expression>),
+
+    // Array operations
+    Array(Vec<Expression>),
+    /// Takes the element at a 1-based index of an array, or NULL if out of range.
+    Element(Box<Expression>, Box<Expression>),
+    /// Compares a value with each element of an array, true if any comparison is true.
+    Any(Box<Expression>, Comparison, Box<Expression>),
+    /// Compares a value with each element of an array, true if all comparisons are true.
+    All(Box<Expression>, Comparison, Box<Expression>),
+
+    /// Calls a registered scalar function by name, see sql::functions.
+    Call(String, Vec<Expression>),
 }
 
 impl Expression {
@@ -78,10 +96,11 @@ impl Expression {
             Self::Equal(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs == rhs),
                 (Integer(lhs), Integer(rhs)) => Boolean(lhs == rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 == rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs == rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs == rhs),
+                (Integer(lhs), Float(rhs)) => Boolean(cmp_floats(lhs as f64, rhs) == Equal),
+                (Float(lhs), Integer(rhs)) => Boolean(cmp_floats(lhs, rhs as f64) == Equal),
+                (Float(lhs), Float(rhs)) => Boolean(cmp_floats(lhs, rhs) == Equal),
                 (String(lhs), String(rhs)) => Boolean(lhs == rhs),
+                (Enum(a, lhs), Enum(b, rhs)) if a.name == b.name => Boolean(lhs == rhs),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
@@ -91,10 +110,11 @@ impl Expression {
                 #[allow(clippy::bool_comparison)]
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs > rhs),
                 (Integer(lhs), Integer(rhs)) => Boolean(lhs > rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 > rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs > rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs > rhs),
+                (Integer(lhs), Float(rhs)) => Boolean(cmp_floats(lhs as f64, rhs) == Greater),
+                (Float(lhs), Integer(rhs)) => Boolean(cmp_floats(lhs, rhs as f64) == Greater),
+                (Float(lhs), Float(rhs)) => Boolean(cmp_floats(lhs, rhs) == Greater),
                 (String(lhs), String(rhs)) => Boolean(lhs > rhs),
+                (Enum(a, lhs), Enum(b, rhs)) if a.name == b.name => Boolean(lhs > rhs),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
@@ -104,10 +124,11 @@ impl Expression {
                 #[allow(clippy::bool_comparison)]
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs < rhs),
                 (Integer(lhs), Integer(rhs)) => Boolean(lhs < rhs),
-                (Integer(lhs), Float(rhs)) => Boolean((lhs as f64) < rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs < rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs < rhs),
+                (Integer(lhs), Float(rhs)) => Boolean(cmp_floats(lhs as f64, rhs) == Less),
+                (Float(lhs), Integer(rhs)) => Boolean(cmp_floats(lhs, rhs as f64) == Less),
+                (Float(lhs), Float(rhs)) => Boolean(cmp_floats(lhs, rhs) == Less),
                 (String(lhs), String(rhs)) => Boolean(lhs < rhs),
+                (Enum(a, lhs), Enum(b, rhs)) if a.name == b.name => Boolean(lhs < rhs),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
@@ -121,7 +142,7 @@ impl Expression {
             // Mathematical operations
             Self::Add(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Integer(lhs), Integer(rhs)) => Integer(
-                    lhs.checked_add(rhs).ok_or_else(|| Error::Value("Integer overflow".into()))?,
+                    lhs.checked_add(rhs).ok_or_else(|| overflow(format!("{} + {}", lhs, rhs)))?,
                 ),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 + rhs),
                 (Integer(_), Null) => Null,
@@ -143,7 +164,9 @@ impl Expression {
                 (Integer(_), Integer(rhs)) if rhs == 0 => {
                     return Err(Error::Value("Can't divide by zero".into()))
                 }
-                (Integer(lhs), Integer(rhs)) => Integer(lhs / rhs),
+                (Integer(lhs), Integer(rhs)) => Integer(
+                    lhs.checked_div(rhs).ok_or_else(|| overflow(format!("{} / {}", lhs, rhs)))?,
+                ),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 / rhs),
                 (Integer(_), Null) => Null,
                 (Float(lhs), Integer(rhs)) => Float(lhs / rhs as f64),
@@ -158,13 +181,20 @@ impl Expression {
             },
             Self::Exponentiate(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Integer(lhs), Integer(rhs)) if rhs >= 0 => Integer(
-                    lhs.checked_pow(rhs as u32)
-                        .ok_or_else(|| Error::Value("Integer overflow".into()))?,
+                    u32::try_from(rhs)
+                        .ok()
+                        .and_then(|rhs| lhs.checked_pow(rhs))
+                        .or_else(|| match lhs {
+                            0 | 1 => Some(lhs),
+                            -1 => Some(if rhs % 2 == 0 { 1 } else { -1 }),
+                            _ => None,
+                        })
+                        .ok_or_else(|| overflow(format!("{} ^ {}", lhs, rhs)))?,
                 ),
                 (Integer(lhs), Integer(rhs)) => Float((lhs as f64).powf(rhs as f64)),
                 (Integer(lhs), Float(rhs)) => Float((lhs as f64).powf(rhs)),
                 (Integer(_), Null) => Null,
-                (Float(lhs), Integer(rhs)) => Float((lhs).powi(rhs as i32)),
+                (Float(lhs), Integer(rhs)) => Float((lhs).powf(rhs as f64)),
                 (Float(lhs), Float(rhs)) => Float((lhs).powf(rhs)),
                 (Float(_), Null) => Null,
                 (Null, Float(_)) => Null,
@@ -178,7 +208,11 @@ impl Expression {
                 Integer(i) if i < 0 => {
                     return Err(Error::Value("Can't take factorial of negative number".into()))
                 }
-                Integer(i) => Integer((1..=i).fold(1, |a, b| a * b as i64)),
+                Integer(i) => Integer(
+                    (1..=i)
+                        .try_fold(1_i64, |a, b| a.checked_mul(b))
+                        .ok_or_else(|| overflow(format!("{}!", i)))?,
+                ),
                 Null => Null,
                 value => return Err(Error::Value(format!("Can't take factorial of {}", value))),
             },
@@ -187,7 +221,8 @@ impl Expression {
                 (Integer(_), Integer(rhs)) if rhs == 0 => {
                     return Err(Error::Value("Can't divide by zero".into()))
                 }
-                (Integer(lhs), Integer(rhs)) => Integer(lhs % rhs),
+                // The only overflow is i64::MIN % -1, whose remainder is 0.
+                (Integer(lhs), Integer(rhs)) => Integer(lhs.wrapping_rem(rhs)),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 % rhs),
                 (Integer(_), Null) => Null,
                 (Float(lhs), Integer(rhs)) => Float(lhs % rhs as f64),
@@ -202,7 +237,7 @@ impl Expression {
             },
             Self::Multiply(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Integer(lhs), Integer(rhs)) => Integer(
-                    lhs.checked_mul(rhs).ok_or_else(|| Error::Value("Integer overflow".into()))?,
+                    lhs.checked_mul(rhs).ok_or_else(|| overflow(format!("{} * {}", lhs, rhs)))?,
                 ),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 * rhs),
                 (Integer(_), Null) => Null,
@@ -217,14 +252,14 @@ impl Expression {
                 }
             },
             Self::Negate(expr) => match expr.evaluate(row)? {
-                Integer(i) => Integer(-i),
+                Integer(i) => Integer(i.checked_neg().ok_or_else(|| overflow(format!("-{}", i)))?),
                 Float(f) => Float(-f),
                 Null => Null,
                 value => return Err(Error::Value(format!("Can't negate {}", value))),
             },
             Self::Subtract(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Integer(lhs), Integer(rhs)) => Integer(
-                    lhs.checked_sub(rhs).ok_or_else(|| Error::Value("Integer overflow".into()))?,
+                    lhs.checked_sub(rhs).ok_or_else(|| overflow(format!("{} - {}", lhs, rhs)))?,
                 ),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 - rhs),
                 (Integer(_), Null) => Null,
@@ -238,6 +273,7 @@ impl Expression {
                     return Err(Error::Value(format!("Can't subtract {} and {}", lhs, rhs)))
                 }
             },
+            Self::Wrapping(expr) => expr.evaluate_wrapping(row)?,
 
             // String operations
             Self::Like(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
@@ -256,9 +292,194 @@ impl Expression {
                 (Null, String(_)) => Null,
                 (lhs, rhs) => return Err(Error::Value(format!("Can't LIKE {} and {}", lhs, rhs))),
             },
+
+            // Array operations
+            Self::Array(exprs) => {
+                let values = exprs.iter().map(|e| e.evaluate(row)).collect::<Result<Vec<_>>>()?;
+                let mut datatype = None;
+                for value in &values {
+                    match (value, value.datatype(), &datatype) {
+                        (Array(_), _, _) => {
+                            return Err(Error::Value("Arrays can't be nested".into()))
+                        }
+                        (_, Some(d), Some(expect)) if &d != expect => {
+                            return Err(Error::Value(format!(
+                                "Array elements must have the same datatype, found {} and {}",
+                                expect, d
+                            )))
+                        }
+                        (_, Some(d), None) => datatype = Some(d),
+                        _ => {}
+                    }
+                }
+                Array(values)
+            }
+            Self::Element(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Array(values), Integer(i)) => match usize::try_from(i) {
+                    Ok(i) if i >= 1 => values.into_iter().nth(i - 1).unwrap_or(Null),
+                    _ => Null,
+                },
+                (Array(_), Null) | (Null, Integer(_)) | (Null, Null) => Null,
+                (lhs, rhs) => {
+                    return Err(Error::Value(format!("Can't index {} with {}", lhs, rhs)))
+                }
+            },
+            Self::Any(lhs, comparison, rhs) => {
+                let results = comparison.evaluate_each(lhs.evaluate(row)?, rhs.evaluate(row)?)?;
+                match results {
+                    None => Null,
+                    Some(r) if r.contains(&Boolean(true)) => Boolean(true),
+                    Some(r) if r.contains(&Null) => Null,
+                    Some(_) => Boolean(false),
+                }
+            }
+            Self::All(lhs, comparison, rhs) => {
+                let results = comparison.evaluate_each(lhs.evaluate(row)?, rhs.evaluate(row)?)?;
+                match results {
+                    None => Null,
+                    Some(r) if r.contains(&Boolean(false)) => Boolean(false),
+                    Some(r) if r.contains(&Null) => Null,
+                    Some(_) => Boolean(true),
+                }
+            }
+
+            Self::Call(name, args) => functions::call(
+                name,
+                args.iter().map(|arg| arg.evaluate(row)).collect::<Result<_>>()?,
+            )?,
         })
     }
 
+    /// Evaluates an arithmetic operation with wrapping semantics for integer operands. Other
+    /// operands, and integer operations that are undefined (e.g. division by zero or factorials
+    /// of negative numbers), are evaluated as usual.
+    fn evaluate_wrapping(&self, row: Option<&Row>) -> Result<Value> {
+        use Value::*;
+        let (lhs, rhs) = match self {
+            Self::Add(lhs, rhs)
+            | Self::Divide(lhs, rhs)
+            | Self::Exponentiate(lhs, rhs)
+            | Self::Multiply(lhs, rhs)
+            | Self::Subtract(lhs, rhs) => (lhs.evaluate(row)?, Some(rhs.evaluate(row)?)),
+            Self::Factorial(expr) | Self::Negate(expr) => (expr.evaluate(row)?, None),
+            expr => return expr.evaluate(row),
+        };
+        Ok(match (self, lhs, rhs) {
+            (Self::Add(..), Integer(lhs), Some(Integer(rhs))) => Integer(lhs.wrapping_add(rhs)),
+            (Self::Divide(..), Integer(lhs), Some(Integer(rhs))) if rhs != 0 => {
+                Integer(lhs.wrapping_div(rhs))
+            }
+            (Self::Exponentiate(..), Integer(lhs), Some(Integer(rhs))) if rhs >= 0 => {
+                // i64::wrapping_pow() only takes u32 exponents, so use exponentiation by squaring.
+                let (mut base, mut exp, mut result) = (lhs, rhs, 1_i64);
+                while exp > 0 {
+                    if exp & 1 == 1 {
+                        result = result.wrapping_mul(base);
+                    }
+                    base = base.wrapping_mul(base);
+                    exp >>= 1;
+                }
+                Integer(result)
+            }
+            (Self::Multiply(..), Integer(lhs), Some(Integer(rhs))) => {
+                Integer(lhs.wrapping_mul(rhs))
+            }
+            (Self::Negate(..), Integer(i), None) => Integer(i.wrapping_neg()),
+            (Self::Subtract(..), Integer(lhs), Some(Integer(rhs))) => {
+                Integer(lhs.wrapping_sub(rhs))
+            }
+            // n! is divisible by 2^64 for n >= 66, so it wraps to 0.
+            (Self::Factorial(..), Integer(i), None) if i >= 66 => Integer(0),
+            (Self::Factorial(..), Integer(i), None) if i >= 0 => {
+                Integer((1..=i).fold(1_i64, |a, b| a.wrapping_mul(b)))
+            }
+            (expr, lhs, rhs) => {
+                let lhs = Box::new(Self::Constant(lhs));
+                let rhs = rhs.map(|rhs| Box::new(Self::Constant(rhs)));
+                match (expr, rhs) {
+                    (Self::Add(..), Some(rhs)) => Self::Add(lhs, rhs),
+                    (Self::Divide(..), Some(rhs)) => Self::Divide(lhs, rhs),
+                    (Self::Exponentiate(..), Some(rhs)) => Self::Exponentiate(lhs, rhs),
+                    (Self::Multiply(..), Some(rhs)) => Self::Multiply(lhs, rhs),
+                    (Self::Subtract(..), Some(rhs)) => Self::Subtract(lhs, rhs),
+                    (Self::Factorial(..), None) => Self::Factorial(lhs),
+                    (Self::Negate(..), None) => Self::Negate(lhs),
+                    (expr, _) => panic!("Unexpected wrapping expression {}", expr),
+                }
+                .evaluate(row)?
+            }
+        })
+    }
+
+    /// Returns the datatype of the expression's values, given the columns of the rows it's
+    /// evaluated on, or None if unknown (e.g. NULL, or an Integer exponentiation which may yield
+    /// a Float). Invalid operand types are not checked, since evaluation errors on them anyway.
+    pub fn datatype(&self, columns: &[Column]) -> Option<DataType> {
+        use DataType::*;
+        match self {
+            Self::Constant(value) => value.datatype(),
+            Self::Field(i, _) => {
+                columns.get(*i).and_then(|c| c.datatype.as_ref()).map(|d| d.value_type())
+            }
+
+            Self::And(..)
+            | Self::Not(..)
+            | Self::Or(..)
+            | Self::Equal(..)
+            | Self::GreaterThan(..)
+            | Self::IsNull(..)
+            | Self::LessThan(..)
+            | Self::Like(..)
+            | Self::Any(..)
+            | Self::All(..) => Some(Boolean),
+
+            Self::Exponentiate(lhs, rhs) => {
+                match (lhs.datatype(columns)?, rhs.datatype(columns)?) {
+                    (Integer, Integer) => None,
+                    (Integer, Float) | (Float, Integer) | (Float, Float) => Some(Float),
+                    _ => None,
+                }
+            }
+            Self::Add(lhs, rhs)
+            | Self::Divide(lhs, rhs)
+            | Self::Modulo(lhs, rhs)
+            | Self::Multiply(lhs, rhs)
+            | Self::Subtract(lhs, rhs) => match (lhs.datatype(columns)?, rhs.datatype(columns)?) {
+                (Integer, Integer) => Some(Integer),
+                (Integer, Float) | (Float, Integer) | (Float, Float) => Some(Float),
+                _ => None,
+            },
+            Self::Factorial(_) => Some(Integer),
+            Self::Assert(expr) | Self::Negate(expr) | Self::Wrapping(expr) => {
+                expr.datatype(columns)
+            }
+            Self::Array(exprs) => {
+                exprs.iter().find_map(|e| e.datatype(columns)).map(|d| Array(Box::new(d)))
+            }
+            Self::Element(expr, _) => match expr.datatype(columns)? {
+                Array(datatype) => Some(*datatype),
+                _ => None,
+            },
+            Self::Call(name, _) => functions::get(name).ok()?.map(|f| f.returns.value_type()),
+        }
+    }
+
+    /// Wraps an integer arithmetic operation in a Wrapping expression, such that integer
+    /// overflow wraps around instead of erroring. Other expressions are returned as is. This
+    /// does not descend into children, and is meant to be applied via transform().
+    pub fn wrapping(self) -> Self {
+        match self {
+            Self::Add(..)
+            | Self::Divide(..)
+            | Self::Exponentiate(..)
+            | Self::Factorial(..)
+            | Self::Multiply(..)
+            | Self::Negate(..)
+            | Self::Subtract(..) => Self::Wrapping(Box::new(self)),
+            expr => expr,
+        }
+    }
+
     /// Walks the expression tree while calling a closure. Returns true as soon as the closure
     /// returns true. This is the inverse of walk().
     pub fn contains<F: Fn(&Expression) -> bool>(&self, visitor: &F) -> bool {
@@ -285,6 +506,7 @@ impl Expression {
             Self::Add(lhs, rhs)
             | Self::And(lhs, rhs)
             | Self::Divide(lhs, rhs)
+            | Self::Element(lhs, rhs)
             | Self::Equal(lhs, rhs)
             | Self::Exponentiate(lhs, rhs)
             | Self::GreaterThan(lhs, rhs)
@@ -293,7 +515,9 @@ impl Expression {
             | Self::Modulo(lhs, rhs)
             | Self::Multiply(lhs, rhs)
             | Self::Or(lhs, rhs)
-            | Self::Subtract(lhs, rhs) => {
+            | Self::Subtract(lhs, rhs)
+            | Self::Any(lhs, _, rhs)
+            | Self::All(lhs, _, rhs) => {
                 Self::replace_with(lhs, |e| e.transform(before, after))?;
                 Self::replace_with(rhs, |e| e.transform(before, after))?;
             }
@@ -302,7 +526,14 @@ impl Expression {
             | Self::Factorial(expr)
             | Self::IsNull(expr)
             | Self::Negate(expr)
-            | Self::Not(expr) => Self::replace_with(expr, |e| e.transform(before, after))?,
+            | Self::Not(expr)
+            | Self::Wrapping(expr) => Self::replace_with(expr, |e| e.transform(before, after))?,
+
+            Self::Call(_, args) | Self::Array(args) => {
+                for arg in args.iter_mut() {
+                    Self::replace_with(arg, |e| e.transform(before, after))?;
+                }
+            }
 
             Self::Constant(_) | Self::Field(_, _) => {}
         };
@@ -316,6 +547,7 @@ impl Expression {
                 Self::Add(lhs, rhs)
                 | Self::And(lhs, rhs)
                 | Self::Divide(lhs, rhs)
+                | Self::Element(lhs, rhs)
                 | Self::Equal(lhs, rhs)
                 | Self::Exponentiate(lhs, rhs)
                 | Self::GreaterThan(lhs, rhs)
@@ -324,13 +556,18 @@ impl Expression {
                 | Self::Modulo(lhs, rhs)
                 | Self::Multiply(lhs, rhs)
                 | Self::Or(lhs, rhs)
-                | Self::Subtract(lhs, rhs) => lhs.walk(visitor) && rhs.walk(visitor),
+                | Self::Subtract(lhs, rhs)
+                | Self::Any(lhs, _, rhs)
+                | Self::All(lhs, _, rhs) => lhs.walk(visitor) && rhs.walk(visitor),
 
                 Self::Assert(expr)
                 | Self::Factorial(expr)
                 | Self::IsNull(expr)
                 | Self::Negate(expr)
-                | Self::Not(expr) => expr.walk(visitor),
+                | Self::Not(expr)
+                | Self::Wrapping(expr) => expr.walk(visitor),
+
+                Self::Call(_, args) | Self::Array(args) => args.iter().all(|arg| arg.walk(visitor)),
 
                 Self::Constant(_) | Self::Field(_, _) => true,
             }
@@ -464,7 +701,8 @@ impl Expression {
     }
 
     // Checks if the expression is a field lookup, and returns the list of values looked up.
-    // Expressions must be a combination of =, IS NULL, OR to be converted.
+    // Expressions must be a combination of =, = ANY() of a constant array, IS NULL, OR to be
+    // converted.
     pub fn as_lookup(&self, field: usize) -> Option<Vec<Value>> {
         use Expression::*;
         // FIXME This should use a single match level, but since the child expressions are boxed
@@ -479,6 +717,13 @@ impl Expression {
                 Field(i, _) if i == &field => Some(vec![Value::Null]),
                 _ => None,
             },
+            // NULL elements never compare equal, so they're not looked up.
+            Any(lhs, Comparison::Equal, rhs) => match (&**lhs, &**rhs) {
+                (Field(i, _), Constant(Value::Array(values))) if i == &field => {
+                    Some(values.iter().filter(|v| **v != Value::Null).cloned().collect())
+                }
+                (_, _) => None,
+            },
             Or(lhs, rhs) => match (lhs.as_lookup(field), rhs.as_lookup(field)) {
                 (Some(mut lvalues), Some(mut rvalues)) => {
                     lvalues.append(&mut rvalues);
@@ -515,6 +760,76 @@ impl Expression {
         )
         .unwrap()
     }
+
+    // Checks if the expression limits a point field to a bounding box, and returns the lower left
+    // and upper right corners of the box. Expressions must be a contains() call with constant
+    // corners, or a distance() from a constant point that is < or <= a constant radius. Points
+    // within the box may still not match, e.g. if they're further away than the radius.
+    pub fn as_bounding_box(&self, field: usize) -> Option<(Value, Value)> {
+        use Expression::*;
+        match &*self {
+            Call(name, args) if name == "contains" => match args.as_slice() {
+                [Constant(Value::Point(ax, ay)), Constant(Value::Point(bx, by)), Field(i, _)]
+                    if i == &field =>
+                {
+                    Some((
+                        Value::Point(ax.min(*bx), ay.min(*by)),
+                        Value::Point(ax.max(*bx), ay.max(*by)),
+                    ))
+                }
+                _ => None,
+            },
+            LessThan(lhs, rhs) => Self::as_distance_box(lhs, rhs, field),
+            GreaterThan(lhs, rhs) => Self::as_distance_box(rhs, lhs, field),
+            Or(lhs, rhs) => match (&**lhs, &**rhs) {
+                (LessThan(a, b), Equal(c, d)) if a == c && b == d => {
+                    Self::as_distance_box(a, b, field)
+                }
+                (GreaterThan(a, b), Equal(c, d)) if a == c && b == d => {
+                    Self::as_distance_box(b, a, field)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Returns the bounding box of a circle, given a distance() call between a point field and a
+    // constant point, and a constant radius. Gives up if the box has NaN corners, e.g. for
+    // infinite coordinates and radius.
+    fn as_distance_box(
+        distance: &Expression,
+        radius: &Expression,
+        field: usize,
+    ) -> Option<(Value, Value)> {
+        use Expression::*;
+        let radius = match radius {
+            Constant(Value::Float(r)) => *r,
+            Constant(Value::Integer(r)) => *r as f64,
+            _ => return None,
+        };
+        match distance {
+            Call(name, args) if name == "distance" => match args.as_slice() {
+                [Field(i, _), Constant(Value::Point(x, y))]
+                | [Constant(Value::Point(x, y)), Field(i, _)]
+                    if i == &field =>
+                {
+                    let (lower, upper) = ((x - radius, y - radius), (x + radius, y + radius));
+                    if [lower.0, lower.1, upper.0, upper.1].iter().any(|c| c.is_nan()) {
+                        return None;
+                    }
+                    Some((Value::Point(lower.0, lower.1), Value::Point(upper.0, upper.1)))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Returns an integer overflow error for the given operation.
+fn overflow(operation: String) -> Error {
+    Error::Value(format!("Integer overflow: {}", operation))
 }
 
 impl Display for Expression {
@@ -543,9 +858,88 @@ impl Display for Expression {
             Self::Multiply(lhs, rhs) => format!("{} * {}", lhs, rhs),
             Self::Negate(expr) => format!("-{}", expr),
             Self::Subtract(lhs, rhs) => format!("{} - {}", lhs, rhs),
+            Self::Wrapping(expr) => format!("WRAPPING({})", expr),
 
             Self::Like(lhs, rhs) => format!("{} LIKE {}", lhs, rhs),
+
+            Self::Array(exprs) => format!(
+                "ARRAY[{}]",
+                exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            Self::Element(lhs, rhs) => format!("{}[{}]", lhs, rhs),
+            Self::Any(lhs, comparison, rhs) => format!("{} {} ANY({})", lhs, comparison, rhs),
+            Self::All(lhs, comparison, rhs) => format!("{} {} ALL({})", lhs, comparison, rhs),
+
+            Self::Call(name, args) => format!(
+                "{}({})",
+                name,
+                args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ")
+            ),
         };
         write!(f, "{}", s)
     }
 }
+
+/// A comparison operator for ANY and ALL array comparisons.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+impl Comparison {
+    /// Builds a comparison expression, in the same way as the planner does for the operator.
+    pub fn build(&self, lhs: Expression, rhs: Expression) -> Expression {
+        use Expression::*;
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match self {
+            Self::Equal => Equal(lhs, rhs),
+            Self::NotEqual => Not(Equal(lhs, rhs).into()),
+            Self::GreaterThan => GreaterThan(lhs, rhs),
+            Self::GreaterThanOrEqual => {
+                Or(GreaterThan(lhs.clone(), rhs.clone()).into(), Equal(lhs, rhs).into())
+            }
+            Self::LessThan => LessThan(lhs, rhs),
+            Self::LessThanOrEqual => {
+                Or(LessThan(lhs.clone(), rhs.clone()).into(), Equal(lhs, rhs).into())
+            }
+        }
+    }
+
+    /// Compares a value with each element of an array, returning the results, or None if the
+    /// array is NULL.
+    fn evaluate_each(&self, value: Value, array: Value) -> Result<Option<Vec<Value>>> {
+        match array {
+            Value::Array(elements) => Ok(Some(
+                elements
+                    .into_iter()
+                    .map(|e| {
+                        self.build(Expression::Constant(value.clone()), Expression::Constant(e))
+                            .evaluate(None)
+                    })
+                    .collect::<Result<_>>()?,
+            )),
+            Value::Null => Ok(None),
+            array => {
+                Err(Error::Value(format!("Can't compare {} with elements of {}", value, array)))
+            }
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::GreaterThan => ">",
+            Self::GreaterThanOrEqual => ">=",
+            Self::LessThan => "<",
+            Self::LessThanOrEqual => "<=",
+        })
+    }
+}
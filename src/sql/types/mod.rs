@@ -1,41 +1,161 @@
 mod expression;
-pub use expression::Expression;
+pub use expression::{Comparison, Expression};
 
 use crate::error::{Error, Result};
+use crate::storage::kv::encoding::encode_point;
 
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
-/// A datatype
+/// A datatype. Integer is a 64-bit integer, while Int is a 32-bit integer whose values are
+/// stored and evaluated as Integer values, but must be within its range. Similarly, Varchar is a
+/// String with a maximum length in characters. Point is a two-dimensional point with float
+/// coordinates. Array is an array of values of a scalar element datatype. Enum is a user-defined
+/// enum type, see Enum.
 #[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     Boolean,
     Integer,
     Float,
     String,
+    Int,
+    Varchar(usize),
+    Point,
+    Array(Box<DataType>),
+    Enum(Arc<Enum>),
 }
 
 impl std::fmt::Display for DataType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.write_str(match self {
-            Self::Boolean => "BOOLEAN",
-            Self::Integer => "INTEGER",
-            Self::Float => "FLOAT",
-            Self::String => "STRING",
-        })
+        match self {
+            Self::Boolean => f.write_str("BOOLEAN"),
+            Self::Integer => f.write_str("INTEGER"),
+            Self::Float => f.write_str("FLOAT"),
+            Self::String => f.write_str("STRING"),
+            Self::Int => f.write_str("INT"),
+            Self::Varchar(length) => write!(f, "VARCHAR({})", length),
+            Self::Point => f.write_str("POINT"),
+            Self::Array(element) => write!(f, "{}[]", element),
+            Self::Enum(e) => f.write_str(&e.name),
+        }
+    }
+}
+
+impl DataType {
+    /// Returns the datatype of the datatype's values, i.e. Integer for Int and String for Varchar,
+    /// and likewise for array elements.
+    pub fn value_type(&self) -> DataType {
+        match self {
+            Self::Int => Self::Integer,
+            Self::Varchar(_) => Self::String,
+            Self::Array(element) => Self::Array(Box::new(element.value_type())),
+            datatype => datatype.clone(),
+        }
+    }
+
+    /// Checks whether a non-null value is of the datatype's value type. Array elements must be
+    /// NULL or of the element datatype, so an array without non-null elements is accepted by any
+    /// array datatype. Does not check the datatype's range, see in_range().
+    pub fn accepts(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Self::Array(element), Value::Array(values)) => {
+                values.iter().all(|v| v == &Value::Null || element.accepts(v))
+            }
+            (datatype, value) => value.datatype() == Some(datatype.value_type()),
+        }
+    }
+
+    /// Converts a string to a value of an enum datatype, if it's one of the enum's labels, and
+    /// likewise for the elements of an enum array. Other values are returned as is, for
+    /// validation to reject them if necessary.
+    pub fn coerce(&self, value: Value) -> Value {
+        match (self, value) {
+            (Self::Array(element), Value::Array(values)) => {
+                Value::Array(values.into_iter().map(|v| element.coerce(v)).collect())
+            }
+            (Self::Enum(e), Value::String(s)) => match e.ordinal(&s) {
+                Some(ordinal) => Value::Enum(e.clone(), ordinal),
+                None => Value::String(s),
+            },
+            (_, value) => value,
+        }
+    }
+
+    /// Returns the maximum length in characters of the datatype's values, if limited.
+    pub fn max_length(&self) -> Option<usize> {
+        match self {
+            Self::Varchar(length) => Some(*length),
+            _ => None,
+        }
+    }
+
+    /// Checks whether a value of the datatype's value type is within the datatype's range, or
+    /// for strings its maximum length.
+    pub fn in_range(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Self::Int, Value::Integer(i)) => i32::try_from(*i).is_ok(),
+            (Self::Varchar(length), Value::String(s)) => s.chars().count() <= *length,
+            (Self::Array(element), Value::Array(values)) => {
+                values.iter().all(|v| element.in_range(v))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A user-defined enum type, created with CREATE TYPE and stored in the catalog. Column datatypes
+/// and values of the enum share its definition, which can't change while it's in use.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Enum {
+    pub name: String,
+    /// The enum's labels, in declaration order
+    pub labels: Vec<String>,
+}
+
+impl Enum {
+    /// Returns the ordinal of a label, i.e. its position in declaration order, if it exists.
+    pub fn ordinal(&self, label: &str) -> Option<u64> {
+        self.labels.iter().position(|l| l == label).map(|i| i as u64)
     }
 }
 
-/// A specific value of a data type
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A specific value of a data type. Floats use SQL rather than IEEE 754 semantics for equality and
+/// ordering (see cmp_floats()), i.e. NaN equals NaN and -0.0 equals 0.0, and likewise for point
+/// coordinates. Points are ordered along a Z-curve, like their key encoding (see cmp_points()).
+/// Arrays are compared element-wise, and ordered lexicographically. Enum values are given by their
+/// ordinal in the enum type, and are ordered by it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Value {
     Null,
     Boolean(bool),
     Integer(i64),
     Float(f64),
     String(String),
+    Point(f64, f64),
+    Array(Vec<Value>),
+    Enum(Arc<Enum>, u64),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::Boolean(a), Self::Boolean(b)) => a == b,
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => cmp_floats(*a, *b) == Ordering::Equal,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Point(ax, ay), Self::Point(bx, by)) => {
+                cmp_points((*ax, *ay), (*bx, *by)) == Ordering::Equal
+            }
+            (Self::Array(a), Self::Array(b)) => a == b,
+            (Self::Enum(a, i), Self::Enum(b, j)) => a.name == b.name && i == j,
+            (_, _) => false,
+        }
+    }
 }
 
 impl std::cmp::Eq for Value {}
@@ -43,13 +163,22 @@ impl std::cmp::Eq for Value {}
 #[allow(clippy::derive_hash_xor_eq)]
 impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.datatype().hash(state);
+        std::mem::discriminant(self).hash(state);
         match self {
-            Value::Null => self.hash(state),
+            Value::Null => {}
             Value::Boolean(v) => v.hash(state),
             Value::Integer(v) => v.hash(state),
-            Value::Float(v) => v.to_be_bytes().hash(state),
+            Value::Float(v) => normalize_float(*v).to_be_bytes().hash(state),
             Value::String(v) => v.hash(state),
+            Value::Point(x, y) => {
+                normalize_float(*x).to_be_bytes().hash(state);
+                normalize_float(*y).to_be_bytes().hash(state);
+            }
+            Value::Array(v) => v.hash(state),
+            Value::Enum(e, i) => {
+                e.name.hash(state);
+                i.hash(state);
+            }
         }
     }
 }
@@ -67,7 +196,8 @@ impl<'a> Into<Cow<'a, Value>> for &'a Value {
 }
 
 impl Value {
-    /// Returns the value's datatype, or None for null values
+    /// Returns the value's datatype, or None if unknown, i.e. for null values and for arrays
+    /// without any non-null elements. Use DataType::accepts() to check a value's datatype.
     pub fn datatype(&self) -> Option<DataType> {
         match self {
             Self::Null => None,
@@ -75,6 +205,12 @@ impl Value {
             Self::Integer(_) => Some(DataType::Integer),
             Self::Float(_) => Some(DataType::Float),
             Self::String(_) => Some(DataType::String),
+            Self::Point(_, _) => Some(DataType::Point),
+            Self::Array(values) => values
+                .iter()
+                .find_map(|v| v.datatype())
+                .map(|element| DataType::Array(Box::new(element))),
+            Self::Enum(e, _) => Some(DataType::Enum(e.clone())),
         }
     }
 
@@ -109,6 +245,22 @@ impl Value {
             v => Err(Error::Value(format!("Not a string: {:?}", v))),
         }
     }
+
+    /// Returns the inner array values, or an error if not an array
+    pub fn array(self) -> Result<Vec<Value>> {
+        match self {
+            Self::Array(values) => Ok(values),
+            v => Err(Error::Value(format!("Not an array: {:?}", v))),
+        }
+    }
+
+    /// Returns the inner point coordinates, or an error if not a point
+    pub fn point(self) -> Result<(f64, f64)> {
+        match self {
+            Self::Point(x, y) => Ok((x, y)),
+            v => Err(Error::Value(format!("Not a point: {:?}", v))),
+        }
+    }
 }
 
 impl std::fmt::Display for Value {
@@ -121,6 +273,21 @@ impl std::fmt::Display for Value {
                 Self::Integer(i) => i.to_string(),
                 Self::Float(f) => f.to_string(),
                 Self::String(s) => s.clone(),
+                Self::Point(x, y) => format!("POINT({}, {})", x, y),
+                Self::Enum(e, i) => e.labels.get(*i as usize).cloned().unwrap_or_default(),
+                Self::Array(values) => format!(
+                    "[{}]",
+                    values
+                        .iter()
+                        .map(|v| match v {
+                            Self::String(_) | Self::Enum(..) => {
+                                format!("'{}'", v.to_string().replace('\'', "''"))
+                            }
+                            v => v.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
             }
             .as_ref(),
         )
@@ -134,16 +301,59 @@ impl PartialOrd for Value {
             (Self::Null, _) => Some(Ordering::Less),
             (_, Self::Null) => Some(Ordering::Greater),
             (Self::Boolean(a), Self::Boolean(b)) => a.partial_cmp(b),
-            (Self::Float(a), Self::Float(b)) => a.partial_cmp(b),
-            (Self::Float(a), Self::Integer(b)) => a.partial_cmp(&(*b as f64)),
-            (Self::Integer(a), Self::Float(b)) => (*a as f64).partial_cmp(b),
+            (Self::Float(a), Self::Float(b)) => Some(cmp_floats(*a, *b)),
+            (Self::Float(a), Self::Integer(b)) => Some(cmp_floats(*a, *b as f64)),
+            (Self::Integer(a), Self::Float(b)) => Some(cmp_floats(*a as f64, *b)),
             (Self::Integer(a), Self::Integer(b)) => a.partial_cmp(b),
             (Self::String(a), Self::String(b)) => a.partial_cmp(b),
+            (Self::Point(ax, ay), Self::Point(bx, by)) => Some(cmp_points((*ax, *ay), (*bx, *by))),
+            (Self::Array(a), Self::Array(b)) => {
+                for (a, b) in a.iter().zip(b) {
+                    match a.partial_cmp(b)? {
+                        Ordering::Equal => {}
+                        ordering => return Some(ordering),
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            }
+            (Self::Enum(a, i), Self::Enum(b, j)) if a.name == b.name => i.partial_cmp(j),
             (_, _) => None,
         }
     }
 }
 
+/// Compares two floats using SQL semantics, which give them a total order: NaN equals NaN and is
+/// greater than all other values (including infinity), and -0.0 equals 0.0. This is the same
+/// order as the key encoding of normalized floats.
+pub fn cmp_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Compares two points by their position along a Z-curve, i.e. by the interleaved bits of their
+/// coordinates, using SQL semantics for the coordinates. This is the same order as the key
+/// encoding of points, which allows bounding boxes to be looked up with index range scans.
+pub fn cmp_points(a: (f64, f64), b: (f64, f64)) -> Ordering {
+    let encode = |(x, y): (f64, f64)| encode_point(normalize_float(x), normalize_float(y));
+    encode(a).cmp(&encode(b))
+}
+
+/// Normalizes a float such that SQL-equal floats have the same binary representation, by
+/// converting -0.0 to 0.0 and any NaN to a positive quiet NaN.
+pub fn normalize_float(f: f64) -> f64 {
+    if f.is_nan() {
+        f64::NAN
+    } else if f == 0.0 {
+        0.0
+    } else {
+        f
+    }
+}
+
 impl From<bool> for Value {
     fn from(v: bool) -> Self {
         Value::Boolean(v)
@@ -181,9 +391,23 @@ pub type Row = Vec<Value>;
 pub type Rows = Box<dyn Iterator<Item = Result<Row>> + Send>;
 
 /// A column (in a result set, see schema::Column for table columns)
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Column {
+    /// The column name, if any, i.e. the table column name or the label given with AS.
     pub name: Option<String>,
+    /// The table the column originates from, if it refers directly to a table column.
+    pub table: Option<String>,
+    /// The column data type, if known. Computed columns have the datatype of the computed
+    /// values, e.g. Integer rather than Int.
+    pub datatype: Option<DataType>,
+}
+
+impl Column {
+    /// Creates a new named result column of the given datatype, which doesn't originate from a
+    /// table.
+    pub fn new(name: &str, datatype: DataType) -> Self {
+        Self { name: Some(name.to_string()), table: None, datatype: Some(datatype) }
+    }
 }
 
 /// A set of columns
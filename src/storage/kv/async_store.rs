@@ -0,0 +1,121 @@
+use super::{Range, Store};
+use crate::error::Result;
+
+use std::sync::{Arc, RwLock};
+
+/// An async key/value store, which wraps a synchronous Store and runs its operations on the tokio
+/// blocking thread pool, such that disk I/O doesn't stall the executor threads. The wrapper is
+/// cheap to clone, and clones share the underlying store.
+#[derive(Clone)]
+pub struct AsyncStore {
+    store: Arc<RwLock<Box<dyn Store>>>,
+}
+
+impl AsyncStore {
+    /// Creates a new async store wrapping the given synchronous store.
+    pub fn new(store: Box<dyn Store>) -> Self {
+        Self { store: Arc::new(RwLock::new(store)) }
+    }
+
+    /// Runs a closure with shared access to the underlying store, on the blocking thread pool.
+    async fn read<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&dyn Store) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(&**store.read()?)).await?
+    }
+
+    /// Runs a closure with exclusive access to the underlying store, on the blocking thread pool.
+    async fn write<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut dyn Store) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(&mut **store.write()?)).await?
+    }
+
+    /// Deletes a key, or does nothing if it does not exist.
+    pub async fn delete(&self, key: Vec<u8>) -> Result<()> {
+        self.write(move |store| store.delete(&key)).await
+    }
+
+    /// Flushes any buffered data to the underlying storage medium.
+    pub async fn flush(&self) -> Result<()> {
+        self.write(|store| store.flush()).await
+    }
+
+    /// Gets a value for a key, if it exists.
+    pub async fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.read(move |store| store.get(&key)).await
+    }
+
+    /// Fetches an ordered range of key/value pairs. Since the scan can't borrow the store across
+    /// await points, the results are buffered in memory.
+    pub async fn scan(&self, range: Range) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read(move |store| store.scan(range).collect()).await
+    }
+
+    /// Sets a value for a key, replacing the existing value if any.
+    pub async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.write(move |store| store.set(&key, value)).await
+    }
+
+    /// Runs a synchronous closure against the underlying store on the blocking thread pool, for
+    /// e.g. batching several operations under a single lock acquisition.
+    pub async fn with<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut dyn Store) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.write(f).await
+    }
+}
+
+impl std::fmt::Display for AsyncStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.store.read() {
+            Ok(store) => write!(f, "async {}", store),
+            Err(_) => write!(f, "async (poisoned)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Memory;
+    use super::*;
+
+    #[tokio::test]
+    async fn operations() -> Result<()> {
+        let s = AsyncStore::new(Box::new(Memory::new()));
+        assert_eq!(None, s.get(b"a".to_vec()).await?);
+
+        s.set(b"a".to_vec(), vec![0x01]).await?;
+        s.set(b"b".to_vec(), vec![0x02]).await?;
+        s.set(b"c".to_vec(), vec![0x03]).await?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a".to_vec()).await?);
+
+        s.delete(b"b".to_vec()).await?;
+        s.flush().await?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"c".to_vec(), vec![0x03])],
+            s.scan(Range::from(..)).await?
+        );
+
+        // Clones share the underlying store.
+        let c = s.clone();
+        c.with(|store| {
+            store.set(b"d", vec![0x04])?;
+            store.delete(b"a")
+        })
+        .await?;
+        assert_eq!(
+            vec![(b"c".to_vec(), vec![0x03]), (b"d".to_vec(), vec![0x04])],
+            s.scan(Range::from(b"b".to_vec()..)).await?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use super::{Range, Scan, Store};
+use crate::error::{Error, Result};
+use crate::storage::Compression;
+
+use std::fmt::Display;
+
+/// The tag byte of an uncompressed value.
+const UNCOMPRESSED: u8 = 0x00;
+
+/// The tag byte of a Snappy-compressed value.
+const SNAPPY: u8 = 0x01;
+
+/// The key of a marker recording that all values in the inner store are tagged. It is written
+/// when the wrapper creates a store, and hidden from reads and scans.
+const TAGGED_KEY: &[u8] = b"\xff\xffcompressed";
+
+/// Key-value store wrapper which compresses values written to an inner store, and transparently
+/// decompresses them on read. Keys are stored as is, so scans are unaffected. Each stored value
+/// is prefixed with a tag byte giving its compression, such that values remain readable when
+/// the compression is changed, and values that don't compress well are stored uncompressed.
+///
+/// Existing stores written without the wrapper have untagged values, and can only be opened
+/// without compression, in which case values are read and written as is.
+pub struct Compressed {
+    inner: Box<dyn Store>,
+    compression: Compression,
+    /// Whether stored values are tagged, i.e. the store was created by the wrapper.
+    tagged: bool,
+}
+
+impl Compressed {
+    /// Creates a new compressed key-value store, wrapping the given store. Errors if the store
+    /// has untagged values written without the wrapper, and compression is enabled.
+    pub fn new(mut inner: Box<dyn Store>, compression: Compression) -> Result<Self> {
+        let tagged = if inner.get(TAGGED_KEY)?.is_some() {
+            true
+        } else if inner.scan(Range::from(..)).next().is_none() {
+            inner.set(TAGGED_KEY, Vec::new())?;
+            true
+        } else if compression == Compression::None {
+            false
+        } else {
+            return Err(Error::Config(format!(
+                "Can't enable {} compression for existing uncompressed store {}",
+                compression, inner
+            )));
+        };
+        Ok(Self { inner, compression, tagged })
+    }
+
+    /// Encodes a value for storage, compressing it if possible.
+    fn encode(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.tagged {
+            return Ok(value);
+        }
+        let (tag, value) = match self.compression.compress(&value)? {
+            Some(compressed) => (SNAPPY, compressed),
+            None => (UNCOMPRESSED, value),
+        };
+        let mut encoded = Vec::with_capacity(value.len() + 1);
+        encoded.push(tag);
+        encoded.extend(value);
+        Ok(encoded)
+    }
+
+    /// Checks that a key isn't the reserved tagged marker key.
+    fn check_key(key: &[u8]) -> Result<()> {
+        match key {
+            TAGGED_KEY => Err(Error::Internal("Can't write reserved compression key".into())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes a stored value, decompressing it if necessary.
+    fn decode(tagged: bool, mut value: Vec<u8>) -> Result<Vec<u8>> {
+        if !tagged {
+            return Ok(value);
+        }
+        match value.first() {
+            Some(&UNCOMPRESSED) => {
+                value.remove(0);
+                Ok(value)
+            }
+            Some(&SNAPPY) => Compression::Snappy.decompress(&value[1..]),
+            Some(tag) => Err(Error::Internal(format!("Invalid value compression tag {}", tag))),
+            None => Err(Error::Internal("Missing value compression tag".into())),
+        }
+    }
+}
+
+impl Display for Compressed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.compression {
+            Compression::None => write!(f, "{}", self.inner),
+            compression => write!(f, "{} ({})", self.inner, compression),
+        }
+    }
+}
+
+impl Store for Compressed {
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        Self::check_key(key)?;
+        self.inner.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if key == TAGGED_KEY {
+            return Ok(None);
+        }
+        let tagged = self.tagged;
+        self.inner.get(key)?.map(|v| Self::decode(tagged, v)).transpose()
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        let tagged = self.tagged;
+        Box::new(
+            self.inner
+                .scan(range)
+                .filter(|r| !matches!(r, Ok((k, _)) if k.as_slice() == TAGGED_KEY))
+                .map(move |r| r.and_then(|(k, v)| Ok((k, Self::decode(tagged, v)?)))),
+        )
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        Self::check_key(key)?;
+        let value = self.encode(value)?;
+        self.inner.set(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Memory, TestSuite};
+    use super::*;
+
+    impl TestSuite<Compressed> for Compressed {
+        fn setup() -> Result<Self> {
+            Compressed::new(Box::new(Memory::new()), Compression::Snappy)
+        }
+    }
+
+    #[test]
+    fn tests() -> Result<()> {
+        Compressed::test()
+    }
+
+    #[test]
+    fn compression() -> Result<()> {
+        let large = b"toydb".repeat(100);
+        let mut s = Compressed::new(Box::new(Memory::new()), Compression::Snappy)?;
+        s.set(b"large", large.clone())?;
+        s.set(b"small", vec![0x01])?;
+
+        // Compressible values are stored compressed, others as is.
+        let stored = s.inner.get(b"large")?.unwrap();
+        assert_eq!(stored[0], SNAPPY);
+        assert!(stored.len() < large.len());
+        assert_eq!(s.inner.get(b"small")?, Some(vec![UNCOMPRESSED, 0x01]));
+
+        // Values are decompressed on read, even when compression is disabled.
+        let s = Compressed::new(s.inner, Compression::None)?;
+        assert_eq!(s.get(b"large")?, Some(large.clone()));
+        assert_eq!(
+            vec![(b"large".to_vec(), large), (b"small".to_vec(), vec![0x01])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+    #[test]
+    fn untagged() -> Result<()> {
+        let untagged = || -> Result<Box<dyn Store>> {
+            let mut inner = Memory::new();
+            inner.set(b"a", vec![UNCOMPRESSED, 0x01])?;
+            inner.set(b"b", vec![SNAPPY])?;
+            Ok(Box::new(inner))
+        };
+
+        // Stores with untagged values can't be opened with compression.
+        assert!(matches!(Compressed::new(untagged()?, Compression::Snappy), Err(Error::Config(_))));
+
+        // Without compression, values are read and written as is.
+        let mut s = Compressed::new(untagged()?, Compression::None)?;
+        assert_eq!(s.get(b"a")?, Some(vec![UNCOMPRESSED, 0x01]));
+        assert_eq!(s.get(b"b")?, Some(vec![SNAPPY]));
+        s.set(b"c", vec![0x02])?;
+        assert_eq!(s.inner.get(b"c")?, Some(vec![0x02]));
+
+        // New stores are marked as tagged, which is hidden from reads.
+        let s = Compressed::new(Box::new(Memory::new()), Compression::None)?;
+        let s = Compressed::new(s.inner, Compression::Snappy)?;
+        assert_eq!(s.get(TAGGED_KEY)?, None);
+        assert!(s.scan(Range::from(..)).next().is_none());
+        Ok(())
+    }
+}
@@ -6,10 +6,20 @@
 //! u64:     Big-endian binary representation.
 //! i64:     Big-endian binary representation, with sign bit flipped.
 //! f64:     Big-endian binary representation, with sign bit flipped if +, all flipped if -.
+//! Point:   Z-curve (Morton code): the bits of both f64 encodings interleaved, x before y.
 //! Value:   Like above, with type prefix 0x00=Null 0x01=Boolean 0x02=Float 0x03=Integer 0x04=String
+//!          0x05=Point 0x06=Array
+//! Array:   Each element value prefixed by 0x01, terminated by 0x00, such that arrays are ordered
+//!          lexicographically by their elements and a prefix of an array sorts before it.
+//!          Enum values are encoded as their Integer ordinal, so they're ordered by declaration
+//!          order and decode as integers, which callers convert back given the enum type.
+//!          Floats (and point coordinates) are normalized first, such that -0.0 and 0.0 (and all
+//!          NaNs) have the same key.
+//!          Null sorts before all other values, matching the default SQL NULL placement (i.e.
+//!          NULLS FIRST for ascending order and NULLS LAST for descending order).
 
 use crate::error::{Error, Result};
-use crate::sql::types::Value;
+use crate::sql::types::{normalize_float, Value};
 
 use std::convert::TryInto;
 
@@ -87,7 +97,8 @@ pub fn take_bytes(bytes: &mut &[u8]) -> Result<Vec<u8>> {
 }
 
 /// Encodes an f64. Uses big-endian form, and flip sign bit to 1 if 0, otherwise flip all bits.
-/// This preserves the natural numerical ordering, with NaN at the end.
+/// This preserves the natural numerical ordering, with positive NaN at the end and negative NaN at
+/// the start. Use normalize_float() to give all NaNs the SQL ordering (at the end).
 pub fn encode_f64(n: f64) -> [u8; 8] {
     let mut bytes = n.to_be_bytes();
     if bytes[0] >> 7 & 1 == 0 {
@@ -118,6 +129,41 @@ pub fn take_f64(bytes: &mut &[u8]) -> Result<f64> {
     Ok(n)
 }
 
+/// Encodes a point as a Z-curve position, by interleaving the bits of the f64 encodings of its
+/// coordinates, starting with the most significant bit of x. Since this preserves the order of
+/// each coordinate, all points within a bounding box encode between its lower left and upper right
+/// corners, so a box can be found with a range scan (along with some points outside it).
+pub fn encode_point(x: f64, y: f64) -> [u8; 16] {
+    let x = u64::from_be_bytes(encode_f64(x));
+    let y = u64::from_be_bytes(encode_f64(y));
+    let mut z: u128 = 0;
+    for i in (0..64).rev() {
+        z = z << 2 | ((x >> i & 1) as u128) << 1 | (y >> i & 1) as u128;
+    }
+    z.to_be_bytes()
+}
+
+/// Decodes a point. See encode_point() for format.
+pub fn decode_point(bytes: [u8; 16]) -> (f64, f64) {
+    let z = u128::from_be_bytes(bytes);
+    let (mut x, mut y) = (0u64, 0u64);
+    for i in (0..64).rev() {
+        x = x << 1 | (z >> (2 * i + 1) & 1) as u64;
+        y = y << 1 | (z >> (2 * i) & 1) as u64;
+    }
+    (decode_f64(x.to_be_bytes()), decode_f64(y.to_be_bytes()))
+}
+
+/// Decodes a point from a slice and shrinks the slice.
+pub fn take_point(bytes: &mut &[u8]) -> Result<(f64, f64)> {
+    if bytes.len() < 16 {
+        return Err(Error::Internal(format!("Unable to decode point from {} bytes", bytes.len())));
+    }
+    let point = decode_point(bytes[0..16].try_into()?);
+    *bytes = &bytes[16..];
+    Ok(point)
+}
+
 /// Encodes an i64. Uses big-endian form, with the first bit flipped to order negative/positive
 /// numbers correctly.
 pub fn encode_i64(n: i64) -> [u8; 8] {
@@ -178,9 +224,22 @@ pub fn encode_value(value: &Value) -> Vec<u8> {
     match value {
         Value::Null => vec![0x00],
         Value::Boolean(b) => vec![0x01, encode_boolean(*b)],
-        Value::Float(f) => [&[0x02][..], &encode_f64(*f)].concat(),
+        Value::Float(f) => [&[0x02][..], &encode_f64(normalize_float(*f))].concat(),
         Value::Integer(i) => [&[0x03][..], &encode_i64(*i)].concat(),
+        Value::Enum(_, ordinal) => [&[0x03][..], &encode_i64(*ordinal as i64)].concat(),
         Value::String(s) => [&[0x04][..], &encode_string(s)].concat(),
+        Value::Point(x, y) => {
+            [&[0x05][..], &encode_point(normalize_float(*x), normalize_float(*y))].concat()
+        }
+        Value::Array(values) => {
+            let mut encoded = vec![0x06];
+            for value in values {
+                encoded.push(0x01);
+                encoded.extend(encode_value(value));
+            }
+            encoded.push(0x00);
+            encoded
+        }
     }
 }
 
@@ -192,6 +251,14 @@ pub fn take_value(bytes: &mut &[u8]) -> Result<Value> {
         0x02 => Ok(Value::Float(take_f64(bytes)?)),
         0x03 => Ok(Value::Integer(take_i64(bytes)?)),
         0x04 => Ok(Value::String(take_string(bytes)?)),
+        0x05 => take_point(bytes).map(|(x, y)| Value::Point(x, y)),
+        0x06 => {
+            let mut values = Vec::new();
+            while take_byte(bytes)? == 0x01 {
+                values.push(take_value(bytes)?);
+            }
+            Ok(Value::Array(values))
+        }
         n => Err(Error::Internal(format!("Invalid value prefix {:x?}", n))),
     }
 }
@@ -447,6 +514,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn encode_point() -> Result<()> {
+        use super::encode_point;
+
+        assert_eq!(encode_point(0.0, 0.0), [0xc0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            encode_point(1.0, 0.0),
+            [0xca, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // Points within a bounding box encode between its corners.
+        let (lower, upper) = (encode_point(-1.5, 2.0), encode_point(3.0, 4.5));
+        for x in &[-1.5, -1.0, 0.0, 0.5, 2.9, 3.0] {
+            for y in &[2.0, 2.5, 4.0, 4.5] {
+                let point = encode_point(*x, *y);
+                assert!(lower <= point && point <= upper, "({}, {}) outside box", x, y);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decode_point() -> Result<()> {
+        use super::{decode_point, encode_point};
+
+        for (x, y) in &[(0.0, 0.0), (-1.5, 2.0), (f64::INFINITY, f64::MIN), (1e-300, -7.25)] {
+            assert_eq!(decode_point(encode_point(*x, *y)), (*x, *y));
+        }
+        Ok(())
+    }
+
     #[test]
     fn encode_value() -> Result<()> {
         use super::encode_value;
@@ -456,7 +554,15 @@ mod tests {
         assert_eq!(encode_value(&Value::Boolean(true)), vec![0x01, 0x01]);
         assert_eq!(
             encode_value(&Value::Float(-0.0)),
-            vec![0x02, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+            vec![0x02, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(encode_value(&Value::Float(-0.0)), encode_value(&Value::Float(0.0)));
+        assert_eq!(
+            encode_value(&Value::Float(-f64::NAN)),
+            vec![0x02, 0xff, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert!(
+            encode_value(&Value::Float(f64::INFINITY)) < encode_value(&Value::Float(-f64::NAN))
         );
         assert_eq!(
             encode_value(&Value::Integer(1024)),
@@ -466,6 +572,22 @@ mod tests {
             encode_value(&Value::String("abc".into())),
             vec![0x04, 0x61, 0x62, 0x63, 0x00, 0x00]
         );
+        assert_eq!(
+            encode_value(&Value::Point(-0.0, 0.0)),
+            vec![0x05, 0xc0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            encode_value(&Value::Array(vec![Value::Null, Value::Boolean(true)])),
+            vec![0x06, 0x01, 0x00, 0x01, 0x01, 0x01, 0x00]
+        );
+        assert!(
+            encode_value(&Value::Array(vec![]))
+                < encode_value(&Value::Array(vec![Value::Integer(1)]))
+        );
+        assert!(
+            encode_value(&Value::Array(vec![Value::Integer(1), Value::Integer(3)]))
+                < encode_value(&Value::Array(vec![Value::Integer(2)]))
+        );
         Ok(())
     }
 
@@ -499,6 +621,15 @@ mod tests {
         assert_eq!(take_value(&mut bytes)?, Value::String("abc".into()));
         assert_eq!(bytes, &[0xaf]);
 
+        let mut bytes: &[u8] =
+            &[0x05, 0xca, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xaf];
+        assert_eq!(take_value(&mut bytes)?, Value::Point(1.0, 0.0));
+        assert_eq!(bytes, &[0xaf]);
+
+        let mut bytes: &[u8] = &[0x06, 0x01, 0x00, 0x01, 0x01, 0x01, 0x00, 0xaf];
+        assert_eq!(take_value(&mut bytes)?, Value::Array(vec![Value::Null, Value::Boolean(true)]));
+        assert_eq!(bytes, &[0xaf]);
+
         Ok(())
     }
 }
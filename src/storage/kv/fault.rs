@@ -0,0 +1,128 @@
+use super::{Range, Scan, Store};
+use crate::error::Result;
+use crate::fault::Faults;
+
+use std::fmt::Display;
+
+/// Key-value store wrapper which injects faults into operations on an inner store, for chaos
+/// testing. Operations are named kv.delete, kv.flush, kv.get, kv.scan, and kv.set. A failed scan
+/// yields a single error.
+pub struct Faulty {
+    inner: Box<dyn Store>,
+    faults: Faults,
+}
+
+impl Faulty {
+    /// Creates a new faulty key-value store, wrapping the given store.
+    pub fn new(inner: Box<dyn Store>, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl Display for Faulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "faulty {}", self.inner)
+    }
+}
+
+impl Store for Faulty {
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.faults.inject("kv.delete")?;
+        self.inner.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.faults.inject("kv.flush")?;
+        self.inner.flush()
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.faults.inject("kv.get")?;
+        self.inner.get(key)
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        match self.faults.inject("kv.scan") {
+            Ok(()) => self.inner.scan(range),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.faults.inject("kv.set")?;
+        self.inner.set(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Memory, TestSuite, MVCC};
+    use super::*;
+    use crate::fault::Rule;
+
+    impl TestSuite<Faulty> for Faulty {
+        fn setup() -> Result<Self> {
+            Ok(Faulty::new(Box::new(Memory::new()), Faults::new()))
+        }
+    }
+
+    #[test]
+    fn tests() -> Result<()> {
+        Faulty::test()
+    }
+
+    #[test]
+    fn inject() -> Result<()> {
+        let faults = Faults::new();
+        let mut s = Faulty::new(Box::new(Memory::new()), faults.clone());
+        s.set(b"a", vec![0x01])?;
+
+        faults.add(Rule::error("kv.set").with_limit(1))?;
+        faults.add(Rule::error("kv.scan").with_limit(1))?;
+        assert_eq!(s.set(b"b", vec![0x02]), Err(Faults::error("kv.set")));
+        assert_eq!(
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>(),
+            Err(Faults::error("kv.scan"))
+        );
+
+        // The failed write had no effect, and the store works once faults are exhausted.
+        assert_eq!(None, s.get(b"b")?);
+        s.set(b"b", vec![0x02])?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"b".to_vec(), vec![0x02])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mvcc_recovery() -> Result<()> {
+        let faults = Faults::new();
+        let mvcc = MVCC::new(Box::new(Faulty::new(Box::new(Memory::new()), faults.clone())));
+
+        // A transaction that fails midway through its writes can be rolled back once the
+        // fault clears, leaving no trace of its writes.
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x01])?;
+        faults.add(Rule::error("kv.set"))?;
+        assert_eq!(txn.set(b"b", vec![0x02]), Err(Faults::error("kv.set")));
+        faults.clear()?;
+        txn.rollback()?;
+
+        let txn = mvcc.begin()?;
+        assert_eq!(None, txn.get(b"a")?);
+        assert_eq!(None, txn.get(b"b")?);
+        txn.commit()?;
+
+        // Reads fail cleanly too, without affecting the transaction.
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x01])?;
+        faults.add(Rule::error("kv.scan").with_limit(1))?;
+        assert_eq!(txn.get(b"a"), Err(Faults::error("kv.scan")));
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        txn.commit()?;
+
+        assert_eq!(Some(vec![0x01]), mvcc.begin()?.get(b"a")?);
+        Ok(())
+    }
+}
@@ -1,10 +1,20 @@
+mod async_store;
+mod compressed;
 pub mod encoding;
+mod fault;
 mod memory;
 pub mod mvcc;
+#[cfg(feature = "sled")]
+mod sled;
 mod std_memory;
 #[cfg(test)]
 mod test;
 
+#[cfg(feature = "sled")]
+pub use self::sled::Sled;
+pub use async_store::AsyncStore;
+pub use compressed::Compressed;
+pub use fault::Faulty;
 pub use memory::Memory;
 pub use mvcc::MVCC;
 pub use std_memory::StdMemory;
@@ -9,12 +9,88 @@ use std::iter::Peekable;
 use std::ops::{Bound, RangeBounds};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+/// Values larger than this are split into chunks of this size, stored under separate keys. This
+/// only bounds the size of values in the underlying store: the full value is still passed to the
+/// MVCC layer in one piece, e.g. in a single Raft log entry.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The magic bytes at the start of an exported snapshot stream.
+const SNAPSHOT_MAGIC: &[u8] = b"TOYDBSNP";
+
+/// The format version of exported snapshot streams.
+const SNAPSHOT_FORMAT: u32 = 1;
+
 /// MVCC status
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
     pub txns: u64,
     pub txns_active: u64,
+    /// The number of update markers, which record the writes of active transactions such that
+    /// they can be rolled back.
+    pub txn_updates: u64,
     pub storage: String,
+    pub stats: Stats,
+}
+
+/// MVCC storage statistics for a key range. These are computed across all versions regardless of
+/// transaction visibility, so uncommitted writes are included.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    /// The number of live keys, i.e. keys whose latest version is not deleted or expired.
+    pub keys: u64,
+    /// The logical size of live keys and their latest values, in bytes.
+    pub bytes: u64,
+    /// The total number of versions across all keys.
+    pub versions: u64,
+    /// The number of versions that are not the latest live version of a key, i.e. superseded
+    /// versions, deletion tombstones, and expired versions, which may be garbage collected.
+    pub dead_versions: u64,
+}
+
+impl Stats {
+    /// Computes statistics for a key range by scanning it.
+    fn compute(store: &dyn Store, range: impl RangeBounds<Vec<u8>>) -> Result<Self> {
+        let next_version = match store.get(&Key::TxnNext.encode())? {
+            Some(ref v) => deserialize(v)?,
+            None => 1,
+        };
+        let mut stats = Self::default();
+        let mut scan = store.scan(Key::record_range(range)).peekable();
+        while let Some((k, v)) = scan.next().transpose()? {
+            let key = match Key::decode(&k)? {
+                Key::Record(key, _) => key.into_owned(),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            stats.versions += 1;
+            // Older versions are followed by a newer version of the same key.
+            let latest = match scan.peek() {
+                Some(Ok((next, _))) => match Key::decode(next)? {
+                    Key::Record(next_key, _) => next_key != key,
+                    k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+                },
+                _ => true,
+            };
+            let size = match deserialize::<Record>(&v)?.size(next_version) {
+                Some(size) if latest => size,
+                _ => {
+                    stats.dead_versions += 1;
+                    continue;
+                }
+            };
+            stats.keys += 1;
+            stats.bytes += key.len() as u64 + size;
+        }
+        Ok(stats)
+    }
+}
+
+impl std::ops::AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        self.keys += other.keys;
+        self.bytes += other.bytes;
+        self.versions += other.versions;
+        self.dead_versions += other.dead_versions;
+    }
 }
 
 /// An MVCC-based transactional key-value store.
@@ -38,12 +114,17 @@ impl MVCC {
     /// Begins a new transaction in read-write mode.
     #[allow(dead_code)]
     pub fn begin(&self) -> Result<Transaction> {
-        Transaction::begin(self.store.clone(), Mode::ReadWrite)
+        Transaction::begin(self.store.clone(), Mode::ReadWrite, Priority::default())
     }
 
     /// Begins a new transaction in the given mode.
     pub fn begin_with_mode(&self, mode: Mode) -> Result<Transaction> {
-        Transaction::begin(self.store.clone(), mode)
+        Transaction::begin(self.store.clone(), mode, Priority::default())
+    }
+
+    /// Begins a new transaction in the given mode and priority.
+    pub fn begin_with_priority(&self, mode: Mode, priority: Priority) -> Result<Transaction> {
+        Transaction::begin(self.store.clone(), mode, priority)
     }
 
     /// Resumes a transaction with the given ID.
@@ -51,6 +132,28 @@ impl MVCC {
         Transaction::resume(self.store.clone(), id)
     }
 
+    /// Flushes the underlying key/value store to the storage medium.
+    pub fn flush(&self) -> Result<()> {
+        self.store.write()?.flush()
+    }
+
+    /// Computes storage statistics for a key range.
+    pub fn stats(&self, range: impl RangeBounds<Vec<u8>>) -> Result<Stats> {
+        Stats::compute(&**self.store.read()?, range)
+    }
+
+    /// Subscribes to committed changes in the given key range, starting at the given commit
+    /// position, see `Transaction::changes()`.
+    pub fn watch(&self, range: impl RangeBounds<Vec<u8>>, from: u64) -> Watch {
+        let clone = |bound: Bound<&Vec<u8>>| match bound {
+            Bound::Included(k) => Bound::Included(k.clone()),
+            Bound::Excluded(k) => Bound::Excluded(k.clone()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range = (clone(range.start_bound()), clone(range.end_bound()));
+        Watch { mvcc: self.clone(), range, from }
+    }
+
     /// Fetches an unversioned metadata value
     pub fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let session = self.store.read()?;
@@ -63,6 +166,419 @@ impl MVCC {
         session.set(&Key::Metadata(key.into()).encode(), value)
     }
 
+    /// Purges expired keys from storage, returning the number of keys purged. A key is purged if
+    /// its latest version below the given version has expired at or before it, in which case that
+    /// version and all older versions of the key are removed. The caller must ensure no active or
+    /// future transactions (including snapshot transactions) read at an earlier version, since
+    /// they would otherwise see the key as missing rather than as it was.
+    pub fn purge_expired(&self, version: u64) -> Result<u64> {
+        let mut session = self.store.write()?;
+        let mut scan = session.scan(Range::from(Key::Record(vec![].into(), 0).encode()..));
+        // Record keys are ordered by key then version, so we track the versions of the current
+        // key and check the latest one once we've seen all of them.
+        let mut expired: Vec<(Vec<u8>, Record)> = Vec::new();
+        let mut current: Vec<(Vec<u8>, Record)> = Vec::new();
+        let mut current_key: Option<Vec<u8>> = None;
+        let mut flush = |current: &mut Vec<(Vec<u8>, Record)>| {
+            if let Some((_, latest)) = current.last() {
+                if latest.expires().filter(|e| *e <= version).is_some() {
+                    expired.append(current);
+                }
+            }
+            current.clear();
+        };
+        while let Some((k, v)) = scan.next().transpose()? {
+            let (key, record_version) = match Key::decode(&k)? {
+                Key::Record(key, record_version) => (key.into_owned(), record_version),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            if record_version >= version {
+                continue;
+            }
+            if current_key.as_ref() != Some(&key) {
+                flush(&mut current);
+                current_key = Some(key);
+            }
+            current.push((k, deserialize(&v)?));
+        }
+        flush(&mut current);
+        std::mem::drop(scan);
+
+        let mut purged = 0;
+        for (k, record) in expired {
+            let key = match Key::decode(&k)? {
+                Key::Record(key, _) => key.into_owned(),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            let record = match record {
+                Record::Expiring { record, .. } => {
+                    purged += 1;
+                    *record
+                }
+                record => record,
+            };
+            if let Record::Chunked { version, chunks, .. } = record {
+                for i in 0..chunks {
+                    session.delete(&Key::Chunk((&key).into(), version, i).encode())?;
+                }
+            }
+            session.delete(&k)?;
+        }
+        Ok(purged)
+    }
+
+    /// Garbage collects old versions that are no longer visible to any transaction reading at or
+    /// after the given version, scanning at most about the given number of records, and returns
+    /// the number of versions removed and whether the garbage collection pass is complete. For
+    /// each key, the latest version visible at the GC version is retained along with all later
+    /// versions, and older versions are removed. The latest visible version is also removed if it
+    /// is deleted or expired, since readers would see the key as missing either way.
+    ///
+    /// A pass caps the GC version at the oldest active transaction and snapshot version, and
+    /// records it as `Key::GcVersion` up front such that snapshot transactions before it are
+    /// rejected rather than seeing incomplete data. The pass then scans records in batches, each
+    /// holding the write lock, and records its progress as `Key::GcCursor`. Calls continue an
+    /// incomplete pass regardless of the given version. Once all records have been scanned,
+    /// snapshots and commit entries (see `Transaction::changes()`) of transactions before the GC
+    /// version are removed too.
+    pub fn gc(&self, version: u64, batch_size: usize) -> Result<(u64, bool)> {
+        let mut session = self.store.write()?;
+        let (version, start) = match session.get(&Key::GcCursor.encode())? {
+            Some(cursor) => (Self::gc_version_in(&**session)?, cursor),
+            None => {
+                let mut version = version;
+                let mut scan = session
+                    .scan(Range::from(Key::TxnActive(0).encode()..Key::TxnSnapshot(0).encode()));
+                while let Some((k, v)) = scan.next().transpose()? {
+                    match (Key::decode(&k)?, deserialize::<TxnState>(&v)?.mode) {
+                        (Key::TxnActive(id), Mode::Snapshot { version: snapshot }) => {
+                            version = version.min(id).min(snapshot)
+                        }
+                        (Key::TxnActive(id), _) => version = version.min(id),
+                        (k, _) => {
+                            return Err(Error::Internal(format!("Expected TxnActive, got {:?}", k)))
+                        }
+                    }
+                }
+                std::mem::drop(scan);
+                if version <= Self::gc_version_in(&**session)? {
+                    return Ok((0, true));
+                }
+                session.set(&Key::GcVersion.encode(), serialize(&version)?)?;
+                (version, Key::Record(vec![].into(), 0).encode())
+            }
+        };
+
+        // Transactions that were active at the GC version, and are thus invisible to it, may have
+        // committed after it. Readers after the GC version may or may not see their writes, so
+        // we retain them and the version they supersede. The snapshot may be missing if the GC
+        // version predates an import, in which case there were no such transactions.
+        let invisible: HashSet<u64> = match session.get(&Key::TxnSnapshot(version).encode())? {
+            Some(v) => deserialize(&v)?,
+            None => HashSet::new(),
+        };
+
+        // Record keys are ordered by key then version, so we track the versions of the current
+        // key below the GC version and pick the removable ones once we've seen all of them. A
+        // batch ends at the first key after the batch size is reached, where the next one begins.
+        let mut removable: Vec<(Vec<u8>, Record)> = Vec::new();
+        let mut current: Vec<(Vec<u8>, u64, Record)> = Vec::new();
+        let mut current_key: Option<Vec<u8>> = None;
+        let mut flush = |current: &mut Vec<(Vec<u8>, u64, Record)>| {
+            if let Some(latest) = current.iter().rposition(|(_, v, _)| !invisible.contains(v)) {
+                // A missing latest version can only be removed if no retained invisible version
+                // precedes it, since readers that see that version would otherwise see it again.
+                let (_, _, record) = &current[latest];
+                let missing = match record {
+                    Record::Deleted => true,
+                    record => record.expires().filter(|e| *e <= version).is_some(),
+                };
+                let shadows = current[..latest].iter().any(|(_, v, _)| invisible.contains(v));
+                let end = if missing && !shadows { latest + 1 } else { latest };
+                removable.extend(
+                    current
+                        .drain(..)
+                        .take(end)
+                        .filter(|(_, v, _)| !invisible.contains(v))
+                        .map(|(k, _, r)| (k, r)),
+                );
+            }
+            current.clear();
+        };
+        let mut scanned = 0;
+        let mut next = None;
+        let mut scan = session.scan(Range::from(start..));
+        while let Some((k, v)) = scan.next().transpose()? {
+            let (key, record_version) = match Key::decode(&k)? {
+                Key::Record(key, record_version) => (key.into_owned(), record_version),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            if current_key.as_ref() != Some(&key) {
+                flush(&mut current);
+                if scanned >= batch_size {
+                    next = Some(k);
+                    break;
+                }
+                current_key = Some(key);
+            }
+            scanned += 1;
+            if record_version < version {
+                current.push((k, record_version, deserialize(&v)?));
+            }
+        }
+        flush(&mut current);
+        std::mem::drop(scan);
+
+        let removed = removable.len() as u64;
+        for (k, record) in removable {
+            let key = match Key::decode(&k)? {
+                Key::Record(key, _) => key.into_owned(),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            let record = match record {
+                Record::Expiring { record, .. } => *record,
+                record => record,
+            };
+            if let Record::Chunked { version, chunks, .. } = record {
+                for i in 0..chunks {
+                    session.delete(&Key::Chunk((&key).into(), version, i).encode())?;
+                }
+            }
+            session.delete(&k)?;
+        }
+        if let Some(next) = next {
+            session.set(&Key::GcCursor.encode(), next)?;
+            return Ok((removed, false));
+        }
+        session.delete(&Key::GcCursor.encode())?;
+
+        // Commit entries of transactions below the GC version are removed too, in commit order
+        // up to the first later transaction. Any others are removed by subsequent passes.
+        let mut commits = Vec::new();
+        let mut scan =
+            session.scan(Range::from(Key::Commit(0).encode()..Key::Commit(u64::MAX).encode()));
+        while let Some((k, v)) = scan.next().transpose()? {
+            let (txn, _): (u64, Vec<Vec<u8>>) = deserialize(&v)?;
+            if txn >= version {
+                break;
+            }
+            commits.push(k);
+        }
+        std::mem::drop(scan);
+        for k in commits {
+            session.delete(&k)?;
+        }
+
+        let mut snapshots = Vec::new();
+        let mut scan = session
+            .scan(Range::from(Key::TxnSnapshot(0).encode()..Key::TxnSnapshot(version).encode()));
+        while let Some((k, _)) = scan.next().transpose()? {
+            snapshots.push(k);
+        }
+        std::mem::drop(scan);
+        for k in snapshots {
+            session.delete(&k)?;
+        }
+        Ok((removed, true))
+    }
+
+    /// Returns the version below which old versions have been garbage collected, or 0 if none.
+    fn gc_version_in(store: &dyn Store) -> Result<u64> {
+        match store.get(&Key::GcVersion.encode())? {
+            Some(v) => deserialize(&v),
+            None => Ok(0),
+        }
+    }
+
+    /// Exports a consistent point-in-time snapshot of all committed data as a portable stream,
+    /// which can be imported into an empty store with `import()`, e.g. for backups or to seed new
+    /// nodes. The snapshot contains the latest committed value of each live key (without history)
+    /// along with all metadata, as seen by a transaction beginning now. It does not begin a
+    /// transaction, and thus doesn't modify the store. Returns the snapshot version, i.e. the next
+    /// transaction ID.
+    pub fn export(&self, mut writer: impl std::io::Write) -> Result<u64> {
+        let session = self.store.read()?;
+        let store: &dyn Store = &**session;
+        let version = match store.get(&Key::TxnNext.encode())? {
+            Some(ref v) => deserialize(v)?,
+            None => 1,
+        };
+        let snapshot = Snapshot::current(store, version)?;
+
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        bincode::serialize_into(
+            &mut writer,
+            &SnapshotEntry::Header { format: SNAPSHOT_FORMAT, version },
+        )?;
+
+        let mut scan = store.scan(Range::from(
+            Key::Metadata(vec![].into()).encode()..Key::Chunk(vec![].into(), 0, 0).encode(),
+        ));
+        while let Some((k, value)) = scan.next().transpose()? {
+            let key = match Key::decode(&k)? {
+                Key::Metadata(key) => key.into_owned(),
+                k => return Err(Error::Internal(format!("Expected Metadata, got {:?}", k))),
+            };
+            bincode::serialize_into(&mut writer, &SnapshotEntry::Metadata { key, value })?;
+        }
+        std::mem::drop(scan);
+
+        // Record keys are ordered by key then version, so we track the latest visible version of
+        // the current key and export it once we've seen all of its versions.
+        let mut records = 0;
+        let mut latest: Option<(Vec<u8>, Vec<u8>)> = None;
+        let mut scan = store.scan(Range::from(Key::Record(vec![].into(), 0).encode()..));
+        while let Some((k, v)) = scan.next().transpose()? {
+            let (key, record_version) = match Key::decode(&k)? {
+                Key::Record(key, record_version) => (key.into_owned(), record_version),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            if !snapshot.is_visible(record_version) {
+                continue;
+            }
+            if let Some((latest_key, bytes)) = latest.take() {
+                if latest_key != key {
+                    records +=
+                        Self::export_record(store, &mut writer, latest_key, &bytes, version)?;
+                }
+            }
+            latest = Some((key, v));
+        }
+        if let Some((key, bytes)) = latest {
+            records += Self::export_record(store, &mut writer, key, &bytes, version)?;
+        }
+        bincode::serialize_into(&mut writer, &SnapshotEntry::End { records })?;
+        writer.flush()?;
+        Ok(version)
+    }
+
+    /// Exports a record as seen at the given version, returning 1 if it was exported or 0 if it
+    /// was deleted or expired.
+    fn export_record(
+        store: &dyn Store,
+        writer: impl std::io::Write,
+        key: Vec<u8>,
+        bytes: &[u8],
+        version: u64,
+    ) -> Result<u64> {
+        let record: Record = deserialize(bytes)?;
+        let expires = record.expires();
+        match record.resolve(&key, version, store)? {
+            Some(value) => {
+                bincode::serialize_into(writer, &SnapshotEntry::Record { key, value, expires })?;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Imports a snapshot stream exported by `export()` into an empty store, returning the
+    /// snapshot version. Keys are written as committed by the transaction preceding the snapshot
+    /// version, and the next transaction ID is set to the snapshot version, such that expiry
+    /// versions are preserved. If the import fails, the store may contain partial data and
+    /// should be discarded.
+    pub fn import(&self, mut reader: impl std::io::Read) -> Result<u64> {
+        let mut session = self.store.write()?;
+        if session.scan(Range::from(..)).next().is_some() {
+            return Err(Error::Value("Can't import snapshot into a non-empty store".into()));
+        }
+
+        let mut magic = [0; SNAPSHOT_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(Error::Value("Invalid snapshot".into()));
+        }
+        let version = match bincode::deserialize_from(&mut reader)? {
+            SnapshotEntry::Header { format, version } if format == SNAPSHOT_FORMAT => version,
+            SnapshotEntry::Header { format, .. } => {
+                return Err(Error::Value(format!("Unsupported snapshot format {}", format)))
+            }
+            entry => {
+                return Err(Error::Value(format!("Expected snapshot header, got {:?}", entry)))
+            }
+        };
+        let record_version = version - 1;
+
+        let mut records = 0;
+        loop {
+            match bincode::deserialize_from(&mut reader)? {
+                SnapshotEntry::Metadata { key, value } => {
+                    session.set(&Key::Metadata(key.into()).encode(), value)?
+                }
+                SnapshotEntry::Record { key, value, expires } => {
+                    let record = if value.len() > CHUNK_SIZE {
+                        for (i, chunk) in value.chunks(CHUNK_SIZE).enumerate() {
+                            session.set(
+                                &Key::Chunk((&key).into(), record_version, i as u64).encode(),
+                                chunk.to_vec(),
+                            )?;
+                        }
+                        Record::Chunked {
+                            version: record_version,
+                            chunks: value.len().div_ceil(CHUNK_SIZE) as u64,
+                            size: value.len() as u64,
+                        }
+                    } else {
+                        Record::Value(value)
+                    };
+                    let record = match expires {
+                        Some(expires) => Record::Expiring { expires, record: Box::new(record) },
+                        None => record,
+                    };
+                    session.set(
+                        &Key::Record(key.into(), record_version).encode(),
+                        serialize(&record)?,
+                    )?;
+                    records += 1;
+                }
+                SnapshotEntry::End { records: expected } if expected == records => break,
+                SnapshotEntry::End { records: expected } => {
+                    return Err(Error::Value(format!(
+                        "Expected {} snapshot records, got {}",
+                        expected, records
+                    )))
+                }
+                SnapshotEntry::Header { .. } => {
+                    return Err(Error::Value("Unexpected snapshot header".into()))
+                }
+            }
+        }
+        session.set(&Key::TxnNext.encode(), serialize(&version)?)?;
+        session.flush()?;
+        Ok(version)
+    }
+
+    /// Dumps the raw contents of the underlying store to a stream, which can be loaded with
+    /// `load()`. Unlike `export()`, this captures the exact MVCC state including all versions,
+    /// active transactions, and uncommitted writes, as needed e.g. for Raft state machine
+    /// snapshots where later log entries may refer to this state.
+    pub fn dump(&self, mut writer: impl std::io::Write) -> Result<()> {
+        let session = self.store.read()?;
+        let mut scan = session.scan(Range::from(..));
+        while let Some((key, value)) = scan.next().transpose()? {
+            bincode::serialize_into(&mut writer, &Some((key, value)))?;
+        }
+        bincode::serialize_into(&mut writer, &None::<(Vec<u8>, Vec<u8>)>)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replaces the contents of the underlying store with a dump from `dump()`.
+    pub fn load(&self, mut reader: impl std::io::Read) -> Result<()> {
+        let mut session = self.store.write()?;
+        let keys =
+            session.scan(Range::from(..)).map(|r| r.map(|(k, _)| k)).collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            session.delete(&key)?;
+        }
+        while let Some((key, value)) =
+            bincode::deserialize_from::<_, Option<(Vec<u8>, Vec<u8>)>>(&mut reader)?
+        {
+            session.set(&key, value)?;
+        }
+        session.flush()
+    }
+
     /// Returns engine status
     //
     // Bizarrely, the return statement is in fact necessary - see:
@@ -80,7 +596,14 @@ impl MVCC {
                     Key::TxnActive(0).encode()..Key::TxnActive(std::u64::MAX).encode(),
                 ))
                 .try_fold(0, |count, r| r.map(|_| count + 1))?,
+            txn_updates: store
+                .scan(Range::from(
+                    Key::TxnUpdate(0, vec![].into()).encode()
+                        ..Key::TxnUpdate(u64::MAX, vec![].into()).encode(),
+                ))
+                .try_fold(0, |count, r| r.map(|_| count + 1))?,
             storage: store.to_string(),
+            stats: Stats::compute(&**store, ..)?,
         });
     }
 }
@@ -103,21 +626,39 @@ pub struct Transaction {
     id: u64,
     /// The transaction mode.
     mode: Mode,
+    /// The transaction priority.
+    priority: Priority,
     /// The snapshot that the transaction is running in.
     snapshot: Snapshot,
+    /// The data version when the transaction began.
+    data_version: u64,
 }
 
 impl Transaction {
-    /// Begins a new transaction in the given mode.
-    fn begin(store: Arc<RwLock<Box<dyn Store>>>, mode: Mode) -> Result<Self> {
+    /// Begins a new transaction in the given mode and priority.
+    fn begin(store: Arc<RwLock<Box<dyn Store>>>, mode: Mode, priority: Priority) -> Result<Self> {
         let mut session = store.write()?;
 
         let id = match session.get(&Key::TxnNext.encode())? {
             Some(ref v) => deserialize(v)?,
             None => 1,
         };
+        if let Mode::Snapshot { version } = mode {
+            let gc_version = MVCC::gc_version_in(&**session)?;
+            if version < gc_version {
+                return Err(Error::Value(format!(
+                    "Version {} is outside the retention window, the earliest available version is {}",
+                    version, gc_version
+                )));
+            }
+        }
         session.set(&Key::TxnNext.encode(), serialize(&(id + 1))?)?;
-        session.set(&Key::TxnActive(id).encode(), serialize(&mode)?)?;
+        let data_version = match session.get(&Key::DataVersion.encode())? {
+            Some(ref v) => deserialize(v)?,
+            None => 0,
+        };
+        let state = TxnState { mode, priority, aborted: false, data_version };
+        session.set(&Key::TxnActive(id).encode(), serialize(&state)?)?;
 
         // We always take a new snapshot, even for snapshot transactions, because all transactions
         // increment the transaction ID and we need to properly record currently active transactions
@@ -128,22 +669,23 @@ impl Transaction {
             snapshot = Snapshot::restore(&store.read()?, *version)?
         }
 
-        Ok(Self { store, id, mode, snapshot })
+        Ok(Self { store, id, mode, priority, snapshot, data_version })
     }
 
     /// Resumes an active transaction with the given ID. Errors if the transaction is not active.
     fn resume(store: Arc<RwLock<Box<dyn Store>>>, id: u64) -> Result<Self> {
         let session = store.read()?;
-        let mode = match session.get(&Key::TxnActive(id).encode())? {
-            Some(v) => deserialize(&v)?,
-            None => return Err(Error::Value(format!("No active transaction {}", id))),
-        };
+        let TxnState { mode, priority, data_version, .. } =
+            match session.get(&Key::TxnActive(id).encode())? {
+                Some(v) => deserialize(&v)?,
+                None => return Err(Error::Value(format!("No active transaction {}", id))),
+            };
         let snapshot = match &mode {
             Mode::Snapshot { version } => Snapshot::restore(&session, *version)?,
             _ => Snapshot::restore(&session, id)?,
         };
         std::mem::drop(session);
-        Ok(Self { store, id, mode, snapshot })
+        Ok(Self { store, id, mode, priority, snapshot, data_version })
     }
 
     /// Returns the transaction ID.
@@ -156,40 +698,108 @@ impl Transaction {
         self.mode
     }
 
-    /// Commits the transaction, by removing the txn from the active set.
+    /// Returns the transaction priority.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns the IDs of the transactions that were active when the transaction's snapshot was
+    /// taken, and whose writes are thus invisible to it.
+    pub fn concurrent(&self) -> &HashSet<u64> {
+        &self.snapshot.invisible
+    }
+
+    /// Returns the data version when the transaction began, i.e. the number of read-write
+    /// transactions that had committed. Read-only transactions with the same data version see the
+    /// same data, since no writes were committed between them. This does not hold for snapshot
+    /// transactions, nor for keys that expire between them.
+    pub fn data_version(&self) -> u64 {
+        self.data_version
+    }
+
+    /// Commits the transaction, by removing the txn from the active set. If the transaction was
+    /// aborted by a higher-priority transaction, it is removed from the active set as if it had
+    /// been rolled back, and a serialization error is returned. Otherwise, read-write transactions
+    /// increment the data version.
     pub fn commit(self) -> Result<()> {
         let mut session = self.store.write()?;
+        let aborted = Self::is_aborted(&**session, self.id)?;
         session.delete(&Key::TxnActive(self.id).encode())?;
-        session.flush()
+        if self.mode.mutable() && !aborted {
+            // The update markers are only needed for rollbacks, so they're removed in the same
+            // write batch. The updated keys are recorded in commit order for changes().
+            let mut keys = Vec::new();
+            for key in Self::remove_updates(&mut **session, self.id)? {
+                if let Key::Record(key, _) = Key::decode(&key)? {
+                    keys.push(key.into_owned());
+                }
+            }
+            let version: u64 = match session.get(&Key::DataVersion.encode())? {
+                Some(ref v) => deserialize(v)?,
+                None => 0,
+            };
+            session.set(&Key::DataVersion.encode(), serialize(&(version + 1))?)?;
+            if !keys.is_empty() {
+                session.set(&Key::Commit(version + 1).encode(), serialize(&(self.id, keys))?)?;
+            }
+        }
+        session.flush()?;
+        match aborted {
+            true => Err(Error::Serialization),
+            false => Ok(()),
+        }
     }
 
     /// Rolls back the transaction, by removing all updated entries.
     pub fn rollback(self) -> Result<()> {
         let mut session = self.store.write()?;
         if self.mode.mutable() {
-            let mut rollback = Vec::new();
-            let mut scan = session.scan(Range::from(
-                Key::TxnUpdate(self.id, vec![].into()).encode()
-                    ..Key::TxnUpdate(self.id + 1, vec![].into()).encode(),
-            ));
-            while let Some((key, _)) = scan.next().transpose()? {
-                match Key::decode(&key)? {
-                    Key::TxnUpdate(_, updated_key) => rollback.push(updated_key.into_owned()),
-                    k => return Err(Error::Internal(format!("Expected TxnUpdate, got {:?}", k))),
-                };
-                rollback.push(key);
-            }
-            std::mem::drop(scan);
-            for key in rollback.into_iter() {
-                session.delete(&key)?;
-            }
+            Self::undo(&mut **session, self.id)?;
         }
         session.delete(&Key::TxnActive(self.id).encode())
     }
 
+    /// Removes all entries updated by the given transaction.
+    fn undo(store: &mut dyn Store, id: u64) -> Result<()> {
+        for key in Self::remove_updates(store, id)? {
+            store.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the update markers of the given transaction, returning the updated keys.
+    fn remove_updates(store: &mut dyn Store, id: u64) -> Result<Vec<Vec<u8>>> {
+        let mut updates = Vec::new();
+        let mut updated = Vec::new();
+        let mut scan = store.scan(Range::from(
+            Key::TxnUpdate(id, vec![].into()).encode()
+                ..Key::TxnUpdate(id + 1, vec![].into()).encode(),
+        ));
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnUpdate(_, updated_key) => updated.push(updated_key.into_owned()),
+                k => return Err(Error::Internal(format!("Expected TxnUpdate, got {:?}", k))),
+            };
+            updates.push(key);
+        }
+        std::mem::drop(scan);
+        for key in updates {
+            store.delete(&key)?;
+        }
+        Ok(updated)
+    }
+
+    /// Checks whether the given transaction was aborted by a higher-priority transaction.
+    fn is_aborted(store: &dyn Store, id: u64) -> Result<bool> {
+        match store.get(&Key::TxnActive(id).encode())? {
+            Some(v) => Ok(deserialize::<TxnState>(&v)?.aborted),
+            None => Ok(false),
+        }
+    }
+
     /// Deletes a key.
     pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.write(key, None)
+        self.write(key, None, None)
     }
 
     /// Fetches a key.
@@ -204,7 +814,7 @@ impl Transaction {
             match Key::decode(&k)? {
                 Key::Record(_, version) => {
                     if self.snapshot.is_visible(version) {
-                        return deserialize(&v);
+                        return Record::load(&v, key, self.snapshot.version, &**session);
                     }
                 }
                 k => return Err(Error::Internal(format!("Expected Txn::Record, got {:?}", k))),
@@ -213,57 +823,195 @@ impl Transaction {
         Ok(None)
     }
 
+    /// Fetches all committed versions of a key visible to the transaction, as (version, value)
+    /// pairs ordered by version. Deleted versions have a None value. The transaction's own
+    /// uncommitted writes are not included.
+    pub fn get_versions(&self, key: &[u8]) -> Result<Vec<(u64, Option<Vec<u8>>)>> {
+        let session = self.store.read()?;
+        let mut scan = session.scan(Range::from(
+            Key::Record(key.into(), 0).encode()..=Key::Record(key.into(), self.id).encode(),
+        ));
+        let mut versions = Vec::new();
+        while let Some((k, v)) = scan.next().transpose()? {
+            match Key::decode(&k)? {
+                Key::Record(_, version) => {
+                    if self.snapshot.is_visible(version) && version != self.id {
+                        versions.push((version, Record::load(&v, key, version, &**session)?));
+                    }
+                }
+                k => return Err(Error::Internal(format!("Expected Txn::Record, got {:?}", k))),
+            };
+        }
+        Ok(versions)
+    }
+
+    /// Fetches the versions that created and last updated the key's value visible to the
+    /// transaction, if any, including the transaction's own writes. The created version is the
+    /// first write since the key was last deleted or expired, or the oldest retained version if
+    /// older versions have been garbage collected.
+    pub fn get_lifetime(&self, key: &[u8]) -> Result<Option<(u64, u64)>> {
+        let session = self.store.read()?;
+        let mut scan = session.scan(Range::from(
+            Key::Record(key.into(), 0).encode()..=Key::Record(key.into(), self.id).encode(),
+        ));
+        let mut lifetime = None;
+        let mut last: Option<Record> = None;
+        while let Some((k, v)) = scan.next().transpose()? {
+            match Key::decode(&k)? {
+                Key::Record(_, version) => {
+                    if !self.snapshot.is_visible(version) {
+                        continue;
+                    }
+                    if matches!(&last, Some(r) if r.size(version).is_none()) {
+                        lifetime = None;
+                    }
+                    let record = deserialize::<Record>(&v)?;
+                    lifetime = match (lifetime, record.size(version)) {
+                        (_, None) => None,
+                        (Some((created, _)), Some(_)) => Some((created, version)),
+                        (None, Some(_)) => Some((version, version)),
+                    };
+                    last = Some(record);
+                }
+                k => return Err(Error::Internal(format!("Expected Txn::Record, got {:?}", k))),
+            };
+        }
+        match last {
+            Some(record) if record.size(self.snapshot.version).is_some() => Ok(lifetime),
+            _ => Ok(None),
+        }
+    }
+
     /// Scans a key range.
     pub fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Result<super::Scan> {
+        let scan = self.store.read()?.scan(Key::record_range(range));
+        Ok(Box::new(Scan::new(scan, self.snapshot.clone(), self.store.clone())))
+    }
+
+    /// Scans keys under a given prefix. The scan can be iterated in either direction, e.g. in
+    /// reverse to find the last key under the prefix.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<super::Scan> {
+        self.scan(prefix_range(prefix)?)
+    }
+
+    /// Scans keys under a given prefix within a key range, e.g. keys under the prefix after a
+    /// given key with `(Bound::Excluded(key), Bound::Unbounded)` to continue a previous scan, or
+    /// keys before it to continue a reverse scan.
+    pub fn scan_prefix_range(
+        &self,
+        prefix: &[u8],
+        range: impl RangeBounds<Vec<u8>>,
+    ) -> Result<super::Scan> {
+        // Use the tighter of the prefix and range bounds at either end.
+        let (start, end) = prefix_range(prefix)?;
         let start = match range.start_bound() {
-            Bound::Excluded(k) => Bound::Excluded(Key::Record(k.into(), std::u64::MAX).encode()),
-            Bound::Included(k) => Bound::Included(Key::Record(k.into(), 0).encode()),
-            Bound::Unbounded => Bound::Included(Key::Record(vec![].into(), 0).encode()),
+            Bound::Included(k) | Bound::Excluded(k) if k.as_slice() >= prefix => {
+                range.start_bound().cloned()
+            }
+            _ => start,
         };
-        let end = match range.end_bound() {
-            Bound::Excluded(k) => Bound::Excluded(Key::Record(k.into(), 0).encode()),
-            Bound::Included(k) => Bound::Included(Key::Record(k.into(), std::u64::MAX).encode()),
-            Bound::Unbounded => Bound::Unbounded,
+        let end = match (end, range.end_bound()) {
+            (Bound::Excluded(end), Bound::Included(k) | Bound::Excluded(k)) if *k >= end => {
+                Bound::Excluded(end)
+            }
+            (end, Bound::Unbounded) => end,
+            (_, bound) => bound.cloned(),
+        };
+        // The range may end before the prefix starts or vice versa, which some stores reject.
+        let empty = match (&start, &end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start >= end,
+            _ => false,
         };
-        let scan = self.store.read()?.scan(Range::from((start, end)));
-        Ok(Box::new(Scan::new(scan, self.snapshot.clone())))
+        if empty {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        self.scan((start, end))
     }
 
-    /// Scans keys under a given prefix.
-    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<super::Scan> {
-        if prefix.is_empty() {
-            return Err(Error::Internal("Scan prefix cannot be empty".into()));
+    /// Fetches committed changes to keys in the given range, in commit order and then by key,
+    /// from the given commit position up to the returned resolved position (exclusive). Each
+    /// read-write transaction that commits takes the next position, i.e. the new data version.
+    /// Changes below the resolved position are final, so callers can fetch subsequent changes by
+    /// passing it as the next position. Only the commits since the given position are read, and
+    /// commits of transactions garbage collected by `MVCC::gc()` are no longer returned.
+    pub fn changes(
+        &self,
+        range: impl RangeBounds<Vec<u8>>,
+        from: u64,
+    ) -> Result<(Vec<Change>, u64)> {
+        let session = self.store.read()?;
+        let mut resolved = match session.get(&Key::DataVersion.encode())? {
+            Some(ref v) => deserialize::<u64>(v)? + 1,
+            None => 1,
         }
-        let start = prefix.to_vec();
-        let mut end = start.clone();
-        for i in (0..end.len()).rev() {
-            match end[i] {
-                // If all 0xff we could in principle use Range::Unbounded, but it won't happen
-                0xff if i == 0 => return Err(Error::Internal("Invalid prefix scan range".into())),
-                0xff => {
-                    end[i] = 0x00;
-                    continue;
-                }
-                v => {
-                    end[i] = v + 1;
-                    break;
+        .max(from);
+        let mut scan =
+            session.scan(Range::from(Key::Commit(from).encode()..Key::Commit(resolved).encode()));
+        let mut changes = Vec::new();
+        while let Some((k, v)) = scan.next().transpose()? {
+            let commit = match Key::decode(&k)? {
+                Key::Commit(commit) => commit,
+                k => return Err(Error::Internal(format!("Expected Commit, got {:?}", k))),
+            };
+            let (version, keys): (u64, Vec<Vec<u8>>) = deserialize(&v)?;
+            // Later commits may be visible while this one isn't, but must be returned after it.
+            if !self.snapshot.is_visible(version) {
+                resolved = commit;
+                break;
+            }
+            for key in keys.into_iter().filter(|k| range.contains(k)) {
+                // The version may have been garbage collected or purged since.
+                if let Some(v) = session.get(&Key::Record((&key).into(), version).encode())? {
+                    let value = Record::load(&v, &key, version, &**session)?;
+                    changes.push(Change { commit, version, key, value });
                 }
             }
         }
-        self.scan(start..end)
+        Ok((changes, resolved))
+    }
+
+    /// Computes storage statistics for keys under a given prefix, see `MVCC::stats()`.
+    pub fn stats_prefix(&self, prefix: &[u8]) -> Result<Stats> {
+        Stats::compute(&**self.store.read()?, prefix_range(prefix)?)
+    }
+
+    /// Fetches committed changes to keys under a given prefix, see changes().
+    pub fn changes_prefix(&self, prefix: &[u8], from: u64) -> Result<(Vec<Change>, u64)> {
+        self.changes(prefix_range(prefix)?, from)
     }
 
     /// Sets a key.
     pub fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.write(key, Some(value))
+        self.write(key, Some(value), None)
+    }
+
+    /// Sets a key which expires at the given version, i.e. it is invisible to transactions with
+    /// this or a later version (transaction ID), as if it had been deleted. Expired keys are
+    /// removed by `MVCC::purge_expired()`.
+    pub fn set_with_expiry(&mut self, key: &[u8], value: Vec<u8>, expires: u64) -> Result<()> {
+        if expires <= self.id {
+            return Err(Error::Value(format!(
+                "Expiry version {} must be after transaction version {}",
+                expires, self.id
+            )));
+        }
+        self.write(key, Some(value), Some(expires))
     }
 
-    /// Writes a value for a key. None is used for deletion.
-    fn write(&self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+    /// Writes a value for a key, optionally expiring at the given version. None is used for
+    /// deletion.
+    fn write(&self, key: &[u8], value: Option<Vec<u8>>, expires: Option<u64>) -> Result<()> {
         if !self.mode.mutable() {
             return Err(Error::ReadOnly);
         }
         let mut session = self.store.write()?;
+        if Self::is_aborted(&**session, self.id)? {
+            return Err(Error::Serialization);
+        }
 
         // Check if the key is dirty, i.e. if it has any uncommitted changes, by scanning for any
         // versions that aren't visible to us.
@@ -274,11 +1022,12 @@ impl Transaction {
                     ..=Key::Record(key.into(), std::u64::MAX).encode(),
             ))
             .rev();
+        let mut conflicts = Vec::new();
         while let Some((k, _)) = scan.next().transpose()? {
             match Key::decode(&k)? {
                 Key::Record(_, version) => {
                     if !self.snapshot.is_visible(version) {
-                        return Err(Error::Serialization);
+                        conflicts.push(version);
                     }
                 }
                 k => return Err(Error::Internal(format!("Expected Txn::Record, got {:?}", k))),
@@ -286,15 +1035,244 @@ impl Transaction {
         }
         std::mem::drop(scan);
 
-        // Write the key and its update record.
-        let key = Key::Record(key.into(), self.id).encode();
-        let update = Key::TxnUpdate(self.id, (&key).into()).encode();
-        session.set(&update, vec![])?;
-        session.set(&key, serialize(&value)?)
-    }
-}
+        // Conflicting writes by active lower-priority transactions are resolved by aborting them,
+        // rolling back their writes. Any other conflict is a serialization failure for us.
+        let mut aborts = Vec::new();
+        for version in conflicts {
+            match session.get(&Key::TxnActive(version).encode())? {
+                Some(v) => match deserialize::<TxnState>(&v)? {
+                    state if state.priority < self.priority => aborts.push((version, state)),
+                    _ => return Err(Error::Serialization),
+                },
+                None => return Err(Error::Serialization),
+            }
+        }
+        for (version, state) in aborts {
+            Self::undo(&mut **session, version)?;
+            let state = TxnState { aborted: true, ..state };
+            session.set(&Key::TxnActive(version).encode(), serialize(&state)?)?;
+        }
 
-/// An MVCC transaction mode.
+        // Remove any chunks of a previous write of the key in this transaction.
+        let mut scan = session.scan(Range::from(
+            Key::Chunk(key.into(), self.id, 0).encode()
+                ..=Key::Chunk(key.into(), self.id, std::u64::MAX).encode(),
+        ));
+        let mut chunks = Vec::new();
+        while let Some((k, _)) = scan.next().transpose()? {
+            chunks.push(k);
+        }
+        std::mem::drop(scan);
+        for chunk in chunks {
+            session.delete(&chunk)?;
+        }
+
+        // Write the key and its update record, splitting large values into chunks.
+        let record = match value {
+            Some(value) if value.len() > CHUNK_SIZE => {
+                for (i, chunk) in value.chunks(CHUNK_SIZE).enumerate() {
+                    let chunk_key = Key::Chunk(key.into(), self.id, i as u64).encode();
+                    session.set(&Key::TxnUpdate(self.id, (&chunk_key).into()).encode(), vec![])?;
+                    session.set(&chunk_key, chunk.to_vec())?;
+                }
+                Record::Chunked {
+                    version: self.id,
+                    chunks: value.len().div_ceil(CHUNK_SIZE) as u64,
+                    size: value.len() as u64,
+                }
+            }
+            Some(value) => Record::Value(value),
+            None => Record::Deleted,
+        };
+        let record = match expires {
+            Some(expires) => Record::Expiring { expires, record: Box::new(record) },
+            None => record,
+        };
+        let key = Key::Record(key.into(), self.id).encode();
+        let update = Key::TxnUpdate(self.id, (&key).into()).encode();
+        session.set(&update, vec![])?;
+        session.set(&key, serialize(&record)?)
+    }
+}
+
+/// A key range, as start and end bounds.
+type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Returns the key range covering all keys under the given prefix. The end is the prefix with
+/// any trailing 0xff bytes removed and the last byte incremented, or unbounded if the prefix only
+/// consists of 0xff bytes.
+fn prefix_range(prefix: &[u8]) -> Result<KeyRange> {
+    if prefix.is_empty() {
+        return Err(Error::Internal("Scan prefix cannot be empty".into()));
+    }
+    let start = Bound::Included(prefix.to_vec());
+    let end = match prefix.iter().rposition(|b| *b != 0xff) {
+        Some(i) => {
+            let mut end = prefix[..=i].to_vec();
+            end[i] += 1;
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    };
+    Ok((start, end))
+}
+
+/// A committed change to a key, as returned by `Transaction::changes()`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The commit position of the transaction that made the change.
+    pub commit: u64,
+    /// The version (i.e. transaction ID) that made the change.
+    pub version: u64,
+    /// The changed key.
+    pub key: Vec<u8>,
+    /// The new value, or None if the key was deleted. Writes with an expiry are returned with
+    /// their value, and neither expiry nor `MVCC::purge_expired()` emit changes, since they don't
+    /// write new versions. Purged versions are no longer returned at all.
+    pub value: Option<Vec<u8>>,
+}
+
+/// A subscription to committed changes in a key range, as returned by `MVCC::watch()`.
+pub struct Watch {
+    /// The MVCC store to watch.
+    mvcc: MVCC,
+    /// The key range to watch.
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    /// The next commit position to fetch changes from.
+    from: u64,
+}
+
+impl Watch {
+    /// Returns the commit position that the next poll will fetch changes from. Consumers can
+    /// persist this to resume the subscription later.
+    pub fn from(&self) -> u64 {
+        self.from
+    }
+
+    /// Polls for new committed changes since the last poll, in commit order.
+    pub fn poll(&mut self) -> Result<Vec<Change>> {
+        let txn = self.mvcc.begin_with_mode(Mode::ReadOnly)?;
+        let (changes, resolved) = txn.changes(self.range.clone(), self.from)?;
+        txn.commit()?;
+        self.from = resolved;
+        Ok(changes)
+    }
+}
+
+/// An entry in an exported snapshot stream, see `MVCC::export()`. The stream consists of
+/// `SNAPSHOT_MAGIC`, a header, any number of metadata and record entries, and an end marker.
+#[derive(Debug, Serialize, Deserialize)]
+enum SnapshotEntry {
+    /// The stream header, with the format and snapshot version.
+    Header { format: u32, version: u64 },
+    /// An unversioned metadata key/value pair.
+    Metadata { key: Vec<u8>, value: Vec<u8> },
+    /// A live key with its value and expiry version, if any.
+    Record { key: Vec<u8>, value: Vec<u8>, expires: Option<u64> },
+    /// The end of the stream, with the number of records as a consistency check.
+    End { records: u64 },
+}
+
+/// A versioned MVCC record value.
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    /// A deletion tombstone.
+    Deleted,
+    /// A value stored inline in the record.
+    Value(Vec<u8>),
+    /// A large value split into chunks, stored under `Key::Chunk(key, version, 0..chunks)`.
+    Chunked { version: u64, chunks: u64, size: u64 },
+    /// A record which expires at the given version.
+    Expiring { expires: u64, record: Box<Record> },
+}
+
+impl Record {
+    /// Deserializes a record value for the given key as seen by a reader at the given version,
+    /// reassembling chunked values from the store. Returns None for deleted or expired records.
+    fn load(bytes: &[u8], key: &[u8], version: u64, store: &dyn Store) -> Result<Option<Vec<u8>>> {
+        deserialize::<Record>(bytes)?.resolve(key, version, store)
+    }
+
+    /// Returns the logical size of the record's value as seen at the given version, or None if it
+    /// is deleted or expired.
+    fn size(&self, version: u64) -> Option<u64> {
+        match self {
+            Record::Deleted => None,
+            Record::Value(value) => Some(value.len() as u64),
+            Record::Chunked { size, .. } => Some(*size),
+            Record::Expiring { expires, .. } if version >= *expires => None,
+            Record::Expiring { record, .. } => record.size(version),
+        }
+    }
+
+    /// Returns the expiry version of the record, if any.
+    fn expires(&self) -> Option<u64> {
+        match self {
+            Record::Expiring { expires, .. } => Some(*expires),
+            _ => None,
+        }
+    }
+
+    /// Resolves the record into its value, see load().
+    fn resolve(self, key: &[u8], version: u64, store: &dyn Store) -> Result<Option<Vec<u8>>> {
+        match self {
+            Record::Expiring { expires, .. } if version >= expires => Ok(None),
+            Record::Expiring { record, .. } => record.resolve(key, version, store),
+            Record::Deleted => Ok(None),
+            Record::Value(value) => Ok(Some(value)),
+            Record::Chunked { version, chunks, size } => {
+                let mut value = Vec::with_capacity(size as usize);
+                for i in 0..chunks {
+                    match store.get(&Key::Chunk(key.into(), version, i).encode())? {
+                        Some(chunk) => value.extend(chunk),
+                        None => {
+                            return Err(Error::Internal(format!(
+                                "Missing chunk {} of version {} for key {:?}",
+                                i, version, key
+                            )))
+                        }
+                    }
+                }
+                if value.len() as u64 != size {
+                    return Err(Error::Internal(format!(
+                        "Expected chunked value of size {}, got {}",
+                        size,
+                        value.len()
+                    )));
+                }
+                Ok(Some(value))
+            }
+        }
+    }
+}
+
+/// An MVCC transaction priority. When a read-write transaction's write conflicts with a write by
+/// an active lower-priority transaction, the lower-priority transaction is aborted: its writes are
+/// rolled back, and its next write or commit fails with a serialization error. Otherwise, the
+/// writer gets the serialization error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// The state of an active transaction, stored as `Key::TxnActive(id)`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct TxnState {
+    /// The transaction mode.
+    mode: Mode,
+    /// The transaction priority.
+    priority: Priority,
+    /// Whether the transaction was aborted by a higher-priority transaction, which rolled back
+    /// its writes. It can't write or commit, only roll back.
+    aborted: bool,
+    /// The data version when the transaction began.
+    data_version: u64,
+}
+
+/// An MVCC transaction mode.
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Mode {
     /// A read-write transaction.
@@ -343,17 +1321,23 @@ struct Snapshot {
 impl Snapshot {
     /// Takes a new snapshot, persisting it as `Key::TxnSnapshot(version)`.
     fn take(session: &mut RwLockWriteGuard<Box<dyn Store>>, version: u64) -> Result<Self> {
+        let snapshot = Self::current(&***session, version)?;
+        session.set(&Key::TxnSnapshot(version).encode(), serialize(&snapshot.invisible)?)?;
+        Ok(snapshot)
+    }
+
+    /// Builds a snapshot for the given version from the currently active transactions, without
+    /// persisting it.
+    fn current(store: &dyn Store, version: u64) -> Result<Self> {
         let mut snapshot = Self { version, invisible: HashSet::new() };
         let mut scan =
-            session.scan(Range::from(Key::TxnActive(0).encode()..Key::TxnActive(version).encode()));
+            store.scan(Range::from(Key::TxnActive(0).encode()..Key::TxnActive(version).encode()));
         while let Some((key, _)) = scan.next().transpose()? {
             match Key::decode(&key)? {
                 Key::TxnActive(id) => snapshot.invisible.insert(id),
                 k => return Err(Error::Internal(format!("Expected TxnActive, got {:?}", k))),
             };
         }
-        std::mem::drop(scan);
-        session.set(&Key::TxnSnapshot(version).encode(), serialize(&snapshot.invisible)?)?;
         Ok(snapshot)
     }
 
@@ -377,7 +1361,7 @@ impl Snapshot {
 enum Key<'a> {
     /// The next available txn ID. Used when starting new txns.
     TxnNext,
-    /// Active txn markers, containing the mode. Used to detect concurrent txns, and to resume.
+    /// Active txn markers, containing the txn state. Used to detect concurrent txns, and to resume.
     TxnActive(u64),
     /// Txn snapshot, containing concurrent active txns at start of txn.
     TxnSnapshot(u64),
@@ -387,9 +1371,35 @@ enum Key<'a> {
     Record(Cow<'a, [u8]>, u64),
     /// Arbitrary unversioned metadata.
     Metadata(Cow<'a, [u8]>),
+    /// A chunk of a large value for a key/version pair, with the chunk index.
+    Chunk(Cow<'a, [u8]>, u64, u64),
+    /// The version below which old versions have been garbage collected.
+    GcVersion,
+    /// The data version, i.e. the number of committed read-write transactions.
+    DataVersion,
+    /// The record key at which an incomplete garbage collection pass continues.
+    GcCursor,
+    /// The version and updated keys of the read-write transaction committed at a commit position,
+    /// i.e. data version. Used to fetch changes in commit order.
+    Commit(u64),
 }
 
 impl<'a> Key<'a> {
+    /// Returns the range of record keys for all versions of keys in the given key range.
+    fn record_range(range: impl RangeBounds<Vec<u8>>) -> Range {
+        let start = match range.start_bound() {
+            Bound::Excluded(k) => Bound::Excluded(Key::Record(k.into(), std::u64::MAX).encode()),
+            Bound::Included(k) => Bound::Included(Key::Record(k.into(), 0).encode()),
+            Bound::Unbounded => Bound::Included(Key::Record(vec![].into(), 0).encode()),
+        };
+        let end = match range.end_bound() {
+            Bound::Excluded(k) => Bound::Excluded(Key::Record(k.into(), 0).encode()),
+            Bound::Included(k) => Bound::Included(Key::Record(k.into(), std::u64::MAX).encode()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Range::from((start, end))
+    }
+
     /// Encodes a key into a byte vector.
     fn encode(self) -> Vec<u8> {
         use encoding::*;
@@ -401,6 +1411,14 @@ impl<'a> Key<'a> {
                 [&[0x04][..], &encode_u64(id), &encode_bytes(&key)].concat()
             }
             Self::Metadata(key) => [&[0x05][..], &encode_bytes(&key)].concat(),
+            Self::Chunk(key, version, index) => {
+                [&[0x06][..], &encode_bytes(&key), &encode_u64(version), &encode_u64(index)]
+                    .concat()
+            }
+            Self::GcVersion => vec![0x07],
+            Self::DataVersion => vec![0x08],
+            Self::GcCursor => vec![0x09],
+            Self::Commit(commit) => [&[0x0a][..], &encode_u64(commit)].concat(),
             Self::Record(key, version) => {
                 [&[0xff][..], &encode_bytes(&key), &encode_u64(version)].concat()
             }
@@ -417,6 +1435,11 @@ impl<'a> Key<'a> {
             0x03 => Self::TxnSnapshot(take_u64(bytes)?),
             0x04 => Self::TxnUpdate(take_u64(bytes)?, take_bytes(bytes)?.into()),
             0x05 => Self::Metadata(take_bytes(bytes)?.into()),
+            0x06 => Self::Chunk(take_bytes(bytes)?.into(), take_u64(bytes)?, take_u64(bytes)?),
+            0x07 => Self::GcVersion,
+            0x08 => Self::DataVersion,
+            0x09 => Self::GcCursor,
+            0x0a => Self::Commit(take_u64(bytes)?),
             0xff => Self::Record(take_bytes(bytes)?.into(), take_u64(bytes)?),
             b => return Err(Error::Internal(format!("Unknown MVCC key prefix {:x?}", b))),
         };
@@ -427,6 +1450,28 @@ impl<'a> Key<'a> {
     }
 }
 
+/// Decodes an MVCC key from arbitrary bytes, for the fuzz targets under fuzz/. Panics if the key
+/// does not encode back to the same bytes.
+#[cfg(feature = "fuzzing")]
+pub fn fuzz_decode_key(bytes: &[u8]) -> Result<()> {
+    let key = Key::decode(bytes)?;
+    let debug = format!("{:?}", key);
+    assert_eq!(bytes, &key.encode()[..], "Key {} did not roundtrip", debug);
+    Ok(())
+}
+
+/// Encodes an MVCC record key for a key/version pair, for the fuzz targets under fuzz/. Panics
+/// if the encoded key does not decode back to the same pair.
+#[cfg(feature = "fuzzing")]
+pub fn fuzz_encode_record_key(key: &[u8], version: u64) -> Vec<u8> {
+    let encoded = Key::Record(key.into(), version).encode();
+    match Key::decode(&encoded) {
+        Ok(Key::Record(k, v)) if *k == *key && v == version => {}
+        result => panic!("Record key {:x?}@{} decoded as {:?}", key, version, result),
+    }
+    encoded
+}
+
 /// A key range scan.
 pub struct Scan {
     /// The augmented KV store iterator, with key (decoded) and value. Note that we don't retain
@@ -434,16 +1479,21 @@ pub struct Scan {
     scan: Peekable<super::Scan>,
     /// Keeps track of next_back() seen key, whose previous versions should be ignored.
     next_back_seen: Option<Vec<u8>>,
+    /// The underlying store, used to fetch chunks of large values.
+    store: Arc<RwLock<Box<dyn Store>>>,
+    /// The version of the scanning transaction, used to hide expired records.
+    version: u64,
 }
 
 impl Scan {
     /// Creates a new scan.
-    fn new(mut scan: super::Scan, snapshot: Snapshot) -> Self {
+    fn new(mut scan: super::Scan, snapshot: Snapshot, store: Arc<RwLock<Box<dyn Store>>>) -> Self {
         // Augment the underlying scan to decode the key and filter invisible versions. We don't
         // return the version, since we don't need it, but beware that all versions of the key
         // will still be returned - we usually only need the last, which is what the next() and
         // next_back() methods need to handle. We also don't decode the value, since we only need
         // to decode the last version.
+        let version = snapshot.version;
         scan = Box::new(scan.filter_map(move |r| {
             r.and_then(|(k, v)| match Key::decode(&k)? {
                 Key::Record(_, version) if !snapshot.is_visible(version) => Ok(None),
@@ -452,7 +1502,7 @@ impl Scan {
             })
             .transpose()
         }));
-        Self { scan: scan.peekable(), next_back_seen: None }
+        Self { scan: scan.peekable(), next_back_seen: None, store, version }
     }
 
     // next() with error handling.
@@ -466,7 +1516,9 @@ impl Scan {
                 None => true,
             } {
                 // Only return non-deleted items.
-                if let Some(value) = deserialize(&value)? {
+                if let Some(value) =
+                    Record::load(&value, &key, self.version, &**self.store.read()?)?
+                {
                     return Ok(Some((key, value)));
                 }
             }
@@ -485,7 +1537,9 @@ impl Scan {
             } {
                 self.next_back_seen = Some(key.clone());
                 // Only return non-deleted items.
-                if let Some(value) = deserialize(&value)? {
+                if let Some(value) =
+                    Record::load(&value, &key, self.version, &**self.store.read()?)?
+                {
                     return Ok(Some((key, value)));
                 }
             }
@@ -615,6 +1669,73 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_data_version() -> Result<()> {
+        let mvcc = setup();
+        let t1 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(0, t1.data_version());
+
+        // Read-only transactions and rollbacks don't change the data version.
+        t1.commit()?;
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x01])?;
+        t2.rollback()?;
+        let t3 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(0, t3.data_version());
+
+        // Committing a read-write transaction increments it, even if it began before t3. The
+        // data version is kept when resuming.
+        let mut t4 = mvcc.begin()?;
+        t4.set(b"a", vec![0x04])?;
+        let t5 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        t4.commit()?;
+        assert_eq!(0, t5.data_version());
+        assert_eq!(0, mvcc.resume(t3.id())?.data_version());
+        let t6 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(1, t6.data_version());
+        assert_eq!(Some(vec![0x04]), t6.get(b"a")?);
+
+        // Aborted transactions don't increment it.
+        let mut t7 = mvcc.begin_with_priority(Mode::ReadWrite, Priority::Low)?;
+        t7.set(b"b", vec![0x07])?;
+        let mut t8 = mvcc.begin_with_priority(Mode::ReadWrite, Priority::High)?;
+        t8.set(b"b", vec![0x08])?;
+        assert_eq!(Err(Error::Serialization), t7.commit());
+        t8.commit()?;
+        assert_eq!(2, mvcc.begin_with_mode(Mode::ReadOnly)?.data_version());
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_updates() -> Result<()> {
+        // Update markers are only kept until a transaction commits or rolls back.
+        let mvcc = setup();
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.set(b"b", vec![0x01; CHUNK_SIZE + 1])?;
+        t1.delete(b"c")?;
+        assert_eq!(5, mvcc.status()?.txn_updates);
+        t1.commit()?;
+        assert_eq!(0, mvcc.status()?.txn_updates);
+
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        let mut t3 = mvcc.begin_with_priority(Mode::ReadWrite, Priority::Low)?;
+        t3.set(b"b", vec![0x03])?;
+        let mut t4 = mvcc.begin_with_priority(Mode::ReadWrite, Priority::High)?;
+        t4.set(b"b", vec![0x04])?;
+        assert_eq!(2, mvcc.status()?.txn_updates);
+        t2.rollback()?;
+        assert_eq!(Err(Error::Serialization), t3.commit());
+        t4.commit()?;
+        assert_eq!(0, mvcc.status()?.txn_updates);
+
+        let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        assert_eq!(Some(vec![0x04]), txn.get(b"b")?);
+        Ok(())
+    }
+
     #[test]
     fn test_resume() -> Result<()> {
         let mvcc = setup();
@@ -961,6 +2082,88 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_txn_scan_prefix_0xff() -> Result<()> {
+        let mvcc = setup();
+        let mut txn = mvcc.begin()?;
+        txn.set(&[0xfe], vec![0x01])?;
+        txn.set(&[0xfe, 0xff], vec![0x02])?;
+        txn.set(&[0xfe, 0xff, 0x00], vec![0x03])?;
+        txn.set(&[0xff], vec![0x04])?;
+        txn.set(&[0xff, 0xff], vec![0x05])?;
+        txn.set(&[0xff, 0xff, 0xff, 0x01], vec![0x06])?;
+        txn.commit()?;
+
+        // Prefixes ending with 0xff don't include the following keys, and prefixes that only
+        // consist of 0xff have an unbounded end.
+        let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        let scan = |prefix: &[u8]| -> Result<Vec<Vec<u8>>> {
+            txn.scan_prefix(prefix)?.map(|r| r.map(|(_, v)| v)).collect()
+        };
+        assert_eq!(vec![vec![0x02], vec![0x03]], scan(&[0xfe, 0xff])?);
+        assert_eq!(vec![vec![0x04], vec![0x05], vec![0x06]], scan(&[0xff])?);
+        assert_eq!(vec![vec![0x05], vec![0x06]], scan(&[0xff, 0xff])?);
+        assert_eq!(vec![vec![0x06]], scan(&[0xff, 0xff, 0xff])?);
+        assert_eq!(
+            vec![vec![0x06], vec![0x05], vec![0x04]],
+            txn.scan_prefix(&[0xff])?
+                .rev()
+                .map(|r| r.map(|(_, v)| v))
+                .collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_scan_prefix_range() -> Result<()> {
+        let mvcc = setup();
+        let mut txn = mvcc.begin()?;
+        for key in [b"a", b"b", b"c"].iter() {
+            for suffix in b"abc" {
+                txn.set(&[key[0], *suffix], vec![key[0], *suffix])?;
+            }
+            txn.set(&key[..], key.to_vec())?;
+        }
+        txn.set(&[0xff, 0x01], vec![0xff, 0x01])?;
+        txn.set(&[0xff, 0x02], vec![0xff, 0x02])?;
+        txn.commit()?;
+
+        let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        let scan = |prefix: &[u8], range: (Bound<Vec<u8>>, Bound<Vec<u8>>)| -> Result<Vec<_>> {
+            txn.scan_prefix_range(prefix, range)?.map(|r| r.map(|(k, _)| k)).collect()
+        };
+        use Bound::*;
+
+        // Open-ended scans after a key, e.g. to continue a scan, stay within the prefix.
+        assert_eq!(
+            vec![b"ba".to_vec(), b"bb".to_vec(), b"bc".to_vec()],
+            scan(b"b", (Excluded(b"b".to_vec()), Unbounded))?
+        );
+        assert_eq!(vec![b"bc".to_vec()], scan(b"b", (Excluded(b"bb".to_vec()), Unbounded))?);
+        assert_eq!(
+            vec![b"b".to_vec(), b"ba".to_vec()],
+            scan(b"b", (Excluded(b"a".to_vec()), Included(b"ba".to_vec())))?
+        );
+        assert_eq!(Vec::<Vec<u8>>::new(), scan(b"b", (Excluded(b"bc".to_vec()), Unbounded))?);
+        assert_eq!(Vec::<Vec<u8>>::new(), scan(b"b", (Included(b"c".to_vec()), Unbounded))?);
+        assert_eq!(Vec::<Vec<u8>>::new(), scan(b"b", (Unbounded, Excluded(b"b".to_vec())))?);
+        assert_eq!(vec![vec![0xff, 0x02]], scan(&[0xff], (Excluded(vec![0xff, 0x01]), Unbounded))?);
+
+        // Reverse scans before a key.
+        assert_eq!(
+            vec![b"bb".to_vec(), b"ba".to_vec(), b"b".to_vec()],
+            txn.scan_prefix_range(b"b", (Unbounded, Excluded(b"bc".to_vec())))?
+                .rev()
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            vec![b"b".to_vec(), b"ba".to_vec(), b"bb".to_vec(), b"bc".to_vec()],
+            scan(b"b", (Unbounded, Included(b"z".to_vec())))?
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_txn_set_conflict() -> Result<()> {
         let mvcc = setup();
@@ -1015,6 +2218,557 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_txn_chunked() -> Result<()> {
+        let mvcc = setup();
+        let large: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| i as u8).collect();
+        let larger: Vec<u8> = (0..CHUNK_SIZE * 4).map(|i| (i / 3) as u8).collect();
+
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x01])?;
+        txn.set(b"b", larger.clone())?;
+        txn.set(b"b", large.clone())?;
+        txn.set(b"c", vec![0x03])?;
+        txn.commit()?;
+
+        // Overwriting the value in a new transaction must retain the old chunks for snapshots.
+        let t1 = mvcc.begin()?;
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"b", larger.clone())?;
+        assert_eq!(Some(large.clone()), t1.get(b"b")?);
+        assert_eq!(Some(larger.clone()), t2.get(b"b")?);
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), vec![0x01]),
+                (b"b".to_vec(), larger.clone()),
+                (b"c".to_vec(), vec![0x03]),
+            ],
+            t2.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            vec![
+                (b"c".to_vec(), vec![0x03]),
+                (b"b".to_vec(), large.clone()),
+                (b"a".to_vec(), vec![0x01]),
+            ],
+            t1.scan(..)?.rev().collect::<Result<Vec<_>>>()?
+        );
+
+        // Rolling back must remove the chunks as well.
+        t2.rollback()?;
+        t1.commit()?;
+        let txn = mvcc.begin()?;
+        assert_eq!(Some(large), txn.get(b"b")?);
+        txn.commit()?;
+
+        let mut txn = mvcc.begin()?;
+        txn.delete(b"b")?;
+        assert_eq!(None, txn.get(b"b")?);
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_set_with_expiry() -> Result<()> {
+        let mvcc = setup();
+        let large: Vec<u8> = (0..CHUNK_SIZE * 2).map(|i| i as u8).collect();
+
+        let mut txn = mvcc.begin()?;
+        assert!(txn.set_with_expiry(b"a", vec![0x01], txn.id()).is_err());
+        txn.set_with_expiry(b"a", vec![0x01], 3)?;
+        txn.set_with_expiry(b"b", large.clone(), 3)?;
+        txn.set(b"c", vec![0x03])?;
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        txn.commit()?;
+
+        // Transaction 2 can see the keys, but transaction 3 can't.
+        let t2 = mvcc.begin()?;
+        let mut t3 = mvcc.begin()?;
+        assert_eq!(Some(vec![0x01]), t2.get(b"a")?);
+        assert_eq!(Some(large.clone()), t2.get(b"b")?);
+        assert_eq!(None, t3.get(b"a")?);
+        assert_eq!(None, t3.get(b"b")?);
+        assert_eq!(
+            vec![(b"c".to_vec(), vec![0x03])],
+            t3.scan(..)?.rev().collect::<Result<Vec<_>>>()?
+        );
+
+        // Expired keys can be written again.
+        t3.set(b"a", vec![0x02])?;
+        assert_eq!(Some(vec![0x02]), t3.get(b"a")?);
+        t2.commit()?;
+        t3.commit()?;
+
+        // Purging removes the expired key b including its chunks, but not a which has been
+        // rewritten. Snapshots before the purge horizon no longer see b.
+        assert_eq!(1, mvcc.purge_expired(4)?);
+        assert_eq!(0, mvcc.purge_expired(4)?);
+        let chunks = mvcc
+            .store
+            .read()?
+            .scan(Range::from(
+                Key::Chunk(b"b"[..].into(), 0, 0).encode()
+                    ..=Key::Chunk(b"b"[..].into(), std::u64::MAX, std::u64::MAX).encode(),
+            ))
+            .try_fold(0, |count, r| r.map(|_| count + 1))?;
+        assert_eq!(0, chunks);
+        let txn = mvcc.begin_with_mode(Mode::Snapshot { version: 2 })?;
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        assert_eq!(None, txn.get(b"b")?);
+        let txn = mvcc.begin()?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x02]), (b"c".to_vec(), vec![0x03])],
+            txn.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc() -> Result<()> {
+        let mvcc = setup();
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x01])?;
+        txn.set(b"b", vec![0x01])?;
+        txn.set(b"c", vec![0x01])?;
+        txn.commit()?;
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x02])?;
+        txn.delete(b"b")?;
+        txn.commit()?;
+
+        // t3 is active when t4 begins, and commits afterwards.
+        let mut t3 = mvcc.begin()?;
+        t3.set(b"c", vec![0x03])?;
+        mvcc.begin()?.commit()?;
+        t3.commit()?;
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x05])?;
+        txn.commit()?;
+
+        // GC at version 4 removes a@1 and the deleted key b, but keeps c@1 since c@3 is invisible
+        // at version 4.
+        assert_eq!((3, true), mvcc.gc(4, usize::MAX)?);
+        assert_eq!((0, true), mvcc.gc(4, usize::MAX)?);
+        let txn = mvcc.begin_with_mode(Mode::Snapshot { version: 4 })?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x02]), (b"c".to_vec(), vec![0x01])],
+            txn.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+        txn.commit()?;
+        let txn = mvcc.begin_with_mode(Mode::Snapshot { version: 5 })?;
+        assert_eq!(Some(vec![0x03]), txn.get(b"c")?);
+        txn.commit()?;
+        assert_eq!(
+            mvcc.begin_with_mode(Mode::Snapshot { version: 3 }).err(),
+            Some(Error::Value(
+                "Version 3 is outside the retention window, the earliest available version is 4"
+                    .into()
+            ))
+        );
+
+        // The GC version is capped at the oldest active transaction.
+        let txn = mvcc.begin()?;
+        assert_eq!(8, txn.id());
+        assert_eq!((2, true), mvcc.gc(100, usize::MAX)?);
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x05]), (b"c".to_vec(), vec![0x03])],
+            txn.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+        txn.commit()?;
+        assert!(mvcc.begin_with_mode(Mode::Snapshot { version: 7 }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_batches() -> Result<()> {
+        let mvcc = setup();
+        for value in 1..=3 {
+            let mut txn = mvcc.begin()?;
+            for key in &[b"a", b"b", b"c"] {
+                txn.set(*key, vec![value])?;
+            }
+            txn.commit()?;
+        }
+
+        // Each batch only garbage collects a single key, since it reaches the batch size. The GC
+        // version is recorded up front, and later calls continue the pass at the GC version
+        // regardless of the given version, retaining later writes.
+        assert_eq!((1, false), mvcc.gc(3, 2)?);
+        assert!(mvcc.begin_with_mode(Mode::Snapshot { version: 2 }).is_err());
+        let mut txn = mvcc.begin()?;
+        txn.set(b"c", vec![0x04])?;
+        txn.commit()?;
+        assert_eq!((1, false), mvcc.gc(100, 2)?);
+        assert_eq!((1, true), mvcc.gc(100, 2)?);
+
+        let txn = mvcc.begin_with_mode(Mode::Snapshot { version: 3 })?;
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), vec![0x03]),
+                (b"b".to_vec(), vec![0x03]),
+                (b"c".to_vec(), vec![0x03])
+            ],
+            txn.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+        txn.commit()?;
+        assert_eq!(Some(vec![0x04]), mvcc.begin()?.get(b"c")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_priority() -> Result<()> {
+        let mvcc = setup();
+
+        // A higher-priority writer aborts an active lower-priority one, rolling back its writes.
+        let mut low = mvcc.begin_with_priority(Mode::ReadWrite, Priority::Low)?;
+        let mut high = mvcc.begin_with_priority(Mode::ReadWrite, Priority::High)?;
+        assert_eq!(Priority::High, high.priority());
+        low.set(b"a", vec![0x01])?;
+        low.set(b"b", vec![0x01])?;
+        high.set(b"a", vec![0x02])?;
+        assert_eq!(Err(Error::Serialization), low.set(b"c", vec![0x01]));
+        let id = low.id();
+        assert_eq!(Priority::Low, mvcc.resume(id)?.priority());
+        assert_eq!(Err(Error::Serialization), low.commit());
+        high.commit()?;
+        let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(vec![(b"a".to_vec(), vec![0x02])], txn.scan(..)?.collect::<Result<Vec<_>>>()?);
+        assert!(mvcc.resume(id).is_err());
+
+        // A lower- or equal-priority writer gets the serialization error, as usual.
+        let mut normal = mvcc.begin()?;
+        let mut low = mvcc.begin_with_priority(Mode::ReadWrite, Priority::Low)?;
+        let mut other = mvcc.begin()?;
+        normal.set(b"a", vec![0x03])?;
+        assert_eq!(Err(Error::Serialization), low.set(b"a", vec![0x04]));
+        assert_eq!(Err(Error::Serialization), other.set(b"a", vec![0x05]));
+        low.rollback()?;
+        other.rollback()?;
+
+        // Committed writes can't be aborted.
+        let mut high = mvcc.begin_with_priority(Mode::ReadWrite, Priority::High)?;
+        normal.commit()?;
+        assert_eq!(Err(Error::Serialization), high.set(b"a", vec![0x06]));
+        high.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_changes() -> Result<()> {
+        let mvcc = setup();
+        let change = |commit, version, key: &[u8], value: Option<Vec<u8>>| Change {
+            commit,
+            version,
+            key: key.to_vec(),
+            value,
+        };
+
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"b", vec![0x01])?;
+        t1.set(b"a", vec![0x01])?;
+        t1.commit()?;
+
+        let mut t2 = mvcc.begin()?;
+        let mut t3 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        t3.delete(b"b")?;
+        t3.set(b"z", vec![0x03])?;
+        t3.commit()?;
+
+        // t3 committed while t2 is still active, so its changes are returned. A read-write
+        // transaction doesn't see its own changes, nor commits of concurrent transactions.
+        let t4 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(
+            (
+                vec![
+                    change(1, 1, b"a", Some(vec![0x01])),
+                    change(1, 1, b"b", Some(vec![0x01])),
+                    change(2, 3, b"b", None),
+                ],
+                3
+            ),
+            t4.changes(..b"c".to_vec(), 0)?
+        );
+        assert_eq!(
+            (vec![change(1, 1, b"a", Some(vec![0x01])), change(1, 1, b"b", Some(vec![0x01]))], 2),
+            t2.changes(.., 0)?
+        );
+        t4.commit()?;
+
+        // Once t2 commits, its changes follow t3's, even though it began earlier.
+        t2.commit()?;
+        let t5 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(
+            (
+                vec![
+                    change(2, 3, b"b", None),
+                    change(2, 3, b"z", Some(vec![0x03])),
+                    change(3, 2, b"a", Some(vec![0x02])),
+                ],
+                4
+            ),
+            t5.changes(.., 2)?
+        );
+        t5.commit()?;
+
+        // Snapshot transactions resolve changes up to the first commit they can't see.
+        let t6 = mvcc.begin_with_mode(Mode::Snapshot { version: 1 })?;
+        assert_eq!(2, t6.changes(.., 0)?.0.len());
+        assert_eq!(2, t6.changes(.., 0)?.1);
+        t6.commit()?;
+
+        // Writes with an expiry are returned with their value, and expiry itself emits no change.
+        // Commits without writes take a position, but have no changes.
+        let mut t7 = mvcc.begin()?;
+        t7.set_with_expiry(b"e", vec![0x07], 8)?;
+        t7.commit()?;
+        mvcc.begin()?.commit()?;
+        let t9 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!((vec![change(4, 7, b"e", Some(vec![0x07]))], 6), t9.changes(.., 4)?);
+        assert_eq!((vec![], 6), t9.changes(.., 6)?);
+
+        // Commits of garbage collected transactions are no longer returned.
+        t9.commit()?;
+        let t10 = mvcc.begin()?;
+        assert!(mvcc.gc(t10.id(), 100)?.1);
+        t10.commit()?;
+        let t11 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!((vec![], 7), t11.changes(.., 0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_get_versions() -> Result<()> {
+        let mvcc = setup();
+
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.set(b"b", vec![0x01])?;
+        t1.commit()?;
+
+        let mut t2 = mvcc.begin()?;
+        t2.delete(b"a")?;
+        t2.commit()?;
+
+        let mut t3 = mvcc.begin()?;
+        let mut t4 = mvcc.begin()?;
+        t3.set(b"a", vec![0x03])?;
+        t4.set(b"a", vec![0x04]).unwrap_err();
+        t4.set(b"b", vec![0x04])?;
+
+        // Uncommitted versions, including the transaction's own, are not visible.
+        assert_eq!(vec![(1, Some(vec![0x01])), (2, None)], t3.get_versions(b"a")?);
+        assert_eq!(vec![(1, Some(vec![0x01])), (2, None)], t4.get_versions(b"a")?);
+        assert_eq!(vec![(1, Some(vec![0x01]))], t4.get_versions(b"b")?);
+        assert_eq!(Vec::<(u64, Option<Vec<u8>>)>::new(), t4.get_versions(b"c")?);
+        t3.commit()?;
+        t4.commit()?;
+
+        let t5 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+        assert_eq!(
+            vec![(1, Some(vec![0x01])), (2, None), (3, Some(vec![0x03]))],
+            t5.get_versions(b"a")?
+        );
+        assert_eq!(vec![(1, Some(vec![0x01])), (4, Some(vec![0x04]))], t5.get_versions(b"b")?);
+
+        // Snapshot transactions only see versions up to the snapshot.
+        let ts = mvcc.begin_with_mode(Mode::Snapshot { version: 2 })?;
+        assert_eq!(vec![(1, Some(vec![0x01])), (2, None)], ts.get_versions(b"a")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txn_get_lifetime() -> Result<()> {
+        let mvcc = setup();
+
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.set(b"b", vec![0x01])?;
+        t1.set_with_expiry(b"c", vec![0x01], 3)?;
+        t1.commit()?;
+
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        t2.delete(b"b")?;
+        t2.commit()?;
+
+        // Own writes are included, and deletes or expiry reset the created version.
+        let mut t3 = mvcc.begin()?;
+        assert_eq!(Some((1, 2)), t3.get_lifetime(b"a")?);
+        assert_eq!(None, t3.get_lifetime(b"b")?);
+        assert_eq!(None, t3.get_lifetime(b"c")?);
+        t3.set(b"a", vec![0x03])?;
+        t3.set(b"b", vec![0x03])?;
+        t3.set(b"c", vec![0x03])?;
+        assert_eq!(Some((1, 3)), t3.get_lifetime(b"a")?);
+        assert_eq!(Some((3, 3)), t3.get_lifetime(b"b")?);
+        assert_eq!(Some((3, 3)), t3.get_lifetime(b"c")?);
+        assert_eq!(None, t3.get_lifetime(b"d")?);
+
+        // Uncommitted writes by others are not visible, and snapshots only see older versions.
+        let t4 = mvcc.begin()?;
+        assert_eq!(Some((1, 2)), t4.get_lifetime(b"a")?);
+        t3.commit()?;
+        let ts = mvcc.begin_with_mode(Mode::Snapshot { version: 1 })?;
+        assert_eq!(Some((1, 1)), ts.get_lifetime(b"a")?);
+        assert_eq!(Some((1, 1)), ts.get_lifetime(b"c")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let mvcc = setup();
+        let large: Vec<u8> = (0..CHUNK_SIZE * 2).map(|i| i as u8).collect();
+
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.set(b"b", vec![0x01, 0x02])?;
+        t1.set(b"c", large.clone())?;
+        t1.set_with_expiry(b"d", vec![0x01], 3)?;
+        t1.commit()?;
+
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02, 0x02, 0x02])?;
+        t2.delete(b"b")?;
+        t2.commit()?;
+
+        // At this point d has expired, a has a superseded version, and b has been deleted.
+        assert_eq!(
+            Stats { keys: 2, bytes: 2 + 3 + large.len() as u64, versions: 6, dead_versions: 4 },
+            mvcc.stats(..)?
+        );
+        assert_eq!(
+            Stats { keys: 1, bytes: 4, versions: 4, dead_versions: 3 },
+            mvcc.stats(b"a".to_vec()..=b"b".to_vec())?
+        );
+        assert_eq!(mvcc.stats(..)?, mvcc.status()?.stats);
+
+        let txn = mvcc.begin()?;
+        assert_eq!(mvcc.stats(b"c".to_vec()..b"d".to_vec())?, txn.stats_prefix(b"c")?);
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch() -> Result<()> {
+        let mvcc = setup();
+        let mut watch = mvcc.watch(b"a".to_vec()..b"c".to_vec(), 0);
+        assert_eq!(Vec::<Change>::new(), watch.poll()?);
+
+        let mut t2 = mvcc.begin()?;
+        let mut t3 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        t3.set(b"b", vec![0x03])?;
+        t3.set(b"c", vec![0x03])?;
+        t3.commit()?;
+
+        // t3 committed before t2, so its changes are emitted first, without waiting for t2.
+        assert_eq!(
+            vec![Change { commit: 1, version: 3, key: b"b".to_vec(), value: Some(vec![0x03]) }],
+            watch.poll()?
+        );
+        assert_eq!(2, watch.from());
+        t2.commit()?;
+        assert_eq!(
+            vec![Change { commit: 2, version: 2, key: b"a".to_vec(), value: Some(vec![0x02]) }],
+            watch.poll()?
+        );
+        assert_eq!(Vec::<Change>::new(), watch.poll()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_import() -> Result<()> {
+        let mvcc = setup();
+        let large: Vec<u8> = (0..CHUNK_SIZE * 2).map(|i| i as u8).collect();
+        mvcc.set_metadata(b"meta", vec![0x01])?;
+
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.set(b"b", vec![0x01])?;
+        t1.set(b"c", large.clone())?;
+        t1.set_with_expiry(b"d", vec![0x01], 3)?;
+        t1.set_with_expiry(b"e", vec![0x01], 8)?;
+        t1.commit()?;
+
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        t2.delete(b"b")?;
+        t2.commit()?;
+
+        // Uncommitted writes are not exported.
+        let mut t3 = mvcc.begin()?;
+        t3.set(b"f", vec![0x03])?;
+
+        // Exporting takes a snapshot at the next version, without modifying the store.
+        let mut snapshot = Vec::new();
+        assert_eq!(4, mvcc.export(&mut snapshot)?);
+        assert_eq!(3, mvcc.status()?.txns);
+        t3.commit()?;
+
+        let import = setup();
+        assert_eq!(4, import.import(&snapshot[..])?);
+        assert_eq!(Some(vec![0x01]), import.get_metadata(b"meta")?);
+        let txn = import.begin()?;
+        assert_eq!(4, txn.id());
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x02]), (b"c".to_vec(), large), (b"e".to_vec(), vec![0x01])],
+            txn.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+        txn.commit()?;
+
+        // Expiry versions are preserved.
+        for _ in 5..8 {
+            import.begin()?.commit()?;
+        }
+        assert_eq!(None, import.begin()?.get(b"e")?);
+
+        // Importing requires an empty store and a valid snapshot.
+        assert!(import.import(&snapshot[..]).is_err());
+        assert!(setup().import(&b"invalid"[..]).is_err());
+        assert!(setup().import(&snapshot[..snapshot.len() - 4]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_load() -> Result<()> {
+        let mvcc = setup();
+        mvcc.set_metadata(b"meta", vec![0x01])?;
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.commit()?;
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        t2.set(b"b", vec![0x02])?;
+
+        let mut dump = Vec::new();
+        mvcc.dump(&mut dump)?;
+
+        // Loading replaces any existing data, and retains history and active transactions.
+        let load = setup();
+        let mut txn = load.begin()?;
+        txn.set(b"c", vec![0x03])?;
+        txn.commit()?;
+        load.load(&dump[..])?;
+        assert_eq!(Some(vec![0x01]), load.get_metadata(b"meta")?);
+        assert_eq!(mvcc.status()?, load.status()?);
+
+        let t2 = load.resume(t2.id())?;
+        assert_eq!(Some(vec![0x02]), t2.get(b"b")?);
+        t2.commit()?;
+        let txn = load.begin_with_mode(Mode::Snapshot { version: 1 })?;
+        assert_eq!(vec![(b"a".to_vec(), vec![0x01])], txn.scan(..)?.collect::<Result<Vec<_>>>()?);
+        Ok(())
+    }
+
     #[test]
     // A dirty write is when t2 overwrites an uncommitted value written by t1.
     fn test_txn_anomaly_dirty_write() -> Result<()> {
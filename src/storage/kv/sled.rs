@@ -0,0 +1,104 @@
+use super::{Range, Scan, Store};
+use crate::error::Result;
+use crate::storage::SyncMode;
+
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Instant;
+
+/// On-disk key-value store using the sled embedded database.
+pub struct Sled {
+    /// The sled database.
+    db: ::sled::Db,
+    /// The sync mode, which determines when flush() fsyncs to disk.
+    sync: SyncMode,
+    /// The time of the last fsync, for SyncMode::Interval.
+    last_sync: Instant,
+}
+
+impl Sled {
+    /// Opens or creates a sled database in the given directory. The cache capacity is given in
+    /// bytes, or the sled default if None.
+    pub fn new(dir: &Path, sync: SyncMode, cache_capacity: Option<u64>) -> Result<Self> {
+        let mut config = ::sled::Config::new().path(dir);
+        if let Some(capacity) = cache_capacity {
+            config = config.cache_capacity(capacity);
+        }
+        Ok(Self { db: config.open()?, sync, last_sync: Instant::now() })
+    }
+}
+
+impl Display for Sled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sled")
+    }
+}
+
+impl Store for Sled {
+    fn flush(&mut self) -> Result<()> {
+        match self.sync {
+            SyncMode::Always => {}
+            SyncMode::Interval(interval) if self.last_sync.elapsed() >= interval => {}
+            SyncMode::Interval(_) | SyncMode::Never => return Ok(()),
+        }
+        self.db.flush()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.db.remove(key)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        Box::new(
+            self.db
+                .range::<Vec<u8>, _>(range)
+                .map(|r| r.map(|(k, v)| (k.to_vec(), v.to_vec())).map_err(|e| e.into())),
+        )
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.db.insert(key, value)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl super::super::TestSuite<Sled> for Sled {
+        fn setup() -> Result<Self> {
+            let db = ::sled::Config::new().temporary(true).open()?;
+            Ok(Sled { db, sync: SyncMode::Never, last_sync: Instant::now() })
+        }
+    }
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        Sled::test()
+    }
+
+    #[test]
+    fn test_persistent() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut s = Sled::new(dir.path(), SyncMode::Always, Some(1 << 20))?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.delete(b"a")?;
+        s.flush()?;
+        drop(s);
+
+        let s = Sled::new(dir.path(), SyncMode::Always, None)?;
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), s.get(b"b")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use super::{Range, Store};
+use crate::error::Result;
+
+use std::sync::{Arc, RwLock};
+
+/// An async log store, which wraps a synchronous Store and runs its operations on the tokio
+/// blocking thread pool, such that disk I/O doesn't stall the executor threads. The wrapper is
+/// cheap to clone, and clones share the underlying store.
+#[derive(Clone)]
+pub struct AsyncStore {
+    store: Arc<RwLock<Box<dyn Store>>>,
+}
+
+impl AsyncStore {
+    /// Creates a new async store wrapping the given synchronous store.
+    pub fn new(store: Box<dyn Store>) -> Self {
+        Self { store: Arc::new(RwLock::new(store)) }
+    }
+
+    /// Runs a closure with shared access to the underlying store, on the blocking thread pool.
+    async fn read<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&dyn Store) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(&**store.read()?)).await?
+    }
+
+    /// Runs a closure with exclusive access to the underlying store, on the blocking thread pool.
+    async fn write<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut dyn Store) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(&mut **store.write()?)).await?
+    }
+
+    /// Appends a log entry, returning its index.
+    pub async fn append(&self, entry: Vec<u8>) -> Result<u64> {
+        self.write(move |store| store.append(entry)).await
+    }
+
+    /// Commits log entries up to and including the given index, making them immutable.
+    pub async fn commit(&self, index: u64) -> Result<()> {
+        self.write(move |store| store.commit(index)).await
+    }
+
+    /// Returns the committed index, if any.
+    pub async fn committed(&self) -> Result<u64> {
+        self.read(|store| Ok(store.committed())).await
+    }
+
+    /// Compacts the log by removing all entries up to and including the given index, replacing
+    /// them with the given snapshot.
+    pub async fn compact(&self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        self.write(move |store| store.compact(index, snapshot)).await
+    }
+
+    /// Returns the index of the last compacted entry, or 0 if the log has not been compacted.
+    pub async fn compacted(&self) -> Result<u64> {
+        self.read(|store| Ok(store.compacted())).await
+    }
+
+    /// Fetches a log entry, if it exists.
+    pub async fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
+        self.read(move |store| store.get(index)).await
+    }
+
+    /// Returns the number of entries in the log.
+    pub async fn len(&self) -> Result<u64> {
+        self.read(|store| Ok(store.len())).await
+    }
+
+    /// Returns true if the log has no entries.
+    pub async fn is_empty(&self) -> Result<bool> {
+        self.read(|store| Ok(store.is_empty())).await
+    }
+
+    /// Fetches the log entries between the given indexes. Since the scan can't borrow the store
+    /// across await points, the entries are buffered in memory.
+    pub async fn scan(&self, range: Range) -> Result<Vec<Vec<u8>>> {
+        self.read(move |store| store.scan(range).collect()).await
+    }
+
+    /// Returns the size of the log, in bytes.
+    pub async fn size(&self) -> Result<u64> {
+        self.read(|store| Ok(store.size())).await
+    }
+
+    /// Fetches the snapshot stored by the last compaction, if any.
+    pub async fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        self.read(|store| store.snapshot()).await
+    }
+
+    /// Truncates the log be removing any entries above the given index, and returns the
+    /// highest index. Errors if asked to truncate any committed entries.
+    pub async fn truncate(&self, index: u64) -> Result<u64> {
+        self.write(move |store| store.truncate(index)).await
+    }
+
+    /// Gets a metadata value.
+    pub async fn get_metadata(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.read(move |store| store.get_metadata(&key)).await
+    }
+
+    /// Sets a metadata value.
+    pub async fn set_metadata(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.write(move |store| store.set_metadata(&key, value)).await
+    }
+
+    /// Runs a synchronous closure against the underlying store on the blocking thread pool, for
+    /// e.g. batching several operations under a single lock acquisition.
+    pub async fn with<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut dyn Store) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.write(f).await
+    }
+}
+
+impl std::fmt::Display for AsyncStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.store.read() {
+            Ok(store) => write!(f, "async {}", store),
+            Err(_) => write!(f, "async (poisoned)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Memory;
+    use super::*;
+
+    #[tokio::test]
+    async fn operations() -> Result<()> {
+        let s = AsyncStore::new(Box::new(Memory::new()));
+        assert_eq!(0, s.len().await?);
+        assert_eq!(1, s.append(vec![0x01]).await?);
+        assert_eq!(2, s.append(vec![0x02]).await?);
+        assert_eq!(3, s.append(vec![0x03]).await?);
+        assert_eq!(3, s.len().await?);
+        assert_eq!(Some(vec![0x02]), s.get(2).await?);
+
+        s.commit(2).await?;
+        assert_eq!(2, s.committed().await?);
+        assert_eq!(2, s.truncate(2).await?);
+        assert!(s.truncate(1).await.is_err());
+        assert_eq!(vec![vec![0x01], vec![0x02]], s.scan(Range::from(..)).await?);
+
+        s.set_metadata(b"a".to_vec(), vec![0x01]).await?;
+        assert_eq!(Some(vec![0x01]), s.get_metadata(b"a".to_vec()).await?);
+
+        // Clones share the underlying store.
+        let c = s.clone();
+        assert_eq!(3, c.with(|store| store.append(vec![0x03])).await?);
+        assert_eq!(3, s.len().await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use super::{Range, Scan, Store};
+use crate::error::Result;
+use crate::fault::Faults;
+
+use std::fmt::Display;
+
+/// Log store wrapper which injects faults into operations on an inner store, for chaos testing.
+/// Fallible operations are named log.append, log.commit, log.compact, log.flush, log.get,
+/// log.scan, log.snapshot, log.truncate, log.get_metadata, and log.set_metadata. A failed scan
+/// yields a single error.
+pub struct Faulty {
+    inner: Box<dyn Store>,
+    faults: Faults,
+}
+
+impl Faulty {
+    /// Creates a new faulty log store, wrapping the given store.
+    pub fn new(inner: Box<dyn Store>, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl Display for Faulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "faulty {}", self.inner)
+    }
+}
+
+impl Store for Faulty {
+    fn append(&mut self, entry: Vec<u8>) -> Result<u64> {
+        self.faults.inject("log.append")?;
+        self.inner.append(entry)
+    }
+
+    fn commit(&mut self, index: u64) -> Result<()> {
+        self.faults.inject("log.commit")?;
+        self.inner.commit(index)
+    }
+
+    fn committed(&self) -> u64 {
+        self.inner.committed()
+    }
+
+    fn compact(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        self.faults.inject("log.compact")?;
+        self.inner.compact(index, snapshot)
+    }
+
+    fn compacted(&self) -> u64 {
+        self.inner.compacted()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.faults.inject("log.flush")?;
+        self.inner.flush()
+    }
+
+    fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
+        self.faults.inject("log.get")?;
+        self.inner.get(index)
+    }
+
+    fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        match self.faults.inject("log.scan") {
+            Ok(()) => self.inner.scan(range),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        self.faults.inject("log.snapshot")?;
+        self.inner.snapshot()
+    }
+
+    fn truncate(&mut self, index: u64) -> Result<u64> {
+        self.faults.inject("log.truncate")?;
+        self.inner.truncate(index)
+    }
+
+    fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.faults.inject("log.get_metadata")?;
+        self.inner.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.faults.inject("log.set_metadata")?;
+        self.inner.set_metadata(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Memory, TestSuite};
+    use super::*;
+    use crate::fault::Rule;
+
+    impl TestSuite<Faulty> for Faulty {
+        fn setup() -> Result<Self> {
+            Ok(Faulty::new(Box::new(Memory::new()), Faults::new()))
+        }
+    }
+
+    #[test]
+    fn tests() -> Result<()> {
+        Faulty::test()
+    }
+
+    #[test]
+    fn inject() -> Result<()> {
+        let faults = Faults::new();
+        let mut s = Faulty::new(Box::new(Memory::new()), faults.clone());
+        s.append(vec![0x01])?;
+
+        faults.add(Rule::error("log.append").with_limit(1))?;
+        faults.add(Rule::error("log.scan").with_limit(1))?;
+        assert_eq!(s.append(vec![0x02]), Err(Faults::error("log.append")));
+        assert_eq!(
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>(),
+            Err(Faults::error("log.scan"))
+        );
+
+        // The failed append had no effect, and the store works once faults are exhausted.
+        assert_eq!(1, s.len());
+        assert_eq!(2, s.append(vec![0x02])?);
+        assert_eq!(vec![vec![1], vec![2]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+        Ok(())
+    }
+}
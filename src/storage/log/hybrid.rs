@@ -1,31 +1,74 @@
 use super::{Range, Scan, Store};
 use crate::error::{Error, Result};
+use crate::storage::{Compression, SyncMode};
 
+use ::log::warn;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::Display;
 use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Seek as _, SeekFrom, Write};
+use std::io::{BufReader, Read, Seek as _, SeekFrom, Write};
 use std::ops::Bound;
-use std::path::Path;
-use std::sync::{Mutex, MutexGuard};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
 
-/// A hybrid log store, storing committed entries in an append-only file, uncommitted entries
-/// in memory, and metadata in a separate file (should be an on-disk key-value store).
+/// Flag set in the high bit of an entry's length prefix if the entry is compressed.
+const COMPRESSED_FLAG: u32 = 1 << 31;
+
+/// The maximum size of an entry, since the high bit of the length prefix is used for flags.
+const MAX_ENTRY_SIZE: usize = (1 << 31) - 1;
+
+/// The file name prefix of log segment files, which is followed by the zero-padded index of the
+/// first entry in the segment.
+const SEGMENT_PREFIX: &str = "raft-log-";
+
+/// The default maximum size of a log segment file, in bytes.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The location of an entry in a segment file: the segment's first index, the entry position and
+/// size, and whether it is compressed.
+type Location = (u64, u64, u32, bool);
+
+/// An entry found while scanning a segment file: its position, size, and compression flag.
+type SegmentEntry = (u64, u32, bool);
+
+/// A hybrid log store, storing committed entries in append-only segment files, uncommitted
+/// entries in memory, and metadata in a separate file (should be an on-disk key-value store).
 ///
-/// The log file contains sequential binary log entries, length-prefixed with a big-endian u32.
-/// Entries are only flushed to disk when they are committed and permanent, thus the file is
-/// written append-only.
+/// Committed entries are written to a sequence of segment files, each containing sequential binary
+/// log entries length-prefixed with a big-endian u32. Segment files are named by the index of their
+/// first entry, and a new segment is started once the current one exceeds the segment size.
+/// Entries are only flushed to disk when they are committed and permanent, thus the files are
+/// written append-only. If compression is enabled, entries are compressed with Snappy when
+/// this makes them smaller, which is flagged by setting the high bit of the length prefix.
 ///
 /// An index of entry positions and sizes is maintained in memory. This is rebuilt on startup by
-/// scanning the file, since maintaining the index in a separate file requires additional fsyncing
-/// which is expensive. Since datasets are expected to be small, scanning the file on startup is
-/// reasonably cheap.
+/// scanning the segment files, since maintaining the index in a separate file requires additional
+/// fsyncing which is expensive. Since datasets are expected to be small, scanning the files on
+/// startup is reasonably cheap.
+///
+/// When the log is compacted, the snapshot is written atomically to a separate file along with
+/// the compacted index, and any segments containing only compacted entries are then deleted. A
+/// segment that also contains later entries is retained until all of its entries are compacted.
+///
+/// Metadata (i.e. the Raft term and vote) is also written atomically via a temporary file, and is
+/// fsynced unless the sync mode is never, since Raft safety depends on it being durable before
+/// votes are sent. If the node crashes while appending to the log, the last segment may end with a
+/// torn (partially written) entry. This entry can't have been acknowledged as durable, so it is
+/// discarded when the log is opened.
 pub struct Hybrid {
-    /// The append-only log file. Protected by a mutex for interior mutability (i.e. read seeks).
-    file: Mutex<File>,
-    /// Index of entry locations and sizes in the log file.
-    index: BTreeMap<u64, (u64, u32)>,
+    /// The directory containing the log files.
+    dir: PathBuf,
+    /// The log segment files, keyed by the index of their first entry. Protected by a mutex for
+    /// interior mutability (i.e. read seeks).
+    segments: Mutex<BTreeMap<u64, File>>,
+    /// The size at which to start a new segment, in bytes.
+    segment_size: u64,
+    /// The index of the last compacted entry.
+    compacted: u64,
+    /// Index of entry locations (segment and position), sizes, and compression flags.
+    index: BTreeMap<u64, Location>,
     /// Uncommitted log entries.
     uncommitted: VecDeque<Vec<u8>>,
     /// Metadata cache. Flushed to disk on changes.
@@ -33,8 +76,14 @@ pub struct Hybrid {
     /// The file used to store metadata.
     /// FIXME Should be an on-disk B-tree key-value store.
     metadata_file: File,
-    /// If true, fsync writes.
-    sync: bool,
+    /// The fsync mode for writes.
+    sync: SyncMode,
+    /// The compression algorithm for new entries.
+    compression: Compression,
+    /// The maximum size of a stored entry, i.e. MAX_ENTRY_SIZE except in tests.
+    max_entry_size: usize,
+    /// The time of the last fsync, used for interval syncing.
+    last_sync: Instant,
 }
 
 impl Display for Hybrid {
@@ -45,11 +94,9 @@ impl Display for Hybrid {
 
 impl Hybrid {
     /// Creates or opens a new hybrid log, with files in the given directory.
-    pub fn new(dir: &Path, sync: bool) -> Result<Self> {
+    pub fn new(dir: &Path, sync: SyncMode, compression: Compression) -> Result<Self> {
         create_dir_all(dir)?;
-
-        let file =
-            OpenOptions::new().read(true).write(true).create(true).open(dir.join("raft-log"))?;
+        Self::migrate_legacy(dir)?;
 
         let metadata_file = OpenOptions::new()
             .read(true)
@@ -57,35 +104,292 @@ impl Hybrid {
             .create(true)
             .open(dir.join("raft-metadata"))?;
 
+        let compacted = match Self::read_snapshot(dir)? {
+            Some((index, _)) => index,
+            None => 0,
+        };
+        let (segments, index) = Self::open_segments(dir, compacted)?;
         Ok(Self {
-            index: Self::build_index(&file)?,
-            file: Mutex::new(file),
+            dir: dir.to_path_buf(),
+            segments: Mutex::new(segments),
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            compacted,
+            index,
             uncommitted: VecDeque::new(),
             metadata: Self::load_metadata(&metadata_file)?,
             metadata_file,
             sync,
+            compression,
+            max_entry_size: MAX_ENTRY_SIZE,
+            last_sync: Instant::now(),
         })
     }
 
-    /// Builds the index by scanning the log file.
-    fn build_index(file: &File) -> Result<BTreeMap<u64, (u64, u32)>> {
+    /// Sets the segment size, at which a new segment file is started.
+    pub fn with_segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Migrates a log written by previous versions as a single file named raft-log, by renaming
+    /// it to the first log segment. Compacted legacy logs, which begin with a header flagged with
+    /// the second-highest bit of the length prefix, are not supported.
+    fn migrate_legacy(dir: &Path) -> Result<()> {
+        let legacy = dir.join("raft-log");
+        if legacy.exists() {
+            let mut prefix = [0; 4];
+            let mut file = File::open(&legacy)?;
+            if file.metadata()?.len() >= 4 {
+                file.read_exact(&mut prefix)?;
+                if u32::from_be_bytes(prefix) & (1 << 30) != 0 {
+                    return Err(Error::Internal(
+                        "Can't migrate compacted legacy Raft log, remove the data directory".into(),
+                    ));
+                }
+            }
+            std::fs::rename(&legacy, dir.join(Self::segment_name(1)))?;
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the file name of the segment starting at the given index.
+    fn segment_name(first: u64) -> String {
+        format!("{}{:020}", SEGMENT_PREFIX, first)
+    }
+
+    /// Opens the log segments in the given directory and builds the entry index. Segments that
+    /// only contain compacted entries are deleted, which may be left behind if we crashed while
+    /// compacting, and a torn entry at the end of the last segment is discarded.
+    fn open_segments(
+        dir: &Path,
+        compacted: u64,
+    ) -> Result<(BTreeMap<u64, File>, BTreeMap<u64, Location>)> {
+        let mut firsts = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if let Some(first) = name.strip_prefix(SEGMENT_PREFIX) {
+                if let Ok(first) = first.parse::<u64>() {
+                    firsts.push(first);
+                }
+            }
+        }
+        firsts.sort_unstable();
+
+        let mut segments = BTreeMap::new();
+        let mut index = BTreeMap::new();
+        let mut next = None;
+        for (i, first) in firsts.iter().copied().enumerate() {
+            let last = i == firsts.len() - 1;
+            let path = dir.join(Self::segment_name(first));
+            if let Some(next) = next {
+                if first != next {
+                    return Err(Error::Internal(format!(
+                        "Log segment {} does not follow previous segment ending at {}",
+                        first,
+                        next - 1
+                    )));
+                }
+            } else if first > compacted + 1 {
+                return Err(Error::Internal(format!(
+                    "Log segment {} does not follow compacted index {}",
+                    first, compacted
+                )));
+            }
+
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let (entries, end) = Self::scan_segment(&file, first)?;
+            let size = file.metadata()?.len();
+            if end < size {
+                if !last {
+                    return Err(Error::Internal(format!("Log segment {} is truncated", first)));
+                }
+                warn!("Discarding torn write of {} bytes at end of Raft log", size - end);
+                file.set_len(end)?;
+                file.sync_all()?;
+            }
+            next = Some(first + entries.len() as u64);
+
+            if first + entries.len() as u64 <= compacted + 1 {
+                std::fs::remove_file(&path)?;
+                continue;
+            }
+            for (i, (pos, size, compressed)) in entries.into_iter().enumerate() {
+                let i = first + i as u64;
+                if i > compacted {
+                    index.insert(i, (first, pos, size, compressed));
+                }
+            }
+            segments.insert(first, file);
+        }
+        Ok((segments, index))
+    }
+
+    /// Scans a segment file, returning the position, size, and compression flag of its entries
+    /// along with the end position of the last complete entry. This is before the end of the file
+    /// if the last entry was torn by a crash during writing.
+    fn scan_segment(file: &File, first: u64) -> Result<(Vec<SegmentEntry>, u64)> {
         let filesize = file.metadata()?.len();
         let mut bufreader = BufReader::new(file);
-        let mut index = BTreeMap::new();
+        let mut entries = Vec::new();
         let mut sizebuf = [0; 4];
         let mut pos = 0;
-        let mut i = 1;
-        while pos < filesize {
+        while filesize - pos >= 4 {
             bufreader.read_exact(&mut sizebuf)?;
-            pos += 4;
-            let size = u32::from_be_bytes(sizebuf);
-            index.insert(i, (pos, size));
-            let mut buf = vec![0; size as usize];
-            bufreader.read_exact(&mut buf)?;
-            pos += size as u64;
-            i += 1;
+            let (size, compressed) = Self::decode_size(u32::from_be_bytes(sizebuf));
+            if filesize - pos - 4 < size as u64 {
+                break;
+            }
+            entries.push((pos + 4, size, compressed));
+            bufreader.seek(SeekFrom::Current(size as i64))?;
+            pos += 4 + size as u64;
+        }
+        if entries.len() as u64 > u64::MAX - first {
+            return Err(Error::Internal(format!("Log segment {} overflows", first)));
+        }
+        Ok((entries, pos))
+    }
+
+    /// Reads the snapshot file, if any, returning the compacted index and snapshot. The file
+    /// contains the compacted index as a big-endian u64, followed by the snapshot.
+    fn read_snapshot(dir: &Path) -> Result<Option<(u64, Vec<u8>)>> {
+        let mut data = match std::fs::read(dir.join("raft-snapshot")) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if data.len() < 8 {
+            return Err(Error::Internal("Invalid log snapshot file".into()));
+        }
+        let snapshot = data.split_off(8);
+        let mut indexbuf = [0; 8];
+        indexbuf.copy_from_slice(&data);
+        Ok(Some((u64::from_be_bytes(indexbuf), snapshot)))
+    }
+
+    /// Fsyncs the given segment according to the sync mode. In interval mode, the segment is
+    /// fsynced once the interval has elapsed, persisting any earlier unsynced writes as well.
+    /// Metadata is synced separately when written, see set_metadata().
+    fn sync_writes(&mut self, segment: u64) -> Result<()> {
+        let segments = self.segments.lock()?;
+        let file = segments
+            .get(&segment)
+            .ok_or_else(|| Error::Internal(format!("Log segment {} not found", segment)))?;
+        match self.sync {
+            SyncMode::Always => file.sync_data()?,
+            SyncMode::Interval(interval) if self.last_sync.elapsed() >= interval => {
+                file.sync_data()?;
+                self.last_sync = Instant::now();
+            }
+            SyncMode::Interval(_) | SyncMode::Never => {}
+        }
+        Ok(())
+    }
+
+    /// Writes a file atomically, by writing it to a temporary file, optionally fsyncing it, and
+    /// then renaming it into place. When syncing, the directory is also fsynced to persist the
+    /// rename. Returns the file, opened for reading and writing.
+    fn write_file(
+        &self,
+        name: &str,
+        sync: bool,
+        write: impl FnOnce(&mut File) -> Result<()>,
+    ) -> Result<File> {
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        let mut file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)?;
+        write(&mut file)?;
+        if sync {
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, self.dir.join(name))?;
+        if sync {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(file)
+    }
+
+    /// Writes committed entries, given as index, entry, and compression flag, to the end of the
+    /// segment files, starting a new segment whenever the current one is full. The previous
+    /// segment is flushed and synced first, since entries must be written in order. Any segments
+    /// created are recorded in created. Returns the entry locations and the last segment written.
+    fn write_entries<'a>(
+        &self,
+        segments: &mut BTreeMap<u64, File>,
+        created: &mut Vec<u64>,
+        entries: impl Iterator<Item = (u64, &'a [u8], bool)>,
+    ) -> Result<(Vec<(u64, Location)>, u64)> {
+        let (mut segment, mut pos) = match segments.iter_mut().next_back() {
+            Some((first, file)) => (Some(*first), file.seek(SeekFrom::End(0))?),
+            None => (None, 0),
+        };
+        let mut buf = Vec::new();
+        let mut locations = Vec::new();
+        for (i, entry, compressed) in entries {
+            let current = match segment {
+                Some(segment) if pos < self.segment_size => segment,
+                previous => {
+                    if let Some(previous) = previous {
+                        let file = Self::segment_mut(segments, previous)?;
+                        file.write_all(&buf)?;
+                        buf.clear();
+                        if self.sync != SyncMode::Never {
+                            file.sync_data()?;
+                        }
+                    }
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(self.dir.join(Self::segment_name(i)))?;
+                    created.push(i);
+                    if self.sync != SyncMode::Never {
+                        File::open(&self.dir)?.sync_all()?;
+                    }
+                    segments.insert(i, file);
+                    segment = Some(i);
+                    pos = 0;
+                    i
+                }
+            };
+            let prefix = entry.len() as u32 | if compressed { COMPRESSED_FLAG } else { 0 };
+            buf.extend_from_slice(&prefix.to_be_bytes());
+            buf.extend_from_slice(entry);
+            locations.push((i, (current, pos + 4, entry.len() as u32, compressed)));
+            pos += 4 + entry.len() as u64;
+        }
+        let segment = segment.ok_or_else(|| Error::Internal("No log entries to write".into()))?;
+        Self::segment_mut(segments, segment)?.write_all(&buf)?;
+        Ok((locations, segment))
+    }
+
+    /// Returns the segment file starting at the given index.
+    fn segment_mut(segments: &mut BTreeMap<u64, File>, segment: u64) -> Result<&mut File> {
+        segments
+            .get_mut(&segment)
+            .ok_or_else(|| Error::Internal(format!("Log segment {} not found", segment)))
+    }
+
+    /// Decodes an entry length prefix into the entry size and compression flag.
+    fn decode_size(prefix: u32) -> (u32, bool) {
+        (prefix & !COMPRESSED_FLAG, prefix & COMPRESSED_FLAG != 0)
+    }
+
+    /// Reads an entry from a segment file, decompressing it if necessary.
+    fn read_entry(&self, segment: u64, pos: u64, size: u32, compressed: bool) -> Result<Vec<u8>> {
+        let mut entry = vec![0; size as usize];
+        let mut segments = self.segments.lock()?;
+        let file = segments
+            .get_mut(&segment)
+            .ok_or_else(|| Error::Internal(format!("Log segment {} not found", segment)))?;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut entry)?;
+        if compressed {
+            Compression::Snappy.decompress(&entry)
+        } else {
+            Ok(entry)
         }
-        Ok(index)
     }
 
     /// Loads metadata from a file.
@@ -111,73 +415,154 @@ impl Store for Hybrid {
     }
 
     fn commit(&mut self, index: u64) -> Result<()> {
+        let committed = self.committed();
         if index > self.len() {
             return Err(Error::Internal(format!("Cannot commit non-existant index {}", index)));
         }
-        if index < self.index.len() as u64 {
+        if index < committed {
             return Err(Error::Internal(format!(
                 "Cannot commit below current committed index {}",
-                self.index.len() as u64
+                committed
             )));
         }
-        if index == self.index.len() as u64 {
+        if index == committed {
             return Ok(());
         }
 
-        let mut file = self.file.lock()?;
-        let mut pos = file.seek(SeekFrom::End(0))?;
-        let mut bufwriter = BufWriter::new(&mut *file);
-        for i in (self.index.len() as u64 + 1)..=index {
-            let entry = self
-                .uncommitted
-                .pop_front()
-                .ok_or_else(|| Error::Internal("Unexpected end of uncommitted entries".into()))?;
-            bufwriter.write_all(&(entry.len() as u32).to_be_bytes())?;
-            pos += 4;
-            self.index.insert(i, (pos, entry.len() as u32));
-            bufwriter.write_all(&entry)?;
-            pos += entry.len() as u64;
+        // Entries are compressed and checked before any of them are committed, such that an
+        // oversized entry fails the commit without leaving the log partially committed.
+        let mut compressed = Vec::with_capacity((index - committed) as usize);
+        for entry in self.uncommitted.iter().take((index - committed) as usize) {
+            let entry_compressed = self.compression.compress(entry)?;
+            let size = entry_compressed.as_ref().map_or(entry.len(), |c| c.len());
+            if size > self.max_entry_size {
+                return Err(Error::Internal(format!("Log entry too large ({} bytes)", size)));
+            }
+            compressed.push(entry_compressed);
         }
-        bufwriter.flush()?;
-        drop(bufwriter);
-        if self.sync {
-            file.sync_data()?;
+
+        // Entries are written before updating the index and uncommitted entries, such that a
+        // write error leaves the in-memory state unchanged. Any partial writes are discarded by
+        // truncating the last segment and removing new segments, so the commit can be retried.
+        let mut segments = self.segments.lock()?;
+        let last = match segments.iter().next_back() {
+            Some((first, file)) => Some((*first, file.metadata()?.len())),
+            None => None,
+        };
+        let mut created = Vec::new();
+        let entries = ((committed + 1)..=index).zip(&self.uncommitted).zip(&compressed).map(
+            |((i, entry), compressed)| match compressed {
+                Some(compressed) => (i, compressed.as_slice(), true),
+                None => (i, entry.as_slice(), false),
+            },
+        );
+        let (locations, segment) = match self.write_entries(&mut segments, &mut created, entries) {
+            Ok(written) => written,
+            Err(err) => {
+                for first in created {
+                    segments.remove(&first);
+                    std::fs::remove_file(self.dir.join(Self::segment_name(first))).ok();
+                }
+                if let Some((first, len)) = last {
+                    if let Some(file) = segments.get(&first) {
+                        file.set_len(len).ok();
+                    }
+                }
+                return Err(err);
+            }
+        };
+        drop(segments);
+        self.uncommitted.drain(..locations.len());
+        self.index.extend(locations);
+        self.sync_writes(segment)
+    }
+
+    fn committed(&self) -> u64 {
+        self.compacted + self.index.len() as u64
+    }
+
+    fn compact(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        if index < self.compacted {
+            return Err(Error::Internal(format!(
+                "Cannot compact below compacted index {}",
+                self.compacted
+            )));
+        }
+        let committed = self.committed();
+
+        // Persist the snapshot and compacted index before removing any entries, so we don't
+        // lose data if we crash.
+        self.write_file("raft-snapshot", true, |file| {
+            file.write_all(&index.to_be_bytes())?;
+            Ok(file.write_all(&snapshot)?)
+        })?;
+
+        // Remove compacted entries from the index, and delete any segments that only contain
+        // compacted entries, i.e. where the next segment (if any) starts at or before index+1.
+        // The last segment is deleted if all committed entries are compacted.
+        self.index = self.index.split_off(&(index + 1));
+        let mut segments = self.segments.lock()?;
+        let firsts: Vec<u64> = segments.keys().copied().collect();
+        for (i, first) in firsts.iter().enumerate() {
+            let next = firsts.get(i + 1).copied().unwrap_or(committed + 1);
+            if next > index + 1 {
+                break;
+            }
+            segments.remove(first);
+            std::fs::remove_file(self.dir.join(Self::segment_name(*first)))?;
         }
+        drop(segments);
+
+        // Compacted uncommitted entries are removed from memory, and become committed.
+        if index > committed {
+            let count = min(index - committed, self.uncommitted.len() as u64);
+            self.uncommitted.drain(..count as usize);
+        }
+        self.compacted = index;
         Ok(())
     }
 
-    fn committed(&self) -> u64 {
-        self.index.len() as u64
+    fn compacted(&self) -> u64 {
+        self.compacted
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.metadata_file.sync_all()?;
+        for file in self.segments.lock()?.values() {
+            file.sync_data()?;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
     }
 
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
+        let committed = self.committed();
         match index {
-            0 => Ok(None),
-            i if i <= self.index.len() as u64 => {
-                let (pos, size) = self.index.get(&i).copied().ok_or_else(|| {
-                    Error::Internal(format!("Indexed position not found for entry {}", i))
-                })?;
-                let mut entry = vec![0; size as usize];
-                let mut file = self.file.lock()?;
-                file.seek(SeekFrom::Start(pos))?;
-                file.read_exact(&mut entry)?;
-                Ok(Some(entry))
+            i if i <= self.compacted => Ok(None),
+            i if i <= committed => {
+                let (segment, pos, size, compressed) =
+                    self.index.get(&i).copied().ok_or_else(|| {
+                        Error::Internal(format!("Indexed position not found for entry {}", i))
+                    })?;
+                Ok(Some(self.read_entry(segment, pos, size, compressed)?))
             }
-            i => Ok(self.uncommitted.get(i as usize - self.index.len() - 1).cloned()),
+            i => Ok(self.uncommitted.get((i - committed) as usize - 1).cloned()),
         }
     }
 
     fn len(&self) -> u64 {
-        self.index.len() as u64 + self.uncommitted.len() as u64
+        self.committed() + self.uncommitted.len() as u64
     }
 
     fn scan(&self, range: Range) -> Scan {
-        let start = match range.start {
-            Bound::Included(0) => 1,
-            Bound::Included(n) => n,
-            Bound::Excluded(n) => n + 1,
-            Bound::Unbounded => 1,
-        };
+        let start = max(
+            self.compacted + 1,
+            match range.start {
+                Bound::Included(n) => n,
+                Bound::Excluded(n) => n + 1,
+                Bound::Unbounded => 1,
+            },
+        );
         let end = match range.end {
             Bound::Included(n) => n,
             Bound::Excluded(0) => 0,
@@ -190,29 +575,22 @@ impl Store for Hybrid {
             return scan;
         }
 
-        // Scan committed entries in file
-        if let Some((offset, _)) = self.index.get(&start) {
-            let mut file = self.file.lock().unwrap();
-            file.seek(SeekFrom::Start(*offset - 4)).unwrap(); // seek to length prefix
-            let mut bufreader = BufReader::new(MutexReader(file)); // FIXME Avoid MutexReader
-            scan =
-                Box::new(scan.chain(self.index.range(start..=end).map(move |(_, (_, size))| {
-                    let mut sizebuf = vec![0; 4];
-                    bufreader.read_exact(&mut sizebuf)?;
-                    let mut entry = vec![0; *size as usize];
-                    bufreader.read_exact(&mut entry)?;
-                    Ok(entry)
-                })));
-        }
+        // Scan committed entries in segment files
+        scan = Box::new(scan.chain(self.index.range(start..=end).map(
+            move |(_, (segment, pos, size, compressed))| {
+                self.read_entry(*segment, *pos, *size, *compressed)
+            },
+        )));
 
         // Scan uncommitted entries in memory
-        if end > self.index.len() as u64 {
+        let committed = self.committed();
+        if end > committed {
             scan = Box::new(
                 scan.chain(
                     self.uncommitted
                         .iter()
-                        .skip(start as usize - min(start as usize, self.index.len() + 1))
-                        .take(end as usize - max(start as usize, self.index.len()) + 1)
+                        .skip((start - min(start, committed + 1)) as usize)
+                        .take((end - max(start - 1, committed)) as usize)
                         .cloned()
                         .map(Ok),
                 ),
@@ -223,17 +601,27 @@ impl Store for Hybrid {
     }
 
     fn size(&self) -> u64 {
-        self.index.iter().next_back().map(|(_, (pos, size))| *pos + *size as u64).unwrap_or(0)
+        match self.segments.lock() {
+            Ok(segments) => {
+                segments.values().filter_map(|f| f.metadata().ok()).map(|m| m.len()).sum()
+            }
+            Err(_) => 0,
+        }
+    }
+
+    fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        Ok(Self::read_snapshot(&self.dir)?.map(|(_, snapshot)| snapshot))
     }
 
     fn truncate(&mut self, index: u64) -> Result<u64> {
-        if index < self.index.len() as u64 {
+        let committed = self.committed();
+        if index < committed {
             return Err(Error::Internal(format!(
                 "Cannot truncate below committed index {}",
-                self.index.len() as u64
+                committed
             )));
         }
-        self.uncommitted.truncate(index as usize - self.index.len());
+        self.uncommitted.truncate((index - committed) as usize);
         Ok(self.len())
     }
 
@@ -243,37 +631,33 @@ impl Store for Hybrid {
 
     fn set_metadata(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.metadata.insert(key.to_vec(), value);
-        self.metadata_file.set_len(0)?;
-        self.metadata_file.seek(SeekFrom::Start(0))?;
-        bincode::serialize_into(&mut self.metadata_file, &self.metadata)?;
-        if self.sync {
-            self.metadata_file.sync_data()?;
-        }
+        let sync = self.sync != SyncMode::Never;
+        let metadata = bincode::serialize(&self.metadata)?;
+        self.metadata_file =
+            self.write_file("raft-metadata", sync, |file| Ok(file.write_all(&metadata)?))?;
         Ok(())
     }
 }
 
 impl Drop for Hybrid {
-    /// Attempt to fsync data on drop, in case we're running without sync.
+    /// Attempt to fsync data on drop, in case we're running without sync or with interval sync.
     fn drop(&mut self) {
         self.metadata_file.sync_all().ok();
-        self.file.lock().map(|f| f.sync_all()).ok();
-    }
-}
-
-struct MutexReader<'a>(MutexGuard<'a, File>);
-
-impl<'a> Read for MutexReader<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.0.read(buf)
+        if let Ok(segments) = self.segments.lock() {
+            segments.values().for_each(|f| {
+                f.sync_all().ok();
+            });
+        }
     }
 }
 
 #[cfg(test)]
 impl super::TestSuite<Hybrid> for Hybrid {
     fn setup() -> Result<Self> {
-        let dir = tempdir::TempDir::new("toydb")?;
-        Hybrid::new(dir.as_ref(), false)
+        // Compaction writes new files, so the directory must outlive the TempDir guard.
+        // Use a tiny segment size, to exercise entries spanning several segments.
+        let dir = tempdir::TempDir::new("toydb")?.into_path();
+        Ok(Hybrid::new(&dir, SyncMode::Never, Compression::None)?.with_segment_size(8))
     }
 }
 
@@ -286,7 +670,7 @@ fn tests() -> Result<()> {
 #[test]
 fn test_persistent() -> Result<()> {
     let dir = tempdir::TempDir::new("toydb")?;
-    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
 
     l.append(vec![0x01])?;
     l.append(vec![0x02])?;
@@ -295,7 +679,7 @@ fn test_persistent() -> Result<()> {
     l.append(vec![0x05])?;
     l.commit(3)?;
 
-    let l = Hybrid::new(dir.as_ref(), true)?;
+    let l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
 
     assert_eq!(
         vec![vec![1], vec![2], vec![3]],
@@ -304,3 +688,299 @@ fn test_persistent() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_compact_persistent() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::Snappy)?;
+
+    let large = b"toydb".repeat(100);
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.append(large.clone())?;
+    l.append(vec![0x04])?;
+    l.append(vec![0x05])?;
+    l.commit(4)?;
+    l.compact(2, vec![0xaa])?;
+    l.append(vec![0x06])?;
+    l.commit(5)?;
+
+    // The compacted index, snapshot, and remaining entries must be retained across restarts,
+    // and uncommitted entries are lost as usual.
+    drop(l);
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+    assert_eq!(2, l.compacted());
+    assert_eq!(5, l.committed());
+    assert_eq!(5, l.len());
+    assert_eq!(Some(vec![0xaa]), l.snapshot()?);
+    assert_eq!(
+        vec![large.clone(), vec![0x04], vec![0x05]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    // Compacting beyond the end of the log also persists.
+    l.compact(7, vec![0xbb])?;
+    drop(l);
+    let l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+    assert_eq!(7, l.compacted());
+    assert_eq!(7, l.len());
+    assert_eq!(Some(vec![0xbb]), l.snapshot()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_torn_write() -> Result<()> {
+    // A crash while appending an entry may leave a partial length prefix or entry at the end of
+    // the file, which should be discarded such that new entries can be appended.
+    for torn in vec![vec![0x00, 0x00], vec![0x00, 0x00, 0x00, 0x03, 0x03]] {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+        l.append(vec![0x01])?;
+        l.append(vec![0x02, 0x02])?;
+        l.commit(2)?;
+        drop(l);
+
+        let mut file =
+            OpenOptions::new().append(true).open(dir.path().join(Hybrid::segment_name(1)))?;
+        file.write_all(&torn)?;
+        drop(file);
+
+        let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+        assert_eq!(2, l.committed());
+        assert_eq!(
+            vec![vec![0x01], vec![0x02, 0x02]],
+            l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        l.append(vec![0x03])?;
+        l.commit(3)?;
+        drop(l);
+
+        let l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+        assert_eq!(
+            vec![vec![0x01], vec![0x02, 0x02], vec![0x03]],
+            l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_segments() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let open = || -> Result<Hybrid> {
+        Ok(Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?.with_segment_size(10))
+    };
+    let segments = || -> Result<Vec<String>> {
+        let mut names = std::fs::read_dir(dir.path())?
+            .map(|e| Ok(e?.file_name().to_string_lossy().to_string()))
+            .filter(|n: &Result<String>| n.as_ref().map_or(true, |n| n.starts_with(SEGMENT_PREFIX)))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    };
+
+    // Each entry takes up 7 bytes, so segments hold 2 entries each.
+    let mut l = open()?;
+    for i in 1..=7 {
+        l.append(vec![i; 3])?;
+    }
+    l.commit(7)?;
+    assert_eq!(
+        vec![
+            Hybrid::segment_name(1),
+            Hybrid::segment_name(3),
+            Hybrid::segment_name(5),
+            Hybrid::segment_name(7)
+        ],
+        segments()?
+    );
+    assert_eq!(49, l.size());
+    assert_eq!(
+        (1..=7).map(|i| vec![i; 3]).collect::<Vec<_>>(),
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    // Compaction deletes segments once all of their entries are compacted, and the remaining
+    // segments are retained across restarts.
+    l.compact(4, vec![0xaa])?;
+    assert_eq!(vec![Hybrid::segment_name(5), Hybrid::segment_name(7)], segments()?);
+    l.compact(5, vec![0xbb])?;
+    assert_eq!(vec![Hybrid::segment_name(5), Hybrid::segment_name(7)], segments()?);
+    drop(l);
+
+    let mut l = open()?;
+    assert_eq!(5, l.compacted());
+    assert_eq!(7, l.committed());
+    assert_eq!(None, l.get(5)?);
+    assert_eq!(Some(vec![6; 3]), l.get(6)?);
+    assert_eq!(vec![vec![6; 3], vec![7; 3]], l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+    l.append(vec![8; 3])?;
+    l.append(vec![9; 3])?;
+    l.commit(9)?;
+    assert_eq!(
+        vec![Hybrid::segment_name(5), Hybrid::segment_name(7), Hybrid::segment_name(9)],
+        segments()?
+    );
+
+    // Compacting all entries deletes all segments, and new segments start after the compacted index.
+    l.compact(10, vec![0xcc])?;
+    assert!(segments()?.is_empty());
+    l.append(vec![11; 3])?;
+    l.commit(11)?;
+    assert_eq!(vec![Hybrid::segment_name(11)], segments()?);
+    drop(l);
+
+    let l = open()?;
+    assert_eq!(10, l.compacted());
+    assert_eq!(vec![vec![11; 3]], l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+    Ok(())
+}
+
+#[test]
+fn test_segments_truncated() -> Result<()> {
+    // A truncated segment is only tolerated at the end of the log.
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l =
+        Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?.with_segment_size(10);
+    for i in 1..=4 {
+        l.append(vec![i; 3])?;
+    }
+    l.commit(4)?;
+    drop(l);
+
+    let file = OpenOptions::new().write(true).open(dir.path().join(Hybrid::segment_name(1)))?;
+    file.set_len(12)?;
+    drop(file);
+    assert!(Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_migrate_legacy() -> Result<()> {
+    // Logs written by previous versions as a single raft-log file are migrated to a segment.
+    let dir = tempdir::TempDir::new("toydb")?;
+    std::fs::write(dir.path().join("raft-log"), vec![0x00, 0x00, 0x00, 0x01, 0x01])?;
+    let l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+    assert_eq!(1, l.committed());
+    assert_eq!(Some(vec![0x01]), l.get(1)?);
+    assert!(!dir.path().join("raft-log").exists());
+    assert!(dir.path().join(Hybrid::segment_name(1)).exists());
+    Ok(())
+}
+
+#[test]
+fn test_metadata_persistent() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+    l.set_metadata(b"a", vec![0x01])?;
+    l.set_metadata(b"b", vec![0x02])?;
+    l.set_metadata(b"a", vec![0x03])?;
+    drop(l);
+
+    // Metadata is written atomically, so a crash while writing it leaves behind a temporary file
+    // which is ignored, rather than a torn metadata file.
+    std::fs::write(dir.path().join("raft-metadata.tmp"), vec![0x01, 0x02])?;
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+    assert_eq!(Some(vec![0x03]), l.get_metadata(b"a")?);
+    assert_eq!(Some(vec![0x02]), l.get_metadata(b"b")?);
+
+    l.set_metadata(b"b", vec![0x04])?;
+    drop(l);
+    let l = Hybrid::new(dir.as_ref(), SyncMode::Always, Compression::None)?;
+    assert_eq!(Some(vec![0x04]), l.get_metadata(b"b")?);
+    assert!(!dir.path().join("raft-metadata.tmp").exists());
+    Ok(())
+}
+
+#[test]
+fn test_compression() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Never, Compression::Snappy)?;
+
+    let large = b"toydb".repeat(100);
+    l.append(vec![0x01])?;
+    l.append(large.clone())?;
+    l.append(vec![0x03])?;
+    l.commit(3)?;
+    assert!(l.size() < large.len() as u64);
+    assert_eq!(l.get(2)?, Some(large.clone()));
+
+    // Compressed entries must be readable after reopening without compression.
+    drop(l);
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Never, Compression::None)?;
+    l.append(large.clone())?;
+    l.commit(4)?;
+    assert_eq!(
+        vec![vec![0x01], large.clone(), vec![0x03], large.clone()],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_commit_too_large() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), SyncMode::Never, Compression::None)?;
+    l.max_entry_size = 4;
+
+    l.append(vec![0x01])?;
+    l.commit(1)?;
+    l.append(vec![0x02])?;
+    l.append(vec![0x03; 5])?;
+    assert_eq!(l.commit(3), Err(Error::Internal("Log entry too large (5 bytes)".into())));
+
+    // The failed commit doesn't commit any of the entries, nor lose them.
+    assert_eq!(l.committed(), 1);
+    assert_eq!(l.len(), 3);
+    assert_eq!(l.get(2)?, Some(vec![0x02]));
+    assert_eq!(l.get(3)?, Some(vec![0x03; 5]));
+
+    // The log can be committed, truncated, and appended to as usual.
+    l.commit(2)?;
+    assert_eq!(l.truncate(2)?, 2);
+    l.append(vec![0x04])?;
+    l.commit(3)?;
+    drop(l);
+    let l = Hybrid::new(dir.as_ref(), SyncMode::Never, Compression::None)?;
+    assert_eq!(
+        vec![vec![0x01], vec![0x02], vec![0x04]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_commit_write_error() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l =
+        Hybrid::new(dir.as_ref(), SyncMode::Never, Compression::None)?.with_segment_size(16);
+    l.append(vec![0x01])?;
+    l.commit(1)?;
+    assert_eq!(l.size(), 5);
+
+    // Entry 2 is written to the first segment, but the segment for entry 3 can't be created
+    // since the directory is gone. The commit fails without committing or losing any entries,
+    // and the partially written segment is truncated.
+    l.append(vec![0x02; 8])?;
+    l.append(vec![0x03])?;
+    std::fs::remove_dir_all(dir.as_ref())?;
+    assert!(l.commit(3).is_err());
+    assert_eq!(l.committed(), 1);
+    assert_eq!(l.len(), 3);
+    assert_eq!(l.size(), 5);
+    assert_eq!(l.get(2)?, Some(vec![0x02; 8]));
+
+    // Once the directory is back, the commit can be retried.
+    std::fs::create_dir_all(dir.as_ref())?;
+    l.commit(3)?;
+    assert_eq!(l.committed(), 3);
+    assert_eq!(
+        vec![vec![0x01], vec![0x02; 8], vec![0x03]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    Ok(())
+}
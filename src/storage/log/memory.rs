@@ -1,6 +1,7 @@
 use super::{Range, Store};
 use crate::error::{Error, Result};
 
+use std::cmp::max;
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::Bound;
@@ -9,13 +10,21 @@ use std::ops::Bound;
 pub struct Memory {
     log: Vec<Vec<u8>>,
     committed: u64,
+    compacted: u64,
+    snapshot: Option<Vec<u8>>,
     metadata: HashMap<Vec<u8>, Vec<u8>>,
 }
 
 impl Memory {
     /// Creates a new in-memory log.
     pub fn new() -> Self {
-        Self { log: Vec::new(), committed: 0, metadata: HashMap::new() }
+        Self {
+            log: Vec::new(),
+            committed: 0,
+            compacted: 0,
+            snapshot: None,
+            metadata: HashMap::new(),
+        }
     }
 }
 
@@ -28,7 +37,7 @@ impl Display for Memory {
 impl Store for Memory {
     fn append(&mut self, entry: Vec<u8>) -> Result<u64> {
         self.log.push(entry);
-        Ok(self.log.len() as u64)
+        Ok(self.len())
     }
 
     fn commit(&mut self, index: u64) -> Result<()> {
@@ -49,33 +58,62 @@ impl Store for Memory {
         self.committed
     }
 
+    fn compact(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        if index < self.compacted {
+            return Err(Error::Internal(format!(
+                "Cannot compact below compacted index {}",
+                self.compacted
+            )));
+        }
+        self.snapshot = Some(snapshot);
+        if index >= self.len() {
+            self.log.clear();
+        } else {
+            self.log.drain(..(index - self.compacted) as usize);
+        }
+        self.compacted = index;
+        self.committed = max(self.committed, index);
+        Ok(())
+    }
+
+    fn compacted(&self) -> u64 {
+        self.compacted
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
         match index {
-            0 => Ok(None),
-            i => Ok(self.log.get(i as usize - 1).cloned()),
+            i if i <= self.compacted => Ok(None),
+            i => Ok(self.log.get((i - self.compacted) as usize - 1).cloned()),
         }
     }
 
     fn len(&self) -> u64 {
-        self.log.len() as u64
+        self.compacted + self.log.len() as u64
     }
 
     fn scan(&self, range: Range) -> super::Scan {
+        // Convert the range into absolute start and exclusive end indexes, then to positions in
+        // the log vector which begins after the compacted index.
+        let start = match range.start {
+            Bound::Included(n) => n,
+            Bound::Excluded(n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end {
+            Bound::Included(n) => n.saturating_add(1),
+            Bound::Excluded(n) => n,
+            Bound::Unbounded => std::u64::MAX,
+        };
+        let first = self.compacted + 1;
         Box::new(
             self.log
                 .iter()
-                .take(match range.end {
-                    Bound::Included(n) => n as usize,
-                    Bound::Excluded(0) => 0,
-                    Bound::Excluded(n) => n as usize - 1,
-                    Bound::Unbounded => std::usize::MAX,
-                })
-                .skip(match range.start {
-                    Bound::Included(0) => 0,
-                    Bound::Included(n) => n as usize - 1,
-                    Bound::Excluded(n) => n as usize,
-                    Bound::Unbounded => 0,
-                })
+                .skip(start.saturating_sub(first) as usize)
+                .take(end.saturating_sub(max(start, first)) as usize)
                 .cloned()
                 .map(Ok),
         )
@@ -85,6 +123,10 @@ impl Store for Memory {
         self.log.iter().map(|v| v.len() as u64).sum()
     }
 
+    fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.snapshot.clone())
+    }
+
     fn truncate(&mut self, index: u64) -> Result<u64> {
         if index < self.committed {
             return Err(Error::Internal(format!(
@@ -92,8 +134,8 @@ impl Store for Memory {
                 self.committed
             )));
         }
-        self.log.truncate(index as usize);
-        Ok(self.log.len() as u64)
+        self.log.truncate((index - self.compacted) as usize);
+        Ok(self.len())
     }
 
     fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -1,8 +1,12 @@
+mod async_store;
+mod fault;
 mod hybrid;
 mod memory;
 #[cfg(test)]
 mod test;
 
+pub use async_store::AsyncStore;
+pub use fault::Faulty;
 pub use hybrid::Hybrid;
 pub use memory::Memory;
 #[cfg(test)]
@@ -24,6 +28,20 @@ pub trait Store: Display + Sync + Send {
     /// Returns the committed index, if any.
     fn committed(&self) -> u64;
 
+    /// Compacts the log by removing all entries up to and including the given index, replacing
+    /// them with the given snapshot, which is persisted before any entries are removed. Later
+    /// entries retain their indexes. If the index is beyond the committed index it becomes
+    /// committed, and if it is beyond the end of the log all entries are removed such that the
+    /// next entry is appended at index+1. Errors if the index is below the compacted index.
+    fn compact(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()>;
+
+    /// Returns the index of the last compacted entry, or 0 if the log has not been compacted.
+    fn compacted(&self) -> u64;
+
+    /// Flushes any buffered data, including committed entries and metadata, to the underlying
+    /// storage medium.
+    fn flush(&mut self) -> Result<()>;
+
     /// Fetches a log entry, if it exists.
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>>;
 
@@ -36,6 +54,9 @@ pub trait Store: Display + Sync + Send {
     /// Returns the size of the log, in bytes.
     fn size(&self) -> u64;
 
+    /// Fetches the snapshot stored by the last compaction, if any.
+    fn snapshot(&self) -> Result<Option<Vec<u8>>>;
+
     /// Truncates the log be removing any entries above the given index, and returns the
     /// highest index. Errors if asked to truncate any committed entries.
     fn truncate(&mut self, index: u64) -> Result<u64>;
@@ -90,6 +111,7 @@ trait TestSuite<S: Store> {
     fn test() -> Result<()> {
         Self::test_append()?;
         Self::test_commit_truncate()?;
+        Self::test_compact()?;
         Self::test_get()?;
         Self::test_metadata()?;
         Self::test_scan()?;
@@ -144,6 +166,64 @@ trait TestSuite<S: Store> {
         Ok(())
     }
 
+    fn test_compact() -> Result<()> {
+        let mut s = Self::setup()?;
+        assert_eq!(0, s.compacted());
+        assert_eq!(None, s.snapshot()?);
+
+        s.append(vec![0x01])?;
+        s.append(vec![0x02])?;
+        s.append(vec![0x03])?;
+        s.append(vec![0x04])?;
+        s.commit(3)?;
+
+        // Compacting removes entries, but retains the indexes of later entries.
+        s.compact(2, vec![0xaa])?;
+        assert_eq!(2, s.compacted());
+        assert_eq!(Some(vec![0xaa]), s.snapshot()?);
+        assert_eq!(3, s.committed());
+        assert_eq!(4, s.len());
+        assert_eq!(None, s.get(1)?);
+        assert_eq!(None, s.get(2)?);
+        assert_eq!(Some(vec![0x03]), s.get(3)?);
+        assert_eq!(vec![vec![3], vec![4]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+        assert_eq!(vec![vec![3]], s.scan(Range::from(1..=3)).collect::<Result<Vec<_>>>()?);
+
+        // Compacting below the compacted index errors.
+        assert_eq!(
+            Err(Error::Internal("Cannot compact below compacted index 2".into())),
+            s.compact(1, vec![0xbb])
+        );
+
+        // Appending, committing, and truncating works as normal.
+        assert_eq!(5, s.append(vec![0x05])?);
+        s.commit(4)?;
+        assert_eq!(4, s.truncate(4)?);
+        assert_eq!(vec![vec![3], vec![4]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+
+        // Compacting beyond the committed index commits it, retaining later entries.
+        s.append(vec![0x05])?;
+        s.append(vec![0x06])?;
+        s.compact(5, vec![0xbb])?;
+        assert_eq!(5, s.compacted());
+        assert_eq!(5, s.committed());
+        assert_eq!(6, s.len());
+        assert_eq!(vec![vec![6]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+
+        // Compacting beyond the end of the log empties it.
+        s.compact(8, vec![0xcc])?;
+        assert_eq!(8, s.compacted());
+        assert_eq!(8, s.committed());
+        assert_eq!(8, s.len());
+        assert_eq!(Some(vec![0xcc]), s.snapshot()?);
+        assert!(s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?.is_empty());
+        assert_eq!(9, s.append(vec![0x09])?);
+        s.commit(9)?;
+        assert_eq!(Some(vec![0x09]), s.get(9)?);
+
+        Ok(())
+    }
+
     fn test_get() -> Result<()> {
         let mut s = Self::setup()?;
         s.append(vec![0x01])?;
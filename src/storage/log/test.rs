@@ -37,6 +37,18 @@ impl Store for Test {
         self.store.read().unwrap().committed()
     }
 
+    fn compact(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        self.store.write()?.compact(index, snapshot)
+    }
+
+    fn compacted(&self) -> u64 {
+        self.store.read().unwrap().compacted()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.store.write()?.flush()
+    }
+
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
         self.store.read()?.get(index)
     }
@@ -54,6 +66,10 @@ impl Store for Test {
         self.store.read().unwrap().size()
     }
 
+    fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        self.store.read()?.snapshot()
+    }
+
     fn truncate(&mut self, index: u64) -> Result<u64> {
         self.store.write()?.truncate(index)
     }
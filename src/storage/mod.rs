@@ -1,2 +1,125 @@
 pub mod kv;
-pub mod log;
\ No newline at end of file
+pub mod log;
+mod registry;
+
+pub use registry::{Options, Registry};
+
+use crate::error::{Error, Result};
+
+use std::time::Duration;
+
+/// Controls how storage engines fsync writes to disk, trading durability for performance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncMode {
+    /// Fsync after every write. Guarantees that acknowledged writes are persisted.
+    Always,
+    /// Fsync on the next write once the given interval has elapsed since the last fsync. Writes
+    /// made since the last fsync can be lost if the machine crashes.
+    Interval(Duration),
+    /// Never fsync explicitly, leaving it to the operating system (except on close).
+    Never,
+}
+
+impl SyncMode {
+    /// Parses a sync mode from a configuration string (always, interval, or never), using the
+    /// given interval for interval mode. For backwards compatibility, true and false are accepted
+    /// as aliases for always and never.
+    pub fn parse(mode: &str, interval: Duration) -> Result<Self> {
+        match mode.to_lowercase().as_str() {
+            "always" | "true" => Ok(Self::Always),
+            "interval" => Ok(Self::Interval(interval)),
+            "never" | "false" => Ok(Self::Never),
+            mode => Err(Error::Config(format!("Invalid sync mode {}", mode))),
+        }
+    }
+}
+
+impl std::fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Always => write!(f, "always"),
+            Self::Interval(interval) => write!(f, "interval ({}ms)", interval.as_millis()),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// A value compression algorithm, used by storage engines to compress stored values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// No compression.
+    None,
+    /// Snappy compression, which is fast but gives moderate compression ratios.
+    Snappy,
+}
+
+impl Compression {
+    /// Parses a compression algorithm from a configuration string (none or snappy).
+    pub fn parse(compression: &str) -> Result<Self> {
+        match compression.to_lowercase().as_str() {
+            "none" | "" => Ok(Self::None),
+            "snappy" => Ok(Self::Snappy),
+            compression => Err(Error::Config(format!("Invalid compression {}", compression))),
+        }
+    }
+
+    /// Compresses a value. Returns None if the value should be stored uncompressed, i.e. if
+    /// compression is disabled or the compressed value would not be smaller.
+    pub fn compress(&self, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::None => Ok(None),
+            Self::Snappy => {
+                let compressed = snap::raw::Encoder::new().compress_vec(value)?;
+                Ok(Some(compressed).filter(|c| c.len() < value.len()))
+            }
+        }
+    }
+
+    /// Decompresses a value compressed with this algorithm.
+    pub fn decompress(&self, value: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Err(Error::Internal("Can't decompress without compression".into())),
+            Self::Snappy => Ok(snap::raw::Decoder::new().decompress_vec(value)?),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Snappy => write!(f, "snappy"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_mode_parse() -> Result<()> {
+        let interval = Duration::from_millis(100);
+        assert_eq!(SyncMode::parse("always", interval)?, SyncMode::Always);
+        assert_eq!(SyncMode::parse("TRUE", interval)?, SyncMode::Always);
+        assert_eq!(SyncMode::parse("interval", interval)?, SyncMode::Interval(interval));
+        assert_eq!(SyncMode::parse("never", interval)?, SyncMode::Never);
+        assert_eq!(SyncMode::parse("false", interval)?, SyncMode::Never);
+        assert!(SyncMode::parse("sometimes", interval).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compression() -> Result<()> {
+        let value = b"abcdefgh".repeat(64);
+        assert_eq!(Compression::None.compress(&value)?, None);
+
+        let compressed = Compression::Snappy.compress(&value)?.unwrap();
+        assert!(compressed.len() < value.len());
+        assert_eq!(Compression::Snappy.decompress(&compressed)?, value);
+
+        // Incompressible values are left uncompressed.
+        assert_eq!(Compression::Snappy.compress(b"abc")?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use super::{kv, log, Compression, SyncMode};
+use crate::error::{Error, Result};
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Options for opening a storage backend.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The data directory. Backends store their files here.
+    pub dir: PathBuf,
+    /// When to fsync writes to disk.
+    pub sync: SyncMode,
+    /// Compression for values written to disk.
+    pub compression: Compression,
+    /// Backend-specific options, as name/value pairs.
+    pub options: HashMap<String, String>,
+}
+
+impl Options {
+    /// Creates a new set of options with no backend-specific options.
+    pub fn new(dir: PathBuf, sync: SyncMode, compression: Compression) -> Self {
+        Self { dir, sync, compression, options: HashMap::new() }
+    }
+
+    /// Sets the backend-specific options.
+    pub fn with_options(mut self, options: HashMap<String, String>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Parses a backend-specific option, if given.
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>> {
+        self.options
+            .get(name)
+            .map(|v| {
+                v.parse().map_err(|_| Error::Config(format!("Invalid value {} for {}", v, name)))
+            })
+            .transpose()
+    }
+}
+
+/// A storage backend, which opens a store with the given options.
+struct Backend<S: ?Sized> {
+    /// The backend-specific options it accepts.
+    options: &'static [&'static str],
+    /// Opens the store.
+    open: fn(&Options) -> Result<Box<S>>,
+}
+
+/// A registry of storage backends, which can be opened by name. Registry::default() contains all
+/// built-in backends, including those enabled via crate features.
+pub struct Registry {
+    kv: BTreeMap<String, Backend<dyn kv::Store>>,
+    log: BTreeMap<String, Backend<dyn log::Store>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register_log("hybrid", &["segment_size"], |o| {
+            let mut store = log::Hybrid::new(&o.dir, o.sync, o.compression)?;
+            if let Some(segment_size) = o.get("segment_size")? {
+                store = store.with_segment_size(segment_size);
+            }
+            Ok(Box::new(store))
+        });
+        registry.register_log("memory", &[], |_| Ok(Box::new(log::Memory::new())));
+        registry.register_kv("memory", &[], |_| Ok(Box::new(kv::Memory::new())));
+        registry.register_kv("stdmemory", &[], |_| Ok(Box::new(kv::StdMemory::new())));
+        #[cfg(feature = "sled")]
+        registry.register_kv("sled", &["cache_capacity"], |o| {
+            Ok(Box::new(kv::Sled::new(&o.dir.join("sled"), o.sync, o.get("cache_capacity")?)?))
+        });
+        registry
+    }
+}
+
+impl Registry {
+    /// Creates a new, empty registry.
+    pub fn new() -> Self {
+        Self { kv: BTreeMap::new(), log: BTreeMap::new() }
+    }
+
+    /// Registers a key/value storage backend, replacing any existing backend with the same name.
+    pub fn register_kv(
+        &mut self,
+        name: &str,
+        options: &'static [&'static str],
+        open: fn(&Options) -> Result<Box<dyn kv::Store>>,
+    ) {
+        self.kv.insert(name.to_string(), Backend { options, open });
+    }
+
+    /// Registers a log storage backend, replacing any existing backend with the same name.
+    pub fn register_log(
+        &mut self,
+        name: &str,
+        options: &'static [&'static str],
+        open: fn(&Options) -> Result<Box<dyn log::Store>>,
+    ) {
+        self.log.insert(name.to_string(), Backend { options, open });
+    }
+
+    /// Returns the names of the registered key/value storage backends.
+    pub fn kv_backends(&self) -> Vec<&str> {
+        self.kv.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Returns the names of the registered log storage backends.
+    pub fn log_backends(&self) -> Vec<&str> {
+        self.log.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Opens a key/value store using the named backend, compressing its values with the given
+    /// compression. Errors if compression is enabled for an existing store written without it.
+    pub fn open_kv(&self, name: &str, options: &Options) -> Result<Box<dyn kv::Store>> {
+        let store = Self::open(&self.kv, "key/value", name, options)?;
+        Ok(Box::new(kv::Compressed::new(store, options.compression)?))
+    }
+
+    /// Opens a log store using the named backend.
+    pub fn open_log(&self, name: &str, options: &Options) -> Result<Box<dyn log::Store>> {
+        Self::open(&self.log, "log", name, options)
+    }
+
+    /// Opens a store using a named backend, checking that all given options are supported.
+    fn open<S: ?Sized>(
+        backends: &BTreeMap<String, Backend<S>>,
+        kind: &str,
+        name: &str,
+        options: &Options,
+    ) -> Result<Box<S>> {
+        let backend = backends.get(name).ok_or_else(|| {
+            Error::Config(format!(
+                "Unknown {} storage backend {}, must be one of: {}",
+                kind,
+                name,
+                backends.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        if let Some(option) =
+            options.options.keys().find(|o| !backend.options.contains(&o.as_str()))
+        {
+            return Err(Error::Config(format!(
+                "Unknown option {} for {} storage backend {}",
+                option, kind, name
+            )));
+        }
+        (backend.open)(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::Store;
+
+    fn options() -> Options {
+        Options::new(PathBuf::new(), SyncMode::Never, Compression::None)
+    }
+
+    #[test]
+    fn open() -> Result<()> {
+        let registry = Registry::default();
+        assert_eq!("memory", registry.open_kv("memory", &options())?.to_string());
+        assert_eq!("stdmemory", registry.open_kv("stdmemory", &options())?.to_string());
+        assert_eq!("memory", registry.open_log("memory", &options())?.to_string());
+        assert!(registry.open_kv("unknown", &options()).is_err());
+        assert!(registry.open_log("stdmemory", &options()).is_err());
+
+        let snappy = Options { compression: Compression::Snappy, ..options() };
+        assert_eq!("memory (snappy)", registry.open_kv("memory", &snappy)?.to_string());
+
+        let mut opts = HashMap::new();
+        opts.insert("unknown".to_string(), "1".to_string());
+        assert!(registry.open_kv("memory", &options().with_options(opts)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn open_kv_uncompressed() -> Result<()> {
+        // Values written without the compression wrapper have no tag byte, and must be read as is.
+        let mut registry = Registry::new();
+        registry.register_kv("existing", &[], |_| {
+            let mut store = kv::StdMemory::new();
+            store.set(b"a", vec![0x01, 0x02])?;
+            store.set(b"b", vec![])?;
+            Ok(Box::new(store))
+        });
+        let store = registry.open_kv("existing", &options())?;
+        assert_eq!(Some(vec![0x01, 0x02]), store.get(b"a")?);
+        assert_eq!(Some(vec![]), store.get(b"b")?);
+
+        // Enabling compression for them is an error, rather than misreading their values.
+        let snappy = Options { compression: Compression::Snappy, ..options() };
+        assert!(matches!(registry.open_kv("existing", &snappy), Err(Error::Config(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn register() -> Result<()> {
+        let mut registry = Registry::new();
+        assert!(registry.kv_backends().is_empty());
+        registry.register_kv("custom", &["size"], |o| {
+            assert_eq!(Some(7), o.get::<u64>("size")?);
+            Ok(Box::new(kv::StdMemory::new()))
+        });
+        assert_eq!(vec!["custom"], registry.kv_backends());
+
+        let mut opts = HashMap::new();
+        opts.insert("size".to_string(), "7".to_string());
+        registry.open_kv("custom", &options().with_options(opts.clone()))?;
+
+        opts.insert("size".to_string(), "x".to_string());
+        assert!(registry.open_kv("custom", &options().with_options(opts)).is_err());
+        Ok(())
+    }
+}
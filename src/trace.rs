@@ -0,0 +1,124 @@
+//! Query tracing. Clients can attach a trace context to queries, which the server propagates
+//! through parsing, planning, optimization, Raft proposals, and execution. With the trace feature
+//! enabled, each stage is recorded as a tracing span nested under a query span tagged with the
+//! trace context, such that slow queries can be followed end-to-end. Without it, spans compile
+//! to nothing and the trace context is ignored.
+
+use crate::error::{Error, Result};
+
+use rand::Rng as _;
+use serde_derive::{Deserialize, Serialize};
+
+/// Evaluates an expression within a tracing span with the given name and optional fields, if the
+/// trace feature is enabled, e.g. span!("parse", (bytes = query.len()); parse(query)). Otherwise,
+/// the expression is simply evaluated.
+macro_rules! span {
+    ($name:literal; $body:expr) => {{
+        #[cfg(feature = "trace")]
+        let _span = tracing::info_span!($name).entered();
+        $body
+    }};
+    ($name:literal, ($($field:tt)+); $body:expr) => {{
+        #[cfg(feature = "trace")]
+        let _span = tracing::info_span!($name, $($field)+).entered();
+        $body
+    }};
+}
+
+pub(crate) use span;
+
+/// A trace context, as given by a W3C Trace Context traceparent header.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceContext {
+    /// The trace ID, shared by all spans in the trace.
+    pub trace_id: [u8; 16],
+    /// The ID of the client's span that the query belongs to.
+    pub span_id: [u8; 8],
+}
+
+impl TraceContext {
+    /// Creates a new trace context with random IDs.
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        Self { trace_id: rng.gen(), span_id: rng.gen() }
+    }
+
+    /// Parses a traceparent header, e.g. 00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01.
+    /// Only version 00 is supported, and the trace flags are ignored.
+    pub fn parse(traceparent: &str) -> Result<Self> {
+        let invalid = || Error::Parse(format!("Invalid traceparent {}", traceparent));
+        let parts = traceparent.split('-').collect::<Vec<_>>();
+        if parts.len() != 4 || parts[0] != "00" || parts[3].len() != 2 {
+            return Err(invalid());
+        }
+        let mut context = Self { trace_id: [0; 16], span_id: [0; 8] };
+        Self::decode_hex(parts[1], &mut context.trace_id).ok_or_else(invalid)?;
+        Self::decode_hex(parts[2], &mut context.span_id).ok_or_else(invalid)?;
+        if context.trace_id == [0; 16] || context.span_id == [0; 8] {
+            return Err(invalid());
+        }
+        Ok(context)
+    }
+
+    /// Returns the trace ID as a hex string.
+    pub fn trace_id_hex(&self) -> String {
+        Self::encode_hex(&self.trace_id)
+    }
+
+    /// Returns the span ID as a hex string.
+    pub fn span_id_hex(&self) -> String {
+        Self::encode_hex(&self.span_id)
+    }
+
+    /// Decodes a hex string into the given buffer, which must match its length.
+    fn decode_hex(hex: &str, buf: &mut [u8]) -> Option<()> {
+        if hex.len() != buf.len() * 2 {
+            return None;
+        }
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(())
+    }
+
+    /// Encodes bytes as a lowercase hex string.
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl std::fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "00-{}-{}-01", self.trace_id_hex(), self.span_id_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let context = TraceContext::parse(traceparent)?;
+        assert_eq!(context.trace_id_hex(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(context.span_id_hex(), "b7ad6b7169203331");
+        assert_eq!(context.to_string(), traceparent);
+
+        let context = TraceContext::new();
+        assert_eq!(TraceContext::parse(&context.to_string())?, context);
+
+        for invalid in &[
+            "",
+            "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319x-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+        ] {
+            assert!(TraceContext::parse(invalid).is_err(), "{} should be invalid", invalid);
+        }
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@ use toydb::error::{Error, Result};
 use toydb::raft;
 use toydb::sql::engine::{Mode, Status};
 use toydb::sql::execution::ResultSet;
+use toydb::sql::functions::{self, ScalarFunction};
 use toydb::sql::schema;
 use toydb::sql::types::{Column, DataType, Value};
 use toydb::storage::kv;
@@ -13,6 +14,7 @@ use toydb::Client;
 
 use pretty_assertions::assert_eq;
 use serial_test::serial;
+use std::collections::BTreeSet;
 
 #[tokio::test(core_threads = 2)]
 #[serial]
@@ -26,79 +28,113 @@ async fn get_table() -> Result<()> {
     assert_eq!(
         c.get_table("movies").await?,
         schema::Table {
+            id: 4,
             name: "movies".into(),
             columns: vec![
                 schema::Column {
+                    id: 1,
                     name: "id".into(),
                     datatype: DataType::Integer,
                     primary_key: true,
                     nullable: false,
                     default: None,
+                    missing: None,
                     unique: true,
                     index: false,
                     references: None,
+                    references_id: None,
+                    comment: None,
                 },
                 schema::Column {
+                    id: 2,
                     name: "title".into(),
                     datatype: DataType::String,
                     primary_key: false,
                     nullable: false,
                     default: None,
+                    missing: None,
                     unique: false,
                     index: false,
                     references: None,
+                    references_id: None,
+                    comment: None,
                 },
                 schema::Column {
+                    id: 3,
                     name: "studio_id".into(),
                     datatype: DataType::Integer,
                     primary_key: false,
                     nullable: false,
                     default: None,
+                    missing: None,
                     unique: false,
                     index: false,
                     references: Some("studios".into()),
+                    references_id: Some(3),
+                    comment: None,
                 },
                 schema::Column {
+                    id: 4,
                     name: "genre_id".into(),
                     datatype: DataType::Integer,
                     primary_key: false,
                     nullable: false,
                     default: None,
+                    missing: None,
                     unique: false,
                     index: false,
                     references: Some("genres".into()),
+                    references_id: Some(2),
+                    comment: None,
                 },
                 schema::Column {
+                    id: 5,
                     name: "released".into(),
                     datatype: DataType::Integer,
                     primary_key: false,
                     nullable: false,
                     default: None,
+                    missing: None,
                     unique: false,
                     index: false,
                     references: None,
+                    references_id: None,
+                    comment: None,
                 },
                 schema::Column {
+                    id: 6,
                     name: "rating".into(),
                     datatype: DataType::Float,
                     primary_key: false,
                     nullable: true,
                     default: Some(Value::Null),
+                    missing: None,
                     unique: false,
                     index: false,
                     references: None,
+                    references_id: None,
+                    comment: None,
                 },
                 schema::Column {
+                    id: 7,
                     name: "ultrahd".into(),
                     datatype: DataType::Boolean,
                     primary_key: false,
                     nullable: true,
                     default: Some(Value::Null),
+                    missing: None,
                     unique: false,
                     index: false,
                     references: None,
+                    references_id: None,
+                    comment: None,
                 },
-            ]
+            ],
+            change: None,
+            comment: None,
+            partitions: Vec::new(),
+            owner: None,
+            policies: Vec::new(),
         }
     );
     Ok(())
@@ -123,20 +159,66 @@ async fn status() -> Result<()> {
         Status {
             raft: raft::Status {
                 server: "test".into(),
+                role: "leader".into(),
+                elections: 0,
                 leader: "test".into(),
                 term: 0,
-                node_last_index: vec![("test".to_string(), 26)].into_iter().collect(),
-                commit_index: 26,
-                apply_index: 26,
+                node_last_index: vec![("test".to_string(), 27)].into_iter().collect(),
+                node_next_index: vec![("test".to_string(), 28)].into_iter().collect(),
+                commit_index: 27,
+                apply_index: 27,
                 storage: "hybrid".into(),
-                storage_size: 3239,
+                storage_size: 3606,
+                draining: BTreeSet::new(),
+                nodes: vec![(
+                    "test".to_string(),
+                    raft::NodeStatus {
+                        role: "leader".into(),
+                        last_index: 27,
+                        apply_index: 27,
+                        last_seen: Some(0),
+                        lag_entries: 0,
+                        lag_millis: 0,
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            },
+            mvcc: kv::mvcc::Status {
+                txns: 1,
+                txns_active: 0,
+                txn_updates: 0,
+                storage: "memory".into(),
+                stats: kv::mvcc::Stats { keys: 34, bytes: 3496, versions: 34, dead_versions: 0 },
             },
-            mvcc: kv::mvcc::Status { txns: 1, txns_active: 0, storage: "memory".into() },
         }
     );
     Ok(())
 }
 
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn system_raft() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    assert_rows(
+        c.execute("SELECT * FROM system.raft").await?,
+        vec![vec![
+            Value::String("test".into()),
+            Value::String("leader".into()),
+            Value::Integer(0),
+            Value::Integer(29),
+            Value::Integer(28),
+            Value::Integer(0),
+            Value::Integer(28),
+            Value::Integer(0),
+            Value::Integer(0),
+        ]],
+    );
+    assert_rows(c.execute("SELECT * FROM system.raft WHERE lag > 0").await?, vec![]);
+    Ok(())
+}
+
 #[tokio::test(core_threads = 2)]
 #[serial]
 async fn execute() -> Result<()> {
@@ -147,7 +229,18 @@ async fn execute() -> Result<()> {
     assert_eq!(
         result,
         ResultSet::Query {
-            columns: vec![Column { name: Some("id".into()) }, Column { name: Some("name".into()) }],
+            columns: vec![
+                Column {
+                    name: Some("id".into()),
+                    table: Some("genres".into()),
+                    datatype: Some(DataType::Integer),
+                },
+                Column {
+                    name: Some("name".into()),
+                    table: Some("genres".into()),
+                    datatype: Some(DataType::String),
+                },
+            ],
             rows: Box::new(std::iter::empty()),
         }
     );
@@ -164,12 +257,52 @@ async fn execute() -> Result<()> {
     assert_eq!(
         result,
         ResultSet::Query {
-            columns: vec![Column { name: Some("id".into()) }, Column { name: Some("name".into()) }],
+            columns: vec![
+                Column {
+                    name: Some("id".into()),
+                    table: Some("genres".into()),
+                    datatype: Some(DataType::Integer),
+                },
+                Column {
+                    name: Some("name".into()),
+                    table: Some("genres".into()),
+                    datatype: Some(DataType::String),
+                },
+            ],
             rows: Box::new(std::iter::empty()),
         }
     );
     assert_rows(result, Vec::new());
 
+    // Projections keep the origin table and datatype of column references, and infer the
+    // datatype of computed columns, which are named after the function or expression.
+    let result = c
+        .execute("SELECT g.name AS genre, g.id * 1.5, COUNT(*) FROM genres g GROUP BY g.name, g.id")
+        .await?;
+    assert_eq!(
+        result,
+        ResultSet::Query {
+            columns: vec![
+                Column {
+                    name: Some("genre".into()),
+                    table: Some("genres".into()),
+                    datatype: Some(DataType::String),
+                },
+                Column {
+                    name: Some("g.id * 1.5".into()),
+                    table: None,
+                    datatype: Some(DataType::Float),
+                },
+                Column {
+                    name: Some("count".into()),
+                    table: None,
+                    datatype: Some(DataType::Integer)
+                },
+            ],
+            rows: Box::new(std::iter::empty()),
+        }
+    );
+
     assert_eq!(
         c.execute("SELECT * FROM x").await,
         Err(Error::Value("Table x does not exist".into()))
@@ -178,7 +311,7 @@ async fn execute() -> Result<()> {
     // INSERT
     assert_eq!(
         c.execute("INSERT INTO genres VALUES (1, 'Western')").await,
-        Err(Error::Value("Primary key 1 already exists for table genres".into())),
+        Err(Error::Constraint("Primary key 1 already exists for table genres".into())),
     );
     assert_eq!(
         c.execute("INSERT INTO genres VALUES (9, 'Western')").await,
@@ -200,7 +333,7 @@ async fn execute() -> Result<()> {
     );
     assert_eq!(
         c.execute("UPDATE genres SET id = 1 WHERE id = 9").await,
-        Err(Error::Value("Primary key 1 already exists for table genres".into()))
+        Err(Error::Constraint("Primary key 1 already exists for table genres".into()))
     );
 
     // DELETE
@@ -220,6 +353,417 @@ async fn execute() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_batch() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    let mut results = c
+        .execute_batch(&[
+            "BEGIN",
+            "INSERT INTO genres VALUES (9, 'Western')",
+            "SELECT name FROM genres WHERE id = 9",
+            "COMMIT",
+        ])
+        .await?
+        .into_iter();
+    assert!(matches!(results.next(), Some(Ok(ResultSet::Begin { .. }))));
+    assert_eq!(results.next(), Some(Ok(ResultSet::Create { count: 1 })));
+    assert_rows(results.next().unwrap()?, vec![vec![Value::String("Western".into())]]);
+    assert!(matches!(results.next(), Some(Ok(ResultSet::Commit { .. }))));
+    assert!(results.next().is_none());
+    assert_eq!(c.txn(), None);
+
+    // Execution stops at the first error.
+    let results = c
+        .execute_batch(&[
+            "INSERT INTO genres VALUES (10, 'Horror')",
+            "INSERT INTO genres VALUES (1, 'Drama')",
+            "INSERT INTO genres VALUES (11, 'Drama')",
+        ])
+        .await?;
+    assert_eq!(
+        results,
+        vec![
+            Ok(ResultSet::Create { count: 1 }),
+            Err(Error::Constraint("Primary key 1 already exists for table genres".into())),
+        ]
+    );
+    assert_rows(
+        c.execute("SELECT id FROM genres WHERE id >= 9").await?,
+        vec![vec![Value::Integer(9)], vec![Value::Integer(10)]],
+    );
+
+    // Queries can contain several statements, which each return a result.
+    let mut results = c
+        .execute_batch(&[
+            "BEGIN; INSERT INTO genres VALUES (12, 'Horror'), (13, 'Comedy');",
+            "SELECT name FROM genres WHERE id >= 12 ORDER BY id; COMMIT",
+        ])
+        .await?
+        .into_iter();
+    assert!(matches!(results.next(), Some(Ok(ResultSet::Begin { .. }))));
+    assert_eq!(results.next(), Some(Ok(ResultSet::Create { count: 2 })));
+    assert_rows(
+        results.next().unwrap()?,
+        vec![vec![Value::String("Horror".into())], vec![Value::String("Comedy".into())]],
+    );
+    assert!(matches!(results.next(), Some(Ok(ResultSet::Commit { .. }))));
+    assert!(results.next().is_none());
+    assert_eq!(c.txn(), None);
+
+    // The client tracks transactions started in a batch.
+    c.execute_batch(&["BEGIN READ ONLY"]).await?;
+    assert!(matches!(c.txn(), Some((_, Mode::ReadOnly))));
+    c.execute("ROLLBACK").await?;
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn ingest() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::simple()).await?;
+    let rows = |ids: std::ops::Range<i64>| -> Vec<Vec<Value>> {
+        ids.map(|id| vec![Value::Integer(id), Value::String(format!("v{}", id))]).collect()
+    };
+
+    // Rows are replicated in chunks of 1000, along with the begin and commit.
+    let index = c.status().await?.raft.commit_index;
+    assert_eq!(c.ingest("test", rows(0..2500)).await?, 2500);
+    assert_eq!(c.status().await?.raft.commit_index, index + 5);
+    assert_rows(
+        c.execute("SELECT COUNT(*), MAX(value) FROM test").await?,
+        vec![vec![Value::Integer(2500), Value::String("v999".into())]],
+    );
+
+    // A failed load doesn't load any rows.
+    assert_eq!(
+        c.ingest("test", rows(2400..2600)).await,
+        Err(Error::Constraint("Primary key 2400 already exists for table test".into()))
+    );
+    assert_eq!(
+        c.ingest("test", vec![vec![Value::String("x".into())]]).await,
+        Err(Error::Value("Invalid row size for table test".into()))
+    );
+    assert_row(c.execute("SELECT COUNT(*) FROM test").await?, vec![Value::Integer(2500)]);
+
+    // Loads in explicit transactions are part of the transaction.
+    c.execute("BEGIN").await?;
+    assert_eq!(c.ingest("test", rows(2500..2600)).await?, 100);
+    assert_row(c.execute("SELECT COUNT(*) FROM test").await?, vec![Value::Integer(2600)]);
+    c.execute("ROLLBACK").await?;
+    assert_row(c.execute("SELECT COUNT(*) FROM test").await?, vec![Value::Integer(2500)]);
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_params() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Parameters are bound as values, not SQL.
+    assert_eq!(
+        c.execute_params(
+            "INSERT INTO genres VALUES (?, ?)",
+            &[Value::Integer(9), Value::String("Western'); DROP TABLE genres; --".into())]
+        )
+        .await?,
+        ResultSet::Create { count: 1 }
+    );
+    assert_row(
+        c.execute_params("SELECT name FROM genres WHERE id = ?", &[Value::Integer(9)]).await?,
+        vec![Value::String("Western'); DROP TABLE genres; --".into())],
+    );
+
+    // LIMIT and OFFSET take parameters and constant expressions of them.
+    assert_rows(
+        c.execute_params(
+            "SELECT id FROM movies ORDER BY id LIMIT ? * 2 OFFSET ?",
+            &[Value::Integer(1), Value::Integer(3)],
+        )
+        .await?,
+        vec![vec![Value::Integer(4)], vec![Value::Integer(5)]],
+    );
+    assert_eq!(
+        c.execute_params("SELECT * FROM movies LIMIT ?", &[Value::Float(1.5)]).await,
+        Err(Error::Value("Invalid limit 1.5".into()))
+    );
+    assert_eq!(
+        c.execute_params("SELECT * FROM movies OFFSET ?", &[Value::Integer(-1)]).await,
+        Err(Error::Value("Invalid offset -1".into()))
+    );
+
+    // The number of values must match the number of parameters.
+    assert_eq!(
+        c.execute_params("SELECT ?, ?", &[Value::Integer(1)]).await,
+        Err(Error::Value("Expected 2 parameters, got 1".into()))
+    );
+    assert_eq!(
+        c.execute_params("SELECT 1", &[Value::Integer(1)]).await,
+        Err(Error::Value("Expected 0 parameters, got 1".into()))
+    );
+    assert_eq!(
+        c.execute("SELECT ?").await,
+        Err(Error::Value("Expected 1 parameters, got 0".into()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn describe() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Parameter datatypes are inferred from their context.
+    assert_eq!(
+        c.describe(
+            "SELECT title FROM movies WHERE id = ? AND rating > ? AND title LIKE ? AND NOT ? \
+             LIMIT ? OFFSET ?"
+        )
+        .await?,
+        vec![
+            Some(DataType::Integer),
+            Some(DataType::Float),
+            Some(DataType::String),
+            Some(DataType::Boolean),
+            Some(DataType::Integer),
+            Some(DataType::Integer),
+        ]
+    );
+    assert_eq!(
+        c.describe("SELECT released + ?, ? FROM movies m JOIN genres g ON ? = g.name").await?,
+        vec![Some(DataType::Integer), None, Some(DataType::String)]
+    );
+    assert_eq!(
+        c.describe("INSERT INTO movies (id, title, rating) VALUES (?, ?, ?), (?, ?, 1 + ?)")
+            .await?,
+        vec![
+            Some(DataType::Integer),
+            Some(DataType::String),
+            Some(DataType::Float),
+            Some(DataType::Integer),
+            Some(DataType::String),
+            Some(DataType::Integer),
+        ]
+    );
+    assert_eq!(
+        c.describe("UPDATE movies SET ultrahd = ? WHERE id = ?").await?,
+        vec![Some(DataType::Boolean), Some(DataType::Integer)]
+    );
+    assert_eq!(
+        c.describe("EXPLAIN DELETE FROM movies WHERE studio_id = ?").await?,
+        vec![Some(DataType::Integer)]
+    );
+    assert_eq!(c.describe("BEGIN").await?, vec![]);
+    assert_eq!(c.describe("SELECT 1").await?, vec![]);
+    assert_eq!(
+        c.describe("SELECT * FROM missing WHERE id = ?").await,
+        Err(Error::Value("Table missing does not exist".into()))
+    );
+
+    // Parameter datatypes can be given explicitly, which takes precedence over inference and
+    // is enforced for values, although NULL is always allowed.
+    assert_eq!(
+        c.describe("SELECT ?::FLOAT, ?::VARCHAR(2) FROM movies WHERE id = ?::STRING").await?,
+        vec![Some(DataType::Float), Some(DataType::Varchar(2)), Some(DataType::String)]
+    );
+    assert_row(
+        c.execute_params("SELECT ?::FLOAT, ?::VARCHAR(2)", &[Value::Float(1.5), Value::Null])
+            .await?,
+        vec![Value::Float(1.5), Value::Null],
+    );
+    assert_eq!(
+        c.execute_params("SELECT ?::FLOAT", &[Value::Integer(1)]).await,
+        Err(Error::Value("Invalid value 1 for parameter 1 of type FLOAT".into()))
+    );
+    assert_eq!(
+        c.execute_params("SELECT ?, ?::VARCHAR(2)", &[Value::Null, Value::String("abc".into())])
+            .await,
+        Err(Error::Value("Invalid value abc for parameter 2 of type VARCHAR(2)".into()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_changefeed() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::simple()).await?;
+
+    c.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    c.execute("INSERT INTO test VALUES (2, 'b')").await?;
+    c.execute("UPDATE test SET value = 'x' WHERE id = 1").await?;
+    c.execute("DELETE FROM test WHERE id = 2").await?;
+
+    let result = c.execute("CHANGEFEED FOR TABLE test").await?;
+    assert_eq!(
+        result,
+        ResultSet::Query {
+            columns: vec![
+                Column::new("version", DataType::Integer),
+                Column::new("operation", DataType::String),
+                Column {
+                    name: Some("id".into()),
+                    table: Some("test".into()),
+                    datatype: Some(DataType::Integer),
+                },
+                Column {
+                    name: Some("value".into()),
+                    table: Some("test".into()),
+                    datatype: Some(DataType::String),
+                },
+            ],
+            rows: Box::new(std::iter::empty()),
+        }
+    );
+    assert_rows(
+        result,
+        vec![
+            vec![
+                Value::Integer(2),
+                Value::String("upsert".into()),
+                Value::Integer(1),
+                Value::String("a".into()),
+            ],
+            vec![
+                Value::Integer(3),
+                Value::String("upsert".into()),
+                Value::Integer(2),
+                Value::String("b".into()),
+            ],
+            vec![
+                Value::Integer(4),
+                Value::String("upsert".into()),
+                Value::Integer(1),
+                Value::String("x".into()),
+            ],
+            vec![Value::Integer(5), Value::String("delete".into()), Value::Integer(2), Value::Null],
+            vec![Value::Integer(6), Value::String("resolved".into()), Value::Null, Value::Null],
+        ],
+    );
+
+    // The resolved row gives the commit position to continue from, which read-only
+    // transactions such as the changefeed itself don't advance.
+    assert_rows(
+        c.execute("CHANGEFEED FOR TABLE test FROM 5").await?,
+        vec![
+            vec![Value::Integer(5), Value::String("delete".into()), Value::Integer(2), Value::Null],
+            vec![Value::Integer(6), Value::String("resolved".into()), Value::Null, Value::Null],
+        ],
+    );
+    assert_rows(
+        c.execute("CHANGEFEED FOR TABLE test FROM 6").await?,
+        vec![vec![Value::Integer(6), Value::String("resolved".into()), Value::Null, Value::Null]],
+    );
+
+    assert_eq!(
+        c.execute("CHANGEFEED FOR TABLE x").await,
+        Err(Error::Value("Table x does not exist".into()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_history() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::simple()).await?;
+
+    c.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    c.execute("INSERT INTO test VALUES (2, 'b')").await?;
+    c.execute("UPDATE test SET value = 'x' WHERE id = 1").await?;
+    c.execute("DELETE FROM test WHERE id = 1").await?;
+
+    assert_rows(
+        c.execute("HISTORY FOR TABLE test KEY 1").await?,
+        vec![
+            vec![
+                Value::Integer(2),
+                Value::String("upsert".into()),
+                Value::Integer(1),
+                Value::String("a".into()),
+            ],
+            vec![
+                Value::Integer(4),
+                Value::String("upsert".into()),
+                Value::Integer(1),
+                Value::String("x".into()),
+            ],
+            vec![Value::Integer(5), Value::String("delete".into()), Value::Integer(1), Value::Null],
+        ],
+    );
+    assert_rows(c.execute("HISTORY FOR TABLE test KEY 3").await?, Vec::new());
+    assert_eq!(
+        c.execute("HISTORY FOR TABLE x KEY 1").await,
+        Err(Error::Value("Table x does not exist".into()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_row_versions() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::simple()).await?;
+
+    c.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    c.execute("INSERT INTO test VALUES (2, 'b')").await?;
+    c.execute("UPDATE test SET value = 'x' WHERE id = 1").await?;
+    c.execute("DELETE FROM test WHERE id = 2").await?;
+    c.execute("INSERT INTO test VALUES (2, 'c')").await?;
+
+    assert_rows(
+        c.execute("SELECT id, _created_version, _updated_version FROM test").await?,
+        vec![
+            vec![Value::Integer(1), Value::Integer(2), Value::Integer(4)],
+            vec![Value::Integer(2), Value::Integer(6), Value::Integer(6)],
+        ],
+    );
+    assert_rows(
+        c.execute("SELECT * FROM test WHERE _updated_version > _created_version").await?,
+        vec![vec![Value::Integer(1), Value::String("x".into())]],
+    );
+
+    // The transaction's own writes are visible.
+    assert_eq!(c.execute("BEGIN").await?, ResultSet::Begin { id: 9, mode: Mode::ReadWrite });
+    c.execute("UPDATE test SET value = 'y' WHERE id = 2").await?;
+    assert_rows(
+        c.execute("SELECT _created_version, _updated_version FROM test WHERE id = 2").await?,
+        vec![vec![Value::Integer(6), Value::Integer(9)]],
+    );
+    c.execute("ROLLBACK").await?;
+
+    Ok(())
+}
+
+/// Registered scalar functions can be used over Raft, including in scan filters which are
+/// evaluated by the Raft state machine.
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_registered_function() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::simple()).await?;
+    functions::register(ScalarFunction::new(
+        "client_upper",
+        vec![DataType::String],
+        DataType::String,
+        |args| match args {
+            [Value::String(s)] => Ok(Value::String(s.to_uppercase())),
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        },
+    ))?;
+
+    c.execute("INSERT INTO test VALUES (1, 'a'), (2, 'b')").await?;
+    assert_rows(
+        c.execute("SELECT id, client_upper(value) FROM test WHERE client_upper(value) = 'B'")
+            .await?,
+        vec![vec![Value::Integer(2), Value::String("B".into())]],
+    );
+    Ok(())
+}
+
 #[tokio::test(core_threads = 2)]
 #[serial]
 async fn execute_txn() -> Result<()> {
@@ -231,7 +775,7 @@ async fn execute_txn() -> Result<()> {
     assert_eq!(c.execute("BEGIN").await?, ResultSet::Begin { id: 2, mode: Mode::ReadWrite });
     assert_eq!(c.txn(), Some((2, Mode::ReadWrite)));
     c.execute("INSERT INTO genres VALUES (4, 'Drama')").await?;
-    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 2 });
+    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 2, chain: None });
     assert_row(
         c.execute("SELECT * FROM genres WHERE id = 4").await?,
         vec![Value::Integer(4), Value::String("Drama".into())],
@@ -246,7 +790,7 @@ async fn execute_txn() -> Result<()> {
         c.execute("SELECT * FROM genres WHERE id = 5").await?,
         vec![Value::Integer(5), Value::String("Musical".into())],
     );
-    assert_eq!(c.execute("ROLLBACK").await?, ResultSet::Rollback { id: 4 });
+    assert_eq!(c.execute("ROLLBACK").await?, ResultSet::Rollback { id: 4, chain: None });
     assert_rows(c.execute("SELECT * FROM genres WHERE id = 5").await?, Vec::new());
     assert_eq!(c.txn(), None);
 
@@ -265,7 +809,7 @@ async fn execute_txn() -> Result<()> {
         c.execute("SELECT * FROM genres WHERE id = 4").await?,
         vec![Value::Integer(4), Value::String("Drama".into())],
     );
-    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 6 });
+    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 6, chain: None });
 
     // Starting a time-travel txn should work, it shouldn't see recent changes, and it should
     // block writes
@@ -283,18 +827,18 @@ async fn execute_txn() -> Result<()> {
         ],
     );
     assert_eq!(c.execute("INSERT INTO genres VALUES (5, 'Musical')").await, Err(Error::ReadOnly));
-    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 7 });
+    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 7, chain: None });
 
     // A txn should still be usable after an error occurs
     assert_eq!(c.execute("BEGIN").await?, ResultSet::Begin { id: 8, mode: Mode::ReadWrite });
     c.execute("INSERT INTO genres VALUES (5, 'Horror')").await?;
     assert_eq!(
         c.execute("INSERT INTO genres VALUES (5, 'Musical')").await,
-        Err(Error::Value("Primary key 5 already exists for table genres".into()))
+        Err(Error::Constraint("Primary key 5 already exists for table genres".into()))
     );
     assert_eq!(c.txn(), Some((8, Mode::ReadWrite)));
     c.execute("INSERT INTO genres VALUES (6, 'Western')").await?;
-    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 8 });
+    assert_eq!(c.execute("COMMIT").await?, ResultSet::Commit { id: 8, chain: None });
     assert_rows(
         c.execute("SELECT * FROM genres").await?,
         vec![
@@ -307,6 +851,35 @@ async fn execute_txn() -> Result<()> {
         ],
     );
 
+    // Chaining a commit or rollback should start a new txn in the same mode, and the
+    // transaction status should be reported by SHOW TRANSACTION
+    assert_row(
+        c.execute("SHOW TRANSACTION").await?,
+        vec![Value::String("idle".into()), Value::Null, Value::Null, Value::Null],
+    );
+    assert_eq!(
+        c.execute("BEGIN READ ONLY AS OF SYSTEM TIME 2").await?,
+        ResultSet::Begin { id: 10, mode: Mode::Snapshot { version: 2 } }
+    );
+    assert_row(
+        c.execute("SHOW TRANSACTION").await?,
+        vec![
+            Value::String("active".into()),
+            Value::Integer(10),
+            Value::Boolean(true),
+            Value::Integer(2),
+        ],
+    );
+    assert_eq!(c.execute("COMMIT AND CHAIN").await?, ResultSet::Commit { id: 10, chain: Some(11) });
+    assert_eq!(c.txn(), Some((11, Mode::Snapshot { version: 2 })));
+    assert_eq!(
+        c.execute("ROLLBACK AND CHAIN").await?,
+        ResultSet::Rollback { id: 11, chain: Some(12) }
+    );
+    assert_eq!(c.txn(), Some((12, Mode::Snapshot { version: 2 })));
+    assert_eq!(c.execute("ROLLBACK").await?, ResultSet::Rollback { id: 12, chain: None });
+    assert_eq!(c.txn(), None);
+
     Ok(())
 }
 
@@ -338,8 +911,8 @@ async fn execute_txn_concurrent() -> Result<()> {
         Err(Error::Serialization)
     );
 
-    assert_eq!(a.execute("COMMIT").await, Ok(ResultSet::Commit { id: 2 }));
-    assert_eq!(b.execute("ROLLBACK").await, Ok(ResultSet::Rollback { id: 3 }));
+    assert_eq!(a.execute("COMMIT").await, Ok(ResultSet::Commit { id: 2, chain: None }));
+    assert_eq!(b.execute("ROLLBACK").await, Ok(ResultSet::Rollback { id: 3, chain: None }));
 
     assert_row(
         a.execute("SELECT * FROM genres WHERE id = 1").await?,
@@ -348,3 +921,93 @@ async fn execute_txn_concurrent() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_txn_priority() -> Result<()> {
+    let (a, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let b = Client::new("127.0.0.1:9605").await?;
+
+    // On conflict, the lower-priority transaction is aborted even though it wrote first.
+    assert_eq!(
+        a.execute("BEGIN PRIORITY LOW").await?,
+        ResultSet::Begin { id: 2, mode: Mode::ReadWrite }
+    );
+    assert_eq!(
+        b.execute("BEGIN PRIORITY HIGH").await?,
+        ResultSet::Begin { id: 3, mode: Mode::ReadWrite }
+    );
+    assert_eq!(
+        a.execute("UPDATE genres SET name = 'x' WHERE id = 1").await,
+        Ok(ResultSet::Update { count: 1 })
+    );
+    assert_eq!(
+        b.execute("UPDATE genres SET name = 'y' WHERE id = 1").await,
+        Ok(ResultSet::Update { count: 1 })
+    );
+    assert_eq!(
+        a.execute("UPDATE genres SET name = 'x' WHERE id = 2").await,
+        Err(Error::Serialization)
+    );
+    assert_eq!(a.execute("COMMIT").await, Err(Error::Serialization));
+    assert_eq!(b.execute("COMMIT").await, Ok(ResultSet::Commit { id: 3, chain: None }));
+
+    assert_row(
+        a.execute("SELECT * FROM genres WHERE id = 1").await?,
+        vec![Value::Integer(1), Value::String("y".into())],
+    );
+    assert_eq!(
+        a.execute("BEGIN READ ONLY PRIORITY HIGH").await,
+        Err(Error::Value("Can't set priority of read-only transaction".into()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn execute_txn_detach() -> Result<()> {
+    let (a, _teardown) = setup::server_with_client(setup::movies()).await?;
+    let b = Client::new("127.0.0.1:9605").await?;
+
+    // A detached txn should be left active, and be resumable by another session of the same
+    // user.
+    assert_eq!(a.execute("BEGIN").await?, ResultSet::Begin { id: 2, mode: Mode::ReadWrite });
+    a.execute("INSERT INTO genres VALUES (4, 'Drama')").await?;
+    assert_eq!(a.execute("DETACH").await?, ResultSet::Detach { id: 2 });
+    assert_eq!(a.txn(), None);
+    assert_rows(a.execute("SELECT * FROM genres WHERE id = 4").await?, Vec::new());
+    assert_eq!(a.execute("DETACH").await, Err(Error::Value("Not in a transaction".into())));
+
+    assert_eq!(
+        b.execute("RESUME TRANSACTION 2").await?,
+        ResultSet::Resume { id: 2, mode: Mode::ReadWrite }
+    );
+    assert_eq!(b.txn(), Some((2, Mode::ReadWrite)));
+    assert_eq!(b.execute("RESUME 2").await, Err(Error::Value("Already in a transaction".into())));
+    assert_row(
+        b.execute("SELECT * FROM genres WHERE id = 4").await?,
+        vec![Value::Integer(4), Value::String("Drama".into())],
+    );
+    assert_eq!(b.execute("COMMIT").await?, ResultSet::Commit { id: 2, chain: None });
+    assert_row(
+        a.execute("SELECT * FROM genres WHERE id = 4").await?,
+        vec![Value::Integer(4), Value::String("Drama".into())],
+    );
+
+    // Finished txns can't be resumed, nor can txns that aren't detached.
+    assert_eq!(
+        a.execute("RESUME 2").await,
+        Err(Error::Value("Transaction 2 is not detached".into()))
+    );
+    b.execute("BEGIN").await?;
+    let (id, _) = b.txn().unwrap();
+    assert_eq!(
+        a.execute(&format!("RESUME {}", id)).await,
+        Err(Error::Value(format!("Transaction {} is not detached", id)))
+    );
+    assert_eq!(b.txn(), Some((id, Mode::ReadWrite)));
+    b.execute("ROLLBACK").await?;
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use super::super::{assert_row, setup};
+
+use toydb::error::Result;
+use toydb::fault::{Faults, Rule};
+use toydb::sql::types::Value;
+use toydb::Client;
+
+use serial_test::serial;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+// Writes should succeed and replicate to all nodes while Raft messages are dropped and delayed.
+async fn lossy_network() -> Result<()> {
+    let faults = Faults::new().with_seed(1);
+    let nodes: HashMap<String, (String, String)> = (0..3)
+        .map(|i| {
+            (
+                format!("toydb{}", i),
+                (format!("127.0.0.1:{}", 9605 + i), format!("127.0.0.1:{}", 9705 + i)),
+            )
+        })
+        .collect();
+    let _teardown = setup::cluster_with_faults(nodes.clone(), faults.clone()).await?;
+
+    let client = Client::new_cluster(nodes.values().map(|(sql, _)| sql.clone()).collect()).await?;
+    client.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, value STRING)").await?;
+
+    // Followers don't retry client requests they proxy to the leader, so a dropped request or
+    // response would stall the client until the next election. Write via the leader instead.
+    let leader = client.status().await?.raft.leader;
+    let client = Client::new(&nodes[&leader].0).await?;
+
+    faults.add(Rule::error("raft.send.*").with_probability(0.05))?;
+    faults.add(Rule::delay("raft.send.*", Duration::from_millis(50)).with_probability(0.2))?;
+    for i in 1..=20 {
+        client.execute(&format!("INSERT INTO test VALUES ({}, 'a')", i)).await?;
+    }
+    faults.clear()?;
+
+    for (addr, _) in nodes.values() {
+        let client = Client::new(addr).await?;
+        assert_row(client.execute("SELECT COUNT(*) FROM test").await?, vec![Value::Integer(20)]);
+    }
+    Ok(())
+}
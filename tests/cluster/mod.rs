@@ -1,2 +1,3 @@
+mod chaos;
 mod isolation;
 mod recovery;
@@ -2,6 +2,7 @@ use super::super::{assert_row, setup};
 
 use toydb::error::{Error, Result};
 use toydb::sql::types::Value;
+use toydb::Client;
 
 use serial_test::serial;
 
@@ -45,3 +46,22 @@ async fn client_commit_error() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(core_threads = 2)]
+#[serial]
+// A client given all node addresses should connect to the leader.
+async fn client_leader_discovery() -> Result<()> {
+    let (_, _, _, _teardown) = setup::cluster_simple().await?;
+
+    let client =
+        Client::new_cluster((0..3).map(|i| format!("127.0.0.1:{}", 9605 + i)).collect()).await?;
+    let status = client.status().await?;
+    assert_eq!(status.raft.server, status.raft.leader);
+
+    client.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    assert_row(
+        client.execute("SELECT * FROM test WHERE id = 1").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    Ok(())
+}
@@ -2,6 +2,7 @@
 
 use toydb::client::{Client, Pool};
 use toydb::error::Result;
+use toydb::fault::Faults;
 use toydb::server::Server;
 use toydb::storage;
 
@@ -73,15 +74,31 @@ pub async fn server(
     addr_sql: &str,
     addr_raft: &str,
     peers: HashMap<String, String>,
+) -> Result<Teardown> {
+    server_with_faults(id, addr_sql, addr_raft, peers, Faults::new()).await
+}
+
+/// Sets up a test server, injecting faults into Raft messages
+pub async fn server_with_faults(
+    id: &str,
+    addr_sql: &str,
+    addr_raft: &str,
+    peers: HashMap<String, String>,
+    faults: Faults,
 ) -> Result<Teardown> {
     let dir = TempDir::new("toydb")?;
     let mut srv = Server::new(
         id,
         peers,
-        Box::new(storage::log::Hybrid::new(&dir.path(), false)?),
+        Box::new(storage::log::Hybrid::new(
+            &dir.path(),
+            storage::SyncMode::Never,
+            storage::Compression::None,
+        )?),
         Box::new(storage::kv::Memory::new()),
     )
-    .await?;
+    .await?
+    .with_raft_faults(faults);
 
     srv = srv.listen(addr_sql, addr_raft).await?;
     let (task, abort) = srv.serve().remote_handle();
@@ -109,6 +126,14 @@ pub async fn server_with_client(queries: Vec<&str>) -> Result<(Client, Teardown)
 
 /// Sets up a server cluster
 pub async fn cluster(nodes: HashMap<String, (String, String)>) -> Result<Teardown> {
+    cluster_with_faults(nodes, Faults::new()).await
+}
+
+/// Sets up a server cluster, where all nodes inject faults from the given fault set
+pub async fn cluster_with_faults(
+    nodes: HashMap<String, (String, String)>,
+    faults: Faults,
+) -> Result<Teardown> {
     let mut teardown = Teardown::empty();
     for (id, (addr_sql, addr_raft)) in nodes.iter() {
         let peers = nodes
@@ -116,7 +141,7 @@ pub async fn cluster(nodes: HashMap<String, (String, String)>) -> Result<Teardow
             .filter(|(i, _)| i != &id)
             .map(|(id, (_, raft))| (id.clone(), raft.clone()))
             .collect();
-        teardown.merge(server(id, addr_sql, addr_raft, peers).await?);
+        teardown.merge(server_with_faults(id, addr_sql, addr_raft, peers, faults.clone()).await?);
     }
     Ok(teardown)
 }
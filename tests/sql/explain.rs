@@ -0,0 +1,89 @@
+//! EXPLAIN output tests, using an in-memory database against golden files in tests/sql/explain/
+use toydb::error::Result;
+use toydb::sql::engine::Engine as _;
+use toydb::sql::execution::ResultSet;
+
+use goldenfile::Mint;
+use std::io::Write;
+
+macro_rules! test_explain {
+    ( $( $name:ident: $query:expr, )* ) => {
+        $(
+            #[test]
+            fn $name() -> Result<()> {
+                let engine = super::setup(vec![
+                    "CREATE TABLE genres (id INTEGER PRIMARY KEY, name STRING NOT NULL)",
+                    "INSERT INTO genres VALUES (1, 'Science Fiction'), (2, 'Action')",
+                    "CREATE TABLE movies (
+                        id INTEGER PRIMARY KEY,
+                        title STRING NOT NULL,
+                        genre_id INTEGER NOT NULL INDEX REFERENCES genres,
+                        released INTEGER NOT NULL
+                    )",
+                    "INSERT INTO movies VALUES
+                        (1, 'Stalker', 1, 1979),
+                        (2, 'Sicario', 2, 2015),
+                        (3, 'Primer', 1, 2004)",
+                ])?;
+                let mut mint = Mint::new("tests/sql/explain");
+                let mut f = mint.new_goldenfile(stringify!($name))?;
+
+                write!(f, "Query: {}\n\n", $query)?;
+                match engine.session()?.execute($query) {
+                    Ok(ResultSet::Explain(explanation)) => write!(f, "{}\n", explanation)?,
+                    Ok(result) => write!(f, "Result: {:?}\n", result)?,
+                    Err(err) => write!(f, "Error: {:?}\n", err)?,
+                };
+                Ok(())
+            }
+        )*
+    }
+}
+
+test_explain! {
+    text: "EXPLAIN SELECT m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.released > 2000 ORDER BY m.title",
+    text_explicit: "EXPLAIN (FORMAT TEXT) SELECT * FROM movies WHERE id = 1",
+    json: "EXPLAIN (FORMAT JSON) SELECT m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.released > 2000 ORDER BY m.title",
+    json_escape: r#"EXPLAIN (FORMAT JSON) SELECT 'a "quoted" \ string' AS "col""#,
+    json_lowercase: "explain (format json) SELECT * FROM genres",
+    dot: "EXPLAIN (FORMAT DOT) SELECT m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.released > 2000 ORDER BY m.title",
+    dot_escape: r#"EXPLAIN (FORMAT DOT) SELECT 'a "quoted" \ string' AS "col""#,
+    dot_mutation: "EXPLAIN (FORMAT DOT) UPDATE movies SET released = released + 1 WHERE genre_id = 1",
+    verbose: "EXPLAIN (VERBOSE) SELECT m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.released > 2000 AND 1 = 1 ORDER BY m.title",
+    verbose_index: "EXPLAIN (VERBOSE) SELECT * FROM movies WHERE genre_id = 1",
+    verbose_noindex: "EXPLAIN (VERBOSE) SELECT * FROM movies WHERE released = 1979",
+    any_lookup: "EXPLAIN SELECT * FROM movies WHERE id = ANY(ARRAY[1, 3, NULL])",
+    verbose_json: "EXPLAIN (FORMAT JSON, VERBOSE) SELECT * FROM movies WHERE genre_id = 1",
+    verbose_dot: "EXPLAIN (VERBOSE, FORMAT DOT) SELECT * FROM movies WHERE genre_id = 1",
+    format_unknown: "EXPLAIN (FORMAT YAML) SELECT * FROM movies",
+    format_missing: "EXPLAIN (FORMAT) SELECT * FROM movies",
+    option_unknown: "EXPLAIN (VERBOSE) SELECT * FROM movies",
+    option_trailing_comma: "EXPLAIN (VERBOSE,) SELECT * FROM movies",
+    paren_missing: "EXPLAIN (FORMAT JSON SELECT * FROM movies",
+    nested: "EXPLAIN (FORMAT JSON) EXPLAIN SELECT * FROM movies",
+}
+
+/// Bounding box and proximity filters on indexed POINT columns use index range scans, keeping
+/// the filter for points within the box that don't match.
+#[test]
+fn index_range() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE places (id INTEGER PRIMARY KEY, location POINT INDEX)",
+        "INSERT INTO places VALUES (1, point(0, 0)), (2, point(3, 4))",
+    ])?;
+    let mut mint = Mint::new("tests/sql/explain");
+    let mut f = mint.new_goldenfile("index_range")?;
+
+    for query in &[
+        "EXPLAIN SELECT * FROM places WHERE contains(point(2, 5), point(-1, 1), location)",
+        "EXPLAIN SELECT * FROM places WHERE distance(location, point(1, 1)) <= 2 AND id > 0",
+        "EXPLAIN SELECT * FROM places WHERE distance(location, point(1, 1)) > 2",
+    ] {
+        write!(f, "Query: {}\n\n", query)?;
+        match engine.session()?.execute(query)? {
+            ResultSet::Explain(explanation) => write!(f, "{}\n\n", explanation)?,
+            result => write!(f, "Result: {:?}\n\n", result)?,
+        };
+    }
+    Ok(())
+}
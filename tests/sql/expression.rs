@@ -1,7 +1,8 @@
 ///! Evaluates SQL expressions and compares with expectations.
 use toydb::error::{Error, Result};
 use toydb::sql::engine::Engine;
-use toydb::sql::types::Value;
+use toydb::sql::functions::{self, ScalarFunction};
+use toydb::sql::types::{DataType, Value};
 
 fn eval_expr(expr: &str) -> Result<Value> {
     let engine = super::setup(Vec::new())?;
@@ -20,6 +21,13 @@ macro_rules! test_expr {
                     Ok(Float(a)) if a.is_nan() => {},
                     _ => panic!("Expected NaN, got {:?}", actual),
                 }
+                // Syntax error snippets are just the evaluated statement, so they're not compared.
+                Err(Error::Syntax { message, line, column, .. }) => match actual {
+                    Err(Error::Syntax { message: m, line: l, column: c, .. }) => {
+                        assert_eq!((message, line, column), (m, l, c))
+                    }
+                    _ => panic!("Expected syntax error, got {:?}", actual),
+                }
                 _ => assert_eq!($expect, actual),
             }
             Ok(())
@@ -28,6 +36,11 @@ macro_rules! test_expr {
     }
 }
 
+/// Returns a syntax error at the given column of the evaluated SELECT statement.
+fn syntax(message: &str, column: usize) -> Error {
+    Error::Syntax { message: message.into(), line: 1, column, snippet: "".into() }
+}
+
 use Value::*;
 
 test_expr! {
@@ -58,8 +71,8 @@ test_expr! {
     lit_integer_zeroprefix: "03" => Ok(Integer(3)),
     lit_integer_max: "9223372036854775807" => Ok(Integer(9_223_372_036_854_775_807)),
     lit_integer_min: "-9223372036854775807" => Ok(Integer(-9_223_372_036_854_775_807)),
-    lit_integer_overflow: "9223372036854775808" => Err(Error::Parse("number too large to fit in target type".into())),
-    lit_integer_underflow: "-9223372036854775808" => Err(Error::Parse("number too large to fit in target type".into())),
+    lit_integer_overflow: "9223372036854775808" => Err(syntax("number too large to fit in target type", 8)),
+    lit_integer_underflow: "-9223372036854775808" => Err(syntax("number too large to fit in target type", 9)),
 
     lit_string: "'Hi! 👋'" => Ok(String("Hi! 👋".into())),
     lit_string_escape: r#"'Try \n newlines and \t tabs'"# => Ok(String(r#"Try \n newlines and \t tabs"#.into())),
@@ -72,8 +85,8 @@ test_expr! {
     func_unknown_case: "UnKnown ( )" => Err(Error::Value("Unknown function unknown".into())),
     func_unknown_space: "unknown ( )" => Err(Error::Value("Unknown function unknown".into())),
     func_unknown_args: "unknown(a, b, c)" => Err(Error::Value("Unknown function unknown".into())),
-    func_unknown_open: "unknown(a, b, c" => Err(Error::Parse("Unexpected end of input".into())),
-    func_unknown_trailing_comma: "unknown(a, b, c,)" => Err(Error::Parse("Expected expression atom, found )".into())),
+    func_unknown_open: "unknown(a, b, c" => Err(syntax("Unexpected end of input", 23)),
+    func_unknown_trailing_comma: "unknown(a, b, c,)" => Err(syntax("Expected expression atom, found )", 24)),
 
     // Logical operators
     op_and_true_true: "TRUE AND TRUE" => Ok(Boolean(true)),
@@ -115,7 +128,10 @@ test_expr! {
     op_eq_float: "3.14 = 3.14" => Ok(Boolean(true)),
     op_eq_float_not: "3.14 = 2.718" => Ok(Boolean(false)),
     op_eq_float_infinity: "INFINITY = INFINITY" => Ok(Boolean(true)),
-    op_eq_float_nan: "NAN = NAN" => Ok(Boolean(false)),
+    op_eq_float_nan: "NAN = NAN" => Ok(Boolean(true)),
+    op_eq_float_nan_negative: "NAN = -NAN" => Ok(Boolean(true)),
+    op_eq_float_nan_not: "NAN = 1.0" => Ok(Boolean(false)),
+    op_eq_float_zero_negative: "0.0 = -0.0" => Ok(Boolean(true)),
     op_eq_float_int: "3.0 = 3" => Ok(Boolean(true)),
     op_eq_float_int_not: "3.01 = 3" => Ok(Boolean(false)),
     op_eq_int: "1 = 1" => Ok(Boolean(true)),
@@ -137,7 +153,7 @@ test_expr! {
     op_neq_float: "3.14 != 2.718" => Ok(Boolean(true)),
     op_neq_float_not: "3.14 != 3.14" => Ok(Boolean(false)),
     op_neq_float_infinity: "INFINITY != INFINITY" => Ok(Boolean(false)),
-    op_neq_float_nan: "NAN != NAN" => Ok(Boolean(true)),
+    op_neq_float_nan: "NAN != NAN" => Ok(Boolean(false)),
     op_neq_float_int: "3.0 != 4" => Ok(Boolean(true)),
     op_neq_float_int_not: "3.0 != 3" => Ok(Boolean(false)),
     op_neq_int: "1 != 2" => Ok(Boolean(true)),
@@ -162,6 +178,8 @@ test_expr! {
     op_gt_float_not: "3.14 > 3.15" => Ok(Boolean(false)),
     op_gt_float_infinity: "INFINITY > INFINITY" => Ok(Boolean(false)),
     op_gt_float_nan: "NAN > NAN" => Ok(Boolean(false)),
+    op_gt_float_nan_infinity: "NAN > INFINITY" => Ok(Boolean(true)),
+    op_gt_float_nan_int: "NAN > 1" => Ok(Boolean(true)),
     op_gt_float_int: "3.01 > 3" => Ok(Boolean(true)),
     op_gt_float_int_eq: "3.0 > 3" => Ok(Boolean(false)),
     op_gt_float_int_not: "2.99 > 3" => Ok(Boolean(false)),
@@ -194,7 +212,7 @@ test_expr! {
     op_gte_float_gt: "3.15 >= 3.14" => Ok(Boolean(true)),
     op_gte_float_not: "3.14 >= 3.15" => Ok(Boolean(false)),
     op_gte_float_infinity: "INFINITY >= INFINITY" => Ok(Boolean(true)),
-    op_gte_float_nan: "NAN >= NAN" => Ok(Boolean(false)),
+    op_gte_float_nan: "NAN >= NAN" => Ok(Boolean(true)),
     op_gte_float_int: "3.0 >= 3" => Ok(Boolean(true)),
     op_gte_float_int_gt: "3.01 >= 3" => Ok(Boolean(true)),
     op_gte_float_int_not: "2.99 >= 3" => Ok(Boolean(false)),
@@ -227,6 +245,8 @@ test_expr! {
     op_lt_float_not: "3.15 < 3.14" => Ok(Boolean(false)),
     op_lt_float_infinity: "INFINITY < INFINITY" => Ok(Boolean(false)),
     op_lt_float_nan: "NAN < NAN" => Ok(Boolean(false)),
+    op_lt_float_infinity_nan: "INFINITY < NAN" => Ok(Boolean(true)),
+    op_lt_int_nan: "1 < NAN" => Ok(Boolean(true)),
     op_lt_float_int: "2.99 < 3" => Ok(Boolean(true)),
     op_lt_float_int_eq: "3.0 < 3" => Ok(Boolean(false)),
     op_lt_float_int_not: "3.01 < 3" => Ok(Boolean(false)),
@@ -259,7 +279,7 @@ test_expr! {
     op_lte_float_lt: "3.14 <= 3.15" => Ok(Boolean(true)),
     op_lte_float_not: "3.15 <= 3.14" => Ok(Boolean(false)),
     op_lte_float_infinity: "INFINITY <= INFINITY" => Ok(Boolean(true)),
-    op_lte_float_nan: "NAN <= NAN" => Ok(Boolean(false)),
+    op_lte_float_nan: "NAN <= NAN" => Ok(Boolean(true)),
     op_lte_float_int: "3.0 <= 3" => Ok(Boolean(true)),
     op_lte_float_int_lt: "3.01 <= 4" => Ok(Boolean(true)),
     op_lte_float_int_not: "3.01 <= 3" => Ok(Boolean(false)),
@@ -288,7 +308,7 @@ test_expr! {
     op_null_not: "NULL IS NOT NULL" => Ok(Boolean(false)),
     op_null_bool: "TRUE IS NULL" => Ok(Boolean(false)),
     op_null_bool_not: "TRUE IS NOT NULL" => Ok(Boolean(true)),
-    op_null_rhs_bool: "NULL IS TRUE" => Err(Error::Parse("Expected token NULL, found TRUE".into())),
+    op_null_rhs_bool: "NULL IS TRUE" => Err(syntax("Expected token NULL, found TRUE", 16)),
 
     // Math operators
     op_add_float_float: "3.1 + 2.71" => Ok(Float(3.1 + 2.71)),
@@ -303,8 +323,8 @@ test_expr! {
     op_add_negative: "1 + -3" => Ok(Integer(-2)),
     op_add_infinity: "1 + INFINITY" => Ok(Float(std::f64::INFINITY)),
     op_add_nan: "1 + NAN" => Ok(Float(std::f64::NAN)),
-    op_add_overflow_int: "9223372036854775807 + 1" => Err(Error::Value("Integer overflow".into())),
-    op_add_underflow_int: "-9223372036854775807 + -2" => Err(Error::Value("Integer overflow".into())),
+    op_add_overflow_int: "9223372036854775807 + 1" => Err(Error::Value("Integer overflow: 9223372036854775807 + 1".into())),
+    op_add_underflow_int: "-9223372036854775807 + -2" => Err(Error::Value("Integer overflow: -9223372036854775807 + -2".into())),
     op_add_overflow_float: "2e308 + 2e308" => Ok(Float(std::f64::INFINITY)),
     op_add_round_int_float: "9223372036854775807 + 10.0" => Ok(Float(9_223_372_036_854_776_000.0)),
     op_add_error_bool: "TRUE + FALSE" => Err(Error::Value("Can't add TRUE and FALSE".into())),
@@ -330,6 +350,7 @@ test_expr! {
     op_divide_integer_integer: "8 / 3" => Ok(Integer(2)),
     op_divide_integer_integer_negative: "8 / -3" => Ok(Integer(-2)),
     op_divide_integer_integer_zero: "1 / 0" => Err(Error::Value("Can't divide by zero".into())),
+    op_divide_integer_integer_overflow: "(-9223372036854775807 - 1) / -1" => Err(Error::Value("Integer overflow: -9223372036854775808 / -1".into())),
     op_divide_integer_null: "1 / NULL" => Ok(Null),
     op_divide_infinity: "1 / INFINITY" => Ok(Float(0.0)),
     op_divide_infinity_divisor: "INFINITY / 10" => Ok(Float(std::f64::INFINITY)),
@@ -343,10 +364,14 @@ test_expr! {
 
     op_exp_float_float: "6.25 ^ 0.5" => Ok(Float(2.5)),
     op_exp_float_int: "6.25 ^ 2" => Ok(Float(39.0625)),
+    op_exp_float_int_large: "2.0 ^ 3000000000" => Ok(Float(std::f64::INFINITY)),
     op_exp_float_null: "3.14 ^ NULL" => Ok(Null),
     op_exp_int_float: "9 ^ 0.5" => Ok(Float(3.0)),
     op_exp_int_int: "2 ^ 3" => Ok(Integer(8)),
-    op_exp_int_int_large: "2 ^ 10000000000" => Err(Error::Value("Integer overflow".into())),
+    op_exp_int_int_large: "2 ^ 10000000000" => Err(Error::Value("Integer overflow: 2 ^ 10000000000".into())),
+    op_exp_int_int_large_u32: "2 ^ 4294967296" => Err(Error::Value("Integer overflow: 2 ^ 4294967296".into())),
+    op_exp_int_int_large_one: "1 ^ 4294967296" => Ok(Integer(1)),
+    op_exp_int_int_large_negative_one: "(-1) ^ 4294967297" => Ok(Integer(-1)),
     op_exp_int_null: "1 ^ NULL" => Ok(Null),
     op_exp_null_float: "NULL ^ 3.14" => Ok(Null),
     op_exp_null_int: "NULL ^ 1" => Ok(Null),
@@ -357,13 +382,16 @@ test_expr! {
     op_exp_nan: "NAN ^ 2" => Ok(Float(std::f64::NAN)),
     op_exp_nan_exp: "2 ^ NAN" => Ok(Float(std::f64::NAN)),
     op_exp_overflow_float: "10e200 ^ 2" => Ok(Float(std::f64::INFINITY)),
-    op_exp_overflow_int: "9223372036854775807 ^ 2" => Err(Error::Value("Integer overflow".into())),
+    op_exp_overflow_int: "9223372036854775807 ^ 2" => Err(Error::Value("Integer overflow: 9223372036854775807 ^ 2".into())),
     op_exp_negative: "2 ^ -3" => Ok(Float(0.125)),
     op_exp_error_bool: "TRUE ^ FALSE" => Err(Error::Value("Can't exponentiate TRUE and FALSE".into())),
     op_exp_error_strings: "'a' ^ 'b'" => Err(Error::Value("Can't exponentiate a and b".into())),
 
     op_factorial: "3!" => Ok(Integer(6)),
     op_factorial_zero: "0!" => Ok(Integer(1)),
+    op_factorial_max: "20!" => Ok(Integer(2_432_902_008_176_640_000)),
+    op_factorial_overflow: "21!" => Err(Error::Value("Integer overflow: 21!".into())),
+    op_factorial_overflow_large: "1000000000000!" => Err(Error::Value("Integer overflow: 1000000000000!".into())),
     op_factorial_null: "NULL!" => Ok(Null),
     op_factorial_error_bool: "TRUE!" => Err(Error::Value("Can't take factorial of TRUE".into())),
     op_factorial_error_float: "3.14!" => Err(Error::Value("Can't take factorial of 3.14".into())),
@@ -383,6 +411,7 @@ test_expr! {
     op_modulo_null_null: "NULL % NULL" => Ok(Null),
     op_modulo_negative: "-5 % 3" => Ok(Integer(-2)),
     op_modulo_negative_rhs: "5 % -3" => Ok(Integer(2)),
+    op_modulo_min_negative_one: "(-9223372036854775807 - 1) % -1" => Ok(Integer(0)),
     op_modulo_infinity: "INFINITY % 7" => Ok(Float(std::f64::NAN)),
     op_modulo_infinity_divisor: "7 % INFINITY" => Ok(Float(7.0)),
     op_modulo_nan: "7 % NAN" => Ok(Float(std::f64::NAN)),
@@ -401,8 +430,8 @@ test_expr! {
     op_multiply_negative: "2 * -3" => Ok(Integer(-6)),
     op_multiply_infinity: "2 * INFINITY" => Ok(Float(std::f64::INFINITY)),
     op_multiply_nan: "2 * NAN" => Ok(Float(std::f64::NAN)),
-    op_multiply_overflow_int: "9223372036854775807 * 2" => Err(Error::Value("Integer overflow".into())),
-    op_multiply_underflow_int: "9223372036854775807 * -2" => Err(Error::Value("Integer overflow".into())),
+    op_multiply_overflow_int: "9223372036854775807 * 2" => Err(Error::Value("Integer overflow: 9223372036854775807 * 2".into())),
+    op_multiply_underflow_int: "9223372036854775807 * -2" => Err(Error::Value("Integer overflow: 9223372036854775807 * -2".into())),
     op_multiply_overflow_float: "2e308 * 2" => Ok(Float(std::f64::INFINITY)),
     op_multiply_round_int_float: "9223372036854775807 * 2.0" => Ok(Float(18_446_744_073_709_552_000.0)),
     op_multiply_error_bool: "TRUE * FALSE" => Err(Error::Value("Can't multiply TRUE and FALSE".into())),
@@ -414,6 +443,7 @@ test_expr! {
     op_negate_mixed: "-+-+-1" => Ok(Integer(-1)),
     op_negate_multi: "---1" => Ok(Integer(-1)),
     op_negate_null: "-NULL" => Ok(Null),
+    op_negate_overflow: "-(-9223372036854775807 - 1)" => Err(Error::Value("Integer overflow: --9223372036854775808".into())),
     op_negate_infinity: "-INFINITY" => Ok(Float(-std::f64::INFINITY)),
     op_negate_nan: "-NAN" => Ok(Float(std::f64::NAN)),
     op_negate_error_bool: "-TRUE" => Err(Error::Value("Can't negate TRUE".into())),
@@ -431,13 +461,32 @@ test_expr! {
     op_subtract_negative: "1 - -3" => Ok(Integer(4)),
     op_subtract_infinity: "1 - INFINITY" => Ok(Float(-std::f64::INFINITY)),
     op_subtract_nan: "1 - NAN" => Ok(Float(std::f64::NAN)),
-    op_subtract_overflow_int: "9223372036854775807 - -1" => Err(Error::Value("Integer overflow".into())),
-    op_subtract_underflow_int: "-9223372036854775807 - 2" => Err(Error::Value("Integer overflow".into())),
+    op_subtract_overflow_int: "9223372036854775807 - -1" => Err(Error::Value("Integer overflow: 9223372036854775807 - -1".into())),
+    op_subtract_underflow_int: "-9223372036854775807 - 2" => Err(Error::Value("Integer overflow: -9223372036854775807 - 2".into())),
     op_subtract_overflow_float: "2e308 - -2e308" => Ok(Float(std::f64::INFINITY)),
     op_subtract_round_int_float: "9223372036854775807 - -10.0" => Ok(Float(9_223_372_036_854_776_000.0)),
     op_subtract_error_bool: "TRUE - FALSE" => Err(Error::Value("Can't subtract TRUE and FALSE".into())),
     op_subtract_error_strings: "'a' - 'b'" => Err(Error::Value("Can't subtract a and b".into())),
 
+    // Wrapping arithmetic, via the WRAPPING_ARITHMETIC hint
+    op_wrapping_add: "/*+ WRAPPING_ARITHMETIC */ 9223372036854775807 + 1" => Ok(Integer(i64::MIN)),
+    op_wrapping_subtract: "/*+ WRAPPING_ARITHMETIC */ -9223372036854775807 - 2" => Ok(Integer(i64::MAX)),
+    op_wrapping_multiply: "/*+ WRAPPING_ARITHMETIC */ 9223372036854775807 * 2" => Ok(Integer(-2)),
+    op_wrapping_divide: "/*+ WRAPPING_ARITHMETIC */ (-9223372036854775807 - 1) / -1" => Ok(Integer(i64::MIN)),
+    op_wrapping_divide_zero: "/*+ WRAPPING_ARITHMETIC */ 1 / 0" => Err(Error::Value("Can't divide by zero".into())),
+    op_wrapping_exp: "/*+ WRAPPING_ARITHMETIC */ 3 ^ 41" => Ok(Integer(3_i64.wrapping_pow(41))),
+    op_wrapping_exp_large: "/*+ WRAPPING_ARITHMETIC */ 2 ^ 4294967296" => Ok(Integer(0)),
+    op_wrapping_exp_large_odd: "/*+ WRAPPING_ARITHMETIC */ -1 ^ 4294967297" => Ok(Integer(-1)),
+    op_wrapping_exp_negative: "/*+ WRAPPING_ARITHMETIC */ 2 ^ -1" => Ok(Float(0.5)),
+    op_wrapping_factorial: "/*+ WRAPPING_ARITHMETIC */ 21!" => Ok(Integer((1..=21).fold(1_i64, |a, b| a.wrapping_mul(b)))),
+    op_wrapping_factorial_large: "/*+ WRAPPING_ARITHMETIC */ 1000000000000!" => Ok(Integer(0)),
+    op_wrapping_factorial_negative: "/*+ WRAPPING_ARITHMETIC */ -1!" => Err(Error::Value("Can't take factorial of negative number".into())),
+    op_wrapping_negate: "/*+ WRAPPING_ARITHMETIC */ -(-9223372036854775807 - 1)" => Ok(Integer(i64::MIN)),
+    op_wrapping_nested: "/*+ WRAPPING_ARITHMETIC */ (9223372036854775807 + 1) - 1" => Ok(Integer(i64::MAX)),
+    op_wrapping_float: "/*+ WRAPPING_ARITHMETIC */ 9223372036854775807 + 1.0" => Ok(Float(9_223_372_036_854_775_808.0)),
+    op_wrapping_null: "/*+ WRAPPING_ARITHMETIC */ 1 + NULL" => Ok(Null),
+    op_wrapping_error: "/*+ WRAPPING_ARITHMETIC */ 1 + 'a'" => Err(Error::Value("Can't add 1 and a".into())),
+
     // String operators
     op_like_percent: "'abcde' LIKE 'a%e'" => Ok(Boolean(true)),
     op_like_percent_escape: "'ab%de' LIKE 'ab%%de'" => Ok(Boolean(true)),
@@ -459,12 +508,32 @@ test_expr! {
     op_like_null: "'abc' LIKE NULL" => Ok(Null),
     op_like_null_lhs: "NULL LIKE 'abc'" => Ok(Null),
 
+    // Array operators
+    op_array: "ARRAY[1, NULL, 3]" => Ok(Array(vec![Integer(1), Null, Integer(3)])),
+    op_array_empty: "ARRAY[]" => Ok(Array(vec![])),
+    op_array_mixed: "ARRAY[1, 2.0]" => Err(Error::Value("Array elements must have the same datatype, found INTEGER and FLOAT".into())),
+    op_array_trailing_comma: "ARRAY[1,]" => Err(syntax("Expected expression atom, found ]", 16)),
+    op_element: "ARRAY['a', 'b'][2]" => Ok(String("b".into())),
+    op_element_expr: "ARRAY['a', 'b'][3 - 2]" => Ok(String("a".into())),
+    op_element_range: "ARRAY['a', 'b'][3]" => Ok(Null),
+    op_element_negative: "ARRAY['a', 'b'][-1]" => Ok(Null),
+    op_element_null: "NULL[1]" => Ok(Null),
+    op_element_float: "ARRAY[1][1.0]" => Err(Error::Value("Can't index [1] with 1".into())),
+    op_any: "2 = ANY(ARRAY[1, 2])" => Ok(Boolean(true)),
+    op_any_empty: "2 = ANY(ARRAY[])" => Ok(Boolean(false)),
+    op_any_null: "NULL = ANY(ARRAY[1])" => Ok(Null),
+    op_all: "2 > ALL(ARRAY[0, 1])" => Ok(Boolean(true)),
+    op_all_empty: "2 > ALL(ARRAY[])" => Ok(Boolean(true)),
+    op_all_null: "2 > ALL(NULL)" => Ok(Null),
+
     // Operator precedence, testing each operator against the ones at the same level and immediately
     // below it in order.
     op_prec_negate_factorial: "-3!" => Err(Error::Value("Can't take factorial of negative number".into())),
     op_prec_negate_factorial_paren: "-(3!)" => Ok(Integer(-6)),
     op_prec_negate_is: "-NULL IS NULL" => Ok(Boolean(true)),
     op_prec_negate_is_paren: "-(NULL IS NULL)" => Err(Error::Value("Can't negate TRUE".into())),
+    op_prec_element_negate: "-ARRAY[1, 2][2]" => Ok(Integer(-2)),
+    op_prec_element_factorial: "ARRAY[1, 3][2]!" => Ok(Integer(6)),
 
     op_prec_not_factorial: "NOT NULL IS NULL" => Ok(Boolean(true)),
     op_prec_not_factorial_paren: "NOT (NULL IS NULL)" => Ok(Boolean(false)),
@@ -584,4 +653,53 @@ test_expr! {
 
     op_prec_and_or: "FALSE AND TRUE OR TRUE" => Ok(Boolean(true)),
     op_prec_and_or_paren: "FALSE AND (TRUE OR TRUE)" => Ok(Boolean(false)),
+
+    nesting_max_paren: &format!("{}1{}", "(".repeat(63), ")".repeat(63)) => Ok(Integer(1)),
+    nesting_max_prefix: &format!("{}1", "-".repeat(63)) => Ok(Integer(-1)),
+    nesting_max_infix_left: &format!("{}1", "1 + ".repeat(63)) => Ok(Integer(64)),
+    nesting_max_infix_right: &format!("{}1", "1 ^ ".repeat(63)) => Ok(Integer(1)),
+    nesting_error_paren: &format!("{}1{}", "(".repeat(64), ")".repeat(64)) => Err(syntax("Expression nesting exceeds maximum depth 64", 71)),
+    nesting_error_prefix: &format!("{}1", "NOT ".repeat(100_000)) => Err(syntax("Expression nesting exceeds maximum depth 64", 260)),
+    nesting_error_infix_left: &format!("{}1", "1 + ".repeat(100_000)) => Err(syntax("Expression nesting exceeds maximum depth 64", 262)),
+    nesting_error_infix_right: &format!("{}1", "1 ^ ".repeat(64)) => Err(syntax("Expression nesting exceeds maximum depth 64", 262)),
+}
+
+/// Registered scalar functions can be called in expressions. Their arguments are checked against
+/// the function signature when planning, and NULL arguments yield NULL.
+#[test]
+fn registered_function() -> Result<()> {
+    functions::register(ScalarFunction::new(
+        "expr_repeat",
+        vec![DataType::String, DataType::Integer],
+        DataType::String,
+        |args| match args {
+            [String(s), Integer(n)] if *n >= 0 => Ok(String(s.repeat(*n as usize))),
+            [_, n] => Err(Error::Value(format!("Can't repeat {} times", n))),
+            _ => Err(Error::Internal("Unexpected arguments".into())),
+        },
+    ))?;
+
+    assert_eq!(eval_expr("expr_repeat('ab', 2 + 1)"), Ok(String("ababab".into())));
+    assert_eq!(eval_expr("EXPR_REPEAT('ab', 2) = 'abab'"), Ok(Boolean(true)));
+    assert_eq!(eval_expr("expr_repeat(NULL, 2)"), Ok(Null));
+    assert_eq!(
+        eval_expr("expr_repeat('ab', -1)"),
+        Err(Error::Value("Can't repeat -1 times".into()))
+    );
+    assert_eq!(
+        eval_expr("expr_repeat('ab')"),
+        Err(Error::Value("Function expr_repeat takes 2 arguments, given 1".into()))
+    );
+    assert_eq!(
+        eval_expr("expr_repeat('ab', 'c')"),
+        Err(Error::Value(
+            "Invalid STRING argument for parameter 2 of function expr_repeat, expected INTEGER"
+                .into()
+        ))
+    );
+    assert_eq!(
+        super::setup(Vec::new())?.session()?.execute("CREATE FUNCTION expr_repeat () AS '1'"),
+        Err(Error::Value("Function name expr_repeat is reserved for a registered function".into()))
+    );
+    Ok(())
 }
@@ -0,0 +1,429 @@
+//! sqllogictest runner. Executes sqllogictest files (see https://www.sqlite.org/sqllogictest) under
+//! tests/sql/logic/ against both a local in-memory SQL engine and a Raft-backed toyDB server. The
+//! TOYDB_SLT environment variable can be set to a file or directory to run other test files
+//! instead, e.g. imported test suites. Records can be restricted to or excluded from toyDB via
+//! onlyif toydb or skipif toydb.
+use super::super::setup;
+use toydb::error::{Error, Result};
+use toydb::sql::engine::{Engine, KV};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::types::Value;
+use toydb::storage::kv;
+
+use regex::Regex;
+use serial_test::serial;
+use std::future::Future;
+use std::path::PathBuf;
+
+/// The database name used for skipif and onlyif conditions.
+const DATABASE: &str = "toydb";
+
+/// Runs the sqllogictest files against a local SQL engine, using a new engine for each file.
+#[test]
+fn local() -> Result<()> {
+    let mut failures = Vec::new();
+    for path in files()? {
+        let engine = KV::new(kv::MVCC::new(Box::new(kv::Memory::new())));
+        let mut session = engine.session()?;
+        let records = parse(&std::fs::read_to_string(&path)?)?;
+        let run = run(&records, |sql| futures::future::ready(session.execute(&sql)));
+        failures.extend(
+            futures::executor::block_on(run)?
+                .into_iter()
+                .map(|f| format!("{}:{}", path.display(), f)),
+        );
+    }
+    assert!(failures.is_empty(), "sqllogictest failures:\n{}", failures.join("\n"));
+    Ok(())
+}
+
+/// Runs the sqllogictest files against a Raft-backed toyDB server, using a new server for each file.
+#[tokio::test(core_threads = 2)]
+#[serial]
+async fn raft() -> Result<()> {
+    let mut failures = Vec::new();
+    for path in files()? {
+        // The previous file's server may not have released its ports yet, so retry briefly.
+        let mut attempt = 0;
+        let (client, _teardown) = loop {
+            match setup::server_with_client(Vec::new()).await {
+                Err(_) if attempt < 20 => attempt += 1,
+                result => break result?,
+            }
+            tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+        };
+        let records = parse(&std::fs::read_to_string(&path)?)?;
+        let run = run(&records, |sql| {
+            let client = client.clone();
+            async move { client.execute(&sql).await }
+        });
+        failures.extend(run.await?.into_iter().map(|f| format!("{}:{}", path.display(), f)));
+    }
+    assert!(failures.is_empty(), "sqllogictest failures:\n{}", failures.join("\n"));
+    Ok(())
+}
+
+/// Returns the sqllogictest files to run, ordered by path.
+fn files() -> Result<Vec<PathBuf>> {
+    let path =
+        PathBuf::from(std::env::var("TOYDB_SLT").unwrap_or_else(|_| "tests/sql/logic".into()));
+    if !path.is_dir() {
+        return Ok(vec![path]);
+    }
+    let mut files = std::fs::read_dir(&path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<PathBuf>>>()?
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "slt" || e == "test"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// A sqllogictest record.
+#[derive(Debug, PartialEq)]
+enum Record {
+    /// Executes a statement, expecting it to succeed or fail with an error matching the pattern.
+    Statement { line: usize, sql: String, expect: Expect },
+    /// Executes a query, expecting the given results, or an error matching the pattern.
+    Query { line: usize, sql: String, types: Vec<char>, sort: Sort, expect: Expect },
+    /// Sets the number of result values above which results are given as an MD5 hash.
+    HashThreshold(usize),
+    /// Stops executing the file.
+    Halt,
+}
+
+/// An expected statement or query outcome.
+#[derive(Debug, PartialEq)]
+enum Expect {
+    /// Success, with the given result lines for queries.
+    Ok(Vec<String>),
+    /// An error, optionally matching a regex pattern.
+    Error(Option<String>),
+}
+
+/// A query result sort mode.
+#[derive(Debug, PartialEq)]
+enum Sort {
+    /// Results are compared in the order returned.
+    None,
+    /// Rows are sorted before comparison.
+    Row,
+    /// Individual values are sorted before comparison, ignoring rows.
+    Value,
+}
+
+/// Parses a sqllogictest script into records, skipping records excluded by skipif or onlyif.
+fn parse(script: &str) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut lines = script.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end())).peekable();
+    let mut skip = false;
+    while let Some((line, text)) = lines.next() {
+        let error = |msg: &str| Error::Parse(format!("line {}: {}", line, msg));
+        let args = text.split_whitespace().collect::<Vec<_>>();
+        let record = match args.as_slice() {
+            [] => continue,
+            [comment, ..] if comment.starts_with('#') => continue,
+            ["skipif", db] => {
+                skip |= *db == DATABASE;
+                continue;
+            }
+            ["onlyif", db] => {
+                skip |= *db != DATABASE;
+                continue;
+            }
+            ["halt"] => Record::Halt,
+            ["hash-threshold", n] => {
+                Record::HashThreshold(n.parse().map_err(|_| error("invalid hash threshold"))?)
+            }
+            ["statement", "ok"] => Record::Statement {
+                line,
+                sql: block(&mut lines, false).0,
+                expect: Expect::Ok(vec![]),
+            },
+            ["statement", "error", ..] | ["query", "error", ..] => {
+                let pattern = text.splitn(3, ' ').nth(2).map(|p| p.trim().to_string());
+                let sql = block(&mut lines, false).0;
+                match args[0] {
+                    "statement" => Record::Statement { line, sql, expect: Expect::Error(pattern) },
+                    _ => Record::Query {
+                        line,
+                        sql,
+                        types: vec![],
+                        sort: Sort::None,
+                        expect: Expect::Error(pattern),
+                    },
+                }
+            }
+            ["query", types, rest @ ..] => {
+                let sort = match rest.first() {
+                    None | Some(&"nosort") => Sort::None,
+                    Some(&"rowsort") => Sort::Row,
+                    Some(&"valuesort") => Sort::Value,
+                    // Anything else is a label, which we don't use.
+                    Some(_) => Sort::None,
+                };
+                let (sql, results) = block(&mut lines, true);
+                Record::Query {
+                    line,
+                    sql,
+                    types: types.chars().collect(),
+                    sort,
+                    expect: Expect::Ok(results),
+                }
+            }
+            _ => return Err(error(&format!("unknown record {}", text))),
+        };
+        if !std::mem::replace(&mut skip, false) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Reads a record's SQL text up to a ---- separator or blank line, and if results is true any
+/// results following the separator up to a blank line.
+fn block<'a, I>(lines: &mut std::iter::Peekable<I>, results: bool) -> (String, Vec<String>)
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    let mut sql = Vec::new();
+    while let Some(&(_, text)) = lines.peek().filter(|(_, l)| !l.is_empty() && *l != "----") {
+        sql.push(text);
+        lines.next();
+    }
+    let mut output = Vec::new();
+    if results && lines.peek().is_some_and(|(_, l)| *l == "----") {
+        lines.next();
+        while let Some(&(_, text)) = lines.peek().filter(|(_, l)| !l.is_empty()) {
+            output.push(text.to_string());
+            lines.next();
+        }
+    }
+    (sql.join("\n"), output)
+}
+
+/// Runs records, executing SQL via the given function. Returns any failures, prefixed by the
+/// line number of the failing record.
+async fn run<F, R>(records: &[Record], mut execute: F) -> Result<Vec<String>>
+where
+    F: FnMut(String) -> R,
+    R: Future<Output = Result<ResultSet>>,
+{
+    let mut failures = Vec::new();
+    let mut hash_threshold = 0;
+    for record in records {
+        let (line, result) = match record {
+            Record::Halt => break,
+            Record::HashThreshold(n) => {
+                hash_threshold = *n;
+                continue;
+            }
+            Record::Statement { line, sql, expect } => {
+                (line, check_statement(execute(sql.clone()).await, expect))
+            }
+            Record::Query { line, sql, types, sort, expect } => {
+                (line, check_query(execute(sql.clone()).await, types, sort, expect, hash_threshold))
+            }
+        };
+        if let Err(failure) = result {
+            failures.push(format!("{}: {}", line, failure));
+        }
+    }
+    Ok(failures)
+}
+
+/// Checks a statement result against the expected outcome.
+fn check_statement(result: Result<ResultSet>, expect: &Expect) -> std::result::Result<(), String> {
+    match (result, expect) {
+        (Ok(ResultSet::Query { rows, .. }), Expect::Ok(_)) => {
+            rows.collect::<Result<Vec<_>>>().map_err(|e| format!("unexpected error: {}", e))?;
+            Ok(())
+        }
+        (Ok(_), Expect::Ok(_)) => Ok(()),
+        // Query errors may not surface until the rows are read.
+        (Ok(ResultSet::Query { rows, .. }), Expect::Error(_)) => {
+            match rows.collect::<Result<Vec<_>>>() {
+                Ok(rows) => Err(format!("expected error, got {} rows", rows.len())),
+                Err(error) => check_error(error, expect),
+            }
+        }
+        (Ok(result), Expect::Error(_)) => Err(format!("expected error, got {:?}", result)),
+        (Err(error), expect) => check_error(error, expect),
+    }
+}
+
+/// Checks a query result against the expected outcome.
+fn check_query(
+    result: Result<ResultSet>,
+    types: &[char],
+    sort: &Sort,
+    expect: &Expect,
+    hash_threshold: usize,
+) -> std::result::Result<(), String> {
+    let expected = match expect {
+        Expect::Ok(expected) => expected,
+        Expect::Error(_) => return check_statement(result, expect),
+    };
+    let rows = match result {
+        Ok(ResultSet::Query { rows, .. }) => rows,
+        Ok(result) => return Err(format!("expected query result, got {:?}", result)),
+        Err(error) => return check_error(error, expect),
+    };
+    let mut rows = rows
+        .map(|row| {
+            let row = row.map_err(|e| format!("unexpected error: {}", e))?;
+            if row.len() != types.len() {
+                return Err(format!("expected {} columns, got {}", types.len(), row.len()));
+            }
+            Ok(row.iter().zip(types).map(|(v, t)| format_value(v, *t)).collect::<Vec<_>>())
+        })
+        .collect::<std::result::Result<Vec<_>, String>>()?;
+    if *sort == Sort::Row {
+        rows.sort();
+    }
+    let mut values = rows.iter().flatten().cloned().collect::<Vec<_>>();
+    if *sort == Sort::Value {
+        values.sort();
+    }
+
+    // Results may be given as a hash, or as either one value or one row per line.
+    let hashed = Regex::new(r"^(\d+) values hashing to ([0-9a-f]{32})$").unwrap();
+    if let Some(captures) = expected.first().and_then(|l| hashed.captures(l)) {
+        let hash = md5(values.iter().map(|v| format!("{}\n", v)).collect::<String>().as_bytes());
+        let actual = format!("{} values hashing to {}", values.len(), hash);
+        return match actual == captures[0] {
+            true => Ok(()),
+            false => Err(format!("expected {}, got {}", &captures[0], actual)),
+        };
+    }
+    let lines = rows.iter().map(|r| r.join(" ")).collect::<Vec<_>>();
+    let normalized = expected.iter().map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "));
+    if *expected == values || (*sort != Sort::Value && normalized.eq(lines.iter().cloned())) {
+        return Ok(());
+    }
+    let actual = match hash_threshold > 0 && values.len() > hash_threshold {
+        true => vec![format!(
+            "{} values hashing to {}",
+            values.len(),
+            md5(values.iter().map(|v| format!("{}\n", v)).collect::<String>().as_bytes())
+        )],
+        false if *sort == Sort::Value => values,
+        false => lines,
+    };
+    Err(format!("expected:\n{}\ngot:\n{}", expected.join("\n"), actual.join("\n")))
+}
+
+/// Checks an error against the expected outcome.
+fn check_error(error: Error, expect: &Expect) -> std::result::Result<(), String> {
+    match expect {
+        Expect::Ok(_) => Err(format!("unexpected error: {}", error)),
+        Expect::Error(None) => Ok(()),
+        Expect::Error(Some(pattern)) => {
+            let regex = Regex::new(pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+            match regex.is_match(&error.to_string()) {
+                true => Ok(()),
+                false => Err(format!("expected error matching {}, got {}", pattern, error)),
+            }
+        }
+    }
+}
+
+/// Formats a value for the given sqllogictest column type: I (integer), R (real, with 3
+/// decimals), or T (text). Empty strings are given as (empty).
+fn format_value(value: &Value, r#type: char) -> String {
+    match (value, r#type) {
+        (Value::Null, _) => "NULL".into(),
+        (Value::Boolean(b), _) => b.to_string(),
+        (Value::Integer(i), 'R') => format!("{:.3}", *i as f64),
+        (Value::Float(f), 'I') => (f.trunc() as i64).to_string(),
+        (Value::Float(f), 'R') => format!("{:.3}", f),
+        (Value::String(s), _) if s.is_empty() => "(empty)".into(),
+        (value, _) => value.to_string(),
+    }
+}
+
+/// Computes the MD5 hex digest of the given data (RFC 1321), for hashed query results.
+fn md5(data: &[u8]) -> String {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+        .collect::<Vec<_>>();
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for chunk in message.chunks(64) {
+        let words = chunk
+            .chunks(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+        let [mut a, mut b, mut c, mut d] = state;
+        for (i, constant) in constants.iter().enumerate() {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(*constant).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (s, v) in state.iter_mut().zip(&[a, b, c, d]) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+    state.iter().flat_map(|s| s.to_le_bytes().to_vec()).map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn md5_digest() {
+    assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(md5(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(
+        md5(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+        "57edf4a22be3c955ac49da2e2107b67a"
+    );
+}
+
+#[test]
+fn parse_records() -> Result<()> {
+    let records = parse(
+        "# comment\nhash-threshold 8\n\nstatement ok\nCREATE TABLE t\n  (id INTEGER PRIMARY KEY)\n\n\
+         skipif toydb\nstatement ok\nSELECT 1\n\nonlyif toydb\nstatement error does not exist\n\
+         SELECT * FROM x\n\nquery IT rowsort label\nSELECT 1, 'a'\n----\n1\na\n\nhalt\n",
+    )?;
+    assert_eq!(
+        records,
+        vec![
+            Record::HashThreshold(8),
+            Record::Statement {
+                line: 4,
+                sql: "CREATE TABLE t\n  (id INTEGER PRIMARY KEY)".into(),
+                expect: Expect::Ok(vec![]),
+            },
+            Record::Statement {
+                line: 13,
+                sql: "SELECT * FROM x".into(),
+                expect: Expect::Error(Some("does not exist".into())),
+            },
+            Record::Query {
+                line: 16,
+                sql: "SELECT 1, 'a'".into(),
+                types: vec!['I', 'T'],
+                sort: Sort::Row,
+                expect: Expect::Ok(vec!["1".into(), "a".into()]),
+            },
+            Record::Halt,
+        ]
+    );
+    assert!(parse("statement maybe\nSELECT 1\n").is_err());
+    Ok(())
+}
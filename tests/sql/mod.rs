@@ -1,4 +1,6 @@
+mod explain;
 mod expression;
+mod logic;
 mod mutation;
 mod query;
 mod schema;
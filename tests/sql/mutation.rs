@@ -1,7 +1,9 @@
 ///! Mutation tests, using an in-memory database against golden files in tests/sql/mutation/
 use toydb::error::Result;
 use toydb::sql::engine::{Engine as _, Mode, Transaction as _};
+use toydb::sql::execution::ResultSet;
 use toydb::sql::schema::Catalog as _;
+use toydb::sql::types::Value;
 
 use goldenfile::Mint;
 use std::io::Write;
@@ -79,6 +81,7 @@ test_mutation! { with [
     delete_case: "DELETE FROM TeSt WHERE ID = 1",
     delete_missing_column_where: "DELETE FROM test WHERE missing = TRUE",
     delete_missing_table: "DELETE FROM missing",
+    delete_system_table: r#"DELETE FROM "system.storage_stats""#,
     delete_multiple_tables: "DELETE FROM test, other WHERE id = 1",
     delete_bare: "DELETE",
     delete_bare_from: "DELETE FROM",
@@ -111,6 +114,7 @@ test_mutation! { with [
     insert_empty_both: "INSERT INTO test () VALUES ()",
     insert_missing_column: "INSERT INTO test (id, missing) VALUES (0, 'x')",
     insert_missing_table: "INSERT INTO missing (id) VALUES (0)",
+    insert_system_table: r#"INSERT INTO "system.storage_stats" VALUES ('x', 1, 2, 3, 4)"#,
     insert_multiple_tables: "INSERT INTO test, other VALUES (1)",
     insert_case: "INSERT INTO TeSt (ID, Name) VALUES (1, 'a')",
     insert_bare: "INSERT INTO test",
@@ -150,3 +154,48 @@ test_mutation! { with [
     update_bare_where: "UPDATE test SET name = 'x' WHERE",
     update_bare_no_table: "UPDATE",
 }
+
+#[test]
+fn audit() -> Result<()> {
+    let engine = super::setup(vec![])?;
+    let mut session = engine.session()?.with_audit("alice");
+    session.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING)")?;
+    session.execute("INSERT INTO test VALUES (1, 'a'), (2, 'b')")?;
+    session.execute("SELECT * FROM test")?;
+    session.execute("BEGIN")?;
+    session.execute("UPDATE test SET name = 'x' WHERE id = 1")?;
+    session.execute("DELETE FROM test WHERE id = 2")?;
+    session.execute("COMMIT")?;
+    session.execute("BEGIN")?;
+    session.execute("DELETE FROM test")?;
+    session.execute("ROLLBACK")?;
+
+    // Unaudited sessions don't record statements.
+    engine.session()?.execute("INSERT INTO test VALUES (3, 'c')")?;
+
+    let rows = match session
+        .execute("SELECT id, user, kind, statement, timestamp > 0 FROM system.audit")?
+    {
+        ResultSet::Query { rows, .. } => rows.collect::<Result<Vec<_>>>()?,
+        result => panic!("Unexpected result {:?}", result),
+    };
+    let row = |id: &str, kind: &str, statement: &str| {
+        vec![
+            Value::String(id.into()),
+            Value::String("alice".into()),
+            Value::String(kind.into()),
+            Value::String(statement.into()),
+            Value::Boolean(true),
+        ]
+    };
+    assert_eq!(
+        rows,
+        vec![
+            row("2.1", "DDL", "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING)"),
+            row("3.1", "DML", "INSERT INTO test VALUES (1, 'a'), (2, 'b')"),
+            row("5.1", "DML", "UPDATE test SET name = 'x' WHERE id = 1"),
+            row("5.2", "DML", "DELETE FROM test WHERE id = 2"),
+        ]
+    );
+    Ok(())
+}
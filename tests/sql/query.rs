@@ -5,7 +5,7 @@ use toydb::sql::engine::{Engine, Mode, Transaction};
 use toydb::sql::execution::ResultSet;
 use toydb::sql::parser::Parser;
 use toydb::sql::plan::Plan;
-use toydb::sql::types::Row;
+use toydb::sql::types::{Row, Value};
 
 use goldenfile::Mint;
 use std::io::Write;
@@ -181,6 +181,7 @@ test_query! {
     as_all: "SELECT * AS all FROM movies",
     as_duplicate: "SELECT 1 AS a, 2 AS a",
     as_qualified: r#"SELECT 1 AS a.b FROM movies"#,
+    as_keyword_nonreserved: "SELECT id AS key, title AS text FROM movies m WHERE m.id = 1",
 
     from_bare: "SELECT * FROM",
     from_multiple: "SELECT * FROM movies, genres, countries",
@@ -188,6 +189,13 @@ test_query! {
     from_alias_duplicate: "SELECT * FROM movies a, genres a",
     from_alias_duplicate_join: "SELECT * FROM movies a JOIN genres a ON TRUE",
     from_duplicate: "SELECT * FROM movies, movies",
+    from_system: "SELECT * FROM system.storage_stats",
+    from_system_lookup: "SELECT * FROM system.storage_stats WHERE table_name = 'genres'",
+    from_system_columns: "SELECT * FROM system.columns WHERE table_name = 'studios'",
+    from_system_tables: "SELECT * FROM system.tables",
+    from_system_jobs: "SHOW JOBS",
+    from_system_settings: "SHOW CLUSTER SETTINGS",
+    from_system_unknown: "SELECT * FROM system.unknown",
 
     where_bare: "SELECT * FROM movies WHERE",
     where_true: "SELECT * FROM movies WHERE TRUE",
@@ -210,6 +218,25 @@ test_query! {
     where_field_aliased_select: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m, genres g WHERE movie_id >= 3 AND genre_id = 1",
     where_field_aliased_table: "SELECT m.id, g.id FROM movies m, genres g WHERE m.id >= 3 AND g.id = 1",
     where_join_inner: "SELECT * FROM movies, genres WHERE movies.genre_id = genres.id",
+    where_infer_range: "SELECT m.id, g.id FROM movies m JOIN genres g ON m.genre_id = g.id WHERE g.id >= 2 ORDER BY m.id",
+    where_infer_or: "SELECT m.id, g.id FROM movies m JOIN genres g ON m.genre_id = g.id WHERE g.id = 1 OR g.id = 3 ORDER BY m.id",
+    where_infer_transitive: "SELECT a.id, b.id, c.id FROM movies a JOIN movies b ON a.id = b.id JOIN movies c ON b.id = c.id WHERE c.id = 3",
+    where_infer_index: "SELECT m.title, s.name FROM movies m JOIN studios s ON m.studio_id = s.id JOIN countries c ON s.country_id = c.id WHERE c.id = 'us' ORDER BY m.title",
+    where_infer_outer: "SELECT m.id, g.id FROM movies m LEFT JOIN genres g ON m.genre_id = g.id WHERE g.id = 1 ORDER BY m.id",
+    where_join_sides: "SELECT m.title, g.name, s.name FROM movies m, genres g, studios s WHERE m.genre_id = g.id AND m.studio_id = s.id AND g.id = 1 AND s.country_id = 'us' AND m.rating > 7 ORDER BY m.title",
+    where_fold_and_false: "SELECT * FROM movies WHERE ultrahd AND FALSE",
+    where_fold_or_true: "SELECT * FROM movies WHERE ultrahd OR TRUE",
+    where_fold_not_not: "SELECT * FROM movies WHERE NOT NOT ultrahd",
+    where_fold_compare_null: "SELECT * FROM movies WHERE rating > NULL OR NULL = id",
+    where_fold_range: "SELECT * FROM movies WHERE id > 5 AND id < 3",
+    where_fold_range_equal: "SELECT * FROM movies WHERE released = 2000 AND released = 2001",
+    where_fold_range_exclusive: "SELECT * FROM movies WHERE released >= 2000 AND released < 2000",
+    where_fold_range_inclusive: "SELECT * FROM movies WHERE released >= 2015 AND released <= 2015",
+    where_fold_range_or: "SELECT * FROM movies WHERE (id < 2 OR id = 3) AND id > 5",
+    where_fold_range_mixed: "SELECT * FROM movies WHERE released > 2000 AND released < 2000.5",
+    where_fold_join: "SELECT * FROM movies m JOIN genres g ON m.genre_id = g.id WHERE g.id > 3 AND g.id < 3",
+    where_fold_join_outer: "SELECT m.id, g.id FROM movies m LEFT JOIN genres g ON m.genre_id = g.id AND g.id > 3 AND 3 > g.id WHERE m.id <= 3",
+    where_fold_having: "SELECT genre_id, COUNT(*) FROM movies GROUP BY genre_id HAVING FALSE",
 
     order: "SELECT * FROM movies ORDER BY released",
     order_asc: "SELECT * FROM movies ORDER BY released ASC",
@@ -220,11 +247,21 @@ test_query! {
     order_multi: "SELECT * FROM movies ORDER BY ultrahd ASC, id DESC",
     order_noselect: "SELECT id, title FROM movies ORDER BY released",
     order_unknown_dir: "SELECT * FROM movies ORDER BY id X",
+    order_nulls_unknown: "SELECT * FROM movies ORDER BY id NULLS X",
+    order_nulls_missing: "SELECT * FROM movies ORDER BY id NULLS",
     order_field_unknown: "SELECT * FROM movies ORDER BY unknown",
     order_field_qualified: "SELECT movies.id, title, name FROM movies, genres WHERE movies.genre_id = genres.id ORDER BY genres.name, movies.title",
     order_field_aliased: "SELECT movies.id, title, genres.name AS genre FROM movies, genres WHERE movies.genre_id = genres.id ORDER BY genre, title",
     order_field_ambiguous: "SELECT * FROM movies, genres WHERE movies.genre_id = genres.id ORDER BY id",
     order_trailing_comma: "SELECT * FROM movies ORDER BY id,",
+    order_ordinal: "SELECT id, title, released FROM movies ORDER BY 3 DESC, 2",
+    order_ordinal_all: "SELECT * FROM movies ORDER BY 5",
+    order_ordinal_aggregate: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY 2 DESC",
+    order_ordinal_zero: "SELECT id, title FROM movies ORDER BY 0",
+    order_ordinal_high: "SELECT id, title FROM movies ORDER BY 3",
+    order_ordinal_hidden: "SELECT id FROM movies ORDER BY title, 1",
+    order_alias_shadow: "SELECT id, title AS released FROM movies ORDER BY released",
+    order_alias_expr: "SELECT id, rating * 10 AS score FROM movies ORDER BY score - id DESC",
     order_aggregate: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY MAX(rating)",
     order_aggregate_noselect: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY MIN(rating)",
     order_group_by_noselect: "SELECT MAX(rating) FROM movies GROUP BY studio_id ORDER BY studio_id",
@@ -242,6 +279,11 @@ test_query! { with [
     ];
     order_float_asc: "SELECT * FROM floats ORDER BY value ASC",
     order_float_desc: "SELECT * FROM floats ORDER BY value DESC",
+    order_float_nulls_first: "SELECT * FROM floats ORDER BY value NULLS FIRST",
+    order_float_nulls_last: "SELECT * FROM floats ORDER BY value NULLS LAST",
+    order_float_asc_nulls_last: "SELECT * FROM floats ORDER BY value ASC NULLS LAST",
+    order_float_desc_nulls_first: "SELECT * FROM floats ORDER BY value DESC NULLS FIRST",
+    order_float_desc_nulls_last: "SELECT * FROM floats ORDER BY value DESC NULLS LAST",
 }
 test_query! { with [
         "CREATE TABLE integers (id INTEGER PRIMARY KEY, value INTEGER)",
@@ -272,6 +314,10 @@ test_query! {
     limit_neg: "SELECT * FROM movies LIMIT -1",
     limit_large: "SELECT * FROM movies LIMIT 9223372036854775807",
     limit_expr: "SELECT * FROM movies LIMIT 1 + 2",
+    limit_param_unbound: "SELECT * FROM movies LIMIT ?",
+    limit_param_typed_unbound: "SELECT * FROM movies LIMIT ?::INTEGER",
+    limit_param_typed_unknown: "SELECT * FROM movies LIMIT ?::FOO",
+    limit_typed: "SELECT * FROM movies LIMIT 1::INTEGER",
     limit_dynamic: "SELECT * FROM movies LIMIT 2000 - released",
     limit_offset: "SELECT * FROM movies LIMIT 2 OFFSET 1",
     limit_multi: "SELECT * FROM movies LIMIT 3, 4",
@@ -329,10 +375,18 @@ test_query! {
         WHERE m.studio_id = s.id
         ORDER BY m.rating DESC, m.released ASC, m.id ASC
     "#,
+    join_self: "SELECT a.title, b.title FROM movies a JOIN movies b ON a.genre_id = b.genre_id WHERE a.id < b.id ORDER BY a.id, b.id",
+    join_self_filter: "SELECT a.id, b.id FROM movies a, movies b WHERE a.released < 1980 AND b.released < 1980 ORDER BY a.id, b.id",
+    join_self_different: "SELECT a.id, b.id FROM movies a, movies b WHERE a.released < 1980 AND b.released > 2010 ORDER BY a.id, b.id",
+    join_build_left: "SELECT g.name, m.title FROM genres g JOIN movies m ON g.id = m.genre_id ORDER BY m.id",
+    join_build_lookup: "SELECT m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.id = 1",
+    join_build_outer: "SELECT g.name, m.title FROM genres g LEFT JOIN movies m ON g.id = m.genre_id ORDER BY m.id",
 
     join_left: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT JOIN genres g ON m.id = g.id",
     join_left_all: "SELECT * FROM movies m LEFT JOIN genres g ON m.id = g.id",
     join_left_outer: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT OUTER JOIN genres g ON m.id = g.id",
+    join_left_where: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT JOIN genres g ON m.id = g.id WHERE g.id IS NULL AND m.id < 6",
+    join_left_where_left: "SELECT m.id AS movie_id, g.id AS genre_id FROM movies m LEFT JOIN genres g ON m.id = g.id WHERE m.id >= 2 AND m.id <= 4",
     join_left_truncate: "SELECT g.id AS genre_id, m.id AS movie_id FROM genres g LEFT JOIN movies m ON m.id = g.id",
 
     join_right: "SELECT g.id AS genre_id, m.id AS movie_id FROM genres g RIGHT JOIN movies m ON m.id = g.id",
@@ -342,12 +396,89 @@ test_query! {
 
     agg_count_star: "SELECT COUNT(*) FROM movies",
     agg_expr: "SELECT SUM(rating * 10) / COUNT(*) FROM movies",
+    agg_expr_arith: "SELECT SUM(rating * released) / COUNT(*) - MIN(rating + 1), -MAX(rating) FROM movies",
+    agg_expr_duplicate: "SELECT SUM(rating), SUM(rating) / COUNT(*), COUNT(*) FROM movies",
+    agg_expr_order: "SELECT studio_id FROM movies GROUP BY studio_id ORDER BY SUM(rating) / COUNT(*) DESC",
+    agg_args_none: "SELECT SUM() FROM movies",
+    agg_args_multi: "SELECT SUM(id, rating) FROM movies",
+    agg_args_none_order: "SELECT id FROM movies ORDER BY COUNT()",
+    agg_where: "SELECT COUNT(*) FROM movies WHERE SUM(rating) > 1",
     agg_nested: "SELECT MAX(MIN(rating)) FROM movies",
+    agg_nested_expr: "SELECT MAX(rating + MIN(rating)) FROM movies",
     agg_ungrouped: "SELECT studio_id, COUNT(*) FROM movies",
     agg_norows: "SELECT MIN(id), MAX(id), SUM(id), COUNT(id), AVG(id) FROM movies WHERE FALSE",
     agg_norows_group: "SELECT MIN(id), MAX(id), SUM(id), COUNT(id), AVG(id) FROM movies WHERE FALSE GROUP BY id",
     agg_const: "SELECT MIN(3), MAX(3), SUM(3), COUNT(3), AVG(3)",
     agg_const_from: "SELECT MIN(3), MAX(3), SUM(3), COUNT(3), AVG(3) FROM genres",
+
+    comment: "SELECT /* all */ * FROM /* the */ movies WHERE id = 3 /* movies */",
+    comment_unterminated: "SELECT /* all * FROM movies",
+
+    hint_no_index: "SELECT /*+ NO_INDEX(movies) */ * FROM movies WHERE id = 3",
+    hint_no_index_alias: "SELECT /*+ NO_INDEX(m) */ m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE g.id = 1 AND m.genre_id = 1",
+    hint_no_index_multi: "SELECT /*+ NO_INDEX(m, g) */ m.title, g.name FROM movies m JOIN genres g ON m.genre_id = g.id WHERE g.id = 1 AND m.genre_id = 1",
+    hint_no_index_unaliased: "SELECT /*+ NO_INDEX(movies) */ * FROM movies m WHERE id = 3",
+    hint_no_index_unknown: "SELECT /*+ NO_INDEX(unknown) */ * FROM movies",
+    hint_join_order: "SELECT /*+ JOIN_ORDER(g, m) */ m.title, g.name FROM movies m, genres g WHERE m.genre_id = g.id AND m.id <= 3",
+    hint_join_order_all: "SELECT /*+ JOIN_ORDER(g, m) */ * FROM movies m, genres g WHERE m.genre_id = g.id AND m.id <= 3",
+    hint_join_order_partial: "SELECT /*+ JOIN_ORDER(s) */ m.title, g.name, s.name FROM movies m, genres g, studios s WHERE m.genre_id = g.id AND m.studio_id = s.id AND m.id <= 3",
+    hint_join_order_explicit: "SELECT /*+ JOIN_ORDER(s, g) */ m.title, g.name, s.name FROM movies m JOIN genres g ON m.genre_id = g.id, studios s WHERE m.studio_id = s.id AND m.id <= 3",
+    hint_join_order_duplicate: "SELECT /*+ JOIN_ORDER(m, g, m) */ * FROM movies m, genres g",
+    hint_join_order_explicit_split: "SELECT /*+ JOIN_ORDER(g, s, m) */ * FROM movies m JOIN genres g ON m.genre_id = g.id, studios s",
+    hint_join_order_unknown: "SELECT /*+ JOIN_ORDER(unknown) */ * FROM movies",
+    hint_join_order_multi: "SELECT /*+ JOIN_ORDER(g, m) */ /*+ JOIN_ORDER(m, g) */ * FROM movies m, genres g",
+    hint_audit_outer_joins: "SELECT /*+ AUDIT_OUTER_JOINS */ m.id, g.id FROM movies m LEFT JOIN genres g ON m.genre_id = g.id AND g.id > 1 WHERE g.id IS NULL OR m.id > 8 ORDER BY m.id",
+    hint_audit_outer_joins_limit: "SELECT /*+ AUDIT_OUTER_JOINS */ g.name, m.title FROM movies m RIGHT JOIN genres g ON m.genre_id = g.id AND m.rating > 8 WHERE g.id <= 3 LIMIT 3",
+    hint_audit_outer_joins_multi: r#"
+        SELECT /*+ AUDIT_OUTER_JOINS */ m.title, g.name, s.name
+        FROM movies m
+            LEFT JOIN genres g ON m.genre_id = g.id AND m.released > 2000
+            LEFT JOIN studios s ON m.studio_id = s.id AND s.country_id = 'us'
+        WHERE g.id IS NOT NULL AND s.id IS NULL
+        ORDER BY m.title"#,
+    hint_audit_outer_joins_invalid: "SELECT /*+ AUDIT_OUTER_JOINS(m) */ * FROM movies m",
+    hint_wrapping_arithmetic: "SELECT /*+ WRAPPING_ARITHMETIC */ id, released * 4611686018427387904 FROM movies WHERE -id + 1 > -3 ORDER BY id",
+    hint_wrapping_arithmetic_invalid: "SELECT /*+ WRAPPING_ARITHMETIC(m) */ * FROM movies m",
+    hint_multi: "SELECT /*+ NO_INDEX(g), JOIN_ORDER(g, m) */ m.title, g.name FROM movies m, genres g WHERE m.genre_id = g.id AND g.id = 1 ORDER BY m.title",
+    hint_empty: "SELECT /*+ */ * FROM movies WHERE id = 3",
+    hint_unknown: "SELECT /*+ UNKNOWN(movies) */ * FROM movies",
+    hint_invalid: "SELECT /*+ NO_INDEX */ * FROM movies",
+    hint_misplaced: "SELECT * FROM movies /*+ NO_INDEX(movies) */ WHERE id = 3",
+}
+test_query! { with [
+        "CREATE TABLE numbers (id INTEGER PRIMARY KEY, value INTEGER)",
+        "INSERT INTO numbers VALUES
+            (1, 2), (2, 4), (3, 6), (4, 8), (5, 10), (6, 12), (7, 14),
+            (8, 16), (9, 18), (10, 20), (11, 22), (12, 24), (13, 26), (14, 28),
+            (15, 30), (16, 32), (17, 34), (18, 36), (19, 38), (20, 40), (21, NULL)",
+    ];
+    join_runtime_bloom: "SELECT m.id, n.id FROM movies m JOIN numbers n ON m.id = n.value ORDER BY m.id",
+    join_runtime_bloom_right: "SELECT a.id, b.id FROM numbers a JOIN numbers b ON a.value = b.id WHERE b.id > 1 ORDER BY a.id",
+    join_runtime_in: "SELECT m.id, n.id FROM movies m JOIN numbers n ON m.id = n.value WHERE n.id <= 3 ORDER BY m.id",
+}
+test_query! { with [
+        "CREATE TABLE events (id INTEGER PRIMARY KEY, name STRING) PARTITION BY RANGE (id) (
+            PARTITION p1 VALUES LESS THAN (10),
+            PARTITION p2 VALUES LESS THAN (20),
+            PARTITION p3 VALUES LESS THAN (100)
+        )",
+        "INSERT INTO events VALUES (1, 'a'), (5, 'b'), (10, 'c'), (15, 'd'), (20, 'e'), (30, 'f')",
+    ];
+    partition_scan: "SELECT * FROM events",
+    partition_prune_range: "SELECT * FROM events WHERE id >= 10 AND id < 20",
+    partition_prune_or: "SELECT * FROM events WHERE id < 10 OR id > 25",
+    partition_prune_none: "SELECT * FROM events WHERE id >= 100",
+    partition_prune_other: "SELECT * FROM events WHERE id > 12 AND name != 'e'",
+    partition_prune_key: "SELECT * FROM events WHERE id = 15",
+    partition_prune_join: "SELECT e.id, e.name, m.title FROM events e JOIN movies m ON e.id = m.id WHERE e.id >= 10",
+}
+test_query! {
+    system_columns: "SELECT id, title, _created_version, _updated_version FROM movies WHERE id < 3",
+    system_columns_star: "SELECT * FROM genres WHERE _updated_version >= _created_version",
+    system_columns_order: "SELECT * FROM genres ORDER BY _created_version DESC, id",
+    system_columns_join: "SELECT m.title, g.name, g._updated_version FROM movies m JOIN genres g ON m.genre_id = g.id AND m._created_version = g._created_version WHERE m.id < 3",
+    system_columns_ambiguous: "SELECT _created_version FROM movies m JOIN genres g ON m.genre_id = g.id",
+    system_columns_system_table: "SELECT name, _created_version FROM system.settings WHERE name = 'mvcc_retention_ms'",
 }
 test_query! { with [
         "CREATE TABLE booleans (id INTEGER PRIMARY KEY, b BOOLEAN)",
@@ -401,6 +532,15 @@ test_query! {
     group_expr_aggr_selfref: "SELECT studio_id, SUM(rating * 10) / COUNT(*) + studio_id FROM movies GROUP BY studio_id ORDER BY studio_id",
     group_expr_aggr_nogroupref: "SELECT studio_id, SUM(rating * 10) / COUNT(*) + id FROM movies GROUP BY studio_id ORDER BY studio_id",
     group_expr_multigroup: "SELECT studio_id + genre_id AS multi, MAX(rating) AS rating FROM movies GROUP BY studio_id, genre_id ORDER BY rating, multi",
+    group_ordinal: "SELECT studio_id, MAX(rating) FROM movies GROUP BY 1 ORDER BY 1",
+    group_ordinal_expr: "SELECT MAX(rating), released / 10 FROM movies GROUP BY 2 ORDER BY 2",
+    group_ordinal_multi: "SELECT studio_id, genre_id, COUNT(*) FROM movies GROUP BY 2, 1 ORDER BY 1, 2",
+    group_ordinal_aggregate: "SELECT studio_id, MAX(rating) FROM movies GROUP BY 2",
+    group_ordinal_zero: "SELECT studio_id, MAX(rating) FROM movies GROUP BY 0",
+    group_ordinal_high: "SELECT studio_id, MAX(rating) FROM movies GROUP BY 3",
+    group_alias_field: "SELECT s.name AS name, COUNT(*) FROM movies m JOIN studios s ON m.studio_id = s.id GROUP BY name ORDER BY name",
+    group_alias_ambiguous: "SELECT title AS name, COUNT(*) FROM movies m JOIN genres g ON m.genre_id = g.id GROUP BY name",
+    group_alias_ambiguous_aggregate: "SELECT studio_id, COUNT(*) AS id FROM movies GROUP BY id",
 
     having: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING rating > 8 ORDER BY studio_id",
     having_aggr: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id HAVING MIN(rating) > 7 ORDER BY studio_id",
@@ -408,5 +548,213 @@ test_query! {
     having_aggr_nested: "SELECT studio_id, MAX(rating) AS best FROM movies GROUP BY studio_id HAVING MIN(best) > 7 ORDER BY studio_id",
     having_nogroup: "SELECT id, rating FROM movies HAVING rating > 8 ORDER BY id",
     having_noselect: "SELECT studio_id FROM movies GROUP BY studio_id HAVING MAX(rating) > 8 ORDER BY studio_id",
+    having_group_join: "SELECT g.name, COUNT(*) FROM movies m JOIN genres g ON m.genre_id = g.id GROUP BY g.name HAVING g.name = 'Action'",
+    having_group_partial: "SELECT released / 10 AS decade, COUNT(*) FROM movies GROUP BY decade HAVING decade >= 200 AND COUNT(*) > 1 ORDER BY decade",
     having_noaggr: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING studio_id >= 3 ORDER BY studio_id",
 }
+
+#[test]
+fn statement_stats() -> Result<()> {
+    let engine = super::setup(vec!["CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING)"])?;
+    let mut session = engine.session()?;
+    session.execute("INSERT INTO test VALUES (1, 'a'), (2, 'b')")?;
+    for id in 1..=3 {
+        let result = session.execute(&format!("SELECT * FROM test WHERE id >= {}", id))?;
+        if let ResultSet::Query { rows, .. } = result {
+            rows.collect::<Result<Vec<_>>>()?;
+        }
+    }
+    assert!(session.execute("SELECT * FROM missing").is_err());
+    engine.session()?.execute("DELETE FROM test")?;
+
+    let stats = |session: &toydb::sql::engine::Session<_>| -> Result<Vec<(String, u64, u64)>> {
+        Ok(session
+            .statement_stats()
+            .get()?
+            .into_iter()
+            .map(|(fingerprint, stat)| (fingerprint, stat.calls, stat.rows))
+            .collect())
+    };
+    let expect = vec![
+        ("INSERT INTO test VALUES (?, ?), (?, ?)".to_string(), 1, 2),
+        ("SELECT * FROM test WHERE id >= ?".to_string(), 3, 3),
+    ];
+    assert_eq!(stats(&session)?, expect);
+
+    // The system table contains statistics for all sessions, including the setup session.
+    let result = engine.session()?.execute(
+        "SELECT fingerprint, calls, rows, max_ms >= p50_ms FROM system.statement_stats \
+         WHERE fingerprint LIKE 'SELECT%' OR fingerprint LIKE 'DELETE%'",
+    )?;
+    let rows = match result {
+        ResultSet::Query { rows, .. } => rows.collect::<Result<Vec<_>>>()?,
+        result => panic!("Unexpected result {:?}", result),
+    };
+    assert_eq!(
+        rows,
+        vec![
+            vec!["DELETE FROM test".into(), 1i64.into(), 2i64.into(), true.into()],
+            vec!["SELECT * FROM test WHERE id >= ?".into(), 3i64.into(), 3i64.into(), true.into()],
+        ] as Vec<Row>
+    );
+    Ok(())
+}
+
+#[test]
+fn query_cache() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING)",
+        "INSERT INTO test VALUES (1, 'a'), (2, 'b')",
+    ])?;
+    let mut session = engine.session()?;
+    let query = |session: &mut toydb::sql::engine::Session<_>,
+                 query: &str,
+                 params: &[Value]|
+     -> Result<Vec<Row>> {
+        match session.execute_params(query, params)? {
+            ResultSet::Query { rows, .. } => rows.collect(),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    };
+    let cache = engine.query_cache();
+    let select = "SELECT name FROM test WHERE id >= ? ORDER BY id";
+    let (a, b): (Row, Row) = (vec!["a".into()], vec!["b".into()]);
+
+    // The cache is disabled by default.
+    assert_eq!(query(&mut session, select, &[1.into()])?, vec![a.clone(), b.clone()]);
+    assert_eq!(query(&mut session, select, &[1.into()])?, vec![a.clone(), b.clone()]);
+    assert_eq!(cache.hits_misses()?, (0, 0));
+
+    // Once enabled, results are cached by query and parameters.
+    engine.session()?.execute("SET CLUSTER SETTING query_cache_entries = 10")?;
+    assert_eq!(query(&mut session, select, &[1.into()])?, vec![a.clone(), b.clone()]);
+    assert_eq!(query(&mut session, select, &[1.into()])?, vec![a.clone(), b.clone()]);
+    assert_eq!(query(&mut session, select, &[2.into()])?, vec![b.clone()]);
+    assert_eq!(cache.hits_misses()?, (1, 2));
+    assert_eq!(cache.len()?, 2);
+
+    // Committed writes advance the data version, invalidating the cache.
+    engine.session()?.execute("UPDATE test SET name = 'c' WHERE id = 2")?;
+    assert_eq!(query(&mut session, select, &[1.into()])?, vec![a.clone(), vec!["c".into()]]);
+    assert_eq!(cache.hits_misses()?, (1, 3));
+    assert_eq!(cache.len()?, 1);
+
+    // Rolled back writes and read-only transactions don't.
+    let mut other = engine.session()?;
+    other.execute("BEGIN")?;
+    other.execute("DELETE FROM test")?;
+    other.execute("ROLLBACK")?;
+    other.execute("SELECT * FROM test")?;
+    assert_eq!(query(&mut session, select, &[1.into()])?, vec![a.clone(), vec!["c".into()]]);
+    assert_eq!(cache.hits_misses()?, (2, 4));
+
+    // Queries in explicit transactions, and queries of system tables, aren't cached.
+    session.execute("BEGIN")?;
+    query(&mut session, select, &[1.into()])?;
+    session.execute("COMMIT")?;
+    query(&mut session, "SELECT * FROM system.tables", &[])?;
+    assert_eq!(cache.hits_misses()?, (2, 4));
+
+    // Sessions with a role are cached separately, since they may see different rows.
+    let mut role = engine.session()?.with_role("reader");
+    query(&mut role, select, &[1.into()])?;
+    assert_eq!(cache.hits_misses()?, (2, 5));
+    Ok(())
+}
+
+/// Hash joins switch their build source while executing if it turns out to be larger than the
+/// probe source, here a join whose row count isn't estimated.
+#[test]
+fn hash_join_replan() -> Result<()> {
+    let ids = (1..=1500).map(|i| format!("({}, {})", i, i)).collect::<Vec<_>>().join(", ");
+    let c = (1..=1100).map(|i| format!("({}, {})", i, i % 10 + 1)).collect::<Vec<_>>().join(", ");
+    let engine = super::setup(vec![
+        "CREATE TABLE a (id INTEGER PRIMARY KEY, value INTEGER)",
+        "CREATE TABLE b (id INTEGER PRIMARY KEY, value INTEGER)",
+        "CREATE TABLE c (id INTEGER PRIMARY KEY, a_id INTEGER)",
+        &format!("INSERT INTO a VALUES {}", ids),
+        &format!("INSERT INTO b VALUES {}", ids),
+        &format!("INSERT INTO c VALUES {}", c),
+    ])?;
+    let mut session = engine.session()?;
+    let mut query = |query: &str| -> Result<Vec<Row>> {
+        match session.execute(query)? {
+            ResultSet::Query { rows, .. } => rows.collect(),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    };
+    let join = "FROM a JOIN b ON a.id = b.id JOIN c ON c.a_id = a.id";
+
+    // The top join builds from c, but only a few rows of a ⋈ b match the filter, so it switches.
+    assert_eq!(
+        query(&format!(
+            "SELECT a.id, COUNT(*) {} WHERE a.value <= 2 GROUP BY a.id ORDER BY a.id",
+            join
+        ))?,
+        vec![vec![1i64.into(), 110i64.into()], vec![2i64.into(), 110i64.into()]] as Vec<Row>
+    );
+    assert_eq!(
+        query(&format!(
+            "SELECT c.id, c.a_id, a.id, b.id {} WHERE a.value = 1 ORDER BY c.id LIMIT 2",
+            join
+        ))?,
+        vec![
+            vec![10i64.into(), 1i64.into(), 1i64.into(), 1i64.into()],
+            vec![20i64.into(), 1i64.into(), 1i64.into(), 1i64.into()],
+        ] as Vec<Row>
+    );
+
+    // Without the filter, a ⋈ b has 1500 rows, so it keeps building from c.
+    assert_eq!(
+        query(&format!("SELECT COUNT(*), SUM(c.id) {}", join))?,
+        vec![vec![1100i64.into(), 605550i64.into()]] as Vec<Row>
+    );
+    Ok(())
+}
+
+#[test]
+fn cursors() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING)",
+        "INSERT INTO test VALUES (1, 'a'), (2, 'b'), (3, 'c')",
+    ])?;
+    let mut session = engine.session()?;
+    let fetch = |session: &mut toydb::sql::engine::Session<_>, query: &str| -> Result<Vec<Row>> {
+        match session.execute(query)? {
+            ResultSet::Query { rows, .. } => rows.collect(),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    };
+
+    // Cursors can only be used in transactions.
+    assert!(session.execute("DECLARE c CURSOR FOR SELECT * FROM test").is_err());
+    assert!(session.execute("FETCH c").is_err());
+
+    session.execute("BEGIN")?;
+    assert_eq!(
+        session.execute("DECLARE c CURSOR FOR SELECT * FROM test ORDER BY id")?,
+        ResultSet::Declare { name: "c".into() }
+    );
+    assert!(session.execute("DECLARE c CURSOR FOR SELECT * FROM test").is_err());
+    assert!(session.execute("DECLARE d CURSOR FOR DELETE FROM test").is_err());
+
+    // Changes made after the cursor is declared are not visible to it.
+    session.execute("DELETE FROM test WHERE id = 3")?;
+    assert_eq!(fetch(&mut session, "FETCH c")?, vec![vec![1i64.into(), "a".into()]] as Vec<Row>);
+    assert_eq!(
+        fetch(&mut session, "FETCH 5 FROM c")?,
+        vec![vec![2i64.into(), "b".into()], vec![3i64.into(), "c".into()]] as Vec<Row>
+    );
+    assert_eq!(fetch(&mut session, "FETCH 5 FROM c")?, Vec::<Row>::new());
+    assert_eq!(session.execute("CLOSE c")?, ResultSet::Close { name: "c".into() });
+    assert!(session.execute("FETCH c").is_err());
+    assert!(session.execute("CLOSE c").is_err());
+
+    // Cursors are closed when the transaction ends.
+    session.execute("DECLARE c CURSOR FOR SELECT id FROM test")?;
+    session.execute("COMMIT")?;
+    session.execute("BEGIN")?;
+    assert!(session.execute("FETCH c").is_err());
+    session.execute("ROLLBACK")?;
+    Ok(())
+}
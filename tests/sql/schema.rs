@@ -1,7 +1,9 @@
 ///! Schema-related tests, using an in-memory database against golden files in tests/sql/chema/
-use toydb::error::Result;
-use toydb::sql::engine::{Engine as _, Mode, Transaction as _};
-use toydb::sql::schema::Catalog as _;
+use toydb::error::{Error, Result};
+use toydb::sql::engine::{Engine as _, Mode, Session, Transaction as _, KV};
+use toydb::sql::execution::ResultSet;
+use toydb::sql::schema::{Catalog as _, Column, SchemaState};
+use toydb::sql::types::{DataType, Value};
 
 use goldenfile::Mint;
 use std::io::Write;
@@ -72,6 +74,12 @@ test_schema! {
     "#,
     create_table_datatype_missing: "CREATE TABLE name (id)",
     create_table_datatype_null: "CREATE TABLE name (id INTEGER PRIMARY KEY, value NULL)",
+    create_table_datatype_varchar: "CREATE TABLE name (id VARCHAR(3) PRIMARY KEY, value VARCHAR(1024) DEFAULT 'abc')",
+    create_table_datatype_varchar_zero: "CREATE TABLE name (id INTEGER PRIMARY KEY, value VARCHAR(0))",
+    create_table_datatype_varchar_large: "CREATE TABLE name (id INTEGER PRIMARY KEY, value VARCHAR(1025))",
+    create_table_datatype_varchar_negative: "CREATE TABLE name (id INTEGER PRIMARY KEY, value VARCHAR(-1))",
+    create_table_datatype_varchar_unclosed: "CREATE TABLE name (id INTEGER PRIMARY KEY, value VARCHAR(3)",
+    create_table_default_conflict_varchar_length: "CREATE TABLE name (id INTEGER PRIMARY KEY, value VARCHAR(2) DEFAULT 'abc')",
 
     create_table_name_alphanumeric: "CREATE TABLE a_123 (id INTEGER PRIMARY KEY)",
     create_table_name_case: "CREATE TABLE mIxEd_cAsE (ÄÅÆ STRING PRIMARY KEY)",
@@ -80,6 +88,8 @@ test_schema! {
     create_table_name_japanese: "CREATE TABLE 表 (身元 INTEGER PRIMARY KEY, 名前 STRING)",
     create_table_name_keyword: "CREATE TABLE table (id INTEGER PRIMARY KEY)",
     create_table_name_keyword_quoted: r#"CREATE TABLE "table" (id INTEGER PRIMARY KEY)"#,
+    create_table_name_keyword_nonreserved: "CREATE TABLE history (key INTEGER PRIMARY KEY, text TEXT, time INTEGER)",
+    create_table_name_quote_case: r#"CREATE TABLE "MiXeD" ("Id" INTEGER PRIMARY KEY)"#,
     create_table_name_missing: "CREATE TABLE (id INTEGER PRIMARY KEY)",
     create_table_name_quote_single: r#"CREATE TABLE 'name' (id INTEGER PRIMARY KEY)"#,
     create_table_name_quote_double: r#"CREATE TABLE "name" (id INTEGER PRIMARY KEY)"#,
@@ -87,6 +97,7 @@ test_schema! {
     create_table_name_quote_double_escaped: r#"CREATE TABLE "name with "" quote" (id INTEGER PRIMARY KEY)"#,
     create_table_name_quote_double_single: r#"CREATE TABLE "name with ' quote" (id INTEGER PRIMARY KEY)"#,
     create_table_name_underscore_prefix: "CREATE TABLE _name (id INTEGER PRIMARY KEY)",
+    create_table_name_system_column: "CREATE TABLE name (id INTEGER PRIMARY KEY, _created_version INTEGER)",
 
     create_table_columns_empty: "CREATE TABLE name ()",
     create_table_columns_missing: "CREATE TABLE name",
@@ -112,6 +123,7 @@ test_schema! {
     create_table_default_conflict: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING DEFAULT 7)",
     create_table_default_conflict_float_integer: "CREATE TABLE name (id INTEGER PRIMARY KEY, value FLOAT DEFAULT 7)",
     create_table_default_conflict_integer_float: "CREATE TABLE name (id INTEGER PRIMARY KEY, value INTEGER DEFAULT 3.14)",
+    create_table_default_conflict_int_range: "CREATE TABLE name (id INTEGER PRIMARY KEY, value INT DEFAULT 2147483648)",
 
     create_table_index: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX)",
     create_table_index_pk: "CREATE TABLE name (id INTEGER PRIMARY KEY INDEX, value STRING)",
@@ -120,6 +132,19 @@ test_schema! {
     create_table_unique_null: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING NULL UNIQUE)",
     create_table_unique_not_null: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING NOT NULL UNIQUE)",
     create_table_unique_default: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING DEFAULT 'foo' UNIQUE)",
+
+    create_table_partition: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING) PARTITION BY RANGE (id) (PARTITION p1 VALUES LESS THAN (10), PARTITION p2 VALUES LESS THAN (5 * 4), PARTITION p3 VALUES LESS THAN (MAXVALUE))",
+    create_table_partition_bounded: "CREATE TABLE name (id STRING PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION a VALUES LESS THAN ('a'), PARTITION b VALUES LESS THAN ('b'))",
+    create_table_partition_empty: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) ()",
+    create_table_partition_column_missing: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (unknown) (PARTITION p VALUES LESS THAN (MAXVALUE))",
+    create_table_partition_column_pk: "CREATE TABLE name (id INTEGER PRIMARY KEY, value INTEGER) PARTITION BY RANGE (value) (PARTITION p VALUES LESS THAN (MAXVALUE))",
+    create_table_partition_duplicate: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p VALUES LESS THAN (1), PARTITION p VALUES LESS THAN (2))",
+    create_table_partition_maxvalue_middle: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p1 VALUES LESS THAN (MAXVALUE), PARTITION p2 VALUES LESS THAN (2))",
+    create_table_partition_bound_datatype: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p VALUES LESS THAN (1.5))",
+    create_table_partition_bound_null: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p VALUES LESS THAN (NULL))",
+    create_table_partition_bound_nan: "CREATE TABLE name (id FLOAT PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p VALUES LESS THAN (NAN))",
+    create_table_partition_bound_unordered: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p1 VALUES LESS THAN (2), PARTITION p2 VALUES LESS THAN (1))",
+    create_table_partition_bound_missing: "CREATE TABLE name (id INTEGER PRIMARY KEY) PARTITION BY RANGE (id) (PARTITION p VALUES LESS THAN ())",
 }
 test_schema! { with ["CREATE TABLE test (id INTEGER PRIMARY KEY)"];
     create_table_exists: "CREATE TABLE test (id INTEGER PRIMARY KEY)",
@@ -215,6 +240,30 @@ test_schema! { with [
     insert_string_float: r#"INSERT INTO types (id, "string") VALUES (0, 3.14)"#,
     insert_string_integer: r#"INSERT INTO types (id, "string") VALUES (0, 1)"#,
 }
+
+test_schema! { with [
+        r#"CREATE TABLE ints (id INTEGER PRIMARY KEY, "int" INT, "bigint" BIGINT)"#
+    ];
+    insert_int: r#"INSERT INTO ints (id, "int") VALUES (0, 1)"#,
+    insert_int_max: r#"INSERT INTO ints (id, "int") VALUES (0, 2147483647)"#,
+    insert_int_min: r#"INSERT INTO ints (id, "int") VALUES (0, -2147483648)"#,
+    insert_int_overflow: r#"INSERT INTO ints (id, "int") VALUES (0, 2147483648)"#,
+    insert_int_underflow: r#"INSERT INTO ints (id, "int") VALUES (0, -2147483649)"#,
+    insert_int_float: r#"INSERT INTO ints (id, "int") VALUES (0, 1.0)"#,
+    insert_bigint_max: r#"INSERT INTO ints (id, "bigint") VALUES (0, 9223372036854775807)"#,
+}
+
+test_schema! { with [
+        r#"CREATE TABLE strings (id INTEGER PRIMARY KEY, "varchar" VARCHAR(3))"#,
+        r#"INSERT INTO strings VALUES (1, 'abc')"#,
+    ];
+    insert_varchar: r#"INSERT INTO strings VALUES (0, 'ab')"#,
+    insert_varchar_max: r#"INSERT INTO strings VALUES (0, 'abc')"#,
+    insert_varchar_long: r#"INSERT INTO strings VALUES (0, 'abcd')"#,
+    insert_varchar_unicode: r#"INSERT INTO strings VALUES (0, '👋👋👋')"#,
+    insert_varchar_integer: r#"INSERT INTO strings VALUES (0, 1)"#,
+    update_varchar_long: r#"UPDATE strings SET "varchar" = 'abcd' WHERE id = 1"#,
+}
 test_schema! { with [
         "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER)",
         "INSERT INTO test VALUES (1, 7)",
@@ -444,3 +493,689 @@ test_schema! { with [
     update_index_pk: "UPDATE test SET id = 4 WHERE id = 1",
     update_index_null: "UPDATE test SET name = NULL WHERE id = 3",
 }
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING INDEX, value INTEGER)",
+        "INSERT INTO test VALUES (1, 'a', 101), (2, 'b', 102), (3, 'b', NULL)",
+    ];
+    alter_table_add_column: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 7",
+    alter_table_add_column_bare: "ALTER TABLE test ADD COLUMN",
+    alter_table_add_column_default_type: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 'x'",
+    alter_table_add_column_exists: "ALTER TABLE test ADD COLUMN value INTEGER",
+    alter_table_add_column_index: "ALTER TABLE test ADD COLUMN extra STRING DEFAULT 'x' INDEX",
+    alter_table_add_column_not_null: "ALTER TABLE test ADD COLUMN extra INTEGER NOT NULL",
+    alter_table_add_column_not_null_default: "ALTER TABLE test ADD COLUMN extra INTEGER NOT NULL DEFAULT 0",
+    alter_table_add_column_nullable: "ALTER TABLE test ADD extra BOOLEAN",
+    alter_table_add_column_primary_key: "ALTER TABLE test ADD COLUMN extra INTEGER PRIMARY KEY",
+    alter_table_add_column_ref: "ALTER TABLE test ADD COLUMN parent_id INTEGER REFERENCES test",
+    alter_table_add_column_ref_default: "ALTER TABLE test ADD COLUMN parent_id INTEGER DEFAULT 1 REFERENCES test",
+    alter_table_add_column_unique: "ALTER TABLE test ADD COLUMN extra INTEGER UNIQUE",
+    alter_table_alter_column_bare: "ALTER TABLE test ALTER COLUMN value SET",
+    alter_table_alter_column_drop_default: "ALTER TABLE test ALTER COLUMN value DROP DEFAULT",
+    alter_table_alter_column_drop_not_null_pk: "ALTER TABLE test ALTER COLUMN id DROP NOT NULL",
+    alter_table_alter_column_missing: "ALTER TABLE test ALTER COLUMN missing SET NOT NULL",
+    alter_table_alter_column_set_default: "ALTER TABLE test ALTER COLUMN value SET DEFAULT 7",
+    alter_table_alter_column_set_default_expr: "ALTER TABLE test ALTER value SET DEFAULT 1 + 2",
+    alter_table_alter_column_set_default_type: "ALTER TABLE test ALTER COLUMN value SET DEFAULT 'x'",
+    alter_table_alter_column_set_not_null: "ALTER TABLE test ALTER name SET NOT NULL",
+    alter_table_alter_column_set_not_null_violated: "ALTER TABLE test ALTER COLUMN value SET NOT NULL",
+    alter_table_missing: "ALTER TABLE missing ADD COLUMN extra INTEGER",
+
+    create_index: "CREATE INDEX ON test (value)",
+    create_index_bare: "CREATE INDEX ON test",
+    create_index_column_missing: "CREATE INDEX ON test (missing)",
+    create_index_exists: "CREATE INDEX ON test (name)",
+    create_index_primary_key: "CREATE INDEX ON test (id)",
+    create_index_table_missing: "CREATE INDEX ON missing (value)",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER NOT NULL DEFAULT 0)",
+        "INSERT INTO test VALUES (1, 1), (2, 2)",
+    ];
+    alter_table_alter_column_not_null_drop_default: "ALTER TABLE test ALTER COLUMN value DROP DEFAULT",
+    alter_table_alter_column_not_null_drop_not_null: "ALTER TABLE test ALTER COLUMN value DROP NOT NULL",
+    alter_table_alter_column_not_null_set_default_null: "ALTER TABLE test ALTER COLUMN value SET DEFAULT NULL",
+    alter_table_alter_column_not_null_set_not_null: "ALTER TABLE test ALTER COLUMN value SET NOT NULL",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING)",
+        "COMMENT ON TABLE test IS 'Test data'",
+        "COMMENT ON COLUMN test.name IS 'The name'",
+    ];
+    comment_bare: "COMMENT ON TABLE test IS",
+    comment_column: "COMMENT ON COLUMN test.id IS 'The ''unique'' ID'",
+    comment_column_missing: "COMMENT ON COLUMN test.missing IS 'x'",
+    comment_column_null: "COMMENT ON COLUMN test.name IS NULL",
+    comment_table: "COMMENT ON TABLE test IS 'Updated'",
+    comment_table_missing: "COMMENT ON TABLE missing IS 'x'",
+    comment_table_null: "COMMENT ON TABLE test IS NULL",
+    comment_table_system: r#"COMMENT ON TABLE "system.audit" IS 'x'"#,
+}
+
+/// Returns a nullable integer column with a NULL default.
+fn column(name: &str, index: bool) -> Column {
+    Column {
+        id: 0,
+        name: name.into(),
+        datatype: DataType::Integer,
+        primary_key: false,
+        nullable: true,
+        default: Some(Value::Null),
+        missing: None,
+        unique: false,
+        references: None,
+        references_id: None,
+        index,
+        comment: None,
+    }
+}
+
+/// Schema changes wait for transactions that don't see them, and are only visible to reads once
+/// all rows have been backfilled, including rows written concurrently.
+#[test]
+fn schema_change_concurrent() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER)",
+        "INSERT INTO test VALUES (1, 10), (2, 20)",
+    ])?;
+
+    let mut old = engine.begin(Mode::ReadWrite)?;
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    txn.add_column("test", column("extra", true))?;
+    txn.commit()?;
+
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    assert_eq!(txn.advance_schema_change("test", 0)?, Some(SchemaState::Backfilling));
+    txn.commit()?;
+
+    // The old transaction doesn't see the change, so backfilling waits for it.
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    assert_eq!(txn.advance_schema_change("test", 0)?, None);
+    txn.commit()?;
+    old.create("test", vec![Value::Integer(3), Value::Integer(30)])?;
+    old.commit()?;
+
+    // Rows written while backfilling don't expose the new column.
+    let mut session = engine.session()?;
+    session.execute("INSERT INTO test VALUES (4, 40)")?;
+    session.execute("UPDATE test SET value = 11 WHERE id = 1")?;
+    session.execute("UPDATE test SET id = 5 WHERE id = 2")?;
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert_eq!(txn.must_read_table("test")?.columns.len(), 2);
+    assert_eq!(
+        txn.read("test", &Value::Integer(4))?,
+        Some(vec![Value::Integer(4), Value::Integer(40)])
+    );
+    assert_eq!(
+        txn.read_index("test", "extra", &Value::Null),
+        Err(Error::Value("Column extra not found in table test".into()))
+    );
+    txn.rollback()?;
+
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    assert_eq!(txn.advance_schema_change("test", 0)?, Some(SchemaState::WriteVisible));
+    txn.commit()?;
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    assert_eq!(txn.advance_schema_change("test", 0)?, Some(SchemaState::ReadVisible));
+    txn.commit()?;
+
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert_eq!(txn.must_read_table("test")?.change, None);
+    assert_eq!(
+        txn.scan("test", None)?.collect::<Result<Vec<_>>>()?,
+        vec![
+            vec![Value::Integer(1), Value::Integer(11), Value::Null],
+            vec![Value::Integer(3), Value::Integer(30), Value::Null],
+            vec![Value::Integer(4), Value::Integer(40), Value::Null],
+            vec![Value::Integer(5), Value::Integer(20), Value::Null],
+        ]
+    );
+    let mut ids = txn.read_index("test", "extra", &Value::Null)?.into_iter().collect::<Vec<_>>();
+    ids.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        ids,
+        vec![Value::Integer(1), Value::Integer(3), Value::Integer(4), Value::Integer(5)]
+    );
+    txn.rollback()?;
+    Ok(())
+}
+
+/// Schema changes are backfilled in batches that commit separately, and an interrupted schema
+/// change is completed by the next schema change.
+#[test]
+fn schema_change_resume() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER)",
+        "CREATE TABLE other (id INTEGER PRIMARY KEY, value INTEGER)",
+    ])?;
+    let mut session = engine.session()?;
+    for i in 0..250 {
+        session.execute(&format!("INSERT INTO test VALUES ({}, {})", i, i % 10))?;
+    }
+
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    txn.create_index("test", "value")?;
+    txn.commit()?;
+    for state in &[SchemaState::Backfilling, SchemaState::Backfilling, SchemaState::Backfilling] {
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        assert_eq!(txn.advance_schema_change("test", 1000)?, Some(*state));
+        txn.commit()?;
+    }
+
+    // The schema change is listed as a job, with its progress. The first step only records the
+    // start time, and each following step backfills a batch of rows.
+    let mut job = match session.execute("SHOW JOBS")? {
+        ResultSet::Query { rows, .. } => rows.collect::<Result<Vec<_>>>()?,
+        result => return Err(Error::Internal(format!("Unexpected result {:?}", result))),
+    };
+    assert_eq!(job.len(), 1);
+    let eta = job[0].pop();
+    assert!(matches!(eta, Some(Value::Integer(_))));
+    assert_eq!(
+        job[0],
+        vec![
+            Value::String("test".into()),
+            Value::String("CREATE INDEX".into()),
+            Value::String("value".into()),
+            Value::String("backfilling".into()),
+            Value::Integer(200),
+            Value::Integer(250),
+            Value::Integer(1000),
+        ]
+    );
+
+    // A step that is rolled back, e.g. due to a crash, has no effect.
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    assert_eq!(txn.advance_schema_change("test", 0)?, Some(SchemaState::WriteVisible));
+    txn.rollback()?;
+
+    // Only one schema change can run on a table at a time.
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    assert_eq!(
+        txn.add_column("test", column("extra", false)),
+        Err(Error::Value("Table test already has a schema change in progress".into()))
+    );
+    txn.rollback()?;
+
+    session.execute("CREATE INDEX ON other (value)")?;
+    let txn = engine.begin(Mode::ReadOnly)?;
+    for table in txn.scan_tables()? {
+        assert_eq!(table.change, None);
+        assert!(table.get_column("value")?.index);
+    }
+    assert_eq!(txn.read_index("test", "value", &Value::Integer(7))?.len(), 25);
+    txn.rollback()?;
+
+    session.execute("BEGIN")?;
+    assert_eq!(
+        session.execute("ALTER TABLE test ADD COLUMN extra INTEGER"),
+        Err(Error::Value("Schema changes can't be run in a transaction".into()))
+    );
+    session.execute("ROLLBACK")?;
+    Ok(())
+}
+
+/// Interrupted schema changes can be completed without running another schema change, and give
+/// up with Error::Abort if older transactions don't finish before the timeout.
+#[test]
+fn schema_change_timeout() -> Result<()> {
+    let engine = super::setup(vec!["CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER)"])?;
+    let mut session = engine.session()?;
+    session.execute("INSERT INTO test VALUES (1, 1)")?;
+
+    let old = engine.begin(Mode::ReadWrite)?;
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    txn.create_index("test", "value")?;
+    txn.commit()?;
+
+    let start = std::time::Instant::now();
+    assert_eq!(
+        session.complete_schema_changes(std::time::Duration::from_millis(200)),
+        Err(Error::Abort)
+    );
+    assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert!(txn.must_read_table("test")?.change.is_some());
+    txn.rollback()?;
+
+    old.rollback()?;
+    assert_eq!(session.complete_schema_changes(std::time::Duration::from_secs(10))?, 1);
+    assert_eq!(session.complete_schema_changes(std::time::Duration::from_secs(10))?, 0);
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert_eq!(txn.must_read_table("test")?.change, None);
+    assert_eq!(txn.read_index("test", "value", &Value::Integer(1))?.len(), 1);
+    txn.rollback()?;
+    Ok(())
+}
+
+/// Backfilling and verification continue from the last processed row, across partitions, and
+/// are throttled by the backfill_rows_per_second cluster setting.
+#[test]
+fn schema_change_batches() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER) PARTITION BY RANGE (id) (
+            PARTITION low VALUES LESS THAN (120), PARTITION high VALUES LESS THAN (MAXVALUE))",
+        "CREATE TABLE other (id INTEGER PRIMARY KEY, value INTEGER)",
+    ])?;
+    let mut session = engine.session()?;
+    for i in 0..250 {
+        session.execute(&format!("INSERT INTO test VALUES ({}, {})", i, i % 10))?;
+        session.execute(&format!("INSERT INTO other VALUES ({}, {})", i, i % 10))?;
+    }
+
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    txn.create_index("test", "value")?;
+    txn.commit()?;
+    let mut states = Vec::new();
+    loop {
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let state = txn.advance_schema_change("test", 0)?;
+        txn.commit()?;
+        states.push(state);
+        if state == Some(SchemaState::ReadVisible) {
+            break;
+        }
+    }
+    assert_eq!(
+        states,
+        vec![
+            Some(SchemaState::Backfilling),
+            Some(SchemaState::Backfilling),
+            Some(SchemaState::Backfilling),
+            Some(SchemaState::WriteVisible),
+            Some(SchemaState::WriteVisible),
+            Some(SchemaState::WriteVisible),
+            Some(SchemaState::ReadVisible),
+        ]
+    );
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert_eq!(txn.read_index("test", "value", &Value::Integer(7))?.len(), 25);
+    txn.rollback()?;
+
+    // With 1000 rows per second, each of the 7 steps takes at least 100ms, except the last.
+    session.execute("SET CLUSTER SETTING backfill_rows_per_second = 1000")?;
+    let start = std::time::Instant::now();
+    session.execute("CREATE INDEX ON other (value)")?;
+    assert!(start.elapsed() >= std::time::Duration::from_millis(600));
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert_eq!(txn.read_index("other", "value", &Value::Integer(7))?.len(), 25);
+    txn.rollback()?;
+    Ok(())
+}
+
+/// Sessions cache schemas for planning, but see schema changes from other sessions as soon as
+/// they commit. Concurrent schema changes conflict, even on different tables.
+#[test]
+fn schema_cache() -> Result<()> {
+    let engine = super::setup(vec!["CREATE TABLE test (id INTEGER PRIMARY KEY)"])?;
+    let mut a = engine.session()?;
+    let mut b = engine.session()?;
+    let columns = |session: &mut Session<KV>, query: &str| -> Result<Vec<String>> {
+        match session.execute(query)? {
+            ResultSet::Query { columns, .. } => {
+                Ok(columns.into_iter().map(|c| c.name.unwrap_or_default()).collect())
+            }
+            result => Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        }
+    };
+
+    assert_eq!(columns(&mut a, "SELECT * FROM test")?, vec!["id"]);
+    assert_eq!(
+        a.execute("SELECT * FROM other"),
+        Err(Error::Value("Table other does not exist".into()))
+    );
+    b.execute("ALTER TABLE test ADD COLUMN value INTEGER")?;
+    b.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)")?;
+    assert_eq!(columns(&mut a, "SELECT * FROM test")?, vec!["id", "value"]);
+    assert_eq!(columns(&mut a, "SELECT * FROM other")?, vec!["id"]);
+
+    // Schemas cached in a transaction that is rolled back are discarded, even though another
+    // session commits a schema change with the same catalog version.
+    a.execute("BEGIN")?;
+    a.execute("CREATE TABLE new (id INTEGER PRIMARY KEY)")?;
+    assert_eq!(columns(&mut a, "SELECT * FROM new")?, vec!["id"]);
+    a.execute("ROLLBACK")?;
+    b.execute("CREATE TABLE new (id INTEGER PRIMARY KEY, name STRING)")?;
+    assert_eq!(columns(&mut a, "SELECT * FROM new")?, vec!["id", "name"]);
+
+    a.execute("BEGIN")?;
+    a.execute("DROP TABLE new")?;
+    assert_eq!(b.execute("DROP TABLE other"), Err(Error::Serialization));
+    a.execute("COMMIT")?;
+    b.execute("DROP TABLE other")?;
+    assert_eq!(
+        a.execute("SELECT * FROM other"),
+        Err(Error::Value("Table other does not exist".into()))
+    );
+    Ok(())
+}
+
+/// Cluster settings are stored in the catalog, so other sessions see changes with their next
+/// statement. Unknown settings and invalid values are rejected.
+#[test]
+fn cluster_settings() -> Result<()> {
+    let engine = super::setup(Vec::new())?;
+    let mut a = engine.session()?;
+    let mut b = engine.session()?;
+    let value = |session: &mut Session<KV>| -> Result<Value> {
+        match session
+            .execute("SELECT value FROM system.settings WHERE name = 'slow_query_threshold_ms'")?
+        {
+            ResultSet::Query { mut rows, .. } => Ok(rows.next().transpose()?.unwrap().remove(0)),
+            result => Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        }
+    };
+
+    assert_eq!(value(&mut a)?, Value::String("0".into()));
+    assert_eq!(
+        b.execute("SET CLUSTER SETTING slow_query_threshold_ms = 100")?,
+        ResultSet::SetSetting { name: "slow_query_threshold_ms".into() }
+    );
+    assert_eq!(value(&mut a)?, Value::String("100".into()));
+
+    // Setting changes are transactional.
+    b.execute("BEGIN")?;
+    b.execute("SET CLUSTER SETTING slow_query_threshold_ms = 5 * 100")?;
+    assert_eq!(value(&mut b)?, Value::String("500".into()));
+    assert_eq!(value(&mut a)?, Value::String("100".into()));
+    b.execute("ROLLBACK")?;
+    assert_eq!(value(&mut b)?, Value::String("100".into()));
+
+    b.execute("SET CLUSTER SETTING slow_query_threshold_ms = DEFAULT")?;
+    assert_eq!(value(&mut a)?, Value::String("0".into()));
+
+    assert_eq!(
+        a.execute("SET CLUSTER SETTING unknown = 1"),
+        Err(Error::Value("Unknown cluster setting unknown".into()))
+    );
+    assert_eq!(
+        a.execute("SET CLUSTER SETTING slow_query_threshold_ms = -1"),
+        Err(Error::Value("Invalid value -1 for cluster setting slow_query_threshold_ms".into()))
+    );
+    assert_eq!(
+        a.execute("SET CLUSTER SETTING slow_query_threshold_ms = NULL"),
+        Err(Error::Value("Invalid value NULL for cluster setting slow_query_threshold_ms".into()))
+    );
+    Ok(())
+}
+
+/// Stored functions are cached by sessions like table schemas, so other sessions see created and
+/// dropped functions with their next statement.
+#[test]
+fn stored_functions() -> Result<()> {
+    let engine = super::setup(Vec::new())?;
+    let mut a = engine.session()?;
+    let mut b = engine.session()?;
+
+    assert_eq!(a.execute("SELECT double(2)"), Err(Error::Value("Unknown function double".into())));
+    assert_eq!(
+        b.execute("CREATE FUNCTION double (x INTEGER) AS 'x * 2'")?,
+        ResultSet::CreateFunction { name: "double".into() }
+    );
+    match a.execute("SELECT double(2)")? {
+        ResultSet::Query { mut rows, .. } => {
+            assert_eq!(rows.next().transpose()?, Some(vec![Value::Integer(4)]))
+        }
+        result => return Err(Error::Internal(format!("Unexpected result {:?}", result))),
+    }
+
+    assert_eq!(
+        b.execute("DROP FUNCTION double")?,
+        ResultSet::DropFunction { name: "double".into() }
+    );
+    assert_eq!(a.execute("SELECT double(2)"), Err(Error::Value("Unknown function double".into())));
+    Ok(())
+}
+
+/// Enum types are visible to other sessions once created, and columns keep a copy of the type,
+/// which can't be dropped while in use.
+#[test]
+fn enum_types() -> Result<()> {
+    let engine = super::setup(Vec::new())?;
+    let mut a = engine.session()?;
+    let mut b = engine.session()?;
+
+    assert_eq!(
+        b.execute("CREATE TYPE mood AS ENUM ('sad', 'happy')")?,
+        ResultSet::CreateType { name: "mood".into() }
+    );
+    a.execute("CREATE TABLE people (id INTEGER PRIMARY KEY, current mood)")?;
+    a.execute("INSERT INTO people VALUES (1, 'happy')")?;
+    assert_eq!(
+        b.execute("DROP TYPE mood"),
+        Err(Error::Value("Type mood is used by table people column current".into()))
+    );
+    match b.execute("SELECT current FROM people WHERE current > 'sad'")? {
+        ResultSet::Query { mut rows, .. } => {
+            assert_eq!(
+                rows.next().transpose()?.map(|r| r[0].to_string()),
+                Some("happy".to_string())
+            )
+        }
+        result => return Err(Error::Internal(format!("Unexpected result {:?}", result))),
+    }
+
+    a.execute("DROP TABLE people")?;
+    assert_eq!(b.execute("DROP TYPE mood")?, ResultSet::DropType { name: "mood".into() });
+    assert_eq!(
+        a.execute("CREATE TABLE people (id INTEGER PRIMARY KEY, current mood)"),
+        Err(Error::Value("Unknown type mood".into()))
+    );
+    Ok(())
+}
+
+/// Row-level security policies filter the rows that roles other than the table owner can read,
+/// update, and delete. Sessions without a role are unrestricted.
+#[test]
+fn row_level_security() -> Result<()> {
+    let engine = super::setup(Vec::new())?;
+    let mut owner = engine.session()?.with_role("owner");
+    let mut alice = engine.session()?.with_role("alice");
+    let mut bob = engine.session()?.with_role("bob");
+    let mut admin = engine.session()?;
+
+    let ids = |session: &mut Session<KV>, query: &str| -> Result<Vec<Value>> {
+        match session.execute(query)? {
+            ResultSet::Query { rows, .. } => rows.map(|r| r.map(|r| r[0].clone())).collect(),
+            result => Err(Error::Internal(format!("Unexpected result {:?}", result))),
+        }
+    };
+    let ints = |ids: &[i64]| ids.iter().map(|i| Value::Integer(*i)).collect::<Vec<_>>();
+
+    owner.execute("CREATE TABLE docs (id INTEGER PRIMARY KEY, author STRING, public BOOLEAN)")?;
+    owner.execute(
+        "INSERT INTO docs VALUES (1, 'alice', FALSE), (2, 'bob', FALSE), (3, 'carol', TRUE)",
+    )?;
+
+    // Tables without policies are unrestricted.
+    assert_eq!(ids(&mut alice, "SELECT id FROM docs ORDER BY id")?, ints(&[1, 2, 3]));
+
+    // Only the owner can create and drop policies.
+    assert_eq!(
+        alice.execute("CREATE POLICY own ON docs TO alice USING (author = 'alice')"),
+        Err(Error::Value("Role alice is not the owner of table docs".into()))
+    );
+    assert_eq!(
+        owner.execute("CREATE POLICY own ON docs TO alice USING (author = 'alice')")?,
+        ResultSet::CreatePolicy { table: "docs".into(), name: "own".into() }
+    );
+    owner.execute("CREATE POLICY public ON docs TO alice USING (public)")?;
+    assert_eq!(
+        owner.execute("CREATE POLICY own ON docs TO bob USING (TRUE)"),
+        Err(Error::Value("Policy own already exists for table docs".into()))
+    );
+    assert_eq!(
+        owner.execute("CREATE POLICY bad ON docs TO bob USING (id)"),
+        Err(Error::Value("Policy predicate must be a BOOLEAN expression, found INTEGER".into()))
+    );
+
+    // Roles see rows allowed by any of their policies, and no rows without policies. The owner
+    // and sessions without a role see all rows.
+    assert_eq!(ids(&mut alice, "SELECT id FROM docs ORDER BY id")?, ints(&[1, 3]));
+    assert_eq!(ids(&mut alice, "SELECT id FROM docs WHERE id > 1")?, ints(&[3]));
+    assert_eq!(ids(&mut alice, "SELECT id FROM docs WHERE id = 2")?, ints(&[]));
+    assert_eq!(ids(&mut bob, "SELECT id FROM docs")?, ints(&[]));
+    assert_eq!(ids(&mut owner, "SELECT id FROM docs ORDER BY id")?, ints(&[1, 2, 3]));
+    assert_eq!(ids(&mut admin, "SELECT id FROM docs ORDER BY id")?, ints(&[1, 2, 3]));
+    assert_eq!(
+        ids(&mut alice, "SELECT a.id FROM docs a JOIN docs b ON a.id = b.id ORDER BY a.id")?,
+        ints(&[1, 3])
+    );
+
+    // Updates and deletes only affect visible rows.
+    assert_eq!(alice.execute("UPDATE docs SET public = TRUE")?, ResultSet::Update { count: 2 });
+    assert_eq!(bob.execute("DELETE FROM docs")?, ResultSet::Delete { count: 0 });
+    assert_eq!(ids(&mut owner, "SELECT id FROM docs WHERE public ORDER BY id")?, ints(&[1, 3]));
+
+    // Restricted roles can't read unfiltered history.
+    assert_eq!(
+        bob.execute("HISTORY FOR TABLE docs KEY 1"),
+        Err(Error::Value("Role bob is restricted by the policies of table docs".into()))
+    );
+
+    assert_eq!(
+        owner.execute("DROP POLICY own ON docs")?,
+        ResultSet::DropPolicy { table: "docs".into(), name: "own".into() }
+    );
+    assert_eq!(
+        owner.execute("DROP POLICY own ON docs"),
+        Err(Error::Value("Policy own does not exist for table docs".into()))
+    );
+    assert_eq!(ids(&mut alice, "SELECT id FROM docs ORDER BY id")?, ints(&[1, 3]));
+    owner.execute("DROP POLICY public ON docs")?;
+    assert_eq!(ids(&mut bob, "SELECT id FROM docs ORDER BY id")?, ints(&[1, 2, 3]));
+    Ok(())
+}
+
+/// Tables and columns are assigned internal IDs, which foreign keys and added columns refer to.
+/// IDs are never reused, so a recreated table doesn't see the rows of a dropped one.
+#[test]
+fn schema_ids() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE genres (id INTEGER PRIMARY KEY)",
+        "CREATE TABLE movies (id INTEGER PRIMARY KEY, genre_id INTEGER REFERENCES genres)",
+        "INSERT INTO genres VALUES (1)",
+    ])?;
+    let mut session = engine.session()?;
+    session.execute("ALTER TABLE genres ADD COLUMN parent_id INTEGER REFERENCES genres")?;
+
+    let txn = engine.begin(Mode::ReadOnly)?;
+    let genres = txn.must_read_table("genres")?;
+    let movies = txn.must_read_table("movies")?;
+    txn.rollback()?;
+    assert_eq!((genres.id, movies.id), (1, 2));
+    assert_eq!(genres.columns.iter().map(|c| c.id).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(genres.columns[1].references_id, Some(1));
+    assert_eq!(movies.columns[1].references, Some("genres".into()));
+    assert_eq!(movies.columns[1].references_id, Some(1));
+
+    session.execute("DROP TABLE movies")?;
+    session.execute("DROP TABLE genres")?;
+    session.execute("CREATE TABLE genres (id INTEGER PRIMARY KEY)")?;
+    let txn = engine.begin(Mode::ReadOnly)?;
+    assert_eq!(txn.must_read_table("genres")?.id, 3);
+    assert_eq!(txn.scan("genres", None)?.count(), 0);
+    txn.rollback()?;
+    Ok(())
+}
+
+/// Partitions are assigned IDs after their table, and rows are stored under the ID of the
+/// partition covering their primary key. Scans, changefeeds, and row history see rows across
+/// partitions, ordered by primary key.
+#[test]
+fn partition_storage() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER) PARTITION BY RANGE (id) (
+            PARTITION p1 VALUES LESS THAN (10),
+            PARTITION p2 VALUES LESS THAN (MAXVALUE)
+        )",
+        "CREATE TABLE other (id INTEGER PRIMARY KEY)",
+    ])?;
+    let txn = engine.begin(Mode::ReadOnly)?;
+    let t = txn.must_read_table("t")?;
+    assert_eq!(t.id, 1);
+    assert_eq!(
+        t.partitions.iter().map(|p| (p.id, p.name.as_str())).collect::<Vec<_>>(),
+        vec![(2, "p1"), (3, "p2")]
+    );
+    assert_eq!(txn.must_read_table("other")?.id, 4);
+    txn.rollback()?;
+
+    // Updating the primary key moves the row to another partition.
+    let mut session = engine.session()?;
+    session.execute("INSERT INTO t VALUES (20, 1), (5, 1)")?;
+    session.execute("UPDATE t SET value = 2 WHERE id = 5")?;
+    session.execute("UPDATE t SET id = 15 WHERE id = 5")?;
+
+    let txn = engine.begin(Mode::ReadOnly)?;
+    let rows = |scan: toydb::sql::engine::Scan| scan.collect::<Result<Vec<_>>>();
+    assert_eq!(
+        rows(txn.scan("t", None)?)?,
+        vec![
+            vec![Value::Integer(15), Value::Integer(2)],
+            vec![Value::Integer(20), Value::Integer(1)]
+        ]
+    );
+    assert_eq!(rows(txn.scan_partition("t", "p1", None)?)?, Vec::<Vec<Value>>::new());
+    assert_eq!(rows(txn.scan_partition("t", "p2", None)?)?.len(), 2);
+    assert_eq!(
+        txn.scan_partition("t", "p3", None).err(),
+        Some(Error::Value("Partition p3 not found in table t".into()))
+    );
+
+    let (changes, _) = txn.changes("t", 0)?;
+    assert_eq!(
+        changes.iter().map(|c| (c.id.clone(), c.row.is_some())).collect::<Vec<_>>(),
+        vec![
+            (Value::Integer(5), true),
+            (Value::Integer(20), true),
+            (Value::Integer(5), true),
+            (Value::Integer(5), false),
+            (Value::Integer(15), true),
+        ]
+    );
+    assert!(changes.windows(2).all(|w| w[0].version <= w[1].version));
+    assert_eq!(
+        txn.history("t", &Value::Integer(5))?.iter().map(|c| c.row.is_some()).collect::<Vec<_>>(),
+        vec![true, true, false]
+    );
+    txn.rollback()?;
+    Ok(())
+}
+
+/// SHOW CREATE TABLE returns the table's schema as SQL, including COMMENT ON clauses for its
+/// comments, which are written to a golden file.
+#[test]
+fn show_create_table() -> Result<()> {
+    let engine = super::setup(vec![
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value INTEGER INDEX)",
+        "COMMENT ON TABLE test IS 'Test data'",
+        "COMMENT ON COLUMN test.name IS 'The ''unique'' name'",
+    ])?;
+    let mut session = engine.session()?;
+    let mut mint = Mint::new("tests/sql/schema");
+    let mut f = mint.new_goldenfile("show_create_table")?;
+
+    for query in &["SHOW CREATE TABLE test", "SHOW CREATE TABLE missing", "SHOW CREATE TABLE"] {
+        write!(f, "Query: {}\n", query)?;
+        match session.execute(query) {
+            Ok(ResultSet::Query { columns, rows }) => {
+                write!(f, "Columns: {:?}\n", columns)?;
+                for row in rows {
+                    for value in row? {
+                        match value {
+                            Value::String(s) => write!(f, "{}\n", s)?,
+                            value => write!(f, "{}\n", value)?,
+                        }
+                    }
+                }
+            }
+            Ok(result) => write!(f, "Result: {:?}\n", result)?,
+            Err(err) => write!(f, "Error: {:?}\n", err)?,
+        }
+        write!(f, "\n")?;
+    }
+    Ok(())
+}